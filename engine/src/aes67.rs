@@ -0,0 +1,583 @@
+// --- AES67 / Livewire output -----------------------------------------------------------
+//
+// Stations with an AoIP plant (an Axia/Livewire console, a Dante or Ravenna
+// network, a codec or transmitter with an AES67 input) want the program bus
+// on the network as a stream, not as Icecast. With `/api/v1/aes67/config`
+// enabled the engine multicasts what goes to air (`AppState.pcm_tx`, as the
+// encoder gets it) as AES67: RTP, L24, 48 kHz stereo, 1 ms packets by default.
+//
+// - `destination`: the multicast group (or a unicast address); with
+//   `livewire_channel` set, the group is the Livewire channel's
+//   (239.192.x.y), so an Axia console finds it by number. Livewire+ nodes
+//   take AES67 streams at 1 ms.
+// - `interface`: the address of the AoIP network card; empty follows the
+//   routing table.
+// - The stream is described by an SDP (`GET /api/v1/aes67/sdp`) and, with
+//   `sap` on, announced every 30 s on SAP (239.255.255.255:9875), which is
+//   how Dante Controller, Axia iProbe and most AES67 receivers discover it.
+//
+// PTP: AES67 receivers align to the PTP grandmaster. The RTP timestamps are
+// the media clock (`a=mediaclk:direct=0`), i.e. TAI in samples, taken from the
+// system clock. Where linuxptp runs (`ptp4l` on the AoIP card, `phc2sys`
+// steering the system clock), `pmc` is asked every 10 s for the grandmaster
+// and the UTC offset, and the SDP names the grandmaster
+// (`a=ts-refclk:ptp=IEEE1588-2008:…`). Without it the stream still plays on
+// receivers that do not insist on a common clock, and the SDP says
+// `ts-refclk:local`.
+//
+// Packets are sent from their own thread, paced by the monotonic clock. When
+// the program bus has nothing (output stopped) the stream carries silence, as
+// AoIP receivers expect a stream that never stops.
+
+use std::collections::VecDeque;
+use std::net::{Ipv4Addr, SocketAddrV4, UdpSocket};
+use std::os::fd::AsRawFd;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::process::Command;
+use tokio::sync::broadcast::{self, error::TryRecvError};
+
+use crate::{unix_ms_now, AppState};
+
+/// s16le stereo at 48 kHz, as `writer_playout`.
+const BYTES_PER_MS: usize = 48 * 4;
+/// Program audio buffered before it is first sent, and after an underrun.
+const PREROLL_MS: usize = 40;
+/// The buffer is cut back to `PREROLL_MS` once it holds this much.
+const MAX_BUFFER_MS: usize = 200;
+/// Packet times AES67 receivers take, microseconds.
+const PACKET_TIMES: &[u32] = &[125, 250, 1000, 4000];
+const SAP_GROUP: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(239, 255, 255, 255), 9875);
+const SAP_EVERY: Duration = Duration::from_secs(30);
+const PTP_EVERY: Duration = Duration::from_secs(10);
+/// TAI - UTC, when PTP does not say.
+const TAI_UTC_S: i64 = 37;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Aes67Config {
+    #[serde(default)]
+    enabled: bool,
+    /// Multicast group (or unicast address) the stream goes to.
+    #[serde(default = "default_destination")]
+    destination: String,
+    /// Livewire channel number (1-32767); overrides `destination`. 0: off.
+    #[serde(default)]
+    livewire_channel: u32,
+    #[serde(default = "default_port")]
+    port: u16,
+    /// Address of the network card to send from; empty: the routing table decides.
+    #[serde(default)]
+    interface: String,
+    #[serde(default = "default_ttl")]
+    ttl: u32,
+    #[serde(default = "default_payload_type")]
+    payload_type: u8,
+    /// Packet time, microseconds (125, 250, 1000 or 4000).
+    #[serde(default = "default_packet_us")]
+    packet_us: u32,
+    /// Stream name receivers list (SDP `s=`).
+    #[serde(default = "default_session_name")]
+    session_name: String,
+    /// PTP domain `pmc` is asked about.
+    #[serde(default)]
+    ptp_domain: u8,
+    /// Announce the stream on SAP.
+    #[serde(default = "default_true")]
+    sap: bool,
+}
+
+fn default_destination() -> String {
+    "239.69.0.1".into()
+}
+
+fn default_port() -> u16 {
+    5004
+}
+
+fn default_ttl() -> u32 {
+    16
+}
+
+fn default_payload_type() -> u8 {
+    97
+}
+
+fn default_packet_us() -> u32 {
+    1000
+}
+
+fn default_session_name() -> String {
+    "StudioCommand program".into()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_config() -> Aes67Config {
+    serde_json::from_str("{}").expect("defaults")
+}
+
+impl Aes67Config {
+    fn group(&self) -> Result<Ipv4Addr, String> {
+        if self.livewire_channel > 0 {
+            let ch = self.livewire_channel;
+            return Ok(Ipv4Addr::new(239, 192, (ch >> 8) as u8, ch as u8));
+        }
+        self.destination.parse().map_err(|_| format!("destination {:?} is not an IPv4 address", self.destination))
+    }
+
+    fn interface(&self) -> Result<Option<Ipv4Addr>, String> {
+        match self.interface.as_str() {
+            "" => Ok(None),
+            s => s.parse().map(Some).map_err(|_| format!("interface {s:?} is not an IPv4 address")),
+        }
+    }
+}
+
+/// The PTP grandmaster as linuxptp sees it.
+#[derive(Clone, Serialize, PartialEq)]
+struct Ptp {
+    /// EUI-64, `00-1D-C1-FF-FE-12-34-56`.
+    grandmaster: String,
+    domain: u8,
+    /// Offset from the master, nanoseconds.
+    offset_ns: Option<i64>,
+    utc_offset_s: Option<i64>,
+}
+
+#[derive(Clone, Serialize, Default)]
+struct Aes67Status {
+    running: bool,
+    since_ms: Option<u64>,
+    /// `group:port` the stream goes to.
+    destination: Option<String>,
+    /// Address the stream comes from (SDP `o=`).
+    source: Option<String>,
+    packets: u64,
+    /// Packets sent as silence because the program bus had nothing.
+    silent_packets: u64,
+    /// Program audio thrown away because the buffer ran over, ms.
+    dropped_ms: u64,
+    ptp: Option<Ptp>,
+    last_error: Option<String>,
+}
+
+static STATUS: Mutex<Option<Aes67Status>> = Mutex::new(None);
+/// The SDP of the running stream.
+static SDP: Mutex<Option<String>> = Mutex::new(None);
+/// Bumped when the configuration is saved, so the stream restarts with it.
+static CONFIG_GEN: AtomicU64 = AtomicU64::new(0);
+static PACKETS: AtomicU64 = AtomicU64::new(0);
+static SILENT_PACKETS: AtomicU64 = AtomicU64::new(0);
+static DROPPED_MS: AtomicU64 = AtomicU64::new(0);
+
+fn status() -> std::sync::MutexGuard<'static, Option<Aes67Status>> {
+    STATUS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn set_error(e: &str) {
+    tracing::warn!("aes67: {e}");
+    status().get_or_insert_with(Aes67Status::default).last_error = Some(e.to_string());
+}
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS aes67_config (
+            id      INTEGER PRIMARY KEY CHECK (id = 1),
+            config  TEXT NOT NULL
+        );
+        "#,
+    )
+}
+
+async fn with_db<T: Send + 'static>(
+    f: impl FnOnce(&Connection) -> anyhow::Result<T> + Send + 'static,
+) -> anyhow::Result<T> {
+    crate::db::call(move |conn| {
+        crate::db_init(conn)?;
+        f(conn)
+    })
+    .await?
+}
+
+async fn load_config() -> anyhow::Result<Aes67Config> {
+    with_db(|conn| {
+        let raw: Option<String> =
+            conn.query_row("SELECT config FROM aes67_config WHERE id = 1", [], |row| row.get(0)).optional()?;
+        Ok(match raw {
+            Some(r) => serde_json::from_str(&r)?,
+            None => default_config(),
+        })
+    })
+    .await
+}
+
+// --- PTP -------------------------------------------------------------------------------
+
+/// `001dc1.fffe.123456` (linuxptp) -> `00-1D-C1-FF-FE-12-34-56` (SDP).
+fn eui64(identity: &str) -> Option<String> {
+    let hex: String = identity.chars().filter(|c| *c != '.').collect();
+    if hex.len() != 16 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let bytes: Vec<String> = (0..8).map(|i| hex[i * 2..i * 2 + 2].to_ascii_uppercase()).collect();
+    Some(bytes.join("-"))
+}
+
+/// Ask the local ptp4l (through `pmc`) for the grandmaster; `None` when
+/// linuxptp is not running or has no grandmaster.
+async fn ptp_status(domain: u8) -> Option<Ptp> {
+    let out = Command::new("pmc")
+        .args(["-u", "-b", "0", "-d", &domain.to_string()])
+        .args(["GET TIME_STATUS_NP", "GET TIME_PROPERTIES_DATA_SET"])
+        .stdin(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let out = tokio::time::timeout(Duration::from_secs(3), out).await.ok()?.ok()?;
+    let text = String::from_utf8_lossy(&out.stdout);
+    let field = |name: &str| {
+        text.lines().find_map(|l| {
+            let mut parts = l.split_whitespace();
+            (parts.next() == Some(name)).then(|| parts.next().unwrap_or("").to_string())
+        })
+    };
+    if field("gmPresent").as_deref() != Some("true") {
+        return None;
+    }
+    Some(Ptp {
+        grandmaster: eui64(&field("gmIdentity")?)?,
+        domain,
+        offset_ns: field("master_offset").and_then(|v| v.parse().ok()),
+        utc_offset_s: field("currentUtcOffset").and_then(|v| v.parse().ok()),
+    })
+}
+
+/// The media clock now: TAI in samples, wrapped to 32 bits as RTP carries it.
+fn media_clock(utc_offset_s: i64) -> u32 {
+    let utc_ns = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as i128)
+        .unwrap_or(0);
+    let tai_ns = utc_ns + utc_offset_s as i128 * 1_000_000_000;
+    (tai_ns * 48_000 / 1_000_000_000) as u32
+}
+
+// --- SDP and SAP -----------------------------------------------------------------------
+
+fn sdp(cfg: &Aes67Config, session: u32, version: u32, group: Ipv4Addr, source: Ipv4Addr, ptp: Option<&Ptp>) -> String {
+    let ptime = cfg.packet_us as f64 / 1000.0;
+    // The TTL goes with a multicast address only.
+    let ttl = if group.is_multicast() { format!("/{}", cfg.ttl) } else { String::new() };
+    let mut s = format!(
+        "v=0\r\no=- {session} {version} IN IP4 {source}\r\ns={}\r\nc=IN IP4 {group}{ttl}\r\nt=0 0\r\n",
+        cfg.session_name
+    );
+    if let Some(p) = ptp {
+        s.push_str(&format!("a=clock-domain:PTPv2 {}\r\n", p.domain));
+    }
+    s.push_str(&format!("m=audio {} RTP/AVP {}\r\n", cfg.port, cfg.payload_type));
+    s.push_str("i=Program\r\n");
+    s.push_str(&format!("a=rtpmap:{} L24/48000/2\r\n", cfg.payload_type));
+    s.push_str("a=recvonly\r\n");
+    s.push_str(&format!("a=ptime:{ptime}\r\n"));
+    s.push_str(&format!("a=framecount:{}\r\n", cfg.packet_us * 48 / 1000));
+    match ptp {
+        Some(p) => s.push_str(&format!("a=ts-refclk:ptp=IEEE1588-2008:{}:{}\r\n", p.grandmaster, p.domain)),
+        None => s.push_str("a=ts-refclk:local\r\n"),
+    }
+    s.push_str("a=mediaclk:direct=0\r\n");
+    s
+}
+
+/// A SAP (RFC 2974) announcement, or its deletion, for `sdp`.
+fn sap_packet(sdp: &str, session: u32, source: Ipv4Addr, delete: bool) -> Vec<u8> {
+    let hash = (session ^ (session >> 16)) as u16;
+    let mut p = vec![if delete { 0x24 } else { 0x20 }, 0];
+    p.extend_from_slice(&hash.to_be_bytes());
+    p.extend_from_slice(&source.octets());
+    p.extend_from_slice(b"application/sdp\0");
+    p.extend_from_slice(sdp.as_bytes());
+    p
+}
+
+// --- Sender ----------------------------------------------------------------------------
+
+fn open_socket(cfg: &Aes67Config, group: Ipv4Addr) -> Result<(UdpSocket, Ipv4Addr), String> {
+    let interface = cfg.interface()?;
+    let socket = UdpSocket::bind((interface.unwrap_or(Ipv4Addr::UNSPECIFIED), 0)).map_err(|e| format!("bind: {e}"))?;
+    socket.set_multicast_ttl_v4(cfg.ttl).map_err(|e| format!("ttl: {e}"))?;
+    if let Some(addr) = interface {
+        // Multicast goes out of the AoIP card, not wherever the default route points.
+        let ifaddr = libc::in_addr { s_addr: u32::from(addr).to_be() };
+        let rc = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::IPPROTO_IP,
+                libc::IP_MULTICAST_IF,
+                &ifaddr as *const libc::in_addr as *const libc::c_void,
+                std::mem::size_of::<libc::in_addr>() as libc::socklen_t,
+            )
+        };
+        if rc != 0 {
+            return Err(format!("interface {addr}: {}", std::io::Error::last_os_error()));
+        }
+        return Ok((socket, addr));
+    }
+    // The source address the routing table picks, for the SDP.
+    let probe = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).map_err(|e| format!("bind: {e}"))?;
+    probe.connect((group, cfg.port)).map_err(|e| format!("{group}: {e}"))?;
+    let source = match probe.local_addr() {
+        Ok(std::net::SocketAddr::V4(a)) => *a.ip(),
+        _ => Ipv4Addr::LOCALHOST,
+    };
+    Ok((socket, source))
+}
+
+/// Send the program bus as RTP until `stop`. Runs on its own thread.
+fn send(
+    mut rx: broadcast::Receiver<Vec<u8>>,
+    socket: UdpSocket,
+    to: SocketAddrV4,
+    cfg: Aes67Config,
+    utc_offset_s: i64,
+    stop: Arc<AtomicBool>,
+) {
+    let frames = (cfg.packet_us * 48 / 1000) as usize;
+    let period = Duration::from_micros(cfg.packet_us as u64);
+    let ssrc = fastrand::u32(..);
+    let mut seq = fastrand::u16(..);
+    let mut timestamp = media_clock(utc_offset_s);
+    let mut buf: VecDeque<u8> = VecDeque::with_capacity(MAX_BUFFER_MS * BYTES_PER_MS * 2);
+    let mut primed = false;
+    let mut packet = Vec::with_capacity(12 + frames * 6);
+    let start = Instant::now();
+    let mut sent: u64 = 0;
+
+    while !stop.load(Ordering::Relaxed) {
+        loop {
+            match rx.try_recv() {
+                Ok(chunk) => buf.extend(chunk),
+                Err(TryRecvError::Lagged(n)) => tracing::warn!("aes67: fell behind the program bus ({n} chunks)"),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Closed) => return,
+            }
+        }
+        if buf.len() > MAX_BUFFER_MS * BYTES_PER_MS {
+            let cut = buf.len() - PREROLL_MS * BYTES_PER_MS;
+            buf.drain(..cut);
+            DROPPED_MS.fetch_add((cut / BYTES_PER_MS) as u64, Ordering::Relaxed);
+        }
+        if !primed && buf.len() >= PREROLL_MS * BYTES_PER_MS {
+            primed = true;
+        }
+
+        let due = start.elapsed().as_micros() as u64 / cfg.packet_us as u64 + 1;
+        if due > sent + 1000 {
+            // Stalled for a while (a suspended VM): pick up from now rather than burst.
+            sent = due - 1;
+            timestamp = media_clock(utc_offset_s);
+        }
+        while sent < due {
+            packet.clear();
+            packet.extend_from_slice(&[0x80, cfg.payload_type & 0x7F]);
+            packet.extend_from_slice(&seq.to_be_bytes());
+            packet.extend_from_slice(&timestamp.to_be_bytes());
+            packet.extend_from_slice(&ssrc.to_be_bytes());
+            let have = if primed { (buf.len() / 4).min(frames) } else { 0 };
+            // s16le -> L24 (big-endian, low byte zero).
+            for _ in 0..have * 2 {
+                let (lo, hi) = (buf.pop_front().unwrap_or(0), buf.pop_front().unwrap_or(0));
+                packet.extend_from_slice(&[hi, lo, 0]);
+            }
+            packet.resize(12 + frames * 6, 0);
+            if have < frames {
+                // Underrun: build the buffer up again before carrying on.
+                primed = false;
+                SILENT_PACKETS.fetch_add(1, Ordering::Relaxed);
+            }
+            if let Err(e) = socket.send_to(&packet, to) {
+                set_error(&format!("{to}: {e}"));
+                std::thread::sleep(Duration::from_secs(1));
+                return;
+            }
+            PACKETS.fetch_add(1, Ordering::Relaxed);
+            seq = seq.wrapping_add(1);
+            timestamp = timestamp.wrapping_add(frames as u32);
+            sent += 1;
+        }
+        let next = start + period * sent as u32;
+        if let Some(wait) = next.checked_duration_since(Instant::now()) {
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+/// Keep the stream going while AES67 output is enabled.
+pub(crate) async fn aes67_task(state: AppState) {
+    loop {
+        let generation = CONFIG_GEN.load(Ordering::Relaxed);
+        match load_config().await {
+            Ok(cfg) if cfg.enabled => {
+                if let Err(e) = run(&state, &cfg, generation).await {
+                    set_error(&e);
+                }
+            }
+            Ok(_) => {}
+            Err(e) => set_error(&e.to_string()),
+        }
+        *SDP.lock().unwrap_or_else(|e| e.into_inner()) = None;
+        if let Some(s) = status().as_mut() {
+            s.running = false;
+        }
+        // Retry after an error, or wait for the configuration to change.
+        for _ in 0..50 {
+            if CONFIG_GEN.load(Ordering::Relaxed) != generation {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+}
+
+async fn run(state: &AppState, cfg: &Aes67Config, generation: u64) -> Result<(), String> {
+    let group = cfg.group()?;
+    let to = SocketAddrV4::new(group, cfg.port);
+    let (socket, source) = open_socket(cfg, group)?;
+    let sap = socket.try_clone().map_err(|e| e.to_string())?;
+    let mut ptp = ptp_status(cfg.ptp_domain).await;
+    let session = fastrand::u32(..);
+    let mut version = 1;
+    let mut description = sdp(cfg, session, version, group, source, ptp.as_ref());
+
+    *SDP.lock().unwrap_or_else(|e| e.into_inner()) = Some(description.clone());
+    for counter in [&PACKETS, &SILENT_PACKETS, &DROPPED_MS] {
+        counter.store(0, Ordering::Relaxed);
+    }
+    *status() = Some(Aes67Status {
+        running: true,
+        since_ms: Some(unix_ms_now()),
+        destination: Some(to.to_string()),
+        source: Some(source.to_string()),
+        ptp: ptp.clone(),
+        ..Default::default()
+    });
+    tracing::info!("aes67: sending the program bus to {to} from {source}, {} us packets", cfg.packet_us);
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let utc_offset_s = ptp.as_ref().and_then(|p| p.utc_offset_s).unwrap_or(TAI_UTC_S);
+    let sender = {
+        let (rx, cfg, stop) = (state.pcm_tx.subscribe(), cfg.clone(), stop.clone());
+        tokio::task::spawn_blocking(move || send(rx, socket, to, cfg, utc_offset_s, stop))
+    };
+
+    let mut check = tokio::time::interval(Duration::from_millis(500));
+    let (mut last_sap, mut last_ptp) = (None::<Instant>, Instant::now());
+    while !sender.is_finished() && CONFIG_GEN.load(Ordering::Relaxed) == generation {
+        check.tick().await;
+        if last_ptp.elapsed() >= PTP_EVERY {
+            last_ptp = Instant::now();
+            let now = ptp_status(cfg.ptp_domain).await;
+            if now.as_ref().map(|p| &p.grandmaster) != ptp.as_ref().map(|p| &p.grandmaster) {
+                version += 1;
+                description = sdp(cfg, session, version, group, source, now.as_ref());
+                *SDP.lock().unwrap_or_else(|e| e.into_inner()) = Some(description.clone());
+                last_sap = None;
+            }
+            ptp = now;
+        }
+        if cfg.sap && last_sap.is_none_or(|t| t.elapsed() >= SAP_EVERY) {
+            last_sap = Some(Instant::now());
+            if let Err(e) = sap.send_to(&sap_packet(&description, session, source, false), SAP_GROUP) {
+                tracing::warn!("aes67: sap: {e}");
+            }
+        }
+        if let Some(s) = status().as_mut() {
+            s.packets = PACKETS.load(Ordering::Relaxed);
+            s.silent_packets = SILENT_PACKETS.load(Ordering::Relaxed);
+            s.dropped_ms = DROPPED_MS.load(Ordering::Relaxed);
+            s.ptp = ptp.clone();
+        }
+    }
+    stop.store(true, Ordering::Relaxed);
+    let _ = sender.await;
+    if cfg.sap {
+        let _ = sap.send_to(&sap_packet(&description, session, source, true), SAP_GROUP);
+    }
+    tracing::info!("aes67: stopped sending to {to}");
+    Ok(())
+}
+
+// --- HTTP API --------------------------------------------------------------------------
+
+type ApiError = (StatusCode, Json<Value>);
+
+fn api_error(status: StatusCode, msg: impl Into<String>) -> ApiError {
+    (status, Json(json!({"ok": false, "error": msg.into()})))
+}
+
+pub(crate) async fn api_aes67_config_get() -> Result<Json<Value>, StatusCode> {
+    let cfg = load_config().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let st = status().clone().unwrap_or_default();
+    Ok(Json(json!({"ok": true, "config": cfg, "status": st})))
+}
+
+pub(crate) async fn api_aes67_config_set(Json(mut cfg): Json<Aes67Config>) -> Result<Json<Value>, ApiError> {
+    let bad = |msg: String| Err(api_error(StatusCode::BAD_REQUEST, msg));
+    cfg.destination = cfg.destination.trim().to_string();
+    cfg.interface = cfg.interface.trim().to_string();
+    cfg.session_name = cfg.session_name.trim().replace(['\r', '\n'], " ");
+    if cfg.livewire_channel > 32767 {
+        return bad("livewire_channel must be 1-32767 (0: off)".into());
+    }
+    if let Err(e) = cfg.group().and(cfg.interface()) {
+        return bad(e);
+    }
+    if cfg.port == 0 {
+        return bad("port must be 1-65535".into());
+    }
+    if !(1..=255).contains(&cfg.ttl) {
+        return bad("ttl must be 1-255".into());
+    }
+    if !(96..=127).contains(&cfg.payload_type) {
+        return bad("payload_type must be 96-127 (dynamic)".into());
+    }
+    if !PACKET_TIMES.contains(&cfg.packet_us) {
+        return bad(format!("packet_us must be one of {PACKET_TIMES:?}"));
+    }
+    if cfg.session_name.is_empty() {
+        cfg.session_name = default_session_name();
+    }
+    let raw = serde_json::to_string(&cfg).map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    with_db(move |conn| {
+        conn.execute(
+            "INSERT INTO aes67_config (id, config) VALUES (1, ?1)
+             ON CONFLICT(id) DO UPDATE SET config=excluded.config",
+            params![raw],
+        )?;
+        Ok(())
+    })
+    .await
+    .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    CONFIG_GEN.fetch_add(1, Ordering::Relaxed);
+    Ok(Json(json!({"ok": true, "config": cfg})))
+}
+
+/// `GET /api/v1/aes67/sdp`: the session description of the running stream.
+pub(crate) async fn api_aes67_sdp() -> Result<Response, ApiError> {
+    let sdp = SDP.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    let sdp = sdp.ok_or_else(|| api_error(StatusCode::NOT_FOUND, "AES67 output is not running"))?;
+    Ok(([(header::CONTENT_TYPE, "application/sdp")], sdp).into_response())
+}
@@ -0,0 +1,634 @@
+// --- Alerts ---------------------------------------------------------------------------------
+//
+// Most of these boxes run unattended overnight, and the first sign of trouble
+// used to be a listener phoning in. A background check now watches for the
+// conditions that take a station off the air and notifies the configured
+// channels when one starts (and, optionally, when it clears):
+// - `dead_air`: the stream is connected but the output has been silent for
+//   `dead_air_s` seconds (peak below `dead_air_level`),
+// - `encoder_down`: output is enabled but has not been connected for
+//   `encoder_down_s` seconds,
+// - `queue_empty`: nothing follows the playing item for `queue_empty_s`
+//   seconds (top-up off or failing),
+// - `disk_low`: a location the engine writes to (`storage.rs`) has less
+//   than `disk_min_free_pct` percent free,
+// - `update_available`: an update check found a newer Release,
+// - `clock_unsynced`: for a minute, the system clock has not been
+//   synchronized, or has been more than `clock_offset_ms` off (`timesync.rs`).
+// A threshold of 0 turns that condition off.
+//
+// Channels (`alert_channels`): `email` (SMTP), `discord` and `slack` (incoming
+// webhooks), `telegram` (bot API). Each one can be limited to some `events`
+// and sends a given event at most once per `min_interval_s`, so a flapping
+// encoder does not flood a phone. Like metadata push, delivery goes through
+// `curl`. `POST /api/v1/alerts/channels/{id}/test` sends a test message,
+// ignoring the event filter and the throttle.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, OnceLock};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::io::AsyncWriteExt;
+use tokio::time::{Duration, Instant};
+
+use crate::{ffmpeg, storage, timesync, unix_ms_now, update, AppState};
+
+/// Longest a single delivery may take.
+const TIMEOUT_S: u32 = 20;
+/// How often the conditions are checked.
+const CHECK_EVERY_S: u64 = 5;
+/// Notifications kept for `GET /api/v1/alerts`.
+const RECENT_KEEP: usize = 100;
+
+pub(crate) const EVENTS: &[&str] = &["dead_air", "encoder_down", "queue_empty", "disk_low", "update_available", "clock_unsynced"];
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct AlertConfig {
+    #[serde(default = "default_true")]
+    enabled: bool,
+    #[serde(default = "default_dead_air_s")]
+    dead_air_s: u32,
+    /// Peak level (0..1) below which the output counts as silent.
+    #[serde(default = "default_dead_air_level")]
+    dead_air_level: f32,
+    #[serde(default = "default_encoder_down_s")]
+    encoder_down_s: u32,
+    #[serde(default = "default_queue_empty_s")]
+    queue_empty_s: u32,
+    #[serde(default = "default_disk_min_free_pct")]
+    disk_min_free_pct: u8,
+    #[serde(default = "default_true")]
+    update_available: bool,
+    /// Largest tolerated clock offset, in milliseconds.
+    #[serde(default = "default_clock_offset_ms")]
+    clock_offset_ms: u32,
+    /// Also notify when a condition clears.
+    #[serde(default = "default_true")]
+    resolved: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_dead_air_s() -> u32 {
+    15
+}
+
+fn default_dead_air_level() -> f32 {
+    0.003
+}
+
+fn default_encoder_down_s() -> u32 {
+    30
+}
+
+fn default_queue_empty_s() -> u32 {
+    10
+}
+
+fn default_disk_min_free_pct() -> u8 {
+    10
+}
+
+fn default_clock_offset_ms() -> u32 {
+    500
+}
+
+fn default_interval() -> u32 {
+    900
+}
+
+fn default_config() -> AlertConfig {
+    serde_json::from_str("{}").expect("defaults")
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct AlertChannel {
+    #[serde(default)]
+    id: i64,
+    name: String,
+    /// "email", "discord", "slack" or "telegram".
+    kind: String,
+    #[serde(default = "default_true")]
+    enabled: bool,
+    /// Events sent to this channel; empty means all.
+    #[serde(default)]
+    events: Vec<String>,
+    /// The same event is sent at most once per this many seconds.
+    #[serde(default = "default_interval")]
+    min_interval_s: u32,
+    // discord, slack
+    #[serde(default)]
+    url: String,
+    // telegram
+    #[serde(default)]
+    bot_token: String,
+    #[serde(default)]
+    chat_id: String,
+    // email
+    /// `smtp://host:587` (STARTTLS) or `smtps://host:465`.
+    #[serde(default)]
+    smtp_url: String,
+    #[serde(default)]
+    username: String,
+    #[serde(default)]
+    password: String,
+    #[serde(default)]
+    from: String,
+    #[serde(default)]
+    to: Vec<String>,
+    /// Delivery status, filled in on read.
+    #[serde(default, skip_deserializing)]
+    status: Option<DeliveryStatus>,
+}
+
+#[derive(Clone, Serialize, Default)]
+pub(crate) struct DeliveryStatus {
+    sent: u64,
+    failed: u64,
+    /// Notifications held back by `min_interval_s`.
+    throttled: u64,
+    last_attempt_ms: Option<u64>,
+    last_ok_ms: Option<u64>,
+    last_error: Option<String>,
+}
+
+impl AlertChannel {
+    fn wants(&self, event: &str) -> bool {
+        self.events.is_empty() || self.events.iter().any(|e| e == event)
+    }
+}
+
+/// A notification as recorded for `GET /api/v1/alerts`.
+#[derive(Clone, Serialize)]
+struct Notice {
+    at_ms: u64,
+    event: &'static str,
+    /// "alert" or "resolved".
+    kind: &'static str,
+    message: String,
+    /// Channels it went out to.
+    sent_to: Vec<String>,
+}
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS alert_config (
+            id      INTEGER PRIMARY KEY CHECK (id = 1),
+            config  TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS alert_channels (
+            id       INTEGER PRIMARY KEY AUTOINCREMENT,
+            config   TEXT NOT NULL
+        );
+        "#,
+    )
+}
+
+fn db_load_config(conn: &Connection) -> anyhow::Result<AlertConfig> {
+    crate::db_init(conn)?;
+    let raw: Option<String> =
+        conn.query_row("SELECT config FROM alert_config WHERE id = 1", [], |row| row.get(0)).optional()?;
+    Ok(match raw {
+        Some(r) => serde_json::from_str(&r)?,
+        None => default_config(),
+    })
+}
+
+fn db_list(conn: &Connection) -> anyhow::Result<Vec<AlertChannel>> {
+    crate::db_init(conn)?;
+    let mut stmt = conn.prepare("SELECT id, config FROM alert_channels ORDER BY id")?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?;
+    let mut out = Vec::new();
+    for r in rows {
+        let (id, config) = r?;
+        match serde_json::from_str::<AlertChannel>(&config) {
+            Ok(mut c) => {
+                c.id = id;
+                out.push(c);
+            }
+            Err(e) => tracing::warn!("alerts: channel {id} unreadable: {e}"),
+        }
+    }
+    Ok(out)
+}
+
+async fn with_db<T: Send + 'static>(
+    f: impl FnOnce(&Connection) -> anyhow::Result<T> + Send + 'static,
+) -> anyhow::Result<T> {
+    crate::db::call(move |conn| f(conn)).await?
+}
+
+fn status() -> &'static Mutex<HashMap<i64, DeliveryStatus>> {
+    static STATUS: OnceLock<Mutex<HashMap<i64, DeliveryStatus>>> = OnceLock::new();
+    STATUS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn recent() -> &'static Mutex<VecDeque<Notice>> {
+    static RECENT: OnceLock<Mutex<VecDeque<Notice>>> = OnceLock::new();
+    RECENT.get_or_init(|| Mutex::new(VecDeque::new()))
+}
+
+/// Conditions active right now, with when they started.
+fn active() -> &'static Mutex<HashMap<&'static str, (u64, String)>> {
+    static ACTIVE: OnceLock<Mutex<HashMap<&'static str, (u64, String)>>> = OnceLock::new();
+    ACTIVE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Whether `event` has fired and not cleared yet.
+pub(crate) fn is_active(event: &str) -> bool {
+    active().lock().unwrap_or_else(|e| e.into_inner()).contains_key(event)
+}
+
+// --- Delivery ------------------------------------------------------------------------
+
+fn host_name() -> String {
+    sysinfo::System::host_name().unwrap_or_else(|| "studiocommand".into())
+}
+
+/// Send `text` to one channel via curl.
+async fn send(c: &AlertChannel, subject: &str, text: &str) -> Result<(), String> {
+    let curl = ffmpeg::curl_bin();
+    let mut cmd = tokio::process::Command::new(&curl);
+    cmd.args(["-sS", "--fail", "-o", "/dev/null", "--max-time", &TIMEOUT_S.to_string()]);
+    let mut stdin_body = None;
+    match c.kind.as_str() {
+        "discord" | "slack" => {
+            let key = if c.kind == "discord" { "content" } else { "text" };
+            let body = json!({ key: format!("{subject}\n{text}") }).to_string();
+            cmd.args(["-H", "Content-Type: application/json", "--data-binary", &body, &c.url]);
+        }
+        "telegram" => {
+            let url = format!("https://api.telegram.org/bot{}/sendMessage", c.bot_token);
+            let body = json!({"chat_id": c.chat_id, "text": format!("{subject}\n{text}")}).to_string();
+            cmd.args(["-H", "Content-Type: application/json", "--data-binary", &body, &url]);
+        }
+        "email" => {
+            cmd.args(["--url", &c.smtp_url, "--mail-from", &c.from]);
+            for to in &c.to {
+                cmd.args(["--mail-rcpt", to]);
+            }
+            if c.smtp_url.starts_with("smtp://") {
+                cmd.arg("--ssl-reqd");
+            }
+            if !c.username.is_empty() {
+                cmd.args(["--user", &format!("{}:{}", c.username, c.password)]);
+            }
+            cmd.args(["--upload-file", "-"]).stdin(std::process::Stdio::piped());
+            stdin_body = Some(format!(
+                "From: {}\r\nTo: {}\r\nSubject: {subject}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n",
+                c.from,
+                c.to.join(", "),
+                text.replace('\n', "\r\n"),
+            ));
+        }
+        other => return Err(format!("unknown kind {other:?}")),
+    }
+    cmd.stdout(std::process::Stdio::null()).stderr(std::process::Stdio::piped()).kill_on_drop(true);
+
+    let run = async {
+        let mut child = cmd.spawn().map_err(|e| format!("{curl}: {e}"))?;
+        if let (Some(body), Some(mut stdin)) = (stdin_body, child.stdin.take()) {
+            stdin.write_all(body.as_bytes()).await.map_err(|e| e.to_string())?;
+        }
+        child.wait_with_output().await.map_err(|e| e.to_string())
+    };
+    let out = match tokio::time::timeout(Duration::from_secs(TIMEOUT_S as u64 + 5), run).await {
+        Ok(r) => r?,
+        Err(_) => return Err("timed out".into()),
+    };
+    if out.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&out.stderr).trim().to_string();
+    Err(if stderr.is_empty() { "request failed".into() } else { stderr })
+}
+
+/// Send and record the outcome in the delivery status.
+async fn deliver(c: AlertChannel, subject: String, text: String) -> Result<(), String> {
+    let res = send(&c, &subject, &text).await;
+    let now_ms = unix_ms_now();
+    let mut all = status().lock().unwrap_or_else(|e| e.into_inner());
+    let s = all.entry(c.id).or_default();
+    s.last_attempt_ms = Some(now_ms);
+    match &res {
+        Ok(()) => {
+            s.sent += 1;
+            s.last_ok_ms = Some(now_ms);
+            s.last_error = None;
+        }
+        Err(e) => {
+            s.failed += 1;
+            s.last_error = Some(e.clone());
+            tracing::warn!("alert to {}: {e}", c.name);
+        }
+    }
+    res
+}
+
+// --- Conditions ----------------------------------------------------------------------
+
+/// One condition being tracked: since when it holds, and whether it was announced.
+#[derive(Default)]
+struct Tracked {
+    since: Option<Instant>,
+    fired: bool,
+}
+
+/// Current value of each condition: `Some(detail)` while it holds, with how
+/// long it must hold before it counts.
+async fn evaluate(state: &AppState, cfg: &AlertConfig) -> Vec<(&'static str, Option<String>, u32)> {
+    let (connected, enabled) = {
+        let o = state.output.lock().await;
+        (o.status.state == "connected", o.config.enabled)
+    };
+    let vu = crate::meters::get();
+    let peak = vu.peak_l.max(vu.peak_r);
+    let upcoming = state.playout.read().await.log.len().saturating_sub(1);
+
+    let mut out = Vec::new();
+    if cfg.dead_air_s > 0 {
+        let silent = connected && peak < cfg.dead_air_level;
+        out.push(("dead_air", silent.then(|| format!("no audio on the stream for {} s", cfg.dead_air_s)), cfg.dead_air_s));
+    }
+    if cfg.encoder_down_s > 0 {
+        let down = enabled && !connected;
+        let detail = format!("stream output not connected for {} s", cfg.encoder_down_s);
+        out.push(("encoder_down", down.then_some(detail), cfg.encoder_down_s));
+    }
+    if cfg.queue_empty_s > 0 {
+        out.push(("queue_empty", (upcoming == 0).then(|| "nothing queued after the playing item".into()), cfg.queue_empty_s));
+    }
+    if cfg.disk_min_free_pct > 0 {
+        let low: Vec<String> = storage::check_all()
+            .await
+            .into_iter()
+            .filter(|c| c.free_pct.is_some_and(|p| p < cfg.disk_min_free_pct as f32))
+            .map(|c| format!("{} ({}): {:.1}% free", c.name, c.path, c.free_pct.unwrap_or(0.0)))
+            .collect();
+        out.push(("disk_low", (!low.is_empty()).then(|| low.join("; ")), 0));
+    }
+    if cfg.update_available {
+        let available = update::status(&state.version).available.map(|v| format!("version {v} is available"));
+        out.push(("update_available", available, 0));
+    }
+    if cfg.clock_offset_ms > 0 {
+        let clock = timesync::status().await;
+        let off = match (clock.synchronized, clock.offset_ms) {
+            (Some(false), _) => Some(clock.warning().unwrap_or_default()),
+            (_, Some(ms)) if ms.abs() > cfg.clock_offset_ms as f64 => Some(format!("clock is {ms:+.0} ms off")),
+            _ => None,
+        };
+        out.push(("clock_unsynced", off, 60));
+    }
+    out
+}
+
+/// Notify every enabled channel that wants `event`, honouring `min_interval_s`.
+async fn notify(
+    event: &'static str,
+    kind: &'static str,
+    detail: &str,
+    last_sent: &mut HashMap<(i64, &'static str), Instant>,
+) {
+    let channels = match with_db(db_list).await {
+        Ok(c) => c,
+        Err(e) => {
+            tracing::warn!("alerts: {e}");
+            return;
+        }
+    };
+    let host = host_name();
+    let (subject, text) = match kind {
+        "alert" => (format!("[{host}] ALERT: {event}"), format!("{detail}\nStation: {host}")),
+        _ => (format!("[{host}] resolved: {event}"), format!("{event} cleared\nStation: {host}")),
+    };
+    let now = Instant::now();
+    let mut sent_to = Vec::new();
+    for c in channels.into_iter().filter(|c| c.enabled && c.wants(event)) {
+        let key = (c.id, event);
+        let interval = Duration::from_secs(c.min_interval_s as u64);
+        // A resolve follows its alert even inside the interval; only alerts are throttled.
+        if kind == "alert" && last_sent.get(&key).is_some_and(|l| now < *l + interval) {
+            status().lock().unwrap_or_else(|e| e.into_inner()).entry(c.id).or_default().throttled += 1;
+            continue;
+        }
+        if kind == "alert" {
+            last_sent.insert(key, now);
+        }
+        sent_to.push(c.name.clone());
+        tokio::spawn(deliver(c, subject.clone(), text.clone()));
+    }
+    let mut r = recent().lock().unwrap_or_else(|e| e.into_inner());
+    if r.len() >= RECENT_KEEP {
+        r.pop_front();
+    }
+    r.push_back(Notice { at_ms: unix_ms_now(), event, kind, message: detail.to_string(), sent_to });
+}
+
+/// Check the conditions every few seconds and notify on changes.
+pub(crate) async fn alerts_task(state: AppState) {
+    let mut tracked: HashMap<&'static str, Tracked> = HashMap::new();
+    let mut last_sent: HashMap<(i64, &'static str), Instant> = HashMap::new();
+    let mut tick = tokio::time::interval(Duration::from_secs(CHECK_EVERY_S));
+    loop {
+        tick.tick().await;
+        let cfg = match with_db(db_load_config).await {
+            Ok(c) => c,
+            Err(e) => {
+                tracing::warn!("alerts: {e}");
+                continue;
+            }
+        };
+        if !cfg.enabled {
+            tracked.clear();
+            active().lock().unwrap_or_else(|e| e.into_inner()).clear();
+            continue;
+        }
+        let now = Instant::now();
+        for (event, detail, hold_s) in evaluate(&state, &cfg).await {
+            let t = tracked.entry(event).or_default();
+            match detail {
+                Some(detail) => {
+                    let since = *t.since.get_or_insert(now);
+                    if !t.fired && now.duration_since(since) >= Duration::from_secs(hold_s as u64) {
+                        t.fired = true;
+                        tracing::warn!("alert: {event}: {detail}");
+                        active().lock().unwrap_or_else(|e| e.into_inner()).insert(event, (unix_ms_now(), detail.clone()));
+                        notify(event, "alert", &detail, &mut last_sent).await;
+                    }
+                }
+                None => {
+                    if t.fired {
+                        tracing::info!("alert cleared: {event}");
+                        active().lock().unwrap_or_else(|e| e.into_inner()).remove(event);
+                        if cfg.resolved {
+                            notify(event, "resolved", "", &mut last_sent).await;
+                        }
+                    }
+                    *t = Tracked::default();
+                }
+            }
+        }
+    }
+}
+
+// --- HTTP API -------------------------------------------------------------------------
+
+/// `GET /api/v1/alerts`: active conditions and recent notifications, newest first.
+pub(crate) async fn api_alerts() -> Json<serde_json::Value> {
+    let active: Vec<_> = active()
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .map(|(event, (since_ms, detail))| json!({"event": event, "since_ms": since_ms, "detail": detail}))
+        .collect();
+    let recent: Vec<Notice> = recent().lock().unwrap_or_else(|e| e.into_inner()).iter().rev().cloned().collect();
+    Json(json!({"ok": true, "active": active, "recent": recent}))
+}
+
+pub(crate) async fn api_alert_config_get() -> Result<Json<AlertConfig>, StatusCode> {
+    with_db(db_load_config).await.map(Json).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+pub(crate) async fn api_alert_config_set(
+    Json(cfg): Json<AlertConfig>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    if !(0.0..=1.0).contains(&cfg.dead_air_level) {
+        return Err((StatusCode::BAD_REQUEST, Json(json!({"ok": false, "error": "dead_air_level must be 0..1"}))));
+    }
+    if cfg.disk_min_free_pct > 99 {
+        return Err((StatusCode::BAD_REQUEST, Json(json!({"ok": false, "error": "disk_min_free_pct must be 0..99"}))));
+    }
+    let raw = serde_json::to_string(&cfg).map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"ok": false}))))?;
+    with_db(move |conn| {
+        crate::db_init(conn)?;
+        conn.execute(
+            "INSERT INTO alert_config (id, config) VALUES (1, ?1)
+             ON CONFLICT(id) DO UPDATE SET config=excluded.config",
+            params![raw],
+        )?;
+        Ok(())
+    })
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"ok": false}))))?;
+    Ok(Json(json!({"ok": true, "config": cfg})))
+}
+
+pub(crate) async fn api_channels_list() -> Result<Json<Vec<AlertChannel>>, StatusCode> {
+    let mut channels = with_db(db_list).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let all = status().lock().unwrap_or_else(|e| e.into_inner());
+    for c in channels.iter_mut() {
+        c.status = Some(all.get(&c.id).cloned().unwrap_or_default());
+    }
+    Ok(Json(channels))
+}
+
+fn validate(c: &mut AlertChannel) -> Result<(), (StatusCode, Json<serde_json::Value>)> {
+    let bad = |msg: String| Err((StatusCode::BAD_REQUEST, Json(json!({"ok": false, "error": msg}))));
+    c.name = c.name.trim().to_string();
+    c.events = c.events.iter().map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect();
+    c.to = c.to.iter().map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect();
+    if c.name.is_empty() {
+        return bad("name is required".into());
+    }
+    if let Some(e) = c.events.iter().find(|e| !EVENTS.contains(&e.as_str())) {
+        return bad(format!("unknown event {e:?} (known: {})", EVENTS.join(", ")));
+    }
+    match c.kind.as_str() {
+        "discord" | "slack" => {
+            if !c.url.starts_with("https://") {
+                return bad("url must be the https:// webhook URL".into());
+            }
+        }
+        "telegram" => {
+            if c.bot_token.trim().is_empty() || c.chat_id.trim().is_empty() {
+                return bad("telegram needs bot_token and chat_id".into());
+            }
+        }
+        "email" => {
+            if !(c.smtp_url.starts_with("smtp://") || c.smtp_url.starts_with("smtps://")) {
+                return bad("smtp_url must start with smtp:// or smtps://".into());
+            }
+            if !c.from.contains('@') || c.to.is_empty() || c.to.iter().any(|t| !t.contains('@')) {
+                return bad("email needs a from address and at least one to address".into());
+            }
+        }
+        _ => return bad("kind must be email, discord, slack or telegram".into()),
+    }
+    c.status = None;
+    Ok(())
+}
+
+pub(crate) async fn api_channel_create(
+    Json(mut c): Json<AlertChannel>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    validate(&mut c)?;
+    let id = with_db(move |conn| {
+        crate::db_init(conn)?;
+        conn.execute("INSERT INTO alert_channels (config) VALUES (?1)", params![serde_json::to_string(&c)?])?;
+        Ok(conn.last_insert_rowid())
+    })
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"ok": false}))))?;
+    Ok(Json(json!({"ok": true, "id": id})))
+}
+
+pub(crate) async fn api_channel_put(
+    Path(id): Path<i64>,
+    Json(mut c): Json<AlertChannel>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    validate(&mut c)?;
+    c.id = id;
+    let n = with_db(move |conn| {
+        crate::db_init(conn)?;
+        Ok(conn.execute("UPDATE alert_channels SET config = ?2 WHERE id = ?1", params![id, serde_json::to_string(&c)?])?)
+    })
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"ok": false}))))?;
+    if n == 0 {
+        return Err((StatusCode::NOT_FOUND, Json(json!({"ok": false}))));
+    }
+    Ok(Json(json!({"ok": true})))
+}
+
+pub(crate) async fn api_channel_delete(Path(id): Path<i64>) -> Result<Json<serde_json::Value>, StatusCode> {
+    let n = with_db(move |conn| {
+        crate::db_init(conn)?;
+        Ok(conn.execute("DELETE FROM alert_channels WHERE id = ?1", params![id])?)
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if n == 0 {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    status().lock().unwrap_or_else(|e| e.into_inner()).remove(&id);
+    Ok(Json(json!({"ok": true})))
+}
+
+/// `POST /api/v1/alerts/channels/{id}/test`: send a test message now,
+/// ignoring the throttle and the event filter.
+pub(crate) async fn api_channel_test(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let channels = with_db(db_list).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let c = channels.into_iter().find(|c| c.id == id).ok_or(StatusCode::NOT_FOUND)?;
+    let host = host_name();
+    let subject = format!("[{host}] test notification");
+    let text = format!("Alerts from StudioCommand {} on {host} reach this channel.", state.version);
+    Ok(Json(match deliver(c, subject, text).await {
+        Ok(()) => json!({"ok": true}),
+        Err(e) => json!({"ok": false, "error": e}),
+    }))
+}
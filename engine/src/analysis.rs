@@ -0,0 +1,246 @@
+// --- Media analysis cache -----------------------------------------------------
+//
+// Probing a file (ffprobe) takes tens of milliseconds and measuring loudness
+// means decoding it completely. Neither result changes unless the file does,
+// so we keep them in `media_analysis`, keyed by path and validated against the
+// file's mtime/size.
+//
+// - `probe_cached` is what top-up, the library scanner and playout call. On a
+//   miss it runs ffprobe once, stores the result, and queues a loudness
+//   measurement in the background (one at a time, so a big top-up batch does
+//   not start a dozen full decodes on a Raspberry Pi).
+// - Ingest measures loudness up front and stores it with `store`.
+
+use std::sync::OnceLock;
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::{library::file_mtime_secs, paths, probe_media, remote, unix_ms_now, MediaProbe};
+
+/// Result of an EBU R128 measurement.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Loudness {
+    pub(crate) integrated_lufs: f64,
+    pub(crate) true_peak_dbtp: Option<f64>,
+}
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS media_analysis (
+            path            TEXT PRIMARY KEY,
+            mtime           INTEGER NOT NULL,
+            size            INTEGER NOT NULL,
+            duration_s      INTEGER NOT NULL,
+            has_audio       INTEGER NOT NULL,
+            title           TEXT,
+            artist          TEXT,
+            album           TEXT,
+            loudness_lufs   REAL,
+            true_peak_dbtp  REAL,
+            analyzed_ms     INTEGER NOT NULL
+        );
+        "#,
+    )
+}
+
+/// Cached probe for `path`, if the file has not changed since it was stored.
+fn db_lookup(conn: &Connection, path: &str, mtime: i64, size: i64) -> anyhow::Result<Option<MediaProbe>> {
+    crate::db_init(conn)?;
+    Ok(conn
+        .query_row(
+            "SELECT duration_s, has_audio, title, artist, album, duration_ms FROM media_analysis
+             WHERE path = ?1 AND mtime = ?2 AND size = ?3",
+            params![path, mtime, size],
+            |row| {
+                Ok(MediaProbe {
+                    duration_s: row.get::<_, i64>(0)? as u32,
+                    duration_ms: row.get::<_, Option<i64>>(5)?.unwrap_or(0).max(0) as u64,
+                    has_audio: row.get::<_, i64>(1)? != 0,
+                    title: row.get(2)?,
+                    artist: row.get(3)?,
+                    album: row.get(4)?,
+                })
+            },
+        )
+        .optional()?)
+}
+
+fn db_store(conn: &Connection, path: &str, mtime: i64, size: i64, probe: &MediaProbe, loudness: Option<Loudness>) -> anyhow::Result<()> {
+    crate::db_init(conn)?;
+    // A re-probe of an unchanged file keeps any loudness already measured.
+    conn.execute(
+        "INSERT INTO media_analysis
+           (path, mtime, size, duration_s, has_audio, title, artist, album, loudness_lufs, true_peak_dbtp, analyzed_ms,
+            duration_ms)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+         ON CONFLICT(path) DO UPDATE SET
+           duration_s=excluded.duration_s,
+           duration_ms=excluded.duration_ms,
+           has_audio=excluded.has_audio,
+           title=excluded.title,
+           artist=excluded.artist,
+           album=excluded.album,
+           loudness_lufs=CASE WHEN media_analysis.mtime = excluded.mtime AND media_analysis.size = excluded.size
+                              THEN COALESCE(excluded.loudness_lufs, media_analysis.loudness_lufs)
+                              ELSE excluded.loudness_lufs END,
+           true_peak_dbtp=CASE WHEN media_analysis.mtime = excluded.mtime AND media_analysis.size = excluded.size
+                               THEN COALESCE(excluded.true_peak_dbtp, media_analysis.true_peak_dbtp)
+                               ELSE excluded.true_peak_dbtp END,
+           mtime=excluded.mtime,
+           size=excluded.size,
+           analyzed_ms=excluded.analyzed_ms",
+        params![
+            path,
+            mtime,
+            size,
+            probe.duration_s as i64,
+            if probe.has_audio { 1 } else { 0 },
+            probe.title,
+            probe.artist,
+            probe.album,
+            loudness.map(|l| l.integrated_lufs),
+            loudness.and_then(|l| l.true_peak_dbtp),
+            unix_ms_now() as i64,
+            (probe.duration_ms > 0).then_some(probe.duration_ms as i64)
+        ],
+    )?;
+    Ok(())
+}
+
+fn db_set_loudness(conn: &Connection, path: &str, l: Loudness) -> anyhow::Result<()> {
+    crate::db_init(conn)?;
+    conn.execute(
+        "UPDATE media_analysis SET loudness_lufs = ?1, true_peak_dbtp = ?2 WHERE path = ?3",
+        params![l.integrated_lufs, l.true_peak_dbtp, path],
+    )?;
+    // Keep the library's copy in step so browse/search show it too.
+    conn.execute(
+        "UPDATE library_tracks SET loudness_lufs = ?1 WHERE path = ?2",
+        params![l.integrated_lufs, path],
+    )?;
+    Ok(())
+}
+
+fn db_needs_loudness(conn: &Connection, path: &str) -> anyhow::Result<bool> {
+    crate::db_init(conn)?;
+    Ok(conn
+        .query_row(
+            "SELECT loudness_lufs IS NULL FROM media_analysis WHERE path = ?1",
+            params![path],
+            |row| row.get::<_, bool>(0),
+        )
+        .optional()?
+        .unwrap_or(true))
+}
+
+fn file_key(path: &str) -> Result<(i64, i64), String> {
+    let md = std::fs::metadata(paths::from_cart(path)).map_err(|e| format!("{path}: {e}"))?;
+    Ok((file_mtime_secs(&md), md.len() as i64))
+}
+
+/// `probe_media`, but answered from the cache when the file is unchanged.
+pub(crate) async fn probe_cached(path: &str) -> Result<MediaProbe, String> {
+    // A remote item's content changes under the same URL: never cached.
+    if remote::is_url(path) {
+        return probe_media(path).await;
+    }
+    let (mtime, size) = file_key(path)?;
+
+    let p = path.to_string();
+    let hit = crate::db::call(move |conn| db_lookup(conn, &p, mtime, size)).await;
+    match hit {
+        Ok(Ok(Some(probe))) => return Ok(probe),
+        Ok(Ok(None)) => {}
+        // The cache is an optimization: on any DB trouble, fall through to ffprobe.
+        Ok(Err(e)) => tracing::warn!("analysis cache lookup failed: {e}"),
+        Err(e) => tracing::warn!("analysis cache lookup task failed: {e}"),
+    }
+
+    let probe = probe_media(path).await?;
+    let (p, stored) = (path.to_string(), probe.clone());
+    let saved = crate::db::call(move |conn| db_store(conn, &p, mtime, size, &stored, None)).await;
+    if let Ok(Err(e)) = saved {
+        tracing::warn!("analysis cache store failed: {e}");
+    }
+
+    if probe.has_audio {
+        tokio::spawn(measure_in_background(path.to_string()));
+    }
+    Ok(probe)
+}
+
+/// Store a full analysis (probe + loudness), e.g. from ingest.
+pub(crate) async fn store(path: &str, probe: &MediaProbe, loudness: Option<Loudness>) {
+    let Ok((mtime, size)) = file_key(path) else { return };
+    let (p, probe) = (path.to_string(), probe.clone());
+    let saved = crate::db::call(move |conn| db_store(conn, &p, mtime, size, &probe, loudness)).await;
+    if let Ok(Err(e)) = saved {
+        tracing::warn!("analysis cache store failed: {e}");
+    }
+}
+
+/// Background loudness measurement, one file at a time.
+async fn measure_in_background(path: String) {
+    static GATE: OnceLock<tokio::sync::Semaphore> = OnceLock::new();
+    let Ok(_permit) = GATE.get_or_init(|| tokio::sync::Semaphore::new(1)).acquire().await else {
+        return;
+    };
+
+    // Another caller may have measured it while we waited.
+    let p = path.clone();
+    let needed = crate::db::call(move |conn| db_needs_loudness(conn, &p)).await;
+    if !matches!(needed, Ok(Ok(true))) {
+        return;
+    }
+
+    match analyze_loudness(&path).await {
+        Ok(l) => {
+            let _ = crate::db::call(move |conn| db_set_loudness(conn, &path, l)).await;
+        }
+        Err(e) => tracing::debug!("analysis: {e}"),
+    }
+}
+
+/// Measure integrated loudness (LUFS) and true peak (dBTP) with ffmpeg's
+/// `ebur128` filter. This decodes the whole file, so it is reserved for
+/// ingest and background analysis rather than anything on the playout path.
+pub(crate) async fn analyze_loudness(path: &str) -> Result<Loudness, String> {
+    use tokio::time::{timeout, Duration};
+
+    let ffmpeg = crate::ffmpeg::ffmpeg_bin();
+    let mut cmd = tokio::process::Command::new(ffmpeg);
+    cmd.arg("-nostats").arg("-hide_banner")
+        .arg("-i").arg(paths::ffmpeg_input(&paths::from_cart(path)))
+        .arg("-filter_complex").arg("ebur128=peak=true")
+        .arg("-f").arg("null").arg("-")
+        .kill_on_drop(true);
+
+    let out = match timeout(Duration::from_secs(120), cmd.output()).await {
+        Ok(Ok(out)) => out,
+        Ok(Err(e)) => return Err(format!("ffmpeg could not be run: {e}")),
+        Err(_) => return Err(format!("{path}: loudness analysis timed out")),
+    };
+    if !out.status.success() {
+        return Err(format!("{path}: loudness analysis failed"));
+    }
+
+    // The summary at the end of stderr looks like:
+    //   Integrated loudness:
+    //     I:         -14.2 LUFS
+    //   ...
+    //   True peak:
+    //     Peak:       -0.4 dBFS
+    let err = String::from_utf8_lossy(&out.stderr);
+    let summary_value = |prefix: &str, unit: &str| {
+        err.lines()
+            .rev()
+            .filter_map(|l| l.trim().strip_prefix(prefix))
+            .find_map(|v| v.trim().trim_end_matches(unit).trim().parse::<f64>().ok())
+            .filter(|v| v.is_finite())
+    };
+
+    let integrated_lufs = summary_value("I:", "LUFS")
+        .ok_or_else(|| format!("{path}: no loudness summary in ffmpeg output"))?;
+    Ok(Loudness { integrated_lufs, true_peak_dbtp: summary_value("Peak:", "dBFS") })
+}
@@ -0,0 +1,393 @@
+// --- Time and station ID announcements ----------------------------------------------
+//
+// Legal IDs and "it's eight o'clock" without building clocks: a few minutes
+// before each local top of the hour the engine inserts, at the position
+// estimated to be airing then (as scheduled events do):
+// - a station ID: a cart/file (`id_cart`), or `id_text` spoken by a TTS command
+//   and cached as a WAV,
+// - a time announcement from `time_dir`, whose files are named by local time
+//   ("0800.mp3", "0815.wav"): the one closest to the estimated air time.
+//
+// Which of the two play is set globally (`time`, `id`) and may be overridden
+// per daypart (first match wins), e.g. no time checks overnight. Both are
+// inserted locked, tagged `ID` and `TIM`.
+//
+// The hour is recorded (`last_hour_ms`) before inserting, so a restart or an
+// error never inserts the same hour twice.
+
+use std::path::{Path as FsPath, PathBuf};
+use std::sync::Arc;
+
+use axum::{http::StatusCode, Json};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::import::{self, ImportEntry};
+use crate::{
+    clocks, estimate_start_times, events, fmt_local_hhmmss, in_daypart, local_weekday_minute, parse_hhmm, unix_ms_now,
+    LogItem, PlayoutState,
+};
+
+/// How long before the top of the hour the announcements are inserted.
+const LEAD_MS: u64 = 5 * 60_000;
+/// Time announcements further than this from the air time are not used.
+const MAX_TIME_OFFSET_MIN: u32 = 30;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct AnnounceConfig {
+    #[serde(default)]
+    enabled: bool,
+    /// Folder of time announcements named "HHMM.<ext>".
+    #[serde(default)]
+    time_dir: String,
+    /// Station ID cart number or file. Empty: speak `id_text` instead.
+    #[serde(default)]
+    id_cart: String,
+    #[serde(default)]
+    id_text: String,
+    /// TTS program and arguments; `{out}` (WAV to write) and `{text}` are
+    /// replaced as whole arguments. No shell is involved.
+    #[serde(default = "default_tts_command")]
+    tts_command: String,
+    /// What plays outside every daypart.
+    #[serde(default = "default_true")]
+    time: bool,
+    #[serde(default = "default_true")]
+    id: bool,
+    #[serde(default)]
+    dayparts: Vec<AnnounceDaypart>,
+}
+
+/// "No time checks between midnight and six."
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct AnnounceDaypart {
+    #[serde(default)]
+    name: String,
+    /// "mon", "tue", ... "sun"; empty means every day.
+    #[serde(default)]
+    days: Vec<String>,
+    /// Local "HH:MM", `end` exclusive (wraps past midnight like top-up dayparts).
+    start: String,
+    end: String,
+    #[serde(default)]
+    time: bool,
+    #[serde(default)]
+    id: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_tts_command() -> String {
+    "espeak-ng -w {out} {text}".into()
+}
+
+fn default_config() -> AnnounceConfig {
+    AnnounceConfig {
+        enabled: false,
+        time_dir: "/opt/studiocommand/shared/announce/time".into(),
+        id_cart: String::new(),
+        id_text: String::new(),
+        tts_command: default_tts_command(),
+        time: true,
+        id: true,
+        dayparts: Vec::new(),
+    }
+}
+
+impl AnnounceConfig {
+    /// (time announcement, station ID) for the hour starting at `unix_ms`.
+    fn wanted_at(&self, unix_ms: u64) -> (bool, bool) {
+        let Some((weekday, minute)) = local_weekday_minute(unix_ms) else { return (self.time, self.id) };
+        match self.dayparts.iter().find(|d| in_daypart(&d.days, &d.start, &d.end, weekday, minute)) {
+            Some(d) => (d.time, d.id),
+            None => (self.time, self.id),
+        }
+    }
+}
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS announce_config (
+            id            INTEGER PRIMARY KEY CHECK (id = 1),
+            enabled       INTEGER NOT NULL,
+            time_dir      TEXT NOT NULL,
+            id_cart       TEXT NOT NULL,
+            id_text       TEXT NOT NULL,
+            tts_command   TEXT NOT NULL,
+            time_on       INTEGER NOT NULL,
+            id_on         INTEGER NOT NULL,
+            dayparts      TEXT NOT NULL,
+            last_hour_ms  INTEGER
+        );
+        "#,
+    )
+}
+
+fn db_load(conn: &Connection) -> anyhow::Result<(AnnounceConfig, Option<u64>)> {
+    crate::db_init(conn)?;
+    let row = conn
+        .query_row(
+            "SELECT enabled, time_dir, id_cart, id_text, tts_command, time_on, id_on, dayparts, last_hour_ms
+             FROM announce_config WHERE id = 1",
+            [],
+            |row| {
+                let cfg = AnnounceConfig {
+                    enabled: row.get::<_, i64>(0)? != 0,
+                    time_dir: row.get(1)?,
+                    id_cart: row.get(2)?,
+                    id_text: row.get(3)?,
+                    tts_command: row.get(4)?,
+                    time: row.get::<_, i64>(5)? != 0,
+                    id: row.get::<_, i64>(6)? != 0,
+                    dayparts: serde_json::from_str(&row.get::<_, String>(7)?).unwrap_or_default(),
+                };
+                Ok((cfg, row.get::<_, Option<i64>>(8)?.map(|v| v as u64)))
+            },
+        )
+        .optional()?;
+    Ok(row.unwrap_or_else(|| (default_config(), None)))
+}
+
+fn db_save(conn: &Connection, cfg: &AnnounceConfig) -> anyhow::Result<()> {
+    crate::db_init(conn)?;
+    conn.execute(
+        "INSERT INTO announce_config (id, enabled, time_dir, id_cart, id_text, tts_command, time_on, id_on, dayparts)
+         VALUES (1, ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+         ON CONFLICT(id) DO UPDATE SET
+           enabled=excluded.enabled,
+           time_dir=excluded.time_dir,
+           id_cart=excluded.id_cart,
+           id_text=excluded.id_text,
+           tts_command=excluded.tts_command,
+           time_on=excluded.time_on,
+           id_on=excluded.id_on,
+           dayparts=excluded.dayparts",
+        params![
+            cfg.enabled as i64,
+            cfg.time_dir,
+            cfg.id_cart,
+            cfg.id_text,
+            cfg.tts_command,
+            cfg.time as i64,
+            cfg.id as i64,
+            serde_json::to_string(&cfg.dayparts)?,
+        ],
+    )?;
+    Ok(())
+}
+
+fn db_mark_hour(conn: &Connection, hour_ms: u64) -> anyhow::Result<()> {
+    crate::db_init(conn)?;
+    // Only recorded once a config row exists: the task does nothing before that.
+    conn.execute("UPDATE announce_config SET last_hour_ms = ?1 WHERE id = 1", params![hour_ms as i64])?;
+    Ok(())
+}
+
+async fn with_db<T: Send + 'static>(
+    f: impl FnOnce(&Connection) -> anyhow::Result<T> + Send + 'static,
+) -> anyhow::Result<T> {
+    crate::db::call(move |conn| f(conn)).await?
+}
+
+// --- Picking the audio -------------------------------------------------------------
+
+/// The file in `dir` named closest to `minute` of the day ("0815.mp3" = 495).
+fn time_file(dir: &str, minute: u32) -> Option<String> {
+    let rd = std::fs::read_dir(dir).ok()?;
+    rd.flatten()
+        .filter_map(|e| {
+            let path = e.path();
+            let stem = path.file_stem()?.to_str()?;
+            if stem.len() != 4 || !path.is_file() {
+                return None;
+            }
+            let m = parse_hhmm(&format!("{}:{}", &stem[..2], &stem[2..]))?;
+            // Distance around the clock: 23:58 is two minutes from 00:00.
+            let d = m.abs_diff(minute).min(1440 - m.abs_diff(minute));
+            (d <= MAX_TIME_OFFSET_MIN).then(|| (d, path.to_string_lossy().to_string()))
+        })
+        .min()
+        .map(|(_, p)| p)
+}
+
+fn tts_cache_dir() -> PathBuf {
+    std::env::var("STUDIOCOMMAND_TTS_CACHE")
+        .unwrap_or_else(|_| "/opt/studiocommand/shared/cache/tts".to_string())
+        .into()
+}
+
+/// Render `text` with the configured TTS command, cached by command + text.
+async fn tts_render(command: &str, text: &str) -> Result<String, String> {
+    use std::hash::{Hash, Hasher};
+    use tokio::time::{timeout, Duration};
+
+    let mut h = std::collections::hash_map::DefaultHasher::new();
+    command.hash(&mut h);
+    text.hash(&mut h);
+    let dir = tts_cache_dir();
+    let wav = dir.join(format!("{:016x}.wav", h.finish()));
+    if tokio::fs::metadata(&wav).await.is_ok() {
+        return Ok(wav.to_string_lossy().to_string());
+    }
+    tokio::fs::create_dir_all(&dir).await.map_err(|e| format!("cannot create {}: {e}", dir.display()))?;
+
+    // Write to a temp name and rename, so a half-written file is never aired.
+    let tmp = dir.join(format!("{}.tmp.wav", uuid::Uuid::new_v4()));
+    let tmp_s = tmp.to_string_lossy().to_string();
+    let mut args = command.split_whitespace().map(|a| match a {
+        "{out}" => tmp_s.clone(),
+        "{text}" => text.to_string(),
+        other => other.to_string(),
+    });
+    let program = args.next().ok_or("tts_command is empty")?;
+    let mut cmd = tokio::process::Command::new(&program);
+    cmd.args(args).kill_on_drop(true);
+    let ok = matches!(timeout(Duration::from_secs(30), cmd.status()).await, Ok(Ok(st)) if st.success());
+    if !ok || tokio::fs::metadata(&tmp).await.is_err() {
+        let _ = tokio::fs::remove_file(&tmp).await;
+        return Err(format!("{program} failed"));
+    }
+    tokio::fs::rename(&tmp, &wav).await.map_err(|e| e.to_string())?;
+    Ok(wav.to_string_lossy().to_string())
+}
+
+async fn item_for(reference: &str, tag: &str, title: &str) -> Result<LogItem, String> {
+    let entry = ImportEntry { reference: reference.to_string(), tag: Some(tag.into()), ..Default::default() };
+    let mut item = import::resolve_entry(&entry, None).await?;
+    item.tag = tag.into();
+    if item.title.is_empty() {
+        item.title = title.into();
+    }
+    item.locked = true;
+    Ok(item.into_log_item("queued"))
+}
+
+/// The items for the hour starting at `hour_ms`, expected to air at `air_ms`.
+async fn build(cfg: &AnnounceConfig, hour_ms: u64, air_ms: u64) -> Vec<LogItem> {
+    let (want_time, want_id) = cfg.wanted_at(hour_ms);
+    let mut items = Vec::new();
+
+    if want_id {
+        let reference = if !cfg.id_cart.trim().is_empty() {
+            Ok(cfg.id_cart.trim().to_string())
+        } else if !cfg.id_text.trim().is_empty() {
+            tts_render(&cfg.tts_command, cfg.id_text.trim()).await
+        } else {
+            Err("no id_cart or id_text configured".into())
+        };
+        match reference {
+            Ok(r) => match item_for(&r, "ID", "Station ID").await {
+                Ok(it) => items.push(it),
+                Err(e) => tracing::warn!("announce: station ID {r}: {e}"),
+            },
+            Err(e) => tracing::warn!("announce: station ID: {e}"),
+        }
+    }
+
+    if want_time {
+        // Local minute of the day the announcement is expected to start.
+        let id_len = items.iter().map(|it| it.length_ms()).sum::<u64>();
+        let minute = local_weekday_minute(air_ms + id_len).map(|(_, m)| m).unwrap_or(0);
+        let dir = cfg.time_dir.clone();
+        match tokio::task::spawn_blocking(move || time_file(&dir, minute)).await.ok().flatten() {
+            Some(path) => match item_for(&path, "TIM", "Time").await {
+                Ok(it) => items.push(it),
+                Err(e) => tracing::warn!("announce: {path}: {e}"),
+            },
+            None => tracing::warn!(
+                "announce: no time announcement in {} near {}",
+                cfg.time_dir,
+                fmt_local_hhmmss(air_ms + id_len)
+            ),
+        }
+    }
+    items
+}
+
+pub(crate) async fn announce_task(playout: Arc<tokio::sync::RwLock<PlayoutState>>) {
+    let mut tick = tokio::time::interval(std::time::Duration::from_secs(15));
+    loop {
+        tick.tick().await;
+        let (cfg, last_hour) = match with_db(db_load).await {
+            Ok(v) => v,
+            Err(e) => {
+                tracing::warn!("announce: {e}");
+                continue;
+            }
+        };
+        if !cfg.enabled {
+            continue;
+        }
+
+        // Next local top of the hour (works for half-hour time zones too).
+        let now_ms = unix_ms_now();
+        let Some((_, minute)) = local_weekday_minute(now_ms) else { continue };
+        let hour_ms = now_ms - (minute % 60) as u64 * 60_000 - now_ms % 60_000 + 3_600_000;
+        if now_ms + LEAD_MS < hour_ms || last_hour == Some(hour_ms) {
+            continue;
+        }
+        if let Err(e) = with_db(move |conn| db_mark_hour(conn, hour_ms)).await {
+            tracing::warn!("announce: failed to record hour: {e}");
+            continue;
+        }
+
+        // Estimated air time of the insertion point, for picking the time file.
+        let air_ms = {
+            let p = playout.read().await;
+            let mut log = p.log.clone();
+            estimate_start_times(&mut log, &p.now, now_ms);
+            let pos = events::insert_position(&log, hour_ms);
+            match (log.get(pos), log.last()) {
+                (Some(it), _) => it.start_ms.unwrap_or(hour_ms),
+                (None, Some(last)) => last.start_ms.unwrap_or(now_ms) + last.length_ms(),
+                (None, None) => now_ms,
+            }
+        };
+        let items = build(&cfg, hour_ms, air_ms).await;
+        if items.is_empty() {
+            continue;
+        }
+        let n = items.len();
+        let pos = events::insert_at(&playout, items, hour_ms).await;
+        tracing::info!("announce: {n} item(s) inserted at position {pos} for {}", fmt_local_hhmmss(hour_ms));
+    }
+}
+
+// --- HTTP API --------------------------------------------------------------------------
+
+pub(crate) async fn api_announce_config_get() -> Result<Json<AnnounceConfig>, StatusCode> {
+    with_db(db_load).await.map(|(cfg, _)| Json(cfg)).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+pub(crate) async fn api_announce_config_set(
+    Json(mut cfg): Json<AnnounceConfig>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    let bad = |msg: &str| Err((StatusCode::BAD_REQUEST, Json(json!({"ok": false, "error": msg}))));
+    cfg.time_dir = cfg.time_dir.trim().to_string();
+    cfg.tts_command = cfg.tts_command.trim().to_string();
+    for d in cfg.dayparts.iter_mut() {
+        d.days = d.days.iter().map(|day| day.trim().to_lowercase()).collect();
+        let known_days = d.days.iter().all(|day| clocks::DAYS.contains(&day.as_str()));
+        if !known_days || parse_hhmm(&d.start).is_none() || parse_hhmm(&d.end).is_none() {
+            return bad("daypart needs known days and HH:MM start/end");
+        }
+    }
+    if cfg.enabled && cfg.time && cfg.time_dir.is_empty() {
+        return bad("time announcements need time_dir");
+    }
+    if cfg.id_cart.trim().is_empty() && !cfg.id_text.trim().is_empty() && !cfg.tts_command.contains("{out}") {
+        return bad("tts_command must contain {out}");
+    }
+    if !cfg.time_dir.is_empty() && !FsPath::new(&cfg.time_dir).is_dir() {
+        tracing::warn!("announce: time_dir {} does not exist (yet)", cfg.time_dir);
+    }
+    let to_save = cfg.clone();
+    with_db(move |conn| db_save(conn, &to_save))
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"ok": false}))))?;
+    Ok(Json(json!({"ok": true, "config": cfg})))
+}
@@ -0,0 +1,936 @@
+// --- HTTP API -------------------------------------------------------------------------------
+//
+// The router and the handlers that only read or poke the shared state
+// (status, system info, transport). Subsystems keep their own handlers next
+// to their code (`api_output_*` in output.rs, `api_queue_*` in queue.rs, ...);
+// this file wires them to paths.
+
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    routing::{get, patch, post, put},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use serde_json::value::RawValue;
+
+use crate::{
+    advance_to_next, aes67, alerts, announce, api_aux_queue_add, api_aux_queue_get, api_aux_queue_item_delete,
+    api_aux_queue_item_enqueue, api_aux_queue_item_patch, api_aux_queue_replace, api_aux_queues_list, api_output_get,
+    api_output_set_config, api_output_start, api_output_stop, api_output_test_tone, api_queue_batch, api_queue_clear,
+    api_queue_insert, api_queue_item_patch, api_queue_move, api_queue_remove, api_queue_reorder, api_queue_replace,
+    api_queue_requeue, api_topup_get, api_topup_preview, api_topup_run, api_topup_set_config, api_webrtc_candidate,
+    api_webrtc_offer, art, asrun, azuracast, backup, bots, breaks, callers, carts, cartwall, changes, clocks, daylog,
+    diagnostics, emergency, eventlog, events, export, failures, fallback, ffmpeg, gpio, history, import, ingest,
+    library, logbuf, macros, maintenance, metapush, meters, mqtt, preview, producers, public, rds, recorder, reload,
+    remote, requests, reset_demo_playout, rivendell, rotation, schedule, selfcheck, serve, settings, simulate, standby,
+    stl, storage, supervisor, surfaces, tags, timesync, topuplog, transitions, update, voicetrack, waveform, AppState,
+    NowPlaying, VuLevels,
+};
+
+/// Sections of `/api/v1/status` that `?fields=` can pick.
+const STATUS_FIELDS: &[&str] = &["now", "vu", "queue", "log", "recent", "producers", "callers", "system"];
+/// The sections an unauthenticated caller gets (see public.rs).
+const PUBLIC_STATUS_FIELDS: &[&str] = &["now", "vu", "queue", "log"];
+
+/// Sections that are not requested, or unchanged in delta mode, are left out.
+#[derive(Serialize)]
+struct StatusResponse {
+    version: String,
+    /// Current queue revision. Echo this back (body `rev` or `If-Match`) on
+    /// queue mutations so the engine can detect concurrent edits.
+    queue_rev: u64,
+    /// Status revision: pass it back as `?since_revision=` to get only the
+    /// lists that changed since (see snapshot.rs).
+    revision: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    now: Option<NowPlaying>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    vu: Option<VuLevels>,
+    /// Back-compat alias for the UI.
+    ///
+    /// The UI historically used `queue` while the engine used `log`.
+    /// Some UI builds treat a missing `queue` as a fatal parse error and
+    /// fall back to DEMO mode.
+    ///
+    /// We now serve both fields, from the same serialized snapshot.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    queue: Option<Box<RawValue>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    log: Option<Box<RawValue>>,
+    /// Recently aired items, newest first (ids usable with `/queue/requeue/{id}`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recent: Option<Box<RawValue>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    producers: Option<Box<RawValue>>,
+    /// Phone calls (callers.rs).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    callers: Option<Box<RawValue>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<SystemInfo>,
+    /// Delta mode: requested lists left out because they did not change.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unchanged: Option<Vec<&'static str>>,
+}
+
+#[derive(Deserialize)]
+struct StatusQuery {
+    /// Comma-separated sections (`STATUS_FIELDS`); all of them by default.
+    fields: Option<String>,
+    /// Delta mode: leave out the lists unchanged since this revision.
+    since_revision: Option<u64>,
+}
+
+
+
+/// Root endpoint: UI is served by nginx; the engine focuses on API/WebSocket.
+async fn root() -> &'static str {
+    "StudioCommand engine is running. UI is served by nginx. Try /api/v1/status"
+}
+pub(crate) fn build_router(state: AppState) -> Router {
+    let router = Router::new()
+        .route("/api/v1/transport/skip", post(api_transport_skip))
+        .route("/api/v1/transport/dump", post(api_transport_dump))
+        .route("/api/v1/transport/reload", post(api_transport_reload))
+        .route("/api/v1/queue/remove", post(api_queue_remove))
+        .route("/api/v1/webrtc/offer", post(api_webrtc_offer))
+        .route("/api/v1/webrtc/candidate", post(api_webrtc_candidate))
+        .route("/api/v1/queue/move", post(api_queue_move))
+        .route("/api/v1/queue/reorder", post(api_queue_reorder))
+        .route("/api/v1/queue/insert", post(api_queue_insert))
+        .route("/api/v1/queue/batch", post(api_queue_batch))
+        .route("/api/v1/queue", put(api_queue_replace))
+        .route("/api/v1/queue/items/:id", patch(api_queue_item_patch))
+        .route("/api/v1/queue/clear", post(api_queue_clear))
+        .route("/api/v1/queue/changes", get(changes::api_queue_changes))
+        .route("/api/v1/queue/import", post(import::api_queue_import))
+        .route("/api/v1/queue/export", get(export::api_queue_export))
+        .route("/api/v1/history", get(history::api_history))
+        .route("/api/v1/history/export", get(export::api_history_export))
+        .route("/api/v1/asrun/:date", get(asrun::api_asrun))
+        .route("/api/v1/metadata/targets", get(metapush::api_targets_list).post(metapush::api_target_create))
+        .route("/api/v1/metadata/targets/:id", put(metapush::api_target_put).delete(metapush::api_target_delete))
+        .route("/api/v1/metadata/targets/:id/test", post(metapush::api_target_test))
+        .route("/api/v1/alerts", get(alerts::api_alerts))
+        .route("/api/v1/alerts/config", get(alerts::api_alert_config_get).post(alerts::api_alert_config_set))
+        .route("/api/v1/alerts/channels", get(alerts::api_channels_list).post(alerts::api_channel_create))
+        .route("/api/v1/alerts/channels/:id", put(alerts::api_channel_put).delete(alerts::api_channel_delete))
+        .route("/api/v1/alerts/channels/:id/test", post(alerts::api_channel_test))
+        .route("/api/v1/gpio", get(gpio::api_gpio_get))
+        .route("/api/v1/gpio/config", post(gpio::api_gpio_config_set))
+        .route(
+            "/api/v1/surfaces/config",
+            get(surfaces::api_surfaces_config_get).post(surfaces::api_surfaces_config_set),
+        )
+        .route(
+            "/api/v1/surfaces/learn",
+            post(surfaces::api_surfaces_learn).delete(surfaces::api_surfaces_learn_cancel),
+        )
+        .route("/api/v1/surfaces/ws", get(surfaces::api_surfaces_ws))
+        .route("/api/v1/rds/config", get(rds::api_rds_config_get).post(rds::api_rds_config_set))
+        .route("/api/v1/aes67/config", get(aes67::api_aes67_config_get).post(aes67::api_aes67_config_set))
+        .route("/api/v1/aes67/sdp", get(aes67::api_aes67_sdp))
+        .route("/api/v1/stl/config", get(stl::api_stl_config_get).post(stl::api_stl_config_set))
+        .route("/api/v1/mqtt/config", get(mqtt::api_mqtt_config_get).post(mqtt::api_mqtt_config_set))
+        .route("/api/v1/bots/config", get(bots::api_bots_config_get).post(bots::api_bots_config_set))
+        .route("/api/v1/public/bots/discord", post(bots::api_discord_interaction))
+        .route("/api/v1/cartwall", get(cartwall::api_cartwall_get))
+        .route("/api/v1/cartwall/grid", post(cartwall::api_cartwall_grid_set))
+        .route("/api/v1/cartwall/stop", post(cartwall::api_cartwall_stop_all))
+        .route(
+            "/api/v1/cartwall/:slot",
+            put(cartwall::api_cartwall_slot_put).delete(cartwall::api_cartwall_slot_delete),
+        )
+        .route("/api/v1/cartwall/:slot/fire", post(cartwall::api_cartwall_fire))
+        .route("/api/v1/cartwall/:slot/stop", post(cartwall::api_cartwall_stop))
+        .route(
+            "/api/v1/voicetracks",
+            get(voicetrack::api_voicetracks_list).post(voicetrack::api_voicetrack_upload)
+                .layer(axum::extract::DefaultBodyLimit::max(voicetrack::MAX_UPLOAD_BYTES)),
+        )
+        .route(
+            "/api/v1/voicetracks/:id",
+            get(voicetrack::api_voicetrack_get)
+                .patch(voicetrack::api_voicetrack_patch)
+                .delete(voicetrack::api_voicetrack_delete),
+        )
+        .route("/api/v1/voicetracks/:id/attach", post(voicetrack::api_voicetrack_attach))
+        .route("/api/v1/emergency", get(emergency::api_emergency_get))
+        .route("/api/v1/producers", get(producers::api_producers_list).post(producers::api_producer_create))
+        .route(
+            "/api/v1/producers/:id",
+            patch(producers::api_producer_patch).delete(producers::api_producer_delete),
+        )
+        .route("/api/v1/producers/:id/token", post(producers::api_producer_token))
+        .route("/api/v1/producers/:id/mix", post(producers::api_producer_mix))
+        .route("/api/v1/producers/messages", get(producers::api_messages_list).post(producers::api_message_send))
+        .route("/api/v1/producers/ws", get(producers::api_messages_ws))
+        .route("/api/v1/callers", get(callers::api_callers_get))
+        .route("/api/v1/callers/config", get(callers::api_callers_config_get).post(callers::api_callers_config_set))
+        .route("/api/v1/callers/log", get(callers::api_callers_log))
+        .route("/api/v1/callers/:id/answer", post(callers::api_caller_answer))
+        .route("/api/v1/callers/:id/hangup", post(callers::api_caller_hangup))
+        .route("/api/v1/callers/:id/mix", post(callers::api_caller_mix))
+        .route("/api/v1/recorder", get(recorder::api_recorder_get))
+        .route("/api/v1/recorder/start", post(recorder::api_recorder_start))
+        .route("/api/v1/recorder/stop", post(recorder::api_recorder_stop))
+        .route(
+            "/api/v1/emergency/play",
+            post(emergency::api_emergency_play)
+                .layer(axum::extract::DefaultBodyLimit::max(emergency::MAX_UPLOAD_BYTES)),
+        )
+        .route("/api/v1/emergency/stop", post(emergency::api_emergency_stop))
+        .route("/api/v1/macros", get(macros::api_macros_list))
+        .route(
+            "/api/v1/macros/:name",
+            get(macros::api_macro_get).put(macros::api_macro_put).delete(macros::api_macro_delete),
+        )
+        .route("/api/v1/macros/:name/run", post(macros::api_macro_run))
+        .route("/api/v1/public/hooks/:token", post(macros::api_macro_hook))
+        .route("/api/v1/public/producers/:token", get(producers::api_producer_me))
+        .route("/api/v1/public/producers/:token/heartbeat", post(producers::api_producer_heartbeat))
+        .route("/api/v1/public/producers/:token/leave", post(producers::api_producer_leave))
+        .route(
+            "/api/v1/public/producers/:token/messages",
+            get(producers::api_producer_messages).post(producers::api_producer_message_send),
+        )
+        .route("/api/v1/public/producers/:token/ws", get(producers::api_producer_ws))
+        .route("/api/v1/public/nowplaying", get(public::api_public_nowplaying))
+        .route("/api/v1/public/history", get(public::api_public_history))
+        .route("/api/v1/public/upcoming", get(public::api_public_upcoming))
+        .route("/api/nowplaying", get(azuracast::api_nowplaying_all))
+        .route("/api/nowplaying/:station", get(azuracast::api_nowplaying_station))
+        .route("/api/nowplaying_static/:station", get(azuracast::api_nowplaying_station))
+        .route("/api/v1/public/library", get(requests::api_public_library))
+        .route(
+            "/api/v1/public/requests",
+            post(requests::api_public_request_submit).options(requests::api_public_request_preflight),
+        )
+        .route("/api/v1/requests", get(requests::api_requests_list))
+        .route("/api/v1/requests/:id/approve", post(requests::api_request_approve))
+        .route("/api/v1/requests/:id/reject", post(requests::api_request_reject))
+        .route("/api/v1/requests/:id/queue", post(requests::api_request_queue))
+        .route(
+            "/api/v1/public-feed/config",
+            get(public::api_public_feed_config_get).post(public::api_public_feed_config_set),
+        )
+        .route("/api/v1/queue/requeue/:id", post(api_queue_requeue))
+        .route("/api/v1/queues", get(api_aux_queues_list))
+        .route(
+            "/api/v1/transitions/config",
+            get(transitions::api_transitions_config_get).post(transitions::api_transitions_config_set),
+        )
+        .route("/api/v1/remote/config", get(remote::api_remote_config_get).post(remote::api_remote_config_set))
+        .route("/api/v1/breaks/config", get(breaks::api_break_config_get).post(breaks::api_break_config_set))
+        .route("/api/v1/announce/config", get(announce::api_announce_config_get).post(announce::api_announce_config_set))
+        .route("/api/v1/queues/:name", get(api_aux_queue_get).put(api_aux_queue_replace))
+        .route("/api/v1/queues/:name/items", post(api_aux_queue_add))
+        .route(
+            "/api/v1/queues/:name/items/:id",
+            patch(api_aux_queue_item_patch).delete(api_aux_queue_item_delete),
+        )
+        .route("/api/v1/queues/:name/items/:id/enqueue", post(api_aux_queue_item_enqueue))
+        .route("/api/v1/library", get(library::api_library_list))
+        .route("/api/v1/library/search", get(library::api_library_search))
+        .route("/api/v1/library/:id", get(library::api_library_get).patch(library::api_library_patch))
+        .route("/api/v1/library/:id/waveform", get(waveform::api_library_waveform))
+        .route("/api/v1/library/:id/art", get(art::api_library_art))
+        .route(
+            "/api/v1/library/config",
+            get(library::api_library_config_get).post(library::api_library_config_set),
+        )
+        .route(
+            "/api/v1/library/scan",
+            get(library::api_library_scan_status).post(library::api_library_scan_start),
+        )
+        .route("/api/v1/ingest/status", get(ingest::api_ingest_status))
+        .route(
+            "/api/v1/logs/import/csv/mapping",
+            get(import::api_csv_mapping_get).post(import::api_csv_mapping_set),
+        )
+        .route("/api/v1/logs", get(daylog::api_daylog_list))
+        .route("/api/v1/logs/config", get(daylog::api_daylog_config_get).post(daylog::api_daylog_config_set))
+        .route(
+            "/api/v1/logs/:date",
+            get(daylog::api_daylog_get).put(daylog::api_daylog_put).delete(daylog::api_daylog_delete),
+        )
+        .route("/api/v1/logs/:date/items", post(daylog::api_daylog_insert))
+        .route(
+            "/api/v1/logs/:date/items/:pos",
+            patch(daylog::api_daylog_item_patch).delete(daylog::api_daylog_item_delete),
+        )
+        .route("/api/v1/logs/:date/move", post(daylog::api_daylog_move))
+        .route("/api/v1/logs/:date/load", post(daylog::api_daylog_load))
+        .route("/api/v1/logs/:date/import/csv", post(import::api_daylog_import_csv))
+        .route("/api/v1/logs/:date/import/rivendell", post(rivendell::api_daylog_import_rivendell))
+        .route("/api/v1/logs/:date/generate", post(clocks::api_daylog_generate))
+        .route("/api/v1/schedule", get(schedule::api_schedule_list).post(schedule::api_schedule_create))
+        .route("/api/v1/schedule/preview", get(preview::api_schedule_preview))
+        .route("/api/v1/schedule/simulate", get(simulate::api_schedule_simulate))
+        .route("/api/v1/schedule/:id", put(schedule::api_schedule_put).delete(schedule::api_schedule_delete))
+        .route("/api/v1/schedule/:id/run", post(schedule::api_schedule_run))
+        .route("/api/v1/schedule/events", get(events::api_events_list).post(events::api_event_create))
+        .route("/api/v1/schedule/events/:id", put(events::api_event_put).delete(events::api_event_delete))
+        .route("/api/v1/clocks", get(clocks::api_clocks_list))
+        .route(
+            "/api/v1/rotation/rules",
+            get(rotation::api_rotation_rules_get).post(rotation::api_rotation_rules_set),
+        )
+        .route("/api/v1/clocks/schedule", get(clocks::api_clock_schedule_get).post(clocks::api_clock_schedule_set))
+        .route(
+            "/api/v1/clocks/:name",
+            get(clocks::api_clock_get).put(clocks::api_clock_put).delete(clocks::api_clock_delete),
+        )
+        .route("/api/v1/rml", post(rivendell::api_rml))
+        .route("/api/v1/carts", get(carts::api_carts_list).post(carts::api_cart_create))
+        .route("/api/v1/carts/categories", get(carts::api_cart_categories_list))
+        .route(
+            "/api/v1/carts/categories/:code",
+            put(carts::api_cart_category_put).delete(carts::api_cart_category_delete),
+        )
+        .route(
+            "/api/v1/carts/:cart",
+            get(carts::api_cart_get).patch(carts::api_cart_patch).delete(carts::api_cart_delete),
+        )
+        .route("/api/v1/tags", get(tags::api_tags_list))
+        .route("/api/v1/tags/:code", put(tags::api_tag_put).delete(tags::api_tag_delete))
+        .route("/api/v1/ingest/config", post(ingest::api_ingest_set_config))
+        .route("/health", get(|| async { "OK" }))
+        .route("/ready", get(selfcheck::ready))
+        .route("/api/v1/selfcheck", get(selfcheck::api_selfcheck))
+        .route("/api/v1/status", get(status))
+        // Lightweight endpoint for high-rate meter polling.
+        .route("/api/v1/meters", get(api_meters))
+        .route("/api/v1/ping", get(ping))
+        .route("/api/v1/system/info", get(system_info))
+        .route("/api/v1/system/ffmpeg", get(ffmpeg::api_ffmpeg))
+        // Admin: System dashboard (v1.0-lite)
+        // This is designed to be additive-only so the UI can evolve safely.
+        .route("/api/v1/admin/system", get(api_admin_system_v1_lite))
+        .route("/api/v1/admin/backup", post(backup::api_backup))
+        .route("/api/v1/admin/backups", get(backup::api_backups_list))
+        .route(
+            "/api/v1/admin/backups/upload",
+            post(backup::api_backup_upload).layer(axum::extract::DefaultBodyLimit::max(backup::MAX_UPLOAD_BYTES)),
+        )
+        .route("/api/v1/admin/restore", post(backup::api_restore))
+        .route("/api/v1/admin/db/status", get(maintenance::api_db_status))
+        .route("/api/v1/admin/db/maintenance", post(maintenance::api_db_maintenance))
+        .route("/api/v1/admin/reload", post(reload::api_reload))
+        .route("/api/v1/admin/logs", get(logbuf::api_admin_logs))
+        .route(
+            "/api/v1/admin/audio/diagnostics",
+            get(diagnostics::api_audio_diagnostics).delete(diagnostics::api_audio_diagnostics_reset),
+        )
+        .route("/api/v1/admin/config", get(settings::api_config_export).post(settings::api_config_import))
+        .route("/api/v1/output", get(api_output_get))
+        .route("/api/v1/output/config", post(api_output_set_config))
+        .route("/api/v1/output/start", post(api_output_start))
+        .route("/api/v1/output/stop", post(api_output_stop))
+        .route("/api/v1/output/test-tone", post(api_output_test_tone))
+        .route(
+            "/api/v1/output/fallback",
+            get(fallback::api_fallback_config_get).post(fallback::api_fallback_config_set),
+        )
+        .route("/api/v1/standby", get(standby::api_standby))
+        .route("/api/v1/standby/takeover", post(standby::api_standby_takeover))
+        .route("/api/v1/standby/failback", post(standby::api_standby_failback))
+        .route("/api/v1/output/events", get(supervisor::api_output_events))
+        .route("/api/v1/playout/topup", get(api_topup_get))
+        .route("/api/v1/playout/topup/config", post(api_topup_set_config))
+        .route("/api/v1/playout/topup/run", post(api_topup_run))
+        .route("/api/v1/playout/topup/preview", get(api_topup_preview))
+        .route("/api/v1/playout/topup/history", get(topuplog::api_topup_history))
+        .route("/api/v1/engine/events", get(eventlog::api_engine_events))
+        .route("/api/v1/playout/failures", get(failures::api_failures).delete(failures::api_failures_clear))
+        .route("/admin/api/v1/update/status", get(update_status))
+        .route("/admin/api/v1/update/check", post(update::api_check))
+        .route("/admin/api/v1/update/apply", post(update::api_apply))
+        .route("/admin/api/v1/update/rollback", post(update::api_rollback))
+        .with_state(state);
+
+    // The browser UI, when the engine serves it itself (see serve.rs).
+    match serve::web_dir() {
+        Some(dir) => serve::with_ui(router, dir),
+        None => router.route("/", get(root)),
+    }
+}
+
+
+
+async fn status(
+    State(state): State<AppState>,
+    Query(q): Query<StatusQuery>,
+    headers: HeaderMap,
+) -> Result<Json<StatusResponse>, (StatusCode, Json<serde_json::Value>)> {
+    let mut wanted: Vec<&str> = match &q.fields {
+        Some(f) => f.split(',').map(str::trim).filter(|f| !f.is_empty()).collect(),
+        None => STATUS_FIELDS.to_vec(),
+    };
+    if let Some(bad) = wanted.iter().find(|f| !STATUS_FIELDS.contains(f)) {
+        let error = format!("unknown field {bad}");
+        return Err((StatusCode::BAD_REQUEST, Json(json!({"ok": false, "error": error, "fields": STATUS_FIELDS}))));
+    }
+    let public_cfg = match public::is_public_request(&headers) {
+        true => Some(public::load_config().await),
+        false => None,
+    };
+    if public_cfg.is_some() {
+        wanted.retain(|f| PUBLIC_STATUS_FIELDS.contains(f));
+    }
+    let want = |name: &str| wanted.contains(&name);
+
+    // Refresh system snapshot (only when asked for: it reads the whole host).
+    let system = if want("system") { Some(system_info(State(state.clone())).await.0) } else { None };
+
+    // Never the playout lock: the snapshot is at most 50 ms old, and air
+    // times are re-estimated at least once a second (see snapshot.rs).
+    let snap = state.snapshot.borrow().clone();
+    // Unauthenticated: the queue as the public feeds show it (or none if it
+    // cannot be read), and no `now` while something other than music airs.
+    let public_view = public_cfg.as_ref().map(|cfg| cfg.public_log(&snap.log));
+    let (log_src, music_on_air): (Option<&RawValue>, bool) = match &public_view {
+        None => (Some(&snap.log), true),
+        Some(Some((log, on_air))) => (Some(log), *on_air),
+        Some(None) => (None, false),
+    };
+
+    // A list goes out when asked for and, in delta mode, changed since.
+    let mut unchanged = Vec::new();
+    let mut list = |name: &'static str, changed: u64, v: &RawValue| {
+        if !want(name) {
+            return None;
+        }
+        if q.since_revision.is_some_and(|since| changed <= since) {
+            unchanged.push(name);
+            return None;
+        }
+        Some(v.to_owned())
+    };
+    // Back-compat: serve both `queue` and `log`.
+    let queue = log_src.and_then(|l| list("queue", snap.changed.log, l));
+    let log = log_src.and_then(|l| list("log", snap.changed.log, l));
+    let recent = list("recent", snap.changed.recent, &snap.recent);
+    let producers = list("producers", snap.changed.producers, &snap.producers);
+    let callers = list("callers", snap.changed.callers, &snap.callers);
+
+    Ok(Json(StatusResponse {
+        version: state.version.clone(),
+        queue_rev: snap.queue_rev,
+        revision: snap.revision,
+        // now.pos/now.pos_f are maintained in the playout loop using a monotonic clock.
+        now: (want("now") && music_on_air).then(|| snap.now.clone()),
+        vu: want("vu").then(meters::get),
+        queue,
+        log,
+        recent,
+        producers,
+        callers,
+        system,
+        unchanged: q.since_revision.map(|_| unchanged),
+    }))
+}
+
+// High-rate meter polling endpoint. Keep it tiny so it stays responsive even
+// over higher-latency connections.
+async fn api_meters() -> Json<VuLevels> {
+    Json(meters::get())
+}
+
+
+#[derive(Serialize)]
+struct SystemInfo {
+    name: String,
+    version: String,
+    arch: String,
+    cpu_model: String,
+    cpu_cores: usize,
+    load_1m: f32,
+    load_5m: f32,
+    load_15m: f32,
+    temp_c: Option<f32>,
+    hostname: Option<String>,
+    /// Free space where the engine writes (see `storage.rs`).
+    storage: Vec<storage::StorageCheck>,
+    /// NTP sync state and offset (see `timesync.rs`).
+    clock: timesync::TimeSync,
+    /// Locations below the free-space thresholds, and an unsynchronized clock.
+    warnings: Vec<String>,
+}
+
+// --- Admin: System dashboard schema (v1.0-lite) ---------------------------
+//
+// Contract goals:
+// - Safe for LIVE: collection must not hang the request (especially on dead
+//   network mounts).
+// - Additive-only: we can add new fields without breaking older UIs.
+// - UI-friendly: small number of stable, well-named fields.
+
+#[derive(Serialize)]
+struct AdminSystemV1Lite {
+    schema_version: String,
+    generated_at: String,
+    build: AdminBuildInfo,
+    server: AdminServerInfo,
+    engine: AdminEngineInfo,
+    host: AdminHostInfo,
+    storage: AdminStorageInfo,
+    events: AdminEvents,
+}
+
+#[derive(Serialize)]
+struct AdminBuildInfo {
+    version: String,
+    // Optional: if the build pipeline injects this later, the UI can display it.
+    // We keep the field for forward-compat, but return null/empty for now.
+    commit: Option<String>,
+}
+
+#[derive(Serialize)]
+struct AdminServerInfo {
+    hostname: Option<String>,
+    timezone: String,
+    uptime_s: u64,
+}
+
+#[derive(Serialize)]
+struct AdminEngineInfo {
+    // The operator's intent is "LIVE"; this engine build currently runs real
+    // playout, so we report LIVE. If a future demo mode returns, this can be
+    // computed instead of hard-coded.
+    mode: String,
+    status: String,
+}
+
+#[derive(Serialize)]
+struct AdminHostInfo {
+    cpu: AdminCpuInfo,
+    memory: AdminMemoryInfo,
+}
+
+#[derive(Serialize)]
+struct AdminCpuInfo {
+    load: AdminLoadAvg,
+}
+
+#[derive(Serialize)]
+struct AdminLoadAvg {
+    one: f32,
+    five: f32,
+    fifteen: f32,
+}
+
+#[derive(Serialize)]
+struct AdminMemoryInfo {
+    total_bytes: u64,
+    used_bytes: u64,
+    available_bytes: u64,
+}
+
+#[derive(Serialize)]
+struct AdminStorageInfo {
+    filesystems: Vec<AdminFilesystem>,
+}
+
+#[derive(Serialize)]
+struct AdminFilesystem {
+    mount: String,
+    source: String,
+    fstype: String,
+    flags: Vec<String>,
+    size_bytes: Option<u64>,
+    used_bytes: Option<u64>,
+    free_bytes: Option<u64>,
+    used_pct: Option<f32>,
+    status: String,
+    message: String,
+}
+
+#[derive(Serialize)]
+struct AdminEvents {
+    recent: Vec<AdminEvent>,
+}
+
+#[derive(Serialize)]
+struct AdminEvent {
+    // RFC3339 UTC when available; empty when the underlying source has no
+    // timestamp (e.g. stderr tail lines).
+    ts: String,
+    level: String,
+    component: String,
+    message: String,
+}
+
+
+
+
+async fn ping(State(state): State<AppState>) -> Json<serde_json::Value> {
+    Json(json!({
+        "ok": true,
+        "version": state.version,
+        "features": ["status", "transport"]
+    }))
+}
+
+async fn system_info(State(st): State<AppState>) -> Json<SystemInfo> {
+    let arch = std::env::consts::ARCH.to_string();
+    let hostname = sysinfo::System::host_name();
+
+    let mut sys = st.sys.lock().await;
+    sys.refresh_all();
+
+    let cpu_model = sys
+        .cpus()
+        .first()
+        .map(|c| c.brand().to_string())
+        .unwrap_or_else(|| "Unknown CPU".to_string());
+    let cpu_cores = sys.cpus().len();
+
+    let la = sysinfo::System::load_average();
+    let temp_c = read_temp_c().ok().flatten();
+    drop(sys);
+
+    let storage = storage::check_all().await;
+    let clock = timesync::status().await;
+    let mut warnings = storage::warnings(&storage);
+    warnings.extend(clock.warning());
+
+    Json(SystemInfo {
+        name: "StudioCommand Playout".to_string(),
+        version: st.version.clone(),
+        arch,
+        cpu_model,
+        cpu_cores,
+        load_1m: la.one as f32,
+        load_5m: la.five as f32,
+        load_15m: la.fifteen as f32,
+        temp_c,
+        hostname,
+        storage,
+        clock,
+        warnings,
+    })
+}
+
+// Admin System (v1.0-lite)
+//
+// This endpoint intentionally avoids "deep" checks and never blocks on slow or
+// broken resources (especially network mounts). For anything that might block,
+// we run it in a blocking thread and time-box it.
+async fn api_admin_system_v1_lite(State(st): State<AppState>) -> Json<AdminSystemV1Lite> {
+    use time::format_description::well_known::Rfc3339;
+    use time::OffsetDateTime;
+    use tokio::time::{timeout, Duration};
+
+    let generated_at = OffsetDateTime::now_utc()
+        .format(&Rfc3339)
+        .unwrap_or_else(|_| "".to_string());
+
+    // Host + load/memory via sysinfo. (sysinfo reports memory in KiB on some
+    // platforms; we standardize to bytes by multiplying by 1024.)
+    let mut sys = st.sys.lock().await;
+    sys.refresh_cpu_all();
+    sys.refresh_memory();
+    let la = sysinfo::System::load_average();
+    let uptime_s = sysinfo::System::uptime();
+    let raw_total = sys.total_memory();
+    let raw_avail = sys.available_memory();
+    // sysinfo historically reported memory in KiB, but some builds report bytes.
+    // Heuristic: values above ~2e9 are almost certainly bytes (>= ~2 GB).
+    let total_bytes = if raw_total > 2_000_000_000 { raw_total } else { raw_total.saturating_mul(1024) };
+    let available_bytes = if raw_avail > 2_000_000_000 { raw_avail } else { raw_avail.saturating_mul(1024) };
+    let used_bytes = total_bytes.saturating_sub(available_bytes);
+
+    drop(sys);
+
+    // Filesystems/mounts (safe, time-boxed).
+    let filesystems = match timeout(Duration::from_millis(650), collect_filesystems_v1_lite()).await {
+        Ok(v) => v,
+        Err(_) => vec![AdminFilesystem {
+            mount: "/".to_string(),
+            source: "unknown".to_string(),
+            fstype: "unknown".to_string(),
+            flags: vec![],
+            size_bytes: None,
+            used_bytes: None,
+            free_bytes: None,
+            used_pct: None,
+            status: "unknown".to_string(),
+            message: "filesystem scan timed out".to_string(),
+        }],
+    };
+
+    // Recent events: best-effort, non-blocking. For now, we surface the
+    // streaming output stderr tail (if configured) because it is frequently the
+    // most actionable information for ops.
+    let recent = {
+        let out = st.output.lock().await;
+        out.stderr_tail
+            .iter()
+            .rev()
+            .take(20)
+            .rev()
+            .map(|line| AdminEvent {
+                ts: "".to_string(),
+                level: "info".to_string(),
+                component: "output".to_string(),
+                message: line.clone(),
+            })
+            .collect::<Vec<_>>()
+    };
+
+    Json(AdminSystemV1Lite {
+        schema_version: "1.0-lite".to_string(),
+        generated_at,
+        build: AdminBuildInfo {
+            version: st.version.clone(),
+            commit: None,
+        },
+        server: AdminServerInfo {
+            hostname: sysinfo::System::host_name(),
+            timezone: "America/Chicago".to_string(),
+            uptime_s,
+        },
+        engine: AdminEngineInfo {
+            mode: "LIVE".to_string(),
+            status: "ok".to_string(),
+        },
+        host: AdminHostInfo {
+            cpu: AdminCpuInfo {
+                load: AdminLoadAvg {
+                    one: la.one as f32,
+                    five: la.five as f32,
+                    fifteen: la.fifteen as f32,
+                },
+            },
+            memory: AdminMemoryInfo {
+                total_bytes,
+                used_bytes,
+                available_bytes,
+            },
+        },
+        storage: AdminStorageInfo { filesystems },
+        events: AdminEvents { recent },
+    })
+}
+
+/// Collect mounted filesystems safely.
+///
+/// We parse /proc/self/mountinfo (fast, local) to discover mount points, then
+/// compute space for each mount via statvfs(). Each statvfs call is time-boxed
+/// so a dead network mount can never hang the request.
+async fn collect_filesystems_v1_lite() -> Vec<AdminFilesystem> {
+    use tokio::time::{timeout, Duration};
+
+    let mounts = read_mountinfo();
+    let mut out = Vec::new();
+
+    for m in mounts {
+        // Each stat call gets its own short timeout.
+        let mount_path = m.mount.clone();
+        let stat_res = timeout(
+            Duration::from_millis(80),
+            tokio::task::spawn_blocking(move || statvfs_bytes(&mount_path)),
+        )
+        .await;
+
+        match stat_res {
+            Ok(Ok(Ok((size, used, free, used_pct)))) => {
+                let (status, message) = if used_pct >= 90.0 {
+                    ("crit", "disk usage above 90%")
+                } else if used_pct >= 80.0 {
+                    ("warn", "disk usage above 80%")
+                } else {
+                    ("ok", "")
+                };
+
+                out.push(AdminFilesystem {
+                    mount: m.mount,
+                    source: m.source,
+                    fstype: m.fstype,
+                    flags: m.flags,
+                    size_bytes: Some(size),
+                    used_bytes: Some(used),
+                    free_bytes: Some(free),
+                    used_pct: Some(used_pct),
+                    status: status.to_string(),
+                    message: message.to_string(),
+                });
+            }
+            Ok(Ok(Err(e))) => {
+                out.push(AdminFilesystem {
+                    mount: m.mount,
+                    source: m.source,
+                    fstype: m.fstype,
+                    flags: m.flags,
+                    size_bytes: None,
+                    used_bytes: None,
+                    free_bytes: None,
+                    used_pct: None,
+                    status: "unknown".to_string(),
+                    message: format!("statvfs failed: {e}"),
+                });
+            }
+            Ok(Err(join_err)) => {
+                out.push(AdminFilesystem {
+                    mount: m.mount,
+                    source: m.source,
+                    fstype: m.fstype,
+                    flags: m.flags,
+                    size_bytes: None,
+                    used_bytes: None,
+                    free_bytes: None,
+                    used_pct: None,
+                    status: "unknown".to_string(),
+                    message: format!("statvfs task failed: {join_err}"),
+                });
+            }
+            Err(_) => {
+                out.push(AdminFilesystem {
+                    mount: m.mount,
+                    source: m.source,
+                    fstype: m.fstype,
+                    flags: m.flags,
+                    size_bytes: None,
+                    used_bytes: None,
+                    free_bytes: None,
+                    used_pct: None,
+                    status: "unknown".to_string(),
+                    message: "statvfs timed out".to_string(),
+                });
+            }
+        }
+    }
+
+    // Stable sort so the UI doesn't jitter.
+    out.sort_by(|a, b| a.mount.cmp(&b.mount));
+    out
+}
+
+#[derive(Clone)]
+pub(crate) struct MountInfoRow {
+    pub(crate) mount: String,
+    source: String,
+    fstype: String,
+    flags: Vec<String>,
+}
+
+pub(crate) fn read_mountinfo() -> Vec<MountInfoRow> {
+    let s = match std::fs::read_to_string("/proc/self/mountinfo") {
+        Ok(s) => s,
+        Err(_) => return vec![],
+    };
+
+    let mut rows = Vec::new();
+    for line in s.lines() {
+        // Split "optional" fields from the fstype/source section.
+        let (left, right) = match line.split_once(" - ") {
+            Some(p) => p,
+            None => continue,
+        };
+
+        let left_fields: Vec<&str> = left.split_whitespace().collect();
+        if left_fields.len() < 6 {
+            continue;
+        }
+        let mount_point = left_fields[4];
+        let flags = left_fields[5]
+            .split(',')
+            .filter(|x| !x.is_empty())
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>();
+
+        let right_fields: Vec<&str> = right.split_whitespace().collect();
+        if right_fields.len() < 2 {
+            continue;
+        }
+        let fstype = right_fields[0];
+        let source = right_fields[1];
+
+        rows.push(MountInfoRow {
+            mount: mount_point.to_string(),
+            source: source.to_string(),
+            fstype: fstype.to_string(),
+            flags,
+        });
+    }
+    rows
+}
+
+pub(crate) fn statvfs_bytes(path: &str) -> anyhow::Result<(u64, u64, u64, f32)> {
+    use std::ffi::CString;
+
+    let c_path = CString::new(path).map_err(|_| anyhow::anyhow!("invalid path"))?;
+    let mut vfs: libc::statvfs = unsafe { std::mem::zeroed() };
+
+    let rc = unsafe { libc::statvfs(c_path.as_ptr(), &mut vfs as *mut libc::statvfs) };
+    if rc != 0 {
+        return Err(anyhow::anyhow!("errno {}", std::io::Error::last_os_error()));
+    }
+
+    let frsize = if vfs.f_frsize > 0 { vfs.f_frsize } else { vfs.f_bsize } as u64;
+    let total = frsize.saturating_mul(vfs.f_blocks as u64);
+    let free = frsize.saturating_mul(vfs.f_bavail as u64);
+    let used = total.saturating_sub(free);
+    let used_pct = if total > 0 {
+        (used as f64 / total as f64 * 100.0) as f32
+    } else {
+        0.0
+    };
+
+    Ok((total, used, free, used_pct))
+}
+
+fn read_temp_c() -> anyhow::Result<Option<f32>> {
+    let paths = [
+        "/sys/class/thermal/thermal_zone0/temp",
+        "/sys/class/hwmon/hwmon0/temp1_input",
+    ];
+    for p in paths {
+        if let Ok(s) = std::fs::read_to_string(p) {
+            if let Ok(v) = s.trim().parse::<f32>() {
+                let c = if v > 1000.0 { v / 1000.0 } else { v };
+                return Ok(Some(c));
+            }
+        }
+    }
+    Ok(None)
+}
+
+#[derive(Serialize)]
+pub(crate) struct UpdateStatus {
+    pub(crate) state: String,
+    pub(crate) current: String,
+    pub(crate) available: Option<String>,
+    pub(crate) staged: Option<String>,
+    pub(crate) last_result: Option<String>,
+    pub(crate) progress: Option<u8>,
+    pub(crate) arch: String,
+}
+
+async fn update_status(State(st): State<AppState>) -> Json<UpdateStatus> {
+    Json(update::status(&st.version))
+}
+
+async fn api_transport_skip(State(state): State<AppState>) -> Json<serde_json::Value> {
+    // "Skip" advances immediately to the next item in the playout log.
+    let mut p = state.playout.write().await;
+    advance_to_next(&mut p, Some("skipped"));
+    Json(json!({"ok": true}))
+}
+
+async fn api_transport_dump(State(state): State<AppState>) -> Json<serde_json::Value> {
+    // "Dump" is an operator action to instantly remove the current playing item.
+    // In this stub engine, we treat it as "skip with reason=dumped".
+    let mut p = state.playout.write().await;
+    advance_to_next(&mut p, Some("dumped"));
+    Json(json!({"ok": true}))
+}
+
+async fn api_transport_reload(State(state): State<AppState>) -> Json<serde_json::Value> {
+    // "Reload" repopulates the in-memory demo log.
+    let mut p = state.playout.write().await;
+    reset_demo_playout(&mut p);
+    Json(json!({"ok": true}))
+}
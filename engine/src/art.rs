@@ -0,0 +1,136 @@
+// --- Cover art ----------------------------------------------------------------
+//
+// `GET /api/v1/library/{id}/art?size=300` serves a JPEG of the track's cover:
+// the artwork embedded in the file if there is one, otherwise a `folder.jpg`
+// style image next to it. ffmpeg does the extraction and resizing; results
+// (including "this file has no art") are cached on disk, so the dashboard and
+// public now-playing widgets polling the same URL cost one file read.
+
+use std::path::{Path as FsPath, PathBuf};
+
+use axum::{
+    extract::{Path, Query},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+
+use crate::{library, paths};
+
+/// Served sizes (square bounding box, pixels). Requests snap to the nearest.
+const SIZES: [u32; 4] = [64, 150, 300, 600];
+
+/// Image files we accept as folder art, in order of preference (case-insensitive).
+const FOLDER_ART: [&str; 6] = ["folder.jpg", "cover.jpg", "front.jpg", "albumart.jpg", "cover.png", "folder.png"];
+
+fn cache_dir() -> PathBuf {
+    std::env::var("STUDIOCOMMAND_ART_CACHE")
+        .unwrap_or_else(|_| "/opt/studiocommand/shared/cache/art".to_string())
+        .into()
+}
+
+/// Cache key: changes whenever the file does, so edited tags/art are picked up.
+fn cache_stem(path: &str) -> Option<String> {
+    use std::hash::{Hash, Hasher};
+
+    let md = std::fs::metadata(paths::from_cart(path)).ok()?;
+    let mut h = std::collections::hash_map::DefaultHasher::new();
+    path.hash(&mut h);
+    library::file_mtime_secs(&md).hash(&mut h);
+    md.len().hash(&mut h);
+    Some(format!("{:016x}", h.finish()))
+}
+
+fn folder_art(path: &str) -> Option<PathBuf> {
+    let path = paths::from_cart(path);
+    let dir = path.parent()?;
+    let names: Vec<(String, PathBuf)> = std::fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .map(|e| (e.file_name().to_string_lossy().to_ascii_lowercase(), e.path()))
+        .collect();
+    FOLDER_ART
+        .iter()
+        .find_map(|want| names.iter().find(|(n, _)| n == want).map(|(_, p)| p.clone()))
+}
+
+/// Run ffmpeg to pull the first picture out of `input` (an audio file with an
+/// attached picture, or an image) scaled to fit `size`x`size`, into `out`.
+async fn extract_to(input: &FsPath, size: u32, out: &FsPath) -> bool {
+    use tokio::time::{timeout, Duration};
+
+    let ffmpeg = crate::ffmpeg::ffmpeg_bin();
+    let mut cmd = tokio::process::Command::new(ffmpeg);
+    cmd.arg("-v").arg("error").arg("-y")
+        .arg("-i").arg(paths::ffmpeg_input(input))
+        .arg("-an")
+        .arg("-map").arg("0:v:0")
+        .arg("-frames:v").arg("1")
+        .arg("-vf").arg(format!("scale={size}:{size}:force_original_aspect_ratio=decrease"))
+        .arg("-q:v").arg("3")
+        .arg("-f").arg("image2").arg("-c:v").arg("mjpeg")
+        .arg(out)
+        .kill_on_drop(true);
+
+    matches!(timeout(Duration::from_secs(15), cmd.status()).await, Ok(Ok(st)) if st.success())
+}
+
+/// JPEG bytes of the cover for `path` at `size`, if it has any.
+pub(crate) async fn cover_jpeg(path: &str, size: u32) -> Option<Vec<u8>> {
+    let stem = cache_stem(path)?;
+    let dir = cache_dir();
+    let jpg = dir.join(format!("{stem}-{size}.jpg"));
+    let none = dir.join(format!("{stem}.none"));
+
+    if let Ok(bytes) = tokio::fs::read(&jpg).await {
+        return Some(bytes);
+    }
+    if tokio::fs::metadata(&none).await.is_ok() {
+        return None;
+    }
+    if let Err(e) = tokio::fs::create_dir_all(&dir).await {
+        tracing::warn!("art: cannot create cache dir {}: {e}", dir.display());
+        return None;
+    }
+
+    // Write to a temp name and rename, so a concurrent request never reads a
+    // half-written JPEG.
+    let tmp = dir.join(format!("{stem}-{size}.{}.tmp", uuid::Uuid::new_v4()));
+    let mut found = extract_to(&paths::from_cart(path), size, &tmp).await;
+    if !found {
+        if let Some(img) = folder_art(path) {
+            found = extract_to(&img, size, &tmp).await;
+        }
+    }
+    if !found {
+        let _ = tokio::fs::remove_file(&tmp).await;
+        let _ = tokio::fs::write(&none, b"").await;
+        return None;
+    }
+    if tokio::fs::rename(&tmp, &jpg).await.is_err() {
+        let _ = tokio::fs::remove_file(&tmp).await;
+    }
+    tokio::fs::read(&jpg).await.ok()
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ArtQuery {
+    size: Option<u32>,
+}
+
+pub(crate) async fn api_library_art(Path(id): Path<i64>, Query(q): Query<ArtQuery>) -> Result<Response, StatusCode> {
+    let want = q.size.unwrap_or(300);
+    let size = *SIZES.iter().min_by_key(|s| s.abs_diff(want)).unwrap_or(&300);
+
+    let path = library::lookup_path(id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let bytes = cover_jpeg(&path, size).await.ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok((
+        [(header::CONTENT_TYPE, "image/jpeg"), (header::CACHE_CONTROL, "public, max-age=3600")],
+        bytes,
+    )
+        .into_response())
+}
@@ -0,0 +1,277 @@
+// --- As-run report ------------------------------------------------------------------
+//
+// Licensing bodies and advertisers ask "did it air, and when?". The as-run
+// report for a date reconciles the day log (what was scheduled) against
+// `play_history` (what actually went out):
+// - scheduled items are matched to aired ones by cart (title + artist for
+//   items without a cart) and report how they ended; unmatched ones are
+//   `missed`,
+// - aired items that were not in the day log (top-up, manual inserts,
+//   events) are listed as unscheduled,
+// - gaps of `DEAD_AIR_MS` or more between one item ending and the next
+//   starting are reported as dead air,
+// - top-up runs that appended items (automation filling in for the log) or
+//   failed are listed as fallback periods.
+//
+// `GET /api/v1/asrun/{date}` returns one time-ordered `rows` list plus a
+// summary as JSON (easy to lay out for print/PDF); `?format=csv` downloads
+// the same rows. Dates are local calendar days.
+
+use std::collections::HashSet;
+
+use axum::{
+    extract::{Path, Query},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::clocks::LocalHour;
+use crate::daylog::{self, DayLogItem};
+use crate::export::csv_field;
+use crate::history::{self, HistoryEntry};
+use crate::topuplog::{self, TopUpHistoryEntry};
+use crate::{fmt_dur_mmss, fmt_local_hhmmss};
+
+/// Shortest silence between items reported as dead air.
+const DEAD_AIR_MS: u64 = 10_000;
+
+#[derive(Deserialize)]
+pub(crate) struct AsRunQuery {
+    format: Option<String>,
+}
+
+#[derive(Serialize, Default)]
+struct AsRunRow {
+    /// "item", "dead_air" or "topup".
+    kind: &'static str,
+    /// Day log time (local `HH:MM:SS`); empty for unscheduled rows.
+    scheduled: String,
+    /// Actual start/end (local `HH:MM:SS`); empty when it never aired.
+    aired: String,
+    ended: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    started_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ended_ms: Option<u64>,
+    tag: String,
+    cart: String,
+    title: String,
+    artist: String,
+    dur: String,
+    /// Items: `played`, `skipped`, `dumped`, `faded`, `interrupted`,
+    /// `on_air` or `missed`. Dead air: `dead_air`. Top-up: `appended` or the error.
+    status: String,
+    #[serde(skip)]
+    sort_ms: Option<u64>,
+}
+
+#[derive(Serialize, Default)]
+struct AsRunSummary {
+    scheduled: u32,
+    aired_as_scheduled: u32,
+    missed: u32,
+    unscheduled: u32,
+    skipped: u32,
+    dumped: u32,
+    faded: u32,
+    interrupted: u32,
+    dead_air_count: u32,
+    dead_air_s: u64,
+    topup_runs: u32,
+    topup_items: u32,
+    topup_errors: u32,
+}
+
+/// Unix millis of a day log `HH:MM[:SS]` time on `date`.
+fn scheduled_ms(date: &str, time: &str) -> Option<u64> {
+    let mut parts = time.trim().split(':').map(|p| p.parse::<u32>().ok());
+    let (h, m) = (parts.next()??, parts.next()??);
+    let s = parts.next().flatten().unwrap_or(0);
+    if h >= 24 || m >= 60 || s >= 60 {
+        return None;
+    }
+    Some(LocalHour::local_ms(date, h * 60 + m)? + s as u64 * 1000)
+}
+
+fn same_item(s: &DayLogItem, a: &HistoryEntry) -> bool {
+    if !s.cart.trim().is_empty() {
+        return s.cart == a.cart;
+    }
+    s.title.eq_ignore_ascii_case(&a.title) && s.artist.eq_ignore_ascii_case(&a.artist)
+}
+
+fn aired_row(a: &HistoryEntry) -> AsRunRow {
+    AsRunRow {
+        kind: "item",
+        aired: fmt_local_hhmmss(a.started_ms),
+        ended: a.ended_ms.map(fmt_local_hhmmss).unwrap_or_default(),
+        started_ms: Some(a.started_ms),
+        ended_ms: a.ended_ms,
+        tag: a.tag.clone(),
+        cart: a.cart.clone(),
+        title: a.title.clone(),
+        artist: a.artist.clone(),
+        dur: a.dur.clone(),
+        status: a.outcome.clone().unwrap_or_else(|| "on_air".into()),
+        sort_ms: Some(a.started_ms),
+        ..Default::default()
+    }
+}
+
+fn build(
+    date: &str,
+    schedule: &[DayLogItem],
+    aired: &[HistoryEntry],
+    topup: &[TopUpHistoryEntry],
+) -> (AsRunSummary, Vec<AsRunRow>) {
+    let mut sum = AsRunSummary { scheduled: schedule.len() as u32, ..Default::default() };
+    let mut rows: Vec<AsRunRow> = Vec::new();
+    let mut matched: HashSet<usize> = HashSet::new();
+
+    for s in schedule {
+        let hit = aired.iter().enumerate().find(|(i, a)| !matched.contains(i) && same_item(s, a)).map(|(i, _)| i);
+        let row = match hit {
+            Some(i) => {
+                matched.insert(i);
+                sum.aired_as_scheduled += 1;
+                AsRunRow { scheduled: s.time.clone(), ..aired_row(&aired[i]) }
+            }
+            None => {
+                sum.missed += 1;
+                AsRunRow {
+                    kind: "item",
+                    scheduled: s.time.clone(),
+                    tag: s.tag.clone(),
+                    cart: s.cart.clone(),
+                    title: s.title.clone(),
+                    artist: s.artist.clone(),
+                    dur: s.dur.clone(),
+                    status: "missed".into(),
+                    sort_ms: scheduled_ms(date, &s.time),
+                    ..Default::default()
+                }
+            }
+        };
+        rows.push(row);
+    }
+    for (i, a) in aired.iter().enumerate() {
+        if !matched.contains(&i) {
+            sum.unscheduled += 1;
+            rows.push(aired_row(a));
+        }
+        match a.outcome.as_deref() {
+            Some("skipped") => sum.skipped += 1,
+            Some("dumped") => sum.dumped += 1,
+            Some("faded") => sum.faded += 1,
+            Some("interrupted") => sum.interrupted += 1,
+            _ => {}
+        }
+    }
+
+    // Dead air: only measurable where the previous item's end is known
+    // (an `interrupted` item has none; the engine was down).
+    for pair in aired.windows(2) {
+        let (Some(end), next) = (pair[0].ended_ms, pair[1].started_ms) else { continue };
+        if next >= end + DEAD_AIR_MS {
+            sum.dead_air_count += 1;
+            sum.dead_air_s += (next - end) / 1000;
+            rows.push(AsRunRow {
+                kind: "dead_air",
+                aired: fmt_local_hhmmss(end),
+                ended: fmt_local_hhmmss(next),
+                started_ms: Some(end),
+                ended_ms: Some(next),
+                title: "Dead air".into(),
+                dur: fmt_dur_mmss(((next - end) / 1000) as u32),
+                status: "dead_air".into(),
+                sort_ms: Some(end),
+                ..Default::default()
+            });
+        }
+    }
+
+    for t in topup {
+        sum.topup_runs += 1;
+        sum.topup_items += t.appended;
+        if t.error.is_some() {
+            sum.topup_errors += 1;
+        }
+        let rule = t.active_rule.as_deref().map(|r| format!(" ({r})")).unwrap_or_default();
+        rows.push(AsRunRow {
+            kind: "topup",
+            aired: fmt_local_hhmmss(t.at_ms),
+            started_ms: Some(t.at_ms),
+            title: format!("Top-up: {} item(s) from {}{rule}", t.appended, t.dir),
+            artist: t.trigger.clone(),
+            status: t.error.clone().unwrap_or_else(|| "appended".into()),
+            sort_ms: Some(t.at_ms),
+            ..Default::default()
+        });
+    }
+
+    // Missed items without a usable time go last, in log order (stable sort).
+    rows.sort_by_key(|r| r.sort_ms.unwrap_or(u64::MAX));
+    (sum, rows)
+}
+
+fn to_csv(rows: &[AsRunRow]) -> String {
+    let mut out = String::from("Kind,Scheduled,Aired,Ended,Tag,Cart,Title,Artist,Length,Status\n");
+    for r in rows {
+        let fields = [
+            r.kind, &r.scheduled, &r.aired, &r.ended, &r.tag, &r.cart, &r.title, &r.artist, &r.dur, &r.status,
+        ];
+        out.push_str(&fields.iter().map(|f| csv_field(f)).collect::<Vec<_>>().join(","));
+        out.push('\n');
+    }
+    out
+}
+
+// --- HTTP API -------------------------------------------------------------------
+
+/// `GET /api/v1/asrun/{date}?format=json|csv`
+pub(crate) async fn api_asrun(Path(date): Path<String>, Query(q): Query<AsRunQuery>) -> Result<Response, StatusCode> {
+    if !daylog::valid_date(&date) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let from_ms = LocalHour::local_ms(&date, 0).ok_or(StatusCode::BAD_REQUEST)?;
+    let to_ms = LocalHour::local_ms(&date, 24 * 60).ok_or(StatusCode::BAD_REQUEST)?;
+    let d = date.clone();
+    let (schedule, aired, topup) = crate::db::call(move |conn| -> anyhow::Result<_> {
+        Ok((
+            daylog::db_load_day(conn, &d)?,
+            history::db_entries_between(conn, from_ms, to_ms)?,
+            topuplog::db_notable_between(conn, from_ms, to_ms)?,
+        ))
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|e| {
+        tracing::warn!("as-run {date}: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let has_schedule = !schedule.is_empty();
+    let (summary, rows) = build(&date, &schedule, &aired, &topup);
+    match q.format.as_deref().unwrap_or("json") {
+        "json" => Ok(Json(json!({
+            "ok": true,
+            "date": date,
+            "has_schedule": has_schedule,
+            "summary": summary,
+            "rows": rows,
+        }))
+        .into_response()),
+        "csv" => Ok((
+            [
+                (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+                (header::CONTENT_DISPOSITION, format!("attachment; filename=\"asrun-{date}.csv\"")),
+            ],
+            to_csv(&rows),
+        )
+            .into_response()),
+        _ => Err(StatusCode::BAD_REQUEST),
+    }
+}
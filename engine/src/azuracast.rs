@@ -0,0 +1,259 @@
+// --- AzuraCast-compatible now playing ---------------------------------------------------
+//
+// Many now-playing widgets, mobile apps, Discord bots and studio displays are
+// written against AzuraCast's API (LibreTime stations often front theirs with
+// the same shape). Rather than asking each to learn `/api/v1/public/*`, the
+// engine answers the requests they make, in the JSON they expect:
+// - `GET /api/nowplaying`: a list with the one station,
+// - `GET /api/nowplaying/{station}` and `/api/nowplaying_static/{station}.json`:
+//   that station, by id (`1`) or shortcode (the stream name, lower-cased,
+//   e.g. `studiocommand_fm`).
+//
+// The content follows the public feeds (public.rs): only music is shown (while
+// something else airs, `now_playing` carries the station name), the next item
+// is the next music item (null when `upcoming_max` is 0), and `song_history`
+// is the last five music items.
+// `listeners` comes from the Icecast server's `status-json.xsl` for the
+// configured mount, fetched at most every 15 s; 0 when it cannot be read.
+// Fields with no counterpart here (album, lyrics, streamer, remotes, HLS)
+// are present and empty, as AzuraCast sends them for a station without them.
+//
+// Like `/api/v1/public/`, these paths are meant to be exposed without auth
+// and carry the same cache and CORS headers.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Response,
+};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::{ffmpeg, parse_dur_to_sec, public, unix_ms_now, AppState};
+
+/// Music items in `song_history`.
+const HISTORY: u32 = 5;
+const LISTENERS_EVERY: Duration = Duration::from_secs(15);
+
+/// The last listener count, and when it was read.
+static LISTENERS: Mutex<Option<(Instant, u64)>> = Mutex::new(None);
+
+/// `My Station FM` -> `my_station_fm`, as AzuraCast forms shortcodes.
+fn shortcode(name: &str) -> String {
+    let s: String =
+        name.trim().chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' }).collect();
+    match s.trim_matches('_') {
+        "" => "studiocommand".to_string(),
+        s => s.to_string(),
+    }
+}
+
+/// AzuraCast's song id: a hash of artist and title, stable across plays.
+fn song_id(artist: &str, title: &str) -> String {
+    let digest = Sha256::digest(format!("{artist} - {title}").to_lowercase().as_bytes());
+    digest.iter().take(16).map(|b| format!("{b:02x}")).collect()
+}
+
+fn song(artist: &str, title: &str, art: Option<&str>) -> Value {
+    let text = if artist.is_empty() { title.to_string() } else { format!("{artist} - {title}") };
+    json!({
+        "id": song_id(artist, title),
+        "text": text,
+        "artist": artist,
+        "title": title,
+        "album": "",
+        "genre": "",
+        "isrc": "",
+        "lyrics": "",
+        "art": art.unwrap_or(""),
+        "custom_fields": [],
+    })
+}
+
+/// Listeners on `mount` from Icecast's JSON status; `None` when it cannot be read.
+async fn icecast_listeners(host: &str, port: u16, mount: &str) -> Option<u64> {
+    let curl = ffmpeg::curl_bin();
+    let url = format!("http://{host}:{port}/status-json.xsl");
+    let out = tokio::process::Command::new(&curl)
+        .args(["-fsS", "--max-time", "3", &url])
+        .kill_on_drop(true)
+        .output()
+        .await
+        .ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    let stats: Value = serde_json::from_slice(&out.stdout).ok()?;
+    // One source is an object, several an array.
+    let sources = match stats.pointer("/icestats/source")? {
+        Value::Array(list) => list.clone(),
+        one => vec![one.clone()],
+    };
+    sources
+        .iter()
+        .find(|s| s.get("listenurl").and_then(Value::as_str).is_some_and(|u| u.ends_with(mount)))
+        .and_then(|s| s.get("listeners"))
+        .and_then(Value::as_u64)
+}
+
+/// Listeners on the stream, cached for 15 s; 0 when unknown.
+pub(crate) async fn listeners(host: &str, port: u16, mount: &str) -> u64 {
+    let cached = *LISTENERS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((at, n)) = cached {
+        if at.elapsed() < LISTENERS_EVERY {
+            return n;
+        }
+    }
+    let n = match host {
+        "" => 0,
+        _ => icecast_listeners(host, port, mount).await.unwrap_or(0),
+    };
+    *LISTENERS.lock().unwrap_or_else(|e| e.into_inner()) = Some((Instant::now(), n));
+    n
+}
+
+/// The station as AzuraCast's `NowPlaying` object.
+async fn now_playing(state: &AppState) -> Value {
+    let cfg = public::load_config().await;
+    let now_s = unix_ms_now() / 1000;
+    let (output, online) = {
+        let o = state.output.lock().await;
+        (o.config.clone(), o.status.state == "connected")
+    };
+    let name = output.name.clone().filter(|n| !n.trim().is_empty()).unwrap_or_else(|| "StudioCommand".into());
+    let listen_url = match output.host.as_str() {
+        "" => String::new(),
+        host => format!("http://{host}:{}{}", output.port, output.mount),
+    };
+    let listeners = listeners(&output.host, output.port, &output.mount).await;
+    let counts = json!({"total": listeners, "unique": listeners, "current": listeners});
+
+    let (current, next) = {
+        let p = state.playout.read().await;
+        let elapsed = p.now.pos_f as u64;
+        let current = match p.log.first().filter(|it| it.state == "playing") {
+            Some(it) if cfg.is_music(&it.tag) => {
+                let duration = p.now.dur as u64;
+                json!({
+                    "sh_id": (it.id.as_u128() >> 96) as u32,
+                    "played_at": now_s.saturating_sub(elapsed),
+                    "duration": duration,
+                    "playlist": "",
+                    "streamer": "",
+                    "is_request": false,
+                    "song": song(&p.now.artist, &p.now.title, p.now.art.as_deref()),
+                    "elapsed": elapsed.min(duration),
+                    "remaining": duration.saturating_sub(elapsed),
+                })
+            }
+            // Not music, or nothing on air: the station name, as AzuraCast shows between songs.
+            _ => json!({
+                "sh_id": 0,
+                "played_at": now_s,
+                "duration": 0,
+                "playlist": "",
+                "streamer": "",
+                "is_request": false,
+                "song": song("", &name, None),
+                "elapsed": 0,
+                "remaining": 0,
+            }),
+        };
+        let next = cfg.upcoming(&p.log).next().map(|it| {
+            json!({
+                "cued_at": now_s,
+                "played_at": it.start_ms.map(|ms| ms / 1000).unwrap_or(now_s),
+                "duration": parse_dur_to_sec(&it.dur),
+                "playlist": "",
+                "is_request": false,
+                "song": song(&it.artist, &it.title, None),
+            })
+        });
+        (current, next)
+    };
+
+    let history = crate::db::call(|conn| public::db_music_history(conn, HISTORY)).await;
+    let history: Vec<Value> = match history {
+        Ok(Ok(items)) => items
+            .into_iter()
+            .map(|(id, t)| {
+                json!({
+                    "sh_id": id,
+                    "played_at": t.started_ms.unwrap_or(0) / 1000,
+                    "duration": t.dur,
+                    "playlist": "",
+                    "streamer": "",
+                    "is_request": false,
+                    "song": song(&t.artist, &t.title, None),
+                })
+            })
+            .collect(),
+        Ok(Err(e)) => {
+            tracing::warn!("azuracast: history: {e}");
+            Vec::new()
+        }
+        Err(_) => Vec::new(),
+    };
+
+    json!({
+        "station": {
+            "id": 1,
+            "name": name,
+            "shortcode": shortcode(&name),
+            "description": output.description.clone().unwrap_or_default(),
+            "frontend": "icecast",
+            "backend": "studiocommand",
+            "listen_url": listen_url,
+            "url": "",
+            "public_player_url": "",
+            "playlist_pls_url": "",
+            "playlist_m3u_url": "",
+            "is_public": output.public.unwrap_or(false),
+            "mounts": [{
+                "id": 1,
+                "name": output.mount,
+                "url": listen_url,
+                "bitrate": output.bitrate_kbps,
+                "format": output.codec,
+                "listeners": counts,
+                "path": output.mount,
+                "is_default": true,
+            }],
+            "remotes": [],
+            "hls_enabled": false,
+            "hls_url": null,
+            "hls_listeners": 0,
+        },
+        "listeners": counts,
+        "live": {"is_live": false, "streamer_name": "", "broadcast_start": null, "art": null},
+        "now_playing": current,
+        "playing_next": next,
+        "song_history": history,
+        "is_online": online,
+        "cache": null,
+    })
+}
+
+// --- HTTP API --------------------------------------------------------------------------
+
+/// `GET /api/nowplaying`
+pub(crate) async fn api_nowplaying_all(State(state): State<AppState>) -> Response {
+    public::public_json(5, json!([now_playing(&state).await]))
+}
+
+/// `GET /api/nowplaying/{station}` (and `/api/nowplaying_static/{station}.json`)
+pub(crate) async fn api_nowplaying_station(
+    State(state): State<AppState>,
+    Path(station): Path<String>,
+) -> Result<Response, StatusCode> {
+    let station = station.strip_suffix(".json").unwrap_or(&station);
+    let np = now_playing(&state).await;
+    let shortcode = np.pointer("/station/shortcode").and_then(Value::as_str).unwrap_or_default();
+    if station != "1" && !station.eq_ignore_ascii_case(shortcode) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(public::public_json(5, np))
+}
@@ -0,0 +1,344 @@
+// --- Database backup and restore -------------------------------------------------------
+//
+// Everything the station is configured with (queue, outputs, top-up, clocks,
+// schedules, library index, history) lives in one SQLite file. Copying that
+// file while the engine runs can catch a write half-way (and misses whatever
+// is still in the WAL), so snapshots use SQLite's online backup API on the
+// database worker's own connection: the copy is consistent, and no write can
+// land in the middle of it.
+//
+// - `POST /api/v1/admin/backup` writes a snapshot into the backup directory
+//   (oldest ones beyond `STUDIOCOMMAND_BACKUP_KEEP` are removed), or returns
+//   it as a download with `?download=1` (e.g. to move to another host).
+// - `GET /api/v1/admin/backups` lists the directory;
+//   `POST /api/v1/admin/backups/upload` stores an uploaded snapshot there.
+// - `POST /api/v1/admin/restore` replaces the live database with a snapshot
+//   from the directory. It is guarded: `confirm` must repeat the file name,
+//   the snapshot must pass `integrity_check` and must not come from a newer
+//   schema, and the current database is snapshotted first (`-pre-restore`),
+//   so a restore can itself be rolled back.
+//
+// The directory and retention are environment settings rather than database
+// rows, so restoring a snapshot never changes where snapshots go.
+//
+// Only plain snapshots are rotated. The safety copies taken before a restore,
+// an import or an update (`-pre-restore`, …) stay until removed by hand, so a
+// run of nightly backups can never delete the way back.
+//
+// A restore swaps the database under a running engine, which still holds the
+// old queue and settings in memory and would write them back (the queue is
+// persisted on every advance). So the database worker is closed as part of the
+// restore and the engine exits a second later for systemd to start it again
+// on the restored data.
+
+use std::path::{Path, PathBuf};
+
+use axum::{
+    body::Bytes,
+    extract::Query,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use rusqlite::{Connection, DatabaseName, OpenFlags};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::clocks::LocalHour;
+use crate::{db, fmt_local_hhmmss, migrations, unix_ms_now, update};
+
+/// Largest snapshot accepted by the upload endpoint.
+pub(crate) const MAX_UPLOAD_BYTES: usize = 1024 * 1024 * 1024;
+
+pub(crate) fn backup_dir() -> PathBuf {
+    std::env::var("STUDIOCOMMAND_BACKUP_DIR")
+        .unwrap_or_else(|_| "/opt/studiocommand/shared/backups".to_string())
+        .into()
+}
+
+/// Snapshots kept by rotation (uploads are never rotated away).
+fn backup_keep() -> usize {
+    std::env::var("STUDIOCOMMAND_BACKUP_KEEP").ok().and_then(|v| v.parse().ok()).filter(|n| *n > 0).unwrap_or(14)
+}
+
+/// `20261017-143005-042`, local time to the millisecond: sorts by age, reads
+/// as station time, and two snapshots in the same second keep their own name.
+pub(crate) fn stamp() -> String {
+    let now = unix_ms_now();
+    let date = LocalHour::at(now).map(|h| h.date()).unwrap_or_default().replace('-', "");
+    format!("{date}-{}-{:03}", fmt_local_hhmmss(now).replace(':', ""), now % 1000)
+}
+
+/// A bare snapshot file name: no directories, `.db` only.
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.ends_with(".db")
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+// --- Snapshots -----------------------------------------------------------------------
+
+/// Snapshot the live database to `dest`, via a temporary name so a partial
+/// copy is never mistaken for a backup.
+async fn snapshot_to(dest: PathBuf) -> anyhow::Result<u64> {
+    let tmp = dest.with_extension("db.part");
+    let t = tmp.clone();
+    let res = db::call(move |conn| conn.backup(DatabaseName::Main, &t, None)).await;
+    match res {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => {
+            let _ = std::fs::remove_file(&tmp);
+            return Err(e.into());
+        }
+        Err(e) => return Err(e.into()),
+    }
+    tokio::fs::rename(&tmp, &dest).await?;
+    Ok(tokio::fs::metadata(&dest).await?.len())
+}
+
+/// `studiocommand-<stamp>.db` without a suffix.
+fn is_plain_snapshot(name: &str) -> bool {
+    name.strip_prefix("studiocommand-")
+        .and_then(|n| n.strip_suffix(".db"))
+        .is_some_and(|stamp| !stamp.is_empty() && stamp.chars().all(|c| c.is_ascii_digit() || c == '-'))
+}
+
+/// Remove the oldest plain snapshots beyond `keep`.
+fn rotate(dir: &Path, keep: usize) -> std::io::Result<Vec<String>> {
+    let mut names: Vec<String> = std::fs::read_dir(dir)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .filter(|n| is_plain_snapshot(n))
+        .collect();
+    names.sort();
+    let excess = names.len().saturating_sub(keep);
+    let removed: Vec<String> = names.into_iter().take(excess).collect();
+    for n in &removed {
+        std::fs::remove_file(dir.join(n))?;
+    }
+    Ok(removed)
+}
+
+/// Snapshot into the backup directory and rotate. Returns (file name, bytes).
+pub(crate) async fn backup_now(suffix: &str) -> anyhow::Result<(String, u64)> {
+    let dir = backup_dir();
+    tokio::fs::create_dir_all(&dir).await?;
+    let name = format!("studiocommand-{}{suffix}.db", stamp());
+    let bytes = snapshot_to(dir.join(&name)).await?;
+    let keep = backup_keep();
+    match tokio::task::spawn_blocking(move || rotate(&dir, keep)).await {
+        Ok(Ok(removed)) if !removed.is_empty() => tracing::info!("backup: rotated out {}", removed.join(", ")),
+        Ok(Err(e)) => tracing::warn!("backup: rotation failed: {e}"),
+        _ => {}
+    }
+    tracing::info!("backup: wrote {name} ({bytes} bytes)");
+    Ok((name, bytes))
+}
+
+/// Check a candidate snapshot without touching the live database: it opens,
+/// passes `integrity_check`, and its schema is one this build can run.
+fn validate(path: &Path) -> Result<u32, String> {
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX)
+        .map_err(|e| format!("cannot open: {e}"))?;
+    let check: String =
+        conn.query_row("PRAGMA integrity_check", [], |row| row.get(0)).map_err(|e| format!("not a database: {e}"))?;
+    if check != "ok" {
+        return Err(format!("integrity check failed: {check}"));
+    }
+    let version = migrations::current(&conn).map_err(|e| e.to_string())?;
+    if version > migrations::latest() {
+        return Err(format!(
+            "snapshot has schema version {version}, this build only knows up to {}",
+            migrations::latest()
+        ));
+    }
+    Ok(version)
+}
+
+// --- HTTP API -------------------------------------------------------------------
+
+#[derive(Deserialize)]
+pub(crate) struct BackupQuery {
+    download: Option<u8>,
+}
+
+/// `POST /api/v1/admin/backup[?download=1]`
+pub(crate) async fn api_backup(Query(q): Query<BackupQuery>) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
+    let fail = |e: anyhow::Error| {
+        tracing::warn!("backup failed: {e}");
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"ok": false, "error": e.to_string()})))
+    };
+
+    if q.download.unwrap_or(0) == 0 {
+        let (file, bytes) = backup_now("").await.map_err(fail)?;
+        return Ok(Json(json!({"ok": true, "file": file, "bytes": bytes})).into_response());
+    }
+
+    let name = format!("studiocommand-{}.db", stamp());
+    let tmp = std::env::temp_dir().join(format!("studiocommand-download-{}-{}.db", std::process::id(), unix_ms_now()));
+    let res = async {
+        snapshot_to(tmp.clone()).await?;
+        Ok::<_, anyhow::Error>(tokio::fs::read(&tmp).await?)
+    }
+    .await;
+    let _ = tokio::fs::remove_file(&tmp).await;
+    let data = res.map_err(fail)?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/vnd.sqlite3".to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{name}\"")),
+        ],
+        data,
+    )
+        .into_response())
+}
+
+#[derive(Serialize)]
+struct BackupFile {
+    file: String,
+    bytes: u64,
+    modified_ms: u64,
+}
+
+/// `GET /api/v1/admin/backups`, newest first.
+pub(crate) async fn api_backups_list() -> Result<Json<serde_json::Value>, StatusCode> {
+    let dir = backup_dir();
+    let files = tokio::task::spawn_blocking(move || -> std::io::Result<Vec<BackupFile>> {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(e) => e,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut out: Vec<BackupFile> = entries
+            .filter_map(|e| e.ok())
+            .filter_map(|e| {
+                let file = e.file_name().to_string_lossy().into_owned();
+                let meta = e.metadata().ok().filter(|m| m.is_file())?;
+                let modified_ms = meta
+                    .modified()
+                    .ok()
+                    .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                    .map(|d| d.as_millis() as u64)
+                    .unwrap_or(0);
+                valid_name(&file).then_some(BackupFile { file, bytes: meta.len(), modified_ms })
+            })
+            .collect();
+        out.sort_by(|a, b| b.modified_ms.cmp(&a.modified_ms).then_with(|| b.file.cmp(&a.file)));
+        Ok(out)
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|e| {
+        tracing::warn!("backup: cannot list {}: {e}", backup_dir().display());
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(json!({"ok": true, "dir": backup_dir(), "keep": backup_keep(), "files": files})))
+}
+
+/// `POST /api/v1/admin/backups/upload` (body: a snapshot, e.g. from
+/// `backup?download=1` on another host). Stored as `upload-<stamp>.db` after
+/// validation; restoring it is a separate, confirmed step.
+pub(crate) async fn api_backup_upload(body: Bytes) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    let err = |code: StatusCode, msg: String| (code, Json(json!({"ok": false, "error": msg})));
+    if body.is_empty() {
+        return Err(err(StatusCode::BAD_REQUEST, "empty upload".into()));
+    }
+    let dir = backup_dir();
+    let name = format!("upload-{}.db", stamp());
+    let dest = dir.join(&name);
+    let res = tokio::task::spawn_blocking(move || -> Result<u32, (StatusCode, String)> {
+        let io = |e: std::io::Error| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+        std::fs::create_dir_all(&dir).map_err(io)?;
+        let tmp = dest.with_extension("db.part");
+        std::fs::write(&tmp, &body).map_err(io)?;
+        match validate(&tmp) {
+            Ok(v) => {
+                std::fs::rename(&tmp, &dest).map_err(io)?;
+                Ok(v)
+            }
+            Err(e) => {
+                let _ = std::fs::remove_file(&tmp);
+                Err((StatusCode::UNPROCESSABLE_ENTITY, e))
+            }
+        }
+    })
+    .await
+    .map_err(|_| err(StatusCode::INTERNAL_SERVER_ERROR, "upload task failed".into()))?;
+    match res {
+        Ok(version) => Ok(Json(json!({"ok": true, "file": name, "schema_version": version}))),
+        Err((code, msg)) => Err(err(code, msg)),
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct RestoreRequest {
+    file: String,
+    /// Must repeat `file`, so a stray or replayed request cannot restore.
+    confirm: String,
+}
+
+/// `POST /api/v1/admin/restore` {"file": "...", "confirm": "<same name>"}
+pub(crate) async fn api_restore(
+    Json(req): Json<RestoreRequest>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    let err = |code: StatusCode, msg: String| (code, Json(json!({"ok": false, "error": msg})));
+    if !valid_name(&req.file) {
+        return Err(err(StatusCode::BAD_REQUEST, "invalid file name".into()));
+    }
+    if req.confirm != req.file {
+        return Err(err(StatusCode::BAD_REQUEST, "confirm must repeat the file name".into()));
+    }
+    let src = backup_dir().join(&req.file);
+    if !src.is_file() {
+        return Err(err(StatusCode::NOT_FOUND, format!("no backup named {}", req.file)));
+    }
+
+    let s = src.clone();
+    let version = tokio::task::spawn_blocking(move || validate(&s))
+        .await
+        .map_err(|_| err(StatusCode::INTERNAL_SERVER_ERROR, "validation task failed".into()))?
+        .map_err(|e| err(StatusCode::UNPROCESSABLE_ENTITY, e))?;
+
+    // The way back, should this snapshot turn out to be the wrong one.
+    let (safety, _) = backup_now("-pre-restore").await.map_err(|e| {
+        err(StatusCode::INTERNAL_SERVER_ERROR, format!("could not snapshot the current database first: {e}"))
+    })?;
+
+    // Outer error: the restore failed and the database is as it was. Inner:
+    // the restored snapshot could not be migrated.
+    let res = db::call(move |conn| -> rusqlite::Result<rusqlite::Result<()>> {
+        conn.restore(DatabaseName::Main, &src, None::<fn(rusqlite::backup::Progress)>)?;
+        // Closed before anything else can fail, so no path leaves it open.
+        db::close();
+        // An older snapshot is brought up to this build's schema right away.
+        Ok(migrations::migrate(conn))
+    })
+    .await;
+    match res {
+        Ok(Ok(Ok(()))) => {
+            tracing::warn!("database restored from {} (previous state saved as {safety}); restarting", req.file);
+            update::exit_for_restart("backup: exiting to restart on the restored database");
+            Ok(Json(json!({
+                "ok": true,
+                "restored": req.file,
+                "schema_version": version,
+                "pre_restore_backup": safety,
+                "restarting": true,
+            })))
+        }
+        Ok(Ok(Err(e))) => {
+            // Still closed: the restart retries the migration on the restored file.
+            tracing::error!("restored {} but could not migrate it: {e}; restarting", req.file);
+            update::exit_for_restart("backup: exiting to restart after a failed migration");
+            let error =
+                format!("migration failed after restoring {}: {e} (previous state saved as {safety})", req.file);
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"ok": false, "error": error, "pre_restore_backup": safety, "restarting": true})),
+            ))
+        }
+        Ok(Err(e)) => Err(err(StatusCode::INTERNAL_SERVER_ERROR, format!("restore failed: {e}"))),
+        Err(e) => Err(err(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
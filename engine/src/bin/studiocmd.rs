@@ -0,0 +1,330 @@
+// --- studiocmd: command-line client ---------------------------------------------------------
+//
+// Everything the engine does is reachable over its HTTP API, but scripting
+// it meant hand-written curl calls and JSON bodies in cron jobs. `studiocmd`
+// wraps the common operations:
+//
+//     studiocmd status
+//     studiocmd skip
+//     studiocmd queue [list]
+//     studiocmd queue insert <cart|path> [--next] [--after N] [--tag TAG]
+//     studiocmd queue move <from> <to>
+//     studiocmd queue reorder <id>...
+//     studiocmd output start|stop|status
+//     studiocmd topup run
+//     studiocmd library search <text> [--limit N]
+//
+// The engine is found at `--url`, else `STUDIOCOMMAND_URL`, else
+// `http://127.0.0.1:3000`; `unix:/run/studiocommand.sock` talks to a Unix
+// socket listener (see serve.rs). `--json` prints the engine's response as
+// is, for `jq`. Like the engine's own outbound requests, calls go through
+// `curl` (`STUDIOCOMMAND_CURL`).
+//
+// Exit status: 0 on success, 1 when the engine refused or could not be
+// reached, 2 on a usage error.
+
+use std::process::{Command, ExitCode};
+
+use serde_json::{json, Value};
+
+const USAGE: &str = "\
+usage: studiocmd [--url URL] [--json] <command>
+
+commands:
+  status                                   now playing, output and queue length
+  skip                                     advance to the next item
+  queue [list]                             the queue, playing item first
+  queue insert <cart|path> [--next] [--after N] [--tag TAG]
+                                           add an item (default: at the end)
+  queue move <from> <to>                   move an upcoming item by position
+  queue reorder <id>...                    set the order of all upcoming items
+  output start|stop|status                 the Icecast stream
+  topup run                                top the queue up now
+  library search <text> [--limit N]        find tracks in the media library
+
+environment:
+  STUDIOCOMMAND_URL    engine address (default http://127.0.0.1:3000, or unix:/path)
+  STUDIOCOMMAND_CURL   curl binary (default curl)";
+
+/// Longest a single request may take.
+const TIMEOUT_S: u32 = 30;
+
+struct Client {
+    /// Base URL requests are made against.
+    base: String,
+    /// Unix socket to connect through, if any.
+    socket: Option<String>,
+}
+
+enum Error {
+    Usage(String),
+    Api(String),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+fn usage(msg: impl Into<String>) -> Error {
+    Error::Usage(msg.into())
+}
+
+impl Client {
+    fn new(url: &str) -> Client {
+        match url.strip_prefix("unix:") {
+            Some(path) => Client { base: "http://localhost".into(), socket: Some(path.into()) },
+            None => Client { base: url.trim_end_matches('/').into(), socket: None },
+        }
+    }
+
+    fn call(&self, method: &str, path: &str, body: Option<Value>) -> Result<Value> {
+        let curl = std::env::var("STUDIOCOMMAND_CURL").unwrap_or_else(|_| "curl".to_string());
+        let mut cmd = Command::new(&curl);
+        cmd.args(["-sS", "--max-time", &TIMEOUT_S.to_string(), "-X", method, "-w", "\n%{http_code}"]);
+        if let Some(socket) = &self.socket {
+            cmd.args(["--unix-socket", socket]);
+        }
+        if let Some(body) = body {
+            cmd.args(["-H", "Content-Type: application/json", "--data-binary", &body.to_string()]);
+        }
+        cmd.arg(format!("{}{path}", self.base));
+        let out = cmd.output().map_err(|e| Error::Api(format!("{curl}: {e}")))?;
+        if !out.status.success() {
+            return Err(Error::Api(String::from_utf8_lossy(&out.stderr).trim().to_string()));
+        }
+        // The status code is on the last line (`-w`), the body before it.
+        let text = String::from_utf8_lossy(&out.stdout);
+        let (body, code) = text.rsplit_once('\n').unwrap_or(("", &text));
+        let code: u16 = code.trim().parse().unwrap_or(0);
+        let value = serde_json::from_str(body).unwrap_or_else(|_| Value::String(body.trim().to_string()));
+        if !(200..300).contains(&code) {
+            let detail = match (&value, value.get("error").and_then(|e| e.as_str())) {
+                (_, Some(e)) => e.to_string(),
+                (Value::String(s), None) => s.clone(),
+                _ => String::new(),
+            };
+            return Err(Error::Api(format!("{method} {path}: HTTP {code} {detail}").trim_end().to_string()));
+        }
+        Ok(value)
+    }
+
+    fn get(&self, path: &str) -> Result<Value> {
+        self.call("GET", path, None)
+    }
+
+    fn post(&self, path: &str, body: Value) -> Result<Value> {
+        self.call("POST", path, Some(body))
+    }
+}
+
+/// Percent-encode a query string value.
+fn encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+fn str_of<'a>(v: &'a Value, key: &str) -> &'a str {
+    v.get(key).and_then(|s| s.as_str()).unwrap_or("")
+}
+
+fn mmss(s: u64) -> String {
+    format!("{}:{:02}", s / 60, s % 60)
+}
+
+/// "Artist - Title", or the title alone.
+fn name(v: &Value) -> String {
+    match (str_of(v, "artist"), str_of(v, "title")) {
+        ("", title) => title.to_string(),
+        (artist, title) => format!("{artist} - {title}"),
+    }
+}
+
+/// Pull `--flag VALUE` out of `args`.
+fn take_opt(args: &mut Vec<String>, flag: &str) -> Result<Option<String>> {
+    let Some(i) = args.iter().position(|a| a == flag) else { return Ok(None) };
+    if i + 1 >= args.len() {
+        return Err(usage(format!("{flag} needs a value")));
+    }
+    let v = args.remove(i + 1);
+    args.remove(i);
+    Ok(Some(v))
+}
+
+/// Pull `--flag` out of `args`.
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    let before = args.len();
+    args.retain(|a| a != flag);
+    args.len() != before
+}
+
+fn number<T: std::str::FromStr>(s: &str, what: &str) -> Result<T> {
+    s.parse().map_err(|_| usage(format!("{what} must be a number, not {s:?}")))
+}
+
+fn print_queue(status: &Value) {
+    let log = status.get("log").and_then(|l| l.as_array()).cloned().unwrap_or_default();
+    if log.is_empty() {
+        println!("(queue is empty)");
+    }
+    for (i, item) in log.iter().enumerate() {
+        let lock = if item.get("locked").and_then(|l| l.as_bool()).unwrap_or(false) { "L" } else { " " };
+        println!(
+            "{i:>3} {lock} {:<8} {:<4} {:>6}  {}  {}",
+            str_of(item, "time"),
+            str_of(item, "tag"),
+            str_of(item, "dur"),
+            name(item),
+            str_of(item, "id"),
+        );
+    }
+}
+
+fn run(client: &Client, json_out: bool, mut args: Vec<String>) -> Result<()> {
+    if args.is_empty() {
+        return Err(usage("no command"));
+    }
+    let cmd = args.remove(0);
+    let sub = (!args.is_empty()).then(|| args.remove(0));
+    let out = match (cmd.as_str(), sub.as_deref()) {
+        ("status", None) => {
+            let status = client.get("/api/v1/status")?;
+            let output = client.get("/api/v1/output")?;
+            if json_out {
+                json!({"status": status, "output": output})
+            } else {
+                let now = &status["now"];
+                let pos = now.get("pos").and_then(|p| p.as_u64()).unwrap_or(0);
+                let dur = now.get("dur").and_then(|d| d.as_u64()).unwrap_or(0);
+                let playing = name(now);
+                if playing.is_empty() {
+                    println!("On air:  (nothing)");
+                } else {
+                    println!("On air:  {playing}  {} / {}", mmss(pos), mmss(dur));
+                }
+                println!("Output:  {}", output.pointer("/status/state").and_then(|s| s.as_str()).unwrap_or("unknown"));
+                let len = status.get("log").and_then(|l| l.as_array()).map(Vec::len).unwrap_or(0);
+                println!("Queue:   {len} items (rev {})", status["queue_rev"]);
+                println!("Engine:  {}", str_of(&status, "version"));
+                return Ok(());
+            }
+        }
+        ("skip", None) => client.post("/api/v1/transport/skip", json!({}))?,
+        ("queue", None | Some("list")) => {
+            let status = client.get("/api/v1/status")?;
+            if !json_out {
+                print_queue(&status);
+                return Ok(());
+            }
+            status["log"].clone()
+        }
+        ("queue", Some("insert")) => {
+            let tag = take_opt(&mut args, "--tag")?;
+            let after = take_opt(&mut args, "--after")?;
+            let next = take_flag(&mut args, "--next");
+            let [reference] = args.as_slice() else { return Err(usage("queue insert takes one cart or path")) };
+            let after: u64 = match (after, next) {
+                (Some(_), true) => return Err(usage("--next and --after are exclusive")),
+                (Some(n), false) => number(&n, "--after")?,
+                (None, true) => 0,
+                // Past the end: the engine appends.
+                (None, false) => u32::MAX as u64,
+            };
+            client.post("/api/v1/queue/insert", json!({"after": after, "ref": reference, "tag": tag}))?
+        }
+        ("queue", Some("move")) => {
+            let [from, to] = args.as_slice() else { return Err(usage("queue move takes <from> <to>")) };
+            let (from, to): (u64, u64) = (number(from, "from")?, number(to, "to")?);
+            client.post("/api/v1/queue/move", json!({"from": from, "to": to}))?
+        }
+        ("queue", Some("reorder")) => {
+            if args.is_empty() {
+                return Err(usage("queue reorder takes the ids of all upcoming items, in the new order"));
+            }
+            client.post("/api/v1/queue/reorder", json!({"order": args}))?
+        }
+        ("output", Some(action @ ("start" | "stop"))) => client.post(&format!("/api/v1/output/{action}"), json!({}))?,
+        ("output", Some("status") | None) => {
+            let output = client.get("/api/v1/output")?;
+            if !json_out {
+                let status = &output["status"];
+                println!("State:   {}", str_of(status, "state"));
+                if let Some(e) = status.get("last_error").and_then(|e| e.as_str()) {
+                    println!("Error:   {e}");
+                }
+                let cfg = &output["config"];
+                println!("Target:  {}:{}{}", str_of(cfg, "host"), cfg["port"], str_of(cfg, "mount"));
+                return Ok(());
+            }
+            output
+        }
+        ("topup", Some("run")) => {
+            let res = client.post("/api/v1/playout/topup/run", json!({}))?;
+            if !json_out {
+                println!("appended {}", res.get("appended").cloned().unwrap_or(Value::from(0)));
+                if let Some(e) = res.get("error").and_then(|e| e.as_str()) {
+                    println!("error: {e}");
+                }
+                return Ok(());
+            }
+            res
+        }
+        ("library", Some("search")) => {
+            let limit = take_opt(&mut args, "--limit")?.map(|n| number::<u32>(&n, "--limit")).transpose()?;
+            if args.is_empty() {
+                return Err(usage("library search needs some text"));
+            }
+            let path =
+                format!("/api/v1/library/search?q={}&per_page={}", encode(&args.join(" ")), limit.unwrap_or(25));
+            let res = client.get(&path)?;
+            if !json_out {
+                for t in res.get("items").and_then(|i| i.as_array()).into_iter().flatten() {
+                    let dur = t.get("duration_s").and_then(|d| d.as_u64()).unwrap_or(0);
+                    let (tag, path) = (str_of(t, "tag"), str_of(t, "path"));
+                    println!("{:>6}  {tag:<4} {:>6}  {}  {path}", t["id"], mmss(dur), name(t));
+                }
+                println!("({} of {} matches)", res["items"].as_array().map(Vec::len).unwrap_or(0), res["total"]);
+                return Ok(());
+            }
+            res
+        }
+        _ => return Err(usage(format!("unknown command: {cmd} {}", sub.unwrap_or_default()).trim_end().to_string())),
+    };
+    if json_out {
+        println!("{}", serde_json::to_string_pretty(&out).unwrap_or_default());
+    } else {
+        println!("ok");
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|a| a == "-h" || a == "--help") {
+        println!("{USAGE}");
+        return ExitCode::SUCCESS;
+    }
+    let url = match take_opt(&mut args, "--url") {
+        Ok(url) => url,
+        Err(Error::Usage(e) | Error::Api(e)) => {
+            eprintln!("studiocmd: {e}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+    let url = url
+        .or_else(|| std::env::var("STUDIOCOMMAND_URL").ok())
+        .unwrap_or_else(|| "http://127.0.0.1:3000".to_string());
+    let json_out = take_flag(&mut args, "--json");
+    match run(&Client::new(&url), json_out, args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(Error::Usage(e)) => {
+            eprintln!("studiocmd: {e}\n\n{USAGE}");
+            ExitCode::from(2)
+        }
+        Err(Error::Api(e)) => {
+            eprintln!("studiocmd: {e}");
+            ExitCode::from(1)
+        }
+    }
+}
@@ -0,0 +1,584 @@
+// --- Chat bots ---------------------------------------------------------------------------
+//
+// A station run by one person is mostly watched from a phone. Alerts
+// (alerts.rs) tell the operator something is wrong; the chat bots let them
+// look and act without opening the web UI over a mobile connection. The
+// same commands work on both:
+// - `/nowplaying`: stream state, the playing item and what comes next,
+// - `/queue`: the next ten items with their estimated air times,
+// - `/skip`: skip the playing item,
+// - `/topup`: top up the queue now (as Top Up Now),
+// - `/restart`: stop and start the stream output.
+//
+// Only the users listed in `allowed_users` may use them; anyone else is told
+// their user id, which is what the operator needs to add them.
+//
+// - Telegram: the engine polls the bot API (`getUpdates`, long polling), so
+//   nothing has to be reachable from the internet. The bot answers in the
+//   chat the command came from.
+// - Discord: slash commands are delivered as HTTP interactions to
+//   `POST /api/v1/public/bots/discord`, which must be set as the
+//   application's Interactions Endpoint URL. It lives under
+//   `/api/v1/public/` so the nginx rule for listener requests exposes it;
+//   every request is checked against the application's public key
+//   (Ed25519), as Discord requires, and refused if its signed timestamp is
+//   more than `DISCORD_MAX_SKEW_S` off, so a captured `/skip` or `/restart`
+//   cannot be replayed later. Saving the configuration registers the
+//   commands with the application. Replies are only visible to the user.
+//
+// Like the alert channels, requests to the bot APIs go through `curl`. Bot
+// tokens are stored sealed (secrets.rs) and never returned.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::time::Duration;
+
+use crate::{
+    estimate_start_times, ffmpeg, fmt_dur_mmss, fmt_local_hhmmss, schedule, secrets, topup, unix_ms_now, AppState,
+};
+
+/// Telegram long-poll timeout; the request itself may take a little longer.
+const POLL_S: u64 = 30;
+/// Wait after a failed poll.
+const RETRY: Duration = Duration::from_secs(10);
+/// Items listed by `/queue`.
+const QUEUE_ITEMS: usize = 10;
+const DISCORD_API: &str = "https://discord.com/api/v10";
+/// How far an interaction's signed timestamp may be from our clock.
+const DISCORD_MAX_SKEW_S: u64 = 300;
+
+/// The commands, with the description Telegram and Discord show for them.
+const COMMANDS: &[(&str, &str)] = &[
+    ("nowplaying", "What is on air"),
+    ("queue", "The next items in the queue"),
+    ("skip", "Skip the playing item"),
+    ("topup", "Top up the queue now"),
+    ("restart", "Restart the stream output"),
+];
+
+#[derive(Clone, Serialize, Deserialize, Default)]
+pub(crate) struct TelegramBot {
+    #[serde(default)]
+    enabled: bool,
+    /// From @BotFather. Never returned; empty on save keeps the current one.
+    #[serde(default)]
+    bot_token: String,
+    /// Telegram user ids.
+    #[serde(default)]
+    allowed_users: Vec<i64>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Default)]
+pub(crate) struct DiscordBot {
+    #[serde(default)]
+    enabled: bool,
+    #[serde(default)]
+    application_id: String,
+    /// The application's public key (hex), for checking interactions.
+    #[serde(default)]
+    public_key: String,
+    /// Used to register the commands. Never returned; empty on save keeps the current one.
+    #[serde(default)]
+    bot_token: String,
+    /// Discord user ids (snowflakes).
+    #[serde(default)]
+    allowed_users: Vec<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Default)]
+pub(crate) struct BotConfig {
+    #[serde(default)]
+    telegram: TelegramBot,
+    #[serde(default)]
+    discord: DiscordBot,
+}
+
+/// Activity of one bot since the engine started.
+#[derive(Clone, Serialize, Default)]
+pub(crate) struct BotStatus {
+    commands: u64,
+    /// Commands from users not in `allowed_users`.
+    refused: u64,
+    last_command: Option<String>,
+    last_command_ms: Option<u64>,
+    last_error: Option<String>,
+}
+
+/// Bumped when the configuration is saved, so polling restarts with it.
+static CONFIG_GEN: AtomicU64 = AtomicU64::new(0);
+
+/// Per bot ("telegram", "discord").
+fn status() -> &'static Mutex<HashMap<&'static str, BotStatus>> {
+    static STATUS: OnceLock<Mutex<HashMap<&'static str, BotStatus>>> = OnceLock::new();
+    STATUS.get_or_init(Default::default)
+}
+
+fn with_status<T>(bot: &'static str, f: impl FnOnce(&mut BotStatus) -> T) -> T {
+    f(status().lock().unwrap_or_else(|e| e.into_inner()).entry(bot).or_default())
+}
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS bot_config (
+            id      INTEGER PRIMARY KEY CHECK (id = 1),
+            config  TEXT NOT NULL
+        );
+        "#,
+    )
+}
+
+async fn with_db<T: Send + 'static>(
+    f: impl FnOnce(&Connection) -> anyhow::Result<T> + Send + 'static,
+) -> anyhow::Result<T> {
+    crate::db::call(move |conn| {
+        crate::db_init(conn)?;
+        f(conn)
+    })
+    .await?
+}
+
+/// The stored configuration, tokens opened.
+async fn load_config() -> anyhow::Result<BotConfig> {
+    with_db(|conn| {
+        let raw: Option<String> =
+            conn.query_row("SELECT config FROM bot_config WHERE id = 1", [], |row| row.get(0)).optional()?;
+        let mut cfg: BotConfig = match raw {
+            Some(r) => serde_json::from_str(&r)?,
+            None => BotConfig::default(),
+        };
+        cfg.telegram.bot_token = secrets::open(&cfg.telegram.bot_token)?;
+        cfg.discord.bot_token = secrets::open(&cfg.discord.bot_token)?;
+        Ok(cfg)
+    })
+    .await
+}
+
+/// The configuration as the API shows it: `has_bot_token` instead of the tokens.
+fn config_view(cfg: &BotConfig) -> Value {
+    let mut v = serde_json::to_value(cfg).unwrap_or_default();
+    for (bot, token) in [("telegram", &cfg.telegram.bot_token), ("discord", &cfg.discord.bot_token)] {
+        if let Some(o) = v.get_mut(bot).and_then(|o| o.as_object_mut()) {
+            o.remove("bot_token");
+            o.insert("has_bot_token".into(), (!token.is_empty()).into());
+        }
+    }
+    v
+}
+
+// --- Commands --------------------------------------------------------------------------
+
+/// `Artist - Title (3:45)`
+fn describe(artist: &str, title: &str, dur: &str) -> String {
+    match artist {
+        "" => format!("{title} ({dur})"),
+        _ => format!("{artist} - {title} ({dur})"),
+    }
+}
+
+async fn now_playing(state: &AppState) -> String {
+    let stream = state.output.lock().await.status.state.clone();
+    let p = state.playout.read().await;
+    let mut out = format!("Stream: {stream}\n");
+    match p.log.first().filter(|it| it.state == "playing") {
+        Some(it) => {
+            let title = match p.now.artist.as_str() {
+                "" => p.now.title.clone(),
+                artist => format!("{artist} - {}", p.now.title),
+            };
+            out.push_str(&format!("Now: {title} [{}] {} / {}", it.tag, fmt_dur_mmss(p.now.pos_f as u32), it.dur));
+        }
+        None => out.push_str("Now: nothing is playing"),
+    }
+    if let Some(next) = p.log.get(1) {
+        out.push_str(&format!("\nNext: {}", describe(&next.artist, &next.title, &next.dur)));
+    }
+    out
+}
+
+async fn queue(state: &AppState) -> String {
+    let (mut log, now) = {
+        let p = state.playout.read().await;
+        (p.log.iter().take(QUEUE_ITEMS + 1).cloned().collect::<Vec<_>>(), p.now.clone())
+    };
+    estimate_start_times(&mut log, &now, unix_ms_now());
+    let lines: Vec<String> = log
+        .iter()
+        .skip(1)
+        .map(|it| {
+            let at = it.start_ms.map(fmt_local_hhmmss).unwrap_or_default();
+            format!("{at} {}", describe(&it.artist, &it.title, &it.dur))
+        })
+        .collect();
+    if lines.is_empty() {
+        return "Nothing is queued after the playing item.".into();
+    }
+    lines.join("\n")
+}
+
+/// Run a command (without the slash); the reply text.
+async fn execute(state: &AppState, command: &str) -> Result<String, String> {
+    match command {
+        "nowplaying" | "np" => Ok(now_playing(state).await),
+        "queue" => Ok(queue(state).await),
+        "skip" => {
+            schedule::run_action(state, &schedule::Action::Skip).await?;
+            Ok(format!("Skipped.\n{}", now_playing(state).await))
+        }
+        "topup" => {
+            let Json(res) = topup::api_topup_run(State(state.clone())).await;
+            let appended = res.get("appended").and_then(Value::as_u64).unwrap_or(0);
+            match res.get("error").and_then(Value::as_str) {
+                Some(e) if appended == 0 => Err(format!("top-up failed: {e}")),
+                _ => Ok(format!("Top-up added {appended} item(s).")),
+            }
+        }
+        "restart" => {
+            schedule::run_action(state, &schedule::Action::OutputStop).await?;
+            if let Err(e) = schedule::run_action(state, &schedule::Action::OutputStart).await {
+                let detail = state.output.lock().await.status.last_error.clone();
+                return Err(detail.map_or(e.clone(), |d| format!("{e}: {d}")));
+            }
+            Ok("Stream output restarted.".into())
+        }
+        "start" | "help" => Ok(COMMANDS.iter().map(|(c, d)| format!("/{c} - {d}")).collect::<Vec<_>>().join("\n")),
+        _ => Err(format!("unknown command /{command}; try /help")),
+    }
+}
+
+/// Check the user, run the command and record it; the reply text.
+async fn handle(state: &AppState, bot: &'static str, user: &str, allowed: bool, command: &str) -> String {
+    if !allowed {
+        with_status(bot, |s| s.refused += 1);
+        tracing::warn!("{bot} bot: /{command} from user {user} refused");
+        return format!("Not authorized. Your user id is {user}; ask the operator to add it.");
+    }
+    tracing::info!("{bot} bot: /{command} from user {user}");
+    let res = execute(state, command).await;
+    with_status(bot, |s| {
+        s.commands += 1;
+        s.last_command = Some(match &res {
+            Ok(_) => format!("/{command} ({user})"),
+            Err(e) => format!("/{command} ({user}): {e}"),
+        });
+        s.last_command_ms = Some(unix_ms_now());
+    });
+    res.unwrap_or_else(|e| format!("Failed: {e}"))
+}
+
+// --- Telegram --------------------------------------------------------------------------
+
+/// Call a Telegram bot API method; its `result`.
+async fn telegram_call(token: &str, method: &str, body: &Value, timeout_s: u64) -> Result<Value, String> {
+    let curl = ffmpeg::curl_bin();
+    let url = format!("https://api.telegram.org/bot{token}/{method}");
+    let out = tokio::process::Command::new(&curl)
+        .args(["-sS", "--max-time", &timeout_s.to_string(), "-H", "Content-Type: application/json"])
+        .args(["--data-binary", &body.to_string(), &url])
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|e| format!("{curl}: {e}"))?;
+    if !out.status.success() {
+        let stderr = String::from_utf8_lossy(&out.stderr).trim().replace(token, "****");
+        return Err(if stderr.is_empty() { format!("{method} failed") } else { stderr });
+    }
+    let reply: Value = serde_json::from_slice(&out.stdout).map_err(|e| format!("{method}: {e}"))?;
+    if reply.get("ok").and_then(Value::as_bool) != Some(true) {
+        let desc = reply.get("description").and_then(Value::as_str).unwrap_or("request failed");
+        return Err(format!("{method}: {desc}"));
+    }
+    Ok(reply.get("result").cloned().unwrap_or(Value::Null))
+}
+
+/// Poll Telegram for commands while the bot is enabled.
+pub(crate) async fn telegram_task(state: AppState) {
+    let mut offset: i64 = 0;
+    let mut offset_token = String::new();
+    let mut menu_set_for = None;
+    loop {
+        let generation = CONFIG_GEN.load(Ordering::Relaxed);
+        let bot = match load_config().await {
+            Ok(cfg) if cfg.telegram.enabled && !cfg.telegram.bot_token.is_empty() => cfg.telegram,
+            _ => {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                continue;
+            }
+        };
+        // Update ids are per bot: a new configuration with another token
+        // starts from 0. (The same token keeps its place, or the last
+        // commands would run again.)
+        if menu_set_for != Some(generation) && offset_token != bot.bot_token {
+            offset = 0;
+            offset_token = bot.bot_token.clone();
+        }
+        // The command menu, once per configuration.
+        if menu_set_for != Some(generation) {
+            let commands: Vec<Value> =
+                COMMANDS.iter().map(|(c, d)| json!({"command": c, "description": d})).collect();
+            match telegram_call(&bot.bot_token, "setMyCommands", &json!({"commands": commands}), 20).await {
+                Ok(_) => menu_set_for = Some(generation),
+                Err(e) => tracing::warn!("telegram bot: {e}"),
+            }
+        }
+        let body = json!({"offset": offset, "timeout": POLL_S, "allowed_updates": ["message"]});
+        let updates = match telegram_call(&bot.bot_token, "getUpdates", &body, POLL_S + 10).await {
+            Ok(u) => {
+                with_status("telegram", |s| s.last_error = None);
+                u
+            }
+            Err(e) => {
+                tracing::warn!("telegram bot: {e}");
+                with_status("telegram", |s| s.last_error = Some(e));
+                tokio::time::sleep(RETRY).await;
+                continue;
+            }
+        };
+        if CONFIG_GEN.load(Ordering::Relaxed) != generation {
+            continue;
+        }
+        for update in updates.as_array().into_iter().flatten() {
+            offset = offset.max(update.get("update_id").and_then(Value::as_i64).unwrap_or(0) + 1);
+            let Some(msg) = update.get("message") else { continue };
+            let text = msg.get("text").and_then(Value::as_str).unwrap_or_default();
+            let Some(command) = text.strip_prefix('/') else { continue };
+            // `/skip@StationBot extra words` -> `skip`
+            let command = command.split_whitespace().next().unwrap_or_default();
+            let command = command.split('@').next().unwrap_or_default().to_lowercase();
+            let user = msg.pointer("/from/id").and_then(Value::as_i64).unwrap_or(0);
+            let chat = msg.pointer("/chat/id").cloned().unwrap_or(Value::Null);
+            let allowed = bot.allowed_users.contains(&user);
+            let reply = handle(&state, "telegram", &user.to_string(), allowed, &command).await;
+            let body = json!({
+                "chat_id": chat,
+                "text": reply,
+                "reply_parameters": {"message_id": msg.get("message_id")},
+            });
+            if let Err(e) = telegram_call(&bot.bot_token, "sendMessage", &body, 20).await {
+                tracing::warn!("telegram bot: {e}");
+                with_status("telegram", |s| s.last_error = Some(e));
+            }
+        }
+    }
+}
+
+// --- Discord ---------------------------------------------------------------------------
+
+/// Register the slash commands with the application (replacing its global commands).
+async fn discord_register(bot: &DiscordBot) -> Result<(), String> {
+    let curl = ffmpeg::curl_bin();
+    let commands: Vec<Value> =
+        COMMANDS.iter().map(|(c, d)| json!({"name": c, "description": d, "type": 1})).collect();
+    let url = format!("{DISCORD_API}/applications/{}/commands", bot.application_id);
+    let out = tokio::process::Command::new(&curl)
+        .args(["-sS", "--fail-with-body", "--max-time", "20", "-X", "PUT", "-o", "-"])
+        .args(["-H", "Content-Type: application/json", "-H", &format!("Authorization: Bot {}", bot.bot_token)])
+        .args(["--data-binary", &Value::from(commands).to_string(), &url])
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|e| format!("{curl}: {e}"))?;
+    if out.status.success() {
+        return Ok(());
+    }
+    let body = String::from_utf8_lossy(&out.stdout);
+    let message = serde_json::from_str::<Value>(&body)
+        .ok()
+        .and_then(|v| v.get("message").and_then(Value::as_str).map(str::to_string));
+    Err(match message {
+        Some(m) => format!("registering commands: {m}"),
+        None => format!("registering commands: {}", String::from_utf8_lossy(&out.stderr).trim()),
+    })
+}
+
+fn hex_decode(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok()).collect()
+}
+
+/// Whether Discord signed `timestamp + body` with the application's key, and
+/// the timestamp is recent.
+fn discord_signed(public_key: &str, headers: &HeaderMap, body: &[u8]) -> bool {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).unwrap_or_default();
+    let timestamp = header("x-signature-timestamp");
+    let fresh = timestamp.parse::<u64>().is_ok_and(|t| t.abs_diff(unix_ms_now() / 1000) <= DISCORD_MAX_SKEW_S);
+    if !fresh {
+        return false;
+    }
+    let (Some(key), Some(sig)) = (hex_decode(public_key.trim()), hex_decode(header("x-signature-ed25519"))) else {
+        return false;
+    };
+    let mut message = timestamp.as_bytes().to_vec();
+    message.extend_from_slice(body);
+    ring::signature::UnparsedPublicKey::new(&ring::signature::ED25519, key).verify(&message, &sig).is_ok()
+}
+
+/// `POST /api/v1/public/bots/discord`: Discord's interactions.
+pub(crate) async fn api_discord_interaction(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<Value>, StatusCode> {
+    let bot = load_config().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?.discord;
+    if !bot.enabled {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    if !discord_signed(&bot.public_key, &headers, &body) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    let interaction: Value = serde_json::from_slice(&body).map_err(|_| StatusCode::BAD_REQUEST)?;
+    match interaction.get("type").and_then(Value::as_u64) {
+        // PING, when the endpoint URL is saved in the developer portal.
+        Some(1) => return Ok(Json(json!({"type": 1}))),
+        Some(2) => {}
+        _ => return Err(StatusCode::BAD_REQUEST),
+    }
+    let command = interaction.pointer("/data/name").and_then(Value::as_str).unwrap_or_default();
+    // In a server the user is under `member`, in a DM at the top level.
+    let user = interaction
+        .pointer("/member/user/id")
+        .or_else(|| interaction.pointer("/user/id"))
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    let allowed = bot.allowed_users.iter().any(|u| u == user);
+    let reply = handle(&state, "discord", user, allowed, command).await;
+    // 4: CHANNEL_MESSAGE_WITH_SOURCE; flag 64: only the user sees it.
+    Ok(Json(json!({"type": 4, "data": {"content": reply, "flags": 64}})))
+}
+
+// --- HTTP API --------------------------------------------------------------------------
+
+type ApiError = (StatusCode, Json<Value>);
+
+fn api_error(status: StatusCode, msg: impl Into<String>) -> ApiError {
+    (status, Json(json!({"ok": false, "error": msg.into()})))
+}
+
+fn status_view() -> Value {
+    let s = status().lock().unwrap_or_else(|e| e.into_inner());
+    json!({
+        "telegram": s.get("telegram").cloned().unwrap_or_default(),
+        "discord": s.get("discord").cloned().unwrap_or_default(),
+    })
+}
+
+pub(crate) async fn api_bots_config_get() -> Result<Json<Value>, StatusCode> {
+    let cfg = load_config().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(json!({"ok": true, "config": config_view(&cfg), "status": status_view()})))
+}
+
+/// The configuration with its tokens, for the settings export (which redacts them).
+pub(crate) async fn export_config() -> Result<Value, String> {
+    let cfg = load_config().await.map_err(|e| e.to_string())?;
+    serde_json::to_value(cfg).map_err(|e| e.to_string())
+}
+
+pub(crate) async fn api_bots_config_set(Json(mut cfg): Json<BotConfig>) -> Result<Json<Value>, ApiError> {
+    let bad = |msg: &str| Err(api_error(StatusCode::BAD_REQUEST, msg));
+    // GET never returns the tokens, so a form saved without touching them
+    // sends none: keep the ones we have.
+    let current = load_config().await.unwrap_or_default();
+    if cfg.telegram.bot_token.is_empty() {
+        cfg.telegram.bot_token = current.telegram.bot_token;
+    }
+    if cfg.discord.bot_token.is_empty() {
+        cfg.discord.bot_token = current.discord.bot_token;
+    }
+    let (t, d) = (&mut cfg.telegram, &mut cfg.discord);
+    t.bot_token = t.bot_token.trim().to_string();
+    d.application_id = d.application_id.trim().to_string();
+    d.public_key = d.public_key.trim().to_lowercase();
+    d.allowed_users = d.allowed_users.iter().map(|u| u.trim().to_string()).filter(|u| !u.is_empty()).collect();
+    if t.enabled && !t.bot_token.contains(':') {
+        return bad("telegram needs bot_token (from @BotFather)");
+    }
+    if d.enabled && (d.application_id.is_empty() || !d.application_id.chars().all(|c| c.is_ascii_digit())) {
+        return bad("discord needs application_id (a number)");
+    }
+    if d.enabled && hex_decode(&d.public_key).is_none_or(|k| k.len() != 32) {
+        return bad("discord needs public_key (64 hex digits)");
+    }
+    if d.allowed_users.iter().any(|u| !u.chars().all(|c| c.is_ascii_digit())) {
+        return bad("discord allowed_users must be user ids (numbers)");
+    }
+
+    let mut stored = cfg.clone();
+    for token in [&mut stored.telegram.bot_token, &mut stored.discord.bot_token] {
+        *token = secrets::seal(token).map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    }
+    let raw =
+        serde_json::to_string(&stored).map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    with_db(move |conn| {
+        conn.execute(
+            "INSERT INTO bot_config (id, config) VALUES (1, ?1)
+             ON CONFLICT(id) DO UPDATE SET config=excluded.config",
+            params![raw],
+        )?;
+        Ok(())
+    })
+    .await
+    .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    CONFIG_GEN.fetch_add(1, Ordering::Relaxed);
+
+    // Without a token the commands must be registered by hand (or were already).
+    let mut warning = None;
+    if cfg.discord.enabled && !cfg.discord.bot_token.is_empty() {
+        let res = discord_register(&cfg.discord).await;
+        with_status("discord", |s| s.last_error = res.clone().err());
+        warning = res.err();
+    }
+    Ok(Json(json!({"ok": true, "config": config_view(&cfg), "warning": warning})))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    fn hex(b: &[u8]) -> String {
+        b.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    /// A key, and headers for `body` signed with it at `timestamp`.
+    fn signed(timestamp: u64, body: &[u8]) -> (String, HeaderMap) {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&ring::rand::SystemRandom::new()).unwrap();
+        let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let mut message = timestamp.to_string().into_bytes();
+        message.extend_from_slice(body);
+        let mut headers = HeaderMap::new();
+        headers.insert("x-signature-timestamp", timestamp.to_string().parse().unwrap());
+        headers.insert("x-signature-ed25519", hex(pair.sign(&message).as_ref()).parse().unwrap());
+        (hex(pair.public_key().as_ref()), headers)
+    }
+
+    #[test]
+    fn discord_accepts_fresh_signatures() {
+        let body = br#"{"type":1}"#;
+        let (key, headers) = signed(unix_ms_now() / 1000, body);
+        assert!(discord_signed(&key, &headers, body));
+        assert!(!discord_signed(&key, &headers, br#"{"type":2}"#));
+    }
+
+    #[test]
+    fn discord_refuses_replays() {
+        let body = br#"{"type":2,"data":{"name":"skip"}}"#;
+        let now = unix_ms_now() / 1000;
+        for stale in [now - DISCORD_MAX_SKEW_S - 60, now + DISCORD_MAX_SKEW_S + 60] {
+            let (key, headers) = signed(stale, body);
+            assert!(!discord_signed(&key, &headers, body), "{stale}");
+        }
+    }
+}
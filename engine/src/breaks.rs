@@ -0,0 +1,199 @@
+// --- Break auto-fill ---------------------------------------------------------------
+//
+// A break marker (`BRK` item) may carry a target length in its `dur`, e.g. a
+// "4:00 spot break" before a network join. When playout reaches it, the spots
+// from the break stack are fitted to that length:
+// - trim: spots that would overrun are held back on the break stack for the
+//   next break (paid spots are never silently dropped),
+// - pad: a shortfall larger than the tolerance is filled with items from the
+//   filler category (a cart category or library tag, e.g. short promos).
+//
+// A marker with no length ("0:00") plays the whole stack, as before.
+//
+// The fitting itself runs under the playout lock, so filler candidates are
+// fetched from SQLite beforehand (`filler_candidates`).
+
+use axum::{http::StatusCode, Json};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{fmt_dur_mmss, fmt_dur_ms, LogItem, QueueInsertItem};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct BreakConfig {
+    /// Cart category or library tag used to pad short breaks.
+    filler_category: String,
+    /// How far (seconds) a filled break may end up from its target.
+    tolerance_s: u32,
+}
+
+fn default_config() -> BreakConfig {
+    BreakConfig { filler_category: "FIL".into(), tolerance_s: 5 }
+}
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS break_config (
+            id               INTEGER PRIMARY KEY CHECK (id = 1),
+            filler_category  TEXT NOT NULL,
+            tolerance_s      INTEGER NOT NULL
+        );
+        "#,
+    )
+}
+
+fn db_load_config(conn: &Connection) -> anyhow::Result<BreakConfig> {
+    crate::db_init(conn)?;
+    let cfg = conn
+        .query_row("SELECT filler_category, tolerance_s FROM break_config WHERE id = 1", [], |row| {
+            Ok(BreakConfig { filler_category: row.get(0)?, tolerance_s: row.get::<_, i64>(1)?.max(0) as u32 })
+        })
+        .optional()?;
+    Ok(cfg.unwrap_or_else(default_config))
+}
+
+/// Break settings plus filler candidates (shuffled).
+pub(crate) struct Filler {
+    tolerance_s: u32,
+    candidates: Vec<QueueInsertItem>,
+}
+
+/// Load the config and filler candidates. Called before taking the playout
+/// lock, only when the next item is a break marker.
+pub(crate) async fn filler_candidates() -> Filler {
+    let res = crate::db::call(move |conn| -> anyhow::Result<Filler> {
+        let cfg = db_load_config(conn)?;
+        let cat = cfg.filler_category;
+        let mut stmt = conn.prepare(
+            "SELECT * FROM (
+                 SELECT cart, title, artist, dur FROM carts WHERE category = ?1
+                 UNION ALL
+                 SELECT path, title, artist, duration_s FROM library_tracks WHERE tag = ?1
+             ) ORDER BY RANDOM() LIMIT 100",
+        )?;
+        let candidates = stmt
+            .query_map(params![cat], |row| {
+                // `dur` is "M:SS" for carts and seconds for library tracks.
+                let (dur, dur_ms) = match row.get_ref(3)? {
+                    rusqlite::types::ValueRef::Integer(s) => {
+                        let s = s.max(0);
+                        (fmt_dur_mmss(s as u32), Some(s as u64 * 1000))
+                    }
+                    other => (other.as_str().unwrap_or("0:00").to_string(), None),
+                };
+                Ok(QueueInsertItem {
+                    tag: cat.clone(),
+                    title: row.get(1)?,
+                    artist: row.get(2)?,
+                    dur,
+                    dur_ms,
+                    cart: row.get(0)?,
+                    locked: false,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(Filler { tolerance_s: cfg.tolerance_s, candidates })
+    })
+    .await;
+    match res {
+        Ok(Ok(f)) => f,
+        Ok(Err(e)) => {
+            tracing::warn!("break fill: {e}");
+            Filler { tolerance_s: default_config().tolerance_s, candidates: Vec::new() }
+        }
+        Err(_) => Filler { tolerance_s: default_config().tolerance_s, candidates: Vec::new() },
+    }
+}
+
+/// Fit `spots` to `target_ms`. Returns the items to air and the spots held back.
+pub(crate) fn fit_break(spots: Vec<LogItem>, target_ms: u64, filler: &Filler) -> (Vec<LogItem>, Vec<LogItem>) {
+    if target_ms == 0 {
+        return (spots, Vec::new());
+    }
+    let tolerance_ms = filler.tolerance_s as u64 * 1000;
+    let limit = target_ms + tolerance_ms;
+    let mut total = 0u64;
+    let mut air = Vec::new();
+    let mut held = Vec::new();
+    for spot in spots {
+        let d = spot.length_ms();
+        if total + d <= limit {
+            total += d;
+            air.push(spot);
+        } else {
+            held.push(spot);
+        }
+    }
+
+    // Pad with the longest filler that still fits, until within tolerance.
+    let mut pool: Vec<&QueueInsertItem> = filler.candidates.iter().collect();
+    while total + tolerance_ms < target_ms {
+        let room = limit - total;
+        let best = pool
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| (1..=room).contains(&c.length_ms()))
+            .max_by_key(|(_, c)| c.length_ms())
+            .map(|(i, _)| i);
+        let Some(i) = best else { break };
+        let c = pool.remove(i);
+        total += c.length_ms();
+        let item = QueueInsertItem {
+            tag: c.tag.clone(),
+            title: c.title.clone(),
+            artist: c.artist.clone(),
+            dur: c.dur.clone(),
+            dur_ms: c.dur_ms,
+            cart: c.cart.clone(),
+            locked: false,
+        };
+        air.push(item.into_log_item("queued"));
+    }
+
+    if total.abs_diff(target_ms) > tolerance_ms {
+        tracing::warn!(
+            "break fill: {} against a {} target ({} spot(s) held back)",
+            fmt_dur_ms(total),
+            fmt_dur_ms(target_ms),
+            held.len()
+        );
+    } else {
+        tracing::info!("break fill: {} for a {} target", fmt_dur_ms(total), fmt_dur_ms(target_ms));
+    }
+    (air, held)
+}
+
+// --- HTTP API --------------------------------------------------------------------------
+
+pub(crate) async fn api_break_config_get() -> Result<Json<BreakConfig>, StatusCode> {
+    crate::db::call(move |conn| db_load_config(conn))
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map(Json)
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+pub(crate) async fn api_break_config_set(Json(mut cfg): Json<BreakConfig>) -> Result<Json<serde_json::Value>, StatusCode> {
+    cfg.filler_category = cfg.filler_category.trim().to_string();
+    if cfg.filler_category.is_empty() || cfg.tolerance_s > 60 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let saved = cfg.clone();
+    crate::db::call(move |conn| -> anyhow::Result<()> {
+        crate::db_init(conn)?;
+        conn.execute(
+            "INSERT INTO break_config (id, filler_category, tolerance_s) VALUES (1, ?1, ?2)
+             ON CONFLICT(id) DO UPDATE SET
+               filler_category=excluded.filler_category,
+               tolerance_s=excluded.tolerance_s",
+            params![saved.filler_category, saved.tolerance_s as i64],
+        )?;
+        Ok(())
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(json!({"ok": true, "config": cfg})))
+}
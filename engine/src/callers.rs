@@ -0,0 +1,734 @@
+// --- Phone callers ---------------------------------------------------------------------
+//
+// Phone-ins through a SIP softphone running next to the engine. The engine
+// does not speak SIP itself: baresip registers with the station's SIP
+// provider or PBX, and the engine drives it over baresip's `ctrl_tcp` module
+// (netstring-framed JSON on `control`, usually 127.0.0.1:4444):
+// - call events (`CALL_INCOMING`, `CALL_ESTABLISHED`, `CALL_CLOSED`, ...)
+//   keep the list of calls, which the status snapshot shows next to the
+//   producers,
+// - answer and hang up are baresip commands (`callfind` selects the call).
+// The link is reconnected every few seconds while it is down, and every call
+// is forgotten when it drops (the softphone may have restarted).
+//
+// Audio: baresip plays the caller to a sound device (`audio_player`, e.g. an
+// ALSA loopback) and the engine captures that device with ffmpeg
+// (`input_format`/`input`) while a call is connected. The capture feeds the
+// caller channel of the producer mixer (see producers.rs), which `writer_playout`
+// sums into the program next to the overlay bus when the caller is on air and
+// not coughed, at the fader level. The channel is buffered `PREROLL_MS` and
+// capped at `MAX_BUFFER_MS`, so the two sound clocks drifting apart costs a
+// dropped or repeated few milliseconds, not a growing delay. What the caller
+// hears back (mix-minus) is the softphone's own `audio_source` and is set up
+// outside the engine. There is one line: a second call can ring, but is
+// answered only once the first has hung up.
+//
+// The capture is also offered, before the fader, to the show recorder
+// (`subscribe`, see recorder.rs).
+//
+// With `record` on, the same ffmpeg writes the caller's side of each connected
+// call to `calls/` in the data directory (FLAC). Finished calls are rows of
+// `call_log`: who, when, whether they went on air, the recording and how the
+// call ended.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+use axum::{
+    extract::{Path, Query},
+    http::StatusCode,
+    Json,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::process::Command;
+use tokio::sync::{broadcast, mpsc};
+use tokio::time::{timeout, Duration};
+
+use crate::{backup, ffmpeg, storage, unix_ms_now};
+
+/// How long to wait between attempts to reach the softphone.
+const RETRY: Duration = Duration::from_secs(3);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+/// Largest control message accepted from the softphone.
+const MAX_MESSAGE: usize = 64 * 1024;
+/// s16le stereo at 48 kHz, as `writer_playout`.
+const BYTES_PER_MS: usize = 48 * 4;
+/// Caller audio buffered before it is first mixed, and after an underrun.
+const PREROLL_MS: usize = 60;
+/// The buffer is cut back to `PREROLL_MS` once it holds this much.
+const MAX_BUFFER_MS: usize = 250;
+/// Fader range, dB (as producers).
+const FADER_MIN_DB: f32 = -60.0;
+const FADER_MAX_DB: f32 = 10.0;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct CallerConfig {
+    #[serde(default)]
+    enabled: bool,
+    /// baresip `ctrl_tcp` address.
+    #[serde(default = "default_control")]
+    control: String,
+    /// ffmpeg input format of the device the softphone plays callers to.
+    #[serde(default = "default_input_format")]
+    input_format: String,
+    /// That device (`hw:Loopback,1,0`, a PulseAudio source, ...).
+    #[serde(default)]
+    input: String,
+    /// Record the caller's side of each call.
+    #[serde(default = "default_true")]
+    record: bool,
+}
+
+fn default_control() -> String {
+    "127.0.0.1:4444".into()
+}
+
+fn default_input_format() -> String {
+    "alsa".into()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_config() -> CallerConfig {
+    serde_json::from_str("{}").expect("defaults")
+}
+
+/// A call the softphone has, as the status panel shows it.
+#[derive(Clone, Serialize)]
+pub(crate) struct Call {
+    id: String,
+    /// The caller's number (the user part of their SIP address).
+    number: String,
+    name: String,
+    /// "incoming" or "outgoing".
+    direction: String,
+    /// "ringing" or "connected".
+    state: &'static str,
+    since_ms: u64,
+    answered_ms: Option<u64>,
+    on_air: bool,
+    fader_db: f32,
+    /// Cough.
+    muted: bool,
+    /// Has been on air during this call.
+    aired: bool,
+    /// File name under `calls/`, while recording.
+    recording: Option<String>,
+}
+
+#[derive(Clone, Serialize, Default)]
+struct LinkStatus {
+    connected: bool,
+    since_ms: Option<u64>,
+    last_error: Option<String>,
+}
+
+static CALLS: Mutex<Vec<Call>> = Mutex::new(Vec::new());
+/// Commands for the connected softphone.
+static COMMANDS: Mutex<Option<mpsc::UnboundedSender<Value>>> = Mutex::new(None);
+/// Bumped when the configuration is saved, so the link reconnects with it.
+static CONFIG_GEN: AtomicU64 = AtomicU64::new(0);
+
+fn calls_lock() -> std::sync::MutexGuard<'static, Vec<Call>> {
+    CALLS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Caller audio as captured, for the show recorder.
+fn tap() -> &'static broadcast::Sender<Vec<u8>> {
+    static TAP: OnceLock<broadcast::Sender<Vec<u8>>> = OnceLock::new();
+    TAP.get_or_init(|| broadcast::channel(64).0)
+}
+
+pub(crate) fn subscribe() -> broadcast::Receiver<Vec<u8>> {
+    tap().subscribe()
+}
+
+fn link() -> std::sync::MutexGuard<'static, LinkStatus> {
+    static LINK: OnceLock<Mutex<LinkStatus>> = OnceLock::new();
+    LINK.get_or_init(|| Mutex::new(LinkStatus::default())).lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The calls, for the status snapshot.
+pub(crate) fn calls() -> Vec<Call> {
+    calls_lock().clone()
+}
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS caller_config (
+            id      INTEGER PRIMARY KEY CHECK (id = 1),
+            config  TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS call_log (
+            id           INTEGER PRIMARY KEY AUTOINCREMENT,
+            number       TEXT NOT NULL,
+            name         TEXT NOT NULL,
+            direction    TEXT NOT NULL,
+            started_ms   INTEGER NOT NULL,
+            answered_ms  INTEGER,
+            ended_ms     INTEGER NOT NULL,
+            aired        INTEGER NOT NULL,
+            recording    TEXT,
+            reason       TEXT NOT NULL
+        );
+        "#,
+    )
+}
+
+async fn with_db<T: Send + 'static>(
+    f: impl FnOnce(&Connection) -> anyhow::Result<T> + Send + 'static,
+) -> anyhow::Result<T> {
+    crate::db::call(move |conn| {
+        crate::db_init(conn)?;
+        f(conn)
+    })
+    .await?
+}
+
+async fn load_config() -> anyhow::Result<CallerConfig> {
+    with_db(|conn| {
+        let raw: Option<String> =
+            conn.query_row("SELECT config FROM caller_config WHERE id = 1", [], |row| row.get(0)).optional()?;
+        Ok(match raw {
+            Some(r) => serde_json::from_str(&r)?,
+            None => default_config(),
+        })
+    })
+    .await
+}
+
+// --- Softphone link --------------------------------------------------------------------
+
+/// Read one netstring (`<len>:<bytes>,`).
+async fn read_netstring(rd: &mut (impl AsyncBufRead + Unpin)) -> anyhow::Result<Vec<u8>> {
+    let mut len = Vec::new();
+    rd.read_until(b':', &mut len).await?;
+    if len.pop() != Some(b':') {
+        anyhow::bail!("connection closed");
+    }
+    let len: usize = std::str::from_utf8(&len)?.trim().parse()?;
+    if len > MAX_MESSAGE {
+        anyhow::bail!("message of {len} bytes");
+    }
+    let mut msg = vec![0u8; len + 1];
+    rd.read_exact(&mut msg).await?;
+    if msg.pop() != Some(b',') {
+        anyhow::bail!("bad netstring");
+    }
+    Ok(msg)
+}
+
+fn netstring(msg: &Value) -> Vec<u8> {
+    let body = msg.to_string();
+    format!("{}:{body},", body.len()).into_bytes()
+}
+
+/// Send a command to the softphone.
+fn command(name: &str, params: &str) -> Result<(), ApiError> {
+    let msg = json!({"command": name, "params": params, "token": name});
+    match COMMANDS.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        Some(tx) if tx.send(msg).is_ok() => Ok(()),
+        _ => Err(api_error(StatusCode::SERVICE_UNAVAILABLE, "softphone not connected")),
+    }
+}
+
+/// Keep the softphone link up while callers are enabled.
+pub(crate) async fn callers_task() {
+    loop {
+        let generation = CONFIG_GEN.load(Ordering::Relaxed);
+        let cfg = match load_config().await {
+            Ok(cfg) => cfg,
+            Err(e) => {
+                tracing::warn!("callers: {e}");
+                tokio::time::sleep(RETRY).await;
+                continue;
+            }
+        };
+        if cfg.enabled {
+            let res = session(&cfg, generation).await;
+            *COMMANDS.lock().unwrap_or_else(|e| e.into_inner()) = None;
+            let was = std::mem::take(&mut *link()).connected;
+            if let Err(e) = &res {
+                if was {
+                    tracing::warn!("callers: softphone link lost: {e}");
+                }
+                link().last_error = Some(e.to_string());
+            }
+            for call in std::mem::take(&mut *calls_lock()) {
+                ended(call, "softphone disconnected");
+            }
+        } else {
+            *link() = LinkStatus::default();
+        }
+        // Wait out the retry, or until the configuration changes.
+        for _ in 0..RETRY.as_millis() / 100 {
+            if CONFIG_GEN.load(Ordering::Relaxed) != generation {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+}
+
+/// One connection to the softphone, until it drops or the configuration changes.
+async fn session(cfg: &CallerConfig, generation: u64) -> anyhow::Result<()> {
+    let stream = timeout(CONNECT_TIMEOUT, TcpStream::connect(&cfg.control))
+        .await
+        .map_err(|_| anyhow::anyhow!("connecting to {}: timed out", cfg.control))?
+        .map_err(|e| anyhow::anyhow!("connecting to {}: {e}", cfg.control))?;
+    let (rd, mut wr) = stream.into_split();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    *COMMANDS.lock().unwrap_or_else(|e| e.into_inner()) = Some(tx);
+    *link() = LinkStatus { connected: true, since_ms: Some(unix_ms_now()), last_error: None };
+    tracing::info!("callers: connected to the softphone at {}", cfg.control);
+
+    // Messages are read in their own task: a read cut short by `select!` would lose its place.
+    let (ev_tx, mut ev_rx) = mpsc::channel(64);
+    let reader = tokio::spawn(async move {
+        let mut rd = BufReader::new(rd);
+        loop {
+            let msg = read_netstring(&mut rd).await;
+            let failed = msg.is_err();
+            if ev_tx.send(msg).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+    let mut check = tokio::time::interval(Duration::from_millis(500));
+    let res = loop {
+        tokio::select! {
+            msg = ev_rx.recv() => match msg {
+                Some(Ok(msg)) => match serde_json::from_slice::<Value>(&msg) {
+                    Ok(msg) => handle(cfg, &msg),
+                    Err(e) => tracing::warn!("callers: unreadable message from the softphone: {e}"),
+                },
+                Some(Err(e)) => break Err(e),
+                None => break Ok(()),
+            },
+            Some(cmd) = rx.recv() => {
+                if let Err(e) = wr.write_all(&netstring(&cmd)).await {
+                    break Err(e.into());
+                }
+            }
+            _ = check.tick() => if CONFIG_GEN.load(Ordering::Relaxed) != generation {
+                break Ok(());
+            },
+        }
+    };
+    reader.abort();
+    res
+}
+
+/// `sip:+4415551234@provider;transport=udp` -> `+4415551234`.
+fn number_of(uri: &str) -> String {
+    let s = uri.trim_start_matches('<');
+    let s = s.split_once(':').map_or(s, |(_, rest)| rest);
+    s.split(['@', ';', '>']).next().unwrap_or_default().to_string()
+}
+
+/// A message from the softphone.
+fn handle(cfg: &CallerConfig, msg: &Value) {
+    let text = |k: &str| msg.get(k).and_then(|v| v.as_str()).unwrap_or_default().to_string();
+    if msg.get("response").is_some() {
+        if msg.get("ok").and_then(|v| v.as_bool()) == Some(false) {
+            tracing::warn!("callers: softphone refused {}: {}", text("token"), text("data"));
+        }
+        return;
+    }
+    if text("class") != "call" {
+        return;
+    }
+    let (kind, id) = (text("type"), text("id"));
+    if id.is_empty() {
+        return;
+    }
+    let now = unix_ms_now();
+    let mut calls = calls_lock();
+    let at = calls.iter().position(|c| c.id == id);
+    match (kind.as_str(), at) {
+        ("CALL_INCOMING" | "CALL_OUTGOING" | "CALL_RINGING" | "CALL_PROGRESS", None) => {
+            let call = Call {
+                id,
+                number: number_of(&text("peeruri")),
+                name: text("peerdisplayname"),
+                direction: if kind == "CALL_INCOMING" { "incoming" } else { "outgoing" }.into(),
+                state: "ringing",
+                since_ms: now,
+                answered_ms: None,
+                on_air: false,
+                fader_db: 0.0,
+                muted: false,
+                aired: false,
+                recording: None,
+            };
+            tracing::info!("callers: {} call from {} ({})", call.direction, call.number, call.name);
+            calls.push(call);
+        }
+        ("CALL_ESTABLISHED", Some(at)) if calls[at].state != "connected" => {
+            let call = &mut calls[at];
+            call.state = "connected";
+            call.answered_ms = Some(now);
+            tracing::info!(target: "audit", "callers: {} connected", call.number);
+            if line().as_ref().is_none_or(|l| l.closing) {
+                call.recording = start_capture(cfg, call);
+            } else {
+                tracing::warn!("callers: {} connected while the line is busy; no audio", call.number);
+            }
+        }
+        ("CALL_CLOSED", Some(at)) => {
+            let call = calls.remove(at);
+            drop(calls);
+            let reason = text("param");
+            ended(call, if reason.is_empty() { "closed" } else { &reason });
+        }
+        _ => {}
+    }
+}
+
+/// A call is over: fade its audio out and log it.
+fn ended(call: Call, reason: &str) {
+    if let Some(l) = line().as_mut().filter(|l| l.call_id == call.id) {
+        l.target = 0.0;
+        l.closing = true;
+    }
+    tracing::info!(target: "audit", "callers: {} ended ({reason})", call.number);
+    let reason = reason.to_string();
+    tokio::spawn(async move {
+        let res = with_db(move |conn| {
+            conn.execute(
+                "INSERT INTO call_log
+                 (number, name, direction, started_ms, answered_ms, ended_ms, aired, recording, reason)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    call.number,
+                    call.name,
+                    call.direction,
+                    call.since_ms,
+                    call.answered_ms,
+                    unix_ms_now(),
+                    call.aired,
+                    call.recording,
+                    reason
+                ],
+            )?;
+            Ok(())
+        })
+        .await;
+        if let Err(e) = res {
+            tracing::warn!("callers: call log: {e}");
+        }
+    });
+}
+
+// --- Caller audio ----------------------------------------------------------------------
+
+/// The captured caller, on its way into the program.
+struct Line {
+    call_id: String,
+    buf: VecDeque<u8>,
+    /// Gain applied at the end of the last mix, and the one wanted now.
+    gain: f32,
+    target: f32,
+    primed: bool,
+    /// The call has ended: the next mix fades it out and drops the line.
+    closing: bool,
+}
+
+static LINE: Mutex<Option<Line>> = Mutex::new(None);
+
+fn line() -> std::sync::MutexGuard<'static, Option<Line>> {
+    LINE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn target_gain(call: &Call) -> f32 {
+    if call.on_air && !call.muted {
+        10f32.powf(call.fader_db / 20.0)
+    } else {
+        0.0
+    }
+}
+
+fn recordings_dir() -> std::path::PathBuf {
+    storage::data_dir().join("calls")
+}
+
+/// Start capturing `call`'s audio. Returns the recording's file name.
+fn start_capture(cfg: &CallerConfig, call: &Call) -> Option<String> {
+    let digits: String = call.number.chars().filter(|c| c.is_ascii_alphanumeric() || *c == '+').take(32).collect();
+    let recording = cfg.record.then(|| format!("{}-{}.flac", backup::stamp(), digits));
+    *line() = Some(Line {
+        call_id: call.id.clone(),
+        buf: VecDeque::new(),
+        gain: 0.0,
+        target: target_gain(call),
+        primed: false,
+        closing: false,
+    });
+    let (call_id, number) = (call.id.clone(), call.number.clone());
+    let (format, input, file) = (cfg.input_format.clone(), cfg.input.clone(), recording.clone());
+    tokio::spawn(async move {
+        if let Err(e) = capture(&call_id, &format, &input, file.as_deref()).await {
+            tracing::warn!("callers: {number}: capture: {e}");
+        }
+    });
+    recording
+}
+
+/// Feed the line from the capture device until the call ends.
+async fn capture(call_id: &str, format: &str, input: &str, recording: Option<&str>) -> anyhow::Result<()> {
+    let mut cmd = Command::new(ffmpeg::ffmpeg_bin());
+    cmd.arg("-hide_banner").arg("-loglevel").arg("error");
+    cmd.arg("-f").arg(format).arg("-i").arg(input);
+    cmd.arg("-f").arg("s16le").arg("-ar").arg("48000").arg("-ac").arg("2").arg("pipe:1");
+    if let Some(name) = recording {
+        let dir = recordings_dir();
+        tokio::fs::create_dir_all(&dir).await?;
+        cmd.arg("-c:a").arg("flac").arg(dir.join(name));
+    }
+    cmd.stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null());
+    let mut child = cmd.spawn()?;
+    let mut stdout = child.stdout.take().ok_or_else(|| anyhow::anyhow!("capture stdout unavailable"))?;
+    let mut chunk = vec![0u8; 10 * BYTES_PER_MS];
+    loop {
+        let n = match timeout(Duration::from_millis(500), stdout.read(&mut chunk)).await {
+            Ok(Ok(0)) => {
+                let status = child.wait().await?;
+                anyhow::bail!("ffmpeg exited ({status})");
+            }
+            Ok(n) => n?,
+            // Nothing from the device: check the call is still on.
+            Err(_) => 0,
+        };
+        let mut line = line();
+        let Some(l) = line.as_mut().filter(|l| l.call_id == call_id && !l.closing) else { break };
+        if n > 0 {
+            let _ = tap().send(chunk[..n].to_vec());
+        }
+        l.buf.extend(&chunk[..n]);
+        if l.buf.len() > MAX_BUFFER_MS * BYTES_PER_MS {
+            let excess = l.buf.len() - PREROLL_MS * BYTES_PER_MS;
+            l.buf.drain(..excess);
+        }
+    }
+    // `q` lets ffmpeg finish the recording properly.
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(b"q").await;
+    }
+    if timeout(Duration::from_secs(3), child.wait()).await.is_err() {
+        let _ = child.kill().await;
+    }
+    Ok(())
+}
+
+/// Sum the caller on air into `buf` (interleaved s16le stereo). Returns
+/// whether anything was mixed in.
+pub(crate) fn mix(buf: &mut [u8]) -> bool {
+    let mut line = line();
+    let Some(l) = line.as_mut() else { return false };
+    if l.closing && (l.gain == 0.0 || !l.primed) {
+        *line = None;
+        return false;
+    }
+    if !l.primed {
+        if l.buf.len() < PREROLL_MS * BYTES_PER_MS {
+            return false;
+        }
+        l.primed = true;
+    }
+    let n = buf.len().min(l.buf.len()) & !3;
+    let (from, to) = (l.gain, l.target);
+    let frames = (n / 4).max(1) as f32;
+    for (i, s) in buf[..n].chunks_exact_mut(2).enumerate() {
+        let (Some(lo), Some(hi)) = (l.buf.pop_front(), l.buf.pop_front()) else { break };
+        let gain = from + (to - from) * ((i / 2) as f32 / frames);
+        if gain == 0.0 {
+            continue;
+        }
+        let main = i16::from_le_bytes([s[0], s[1]]) as f32;
+        let add = i16::from_le_bytes([lo, hi]) as f32 * gain;
+        let sum = (main + add).clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+        s.copy_from_slice(&sum.to_le_bytes());
+    }
+    l.gain = to;
+    if l.closing {
+        *line = None;
+    } else if n < buf.len() {
+        // Underrun: build the buffer up again before carrying on.
+        l.primed = false;
+    }
+    n > 0 && (from > 0.0 || to > 0.0)
+}
+
+// --- HTTP API --------------------------------------------------------------------------
+
+type ApiError = (StatusCode, Json<Value>);
+
+fn api_error(status: StatusCode, msg: impl Into<String>) -> ApiError {
+    (status, Json(json!({"ok": false, "error": msg.into()})))
+}
+
+fn no_call(id: &str) -> ApiError {
+    api_error(StatusCode::NOT_FOUND, format!("no call {id}"))
+}
+
+/// `GET /api/v1/callers`: the softphone link and the calls.
+pub(crate) async fn api_callers_get() -> Json<Value> {
+    let link = link().clone();
+    Json(json!({"ok": true, "link": link, "calls": calls()}))
+}
+
+pub(crate) async fn api_callers_config_get() -> Result<Json<Value>, StatusCode> {
+    let cfg = load_config().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(json!({"ok": true, "config": cfg})))
+}
+
+pub(crate) async fn api_callers_config_set(Json(mut cfg): Json<CallerConfig>) -> Result<Json<Value>, ApiError> {
+    cfg.control = cfg.control.trim().to_string();
+    cfg.input_format = cfg.input_format.trim().to_string();
+    cfg.input = cfg.input.trim().to_string();
+    if !cfg.control.contains(':') {
+        return Err(api_error(StatusCode::BAD_REQUEST, "control must be host:port"));
+    }
+    if cfg.input_format.is_empty() || !cfg.input_format.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(api_error(StatusCode::BAD_REQUEST, "input_format must be an ffmpeg input format (alsa, pulse, ...)"));
+    }
+    if cfg.enabled && cfg.input.is_empty() {
+        return Err(api_error(StatusCode::BAD_REQUEST, "input (the device callers play to) is required"));
+    }
+    let raw = serde_json::to_string(&cfg).map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    with_db(move |conn| {
+        conn.execute(
+            "INSERT INTO caller_config (id, config) VALUES (1, ?1)
+             ON CONFLICT(id) DO UPDATE SET config=excluded.config",
+            params![raw],
+        )?;
+        Ok(())
+    })
+    .await
+    .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    CONFIG_GEN.fetch_add(1, Ordering::Relaxed);
+    Ok(Json(json!({"ok": true, "config": cfg})))
+}
+
+/// `POST /api/v1/callers/{id}/answer`
+pub(crate) async fn api_caller_answer(Path(id): Path<String>) -> Result<Json<Value>, ApiError> {
+    {
+        let calls = calls_lock();
+        let call = calls.iter().find(|c| c.id == id).ok_or_else(|| no_call(&id))?;
+        if call.state != "ringing" {
+            return Err(api_error(StatusCode::CONFLICT, "call is not ringing"));
+        }
+        if calls.iter().any(|c| c.state == "connected") {
+            return Err(api_error(StatusCode::CONFLICT, "the line is busy: hang up the connected call first"));
+        }
+    }
+    command("callfind", &id)?;
+    command("accept", "")?;
+    Ok(Json(json!({"ok": true})))
+}
+
+/// `POST /api/v1/callers/{id}/hangup`
+pub(crate) async fn api_caller_hangup(Path(id): Path<String>) -> Result<Json<Value>, ApiError> {
+    if !calls_lock().iter().any(|c| c.id == id) {
+        return Err(no_call(&id));
+    }
+    command("callfind", &id)?;
+    command("hangup", "")?;
+    Ok(Json(json!({"ok": true})))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct CallerMix {
+    on_air: Option<bool>,
+    fader_db: Option<f32>,
+    muted: Option<bool>,
+}
+
+/// `POST /api/v1/callers/{id}/mix`: on air, fader and cough, as for producers.
+pub(crate) async fn api_caller_mix(Path(id): Path<String>, Json(req): Json<CallerMix>) -> Result<Json<Value>, ApiError> {
+    if let Some(db) = req.fader_db {
+        if !(FADER_MIN_DB..=FADER_MAX_DB).contains(&db) {
+            return Err(api_error(
+                StatusCode::BAD_REQUEST,
+                format!("fader_db must be {FADER_MIN_DB}..{FADER_MAX_DB}"),
+            ));
+        }
+    }
+    let mut calls = calls_lock();
+    let call = calls.iter_mut().find(|c| c.id == id).ok_or_else(|| no_call(&id))?;
+    call.on_air = req.on_air.unwrap_or(call.on_air);
+    call.fader_db = req.fader_db.unwrap_or(call.fader_db);
+    call.muted = req.muted.unwrap_or(call.muted);
+    call.aired |= call.on_air && call.state == "connected";
+    if let Some(l) = line().as_mut().filter(|l| l.call_id == call.id) {
+        l.target = target_gain(call);
+    }
+    tracing::info!(
+        target: "audit",
+        "callers: {} mix: on_air={} fader={:.1}dB muted={}",
+        call.number,
+        call.on_air,
+        call.fader_db,
+        call.muted
+    );
+    Ok(Json(json!({"ok": true, "call": call.clone()})))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct LogQuery {
+    #[serde(default)]
+    limit: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct LogRow {
+    id: i64,
+    number: String,
+    name: String,
+    direction: String,
+    started_ms: u64,
+    answered_ms: Option<u64>,
+    ended_ms: u64,
+    aired: bool,
+    recording: Option<String>,
+    reason: String,
+}
+
+/// `GET /api/v1/callers/log?limit=50`: finished calls, newest first.
+pub(crate) async fn api_callers_log(Query(q): Query<LogQuery>) -> Result<Json<Value>, StatusCode> {
+    let limit = q.limit.unwrap_or(50).clamp(1, 1000);
+    let calls = with_db(move |conn| {
+        let mut stmt = conn.prepare(
+            "SELECT id, number, name, direction, started_ms, answered_ms, ended_ms, aired, recording, reason
+             FROM call_log ORDER BY id DESC LIMIT ?1",
+        )?;
+        let rows = stmt.query_map([limit], |row| {
+            Ok(LogRow {
+                id: row.get(0)?,
+                number: row.get(1)?,
+                name: row.get(2)?,
+                direction: row.get(3)?,
+                started_ms: row.get(4)?,
+                answered_ms: row.get(5)?,
+                ended_ms: row.get(6)?,
+                aired: row.get(7)?,
+                recording: row.get(8)?,
+                reason: row.get(9)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(json!({"ok": true, "calls": calls})))
+}
@@ -0,0 +1,405 @@
+// --- Carts ---------------------------------------------------------------------
+//
+// A cart is a short, stable number (e.g. `080-0599`) an operator types or a
+// log refers to, mapped to an audio file plus a category (MUS, SWP, COM, ID,
+// ...) and an optional free-form group ("Morning sweepers", "Client X").
+//
+// Historically carts only existed as file names in
+// `/opt/studiocommand/shared/carts/<cart>.<ext>`. The `carts` table makes the
+// mapping explicit and editable; `resolve_cart_to_path` consults it first and
+// only falls back to the old folder guess for carts that are not registered.
+//
+// `resolve_cart_to_path` is synchronous and runs on the playout path, so the
+// table is mirrored into an in-memory index that CRUD handlers keep current.
+
+use std::collections::HashMap;
+use std::sync::{OnceLock, RwLock};
+
+use axum::{
+    extract::{Path, Query},
+    http::StatusCode,
+    Json,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{analysis, fmt_dur_mmss, tags, title_from_path, unix_ms_now};
+
+#[derive(Clone, Serialize)]
+pub(crate) struct Cart {
+    cart: String,
+    path: String,
+    title: String,
+    artist: String,
+    category: String,
+    group: String,
+    /// Formatted "M:SS", like queue items.
+    dur: String,
+    updated_ms: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct CartCategory {
+    code: String,
+    name: String,
+}
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS cart_categories (
+            code  TEXT PRIMARY KEY,
+            name  TEXT NOT NULL
+        );
+
+        INSERT OR IGNORE INTO cart_categories (code, name) VALUES
+            ('MUS', 'Music'),
+            ('SWP', 'Sweeper'),
+            ('COM', 'Commercial'),
+            ('ID',  'Station ID');
+
+        CREATE TABLE IF NOT EXISTS carts (
+            cart        TEXT PRIMARY KEY,
+            path        TEXT NOT NULL,
+            title       TEXT NOT NULL,
+            artist      TEXT NOT NULL,
+            category    TEXT NOT NULL REFERENCES cart_categories(code),
+            grp         TEXT NOT NULL DEFAULT '',
+            dur         TEXT NOT NULL,
+            updated_ms  INTEGER NOT NULL
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_carts_category ON carts(category);
+        "#,
+    )
+}
+
+// --- In-memory index ---------------------------------------------------------
+
+fn index() -> &'static RwLock<HashMap<String, String>> {
+    static INDEX: OnceLock<RwLock<HashMap<String, String>>> = OnceLock::new();
+    INDEX.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+/// Registered file for `cart`, if any. Cheap; safe to call from playout.
+pub(crate) fn lookup(cart: &str) -> Option<String> {
+    index().read().ok()?.get(cart).cloned()
+}
+
+fn index_set(cart: &str, path: Option<&str>) {
+    if let Ok(mut idx) = index().write() {
+        match path {
+            Some(p) => idx.insert(cart.to_string(), p.to_string()),
+            None => idx.remove(cart),
+        };
+    }
+}
+
+/// Load the cart index at startup.
+pub(crate) async fn load_index() {
+    let res = crate::db::call(move |conn| -> anyhow::Result<HashMap<String, String>> {
+        crate::db_init(conn)?;
+        let mut stmt = conn.prepare("SELECT cart, path FROM carts")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<rusqlite::Result<HashMap<_, _>>>()?)
+    })
+    .await;
+    match res {
+        Ok(Ok(map)) => {
+            if let Ok(mut idx) = index().write() {
+                *idx = map;
+            }
+        }
+        Ok(Err(e)) => tracing::warn!("carts: failed to load index: {e}"),
+        Err(e) => tracing::warn!("carts: index load task failed: {e}"),
+    }
+}
+
+// --- SQLite ----------------------------------------------------------------------
+
+const CART_COLUMNS: &str = "cart, path, title, artist, category, grp, dur, updated_ms";
+
+fn cart_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Cart> {
+    Ok(Cart {
+        cart: row.get(0)?,
+        path: row.get(1)?,
+        title: row.get(2)?,
+        artist: row.get(3)?,
+        category: row.get(4)?,
+        group: row.get(5)?,
+        dur: row.get(6)?,
+        updated_ms: row.get::<_, i64>(7)? as u64,
+    })
+}
+
+fn db_list(conn: &Connection, category: Option<&str>, group: Option<&str>) -> anyhow::Result<Vec<Cart>> {
+    crate::db_init(conn)?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {CART_COLUMNS} FROM carts
+         WHERE (?1 IS NULL OR category = ?1) AND (?2 IS NULL OR grp = ?2)
+         ORDER BY cart"
+    ))?;
+    let rows = stmt.query_map(params![category, group], cart_from_row)?;
+    Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+}
+
+fn db_get(conn: &Connection, cart: &str) -> anyhow::Result<Option<Cart>> {
+    crate::db_init(conn)?;
+    Ok(conn
+        .query_row(
+            &format!("SELECT {CART_COLUMNS} FROM carts WHERE cart = ?1"),
+            params![cart],
+            cart_from_row,
+        )
+        .optional()?)
+}
+
+fn db_upsert(conn: &Connection, c: &Cart) -> anyhow::Result<()> {
+    crate::db_init(conn)?;
+    conn.execute(
+        "INSERT INTO carts (cart, path, title, artist, category, grp, dur, updated_ms)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+         ON CONFLICT(cart) DO UPDATE SET
+           path=excluded.path,
+           title=excluded.title,
+           artist=excluded.artist,
+           category=excluded.category,
+           grp=excluded.grp,
+           dur=excluded.dur,
+           updated_ms=excluded.updated_ms",
+        params![c.cart, c.path, c.title, c.artist, c.category, c.group, c.dur, c.updated_ms as i64],
+    )?;
+    Ok(())
+}
+
+fn db_delete(conn: &Connection, cart: &str) -> anyhow::Result<bool> {
+    crate::db_init(conn)?;
+    Ok(conn.execute("DELETE FROM carts WHERE cart = ?1", params![cart])? > 0)
+}
+
+fn db_categories(conn: &Connection) -> anyhow::Result<Vec<CartCategory>> {
+    crate::db_init(conn)?;
+    let mut stmt = conn.prepare("SELECT code, name FROM cart_categories ORDER BY code")?;
+    let rows = stmt.query_map([], |row| Ok(CartCategory { code: row.get(0)?, name: row.get(1)? }))?;
+    Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+}
+
+fn db_category_exists(conn: &Connection, code: &str) -> anyhow::Result<bool> {
+    crate::db_init(conn)?;
+    Ok(conn
+        .query_row("SELECT 1 FROM cart_categories WHERE code = ?1", params![code], |_| Ok(()))
+        .optional()?
+        .is_some())
+}
+
+/// Run a blocking DB closure, mapping every failure to a 500.
+async fn with_db<T: Send + 'static>(
+    f: impl FnOnce(&Connection) -> anyhow::Result<T> + Send + 'static,
+) -> Result<T, StatusCode> {
+    crate::db::call(move |conn| f(conn))
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|e| {
+        tracing::warn!("carts: db error: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+// --- HTTP API ----------------------------------------------------------------------
+
+/// Cart numbers end up in file names and URLs, so keep them boring.
+fn valid_cart_number(cart: &str) -> bool {
+    !cart.is_empty()
+        && cart.len() <= 32
+        && cart.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct CartListQuery {
+    category: Option<String>,
+    group: Option<String>,
+}
+
+pub(crate) async fn api_carts_list(Query(q): Query<CartListQuery>) -> Result<Json<Vec<Cart>>, StatusCode> {
+    let category = q.category.map(|c| c.trim().to_ascii_uppercase());
+    with_db(move |conn| db_list(conn, category.as_deref(), q.group.as_deref())).await.map(Json)
+}
+
+pub(crate) async fn api_cart_get(Path(cart): Path<String>) -> Result<Json<Cart>, StatusCode> {
+    with_db(move |conn| db_get(conn, &cart)).await?.map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+#[derive(Deserialize)]
+pub(crate) struct CartCreateReq {
+    cart: String,
+    path: String,
+    category: String,
+    #[serde(default)]
+    group: String,
+    /// Defaults to the file's tags.
+    title: Option<String>,
+    artist: Option<String>,
+}
+
+/// Create a cart. The file must exist and be playable; title, artist and
+/// duration default to what the file says about itself.
+pub(crate) async fn api_cart_create(Json(req): Json<CartCreateReq>) -> Result<Json<serde_json::Value>, StatusCode> {
+    let cart = req.cart.trim().to_string();
+    let category = req.category.trim().to_ascii_uppercase();
+    if !valid_cart_number(&cart) || !req.path.starts_with('/') {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let probe = analysis::probe_cached(&req.path).await.map_err(|e| {
+        tracing::warn!("carts: {e}");
+        StatusCode::UNPROCESSABLE_ENTITY
+    })?;
+    if !probe.has_audio {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    let c = Cart {
+        cart: cart.clone(),
+        title: req.title.or(probe.title).unwrap_or_else(|| title_from_path(&req.path)),
+        artist: req.artist.or(probe.artist).unwrap_or_default(),
+        path: req.path,
+        category,
+        group: req.group.trim().to_string(),
+        dur: fmt_dur_mmss(probe.duration_s),
+        updated_ms: unix_ms_now(),
+    };
+    let saved = c.clone();
+    with_db(move |conn| {
+        if db_get(conn, &saved.cart)?.is_some() {
+            return Ok(Err(StatusCode::CONFLICT));
+        }
+        if !db_category_exists(conn, &saved.category)? {
+            return Ok(Err(StatusCode::BAD_REQUEST));
+        }
+        db_upsert(conn, &saved)?;
+        Ok(Ok(()))
+    })
+    .await??;
+
+    index_set(&c.cart, Some(&c.path));
+    Ok(Json(json!({"ok": true, "cart": c})))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct CartPatch {
+    path: Option<String>,
+    title: Option<String>,
+    artist: Option<String>,
+    category: Option<String>,
+    group: Option<String>,
+}
+
+pub(crate) async fn api_cart_patch(
+    Path(cart): Path<String>,
+    Json(req): Json<CartPatch>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let key = cart.clone();
+    let mut c = with_db(move |conn| db_get(conn, &key)).await?.ok_or(StatusCode::NOT_FOUND)?;
+
+    if let Some(path) = req.path {
+        if !path.starts_with('/') {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        // A new file means a new length.
+        let probe = analysis::probe_cached(&path).await.map_err(|_| StatusCode::UNPROCESSABLE_ENTITY)?;
+        if !probe.has_audio {
+            return Err(StatusCode::UNPROCESSABLE_ENTITY);
+        }
+        c.dur = fmt_dur_mmss(probe.duration_s);
+        c.path = path;
+    }
+    if let Some(t) = req.title {
+        c.title = t.trim().to_string();
+    }
+    if let Some(a) = req.artist {
+        c.artist = a.trim().to_string();
+    }
+    if let Some(cat) = req.category {
+        c.category = cat.trim().to_ascii_uppercase();
+    }
+    if let Some(g) = req.group {
+        c.group = g.trim().to_string();
+    }
+    c.updated_ms = unix_ms_now();
+
+    let saved = c.clone();
+    with_db(move |conn| {
+        if !db_category_exists(conn, &saved.category)? {
+            return Ok(Err(StatusCode::BAD_REQUEST));
+        }
+        db_upsert(conn, &saved)?;
+        Ok(Ok(()))
+    })
+    .await??;
+
+    index_set(&c.cart, Some(&c.path));
+    Ok(Json(json!({"ok": true, "cart": c})))
+}
+
+pub(crate) async fn api_cart_delete(Path(cart): Path<String>) -> Result<Json<serde_json::Value>, StatusCode> {
+    let key = cart.clone();
+    if !with_db(move |conn| db_delete(conn, &key)).await? {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    index_set(&cart, None);
+    Ok(Json(json!({"ok": true})))
+}
+
+pub(crate) async fn api_cart_categories_list() -> Result<Json<Vec<CartCategory>>, StatusCode> {
+    with_db(db_categories).await.map(Json)
+}
+
+#[derive(Deserialize)]
+pub(crate) struct CartCategoryReq {
+    name: String,
+}
+
+/// Create or rename a category. A new category is registered as a tag too
+/// (tags.rs), so its carts can be queued under it.
+pub(crate) async fn api_cart_category_put(
+    Path(code): Path<String>,
+    Json(req): Json<CartCategoryReq>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let code = code.trim().to_ascii_uppercase();
+    let name = req.name.trim().to_string();
+    if !valid_cart_number(&code) || name.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let (tag, label) = (code.clone(), name.clone());
+    with_db(move |conn| {
+        crate::db_init(conn)?;
+        conn.execute(
+            "INSERT INTO cart_categories (code, name) VALUES (?1, ?2)
+             ON CONFLICT(code) DO UPDATE SET name=excluded.name",
+            params![code, name],
+        )?;
+        Ok(())
+    })
+    .await?;
+    tags::ensure(tag, label).await?;
+    Ok(Json(json!({"ok": true})))
+}
+
+/// Delete a category; refused with 409 while carts still use it.
+pub(crate) async fn api_cart_category_delete(Path(code): Path<String>) -> Result<Json<serde_json::Value>, StatusCode> {
+    let code = code.trim().to_ascii_uppercase();
+    with_db(move |conn| {
+        crate::db_init(conn)?;
+        let in_use: i64 = conn.query_row("SELECT COUNT(*) FROM carts WHERE category = ?1", params![code], |r| r.get(0))?;
+        if in_use > 0 {
+            return Ok(Err(StatusCode::CONFLICT));
+        }
+        if conn.execute("DELETE FROM cart_categories WHERE code = ?1", params![code])? == 0 {
+            return Ok(Err(StatusCode::NOT_FOUND));
+        }
+        Ok(Ok(()))
+    })
+    .await??;
+    Ok(Json(json!({"ok": true})))
+}
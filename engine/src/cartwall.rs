@@ -0,0 +1,300 @@
+// --- Cart wall ---------------------------------------------------------------------------
+//
+// The jingle box: a grid of buttons, each holding a cart, that plays the cart
+// the moment it is pressed, over whatever is on air. The queue cannot do that
+// (an insert waits for the current item to end), so a fire goes to the
+// overlay bus (overlay.rs) instead and the queue never sees it.
+//
+// The grid is `pages` x `rows` x `cols` (`cartwall_config`); a slot is named
+// `page-row-col`, counting from 1 (`2-1-3` is page 2, first row, third
+// button). Each assigned slot is a row of `cartwall_slots` with its cart (a
+// cart number or an absolute path), a label, a colour for the UI and a gain.
+// Shrinking the grid hides slots outside it; their assignments come back when
+// it grows again.
+//
+// Firing a slot that is still playing starts it again from the top. Fires
+// need the stream running, since the bus is mixed in by the playout writer.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{overlay, paths, resolve_cart_to_path, voicetrack, AppState};
+
+const MAX_PAGES: u32 = 16;
+const MAX_ROWS: u32 = 12;
+const MAX_COLS: u32 = 12;
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct Grid {
+    pages: u32,
+    rows: u32,
+    cols: u32,
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct Slot {
+    slot: String,
+    page: u32,
+    row: u32,
+    col: u32,
+    cart: String,
+    label: String,
+    color: String,
+    gain_db: f64,
+}
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS cartwall_config (
+            id     INTEGER PRIMARY KEY CHECK (id = 1),
+            pages  INTEGER NOT NULL,
+            rows   INTEGER NOT NULL,
+            cols   INTEGER NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS cartwall_slots (
+            page     INTEGER NOT NULL,
+            row      INTEGER NOT NULL,
+            col      INTEGER NOT NULL,
+            cart     TEXT NOT NULL,
+            label    TEXT NOT NULL DEFAULT '',
+            color    TEXT NOT NULL DEFAULT '',
+            gain_db  REAL NOT NULL DEFAULT 0,
+            PRIMARY KEY (page, row, col)
+        );
+        "#,
+    )
+}
+
+async fn with_db<T: Send + 'static>(
+    f: impl FnOnce(&Connection) -> anyhow::Result<T> + Send + 'static,
+) -> anyhow::Result<T> {
+    crate::db::call(move |conn| {
+        crate::db_init(conn)?;
+        f(conn)
+    })
+    .await?
+}
+
+fn db_grid(conn: &Connection) -> anyhow::Result<Grid> {
+    let grid = conn
+        .query_row("SELECT pages, rows, cols FROM cartwall_config WHERE id = 1", [], |row| {
+            Ok(Grid { pages: row.get(0)?, rows: row.get(1)?, cols: row.get(2)? })
+        })
+        .optional()?;
+    Ok(grid.unwrap_or(Grid { pages: 4, rows: 4, cols: 6 }))
+}
+
+fn slot_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Slot> {
+    let (page, r, col): (u32, u32, u32) = (row.get(0)?, row.get(1)?, row.get(2)?);
+    Ok(Slot {
+        slot: format!("{page}-{r}-{col}"),
+        page,
+        row: r,
+        col,
+        cart: row.get(3)?,
+        label: row.get(4)?,
+        color: row.get(5)?,
+        gain_db: row.get(6)?,
+    })
+}
+
+fn db_slot(conn: &Connection, (page, row, col): (u32, u32, u32)) -> anyhow::Result<Option<Slot>> {
+    Ok(conn
+        .query_row(
+            "SELECT page, row, col, cart, label, color, gain_db FROM cartwall_slots
+             WHERE page = ?1 AND row = ?2 AND col = ?3",
+            params![page, row, col],
+            slot_from_row,
+        )
+        .optional()?)
+}
+
+/// `2-1-3` -> (2, 1, 3), if it is inside `grid`.
+fn parse_slot(slot: &str, grid: Grid) -> Option<(u32, u32, u32)> {
+    let mut parts = slot.split('-').map(|p| p.parse::<u32>().ok());
+    let (page, row, col) = (parts.next()??, parts.next()??, parts.next()??);
+    let inside = (1..=grid.pages).contains(&page) && (1..=grid.rows).contains(&row) && (1..=grid.cols).contains(&col);
+    (parts.next().is_none() && inside).then_some((page, row, col))
+}
+
+/// The file a slot's cart plays, as the playout writer resolves it.
+fn cart_path(cart: &str) -> Option<String> {
+    resolve_cart_to_path(cart).or_else(|| cart.starts_with('/').then(|| cart.to_string()))
+}
+
+/// What the wall has playing (the bus also carries voice-track handovers).
+fn wall_playing() -> Vec<overlay::Playing> {
+    overlay::playing().into_iter().filter(|p| p.key != voicetrack::HANDOVER).collect()
+}
+
+type ApiError = (StatusCode, Json<serde_json::Value>);
+
+fn api_error(status: StatusCode, msg: impl Into<String>) -> ApiError {
+    (status, Json(json!({"ok": false, "error": msg.into()})))
+}
+
+fn db_error(e: anyhow::Error) -> ApiError {
+    api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+/// Look up `slot` in the current grid.
+async fn find_slot(slot: String) -> Result<Slot, ApiError> {
+    let found = with_db(move |conn| {
+        let Some(at) = parse_slot(&slot, db_grid(conn)?) else {
+            return Ok(Err(api_error(StatusCode::NOT_FOUND, format!("no slot {slot} on the wall"))));
+        };
+        Ok(db_slot(conn, at)?.ok_or_else(|| api_error(StatusCode::NOT_FOUND, format!("slot {slot} is empty"))))
+    })
+    .await
+    .map_err(db_error)?;
+    found
+}
+
+/// Play the cart on `slot` now, over the program.
+async fn fire_slot(state: &AppState, slot: &str) -> Result<(), ApiError> {
+    let s = find_slot(slot.to_string()).await?;
+    if state.output.lock().await.encoder.is_none() {
+        return Err(api_error(StatusCode::CONFLICT, "the stream is not running"));
+    }
+    let path = cart_path(&s.cart)
+        .ok_or_else(|| api_error(StatusCode::UNPROCESSABLE_ENTITY, format!("cart {} not found", s.cart)))?;
+    tracing::info!(target: "audit", "cartwall: {} fired ({})", s.slot, s.cart);
+    let label = if s.label.is_empty() { s.cart } else { s.label };
+    overlay::play(s.slot, label, path, s.gain_db as f32);
+    Ok(())
+}
+
+/// `fire_slot` for the `cartwall_fire` action.
+pub(crate) async fn fire(state: &AppState, slot: &str) -> Result<(), String> {
+    fire_slot(state, slot).await.map_err(|(_, Json(e))| e["error"].as_str().unwrap_or_default().to_string())
+}
+
+// --- HTTP API --------------------------------------------------------------------------
+
+/// `GET /api/v1/cartwall`: the grid, the assigned slots in it, and what plays.
+pub(crate) async fn api_cartwall_get() -> Result<Json<serde_json::Value>, StatusCode> {
+    let (grid, slots) = with_db(|conn| {
+        let grid = db_grid(conn)?;
+        let mut stmt = conn.prepare(
+            "SELECT page, row, col, cart, label, color, gain_db FROM cartwall_slots
+             WHERE page <= ?1 AND row <= ?2 AND col <= ?3 ORDER BY page, row, col",
+        )?;
+        let slots = stmt
+            .query_map(params![grid.pages, grid.rows, grid.cols], slot_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok((grid, slots))
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(json!({"ok": true, "grid": grid, "slots": slots, "playing": wall_playing()})))
+}
+
+/// `POST /api/v1/cartwall/grid`
+pub(crate) async fn api_cartwall_grid_set(Json(grid): Json<Grid>) -> Result<Json<serde_json::Value>, ApiError> {
+    let fits = (1..=MAX_PAGES).contains(&grid.pages)
+        && (1..=MAX_ROWS).contains(&grid.rows)
+        && (1..=MAX_COLS).contains(&grid.cols);
+    if !fits {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            format!("pages must be 1..{MAX_PAGES}, rows 1..{MAX_ROWS}, cols 1..{MAX_COLS}"),
+        ));
+    }
+    with_db(move |conn| {
+        conn.execute(
+            "INSERT INTO cartwall_config (id, pages, rows, cols) VALUES (1, ?1, ?2, ?3)
+             ON CONFLICT(id) DO UPDATE SET pages=excluded.pages, rows=excluded.rows, cols=excluded.cols",
+            params![grid.pages, grid.rows, grid.cols],
+        )?;
+        Ok(())
+    })
+    .await
+    .map_err(db_error)?;
+    Ok(Json(json!({"ok": true, "grid": grid})))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct SlotPut {
+    cart: String,
+    #[serde(default)]
+    label: String,
+    #[serde(default)]
+    color: String,
+    #[serde(default)]
+    gain_db: f64,
+}
+
+/// `PUT /api/v1/cartwall/{slot}`: assign a cart to a slot.
+pub(crate) async fn api_cartwall_slot_put(
+    Path(slot): Path<String>,
+    Json(req): Json<SlotPut>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let cart = req.cart.trim().to_string();
+    if cart_path(&cart).is_none_or(|p| !paths::from_cart(&p).is_file()) {
+        return Err(api_error(StatusCode::UNPROCESSABLE_ENTITY, format!("cart {cart} not found")));
+    }
+    if !(-30.0..=12.0).contains(&req.gain_db) {
+        return Err(api_error(StatusCode::BAD_REQUEST, "gain_db must be -30..12"));
+    }
+    let saved = with_db(move |conn| {
+        let Some((page, row, col)) = parse_slot(&slot, db_grid(conn)?) else {
+            return Ok(None);
+        };
+        conn.execute(
+            "INSERT INTO cartwall_slots (page, row, col, cart, label, color, gain_db)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+             ON CONFLICT(page, row, col) DO UPDATE SET cart=excluded.cart, label=excluded.label,
+                 color=excluded.color, gain_db=excluded.gain_db",
+            params![page, row, col, cart, req.label.trim(), req.color.trim(), req.gain_db],
+        )?;
+        db_slot(conn, (page, row, col))
+    })
+    .await
+    .map_err(db_error)?;
+    let slot = saved.ok_or_else(|| api_error(StatusCode::NOT_FOUND, "no such slot on the wall"))?;
+    Ok(Json(json!({"ok": true, "slot": slot})))
+}
+
+/// `DELETE /api/v1/cartwall/{slot}`
+pub(crate) async fn api_cartwall_slot_delete(Path(slot): Path<String>) -> Result<Json<serde_json::Value>, ApiError> {
+    let s = find_slot(slot).await?;
+    with_db(move |conn| {
+        conn.execute(
+            "DELETE FROM cartwall_slots WHERE page = ?1 AND row = ?2 AND col = ?3",
+            params![s.page, s.row, s.col],
+        )?;
+        Ok(())
+    })
+    .await
+    .map_err(db_error)?;
+    Ok(Json(json!({"ok": true})))
+}
+
+/// `POST /api/v1/cartwall/{slot}/fire`
+pub(crate) async fn api_cartwall_fire(
+    State(state): State<AppState>,
+    Path(slot): Path<String>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    fire_slot(&state, &slot).await?;
+    Ok(Json(json!({"ok": true, "playing": wall_playing()})))
+}
+
+/// `POST /api/v1/cartwall/{slot}/stop`
+pub(crate) async fn api_cartwall_stop(Path(slot): Path<String>) -> Json<serde_json::Value> {
+    let stopped = if slot == voicetrack::HANDOVER { 0 } else { overlay::stop(Some(&slot)) };
+    Json(json!({"ok": true, "stopped": stopped}))
+}
+
+/// `POST /api/v1/cartwall/stop`: stop everything on the wall.
+pub(crate) async fn api_cartwall_stop_all() -> Json<serde_json::Value> {
+    let stopped = wall_playing().iter().map(|p| overlay::stop(Some(&p.key))).sum::<usize>();
+    Json(json!({"ok": true, "stopped": stopped}))
+}
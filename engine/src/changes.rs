@@ -0,0 +1,119 @@
+// --- Queue change long-poll ------------------------------------------------------------
+//
+// Some operators sit behind proxies that drop WebSockets. For them,
+// `GET /api/v1/queue/changes?since_rev=N` waits (up to 30 s) until the queue
+// revision moves past N, then answers with the new revision and what changed,
+// so a UI stays near real time on plain HTTP.
+//
+// The diff needs the queue as it was at N. The status snapshot (snapshot.rs)
+// sees every queue revision it publishes; it hands each one to `record`, and
+// the last `KEPT` versions are kept here. A revision that is too old, or
+// that the snapshot never saw (two edits within one 50 ms refresh), gets the
+// whole queue instead.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::{shutdown, AppState, LogItem};
+
+/// Queue versions kept for diffs.
+const KEPT: usize = 64;
+
+/// Longest wait.
+const MAX_WAIT_S: u64 = 30;
+
+static HISTORY: Mutex<VecDeque<(u64, Arc<Vec<LogItem>>)>> = Mutex::new(VecDeque::new());
+
+/// Remember the queue at `rev` (called by the snapshot on every new revision).
+pub(crate) fn record(rev: u64, log: &[LogItem]) {
+    let mut h = HISTORY.lock().unwrap_or_else(|e| e.into_inner());
+    if h.len() >= KEPT {
+        h.pop_front();
+    }
+    h.push_back((rev, Arc::new(log.to_vec())));
+}
+
+fn version(rev: u64) -> Option<Arc<Vec<LogItem>>> {
+    let h = HISTORY.lock().unwrap_or_else(|e| e.into_inner());
+    h.iter().rev().find(|(r, _)| *r == rev).map(|(_, log)| log.clone())
+}
+
+/// An item as compared between versions: air times move on their own and
+/// are not a change.
+fn content(item: &LogItem) -> Value {
+    let mut v = serde_json::to_value(item).unwrap_or(Value::Null);
+    if let Some(o) = v.as_object_mut() {
+        o.remove("time");
+        o.remove("start_ms");
+    }
+    v
+}
+
+/// `old` -> `new` as the ids removed, the items added or changed, and the new order.
+fn diff(old: &[LogItem], new: &[LogItem]) -> Value {
+    let removed: Vec<Uuid> = old.iter().filter(|o| !new.iter().any(|n| n.id == o.id)).map(|o| o.id).collect();
+    let items: Vec<&LogItem> = new
+        .iter()
+        .filter(|n| old.iter().find(|o| o.id == n.id).is_none_or(|o| content(o) != content(n)))
+        .collect();
+    let order: Vec<Uuid> = new.iter().map(|n| n.id).collect();
+    json!({"removed": removed, "items": items, "order": order})
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ChangesQuery {
+    /// The revision the client has; without it, the whole queue at once.
+    since_rev: Option<u64>,
+    /// Wait at most this long (1..30 s, default 30).
+    timeout_s: Option<u64>,
+}
+
+/// `GET /api/v1/queue/changes?since_rev=&timeout_s=`
+pub(crate) async fn api_queue_changes(
+    State(state): State<AppState>,
+    Query(q): Query<ChangesQuery>,
+) -> Json<Value> {
+    let mut rx = state.snapshot.clone();
+    let wait = Duration::from_secs(q.timeout_s.unwrap_or(MAX_WAIT_S).clamp(1, MAX_WAIT_S));
+    let deadline = tokio::time::Instant::now() + wait;
+    let snap = loop {
+        let snap = rx.borrow_and_update().clone();
+        if q.since_rev.is_none_or(|since| snap.queue_rev != since) {
+            break snap;
+        }
+        let woke = tokio::time::timeout_at(deadline, rx.changed()).await;
+        if !matches!(woke, Ok(Ok(()))) || shutdown::requested() {
+            return Json(json!({"ok": true, "rev": snap.queue_rev, "changed": false}));
+        }
+    };
+
+    // Diff against the version the client has, if it is still known.
+    let new = version(snap.queue_rev);
+    let old = q.since_rev.and_then(version);
+    match (old, new) {
+        (Some(old), Some(new)) => {
+            let mut body = diff(&old, &new);
+            body["ok"] = json!(true);
+            body["rev"] = json!(snap.queue_rev);
+            body["changed"] = json!(true);
+            body["full"] = json!(false);
+            Json(body)
+        }
+        _ => Json(json!({
+            "ok": true,
+            "rev": snap.queue_rev,
+            "changed": true,
+            "full": true,
+            "queue": snap.log,
+        })),
+    }
+}
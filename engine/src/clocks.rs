@@ -0,0 +1,560 @@
+// --- Clockwheel scheduler ----------------------------------------------------------
+//
+// A clock is the template for one hour: an ordered list of slots, each a minute
+// past the hour plus a category (`:00 ID`, `:02 MUS-A`, `:06 SWP`, ...). A
+// category is either a cart category (ID, SWP, COM, ...) or a library tag
+// (MUS, MUS-A, ...), so clocks can mix registered carts with library music.
+//
+// Dayparts assign clocks to hours of the week ("mon-fri 06-10 -> Morning").
+// The first daypart that matches an hour wins; hours without a clock are left
+// to top-up.
+//
+// The generator fills each slot from its category (least recently played first,
+// subject to the rotation rules in `rotation.rs`) and is used two ways:
+// - `POST /api/v1/logs/{date}/generate` writes a whole day as a day log, for
+//   review before it goes to air.
+// - When the schedule is enabled, a background task generates the next hour
+//   `lead_minutes` before it starts and appends it to the queue. Generated hours
+//   are recorded so a restart does not push the same hour twice.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use axum::{extract::Path, http::StatusCode, Json};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::rotation::Separation;
+use crate::{
+    bump_queue_rev, daylog, fmt_dur_mmss, normalize_log_state, persist_queue, resolve_cart_to_path,
+    unix_ms_now, PlayoutState, QueueInsertItem,
+};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct ClockSlot {
+    /// Minute past the hour, 0..=59.
+    minute: u8,
+    /// Cart category code or library tag.
+    category: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Clock {
+    name: String,
+    slots: Vec<ClockSlot>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Daypart {
+    /// Day abbreviations: "mon", "tue", ... "sun".
+    days: Vec<String>,
+    /// First hour covered, 0..=23.
+    start_hour: u8,
+    /// Hour the daypart ends (exclusive), 1..=24.
+    end_hour: u8,
+    clock: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct ClockSchedule {
+    /// Push generated hours into the queue automatically.
+    #[serde(default)]
+    pub(crate) enabled: bool,
+    /// How long before the top of the hour the next hour is generated.
+    #[serde(default = "default_lead_minutes")]
+    pub(crate) lead_minutes: u32,
+    #[serde(default)]
+    dayparts: Vec<Daypart>,
+}
+
+fn default_lead_minutes() -> u32 {
+    10
+}
+
+fn default_schedule() -> ClockSchedule {
+    // Off by default: an empty schedule would do nothing anyway, and stations
+    // that only use top-up should not have to think about clocks.
+    ClockSchedule { enabled: false, lead_minutes: default_lead_minutes(), dayparts: Vec::new() }
+}
+
+pub(crate) const DAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS clocks (
+            name        TEXT PRIMARY KEY,
+            slots       TEXT NOT NULL,
+            updated_ms  INTEGER NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS clock_schedule (
+            id            INTEGER PRIMARY KEY CHECK (id = 1),
+            enabled       INTEGER NOT NULL,
+            lead_minutes  INTEGER NOT NULL,
+            dayparts      TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS clock_generated (
+            hour_key      TEXT PRIMARY KEY,
+            clock         TEXT NOT NULL,
+            items         INTEGER NOT NULL,
+            generated_ms  INTEGER NOT NULL
+        );
+        "#,
+    )
+}
+
+// --- Local time ----------------------------------------------------------------------
+
+/// Local calendar position of a Unix millis timestamp.
+#[derive(Clone, Copy)]
+pub(crate) struct LocalHour {
+    year: i32,
+    month: u32,
+    day: u32,
+    /// 0 = Sunday, like `tm_wday`.
+    weekday: usize,
+    hour: u8,
+}
+
+impl LocalHour {
+    pub(crate) fn at(unix_ms: u64) -> Option<Self> {
+        let t = (unix_ms / 1000) as libc::time_t;
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        if unsafe { libc::localtime_r(&t, &mut tm) }.is_null() {
+            return None;
+        }
+        Some(LocalHour {
+            year: tm.tm_year + 1900,
+            month: (tm.tm_mon + 1) as u32,
+            day: tm.tm_mday as u32,
+            weekday: tm.tm_wday as usize,
+            hour: tm.tm_hour as u8,
+        })
+    }
+
+    /// Hour 0 of a `YYYY-MM-DD` date (weekday computed by mktime).
+    fn from_date(date: &str) -> Option<Self> {
+        let mut parts = date.split('-').map(|p| p.parse::<i32>().ok());
+        let (y, m, d) = (parts.next()??, parts.next()??, parts.next()??);
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        tm.tm_year = y - 1900;
+        tm.tm_mon = m - 1;
+        tm.tm_mday = d;
+        tm.tm_hour = 12; // away from DST transitions
+        tm.tm_isdst = -1;
+        if unsafe { libc::mktime(&mut tm) } == -1 {
+            return None;
+        }
+        Some(LocalHour { year: y, month: m as u32, day: d as u32, weekday: tm.tm_wday as usize, hour: 0 })
+    }
+
+    /// Unix millis of `minute` past this hour.
+    pub(crate) fn unix_ms(&self, minute: u8) -> Option<u64> {
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        tm.tm_year = self.year - 1900;
+        tm.tm_mon = self.month as i32 - 1;
+        tm.tm_mday = self.day as i32;
+        tm.tm_hour = self.hour as i32;
+        tm.tm_min = minute as i32;
+        tm.tm_isdst = -1;
+        let t = unsafe { libc::mktime(&mut tm) };
+        (t >= 0).then(|| t as u64 * 1000)
+    }
+
+    /// Unix millis of `minute_of_day` (local) on a `YYYY-MM-DD` date.
+    pub(crate) fn local_ms(date: &str, minute_of_day: u32) -> Option<u64> {
+        let at = LocalHour { hour: (minute_of_day / 60) as u8, ..LocalHour::from_date(date)? };
+        at.unix_ms((minute_of_day % 60) as u8)
+    }
+
+    pub(crate) fn date(&self) -> String {
+        format!("{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+
+    pub(crate) fn key(&self) -> String {
+        format!("{} {:02}", self.date(), self.hour)
+    }
+}
+
+// --- Storage ---------------------------------------------------------------------------
+
+fn db_list_clocks(conn: &Connection) -> anyhow::Result<Vec<Clock>> {
+    crate::db_init(conn)?;
+    let mut stmt = conn.prepare("SELECT name, slots FROM clocks ORDER BY name")?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+    let mut out = Vec::new();
+    for r in rows {
+        let (name, slots) = r?;
+        out.push(Clock { name, slots: serde_json::from_str(&slots).unwrap_or_default() });
+    }
+    Ok(out)
+}
+
+fn db_get_clock(conn: &Connection, name: &str) -> anyhow::Result<Option<Clock>> {
+    crate::db_init(conn)?;
+    let slots: Option<String> = conn
+        .query_row("SELECT slots FROM clocks WHERE name = ?1", params![name], |row| row.get(0))
+        .optional()?;
+    Ok(slots.map(|s| Clock { name: name.to_string(), slots: serde_json::from_str(&s).unwrap_or_default() }))
+}
+
+pub(crate) fn db_load_schedule(conn: &Connection) -> anyhow::Result<ClockSchedule> {
+    crate::db_init(conn)?;
+    let row = conn
+        .query_row("SELECT enabled, lead_minutes, dayparts FROM clock_schedule WHERE id = 1", [], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?, row.get::<_, String>(2)?))
+        })
+        .optional()?;
+    Ok(match row {
+        Some((enabled, lead, dayparts)) => ClockSchedule {
+            enabled: enabled != 0,
+            lead_minutes: lead.max(0) as u32,
+            dayparts: serde_json::from_str(&dayparts).unwrap_or_default(),
+        },
+        None => default_schedule(),
+    })
+}
+
+/// Whether the automatic push already appended the hour `key` to the queue.
+pub(crate) fn db_hour_generated(conn: &Connection, key: &str) -> anyhow::Result<bool> {
+    crate::db_init(conn)?;
+    let done: Option<i64> = conn
+        .query_row("SELECT 1 FROM clock_generated WHERE hour_key = ?1", params![key], |row| row.get(0))
+        .optional()?;
+    Ok(done.is_some())
+}
+
+fn db_save_schedule(conn: &Connection, s: &ClockSchedule) -> anyhow::Result<()> {
+    crate::db_init(conn)?;
+    conn.execute(
+        "INSERT INTO clock_schedule (id, enabled, lead_minutes, dayparts)
+         VALUES (1, ?1, ?2, ?3)
+         ON CONFLICT(id) DO UPDATE SET
+           enabled=excluded.enabled,
+           lead_minutes=excluded.lead_minutes,
+           dayparts=excluded.dayparts",
+        params![s.enabled as i64, s.lead_minutes as i64, serde_json::to_string(&s.dayparts)?],
+    )?;
+    Ok(())
+}
+
+async fn with_db<T: Send + 'static>(
+    f: impl FnOnce(&mut Connection) -> anyhow::Result<T> + Send + 'static,
+) -> Result<T, StatusCode> {
+    crate::db::call(move |conn| f(conn))
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|e| {
+        tracing::warn!("clocks: db error: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+// --- Generator ---------------------------------------------------------------------------
+
+/// The clock assigned to an hour, if any.
+fn clock_for(schedule: &ClockSchedule, at: &LocalHour) -> Option<String> {
+    schedule
+        .dayparts
+        .iter()
+        .find(|d| {
+            d.days.iter().any(|day| day.eq_ignore_ascii_case(DAYS[at.weekday]))
+                && d.start_hour <= at.hour
+                && at.hour < d.end_hour
+        })
+        .map(|d| d.clock.clone())
+}
+
+/// One item for `category`: registered carts first, then library tracks with
+/// that tag, least recently played first. Candidates that satisfy the rotation
+/// rules win; if none do, anything not already picked in this run will do.
+fn pick_for_slot(conn: &Connection, category: &str, sep: &mut Separation) -> anyhow::Result<Option<QueueInsertItem>> {
+    let mut stmt = conn.prepare(
+        "SELECT cart, title, artist, dur FROM carts WHERE category = ?1 ORDER BY RANDOM() LIMIT 50",
+    )?;
+    let carts = stmt
+        .query_map(params![category], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?, row.get::<_, String>(3)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let mut stmt = conn.prepare(
+        "SELECT path, title, artist, duration_s FROM library_tracks
+         WHERE tag = ?1
+         ORDER BY COALESCE(last_played_ms, 0), RANDOM()
+         LIMIT 200",
+    )?;
+    let tracks = stmt
+        .query_map(params![category], |row| {
+            let dur: u32 = row.get(3)?;
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?, fmt_dur_mmss(dur)))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    // History records file paths; compare carts by the file they point at.
+    let candidates: Vec<_> = carts
+        .into_iter()
+        .chain(tracks)
+        .map(|c| (resolve_cart_to_path(&c.0).unwrap_or_else(|| c.0.clone()), c))
+        .collect();
+    let choice = candidates
+        .iter()
+        .find(|(path, (_, _, artist, _))| sep.allows(path, artist, category))
+        .or_else(|| candidates.iter().find(|(path, _)| !sep.was_picked(path)))
+        .cloned();
+
+    Ok(choice.map(|(path, (cart, title, artist, dur))| {
+        sep.note_picked(&path, &artist, category);
+        QueueInsertItem { tag: category.to_string(), title, artist, dur, dur_ms: None, cart, locked: false }
+    }))
+}
+
+/// A generated hour: `(time, item)` in slot order, plus slots that found nothing.
+pub(crate) struct GeneratedHour {
+    pub(crate) clock: String,
+    pub(crate) items: Vec<(String, QueueInsertItem)>,
+    pub(crate) empty_slots: Vec<String>,
+}
+
+pub(crate) fn generate_hour(
+    conn: &Connection,
+    schedule: &ClockSchedule,
+    at: &LocalHour,
+    sep: &mut Separation,
+) -> anyhow::Result<Option<GeneratedHour>> {
+    let Some(name) = clock_for(schedule, at) else { return Ok(None) };
+    let Some(clock) = db_get_clock(conn, &name)? else {
+        anyhow::bail!("daypart refers to unknown clock {name}");
+    };
+    let mut slots = clock.slots;
+    slots.sort_by_key(|s| s.minute);
+
+    let mut out = GeneratedHour { clock: name, items: Vec::new(), empty_slots: Vec::new() };
+    for slot in slots {
+        let time = format!("{:02}:{:02}:00", at.hour, slot.minute);
+        // Judge separation as of the slot's air time, not generation time.
+        if let Some(ms) = at.unix_ms(slot.minute) {
+            sep.set_now(ms);
+        }
+        match pick_for_slot(conn, &slot.category, sep)? {
+            Some(item) => out.items.push((time, item)),
+            None => out.empty_slots.push(format!("{time} {}", slot.category)),
+        }
+    }
+    Ok(Some(out))
+}
+
+// --- Automatic push ----------------------------------------------------------------------
+
+/// Generate the upcoming hour `lead_minutes` before it starts and append it to
+/// the queue. Runs forever; the schedule is re-read every pass so API changes
+/// take effect without a restart.
+pub(crate) async fn scheduler_task(playout: Arc<tokio::sync::RwLock<PlayoutState>>) {
+    let mut tick = tokio::time::interval(std::time::Duration::from_secs(30));
+    loop {
+        tick.tick().await;
+        if let Err(e) = scheduler_pass(&playout).await {
+            tracing::warn!("clock scheduler: {e}");
+        }
+    }
+}
+
+async fn scheduler_pass(playout: &Arc<tokio::sync::RwLock<PlayoutState>>) -> anyhow::Result<()> {
+    let now_ms = unix_ms_now();
+    // What is already queued counts against separation like recent plays.
+    let queued: Vec<(String, String, String)> = playout
+        .read()
+        .await
+        .log
+        .iter()
+        .map(|it| (resolve_cart_to_path(&it.cart).unwrap_or_else(|| it.cart.clone()), it.artist.clone(), it.tag.clone()))
+        .collect();
+    let generated = crate::db::call(move |conn| -> anyhow::Result<Option<(String, GeneratedHour)>> {
+        let schedule = db_load_schedule(conn)?;
+        if !schedule.enabled {
+            return Ok(None);
+        }
+        let (Some(now), Some(target)) =
+            (LocalHour::at(now_ms), LocalHour::at(now_ms + schedule.lead_minutes as u64 * 60_000))
+        else {
+            return Ok(None);
+        };
+        // Only ever the *next* hour: enabling the schedule mid-hour must not
+        // dump the current hour's remainder into the queue.
+        let key = target.key();
+        if key == now.key() {
+            return Ok(None);
+        }
+        if db_hour_generated(conn, &key)? {
+            return Ok(None);
+        }
+        let mut sep = Separation::load(conn, now_ms)?;
+        for (path, artist, tag) in &queued {
+            sep.note_queued(path, artist, tag);
+        }
+        let Some(hour) = generate_hour(conn, &schedule, &target, &mut sep)? else { return Ok(None) };
+        conn.execute(
+            "INSERT OR REPLACE INTO clock_generated (hour_key, clock, items, generated_ms) VALUES (?1, ?2, ?3, ?4)",
+            params![key, hour.clock, hour.items.len() as i64, now_ms as i64],
+        )?;
+        Ok(Some((key, hour)))
+    })
+    .await??;
+
+    let Some((key, hour)) = generated else { return Ok(()) };
+    if !hour.empty_slots.is_empty() {
+        tracing::warn!("clock {} for {key}: nothing to schedule for {}", hour.clock, hour.empty_slots.join(", "));
+    }
+    if hour.items.is_empty() {
+        return Ok(());
+    }
+    tracing::info!("clock {} for {key}: appending {} items", hour.clock, hour.items.len());
+
+    let mut p = playout.write().await;
+    for (_, item) in hour.items {
+        let st = if p.log.is_empty() { "playing" } else { "queued" };
+        p.log.push(item.into_log_item(st));
+    }
+    normalize_log_state(&mut p);
+    bump_queue_rev(&mut p);
+    persist_queue(p.log.clone()).await;
+    Ok(())
+}
+
+// --- HTTP API ------------------------------------------------------------------------------
+
+pub(crate) async fn api_clocks_list() -> Result<Json<Vec<Clock>>, StatusCode> {
+    with_db(|conn| db_list_clocks(conn)).await.map(Json)
+}
+
+pub(crate) async fn api_clock_get(Path(name): Path<String>) -> Result<Json<Clock>, StatusCode> {
+    with_db(move |conn| db_get_clock(conn, &name)).await?.map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ClockPutReq {
+    slots: Vec<ClockSlot>,
+}
+
+/// Create or replace a clock.
+pub(crate) async fn api_clock_put(
+    Path(name): Path<String>,
+    Json(req): Json<ClockPutReq>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    let bad = |msg: &str| (StatusCode::BAD_REQUEST, Json(json!({"ok": false, "error": msg})));
+    if name.trim().is_empty() {
+        return Err(bad("clock name must not be empty"));
+    }
+    if req.slots.iter().any(|s| s.minute > 59 || s.category.trim().is_empty()) {
+        return Err(bad("every slot needs a minute 0-59 and a category"));
+    }
+    let slots = serde_json::to_string(&req.slots).unwrap_or_else(|_| "[]".into());
+    with_db(move |conn| {
+        crate::db_init(conn)?;
+        conn.execute(
+            "INSERT INTO clocks (name, slots, updated_ms) VALUES (?1, ?2, ?3)
+             ON CONFLICT(name) DO UPDATE SET slots=excluded.slots, updated_ms=excluded.updated_ms",
+            params![name, slots, unix_ms_now() as i64],
+        )?;
+        Ok(())
+    })
+    .await
+    .map_err(|code| (code, Json(json!({"ok": false}))))?;
+    Ok(Json(json!({"ok": true})))
+}
+
+/// Delete a clock. Refused while a daypart still uses it.
+pub(crate) async fn api_clock_delete(Path(name): Path<String>) -> Result<Json<serde_json::Value>, StatusCode> {
+    let deleted = with_db(move |conn| {
+        let schedule = db_load_schedule(conn)?;
+        if schedule.dayparts.iter().any(|d| d.clock == name) {
+            return Ok(Err(StatusCode::CONFLICT));
+        }
+        Ok(Ok(conn.execute("DELETE FROM clocks WHERE name = ?1", params![name])? > 0))
+    })
+    .await??;
+    if !deleted {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(Json(json!({"ok": true})))
+}
+
+pub(crate) async fn api_clock_schedule_get() -> Result<Json<ClockSchedule>, StatusCode> {
+    with_db(|conn| db_load_schedule(conn)).await.map(Json)
+}
+
+pub(crate) async fn api_clock_schedule_set(
+    Json(s): Json<ClockSchedule>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    let bad = |msg: String| (StatusCode::BAD_REQUEST, Json(json!({"ok": false, "error": msg})));
+    for d in &s.dayparts {
+        if let Some(day) = d.days.iter().find(|day| !DAYS.contains(&day.to_lowercase().as_str())) {
+            return Err(bad(format!("unknown day {day:?} (use mon, tue, ... sun)")));
+        }
+        if d.start_hour >= d.end_hour || d.end_hour > 24 {
+            return Err(bad(format!("daypart for {} needs 0 <= start_hour < end_hour <= 24", d.clock)));
+        }
+    }
+    if s.lead_minutes > 59 {
+        return Err(bad("lead_minutes must be 0-59".into()));
+    }
+    let missing = {
+        let s = s.clone();
+        with_db(move |conn| {
+            let known: HashSet<String> = db_list_clocks(conn)?.into_iter().map(|c| c.name).collect();
+            let missing: Option<String> = s.dayparts.iter().find(|d| !known.contains(&d.clock)).map(|d| d.clock.clone());
+            if missing.is_none() {
+                db_save_schedule(conn, &s)?;
+            }
+            Ok(missing)
+        })
+        .await
+        .map_err(|code| (code, Json(json!({"ok": false}))))?
+    };
+    if let Some(clock) = missing {
+        return Err(bad(format!("unknown clock {clock}")));
+    }
+    Ok(Json(json!({"ok": true, "schedule": s})))
+}
+
+/// Generate every scheduled hour of `date` into its day log (replacing it).
+pub(crate) async fn api_daylog_generate(
+    Path(date): Path<String>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    let Some(day) = daylog::valid_date(&date).then(|| LocalHour::from_date(&date)).flatten() else {
+        return Err((StatusCode::BAD_REQUEST, Json(json!({"ok": false, "error": "date must be YYYY-MM-DD"}))));
+    };
+    let result = with_db(move |conn| {
+        let schedule = db_load_schedule(conn)?;
+        let mut sep = Separation::load(conn, unix_ms_now())?;
+        let mut items = Vec::new();
+        let mut hours = Vec::new();
+        let mut empty_slots = Vec::new();
+        for hour in 0..24u8 {
+            let at = LocalHour { hour, ..day };
+            if let Some(h) = generate_hour(conn, &schedule, &at, &mut sep)? {
+                hours.push(json!({"hour": hour, "clock": h.clock, "items": h.items.len()}));
+                empty_slots.extend(h.empty_slots);
+                items.extend(h.items.into_iter().map(|(time, it)| daylog::DayLogItem::from_insert(time, it)));
+            }
+        }
+        Ok((items, hours, empty_slots))
+    })
+    .await;
+    let (items, hours, empty_slots) = result.map_err(|code| (code, Json(json!({"ok": false}))))?;
+
+    if items.is_empty() {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({"ok": false, "date": date, "error": "no clocks scheduled for this day", "empty_slots": empty_slots})),
+        ));
+    }
+    let generated = items.len();
+    daylog::save_day(date.clone(), items)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"ok": false}))))?;
+    Ok(Json(json!({"ok": true, "date": date, "generated": generated, "hours": hours, "empty_slots": empty_slots})))
+}
@@ -0,0 +1,232 @@
+// --- Cron specs ---------------------------------------------------------------------
+//
+// The usual five fields, evaluated in local time:
+//
+//     minute hour day-of-month month day-of-week
+//       0     *        *         *        *          top of every hour
+//       58    5-22     *         *       1-5         :58 past, weekday daytime
+//      */15   *        *         *        *          every 15 minutes
+//
+// Fields accept `*`, numbers, `a-b` ranges, `a,b,c` lists and `/step`.
+// Day-of-week is 0-6 with 0 = Sunday (7 is accepted as Sunday too). As in cron,
+// when both day-of-month and day-of-week are restricted either may match.
+// `@hourly` and `@daily` are accepted as shorthands.
+
+#[derive(Clone, Debug)]
+pub(crate) struct CronSpec {
+    minutes: u64,
+    hours: u32,
+    days: u32,
+    months: u16,
+    weekdays: u8,
+    days_any: bool,
+    weekdays_any: bool,
+}
+
+/// Parse one field into a bitmask of allowed values in `min..=max`.
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut mask = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((r, s)) => (r, s.parse::<u32>().map_err(|_| format!("bad step in {part:?}"))?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            return Err(format!("step must be positive in {part:?}"));
+        }
+        let (lo, hi) = if range == "*" {
+            (min, max)
+        } else if let Some((a, b)) = range.split_once('-') {
+            let a = a.parse::<u32>().map_err(|_| format!("bad number in {part:?}"))?;
+            let b = b.parse::<u32>().map_err(|_| format!("bad number in {part:?}"))?;
+            (a, b)
+        } else {
+            let v = range.parse::<u32>().map_err(|_| format!("bad number in {part:?}"))?;
+            // "5/10" means "from 5, every 10".
+            (v, if part.contains('/') { max } else { v })
+        };
+        if lo < min || hi > max || lo > hi {
+            return Err(format!("{part:?} is outside {min}-{max}"));
+        }
+        let mut v = lo;
+        while v <= hi {
+            mask |= 1 << v;
+            v += step;
+        }
+    }
+    Ok(mask)
+}
+
+impl CronSpec {
+    pub(crate) fn parse(spec: &str) -> Result<Self, String> {
+        let spec = match spec.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            other => other,
+        };
+        let fields: Vec<&str> = spec.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields.as_slice() else {
+            return Err("expected 5 fields: minute hour day-of-month month day-of-week".into());
+        };
+        let mut weekdays = parse_field(weekday, 0, 7)?;
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        Ok(CronSpec {
+            minutes: parse_field(minute, 0, 59)?,
+            hours: parse_field(hour, 0, 23)? as u32,
+            days: parse_field(day, 1, 31)? as u32,
+            months: parse_field(month, 1, 12)? as u16,
+            weekdays: (weekdays & 0x7f) as u8,
+            days_any: *day == "*",
+            weekdays_any: *weekday == "*",
+        })
+    }
+
+    fn day_matches(&self, tm: &libc::tm) -> bool {
+        let dom = self.days & (1 << tm.tm_mday) != 0;
+        let dow = self.weekdays & (1 << tm.tm_wday) != 0;
+        let month = self.months & (1 << (tm.tm_mon + 1)) != 0;
+        month
+            && match (self.days_any, self.weekdays_any) {
+                (true, true) => true,
+                (true, false) => dow,
+                (false, true) => dom,
+                (false, false) => dom || dow,
+            }
+    }
+
+    /// The first matching minute strictly after `unix_ms` (searching a year ahead).
+    pub(crate) fn next_after(&self, unix_ms: u64) -> Option<u64> {
+        let mut t = (unix_ms / 60_000 + 1) * 60;
+        let limit = t + 366 * 86_400;
+        while t < limit {
+            let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+            if unsafe { libc::localtime_r(&(t as libc::time_t), &mut tm) }.is_null() {
+                return None;
+            }
+            let into_hour = tm.tm_min as u64 * 60;
+            if !self.day_matches(&tm) {
+                // Jump to the next local midnight (approximately, across DST).
+                t += 86_400 - (tm.tm_hour as u64 * 3600 + into_hour);
+            } else if self.hours & (1 << tm.tm_hour) == 0 {
+                t += 3600 - into_hour;
+            } else if self.minutes & (1 << tm.tm_min) == 0 {
+                t += 60;
+            } else {
+                return Some(t * 1000);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Evaluate local time as UTC, so the expected minutes below hold anywhere.
+    fn utc() {
+        extern "C" {
+            fn tzset();
+        }
+        static ONCE: std::sync::Once = std::sync::Once::new();
+        ONCE.call_once(|| {
+            std::env::set_var("TZ", "UTC");
+            unsafe { tzset() };
+        });
+    }
+
+    /// Saturday 2026-10-17 10:07:30 UTC.
+    const SAT_1007: u64 = 1_792_231_650_000;
+
+    fn next(spec: &str, after_ms: u64) -> Option<u64> {
+        utc();
+        CronSpec::parse(spec).unwrap().next_after(after_ms)
+    }
+
+    #[test]
+    fn refuses_bad_specs() {
+        for bad in [
+            "",
+            "* * * *",
+            "* * * * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * 32 * *",
+            "* * * 0 *",
+            "* * * 13 *",
+            "* * * * 8",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "a * * * *",
+            "1,,2 * * * *",
+            "@weekly",
+        ] {
+            assert!(CronSpec::parse(bad).is_err(), "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn parses_fields() {
+        let s = CronSpec::parse("*/15 5-22 * * 1-5").unwrap();
+        assert_eq!(s.minutes, 1 << 0 | 1 << 15 | 1 << 30 | 1 << 45);
+        assert_eq!(s.hours, (5..=22).fold(0, |m, h| m | 1 << h));
+        assert_eq!(s.weekdays, 0b0011_1110);
+        assert!(s.days_any && !s.weekdays_any);
+        assert_eq!(CronSpec::parse("5/20 * * * *").unwrap().minutes, 1 << 5 | 1 << 25 | 1 << 45);
+        assert_eq!(CronSpec::parse("1,3,10-12 * * * *").unwrap().minutes, 1 << 1 | 1 << 3 | 0b111 << 10);
+        // 7 is Sunday too.
+        assert_eq!(CronSpec::parse("0 0 * * 7").unwrap().weekdays, 1);
+        assert_eq!(CronSpec::parse("0 0 * * 0,7").unwrap().weekdays, 1);
+    }
+
+    #[test]
+    fn shorthands() {
+        let hourly = CronSpec::parse("@hourly").unwrap();
+        assert_eq!((hourly.minutes, hourly.hours), (1, (1 << 24) - 1));
+        let daily = CronSpec::parse(" @daily ").unwrap();
+        assert_eq!((daily.minutes, daily.hours), (1, 1));
+        assert_eq!(CronSpec::parse("@midnight").unwrap().hours, 1);
+    }
+
+    #[test]
+    fn next_minute_hour_and_day() {
+        // 11:00 the same day.
+        assert_eq!(next("0 * * * *", SAT_1007), Some(1_792_234_800_000));
+        assert_eq!(next("@hourly", SAT_1007), Some(1_792_234_800_000));
+        // 10:15.
+        assert_eq!(next("*/15 * * * *", SAT_1007), Some(1_792_232_100_000));
+        // Sunday 00:00.
+        assert_eq!(next("@daily", SAT_1007), Some(1_792_281_600_000));
+        // A weekday rule on a Saturday: Monday 05:58.
+        assert_eq!(next("58 5-22 * * 1-5", SAT_1007), Some(1_792_389_480_000));
+        // The first of next month.
+        assert_eq!(next("0 0 1 * *", SAT_1007), Some(1_793_491_200_000));
+        // December 1st, 06:30.
+        assert_eq!(next("30 6 * 12 *", SAT_1007), Some(1_796_106_600_000));
+    }
+
+    #[test]
+    fn next_is_strictly_after() {
+        let eleven = 1_792_234_800_000;
+        assert_eq!(next("0 * * * *", eleven), Some(eleven + 3_600_000));
+        assert_eq!(next("* * * * *", eleven), Some(eleven + 60_000));
+        assert_eq!(next("* * * * *", eleven - 1), Some(eleven));
+    }
+
+    #[test]
+    fn day_of_month_or_day_of_week() {
+        // Restricted both ways either matches: Friday the 23rd comes before the 13th of November.
+        assert_eq!(next("0 12 13 * 5", SAT_1007), Some(1_792_756_800_000));
+        // Only day-of-month restricted: weekday is ignored.
+        assert_eq!(next("0 12 13 * *", SAT_1007), next("0 12 13 11 *", SAT_1007));
+    }
+
+    #[test]
+    fn gives_up_after_a_year() {
+        // The next February 29th is in 2028.
+        assert_eq!(next("0 0 29 2 *", SAT_1007), None);
+    }
+}
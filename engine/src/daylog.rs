@@ -0,0 +1,542 @@
+// --- Day logs --------------------------------------------------------------------
+//
+// A day log is the planned running order for one calendar day, as produced by
+// a traffic/music scheduler (CSV import, Rivendell import, clock generation)
+// or edited by hand. It is stored separately from the live queue: editing
+// tomorrow's log must not touch what is on air now.
+//
+// `POST /api/v1/logs/{date}/load` copies a day log into the queue on demand;
+// with auto-load enabled, the handoff task does it at the configured changeover
+// time (e.g. 06:00 for a station whose broadcast day starts then).
+//
+// Every save bumps the day's `rev`. Edits may pass the revision they were made
+// against and get 409 if someone else changed the log in between.
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::clocks::LocalHour;
+use crate::{
+    bump_queue_rev, check_queue_rev, normalize_log_state, parse_hhmm, persist_queue, unix_ms_now, AppState,
+    QueueInsertItem,
+};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct DayLogItem {
+    /// Scheduled start, local "HH:MM:SS"; empty when the source had no time.
+    #[serde(default)]
+    pub(crate) time: String,
+    pub(crate) tag: String,
+    pub(crate) title: String,
+    pub(crate) artist: String,
+    pub(crate) dur: String,
+    pub(crate) cart: String,
+}
+
+impl DayLogItem {
+    pub(crate) fn from_insert(time: String, item: QueueInsertItem) -> Self {
+        DayLogItem { time, tag: item.tag, title: item.title, artist: item.artist, dur: item.dur, cart: item.cart }
+    }
+
+    fn into_insert(self) -> QueueInsertItem {
+        QueueInsertItem {
+            tag: self.tag,
+            title: self.title,
+            artist: self.artist,
+            dur: self.dur,
+            dur_ms: None,
+            cart: self.cart,
+            locked: false,
+        }
+    }
+}
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS day_log_items (
+            date      TEXT NOT NULL,
+            position  INTEGER NOT NULL,
+            time      TEXT NOT NULL,
+            tag       TEXT NOT NULL,
+            title     TEXT NOT NULL,
+            artist    TEXT NOT NULL,
+            dur       TEXT NOT NULL,
+            cart      TEXT NOT NULL,
+            PRIMARY KEY (date, position)
+        );
+
+        CREATE TABLE IF NOT EXISTS day_logs (
+            date        TEXT PRIMARY KEY,
+            rev         INTEGER NOT NULL,
+            updated_ms  INTEGER NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS day_log_config (
+            id                INTEGER PRIMARY KEY CHECK (id = 1),
+            auto_load         INTEGER NOT NULL,
+            changeover        TEXT NOT NULL,
+            lead_minutes      INTEGER NOT NULL,
+            last_loaded_date  TEXT
+        );
+        "#,
+    )
+}
+
+/// `YYYY-MM-DD` with plausible ranges. Dates are keys, not arithmetic, so this
+/// is all the validation we need.
+pub(crate) fn valid_date(date: &str) -> bool {
+    let parts: Vec<&str> = date.split('-').collect();
+    matches!(parts.as_slice(), [y, m, d]
+        if y.len() == 4 && m.len() == 2 && d.len() == 2
+            && y.parse::<u32>().is_ok()
+            && m.parse::<u32>().map(|m| (1..=12).contains(&m)).unwrap_or(false)
+            && d.parse::<u32>().map(|d| (1..=31).contains(&d)).unwrap_or(false))
+}
+
+pub(crate) fn db_load_day(conn: &Connection, date: &str) -> anyhow::Result<Vec<DayLogItem>> {
+    crate::db_init(conn)?;
+    let mut stmt = conn.prepare(
+        "SELECT time, tag, title, artist, dur, cart FROM day_log_items WHERE date = ?1 ORDER BY position",
+    )?;
+    let rows = stmt.query_map(params![date], |row| {
+        Ok(DayLogItem {
+            time: row.get(0)?,
+            tag: row.get(1)?,
+            title: row.get(2)?,
+            artist: row.get(3)?,
+            dur: row.get(4)?,
+            cart: row.get(5)?,
+        })
+    })?;
+    Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+}
+
+fn db_day_rev(conn: &Connection, date: &str) -> anyhow::Result<u64> {
+    crate::db_init(conn)?;
+    let rev: Option<i64> =
+        conn.query_row("SELECT rev FROM day_logs WHERE date = ?1", params![date], |row| row.get(0)).optional()?;
+    Ok(rev.unwrap_or(0) as u64)
+}
+
+/// Replace the whole log for `date`; returns the new revision. An empty list
+/// deletes the log.
+pub(crate) fn db_save_day(conn: &mut Connection, date: &str, items: &[DayLogItem]) -> anyhow::Result<u64> {
+    crate::db_init(conn)?;
+    let tx = conn.transaction()?;
+    tx.execute("DELETE FROM day_log_items WHERE date = ?1", params![date])?;
+    for (i, it) in items.iter().enumerate() {
+        tx.execute(
+            "INSERT INTO day_log_items (date, position, time, tag, title, artist, dur, cart)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![date, i as i64, it.time, it.tag, it.title, it.artist, it.dur, it.cart],
+        )?;
+    }
+    tx.execute(
+        "INSERT INTO day_logs (date, rev, updated_ms) VALUES (?1, 1, ?2)
+         ON CONFLICT(date) DO UPDATE SET rev = rev + 1, updated_ms = excluded.updated_ms",
+        params![date, unix_ms_now() as i64],
+    )?;
+    let rev: i64 = tx.query_row("SELECT rev FROM day_logs WHERE date = ?1", params![date], |row| row.get(0))?;
+    tx.commit()?;
+    Ok(rev as u64)
+}
+
+pub(crate) async fn save_day(date: String, items: Vec<DayLogItem>) -> anyhow::Result<()> {
+    crate::db::call(move |conn| db_save_day(conn, &date, &items).map(|_| ())).await?
+}
+
+async fn load_day(date: String) -> Result<Vec<DayLogItem>, StatusCode> {
+    crate::db::call(move |conn| db_load_day(conn, &date))
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+// --- HTTP API ----------------------------------------------------------------------
+
+pub(crate) async fn api_daylog_get(Path(date): Path<String>) -> Result<Json<serde_json::Value>, StatusCode> {
+    if !valid_date(&date) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let d = date.clone();
+    let (items, rev) = with_db(move |conn| Ok((db_load_day(conn, &d)?, db_day_rev(conn, &d)?))).await?;
+    Ok(Json(json!({"date": date, "rev": rev, "items": items})))
+}
+
+/// Dates that have a log, with item counts.
+pub(crate) async fn api_daylog_list() -> Result<Json<serde_json::Value>, StatusCode> {
+    let logs = with_db(|conn| {
+        crate::db_init(conn)?;
+        let mut stmt = conn.prepare(
+            "SELECT d.date, d.rev, d.updated_ms, COUNT(i.position)
+             FROM day_logs d JOIN day_log_items i ON i.date = d.date
+             GROUP BY d.date ORDER BY d.date",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(json!({
+                "date": row.get::<_, String>(0)?,
+                "rev": row.get::<_, i64>(1)?,
+                "updated_ms": row.get::<_, i64>(2)?,
+                "items": row.get::<_, i64>(3)?,
+            }))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    })
+    .await?;
+    Ok(Json(json!({"logs": logs})))
+}
+
+async fn with_db<T: Send + 'static>(
+    f: impl FnOnce(&mut Connection) -> anyhow::Result<T> + Send + 'static,
+) -> Result<T, StatusCode> {
+    crate::db::call(move |conn| f(conn))
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Load, check `rev`, apply `edit`, save. Returns the new revision.
+async fn edit_day(
+    date: String,
+    rev: Option<u64>,
+    edit: impl FnOnce(&mut Vec<DayLogItem>) -> Result<(), StatusCode> + Send + 'static,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    if !valid_date(&date) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let res = with_db(move |conn| {
+        if let Some(rev) = rev {
+            if rev != db_day_rev(conn, &date)? {
+                return Ok(Err(StatusCode::CONFLICT));
+            }
+        }
+        let mut items = db_load_day(conn, &date)?;
+        if let Err(code) = edit(&mut items) {
+            return Ok(Err(code));
+        }
+        let new_rev = db_save_day(conn, &date, &items)?;
+        Ok(Ok((new_rev, items.len())))
+    })
+    .await??;
+    Ok(Json(json!({"ok": true, "rev": res.0, "items": res.1})))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct DayLogPutReq {
+    items: Vec<DayLogItem>,
+    #[serde(default)]
+    rev: Option<u64>,
+}
+
+/// Replace a day's log.
+pub(crate) async fn api_daylog_put(
+    Path(date): Path<String>,
+    Json(req): Json<DayLogPutReq>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    edit_day(date, req.rev, move |items| {
+        *items = req.items;
+        Ok(())
+    })
+    .await
+}
+
+#[derive(Deserialize, Default)]
+pub(crate) struct DayLogRevReq {
+    #[serde(default)]
+    rev: Option<u64>,
+}
+
+pub(crate) async fn api_daylog_delete(
+    Path(date): Path<String>,
+    body: Option<Json<DayLogRevReq>>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let rev = body.and_then(|Json(b)| b.rev);
+    edit_day(date, rev, |items| {
+        items.clear();
+        Ok(())
+    })
+    .await
+}
+
+#[derive(Deserialize)]
+pub(crate) struct DayLogInsertReq {
+    /// Insert before this position; default appends.
+    #[serde(default)]
+    position: Option<usize>,
+    item: DayLogItem,
+    #[serde(default)]
+    rev: Option<u64>,
+}
+
+pub(crate) async fn api_daylog_insert(
+    Path(date): Path<String>,
+    Json(req): Json<DayLogInsertReq>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    edit_day(date, req.rev, move |items| {
+        let pos = req.position.unwrap_or(items.len());
+        if pos > items.len() {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        items.insert(pos, req.item);
+        Ok(())
+    })
+    .await
+}
+
+/// Partial update of one item; absent fields are left alone.
+#[derive(Deserialize)]
+pub(crate) struct DayLogItemPatch {
+    time: Option<String>,
+    tag: Option<String>,
+    title: Option<String>,
+    artist: Option<String>,
+    dur: Option<String>,
+    cart: Option<String>,
+    #[serde(default)]
+    rev: Option<u64>,
+}
+
+pub(crate) async fn api_daylog_item_patch(
+    Path((date, pos)): Path<(String, usize)>,
+    Json(req): Json<DayLogItemPatch>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    edit_day(date, req.rev, move |items| {
+        let it = items.get_mut(pos).ok_or(StatusCode::NOT_FOUND)?;
+        if let Some(v) = req.time {
+            it.time = v;
+        }
+        if let Some(v) = req.tag {
+            it.tag = v;
+        }
+        if let Some(v) = req.title {
+            it.title = v;
+        }
+        if let Some(v) = req.artist {
+            it.artist = v;
+        }
+        if let Some(v) = req.dur {
+            it.dur = v;
+        }
+        if let Some(v) = req.cart {
+            it.cart = v;
+        }
+        Ok(())
+    })
+    .await
+}
+
+pub(crate) async fn api_daylog_item_delete(
+    Path((date, pos)): Path<(String, usize)>,
+    body: Option<Json<DayLogRevReq>>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let rev = body.and_then(|Json(b)| b.rev);
+    edit_day(date, rev, move |items| {
+        if pos >= items.len() {
+            return Err(StatusCode::NOT_FOUND);
+        }
+        items.remove(pos);
+        Ok(())
+    })
+    .await
+}
+
+#[derive(Deserialize)]
+pub(crate) struct DayLogMoveReq {
+    from: usize,
+    to: usize,
+    #[serde(default)]
+    rev: Option<u64>,
+}
+
+pub(crate) async fn api_daylog_move(
+    Path(date): Path<String>,
+    Json(req): Json<DayLogMoveReq>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    edit_day(date, req.rev, move |items| {
+        if req.from >= items.len() || req.to >= items.len() {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        let it = items.remove(req.from);
+        items.insert(req.to, it);
+        Ok(())
+    })
+    .await
+}
+
+#[derive(Deserialize, Default)]
+pub(crate) struct DayLogLoadReq {
+    rev: Option<u64>,
+}
+
+/// Append a day log to the end of the queue.
+pub(crate) async fn api_daylog_load(
+    State(state): State<AppState>,
+    Path(date): Path<String>,
+    headers: HeaderMap,
+    body: Option<Json<DayLogLoadReq>>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    if !valid_date(&date) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let req = body.map(|Json(b)| b).unwrap_or_default();
+    let items = load_day(date).await?;
+    if items.is_empty() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let mut p = state.playout.write().await;
+    if req.rev.is_some() || headers.contains_key(axum::http::header::IF_MATCH) {
+        check_queue_rev(&p, &headers, req.rev)?;
+    }
+    let added = items.len();
+    for it in items {
+        let st = if p.log.is_empty() { "playing" } else { "queued" };
+        p.log.push(it.into_insert().into_log_item(st));
+    }
+    normalize_log_state(&mut p);
+    bump_queue_rev(&mut p);
+    persist_queue(p.log.clone()).await;
+
+    Ok(Json(json!({"ok": true, "rev": p.queue_rev, "added": added})))
+}
+
+// --- Automatic handoff -------------------------------------------------------------
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct DayLogConfig {
+    /// Load each day's log into the queue automatically.
+    #[serde(default)]
+    auto_load: bool,
+    /// Local "HH:MM" at which the broadcast day starts.
+    #[serde(default = "default_changeover")]
+    changeover: String,
+    /// How long before the changeover the log is appended, so it is queued
+    /// behind whatever is finishing the previous day.
+    #[serde(default = "default_handoff_lead")]
+    lead_minutes: u32,
+    /// Last date auto-loaded (read-only).
+    #[serde(default)]
+    last_loaded_date: Option<String>,
+}
+
+fn default_changeover() -> String {
+    "00:00".into()
+}
+
+fn default_handoff_lead() -> u32 {
+    10
+}
+
+fn default_config() -> DayLogConfig {
+    DayLogConfig {
+        auto_load: false,
+        changeover: default_changeover(),
+        lead_minutes: default_handoff_lead(),
+        last_loaded_date: None,
+    }
+}
+
+fn db_load_config(conn: &Connection) -> anyhow::Result<DayLogConfig> {
+    crate::db_init(conn)?;
+    let cfg = conn
+        .query_row(
+            "SELECT auto_load, changeover, lead_minutes, last_loaded_date FROM day_log_config WHERE id = 1",
+            [],
+            |row| {
+                Ok(DayLogConfig {
+                    auto_load: row.get::<_, i64>(0)? != 0,
+                    changeover: row.get(1)?,
+                    lead_minutes: row.get::<_, i64>(2)?.max(0) as u32,
+                    last_loaded_date: row.get(3)?,
+                })
+            },
+        )
+        .optional()?;
+    Ok(cfg.unwrap_or_else(default_config))
+}
+
+fn db_save_config(conn: &Connection, cfg: &DayLogConfig) -> anyhow::Result<()> {
+    crate::db_init(conn)?;
+    conn.execute(
+        "INSERT INTO day_log_config (id, auto_load, changeover, lead_minutes, last_loaded_date)
+         VALUES (1, ?1, ?2, ?3, ?4)
+         ON CONFLICT(id) DO UPDATE SET
+           auto_load=excluded.auto_load,
+           changeover=excluded.changeover,
+           lead_minutes=excluded.lead_minutes,
+           last_loaded_date=excluded.last_loaded_date",
+        params![cfg.auto_load as i64, cfg.changeover, cfg.lead_minutes as i64, cfg.last_loaded_date],
+    )?;
+    Ok(())
+}
+
+/// The date whose changeover falls within `lead_minutes` of now, if its log
+/// has not been loaded yet. A handoff more than an hour late (engine was down)
+/// is skipped: appending a whole day hours into it would do more harm than good.
+fn due_handoff(conn: &Connection, now_ms: u64) -> anyhow::Result<Option<(DayLogConfig, String)>> {
+    let cfg = db_load_config(conn)?;
+    let Some(minute) = parse_hhmm(&cfg.changeover) else { return Ok(None) };
+    if !cfg.auto_load {
+        return Ok(None);
+    }
+    let lead_ms = cfg.lead_minutes as u64 * 60_000;
+    let Some(date) = LocalHour::at(now_ms + lead_ms).map(|h| h.date()) else { return Ok(None) };
+    let Some(changeover_ms) = LocalHour::local_ms(&date, minute) else { return Ok(None) };
+    let due = now_ms + lead_ms >= changeover_ms && now_ms < changeover_ms + 3_600_000;
+    if !due || cfg.last_loaded_date.as_deref() == Some(date.as_str()) {
+        return Ok(None);
+    }
+    Ok(Some((cfg, date)))
+}
+
+pub(crate) async fn handoff_task(state: AppState) {
+    let mut tick = tokio::time::interval(std::time::Duration::from_secs(30));
+    loop {
+        tick.tick().await;
+        let now_ms = unix_ms_now();
+        let due = match with_db(move |conn| due_handoff(conn, now_ms)).await {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+        let Some((mut cfg, date)) = due else { continue };
+
+        // Record first so a failing load is not retried every 30 s; the reason
+        // is in the log, and an operator can still load it by hand.
+        cfg.last_loaded_date = Some(date.clone());
+        let to_save = cfg.clone();
+        if with_db(move |conn| db_save_config(conn, &to_save)).await.is_err() {
+            continue;
+        }
+        match api_daylog_load(State(state.clone()), Path(date.clone()), HeaderMap::new(), None).await {
+            Ok(_) => tracing::info!("day log {date} loaded at changeover"),
+            Err(StatusCode::NOT_FOUND) => tracing::warn!("changeover: no day log for {date}; leaving the queue to top-up"),
+            Err(code) => tracing::warn!("changeover: loading day log {date} failed ({code})"),
+        }
+    }
+}
+
+pub(crate) async fn api_daylog_config_get() -> Result<Json<DayLogConfig>, StatusCode> {
+    with_db(|conn| db_load_config(conn)).await.map(Json)
+}
+
+pub(crate) async fn api_daylog_config_set(Json(mut cfg): Json<DayLogConfig>) -> Result<Json<serde_json::Value>, StatusCode> {
+    cfg.changeover = cfg.changeover.trim().to_string();
+    if parse_hhmm(&cfg.changeover).is_none() || cfg.lead_minutes > 120 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let saved = with_db(move |conn| {
+        // `last_loaded_date` is engine state, not something clients set.
+        cfg.last_loaded_date = db_load_config(conn)?.last_loaded_date;
+        db_save_config(conn, &cfg)?;
+        Ok(cfg)
+    })
+    .await?;
+    Ok(Json(json!({"ok": true, "config": saved})))
+}
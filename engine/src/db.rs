@@ -0,0 +1,167 @@
+// --- Database worker -------------------------------------------------------------------
+//
+// Every persistence call used to open its own SQLite connection inside
+// `spawn_blocking`: an open, the PRAGMAs and a schema check per call, no
+// statement reuse, and concurrent writers contending for the file lock (a
+// queue persist racing a history insert could hit SQLITE_BUSY).
+//
+// Now one worker thread owns the only connection (WAL) and runs jobs sent
+// over a channel, one at a time:
+// - writes are serialized by construction,
+// - the connection's prepared-statement cache makes `prepare_cached`
+//   worthwhile on hot paths (history, queue persist, library lookups),
+// - `crate::db_init` runs once when the connection is opened; the calls left
+//   at the top of job bodies are then no-ops on this thread.
+//
+// `call` is the async entry point, `call_blocking` the one for code already
+// on a blocking thread (scans, top-up folder reads). A job that panics is
+// contained: its caller gets `DbError::Gone` and the worker carries on. If the
+// database cannot be opened, jobs fail with `DbError::Unavailable` and the
+// next job retries the open.
+//
+// `close` is for a database restored under the running engine: every later
+// job fails, so the old in-memory queue and settings cannot be written over
+// the restored file before the engine restarts.
+
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use rusqlite::Connection;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::db_path;
+
+/// Pending jobs before `call` waits for room.
+const QUEUE_DEPTH: usize = 256;
+/// Statements kept prepared on the shared connection.
+const STATEMENT_CACHE: usize = 128;
+
+type Job = Box<dyn FnOnce(Result<&mut Connection, String>) + Send>;
+
+#[derive(Debug)]
+pub(crate) enum DbError {
+    /// The database could not be opened (message from SQLite).
+    Unavailable(String),
+    /// The worker is gone or the job panicked.
+    Gone,
+}
+
+impl std::fmt::Display for DbError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DbError::Unavailable(e) => write!(f, "database unavailable: {e}"),
+            DbError::Gone => write!(f, "database worker failed"),
+        }
+    }
+}
+
+impl std::error::Error for DbError {}
+
+/// Set by `close`; never cleared.
+static CLOSED: AtomicBool = AtomicBool::new(false);
+
+/// Refuse every job after the current one until the process exits. Call it
+/// from inside the job that replaced the database.
+pub(crate) fn close() {
+    CLOSED.store(true, Ordering::Release);
+}
+
+thread_local! {
+    static WORKER_READY: Cell<bool> = const { Cell::new(false) };
+}
+
+/// True on the worker thread once its connection is initialized, so
+/// `crate::db_init` can skip work it has already done.
+pub(crate) fn initialized_here() -> bool {
+    WORKER_READY.with(|r| r.get())
+}
+
+fn open() -> Result<Connection, String> {
+    let conn = Connection::open(db_path()).map_err(|e| e.to_string())?;
+    conn.busy_timeout(Duration::from_secs(5)).map_err(|e| e.to_string())?;
+    conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE);
+    crate::db_init(&conn).map_err(|e| e.to_string())?;
+    Ok(conn)
+}
+
+fn worker(mut rx: mpsc::Receiver<Job>) {
+    let mut conn: Option<Connection> = None;
+    while let Some(job) = rx.blocking_recv() {
+        if CLOSED.load(Ordering::Acquire) {
+            job(Err("database closed for a restart".into()));
+            continue;
+        }
+        if conn.is_none() {
+            match open() {
+                Ok(c) => {
+                    WORKER_READY.with(|r| r.set(true));
+                    conn = Some(c);
+                }
+                Err(e) => {
+                    tracing::error!("database: cannot open {}: {e}", db_path());
+                    job(Err(e));
+                    continue;
+                }
+            }
+        }
+        if let Some(c) = conn.as_mut() {
+            let run = std::panic::AssertUnwindSafe(|| job(Ok(c)));
+            if std::panic::catch_unwind(run).is_err() {
+                tracing::error!("database: a job panicked");
+                // The connection may be mid-transaction; start afresh.
+                if !c.is_autocommit() {
+                    let _ = c.execute_batch("ROLLBACK");
+                }
+            }
+        }
+    }
+}
+
+fn sender() -> &'static mpsc::Sender<Job> {
+    static TX: OnceLock<mpsc::Sender<Job>> = OnceLock::new();
+    TX.get_or_init(|| {
+        let (tx, rx) = mpsc::channel(QUEUE_DEPTH);
+        std::thread::Builder::new()
+            .name("db".into())
+            .spawn(move || worker(rx))
+            .expect("spawn database worker");
+        tx
+    })
+}
+
+fn job<T, F>(f: F) -> (Job, oneshot::Receiver<Result<T, DbError>>)
+where
+    T: Send + 'static,
+    F: FnOnce(&mut Connection) -> T + Send + 'static,
+{
+    let (reply, rx) = oneshot::channel();
+    let job: Job = Box::new(move |conn| {
+        let _ = reply.send(conn.map(f).map_err(DbError::Unavailable));
+    });
+    (job, rx)
+}
+
+/// Run `f` on the database worker.
+pub(crate) async fn call<T, F>(f: F) -> Result<T, DbError>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut Connection) -> T + Send + 'static,
+{
+    let (job, rx) = job(f);
+    sender().send(job).await.map_err(|_| DbError::Gone)?;
+    rx.await.map_err(|_| DbError::Gone)?
+}
+
+/// `call` for code on a blocking thread. Never use it from async code (or
+/// from inside a job: the worker would wait on itself).
+pub(crate) fn call_blocking<T, F>(f: F) -> Result<T, DbError>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut Connection) -> T + Send + 'static,
+{
+    let (job, rx) = job(f);
+    sender().blocking_send(job).map_err(|_| DbError::Gone)?;
+    rx.blocking_recv().map_err(|_| DbError::Gone)?
+}
@@ -0,0 +1,162 @@
+// --- Audio pipeline diagnostics -------------------------------------------------------------
+//
+// "The stream stutters on this Pi" used to come with nothing to go on. The
+// playout writer moves 20 ms chunks: read from the decoder, wait for the
+// pacing tick, write to the encoder; Listen Live takes the same chunks off a
+// broadcast channel and Opus-encodes them. Any stage that takes longer than a
+// chunk, or a tick that fires late, is an audible gap somewhere.
+//
+// Each stage records its timings here, into fixed histograms of atomics (like
+// the meters, the writer never waits on a lock to record).
+// `GET /api/v1/admin/audio/diagnostics` reports, per stage, count, mean,
+// approximate p50/p99 (histogram bucket bounds), max, and how many took longer
+// than a chunk:
+// - `decoder_read`: waiting for the decoder's next chunk (a slow disk or CPU),
+// - `tick_late`: how late the pacing tick fired after its deadline,
+// - `encoder_write`: writing a chunk to the encoder (ffmpeg not keeping up),
+// - `pipeline`: decoder read returned to encoder write done,
+// - `opus_encode`: Listen Live's Opus encode per chunk,
+// plus `pcm_lag`: how often, and by how many chunks, a Listen Live session fell
+// behind the broadcast channel and dropped audio. `DELETE` on the same path
+// starts the counts afresh, e.g. before reproducing a problem.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use axum::Json;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::unix_ms_now;
+
+/// The writer's chunk length; a stage slower than this cannot keep up.
+const CHUNK: Duration = Duration::from_millis(20);
+
+/// Histogram bucket upper bounds, microseconds (the last catches the rest).
+const BOUNDS_US: [u64; 13] =
+    [100, 250, 500, 1_000, 2_500, 5_000, 10_000, 20_000, 40_000, 80_000, 160_000, 500_000, u64::MAX];
+
+pub(crate) struct Timing {
+    sum_us: AtomicU64,
+    max_us: AtomicU64,
+    over_chunk: AtomicU64,
+    buckets: [AtomicU64; BOUNDS_US.len()],
+}
+
+#[derive(Serialize)]
+struct TimingView {
+    count: u64,
+    mean_ms: f64,
+    p50_ms: f64,
+    p99_ms: f64,
+    max_ms: f64,
+    /// Longer than one 20 ms chunk.
+    over_chunk: u64,
+}
+
+fn ms(us: u64) -> f64 {
+    (us as f64 / 10.0).round() / 100.0
+}
+
+impl Timing {
+    const fn new() -> Self {
+        Timing {
+            sum_us: AtomicU64::new(0),
+            max_us: AtomicU64::new(0),
+            over_chunk: AtomicU64::new(0),
+            buckets: [const { AtomicU64::new(0) }; BOUNDS_US.len()],
+        }
+    }
+
+    pub(crate) fn record(&self, d: Duration) {
+        let us = d.as_micros().min(u64::MAX as u128) as u64;
+        self.sum_us.fetch_add(us, Ordering::Relaxed);
+        self.max_us.fetch_max(us, Ordering::Relaxed);
+        if d > CHUNK {
+            self.over_chunk.fetch_add(1, Ordering::Relaxed);
+        }
+        let i = BOUNDS_US.iter().position(|b| us <= *b).unwrap_or(BOUNDS_US.len() - 1);
+        self.buckets[i].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn reset(&self) {
+        for a in [&self.sum_us, &self.max_us, &self.over_chunk].into_iter().chain(&self.buckets) {
+            a.store(0, Ordering::Relaxed);
+        }
+    }
+
+    fn view(&self) -> TimingView {
+        let counts: Vec<u64> = self.buckets.iter().map(|b| b.load(Ordering::Relaxed)).collect();
+        let count: u64 = counts.iter().sum();
+        let max_us = self.max_us.load(Ordering::Relaxed);
+        TimingView {
+            count,
+            mean_ms: self.sum_us.load(Ordering::Relaxed).checked_div(count).map(ms).unwrap_or(0.0),
+            p50_ms: ms(quantile_us(&counts, 0.50, max_us)),
+            p99_ms: ms(quantile_us(&counts, 0.99, max_us)),
+            max_ms: ms(max_us),
+            over_chunk: self.over_chunk.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Upper bound of the bucket holding the `q` quantile, capped at the max.
+fn quantile_us(counts: &[u64], q: f64, max_us: u64) -> u64 {
+    let total: u64 = counts.iter().sum();
+    let rank = ((total as f64 * q).ceil() as u64).max(1);
+    let mut seen = 0;
+    for (i, c) in counts.iter().enumerate() {
+        seen += c;
+        if seen >= rank {
+            return BOUNDS_US[i].min(max_us);
+        }
+    }
+    max_us
+}
+
+pub(crate) static DECODER_READ: Timing = Timing::new();
+pub(crate) static TICK_LATE: Timing = Timing::new();
+pub(crate) static ENCODER_WRITE: Timing = Timing::new();
+pub(crate) static PIPELINE: Timing = Timing::new();
+pub(crate) static OPUS_ENCODE: Timing = Timing::new();
+
+static PCM_LAG_EVENTS: AtomicU64 = AtomicU64::new(0);
+static PCM_LAGGED_CHUNKS: AtomicU64 = AtomicU64::new(0);
+static SINCE_MS: AtomicU64 = AtomicU64::new(0);
+
+/// A Listen Live receiver fell behind by `chunks` and skipped them.
+pub(crate) fn note_pcm_lag(chunks: u64) {
+    PCM_LAG_EVENTS.fetch_add(1, Ordering::Relaxed);
+    PCM_LAGGED_CHUNKS.fetch_add(chunks, Ordering::Relaxed);
+}
+
+/// `GET /api/v1/admin/audio/diagnostics`
+pub(crate) async fn api_audio_diagnostics() -> Json<serde_json::Value> {
+    let since = SINCE_MS.load(Ordering::Relaxed);
+    Json(json!({
+        "ok": true,
+        // 0: since the engine started.
+        "since_ms": since,
+        "chunk_ms": CHUNK.as_millis() as u64,
+        "decoder_read": DECODER_READ.view(),
+        "tick_late": TICK_LATE.view(),
+        "encoder_write": ENCODER_WRITE.view(),
+        "pipeline": PIPELINE.view(),
+        "opus_encode": OPUS_ENCODE.view(),
+        "pcm_lag": {
+            "events": PCM_LAG_EVENTS.load(Ordering::Relaxed),
+            "chunks_dropped": PCM_LAGGED_CHUNKS.load(Ordering::Relaxed),
+        },
+    }))
+}
+
+/// `DELETE /api/v1/admin/audio/diagnostics`: start counting afresh.
+pub(crate) async fn api_audio_diagnostics_reset() -> Json<serde_json::Value> {
+    for t in [&DECODER_READ, &TICK_LATE, &ENCODER_WRITE, &PIPELINE, &OPUS_ENCODE] {
+        t.reset();
+    }
+    PCM_LAG_EVENTS.store(0, Ordering::Relaxed);
+    PCM_LAGGED_CHUNKS.store(0, Ordering::Relaxed);
+    SINCE_MS.store(unix_ms_now(), Ordering::Relaxed);
+    Json(json!({"ok": true}))
+}
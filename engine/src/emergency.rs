@@ -0,0 +1,361 @@
+// --- Emergency announcements ------------------------------------------------------------
+//
+// A priority interrupt for EAS alerts and other announcements that cannot wait
+// for the current song to end. `POST /api/v1/emergency/play` queues a cart or
+// an uploaded file; `writer_playout` notices within one 20 ms chunk, fades the
+// item on air out over `FADE_MS`, plays every pending announcement in turn,
+// then restarts the item's decoder where the fade ended and fades it back in.
+// The queue, its revision and now-playing are untouched, so the interrupted
+// item simply carries on. With nothing on air the announcement plays over
+// silence.
+//
+// Announcements go into the same PCM stream as the program, so they reach the
+// encoder, the meters and Listen Live alike. The overlay bus (cart wall,
+// voice-track handovers) is held while one plays. Each announcement is a row
+// of `emergency_log`: when it was asked for and played, what it interrupted
+// and at which position, and how it ended (`played`, `stopped`, `failed: …`).
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use axum::{
+    body::Bytes,
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    Json,
+};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use uuid::Uuid;
+
+use crate::{
+    analyze_pcm_s16le_stereo, diagnostics, meters, probe_media, resolve_cart_to_path, spawn_ffmpeg_decoder, storage,
+    unix_ms_now, AppState,
+};
+
+/// Largest file accepted by the upload form of `play`.
+pub(crate) const MAX_UPLOAD_BYTES: usize = 100 * 1024 * 1024;
+/// How long the program takes to fade out before, and back in after.
+pub(crate) const FADE_MS: u64 = 250;
+
+struct Announcement {
+    id: i64,
+    label: String,
+    path: String,
+    /// An upload, removed once played.
+    temp: bool,
+}
+
+static PENDING: Mutex<VecDeque<Announcement>> = Mutex::new(VecDeque::new());
+/// The announcement on air, by id and label.
+static ACTIVE: Mutex<Option<(i64, String)>> = Mutex::new(None);
+static STOP: AtomicBool = AtomicBool::new(false);
+
+fn pending_lock() -> std::sync::MutexGuard<'static, VecDeque<Announcement>> {
+    PENDING.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn active_lock() -> std::sync::MutexGuard<'static, Option<(i64, String)>> {
+    ACTIVE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Whether an announcement is waiting. Cheap; checked by the writer every chunk.
+pub(crate) fn pending() -> bool {
+    !pending_lock().is_empty()
+}
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS emergency_log (
+            id             INTEGER PRIMARY KEY AUTOINCREMENT,
+            requested_ms   INTEGER NOT NULL,
+            source         TEXT NOT NULL,
+            label          TEXT NOT NULL,
+            started_ms     INTEGER,
+            ended_ms       INTEGER,
+            interrupted    TEXT NOT NULL DEFAULT '',
+            resume_at_ms   INTEGER,
+            result         TEXT NOT NULL DEFAULT 'pending'
+        );
+        "#,
+    )
+}
+
+async fn with_db<T: Send + 'static>(
+    f: impl FnOnce(&Connection) -> anyhow::Result<T> + Send + 'static,
+) -> anyhow::Result<T> {
+    crate::db::call(move |conn| {
+        crate::db_init(conn)?;
+        f(conn)
+    })
+    .await?
+}
+
+/// Best-effort log update from the writer.
+fn log_update(sql: &'static str, params: impl rusqlite::Params + Send + 'static) {
+    tokio::spawn(async move {
+        let res = with_db(move |conn| {
+            conn.execute(sql, params)?;
+            Ok(())
+        })
+        .await;
+        if let Err(e) = res {
+            tracing::warn!("emergency: log update failed: {e}");
+        }
+    });
+}
+
+/// Play every pending announcement into the program stream. `interrupted` is
+/// the item on air and the position it resumes at, if any.
+pub(crate) async fn play_pending(
+    stdin: &mut tokio::process::ChildStdin,
+    pcm_tx: &tokio::sync::broadcast::Sender<Vec<u8>>,
+    interval: &mut tokio::time::Interval,
+    interrupted: Option<(String, u64)>,
+) -> std::io::Result<()> {
+    STOP.store(false, Ordering::Relaxed);
+    loop {
+        let Some(a) = pending_lock().pop_front() else { break };
+        *active_lock() = Some((a.id, a.label.clone()));
+        let (what, at_ms) = interrupted.clone().map_or((String::new(), None), |(w, at)| (w, Some(at)));
+        tracing::warn!(target: "audit", "emergency: {} on air ({}), interrupting {:?}", a.id, a.label, what);
+        log_update(
+            "UPDATE emergency_log SET started_ms = ?2, interrupted = ?3, resume_at_ms = ?4 WHERE id = ?1",
+            (a.id, unix_ms_now(), what, at_ms),
+        );
+
+        let played = play_one(stdin, pcm_tx, interval, &a.path).await;
+        let result = match &played {
+            Ok(true) => "played".to_string(),
+            Ok(false) => "stopped".to_string(),
+            Err(e) => format!("failed: {e}"),
+        };
+        tracing::info!(target: "audit", "emergency: {} {}", a.id, result);
+        log_update(
+            "UPDATE emergency_log SET ended_ms = ?2, result = ?3, resume_at_ms = ?4 WHERE id = ?1",
+            (a.id, unix_ms_now(), result, at_ms),
+        );
+        *active_lock() = None;
+        if a.temp {
+            let _ = tokio::fs::remove_file(&a.path).await;
+        }
+        // A failed write means the encoder is gone; the writer handles that.
+        if let Err(PlayError::Encoder(e)) = played {
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+enum PlayError {
+    Decoder(String),
+    Encoder(std::io::Error),
+}
+
+impl std::fmt::Display for PlayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlayError::Decoder(e) => write!(f, "decoder: {e}"),
+            PlayError::Encoder(e) => write!(f, "encoder: {e}"),
+        }
+    }
+}
+
+/// Decode `path` into the stream. `Ok(false)` when it was stopped.
+async fn play_one(
+    stdin: &mut tokio::process::ChildStdin,
+    pcm_tx: &tokio::sync::broadcast::Sender<Vec<u8>>,
+    interval: &mut tokio::time::Interval,
+    path: &str,
+) -> Result<bool, PlayError> {
+    let (mut child, mut stdout) =
+        spawn_ffmpeg_decoder(path, 0).await.map_err(|e| PlayError::Decoder(e.to_string()))?;
+    // 20 ms of s16le stereo at 48 kHz, as `writer_playout`.
+    let mut buf = vec![0u8; 960 * 4];
+    let mut last_meter = std::time::Instant::now();
+    let finished = loop {
+        if STOP.swap(false, Ordering::Relaxed) {
+            break false;
+        }
+        let n = stdout.read(&mut buf).await.map_err(|e| PlayError::Decoder(e.to_string()))?;
+        if n == 0 {
+            break true;
+        }
+        let _ = pcm_tx.send(buf[..n].to_vec());
+        if last_meter.elapsed() >= std::time::Duration::from_millis(33) {
+            last_meter = std::time::Instant::now();
+            meters::update(&analyze_pcm_s16le_stereo(&buf[..n]));
+        }
+        diagnostics::TICK_LATE.record(interval.tick().await.elapsed());
+        stdin.write_all(&buf[..n]).await.map_err(PlayError::Encoder)?;
+    };
+    let _ = child.kill().await;
+    Ok(finished)
+}
+
+// --- HTTP API --------------------------------------------------------------------------
+
+type ApiError = (StatusCode, Json<serde_json::Value>);
+
+fn api_error(status: StatusCode, msg: impl Into<String>) -> ApiError {
+    (status, Json(json!({"ok": false, "error": msg.into()})))
+}
+
+#[derive(Deserialize)]
+struct PlayReq {
+    cart: String,
+    #[serde(default)]
+    label: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct PlayQuery {
+    #[serde(default)]
+    label: Option<String>,
+}
+
+/// `POST /api/v1/emergency/play`: `{"cart": …}` as JSON, or the announcement
+/// itself as the body (any format ffmpeg reads; `?label=` names it).
+pub(crate) async fn api_emergency_play(
+    State(state): State<AppState>,
+    Query(q): Query<PlayQuery>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    if state.output.lock().await.encoder.is_none() {
+        return Err(api_error(StatusCode::CONFLICT, "the stream is not running"));
+    }
+    let is_json = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|ct| ct.starts_with("application/json"));
+
+    let (source, label, path, temp) = if is_json {
+        let req: PlayReq = serde_json::from_slice(&body).map_err(|e| api_error(StatusCode::BAD_REQUEST, e.to_string()))?;
+        let cart = req.cart.trim().to_string();
+        let path = resolve_cart_to_path(&cart)
+            .ok_or_else(|| api_error(StatusCode::UNPROCESSABLE_ENTITY, format!("cart {cart} not found")))?;
+        let label = req.label.or(q.label).unwrap_or_else(|| cart.clone());
+        (cart, label, path, false)
+    } else {
+        if body.is_empty() {
+            return Err(api_error(StatusCode::BAD_REQUEST, "give {\"cart\": …} as JSON, or upload the announcement"));
+        }
+        let dir = storage::data_dir().join("emergency");
+        let path = dir.join(format!("{}.upload", Uuid::new_v4().simple()));
+        let io = |e: std::io::Error| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+        tokio::fs::create_dir_all(&dir).await.map_err(io)?;
+        tokio::fs::write(&path, &body).await.map_err(io)?;
+        let label = q.label.unwrap_or_else(|| "Emergency announcement".into());
+        ("upload".to_string(), label, path.to_string_lossy().into_owned(), true)
+    };
+
+    let usable = match probe_media(&path).await {
+        Ok(p) if p.has_audio => Ok(()),
+        Ok(_) => Err(format!("{path}: no audio stream")),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = usable {
+        if temp {
+            let _ = tokio::fs::remove_file(&path).await;
+        }
+        return Err(api_error(StatusCode::UNPROCESSABLE_ENTITY, e));
+    }
+
+    let (src, lbl) = (source.clone(), label.clone());
+    let id = with_db(move |conn| {
+        conn.execute(
+            "INSERT INTO emergency_log (requested_ms, source, label) VALUES (?1, ?2, ?3)",
+            params![unix_ms_now(), src, lbl],
+        )?;
+        Ok(conn.last_insert_rowid())
+    })
+    .await
+    .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    tracing::warn!(target: "audit", "emergency: {id} requested ({source}: {label})");
+    let ahead = {
+        let mut pending = pending_lock();
+        pending.push_back(Announcement { id, label, path, temp });
+        pending.len() - 1 + active_lock().is_some() as usize
+    };
+    Ok(Json(json!({"ok": true, "id": id, "ahead": ahead})))
+}
+
+/// Cut the announcement on air short and drop the ones waiting. Returns
+/// whether one was on air, and how many were dropped.
+pub(crate) fn stop(reason: &'static str) -> (bool, usize) {
+    let dropped: Vec<Announcement> = pending_lock().drain(..).collect();
+    let stopping = active_lock().is_some();
+    STOP.store(stopping, Ordering::Relaxed);
+    for a in &dropped {
+        log_update("UPDATE emergency_log SET ended_ms = ?2, result = ?3 WHERE id = ?1", (a.id, unix_ms_now(), reason));
+        if a.temp {
+            let _ = std::fs::remove_file(&a.path);
+        }
+    }
+    if stopping || !dropped.is_empty() {
+        tracing::info!(target: "audit", "emergency: stop ({} dropped, {reason})", dropped.len());
+    }
+    (stopping, dropped.len())
+}
+
+/// `POST /api/v1/emergency/stop`: cut the announcement on air short and drop
+/// the ones waiting; the program resumes.
+pub(crate) async fn api_emergency_stop() -> Json<serde_json::Value> {
+    let (stopped, dropped) = stop("dropped");
+    Json(json!({"ok": true, "stopped": stopped, "dropped": dropped}))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct LogQuery {
+    #[serde(default)]
+    limit: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct LogRow {
+    id: i64,
+    requested_ms: u64,
+    source: String,
+    label: String,
+    started_ms: Option<u64>,
+    ended_ms: Option<u64>,
+    interrupted: String,
+    resume_at_ms: Option<u64>,
+    result: String,
+}
+
+/// `GET /api/v1/emergency?limit=50`: what is on air, what waits, and the log.
+pub(crate) async fn api_emergency_get(Query(q): Query<LogQuery>) -> Result<Json<serde_json::Value>, StatusCode> {
+    let limit = q.limit.unwrap_or(50).clamp(1, 1000);
+    let events = with_db(move |conn| {
+        let mut stmt = conn.prepare(
+            "SELECT id, requested_ms, source, label, started_ms, ended_ms, interrupted, resume_at_ms, result
+             FROM emergency_log ORDER BY id DESC LIMIT ?1",
+        )?;
+        let rows = stmt.query_map([limit], |row| {
+            Ok(LogRow {
+                id: row.get(0)?,
+                requested_ms: row.get(1)?,
+                source: row.get(2)?,
+                label: row.get(3)?,
+                started_ms: row.get(4)?,
+                ended_ms: row.get(5)?,
+                interrupted: row.get(6)?,
+                resume_at_ms: row.get(7)?,
+                result: row.get(8)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let active = active_lock().clone().map(|(id, label)| json!({"id": id, "label": label}));
+    let pending: Vec<_> = pending_lock().iter().map(|a| json!({"id": a.id, "label": a.label})).collect();
+    Ok(Json(json!({"ok": true, "active": active, "pending": pending, "events": events})))
+}
@@ -0,0 +1,283 @@
+// --- Engine ---------------------------------------------------------------------------------
+//
+// What `main` used to do inline: load the queue and configuration, build the
+// shared state, start the background tasks (playout, scheduler, ingest,
+// alerts, ...), then serve HTTP until SIGTERM. `EngineBuilder::start` does
+// everything up to serving, so a host application can run the engine
+// headless and drive it through `Engine`'s methods, mount `Engine::router`
+// in its own server, or call `Engine::serve` as the binary does.
+//
+// Settings not on the builder come from the environment and the config file
+// as before (see reload.rs); the builder's win where both exist.
+
+use std::sync::Arc;
+
+use sysinfo::System;
+use tracing::warn;
+
+use crate::{
+    advance_to_next, aes67, alerts, announce, api, bots, callers, carts, clocks, daylog, events, gpio, history, ingest,
+    library, maintenance, metapush, meters, mpd, mqtt, producers, rds, recorder, reload, remote, resume, schedule,
+    selfcheck, serve, shutdown, snapshot, standby, stl, supervisor, surfaces, tags, transitions, unix_ms_now,
+    voicetrack, AppState, LogItem, NowPlaying, OutputRuntime, PlayoutState, TopUpStats, VuLevels,
+};
+
+/// Configures and starts an [`Engine`].
+pub struct EngineBuilder {
+    version: String,
+    database: Option<String>,
+    bind: Option<String>,
+    autostart_output: bool,
+}
+
+impl EngineBuilder {
+    /// Version reported by the API and used by self-update (default: this crate's).
+    pub fn version(mut self, version: impl Into<String>) -> Self {
+        self.version = version.into();
+        self
+    }
+
+    /// SQLite database file (default: `STUDIOCOMMAND_DB_PATH`, else
+    /// `/opt/studiocommand/shared/studiocommand.db`).
+    pub fn database(mut self, path: impl Into<String>) -> Self {
+        self.database = Some(path.into());
+        self
+    }
+
+    /// Listen addresses for [`Engine::serve`], in `STUDIOCOMMAND_BIND` form
+    /// (`127.0.0.1:3000,unix:/run/studiocommand.sock`).
+    pub fn bind(mut self, spec: impl Into<String>) -> Self {
+        self.bind = Some(spec.into());
+        self
+    }
+
+    /// Start the Icecast output at startup when its config says enabled (default: true).
+    pub fn autostart_output(mut self, on: bool) -> Self {
+        self.autostart_output = on;
+        self
+    }
+
+    /// Load the state and start playout and the background tasks.
+    pub async fn start(self) -> anyhow::Result<Engine> {
+        if let Some(path) = self.database {
+            crate::persistence::set_db_path(path);
+        }
+        crate::update::startup(&self.version);
+
+        let sys = System::new_all();
+
+        // The queue from SQLite if present; otherwise a demo queue.
+        let log = crate::load_queue_from_db_or_demo().await;
+        let output_cfg = crate::load_output_config_from_db_or_default().await;
+        let topup_cfg = crate::load_topup_config_from_db_or_default().await;
+
+        // Ensure the current queue is persisted so restarts are deterministic.
+        // This is cheap (single transaction) and makes initial installs predictable.
+        crate::persist_queue(log.clone()).await;
+
+        let playout = PlayoutState {
+            now: NowPlaying {
+                title: "Neutron Dance".into(),
+                artist: "Pointer Sisters".into(),
+                dur: 242,
+                pos: 0,
+                pos_f: 0.0,
+                art: None,
+                intro: None,
+                intro_remaining_sec: None,
+            },
+            log,
+            track_started_at: None,
+            queue_rev: 1,
+            recent: history::load_recent().await,
+            aux: crate::load_aux_queues_from_db().await,
+            fade_out: None,
+        };
+
+        let (snapshot_tx, snapshot_rx) = snapshot::channel(&playout);
+
+        // WebRTC Listen Live needs access to the real PCM stream.
+        // We expose it internally as a broadcast channel so each peer can subscribe.
+        let (pcm_tx, _pcm_rx) = tokio::sync::broadcast::channel::<Vec<u8>>(64);
+
+        let state = AppState {
+            version: self.version,
+            sys: Arc::new(tokio::sync::Mutex::new(sys)),
+            playout: Arc::new(tokio::sync::RwLock::new(playout)),
+            topup: Arc::new(tokio::sync::Mutex::new(topup_cfg)),
+            topup_stats: Arc::new(tokio::sync::Mutex::new(TopUpStats::default())),
+            output: Arc::new(tokio::sync::Mutex::new(OutputRuntime::new(output_cfg))),
+            pcm_tx,
+            webrtc: Arc::new(tokio::sync::Mutex::new(None)),
+            library_scan: Arc::new(tokio::sync::Mutex::new(library::LibraryScanStatus::default())),
+            ingest: Arc::new(tokio::sync::Mutex::new(ingest::load_ingest_config_from_db_or_default().await)),
+            ingest_runtime: Arc::new(tokio::sync::Mutex::new(ingest::IngestRuntime::default())),
+            snapshot: snapshot_rx,
+        };
+        tokio::spawn(snapshot::refresh_task(state.playout.clone(), snapshot_tx));
+
+        // Settings from the config file win over the database ones, but only where
+        // the file changed since it was last applied (see reload.rs).
+        reload::apply_at_startup(&state).await;
+        tokio::spawn(reload::sighup_task(state.clone()));
+
+        // Refresh the media library index in the background. Scans are incremental,
+        // so this is cheap when nothing changed since the last run.
+        carts::load_index().await;
+        tags::load().await;
+        transitions::load().await;
+        remote::load().await;
+        voicetrack::load_index().await;
+        producers::load().await;
+        tokio::spawn(library::run_scan(state.library_scan.clone()));
+        tokio::spawn(history::close_interrupted(unix_ms_now()));
+        tokio::spawn(recorder::close_interrupted());
+        // Before output starts: the writer asks for it when it starts the first item.
+        resume::load().await;
+        tokio::spawn(ingest::ingest_task(state.ingest.clone(), state.ingest_runtime.clone()));
+        tokio::spawn(clocks::scheduler_task(state.playout.clone()));
+        tokio::spawn(events::events_task(state.playout.clone()));
+        tokio::spawn(remote::prefetch_task(state.playout.clone()));
+        tokio::spawn(schedule::schedule_task(state.clone()));
+        tokio::spawn(daylog::handoff_task(state.clone()));
+        tokio::spawn(announce::announce_task(state.playout.clone()));
+        tokio::spawn(metapush::push_task());
+        tokio::spawn(rds::rds_task());
+        tokio::spawn(aes67::aes67_task(state.clone()));
+        tokio::spawn(stl::stl_task(state.clone()));
+        tokio::spawn(mqtt::mqtt_task(state.clone()));
+        tokio::spawn(bots::telegram_task(state.clone()));
+        tokio::spawn(maintenance::maintenance_task());
+        tokio::spawn(alerts::alerts_task(state.clone()));
+        tokio::spawn(supervisor::restart_task(state.clone()));
+        tokio::spawn(standby::standby_task(state.clone()));
+        tokio::spawn(mpd::mpd_task(state.clone()));
+        tokio::spawn(gpio::gpio_task(state.clone()));
+        tokio::spawn(surfaces::midi_task(state.clone()));
+        tokio::spawn(callers::callers_task());
+
+        // Validate the environment before output starts (see selfcheck.rs).
+        selfcheck::run(&state).await;
+
+        let engine = Engine { state, bind: self.bind };
+
+        // Optional: auto-start streaming output if config says enabled.
+        // (If ffmpeg isn't installed or creds are wrong, status will surface the error.)
+        // A hot standby stays off the air until it takes over (see standby.rs).
+        let standby = standby::primary_url().is_some();
+        if self.autostart_output && !standby && engine.state.output.lock().await.config.enabled {
+            let engine = engine.clone();
+            tokio::spawn(async move {
+                let _ = engine.start_output().await;
+            });
+        }
+
+        Ok(engine)
+    }
+}
+
+/// A running engine. Clones share it.
+#[derive(Clone)]
+pub struct Engine {
+    state: AppState,
+    bind: Option<String>,
+}
+
+impl Engine {
+    pub fn builder() -> EngineBuilder {
+        EngineBuilder {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            database: None,
+            bind: None,
+            autostart_output: true,
+        }
+    }
+
+    /// The HTTP API (and the browser UI, when `STUDIOCOMMAND_WEB_DIR` is set).
+    pub fn router(&self) -> axum::Router {
+        api::build_router(self.state.clone())
+    }
+
+    /// Serve the API on the configured addresses until SIGTERM or Ctrl-C,
+    /// then take the station off the air (see shutdown.rs).
+    pub async fn serve(self) -> anyhow::Result<()> {
+        // Loopback by default; put Nginx/Caddy in front for LAN/Internet, or let
+        // the engine terminate TLS itself. Several addresses and Unix sockets are
+        // allowed (see serve.rs).
+        let bind = self.bind.clone().unwrap_or_else(reload::bind_addr);
+        let binds = serve::parse_binds(&bind)?;
+        let app = self.router();
+        serve::serve(binds, app, async move {
+            shutdown_signal().await;
+            // Take the station off the air in order while the API still answers.
+            self.shutdown().await;
+        })
+        .await
+    }
+
+    /// Fade out, flush the encoder and save the resume position.
+    pub async fn shutdown(&self) {
+        shutdown::run(&self.state).await;
+    }
+
+    pub fn now_playing(&self) -> NowPlaying {
+        self.state.snapshot.borrow().now.clone()
+    }
+
+    /// The queue, playing item first.
+    pub async fn queue(&self) -> Vec<LogItem> {
+        self.state.playout.read().await.log.clone()
+    }
+
+    pub fn meters(&self) -> VuLevels {
+        meters::get()
+    }
+
+    /// Advance to the next item, as `POST /api/v1/transport/skip`.
+    pub async fn skip(&self) {
+        advance_to_next(&mut *self.state.playout.write().await, Some("skipped"));
+    }
+
+    /// Start the Icecast output with its stored config.
+    pub async fn start_output(&self) -> anyhow::Result<()> {
+        let s = &self.state;
+        let started = crate::output_start_internal(
+            s.output.clone(),
+            s.playout.clone(),
+            s.topup.clone(),
+            s.topup_stats.clone(),
+            s.pcm_tx.clone(),
+        )
+        .await;
+        if started.is_ok() {
+            return Ok(());
+        }
+        let err = s.output.lock().await.status.last_error.clone();
+        Err(anyhow::anyhow!(err.unwrap_or_else(|| "output did not start".into())))
+    }
+
+    pub async fn stop_output(&self) {
+        crate::output_stop_internal(self.state.output.clone()).await;
+    }
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async { tokio::signal::ctrl_c().await.ok(); };
+
+    #[cfg(unix)]
+    let term = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut sigterm = signal(SignalKind::terminate()).expect("sigterm handler");
+        sigterm.recv().await;
+    };
+
+    #[cfg(not(unix))]
+    let term = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = term => {},
+    }
+
+    warn!("Shutdown signal received.");
+}
@@ -0,0 +1,125 @@
+// --- Engine event log ------------------------------------------------------------------
+//
+// "What did the automation do overnight?" used to mean grepping journald for
+// the right mix of `playout start`, `top-up` and `encoder restarting` lines.
+// The events that matter to an operator are also written to `engine_events`,
+// one row each, as a timeline:
+// - `track_started`, `track_ended` (with how: ended, stopped, faded,
+//   errored) and `track_skipped` (gave up after repeated failures),
+// - `topup` when top-up appended something,
+// - `output` for the encoder starting, exiting, restarting or giving up,
+// - `fallback` when listeners are moved to or from the fallback mount,
+// - `hard_time` when a hard-timed event cuts the playing item short,
+// - `remote` when a remote item could not be fetched (remote.rs).
+//
+// Tracing keeps the detail (and everything else); this is the short version.
+// Each event has a one-line `message` and, where useful, structured `data`.
+// The table is rolling: only the newest `KEEP_ROWS` entries are kept.
+
+use axum::{extract::Query, http::StatusCode, Json};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::unix_ms_now;
+
+/// A few weeks of a busy station.
+const KEEP_ROWS: i64 = 50_000;
+
+/// Migration 10: the event table.
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS engine_events (
+            id       INTEGER PRIMARY KEY AUTOINCREMENT,
+            at_ms    INTEGER NOT NULL,
+            kind     TEXT NOT NULL,
+            message  TEXT NOT NULL,
+            data     TEXT
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_engine_events_at ON engine_events(at_ms);
+        "#,
+    )
+}
+
+#[derive(Serialize)]
+pub(crate) struct EngineEvent {
+    id: i64,
+    at_ms: u64,
+    kind: String,
+    message: String,
+    #[serde(skip_serializing_if = "Value::is_null")]
+    data: Value,
+}
+
+/// Record an event in the background; callers never wait on this. `data` may
+/// be `Value::Null`.
+pub(crate) fn record(kind: &'static str, message: impl Into<String>, data: Value) {
+    let at_ms = unix_ms_now();
+    let message = message.into();
+    let data = (!data.is_null()).then(|| data.to_string());
+    tokio::spawn(async move {
+        let res = crate::db::call(move |conn| -> anyhow::Result<()> {
+            crate::db_init(conn)?;
+            conn.execute(
+                "INSERT INTO engine_events (at_ms, kind, message, data) VALUES (?1, ?2, ?3, ?4)",
+                params![at_ms as i64, kind, message, data],
+            )?;
+            let id = conn.last_insert_rowid();
+            conn.execute("DELETE FROM engine_events WHERE id <= ?1", params![id - KEEP_ROWS])?;
+            Ok(())
+        })
+        .await;
+        if let Ok(Err(e)) = res {
+            tracing::warn!("event log: failed to record: {e}");
+        }
+    });
+}
+
+fn event_from_row(row: &rusqlite::Row) -> rusqlite::Result<EngineEvent> {
+    let data: Option<String> = row.get(4)?;
+    Ok(EngineEvent {
+        id: row.get(0)?,
+        at_ms: row.get::<_, i64>(1)? as u64,
+        kind: row.get(2)?,
+        message: row.get(3)?,
+        data: data.and_then(|d| serde_json::from_str(&d).ok()).unwrap_or(Value::Null),
+    })
+}
+
+#[derive(Deserialize)]
+pub(crate) struct EventsQuery {
+    /// Unix millis; events at or after it.
+    since: Option<u64>,
+    /// Only this kind (`track_started`, `topup`, ...).
+    kind: Option<String>,
+    limit: Option<u32>,
+}
+
+/// `GET /api/v1/engine/events?since=&kind=&limit=`, oldest first. With
+/// `since`, the first `limit` events from then on; without, the latest `limit`.
+pub(crate) async fn api_engine_events(Query(q): Query<EventsQuery>) -> Result<Json<Vec<EngineEvent>>, StatusCode> {
+    let limit = q.limit.unwrap_or(200).clamp(1, 5000);
+    crate::db::call(move |conn| -> anyhow::Result<Vec<EngineEvent>> {
+        crate::db_init(conn)?;
+        let sql = if q.since.is_some() {
+            "SELECT id, at_ms, kind, message, data FROM engine_events
+             WHERE at_ms >= ?1 AND (?2 IS NULL OR kind = ?2)
+             ORDER BY at_ms, id LIMIT ?3"
+        } else {
+            "SELECT * FROM (
+                 SELECT id, at_ms, kind, message, data FROM engine_events
+                 WHERE at_ms >= ?1 AND (?2 IS NULL OR kind = ?2)
+                 ORDER BY at_ms DESC, id DESC LIMIT ?3
+             ) ORDER BY at_ms, id"
+        };
+        let mut stmt = conn.prepare(sql)?;
+        let rows = stmt.query_map(params![q.since.unwrap_or(0) as i64, q.kind, limit], event_from_row)?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map(Json)
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
@@ -0,0 +1,352 @@
+// --- Scheduled events --------------------------------------------------------------
+//
+// Audio that must air at a time of day, e.g. the legal ID at the top of the
+// hour: a cron spec (see `cron.rs`), a cart, and a hard/soft flag.
+//
+// A few minutes ahead of each occurrence the event's cart is inserted into the
+// queue, locked, at the position estimated to be airing at that time:
+// - soft events simply wait there and play when the item before them ends,
+// - hard events additionally start exactly on time: shortly before the event,
+//   anything queued in front of it is moved behind it and the playing item is
+//   faded out (`PlayoutState::fade_out`) so the event starts on the minute.
+//
+// The occurrence is recorded in `last_fired_ms` when the item is inserted, so a
+// restart does not insert it twice. Occurrences missed while the engine was
+// down are skipped, not played late.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::{extract::Path, http::StatusCode, Json};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::cron::CronSpec;
+use crate::import::{self, ImportEntry};
+use crate::{
+    bump_queue_rev, eventlog, normalize_log_state, persist_queue, unix_ms_now, LogItem, PlayoutState,
+};
+
+/// How long before the event its item is put into the queue.
+const INSERT_LEAD_MS: u64 = 5 * 60_000;
+/// Length of the fade that makes room for a hard event.
+pub(crate) const HARD_FADE_MS: u64 = 2_000;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct ScheduledEvent {
+    #[serde(default)]
+    id: i64,
+    name: String,
+    /// Cron spec, local time.
+    spec: String,
+    cart: String,
+    /// Start exactly on time (fading the playing item) instead of after it.
+    #[serde(default)]
+    hard: bool,
+    #[serde(default = "default_true")]
+    enabled: bool,
+    #[serde(default)]
+    last_fired_ms: Option<u64>,
+    /// Next occurrence, computed on read.
+    #[serde(default)]
+    next_ms: Option<u64>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS scheduled_events (
+            id             INTEGER PRIMARY KEY AUTOINCREMENT,
+            name           TEXT NOT NULL,
+            spec           TEXT NOT NULL,
+            cart           TEXT NOT NULL,
+            hard           INTEGER NOT NULL DEFAULT 0,
+            enabled        INTEGER NOT NULL DEFAULT 1,
+            last_fired_ms  INTEGER
+        );
+        "#,
+    )
+}
+
+fn db_list(conn: &Connection) -> anyhow::Result<Vec<ScheduledEvent>> {
+    crate::db_init(conn)?;
+    let mut stmt =
+        conn.prepare("SELECT id, name, spec, cart, hard, enabled, last_fired_ms FROM scheduled_events ORDER BY id")?;
+    let rows = stmt.query_map([], |row| {
+        Ok(ScheduledEvent {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            spec: row.get(2)?,
+            cart: row.get(3)?,
+            hard: row.get::<_, i64>(4)? != 0,
+            enabled: row.get::<_, i64>(5)? != 0,
+            last_fired_ms: row.get::<_, Option<i64>>(6)?.map(|v| v as u64),
+            next_ms: None,
+        })
+    })?;
+    Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+}
+
+fn db_mark_fired(conn: &Connection, id: i64, at_ms: u64) -> anyhow::Result<()> {
+    crate::db_init(conn)?;
+    conn.execute("UPDATE scheduled_events SET last_fired_ms = ?2 WHERE id = ?1", params![id, at_ms as i64])?;
+    Ok(())
+}
+
+async fn with_db<T: Send + 'static>(
+    f: impl FnOnce(&Connection) -> anyhow::Result<T> + Send + 'static,
+) -> anyhow::Result<T> {
+    crate::db::call(move |conn| f(conn)).await?
+}
+
+/// Next occurrence that has not been handled yet.
+fn next_occurrence(ev: &ScheduledEvent, now_ms: u64) -> Option<u64> {
+    let spec = CronSpec::parse(&ev.spec).ok()?;
+    // Never go back before "now": missed occurrences are skipped.
+    spec.next_after(ev.last_fired_ms.unwrap_or(0).max(now_ms.saturating_sub(60_000)))
+}
+
+// --- Engine -------------------------------------------------------------------------
+
+/// An inserted event waiting for its hard start.
+struct Pending {
+    at_ms: u64,
+    item_id: Uuid,
+}
+
+pub(crate) async fn events_task(playout: Arc<tokio::sync::RwLock<PlayoutState>>) {
+    let mut tick = tokio::time::interval(std::time::Duration::from_millis(250));
+    let mut events: Vec<ScheduledEvent> = Vec::new();
+    let mut loaded_at: Option<std::time::Instant> = None;
+    let mut pending: HashMap<i64, Pending> = HashMap::new();
+
+    loop {
+        tick.tick().await;
+        // Re-read the table now and then so API edits apply without a restart.
+        if loaded_at.map(|t| t.elapsed().as_secs() >= 15).unwrap_or(true) {
+            match with_db(db_list).await {
+                Ok(v) => events = v,
+                Err(e) => tracing::warn!("scheduled events: {e}"),
+            }
+            loaded_at = Some(std::time::Instant::now());
+        }
+
+        let now_ms = unix_ms_now();
+        for ev in events.iter_mut().filter(|e| e.enabled) {
+            let Some(at_ms) = next_occurrence(ev, now_ms) else { continue };
+            if now_ms + INSERT_LEAD_MS < at_ms {
+                continue;
+            }
+            ev.last_fired_ms = Some(at_ms);
+            let id = ev.id;
+            if let Err(e) = with_db(move |conn| db_mark_fired(conn, id, at_ms)).await {
+                tracing::warn!("scheduled event {}: failed to record: {e}", ev.name);
+            }
+            match insert_event(&playout, ev, at_ms).await {
+                Ok(item_id) if ev.hard => {
+                    pending.insert(ev.id, Pending { at_ms, item_id });
+                }
+                Ok(_) => {}
+                Err(e) => tracing::warn!("scheduled event {}: {e}", ev.name),
+            }
+        }
+
+        // Hard starts: make the event next and fade whatever is playing.
+        let due: Vec<i64> =
+            pending.iter().filter(|(_, p)| now_ms + HARD_FADE_MS >= p.at_ms).map(|(id, _)| *id).collect();
+        for id in due {
+            let Some(ev) = pending.remove(&id) else { continue };
+            hard_start(&playout, ev.item_id).await;
+        }
+    }
+}
+
+/// Insert the event's cart, locked, where the queue is expected to be at `at_ms`.
+async fn insert_event(
+    playout: &Arc<tokio::sync::RwLock<PlayoutState>>,
+    ev: &ScheduledEvent,
+    at_ms: u64,
+) -> Result<Uuid, String> {
+    let entry = ImportEntry { reference: ev.cart.clone(), tag: Some("EVT".into()), ..Default::default() };
+    let mut item = import::resolve_entry(&entry, None).await.map_err(|e| format!("cart {}: {e}", ev.cart))?;
+    item.tag = "EVT".into();
+    if item.title.is_empty() {
+        item.title = ev.name.clone();
+    }
+    item.locked = true;
+    let mut item = item.into_log_item("queued");
+    item.hard_start_ms = ev.hard.then_some(at_ms);
+    let item_id = item.id;
+    let pos = insert_at(playout, vec![item], at_ms).await;
+    tracing::info!("scheduled event {} inserted at position {pos} for {}", ev.name, crate::fmt_local_hhmmss(at_ms));
+    Ok(item_id)
+}
+
+/// Where an item meant to air at `at_ms` goes: after the item estimated to be
+/// playing then, never before the playing item. `log` must have fresh estimates.
+pub(crate) fn insert_position(log: &[LogItem], at_ms: u64) -> usize {
+    let pos = log.iter().position(|it| it.start_ms.map(|s| s >= at_ms).unwrap_or(false)).unwrap_or(log.len());
+    if log.is_empty() {
+        0
+    } else {
+        pos.max(1)
+    }
+}
+
+/// Insert `items` (in order) where the queue is expected to be at `at_ms`.
+/// Returns the position of the first one.
+pub(crate) async fn insert_at(
+    playout: &Arc<tokio::sync::RwLock<PlayoutState>>,
+    items: Vec<LogItem>,
+    at_ms: u64,
+) -> usize {
+    let mut guard = playout.write().await;
+    let p = &mut *guard;
+    // Fresh estimates: the stored ones are only as new as the last queue edit.
+    crate::estimate_start_times(&mut p.log, &p.now, unix_ms_now());
+    let pos = insert_position(&p.log, at_ms);
+    p.log.splice(pos..pos, items);
+    normalize_log_state(p);
+    bump_queue_rev(p);
+    persist_queue(p.log.clone()).await;
+    pos
+}
+
+async fn hard_start(playout: &Arc<tokio::sync::RwLock<PlayoutState>>, item_id: Uuid) {
+    let mut p = playout.write().await;
+    let Some(idx) = p.log.iter().position(|it| it.id == item_id) else {
+        // Removed by an operator; nothing to do.
+        return;
+    };
+    if idx == 0 {
+        return;
+    }
+    if idx > 1 {
+        let item = p.log.remove(idx);
+        p.log.insert(1, item);
+        normalize_log_state(&mut p);
+        bump_queue_rev(&mut p);
+        persist_queue(p.log.clone()).await;
+    }
+    p.fade_out = p.log.first().map(|it| it.id);
+    if let Some(playing) = p.log.first() {
+        let left_s = (p.now.dur as f64 - p.now.pos_f).max(0.0).round() as u64;
+        eventlog::record(
+            "hard_time",
+            format!("hard start: fading {} - {} with {left_s} s left", playing.artist, playing.title),
+            json!({"item_id": playing.id, "trimmed_s": left_s}),
+        );
+    }
+}
+
+/// An occurrence inside a preview window (see `preview.rs`).
+pub(crate) struct Upcoming {
+    pub(crate) at_ms: u64,
+    pub(crate) name: String,
+    pub(crate) cart: String,
+    pub(crate) hard: bool,
+}
+
+/// Enabled event occurrences not yet inserted, from `now_ms` until `until_ms`.
+pub(crate) async fn upcoming(now_ms: u64, until_ms: u64) -> anyhow::Result<Vec<Upcoming>> {
+    let events = with_db(db_list).await?;
+    let mut out = Vec::new();
+    for ev in events.iter().filter(|e| e.enabled) {
+        let Ok(spec) = CronSpec::parse(&ev.spec) else { continue };
+        let mut next = next_occurrence(ev, now_ms);
+        while let Some(at_ms) = next.filter(|t| *t < until_ms) {
+            out.push(Upcoming { at_ms, name: ev.name.clone(), cart: ev.cart.clone(), hard: ev.hard });
+            next = spec.next_after(at_ms);
+        }
+    }
+    out.sort_by_key(|u| u.at_ms);
+    Ok(out)
+}
+
+// --- HTTP API -------------------------------------------------------------------------
+
+pub(crate) async fn api_events_list() -> Result<Json<Vec<ScheduledEvent>>, StatusCode> {
+    let mut events = with_db(db_list).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let now_ms = unix_ms_now();
+    for ev in events.iter_mut() {
+        ev.next_ms = if ev.enabled { next_occurrence(ev, now_ms) } else { None };
+    }
+    Ok(Json(events))
+}
+
+fn validate(ev: &ScheduledEvent) -> Result<(), (StatusCode, Json<serde_json::Value>)> {
+    let bad = |msg: String| Err((StatusCode::BAD_REQUEST, Json(json!({"ok": false, "error": msg}))));
+    if ev.name.trim().is_empty() || ev.cart.trim().is_empty() {
+        return bad("name and cart are required".into());
+    }
+    if let Err(e) = CronSpec::parse(&ev.spec) {
+        return bad(format!("spec: {e}"));
+    }
+    Ok(())
+}
+
+pub(crate) async fn api_event_create(
+    Json(ev): Json<ScheduledEvent>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    validate(&ev)?;
+    let id = with_db(move |conn| {
+        crate::db_init(conn)?;
+        conn.execute(
+            "INSERT INTO scheduled_events (name, spec, cart, hard, enabled) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![ev.name.trim(), ev.spec.trim(), ev.cart.trim(), ev.hard as i64, ev.enabled as i64],
+        )?;
+        Ok(conn.last_insert_rowid())
+    })
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"ok": false}))))?;
+    Ok(Json(json!({"ok": true, "id": id})))
+}
+
+pub(crate) async fn api_event_put(
+    Path(id): Path<i64>,
+    Json(ev): Json<ScheduledEvent>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    validate(&ev)?;
+    let updated = with_db(move |conn| {
+        crate::db_init(conn)?;
+        // A changed spec starts afresh; `last_fired_ms` only guards the old schedule.
+        let old_spec: Option<String> = conn
+            .query_row("SELECT spec FROM scheduled_events WHERE id = ?1", params![id], |row| row.get(0))
+            .optional()?;
+        let Some(old_spec) = old_spec else { return Ok(false) };
+        let reset = old_spec != ev.spec.trim();
+        conn.execute(
+            "UPDATE scheduled_events
+             SET name = ?2, spec = ?3, cart = ?4, hard = ?5, enabled = ?6,
+                 last_fired_ms = CASE WHEN ?7 THEN NULL ELSE last_fired_ms END
+             WHERE id = ?1",
+            params![id, ev.name.trim(), ev.spec.trim(), ev.cart.trim(), ev.hard as i64, ev.enabled as i64, reset],
+        )?;
+        Ok(true)
+    })
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"ok": false}))))?;
+    if !updated {
+        return Err((StatusCode::NOT_FOUND, Json(json!({"ok": false}))));
+    }
+    Ok(Json(json!({"ok": true})))
+}
+
+pub(crate) async fn api_event_delete(Path(id): Path<i64>) -> Result<Json<serde_json::Value>, StatusCode> {
+    let n = with_db(move |conn| {
+        crate::db_init(conn)?;
+        Ok(conn.execute("DELETE FROM scheduled_events WHERE id = ?1", params![id])?)
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if n == 0 {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(Json(json!({"ok": true})))
+}
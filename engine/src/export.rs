@@ -0,0 +1,91 @@
+// --- Queue / history export -------------------------------------------------------
+//
+// `GET /api/v1/queue/export?format=m3u|csv` and `GET /api/v1/history/export?...`
+// let operators back up a hand-built log or feed what aired into external
+// reporting tools. M3U is for re-importing (here or elsewhere); CSV is for
+// spreadsheets and uses the same column names the CSV log importer defaults
+// to, so an exported queue can be imported as a day log unchanged.
+
+use axum::{
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+
+use crate::{estimate_start_times, parse_dur_to_sec, resolve_cart_to_path, unix_ms_now, AppState, LogItem};
+
+#[derive(Deserialize)]
+pub(crate) struct ExportQuery {
+    format: Option<String>,
+}
+
+/// Quote a CSV field when it needs it (RFC 4180).
+pub(crate) fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn to_m3u(items: &[LogItem]) -> String {
+    let mut out = String::from("#EXTM3U\n");
+    for it in items {
+        // Cart numbers mean nothing outside this station; write the file.
+        let location = resolve_cart_to_path(&it.cart).unwrap_or_else(|| it.cart.clone());
+        if location.trim().is_empty() {
+            continue;
+        }
+        let display = if it.artist.is_empty() { it.title.clone() } else { format!("{} - {}", it.artist, it.title) };
+        out.push_str(&format!("#EXTINF:{},{}\n{}\n", parse_dur_to_sec(&it.dur), display, location));
+    }
+    out
+}
+
+fn to_csv(items: &[LogItem]) -> String {
+    let mut out = String::from("Time,Tag,Cart,Title,Artist,Length,State\n");
+    for it in items {
+        let row = [it.time.as_str(), &it.tag, &it.cart, &it.title, &it.artist, &it.dur, &it.state];
+        out.push_str(&row.iter().map(|f| csv_field(f)).collect::<Vec<_>>().join(","));
+        out.push('\n');
+    }
+    out
+}
+
+fn export_response(items: &[LogItem], format: Option<&str>, name: &str) -> Result<Response, StatusCode> {
+    let (body, content_type, ext) = match format.unwrap_or("m3u") {
+        "m3u" | "m3u8" => (to_m3u(items), "audio/x-mpegurl; charset=utf-8", "m3u8"),
+        "csv" => (to_csv(items), "text/csv; charset=utf-8", "csv"),
+        _ => return Err(StatusCode::BAD_REQUEST),
+    };
+    let disposition = format!("attachment; filename=\"{name}.{ext}\"");
+    Ok((
+        [(header::CONTENT_TYPE, content_type.to_string()), (header::CONTENT_DISPOSITION, disposition)],
+        body,
+    )
+        .into_response())
+}
+
+/// The whole queue (playing item first) with estimated air times.
+pub(crate) async fn api_queue_export(
+    State(state): State<AppState>,
+    Query(q): Query<ExportQuery>,
+) -> Result<Response, StatusCode> {
+    let items = {
+        let p = state.playout.read().await;
+        let mut log = p.log.clone();
+        estimate_start_times(&mut log, &p.now, unix_ms_now());
+        log
+    };
+    export_response(&items, q.format.as_deref(), "queue")
+}
+
+/// Recently aired items, oldest first.
+pub(crate) async fn api_history_export(
+    State(state): State<AppState>,
+    Query(q): Query<ExportQuery>,
+) -> Result<Response, StatusCode> {
+    let items: Vec<LogItem> = state.playout.read().await.recent.iter().cloned().collect();
+    export_response(&items, q.format.as_deref(), "history")
+}
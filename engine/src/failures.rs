@@ -0,0 +1,148 @@
+// --- Playback failures ----------------------------------------------------------------------
+//
+// A queue item whose file is missing or will not decode used to stop the
+// station quietly: the writer played silence and tried the same item again,
+// forever if nothing came after it, and a file that died at its first byte
+// was logged as "played".
+//
+// Now every failed start is counted, in memory for the item and in
+// `playback_failures` for the file. After `STUDIOCOMMAND_SKIP_AFTER_FAILURES`
+// failed starts in a row (default 3, half a second of silence apart; 0 never
+// skips) the item is skipped and ends as `errored` in the play history. A
+// decoder that gives out part way through an item (after its restarts, see
+// supervisor.rs) also counts against the file, and the item ends `errored`.
+// A file that plays again clears its run of failures but keeps its total.
+//
+// `GET /api/v1/playout/failures` lists the files that failed, worst first, so
+// they can be replaced or removed from rotation; `DELETE` with `?path=`
+// forgets one file, without it every file.
+
+use axum::{extract::Query, http::StatusCode, Json};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::unix_ms_now;
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS playback_failures (
+            path             TEXT PRIMARY KEY,
+            title            TEXT NOT NULL,
+            consecutive      INTEGER NOT NULL,
+            total            INTEGER NOT NULL,
+            last_error       TEXT NOT NULL,
+            first_failed_ms  INTEGER NOT NULL,
+            last_failed_ms   INTEGER NOT NULL
+        );
+        "#,
+    )
+}
+
+/// Failed starts in a row after which an item is skipped (0 = never).
+pub(crate) fn skip_after() -> u32 {
+    std::env::var("STUDIOCOMMAND_SKIP_AFTER_FAILURES").ok().and_then(|v| v.trim().parse().ok()).unwrap_or(3)
+}
+
+/// `path` failed to play (or, for an unresolvable cart, the cart).
+pub(crate) async fn record(path: String, title: String, error: String) {
+    let res = crate::db::call(move |conn| -> anyhow::Result<()> {
+        crate::db_init(conn)?;
+        let now = unix_ms_now() as i64;
+        conn.prepare_cached(
+            "INSERT INTO playback_failures (path, title, consecutive, total, last_error, first_failed_ms, last_failed_ms)
+             VALUES (?1, ?2, 1, 1, ?3, ?4, ?4)
+             ON CONFLICT(path) DO UPDATE SET
+               title=excluded.title,
+               consecutive=consecutive + 1,
+               total=total + 1,
+               last_error=excluded.last_error,
+               last_failed_ms=excluded.last_failed_ms",
+        )?
+        .execute(params![path, title, error, now])?;
+        Ok(())
+    })
+    .await;
+    if let Ok(Err(e)) = res {
+        tracing::warn!("failures: failed to record: {e}");
+    }
+}
+
+/// `path` played: its run of failures is over.
+pub(crate) async fn clear_run(path: String) {
+    let res = crate::db::call(move |conn| -> anyhow::Result<()> {
+        crate::db_init(conn)?;
+        conn.prepare_cached("UPDATE playback_failures SET consecutive = 0 WHERE path = ?1 AND consecutive > 0")?
+            .execute(params![path])?;
+        Ok(())
+    })
+    .await;
+    if let Ok(Err(e)) = res {
+        tracing::warn!("failures: failed to update: {e}");
+    }
+}
+
+// --- HTTP API -------------------------------------------------------------------
+
+#[derive(Serialize)]
+struct Failure {
+    path: String,
+    title: String,
+    /// Failures since the file last played.
+    consecutive: u32,
+    total: u32,
+    last_error: String,
+    first_failed_ms: u64,
+    last_failed_ms: u64,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct FailuresQuery {
+    path: Option<String>,
+}
+
+/// `GET /api/v1/playout/failures`, still failing first, then by total.
+pub(crate) async fn api_failures() -> Result<Json<serde_json::Value>, StatusCode> {
+    let items = crate::db::call(|conn| -> anyhow::Result<Vec<Failure>> {
+        crate::db_init(conn)?;
+        let mut stmt = conn.prepare(
+            "SELECT path, title, consecutive, total, last_error, first_failed_ms, last_failed_ms
+             FROM playback_failures ORDER BY consecutive > 0 DESC, total DESC, last_failed_ms DESC",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(Failure {
+                path: row.get(0)?,
+                title: row.get(1)?,
+                consecutive: row.get(2)?,
+                total: row.get(3)?,
+                last_error: row.get(4)?,
+                first_failed_ms: row.get::<_, i64>(5)? as u64,
+                last_failed_ms: row.get::<_, i64>(6)? as u64,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|e| {
+        tracing::warn!("failures list failed: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(json!({"skip_after": skip_after(), "items": items})))
+}
+
+/// `DELETE /api/v1/playout/failures[?path=]`: forget one file, or all.
+pub(crate) async fn api_failures_clear(Query(q): Query<FailuresQuery>) -> Result<Json<serde_json::Value>, StatusCode> {
+    let removed = crate::db::call(move |conn| -> anyhow::Result<usize> {
+        crate::db_init(conn)?;
+        Ok(match q.path {
+            Some(path) => conn.execute("DELETE FROM playback_failures WHERE path = ?1", params![path])?,
+            None => conn.execute("DELETE FROM playback_failures", [])?,
+        })
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(json!({"ok": true, "removed": removed})))
+}
@@ -0,0 +1,291 @@
+// --- Icecast fallback mount -------------------------------------------------------------
+//
+// When the stream is stopped on purpose (an operator or a timed action
+// stopping it for maintenance, `systemctl stop` for an update), Icecast
+// drops the source and every listener with it; players give up or retry
+// against a dead mount. With `/api/v1/output/fallback` enabled, the engine
+// moves the listeners to a backup mount first (typically a loop served by
+// Icecast itself or another source), through the admin API:
+//   `GET /admin/moveclients?mount=<mount>&destination=<fallback mount>`
+// and, whenever the stream connects again, moves them back the same way.
+//
+// - The move happens before the encoder stops, while the live mount still
+//   exists; a stream that is not connected has nobody to move. An encoder
+//   that dies is not a stop on purpose: for that, Icecast's own
+//   `<fallback-mount>` is the tool.
+// - The restore does not depend on this engine having moved anyone, so
+//   listeners come back after a restart of the engine, a standby taking over,
+//   or Icecast's `<fallback-mount>` having moved them. The backup mount
+//   should therefore only be used for this. The live mount is only known to
+//   Icecast a moment after connecting, so the restore is tried up to five
+//   times, 2 s apart.
+// - The admin account is Icecast's `<admin-user>`/`<admin-password>`, not the
+//   source credentials. The password is stored sealed (secrets.rs) and never
+//   returned. Requests go through curl (`STUDIOCOMMAND_CURL`) to the output's
+//   host and port.
+//
+// The last move and its outcome are in `status`.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use axum::{http::StatusCode, Json};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{eventlog, ffmpeg, secrets, unix_ms_now, StreamOutputConfig};
+
+const RESTORE_ATTEMPTS: u32 = 5;
+const RESTORE_EVERY: Duration = Duration::from_secs(2);
+/// Longest an admin request may take; a stop waits for it.
+const TIMEOUT_S: u64 = 3;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct FallbackConfig {
+    #[serde(default)]
+    enabled: bool,
+    /// Where listeners go while the stream is stopped, e.g. `/backup`.
+    #[serde(default)]
+    mount: String,
+    #[serde(default = "default_admin_user")]
+    admin_user: String,
+    /// Never returned (see `config_view`); empty on save keeps the current one.
+    #[serde(default)]
+    admin_password: String,
+    /// Move the listeners back when the stream connects.
+    #[serde(default = "default_true")]
+    restore: bool,
+}
+
+fn default_admin_user() -> String {
+    "admin".into()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_config() -> FallbackConfig {
+    serde_json::from_str("{}").expect("defaults")
+}
+
+#[derive(Clone, Default, Serialize)]
+pub(crate) struct FallbackStatus {
+    /// Listeners were moved to the fallback mount and not yet back.
+    moved: bool,
+    /// The last move, e.g. `/live -> /backup`.
+    last_move: Option<String>,
+    last_move_ms: Option<u64>,
+    last_error: Option<String>,
+}
+
+static STATUS: Mutex<FallbackStatus> =
+    Mutex::new(FallbackStatus { moved: false, last_move: None, last_move_ms: None, last_error: None });
+
+fn with_status<T>(f: impl FnOnce(&mut FallbackStatus) -> T) -> T {
+    f(&mut STATUS.lock().unwrap_or_else(|e| e.into_inner()))
+}
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS fallback_config (
+            id      INTEGER PRIMARY KEY CHECK (id = 1),
+            config  TEXT NOT NULL
+        );
+        "#,
+    )
+}
+
+async fn with_db<T: Send + 'static>(
+    f: impl FnOnce(&Connection) -> anyhow::Result<T> + Send + 'static,
+) -> anyhow::Result<T> {
+    crate::db::call(move |conn| {
+        crate::db_init(conn)?;
+        f(conn)
+    })
+    .await?
+}
+
+/// The stored configuration, password opened.
+async fn load_config() -> anyhow::Result<FallbackConfig> {
+    with_db(|conn| {
+        let raw: Option<String> =
+            conn.query_row("SELECT config FROM fallback_config WHERE id = 1", [], |row| row.get(0)).optional()?;
+        let mut cfg: FallbackConfig = match raw {
+            Some(r) => serde_json::from_str(&r)?,
+            None => default_config(),
+        };
+        cfg.admin_password = secrets::open(&cfg.admin_password)?;
+        Ok(cfg)
+    })
+    .await
+}
+
+/// The configuration as the API shows it: `has_admin_password` instead of the password.
+fn config_view(cfg: &FallbackConfig) -> Value {
+    let mut v = serde_json::to_value(cfg).unwrap_or_default();
+    if let Some(o) = v.as_object_mut() {
+        o.remove("admin_password");
+        o.insert("has_admin_password".into(), (!cfg.admin_password.is_empty()).into());
+    }
+    v
+}
+
+/// The enabled configuration, if the output is one it applies to.
+async fn active(output: &StreamOutputConfig) -> Option<FallbackConfig> {
+    if output.host.is_empty() || output.mount.is_empty() {
+        return None;
+    }
+    match load_config().await {
+        Ok(cfg) if cfg.enabled && !cfg.mount.is_empty() && cfg.mount != output.mount => Some(cfg),
+        Ok(_) => None,
+        Err(e) => {
+            tracing::warn!("fallback: {e}");
+            None
+        }
+    }
+}
+
+/// `<message>` of Icecast's admin response.
+fn ice_message(body: &str) -> Option<&str> {
+    let start = body.find("<message>")? + "<message>".len();
+    let end = body[start..].find("</message>")?;
+    Some(body[start..start + end].trim())
+}
+
+/// Moves the listeners of `from` to `to` with Icecast's `moveclients`.
+async fn move_clients(output: &StreamOutputConfig, cfg: &FallbackConfig, from: &str, to: &str) -> Result<(), String> {
+    let curl = ffmpeg::curl_bin();
+    let url = format!("http://{}:{}/admin/moveclients", output.host, output.port);
+    let auth = format!("{}:{}", cfg.admin_user, cfg.admin_password);
+    let out = tokio::process::Command::new(&curl)
+        .args(["-sS", "--max-time", &TIMEOUT_S.to_string(), "-G", "-u", &auth])
+        .args(["--data-urlencode", &format!("mount={from}"), "--data-urlencode", &format!("destination={to}")])
+        .args(["-w", "\n%{http_code}", &url])
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|e| format!("{curl}: {e}"))?;
+    if !out.status.success() {
+        let stderr = String::from_utf8_lossy(&out.stderr).trim().to_string();
+        return Err(if stderr.is_empty() { "request failed".into() } else { stderr });
+    }
+    let reply = String::from_utf8_lossy(&out.stdout);
+    let (body, code) = reply.rsplit_once('\n').unwrap_or(("", &reply));
+    match code.trim() {
+        "200" if body.contains("<return>1</return>") => Ok(()),
+        "401" => Err("Icecast refused the admin credentials".into()),
+        code => Err(ice_message(body).map(str::to_string).unwrap_or_else(|| format!("HTTP {code}"))),
+    }
+}
+
+fn record(from: &str, to: &str, moved: bool, res: &Result<(), String>) {
+    let message = match res {
+        Ok(()) => format!("listeners moved from {from} to {to}"),
+        Err(e) => format!("moving listeners from {from} to {to} failed: {e}"),
+    };
+    eventlog::record("fallback", message, json!({"from": from, "to": to, "engaged": moved, "ok": res.is_ok()}));
+    with_status(|s| {
+        s.last_move = Some(format!("{from} -> {to}"));
+        s.last_move_ms = Some(unix_ms_now());
+        match res {
+            Ok(()) => {
+                s.moved = moved;
+                s.last_error = None;
+            }
+            Err(e) => s.last_error = Some(e.clone()),
+        }
+    });
+}
+
+/// Before a stop on purpose: moves the listeners to the fallback mount.
+pub(crate) async fn engage(output: &StreamOutputConfig) {
+    let Some(cfg) = active(output).await else { return };
+    let res = move_clients(output, &cfg, &output.mount, &cfg.mount).await;
+    match &res {
+        Ok(()) => tracing::info!("fallback: listeners moved from {} to {}", output.mount, cfg.mount),
+        Err(e) => tracing::warn!("fallback: moving listeners to {}: {e}", cfg.mount),
+    }
+    record(&output.mount, &cfg.mount, true, &res);
+}
+
+/// Once the stream is connected: moves the listeners back from the fallback mount.
+pub(crate) async fn restore(output: StreamOutputConfig) {
+    let Some(cfg) = active(&output).await.filter(|c| c.restore) else { return };
+    let mut attempt = 1;
+    let res = loop {
+        let res = move_clients(&output, &cfg, &cfg.mount, &output.mount).await;
+        if res.is_ok() || attempt == RESTORE_ATTEMPTS {
+            break res;
+        }
+        attempt += 1;
+        tokio::time::sleep(RESTORE_EVERY).await;
+    };
+    match &res {
+        Ok(()) => tracing::info!("fallback: listeners moved back from {} to {}", cfg.mount, output.mount),
+        Err(e) => tracing::warn!("fallback: moving listeners back from {}: {e}", cfg.mount),
+    }
+    record(&cfg.mount, &output.mount, false, &res);
+}
+
+// --- HTTP API --------------------------------------------------------------------------
+
+type ApiError = (StatusCode, Json<Value>);
+
+fn api_error(status: StatusCode, msg: impl Into<String>) -> ApiError {
+    (status, Json(json!({"ok": false, "error": msg.into()})))
+}
+
+pub(crate) async fn api_fallback_config_get() -> Result<Json<Value>, StatusCode> {
+    let cfg = load_config().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let status = with_status(|s| s.clone());
+    Ok(Json(json!({"ok": true, "config": config_view(&cfg), "status": status})))
+}
+
+/// The configuration with its password, for the settings export (which redacts it).
+pub(crate) async fn export_config() -> Result<Value, String> {
+    let cfg = load_config().await.map_err(|e| e.to_string())?;
+    serde_json::to_value(cfg).map_err(|e| e.to_string())
+}
+
+pub(crate) async fn api_fallback_config_set(Json(mut cfg): Json<FallbackConfig>) -> Result<Json<Value>, ApiError> {
+    let bad = |msg: &str| Err(api_error(StatusCode::BAD_REQUEST, msg));
+    cfg.mount = cfg.mount.trim().to_string();
+    cfg.admin_user = cfg.admin_user.trim().to_string();
+    if !cfg.mount.is_empty() && (!cfg.mount.starts_with('/') || cfg.mount.contains(char::is_whitespace)) {
+        return bad("mount must start with / and contain no spaces");
+    }
+    if cfg.enabled && cfg.mount.is_empty() {
+        return bad("mount (the fallback mount) is required");
+    }
+    if cfg.enabled && cfg.admin_user.is_empty() {
+        return bad("admin_user is required");
+    }
+    // GET never returns the password, so a form saved without touching it
+    // sends none: keep the one we have.
+    if cfg.admin_password.is_empty() {
+        cfg.admin_password = load_config().await.map(|c| c.admin_password).unwrap_or_default();
+    }
+    if cfg.enabled && cfg.admin_password.is_empty() {
+        return bad("admin_password is required");
+    }
+
+    let mut stored = cfg.clone();
+    stored.admin_password =
+        secrets::seal(&cfg.admin_password).map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let raw =
+        serde_json::to_string(&stored).map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    with_db(move |conn| {
+        conn.execute(
+            "INSERT INTO fallback_config (id, config) VALUES (1, ?1)
+             ON CONFLICT(id) DO UPDATE SET config=excluded.config",
+            params![raw],
+        )?;
+        Ok(())
+    })
+    .await
+    .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(json!({"ok": true, "config": config_view(&cfg)})))
+}
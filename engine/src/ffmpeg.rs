@@ -0,0 +1,240 @@
+// --- ffmpeg / ffprobe discovery -------------------------------------------------------------
+//
+// All decoding, encoding and probing goes through the ffmpeg and ffprobe
+// binaries (`STUDIOCOMMAND_FFMPEG` / `STUDIOCOMMAND_FFPROBE`, else the names
+// looked up on `PATH`). Distribution builds differ: some ship without
+// libmp3lame, a static build may be older than expected, and a wrong
+// override points at nothing. A stream started with a missing encoder used
+// to fail with "Unknown encoder 'libmp3lame'" somewhere in the stderr tail.
+//
+// `detect` resolves both binaries to a path, reads their version and the
+// encoders ffmpeg was built with (`ffmpeg -encoders`), and keeps the result
+// until the next `?refresh=1`. `GET /api/v1/system/ffmpeg` reports it, the
+// self-check uses it, and output start refuses a codec whose encoder is not
+// compiled in, with that as the error.
+//
+// curl, which every outbound HTTP request goes through, is configured the
+// same way (`STUDIOCOMMAND_CURL`) and resolved here too.
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use axum::{extract::Query, Json};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::unix_ms_now;
+
+/// Encoders worth reporting: what output codecs use, and what else a station might want.
+const ENCODERS: &[&str] = &["libmp3lame", "aac", "libfdk_aac", "libopus", "libvorbis", "flac", "pcm_s16le", "s302m"];
+
+/// ffmpeg as configured (`STUDIOCOMMAND_FFMPEG`, default `ffmpeg`).
+pub(crate) fn ffmpeg_bin() -> String {
+    std::env::var("STUDIOCOMMAND_FFMPEG").unwrap_or_else(|_| "ffmpeg".to_string())
+}
+
+/// ffprobe as configured (`STUDIOCOMMAND_FFPROBE`, default `ffprobe`).
+pub(crate) fn ffprobe_bin() -> String {
+    std::env::var("STUDIOCOMMAND_FFPROBE").unwrap_or_else(|_| "ffprobe".to_string())
+}
+
+/// curl as configured (`STUDIOCOMMAND_CURL`, default `curl`).
+pub(crate) fn curl_bin() -> String {
+    std::env::var("STUDIOCOMMAND_CURL").unwrap_or_else(|_| "curl".to_string())
+}
+
+/// Encoder output codecs need (`StreamOutputConfig::codec`, and the STL's in stl.rs).
+pub(crate) fn encoder_for(codec: &str) -> Option<&'static str> {
+    match codec {
+        "mp3" => Some("libmp3lame"),
+        "aac" => Some("aac"),
+        "opus" => Some("libopus"),
+        "pcm" => Some("s302m"),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct Binary {
+    /// As configured (a name or a path).
+    configured: String,
+    /// Where it resolved to; `None` if it was not found.
+    path: Option<String>,
+    /// "env" (`STUDIOCOMMAND_*`) or "path".
+    source: &'static str,
+    /// e.g. `6.1.1-3ubuntu5`.
+    version: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct Tools {
+    detected_ms: u64,
+    ffmpeg: Binary,
+    ffprobe: Binary,
+    /// Each of `ENCODERS`, and whether this ffmpeg has it.
+    encoders: std::collections::BTreeMap<&'static str, bool>,
+    /// Why the encoder list could not be read; encoders then count as present.
+    encoders_error: Option<String>,
+}
+
+static CACHE: Mutex<Option<Tools>> = Mutex::new(None);
+
+/// `name` as a path: itself if it has a slash, else the first match on `PATH`.
+fn resolve(name: &str) -> Option<PathBuf> {
+    if name.contains('/') {
+        return Path::new(name).is_file().then(|| PathBuf::from(name));
+    }
+    let paths = std::env::var_os("PATH")?;
+    std::env::split_paths(&paths).map(|dir| dir.join(name)).find(|p| p.is_file())
+}
+
+async fn run(program: &Path, args: &[&str]) -> Result<String, String> {
+    let mut cmd = tokio::process::Command::new(program);
+    cmd.args(args).kill_on_drop(true);
+    let out = match tokio::time::timeout(Duration::from_secs(5), cmd.output()).await {
+        Ok(Ok(out)) => out,
+        Ok(Err(e)) => return Err(format!("{}: {e}", program.display())),
+        Err(_) => return Err(format!("{}: timed out", program.display())),
+    };
+    if !out.status.success() {
+        return Err(format!("{} {} exited with {}", program.display(), args.join(" "), out.status));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+/// `ffmpeg version 6.1.1-3ubuntu5 Copyright ...` -> `6.1.1-3ubuntu5`.
+fn parse_version(text: &str) -> Option<String> {
+    let first = text.lines().next()?;
+    let rest = first.split_once(" version ")?.1;
+    rest.split_whitespace().next().map(str::to_string)
+}
+
+async fn detect_binary(env: &str, configured: String) -> Binary {
+    let source = if std::env::var_os(env).is_some() { "env" } else { "path" };
+    let mut b = Binary { configured, path: None, source, version: None, error: None };
+    let Some(path) = resolve(&b.configured) else {
+        b.error = Some(match source {
+            "env" => format!("{env}={} does not exist", b.configured),
+            _ => format!("{} not found on PATH", b.configured),
+        });
+        return b;
+    };
+    b.path = Some(path.display().to_string());
+    match run(&path, &["-version"]).await {
+        Ok(text) => b.version = parse_version(&text),
+        Err(e) => b.error = Some(e),
+    }
+    b
+}
+
+/// Encoder names from `ffmpeg -encoders` (lines like ` A....D libmp3lame  ...`).
+fn parse_encoders(text: &str) -> Vec<String> {
+    text.lines()
+        .skip_while(|l| !l.trim_start().starts_with("------"))
+        .skip(1)
+        .filter_map(|l| l.split_whitespace().nth(1).map(str::to_string))
+        .collect()
+}
+
+/// Look both binaries up again and cache the result.
+pub(crate) async fn detect() -> Tools {
+    let ffmpeg = detect_binary("STUDIOCOMMAND_FFMPEG", ffmpeg_bin()).await;
+    let ffprobe = detect_binary("STUDIOCOMMAND_FFPROBE", ffprobe_bin()).await;
+    let mut have = Vec::new();
+    let mut encoders_error = None;
+    if let (Some(path), None) = (&ffmpeg.path, &ffmpeg.error) {
+        match run(Path::new(path), &["-hide_banner", "-encoders"]).await {
+            Ok(text) => have = parse_encoders(&text),
+            Err(e) => {
+                tracing::warn!("ffmpeg: cannot list encoders: {e}");
+                encoders_error = Some(e);
+            }
+        }
+    }
+    let tools = Tools {
+        detected_ms: unix_ms_now(),
+        ffmpeg,
+        ffprobe,
+        encoders: ENCODERS.iter().map(|e| (*e, have.iter().any(|h| h == e))).collect(),
+        encoders_error,
+    };
+    *CACHE.lock().unwrap_or_else(|e| e.into_inner()) = Some(tools.clone());
+    tools
+}
+
+/// Whether output `codec` can be encoded, looking again before refusing
+/// (ffmpeg may have been replaced since the last detection).
+pub(crate) async fn check_output_codec(codec: &str) -> Result<(), String> {
+    if tools().await.check_codec(codec).is_ok() {
+        return Ok(());
+    }
+    detect().await.check_codec(codec)
+}
+
+/// The cached detection, running it the first time.
+pub(crate) async fn tools() -> Tools {
+    let cached = CACHE.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    match cached {
+        Some(t) => t,
+        None => detect().await,
+    }
+}
+
+impl Tools {
+    /// `Ok(version)` if ffmpeg runs, else why not.
+    pub(crate) fn ffmpeg_status(&self) -> Result<String, String> {
+        status_of(&self.ffmpeg)
+    }
+
+    pub(crate) fn ffprobe_status(&self) -> Result<String, String> {
+        status_of(&self.ffprobe)
+    }
+
+    /// Output codecs this ffmpeg cannot encode.
+    pub(crate) fn missing_output_encoders(&self) -> Vec<&'static str> {
+        ["mp3", "aac"].into_iter().filter(|c| self.check_codec(c).is_err()).collect()
+    }
+
+    /// Whether ffmpeg can encode output `codec`, with an operator-facing reason if not.
+    pub(crate) fn check_codec(&self, codec: &str) -> Result<(), String> {
+        let path = match (&self.ffmpeg.path, &self.ffmpeg.error) {
+            (Some(p), None) => p,
+            (_, Some(e)) => return Err(format!("ffmpeg is not usable: {e}")),
+            (None, None) => return Err("ffmpeg was not found".into()),
+        };
+        let Some(encoder) = encoder_for(codec) else {
+            return Err(format!("unsupported codec: {codec}"));
+        };
+        // Without a list, let ffmpeg itself be the judge.
+        if self.encoders_error.is_some() || self.encoders.get(encoder).copied().unwrap_or(false) {
+            Ok(())
+        } else {
+            Err(format!(
+                "{path} was built without the {encoder} encoder needed for {codec}; \
+                 install an ffmpeg with {encoder} or choose another codec"
+            ))
+        }
+    }
+}
+
+fn status_of(b: &Binary) -> Result<String, String> {
+    match (&b.path, &b.error) {
+        (_, Some(e)) => Err(e.clone()),
+        (Some(p), None) => Ok(format!("{p} ({})", b.version.as_deref().unwrap_or("unknown version"))),
+        (None, None) => Err(format!("{} not found", b.configured)),
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct FfmpegQuery {
+    refresh: Option<u8>,
+}
+
+/// `GET /api/v1/system/ffmpeg[?refresh=1]`
+pub(crate) async fn api_ffmpeg(Query(q): Query<FfmpegQuery>) -> Json<serde_json::Value> {
+    let tools = if q.refresh.unwrap_or(0) != 0 { detect().await } else { tools().await };
+    let ok = tools.ffmpeg_status().is_ok();
+    Json(json!({"ok": ok, "tools": tools, "missing_output_encoders": tools.missing_output_encoders()}))
+}
@@ -0,0 +1,440 @@
+// --- GPIO ---------------------------------------------------------------------------------
+//
+// Studios wire things to the automation box: an ON AIR light over the door,
+// a "next" button on the desk, a start/stop key on the transmitter rack. On
+// a Raspberry Pi-class board those are GPIO lines, driven here through the
+// kernel's GPIO character device (`/dev/gpiochip0`, no extra tools or
+// daemons):
+// - `outputs` follow a signal: `streaming` (the stream is connected),
+//   `playing` (connected and something is queued), or `silence` (connected
+//   but no audio, peak below 0.003 as the dead-air alert), for tally lights
+//   and warning lamps;
+// - `inputs` run an action on a press: any scheduled action type
+//   (schedule.rs: `skip`, `output_start`, `output_stop`, `output_toggle`,
+//   `insert_cart`, ...). A press is the line going active and staying there
+//   for `debounce_ms` (default 30). Buttons usually pull the line to ground,
+//   so inputs default to active-low with the pull-up on.
+//
+// Lines are numbered by their offset on `chip` (on a Pi, the BCM number).
+// `POST /api/v1/gpio/config` applies a new configuration at once; lines are
+// released and claimed again. `GET /api/v1/gpio` shows each line's current
+// value and last press, and why a line could not be claimed.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::{Mutex, OnceLock};
+
+use axum::{http::StatusCode, Json};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::time::{Duration, Instant};
+
+use crate::schedule::{self, Action};
+use crate::{meters, standby, unix_ms_now, AppState};
+
+/// How often inputs are read and outputs updated.
+const POLL: Duration = Duration::from_millis(10);
+/// How often the configuration is re-read.
+const RELOAD: Duration = Duration::from_secs(5);
+/// Peak level (0..1) below which the stream counts as silent.
+const SILENT_PEAK: f32 = 0.003;
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct GpioConfig {
+    #[serde(default)]
+    enabled: bool,
+    #[serde(default = "default_chip")]
+    chip: String,
+    #[serde(default)]
+    outputs: Vec<GpioOutput>,
+    #[serde(default)]
+    inputs: Vec<GpioInput>,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct GpioOutput {
+    line: u32,
+    /// "streaming", "playing" or "silence".
+    follows: String,
+    #[serde(default)]
+    active_low: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct GpioInput {
+    line: u32,
+    action: Action,
+    #[serde(default = "default_true")]
+    active_low: bool,
+    #[serde(default = "default_true")]
+    pull_up: bool,
+    #[serde(default = "default_debounce_ms")]
+    debounce_ms: u64,
+}
+
+// `Action` has no PartialEq; compare inputs by their JSON.
+impl PartialEq for GpioInput {
+    fn eq(&self, other: &Self) -> bool {
+        serde_json::to_value(self).ok() == serde_json::to_value(other).ok()
+    }
+}
+
+fn default_chip() -> String {
+    "/dev/gpiochip0".into()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_debounce_ms() -> u64 {
+    30
+}
+
+fn default_config() -> GpioConfig {
+    serde_json::from_str("{}").expect("defaults")
+}
+
+const SIGNALS: &[&str] = &["streaming", "playing", "silence"];
+
+#[derive(Clone, Serialize, Default)]
+struct LineStatus {
+    /// "input" or "output".
+    direction: &'static str,
+    /// Logical value: true is active (lit, pressed).
+    active: Option<bool>,
+    last_press_ms: Option<u64>,
+    /// The result of the last press's action.
+    last_result: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Clone, Serialize, Default)]
+struct GpioStatus {
+    /// The chip could not be opened.
+    error: Option<String>,
+    lines: HashMap<u32, LineStatus>,
+}
+
+fn status() -> &'static Mutex<GpioStatus> {
+    static STATUS: OnceLock<Mutex<GpioStatus>> = OnceLock::new();
+    STATUS.get_or_init(|| Mutex::new(GpioStatus::default()))
+}
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS gpio_config (
+            id      INTEGER PRIMARY KEY CHECK (id = 1),
+            config  TEXT NOT NULL
+        );
+        "#,
+    )
+}
+
+fn db_load_config(conn: &Connection) -> anyhow::Result<GpioConfig> {
+    crate::db_init(conn)?;
+    let raw: Option<String> =
+        conn.query_row("SELECT config FROM gpio_config WHERE id = 1", [], |row| row.get(0)).optional()?;
+    Ok(match raw {
+        Some(r) => serde_json::from_str(&r)?,
+        None => default_config(),
+    })
+}
+
+async fn load_config() -> anyhow::Result<GpioConfig> {
+    crate::db::call(move |conn| db_load_config(conn)).await?
+}
+
+// --- Character device ----------------------------------------------------------------------
+//
+// The v1 line-handle ABI (`linux/gpio.h`): one request per line, then get or
+// set its value. It is in every kernel a Pi runs, and needs no libgpiod.
+
+const GPIOHANDLES_MAX: usize = 64;
+
+#[repr(C)]
+struct HandleRequest {
+    lineoffsets: [u32; GPIOHANDLES_MAX],
+    flags: u32,
+    default_values: [u8; GPIOHANDLES_MAX],
+    consumer_label: [u8; 32],
+    lines: u32,
+    fd: libc::c_int,
+}
+
+#[repr(C)]
+struct HandleData {
+    values: [u8; GPIOHANDLES_MAX],
+}
+
+// _IOWR(0xB4, nr, size)
+const GPIO_GET_LINEHANDLE_IOCTL: u32 = 0xC16C_B403;
+const GPIOHANDLE_GET_LINE_VALUES_IOCTL: u32 = 0xC040_B408;
+const GPIOHANDLE_SET_LINE_VALUES_IOCTL: u32 = 0xC040_B409;
+
+const GPIOHANDLE_REQUEST_INPUT: u32 = 1 << 0;
+const GPIOHANDLE_REQUEST_OUTPUT: u32 = 1 << 1;
+const GPIOHANDLE_REQUEST_ACTIVE_LOW: u32 = 1 << 2;
+const GPIOHANDLE_REQUEST_BIAS_PULL_UP: u32 = 1 << 5;
+
+/// One claimed line. Values are logical: the kernel applies active-low.
+struct Line {
+    fd: OwnedFd,
+}
+
+impl Line {
+    fn request(chip: &File, offset: u32, flags: u32) -> std::io::Result<Line> {
+        let mut req = HandleRequest {
+            lineoffsets: [0; GPIOHANDLES_MAX],
+            flags,
+            default_values: [0; GPIOHANDLES_MAX],
+            consumer_label: [0; 32],
+            lines: 1,
+            fd: -1,
+        };
+        req.lineoffsets[0] = offset;
+        req.consumer_label[..13].copy_from_slice(b"studiocommand");
+        // SAFETY: `req` is a valid, writable `gpiohandle_request` for this ioctl.
+        if unsafe { libc::ioctl(chip.as_raw_fd(), GPIO_GET_LINEHANDLE_IOCTL as _, &mut req) } < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        // SAFETY: on success the kernel returned a new file descriptor we now own.
+        Ok(Line { fd: unsafe { OwnedFd::from_raw_fd(req.fd) } })
+    }
+
+    fn get(&self) -> std::io::Result<bool> {
+        let mut data = HandleData { values: [0; GPIOHANDLES_MAX] };
+        // SAFETY: `data` is a valid, writable `gpiohandle_data`.
+        if unsafe { libc::ioctl(self.fd.as_raw_fd(), GPIOHANDLE_GET_LINE_VALUES_IOCTL as _, &mut data) } < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(data.values[0] != 0)
+    }
+
+    fn set(&self, active: bool) -> std::io::Result<()> {
+        let mut data = HandleData { values: [0; GPIOHANDLES_MAX] };
+        data.values[0] = active as u8;
+        // SAFETY: `data` is a valid `gpiohandle_data`.
+        if unsafe { libc::ioctl(self.fd.as_raw_fd(), GPIOHANDLE_SET_LINE_VALUES_IOCTL as _, &mut data) } < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+// --- Polling ---------------------------------------------------------------------------------
+
+struct Output {
+    cfg: GpioOutput,
+    line: Line,
+    value: Option<bool>,
+}
+
+struct Input {
+    cfg: GpioInput,
+    line: Line,
+    /// Debounced value, and the raw value with when it last changed.
+    stable: bool,
+    raw: (bool, Instant),
+}
+
+/// Claim the configured lines; the ones that fail are reported and skipped.
+fn open(cfg: &GpioConfig) -> (Vec<Output>, Vec<Input>) {
+    let mut st = GpioStatus::default();
+    let mut outputs = Vec::new();
+    let mut inputs = Vec::new();
+    if !cfg.enabled {
+        *status().lock().unwrap_or_else(|e| e.into_inner()) = st;
+        return (outputs, inputs);
+    }
+    let chip = match File::open(&cfg.chip) {
+        Ok(f) => f,
+        Err(e) => {
+            tracing::warn!("gpio: {}: {e}", cfg.chip);
+            st.error = Some(format!("{}: {e}", cfg.chip));
+            *status().lock().unwrap_or_else(|e| e.into_inner()) = st;
+            return (outputs, inputs);
+        }
+    };
+    for o in &cfg.outputs {
+        let flags = GPIOHANDLE_REQUEST_OUTPUT | if o.active_low { GPIOHANDLE_REQUEST_ACTIVE_LOW } else { 0 };
+        let mut ls = LineStatus { direction: "output", ..Default::default() };
+        match Line::request(&chip, o.line, flags) {
+            Ok(line) => outputs.push(Output { cfg: o.clone(), line, value: None }),
+            Err(e) => ls.error = Some(e.to_string()),
+        }
+        st.lines.insert(o.line, ls);
+    }
+    for i in &cfg.inputs {
+        let mut flags = GPIOHANDLE_REQUEST_INPUT;
+        if i.active_low {
+            flags |= GPIOHANDLE_REQUEST_ACTIVE_LOW;
+        }
+        if i.pull_up {
+            flags |= GPIOHANDLE_REQUEST_BIAS_PULL_UP;
+        }
+        let mut ls = LineStatus { direction: "input", ..Default::default() };
+        match Line::request(&chip, i.line, flags).and_then(|line| Ok((line.get()?, line))) {
+            Ok((value, line)) => {
+                ls.active = Some(value);
+                inputs.push(Input { cfg: i.clone(), line, stable: value, raw: (value, Instant::now()) });
+            }
+            Err(e) => ls.error = Some(e.to_string()),
+        }
+        st.lines.insert(i.line, ls);
+    }
+    for (line, ls) in &st.lines {
+        if let Some(e) = &ls.error {
+            tracing::warn!("gpio: line {line}: {e}");
+        }
+    }
+    *status().lock().unwrap_or_else(|e| e.into_inner()) = st;
+    (outputs, inputs)
+}
+
+fn set_line_status(line: u32, f: impl FnOnce(&mut LineStatus)) {
+    if let Some(ls) = status().lock().unwrap_or_else(|e| e.into_inner()).lines.get_mut(&line) {
+        f(ls);
+    }
+}
+
+/// Drive the output lines and watch the input lines.
+pub(crate) async fn gpio_task(state: AppState) {
+    let mut cfg: Option<GpioConfig> = None;
+    let mut loaded_at = Instant::now() - RELOAD;
+    let (mut outputs, mut inputs) = (Vec::new(), Vec::new());
+    let mut tick = tokio::time::interval(POLL);
+    tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        tick.tick().await;
+        if loaded_at.elapsed() >= RELOAD {
+            loaded_at = Instant::now();
+            match load_config().await {
+                Ok(new) if cfg.as_ref() != Some(&new) => {
+                    // Release the old lines before claiming the new ones.
+                    outputs.clear();
+                    inputs.clear();
+                    (outputs, inputs) = open(&new);
+                    cfg = Some(new);
+                }
+                Ok(_) => {}
+                Err(e) => tracing::warn!("gpio: {e}"),
+            }
+        }
+        if outputs.is_empty() && inputs.is_empty() {
+            continue;
+        }
+
+        if !outputs.is_empty() {
+            let streaming = state.output.lock().await.status.state == "connected";
+            let queued = state.snapshot.borrow().log.get() != "[]";
+            let vu = meters::get();
+            for o in &mut outputs {
+                let want = match o.cfg.follows.as_str() {
+                    "streaming" => streaming,
+                    "playing" => streaming && queued,
+                    "silence" => streaming && vu.peak_l.max(vu.peak_r) < SILENT_PEAK,
+                    _ => false,
+                };
+                if o.value == Some(want) {
+                    continue;
+                }
+                let res = o.line.set(want);
+                o.value = res.is_ok().then_some(want);
+                set_line_status(o.cfg.line, |ls| {
+                    ls.active = o.value;
+                    ls.error = res.err().map(|e| e.to_string());
+                });
+            }
+        }
+
+        for i in &mut inputs {
+            let value = match i.line.get() {
+                Ok(v) => v,
+                Err(e) => {
+                    set_line_status(i.cfg.line, |ls| ls.error = Some(e.to_string()));
+                    continue;
+                }
+            };
+            if value != i.raw.0 {
+                i.raw = (value, Instant::now());
+            }
+            if value == i.stable || i.raw.1.elapsed() < Duration::from_millis(i.cfg.debounce_ms) {
+                continue;
+            }
+            i.stable = value;
+            set_line_status(i.cfg.line, |ls| ls.active = Some(value));
+            if !value {
+                continue;
+            }
+            tracing::info!(target: "audit", "gpio: line {} pressed", i.cfg.line);
+            let (state, action, line) = (state.clone(), i.cfg.action.clone(), i.cfg.line);
+            tokio::spawn(async move {
+                // A standby leaves the actions to the primary (see standby.rs).
+                let result = if standby::is_standby() {
+                    "skipped (standby)".to_string()
+                } else {
+                    match schedule::run_action(&state, &action).await {
+                        Ok(()) => "ok".to_string(),
+                        Err(e) => {
+                            tracing::warn!("gpio: line {line}: {e}");
+                            e
+                        }
+                    }
+                };
+                set_line_status(line, |ls| {
+                    ls.last_press_ms = Some(unix_ms_now());
+                    ls.last_result = Some(result);
+                });
+            });
+        }
+    }
+}
+
+// --- HTTP API --------------------------------------------------------------------------
+
+/// `GET /api/v1/gpio`: the configuration and the state of each line.
+pub(crate) async fn api_gpio_get() -> Result<Json<serde_json::Value>, StatusCode> {
+    let cfg = load_config().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let st = status().lock().unwrap_or_else(|e| e.into_inner()).clone();
+    Ok(Json(json!({"ok": true, "config": cfg, "status": st})))
+}
+
+/// `POST /api/v1/gpio/config`, applied within a few seconds.
+pub(crate) async fn api_gpio_config_set(
+    Json(mut cfg): Json<GpioConfig>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    let bad = |msg: String| Err((StatusCode::BAD_REQUEST, Json(json!({"ok": false, "error": msg}))));
+    cfg.chip = cfg.chip.trim().to_string();
+    if !cfg.chip.starts_with("/dev/") {
+        return bad("chip must be a device path such as /dev/gpiochip0".into());
+    }
+    let mut lines: Vec<u32> = cfg.outputs.iter().map(|o| o.line).chain(cfg.inputs.iter().map(|i| i.line)).collect();
+    lines.sort_unstable();
+    if let Some(w) = lines.windows(2).find(|w| w[0] == w[1]) {
+        return bad(format!("line {} is configured twice", w[0]));
+    }
+    if let Some(o) = cfg.outputs.iter().find(|o| !SIGNALS.contains(&o.follows.as_str())) {
+        return bad(format!("line {}: follows must be one of {}", o.line, SIGNALS.join(", ")));
+    }
+    if let Some(i) = cfg.inputs.iter().find(|i| i.debounce_ms > 1000) {
+        return bad(format!("line {}: debounce_ms must be at most 1000", i.line));
+    }
+    let raw = serde_json::to_string(&cfg).map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"ok": false}))))?;
+    crate::db::call(move |conn| -> anyhow::Result<()> {
+        crate::db_init(conn)?;
+        conn.execute(
+            "INSERT INTO gpio_config (id, config) VALUES (1, ?1)
+             ON CONFLICT(id) DO UPDATE SET config=excluded.config",
+            params![raw],
+        )?;
+        Ok(())
+    })
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"ok": false}))))?
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"ok": false}))))?;
+    Ok(Json(json!({"ok": true, "config": cfg})))
+}
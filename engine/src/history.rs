@@ -0,0 +1,302 @@
+// --- Play history ----------------------------------------------------------------
+//
+// Every item that starts playing is recorded in `play_history`. Unlike the
+// in-memory `recent` list (last 50 items) this keeps everything and survives
+// restarts, which is what separation rules need: "this song aired 20 minutes
+// ago" must still be true after the engine is redeployed. `recent` itself is
+// rebuilt from it at startup (`load_recent`), so the recently played items
+// and requeue still work after an update, a restore or a bot's `/restart`.
+//
+// The row is opened when the item starts and closed when it leaves the air,
+// with how it ended: `played` (ran to the end), `skipped`, `dumped`, `faded`
+// (cut by a hard event), `errored` (would not play, see failures.rs) or
+// `interrupted` (the engine stopped mid-item). That
+// makes the table usable as an as-aired log via `GET /api/v1/history`.
+
+use std::collections::VecDeque;
+
+use axum::{extract::Query, http::StatusCode, Json};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{clocks::LocalHour, fmt_local_hhmmss, parse_dur_to_sec, unix_ms_now, LogItem};
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS play_history (
+            id          INTEGER PRIMARY KEY AUTOINCREMENT,
+            started_ms  INTEGER NOT NULL,
+            path        TEXT NOT NULL,
+            tag         TEXT NOT NULL,
+            title       TEXT NOT NULL,
+            artist      TEXT NOT NULL
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_play_history_started ON play_history(started_ms);
+        "#,
+    )?;
+    crate::db_add_column_if_missing(conn, "play_history", "item_id", "TEXT")?;
+    crate::db_add_column_if_missing(conn, "play_history", "cart", "TEXT NOT NULL DEFAULT ''")?;
+    crate::db_add_column_if_missing(conn, "play_history", "dur", "TEXT NOT NULL DEFAULT ''")?;
+    crate::db_add_column_if_missing(conn, "play_history", "ended_ms", "INTEGER")?;
+    crate::db_add_column_if_missing(conn, "play_history", "outcome", "TEXT")?;
+    conn.execute_batch("CREATE INDEX IF NOT EXISTS idx_play_history_item ON play_history(item_id);")
+}
+
+/// One aired item, as far as rotation rules care.
+pub(crate) struct Aired {
+    pub(crate) started_ms: u64,
+    pub(crate) path: String,
+    pub(crate) tag: String,
+    pub(crate) artist: String,
+}
+
+pub(crate) fn db_aired_since(conn: &Connection, since_ms: u64) -> anyhow::Result<Vec<Aired>> {
+    crate::db_init(conn)?;
+    let mut stmt = conn.prepare(
+        "SELECT started_ms, path, tag, artist FROM play_history WHERE started_ms >= ?1 ORDER BY started_ms",
+    )?;
+    let rows = stmt.query_map(params![since_ms as i64], |row| {
+        Ok(Aired {
+            started_ms: row.get::<_, i64>(0)? as u64,
+            path: row.get(1)?,
+            tag: row.get(2)?,
+            artist: row.get(3)?,
+        })
+    })?;
+    Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+}
+
+/// What `record_start` stores about the item going on air.
+#[derive(Clone)]
+pub(crate) struct Started {
+    pub(crate) item_id: Uuid,
+    pub(crate) path: String,
+    pub(crate) cart: String,
+    pub(crate) tag: String,
+    pub(crate) title: String,
+    pub(crate) artist: String,
+    pub(crate) dur: String,
+}
+
+/// Record that an item just started playing. Another start of the same item
+/// while its row is open (the writer retrying a failed start) adds nothing.
+pub(crate) async fn record_start(s: Started) {
+    let res = crate::db::call(move |conn| -> anyhow::Result<()> {
+        crate::db_init(conn)?;
+        conn.prepare_cached(
+            "INSERT INTO play_history (started_ms, path, tag, title, artist, item_id, cart, dur)
+             SELECT ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8
+             WHERE NOT EXISTS (SELECT 1 FROM play_history WHERE item_id = ?6 AND ended_ms IS NULL)",
+        )?
+        .execute(params![unix_ms_now() as i64, s.path, s.tag, s.title, s.artist, s.item_id.to_string(), s.cart, s.dur])?;
+        Ok(())
+    })
+    .await;
+    if let Ok(Err(e)) = res {
+        tracing::warn!("history: failed to record play: {e}");
+    }
+}
+
+/// Record an item that never got on air as `errored`, unless it already has
+/// a row (a file that failed after starting; `record_end` closes that one).
+pub(crate) async fn record_errored(s: Started) {
+    let res = crate::db::call(move |conn| -> anyhow::Result<()> {
+        crate::db_init(conn)?;
+        let now = unix_ms_now() as i64;
+        conn.prepare_cached(
+            "INSERT INTO play_history (started_ms, ended_ms, outcome, path, tag, title, artist, item_id, cart, dur)
+             SELECT ?1, ?1, 'errored', ?2, ?3, ?4, ?5, ?6, ?7, ?8
+             WHERE NOT EXISTS (SELECT 1 FROM play_history WHERE item_id = ?6)",
+        )?
+        .execute(params![now, s.path, s.tag, s.title, s.artist, s.item_id.to_string(), s.cart, s.dur])?;
+        Ok(())
+    })
+    .await;
+    if let Ok(Err(e)) = res {
+        tracing::warn!("history: failed to record failed item: {e}");
+    }
+}
+
+/// Close the open row for `item_id` with how it ended. Items that never
+/// started (removed while queued) have no row and are ignored.
+pub(crate) async fn record_end(item_id: Uuid, outcome: String) {
+    let res = crate::db::call(move |conn| -> anyhow::Result<()> {
+        crate::db_init(conn)?;
+        conn.prepare_cached(
+            "UPDATE play_history SET ended_ms = ?1, outcome = ?2 WHERE item_id = ?3 AND ended_ms IS NULL",
+        )?
+        .execute(params![unix_ms_now() as i64, outcome, item_id.to_string()])?;
+        Ok(())
+    })
+    .await;
+    if let Ok(Err(e)) = res {
+        tracing::warn!("history: failed to record end of play: {e}");
+    }
+}
+
+/// At startup: rows still open from before `boot_ms` were on air when the
+/// engine stopped. Their end time is unknown, so they keep `ended_ms` empty.
+pub(crate) async fn close_interrupted(boot_ms: u64) {
+    let res = crate::db::call(move |conn| -> anyhow::Result<usize> {
+        crate::db_init(conn)?;
+        Ok(conn.execute(
+            "UPDATE play_history SET outcome = 'interrupted' WHERE outcome IS NULL AND started_ms < ?1",
+            params![boot_ms as i64],
+        )?)
+    })
+    .await;
+    match res {
+        Ok(Ok(n)) if n > 0 => tracing::info!("history: {n} item(s) interrupted by the last shutdown"),
+        Ok(Err(e)) => tracing::warn!("history: failed to close interrupted plays: {e}"),
+        _ => {}
+    }
+}
+
+/// The last `limit` items that left the air, oldest first, as `recent` holds
+/// them: `state` is how they ended. The item on air when the engine stopped
+/// (still open, or `interrupted`) is in the queue again, so it is left out.
+pub(crate) fn db_recent(conn: &Connection, limit: usize) -> anyhow::Result<Vec<LogItem>> {
+    crate::db_init(conn)?;
+    let mut stmt = conn.prepare(
+        "SELECT started_ms, item_id, tag, title, artist, cart, path, dur, outcome FROM play_history
+         WHERE outcome IS NOT NULL AND outcome != 'interrupted'
+         ORDER BY COALESCE(ended_ms, started_ms) DESC, id DESC LIMIT ?1",
+    )?;
+    let rows = stmt.query_map(params![limit as i64], |row| {
+        let started_ms = row.get::<_, i64>(0)? as u64;
+        let item_id: Option<String> = row.get(1)?;
+        let (cart, path, dur): (String, String, String) = (row.get(5)?, row.get(6)?, row.get(7)?);
+        Ok(LogItem {
+            // Rows from before item ids were recorded get a new one.
+            id: item_id.and_then(|id| Uuid::parse_str(&id).ok()).unwrap_or_else(Uuid::new_v4),
+            tag: row.get(2)?,
+            time: fmt_local_hhmmss(started_ms),
+            title: row.get(3)?,
+            artist: row.get(4)?,
+            state: row.get(8)?,
+            dur_ms: parse_dur_to_sec(&dur) as u64 * 1000,
+            dur,
+            cart: if cart.is_empty() { path } else { cart },
+            locked: false,
+            start_ms: Some(started_ms),
+            transition: None,
+            hard_start_ms: None,
+        })
+    })?;
+    let mut items = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+    items.reverse();
+    Ok(items)
+}
+
+/// `recent` for a starting engine; empty if the history cannot be read.
+pub(crate) async fn load_recent() -> VecDeque<LogItem> {
+    match crate::db::call(|conn| db_recent(conn, crate::MAX_RECENT)).await {
+        Ok(Ok(items)) => items.into(),
+        Ok(Err(e)) => {
+            tracing::warn!("history: failed to load recently played items: {e}");
+            VecDeque::new()
+        }
+        Err(_) => VecDeque::new(),
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct HistoryEntry {
+    pub(crate) started_ms: u64,
+    pub(crate) ended_ms: Option<u64>,
+    pub(crate) item_id: Option<String>,
+    pub(crate) tag: String,
+    pub(crate) title: String,
+    pub(crate) artist: String,
+    pub(crate) cart: String,
+    pub(crate) path: String,
+    pub(crate) dur: String,
+    /// `None` while the item is still on air.
+    pub(crate) outcome: Option<String>,
+}
+
+const ENTRY_COLUMNS: &str = "started_ms, ended_ms, item_id, tag, title, artist, cart, path, dur, outcome";
+
+fn entry_from_row(row: &rusqlite::Row) -> rusqlite::Result<HistoryEntry> {
+    Ok(HistoryEntry {
+        started_ms: row.get::<_, i64>(0)? as u64,
+        ended_ms: row.get::<_, Option<i64>>(1)?.map(|v| v as u64),
+        item_id: row.get(2)?,
+        tag: row.get(3)?,
+        title: row.get(4)?,
+        artist: row.get(5)?,
+        cart: row.get(6)?,
+        path: row.get(7)?,
+        dur: row.get(8)?,
+        outcome: row.get(9)?,
+    })
+}
+
+/// Everything that started in `[from_ms, to_ms)`, oldest first.
+pub(crate) fn db_entries_between(conn: &Connection, from_ms: u64, to_ms: u64) -> anyhow::Result<Vec<HistoryEntry>> {
+    crate::db_init(conn)?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {ENTRY_COLUMNS} FROM play_history WHERE started_ms >= ?1 AND started_ms < ?2 ORDER BY started_ms, id"
+    ))?;
+    let rows = stmt.query_map(params![from_ms as i64, to_ms as i64], entry_from_row)?;
+    Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+}
+
+// --- HTTP API -------------------------------------------------------------------
+
+#[derive(Deserialize)]
+pub(crate) struct HistoryQuery {
+    /// Local calendar day, `YYYY-MM-DD`. Overrides `from_ms`/`to_ms`.
+    date: Option<String>,
+    from_ms: Option<u64>,
+    to_ms: Option<u64>,
+    /// Only items that ended this way (`played`, `skipped`, ...).
+    outcome: Option<String>,
+    limit: Option<u32>,
+    offset: Option<u32>,
+}
+
+
+/// `GET /api/v1/history?date=YYYY-MM-DD|from_ms=&to_ms=&outcome=&limit=&offset=`, newest first.
+pub(crate) async fn api_history(Query(q): Query<HistoryQuery>) -> Result<Json<serde_json::Value>, StatusCode> {
+    let limit = q.limit.unwrap_or(50).clamp(1, 500);
+    let offset = q.offset.unwrap_or(0);
+    let (from_ms, to_ms) = match q.date.as_deref() {
+        Some(date) => {
+            let from = LocalHour::local_ms(date, 0).ok_or(StatusCode::BAD_REQUEST)?;
+            // mktime normalises hour 24 to midnight of the next day (23 or 25
+            // hours later on DST change days).
+            let to = LocalHour::local_ms(date, 24 * 60).ok_or(StatusCode::BAD_REQUEST)?;
+            (from, to)
+        }
+        None => (q.from_ms.unwrap_or(0), q.to_ms.unwrap_or(i64::MAX as u64)),
+    };
+    let (from_ms, to_ms) = (from_ms.min(i64::MAX as u64) as i64, to_ms.min(i64::MAX as u64) as i64);
+    let outcome = q.outcome;
+    let (total, items) = crate::db::call(move |conn| -> anyhow::Result<(i64, Vec<HistoryEntry>)> {
+        crate::db_init(conn)?;
+        const WHERE: &str = "WHERE started_ms >= ?1 AND started_ms < ?2 AND (?3 IS NULL OR outcome = ?3)";
+        let total: i64 = conn.query_row(
+            &format!("SELECT COUNT(*) FROM play_history {WHERE}"),
+            params![from_ms, to_ms, outcome],
+            |row| row.get(0),
+        )?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {ENTRY_COLUMNS} FROM play_history {WHERE} ORDER BY started_ms DESC, id DESC LIMIT ?4 OFFSET ?5"
+        ))?;
+        let rows = stmt.query_map(params![from_ms, to_ms, outcome, limit, offset], entry_from_row)?;
+        Ok((total, rows.collect::<rusqlite::Result<Vec<_>>>()?))
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|e| {
+        tracing::warn!("history list failed: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(json!({"total": total, "limit": limit, "offset": offset, "items": items})))
+}
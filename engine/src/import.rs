@@ -0,0 +1,644 @@
+// --- Playlist / log import -----------------------------------------------------
+//
+// Scheduling tools we want to interoperate with all speak some simple text
+// format. Each importer here turns its input into a list of `ImportEntry`
+// values; `resolve_entries` then maps each entry to a playable file (library,
+// carts, filesystem) and builds queue items with proper metadata.
+//
+// Import never half-applies silently: entries that cannot be resolved are
+// reported back with their line number and the reason, and the caller decides
+// (per endpoint) whether partial imports are acceptable.
+
+use std::collections::HashMap;
+use std::path::{Path as FsPath, PathBuf};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    Json,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    analysis, bump_queue_rev, check_queue_rev, daylog, fmt_dur_ms, library, normalize_log_state, paths, persist_queue,
+    resolve_cart_to_path, title_from_path, AppState, QueueInsertItem,
+};
+
+/// One line of an imported playlist/log, before resolution.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ImportEntry {
+    /// 1-based line number in the source, for error reports.
+    pub(crate) line: usize,
+    /// What the source says to play: a path, file name or cart number.
+    pub(crate) reference: String,
+    pub(crate) title: Option<String>,
+    pub(crate) artist: Option<String>,
+    pub(crate) dur_s: Option<u32>,
+    pub(crate) tag: Option<String>,
+}
+
+/// An entry we could not turn into a playable item.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct Unresolved {
+    pub(crate) line: usize,
+    pub(crate) entry: String,
+    pub(crate) error: String,
+}
+
+// --- M3U / PLS -------------------------------------------------------------------
+
+/// `#EXTINF:<seconds>,<Artist - Title>` (the display part is optional).
+fn parse_extinf(rest: &str) -> (Option<u32>, Option<String>, Option<String>) {
+    let (secs, display) = rest.split_once(',').unwrap_or((rest, ""));
+    // EXTINF may carry attributes after the length: `#EXTINF:180 tvg-id="x",...`
+    let secs = secs
+        .split_whitespace()
+        .next()
+        .and_then(|s| s.parse::<f64>().ok())
+        .filter(|s| *s > 0.0)
+        .map(|s| s.round() as u32);
+    let display = display.trim();
+    match display.split_once(" - ") {
+        Some((a, t)) => (secs, Some(t.trim().to_string()), Some(a.trim().to_string())),
+        None if !display.is_empty() => (secs, Some(display.to_string()), None),
+        None => (secs, None, None),
+    }
+}
+
+pub(crate) fn parse_m3u(text: &str) -> Vec<ImportEntry> {
+    let mut out = Vec::new();
+    let mut pending = ImportEntry::default();
+    for (i, raw) in text.lines().enumerate() {
+        let line = raw.trim().trim_start_matches('\u{feff}');
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(rest) = line.strip_prefix("#EXTINF:") {
+            let (dur_s, title, artist) = parse_extinf(rest);
+            pending = ImportEntry { dur_s, title, artist, ..Default::default() };
+            continue;
+        }
+        if line.starts_with('#') {
+            continue;
+        }
+        out.push(ImportEntry { line: i + 1, reference: line.to_string(), ..std::mem::take(&mut pending) });
+    }
+    out
+}
+
+pub(crate) fn parse_pls(text: &str) -> Vec<ImportEntry> {
+    use std::collections::BTreeMap;
+
+    // Entries are numbered (File1, Title1, Length1, ...) and may appear in any order.
+    let mut by_num: BTreeMap<u32, ImportEntry> = BTreeMap::new();
+    for (i, raw) in text.lines().enumerate() {
+        let Some((key, value)) = raw.trim().split_once('=') else { continue };
+        let key = key.trim().to_ascii_lowercase();
+        let value = value.trim();
+        let field = ["file", "title", "length"].into_iter().find(|f| key.starts_with(f));
+        let Some(field) = field else { continue };
+        let Ok(n) = key[field.len()..].parse::<u32>() else { continue };
+        let e = by_num.entry(n).or_default();
+        match field {
+            "file" => {
+                e.line = i + 1;
+                e.reference = value.to_string();
+            }
+            "title" => {
+                let (_, title, artist) = parse_extinf(&format!("0,{value}"));
+                e.title = title;
+                e.artist = artist;
+            }
+            _ => e.dur_s = value.parse::<i64>().ok().filter(|s| *s > 0).map(|s| s as u32),
+        }
+    }
+    by_num.into_values().filter(|e| !e.reference.is_empty()).collect()
+}
+
+// --- CSV ----------------------------------------------------------------------------
+
+/// Which CSV column holds a field: a header name or a 0-based index.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum CsvColumn {
+    Index(usize),
+    Name(String),
+}
+
+/// Column mapping for scheduler CSV exports. Stored, so the nightly import
+/// only has to send the file.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct CsvMapping {
+    #[serde(default = "default_csv_delimiter")]
+    delimiter: char,
+    #[serde(default = "default_true")]
+    has_header: bool,
+    cart: CsvColumn,
+    time: Option<CsvColumn>,
+    title: Option<CsvColumn>,
+    artist: Option<CsvColumn>,
+    length: Option<CsvColumn>,
+    tag: Option<CsvColumn>,
+}
+
+fn default_csv_delimiter() -> char {
+    ','
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_csv_mapping() -> CsvMapping {
+    let name = |n: &str| Some(CsvColumn::Name(n.into()));
+    CsvMapping {
+        delimiter: ',',
+        has_header: true,
+        cart: CsvColumn::Name("Cart".into()),
+        time: name("Time"),
+        title: name("Title"),
+        artist: name("Artist"),
+        length: name("Length"),
+        tag: None,
+    }
+}
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS csv_import_config (
+            id       INTEGER PRIMARY KEY CHECK (id = 1),
+            mapping  TEXT NOT NULL
+        );
+        "#,
+    )
+}
+
+fn db_load_csv_mapping(conn: &Connection) -> anyhow::Result<CsvMapping> {
+    crate::db_init(conn)?;
+    let json: Option<String> = conn
+        .query_row("SELECT mapping FROM csv_import_config WHERE id = 1", [], |row| row.get(0))
+        .optional()?;
+    match json {
+        Some(j) => Ok(serde_json::from_str(&j)?),
+        None => Ok(default_csv_mapping()),
+    }
+}
+
+fn db_save_csv_mapping(conn: &Connection, m: &CsvMapping) -> anyhow::Result<()> {
+    crate::db_init(conn)?;
+    conn.execute(
+        "INSERT INTO csv_import_config (id, mapping) VALUES (1, ?1)
+         ON CONFLICT(id) DO UPDATE SET mapping=excluded.mapping",
+        params![serde_json::to_string(m)?],
+    )?;
+    Ok(())
+}
+
+/// Minimal RFC 4180 reader: quoted fields, doubled quotes, CRLF, and newlines
+/// inside quotes. Returns each record with the line it started on.
+pub(crate) fn parse_csv(text: &str, delim: char) -> Vec<(usize, Vec<String>)> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut line = 1;
+    let mut start_line = 1;
+    let mut chars = text.trim_start_matches('\u{feff}').chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => in_quotes = false,
+                '\n' => {
+                    line += 1;
+                    field.push(c);
+                }
+                _ => field.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' if field.is_empty() => in_quotes = true,
+            '\r' => {}
+            '\n' => {
+                record.push(std::mem::take(&mut field));
+                if record.iter().any(|f| !f.trim().is_empty()) {
+                    records.push((start_line, std::mem::take(&mut record)));
+                }
+                record.clear();
+                line += 1;
+                start_line = line;
+            }
+            c if c == delim => record.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    record.push(field);
+    if record.iter().any(|f| !f.trim().is_empty()) {
+        records.push((start_line, record));
+    }
+    records
+}
+
+/// "3:45", "0:03:45", "225" or "225.4" -> seconds.
+pub(crate) fn parse_length_secs(s: &str) -> Option<u32> {
+    let mut total = 0.0f64;
+    for part in s.trim().split(':') {
+        total = total * 60.0 + part.trim().parse::<f64>().ok()?;
+    }
+    (total.is_finite() && total >= 0.0).then(|| total.round() as u32)
+}
+
+/// "6:00", "06:00:00" -> "06:00:00".
+pub(crate) fn normalize_clock(s: &str) -> Option<String> {
+    let parts: Vec<u32> = s.trim().split(':').map(|p| p.parse().ok()).collect::<Option<_>>()?;
+    let (h, m, sec) = match parts.as_slice() {
+        [h, m] => (*h, *m, 0),
+        [h, m, s] => (*h, *m, *s),
+        _ => return None,
+    };
+    (h < 24 && m < 60 && sec < 60).then(|| format!("{h:02}:{m:02}:{sec:02}"))
+}
+
+/// Scheduled times by source line.
+type LineTimes = HashMap<usize, String>;
+
+/// Parse a CSV log with `mapping`. Returns entries, their scheduled times,
+/// and lines that are malformed before any lookup happens.
+fn parse_csv_log(text: &str, mapping: &CsvMapping) -> Result<(Vec<ImportEntry>, LineTimes, Vec<Unresolved>), String> {
+    let mut records = parse_csv(text, mapping.delimiter).into_iter();
+    let header: Vec<String> = if mapping.has_header {
+        records.next().map(|(_, h)| h).unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+    let column = |c: &CsvColumn| -> Result<usize, String> {
+        match c {
+            CsvColumn::Index(i) => Ok(*i),
+            CsvColumn::Name(n) => header
+                .iter()
+                .position(|h| h.trim().eq_ignore_ascii_case(n.trim()))
+                .ok_or_else(|| format!("column {n:?} not found in header")),
+        }
+    };
+    let opt_column = |c: &Option<CsvColumn>| c.as_ref().map(column).transpose();
+
+    let cart_col = column(&mapping.cart)?;
+    let time_col = opt_column(&mapping.time)?;
+    let title_col = opt_column(&mapping.title)?;
+    let artist_col = opt_column(&mapping.artist)?;
+    let length_col = opt_column(&mapping.length)?;
+    let tag_col = opt_column(&mapping.tag)?;
+
+    let mut entries = Vec::new();
+    let mut times = HashMap::new();
+    let mut bad = Vec::new();
+    for (line, rec) in records {
+        let get = |c: Option<usize>| c.and_then(|i| rec.get(i)).map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+        let Some(cart) = get(Some(cart_col)) else {
+            bad.push(Unresolved { line, entry: rec.join(&mapping.delimiter.to_string()), error: "no cart".into() });
+            continue;
+        };
+        if let Some(t) = get(time_col) {
+            match normalize_clock(&t) {
+                Some(t) => {
+                    times.insert(line, t);
+                }
+                None => {
+                    bad.push(Unresolved { line, entry: cart, error: format!("invalid time {t:?}") });
+                    continue;
+                }
+            }
+        }
+        entries.push(ImportEntry {
+            line,
+            reference: cart,
+            title: get(title_col),
+            artist: get(artist_col),
+            dur_s: get(length_col).and_then(|l| parse_length_secs(&l)).filter(|d| *d > 0),
+            tag: get(tag_col).map(|t| t.to_ascii_uppercase()),
+        });
+    }
+    Ok((entries, times, bad))
+}
+
+// --- Resolution --------------------------------------------------------------------
+
+/// Turn entries into queue items. `base_dir` resolves relative paths (the
+/// directory of a server-side playlist file).
+pub(crate) async fn resolve_entries(
+    entries: Vec<ImportEntry>,
+    base_dir: Option<&FsPath>,
+) -> (Vec<(usize, QueueInsertItem)>, Vec<Unresolved>) {
+    let mut items = Vec::new();
+    let mut unresolved = Vec::new();
+
+    for e in entries {
+        match resolve_entry(&e, base_dir).await {
+            Ok(item) => items.push((e.line, item)),
+            Err(error) => unresolved.push(Unresolved { line: e.line, entry: e.reference.clone(), error }),
+        }
+    }
+    (items, unresolved)
+}
+
+pub(crate) async fn resolve_entry(e: &ImportEntry, base_dir: Option<&FsPath>) -> Result<QueueInsertItem, String> {
+    let reference = e.reference.trim();
+    if reference.contains("://") {
+        return Err("stream URLs are not supported".into());
+    }
+
+    // 1. The file itself (absolute, or relative to the playlist).
+    let mut path: Option<String> = None;
+    let candidate = if reference.starts_with('/') {
+        Some(PathBuf::from(reference))
+    } else {
+        base_dir.map(|b| b.join(reference.replace('\\', "/")))
+    };
+    if let Some(c) = candidate.filter(|c| c.is_file()) {
+        path = Some(paths::to_cart(&c));
+    }
+
+    // 2. A registered cart / carts-folder file.
+    if path.is_none() {
+        let r = reference.to_string();
+        path = tokio::task::spawn_blocking(move || resolve_cart_to_path(&r)).await.ok().flatten();
+    }
+
+    // 3. The library, by path or by file name (playlists from other machines).
+    let track = match &path {
+        Some(p) => library::find_track(p).await.ok().flatten(),
+        None => library::find_track(reference).await.ok().flatten(),
+    };
+    let path = match (path, &track) {
+        (Some(p), _) => p,
+        (None, Some(t)) => t.path.clone(),
+        (None, None) => return Err("not found in library, carts or filesystem".into()),
+    };
+
+    // Metadata: library first, then what the playlist said, then the file.
+    let (mut title, mut artist, mut dur_ms) = match &track {
+        Some(t) => (
+            Some(t.title.clone()),
+            Some(t.artist.clone()).filter(|a| !a.is_empty()),
+            Some(t.duration_s as u64 * 1000),
+        ),
+        None => (None, None, None),
+    };
+    title = title.or_else(|| e.title.clone());
+    artist = artist.or_else(|| e.artist.clone());
+    dur_ms = dur_ms.filter(|d| *d > 0).or(e.dur_s.map(|s| s as u64 * 1000));
+    if title.is_none() || dur_ms.is_none() {
+        let probe = analysis::probe_cached(&path).await?;
+        if !probe.has_audio {
+            return Err(format!("{path}: no audio stream"));
+        }
+        dur_ms = dur_ms.or(Some(probe.length_ms()).filter(|d| *d > 0));
+        title = title.or(probe.title);
+        artist = artist.or(probe.artist);
+    }
+
+    Ok(QueueInsertItem {
+        tag: e.tag.clone().or_else(|| track.as_ref().map(|t| t.tag.clone())).unwrap_or_else(|| "MUS".into()),
+        title: title.unwrap_or_else(|| title_from_path(&path)),
+        artist: artist.unwrap_or_default(),
+        dur: fmt_dur_ms(dur_ms.unwrap_or(0)),
+        dur_ms,
+        cart: path,
+        locked: false,
+    })
+}
+
+// --- HTTP API ----------------------------------------------------------------------
+
+#[derive(Deserialize)]
+pub(crate) struct QueueImportQuery {
+    /// "m3u" or "pls"; detected from the content/path when omitted.
+    format: Option<String>,
+}
+
+/// JSON form of `POST /api/v1/queue/import`.
+#[derive(Deserialize)]
+struct QueueImportReq {
+    /// Playlist text.
+    content: Option<String>,
+    /// Or: a playlist file on the server.
+    path: Option<String>,
+    format: Option<String>,
+    rev: Option<u64>,
+}
+
+/// Import endpoints take either the raw file as the body or a JSON envelope.
+pub(crate) fn is_json_request(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.starts_with("application/json"))
+        .unwrap_or(false)
+}
+
+/// Files referenced by a server-side playlist, for top-up (`playlist:<path>`).
+/// Entries resolve as absolute paths, paths relative to the playlist, or cart
+/// numbers; anything else is skipped. Blocking.
+pub(crate) fn playlist_paths(path: &str) -> anyhow::Result<Vec<String>> {
+    let text = std::fs::read(path).map_err(|e| anyhow::anyhow!("cannot read playlist {path}: {e}"))?;
+    let text = String::from_utf8_lossy(&text);
+    let entries = match detect_playlist_format(Some(path), &text) {
+        "pls" => parse_pls(&text),
+        _ => parse_m3u(&text),
+    };
+    let base = FsPath::new(path).parent();
+    let mut out = Vec::new();
+    for e in entries {
+        let reference = e.reference.trim();
+        let candidate = if reference.starts_with('/') {
+            Some(PathBuf::from(reference))
+        } else {
+            base.map(|b| b.join(reference.replace('\\', "/")))
+        };
+        match candidate.filter(|c| c.is_file()) {
+            Some(c) => out.push(paths::to_cart(&c)),
+            None => out.extend(resolve_cart_to_path(reference)),
+        }
+    }
+    Ok(out)
+}
+
+fn detect_playlist_format(hint: Option<&str>, text: &str) -> &'static str {
+    let hint = hint.unwrap_or("").to_ascii_lowercase();
+    if hint.ends_with("pls") {
+        return "pls";
+    }
+    if hint.ends_with("m3u") || hint.ends_with("m3u8") {
+        return "m3u";
+    }
+    if text.trim_start().to_ascii_lowercase().starts_with("[playlist]") { "pls" } else { "m3u" }
+}
+
+/// Append an M3U/M3U8/PLS playlist to the end of the queue.
+///
+/// Accepts either the raw playlist as the request body (`curl --data-binary
+/// @show.m3u`) or JSON `{"content": "..."}` / `{"path": "/srv/show.m3u"}`.
+pub(crate) async fn api_queue_import(
+    State(state): State<AppState>,
+    Query(q): Query<QueueImportQuery>,
+    headers: HeaderMap,
+    body: String,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    let bad = |msg: String| (StatusCode::BAD_REQUEST, Json(json!({"ok": false, "error": msg})));
+
+    let (text, base_dir, format_hint, rev) = if is_json_request(&headers) {
+        let req: QueueImportReq = serde_json::from_str(&body).map_err(|e| bad(format!("invalid JSON: {e}")))?;
+        match (req.content, req.path) {
+            (Some(content), None) => (content, None, req.format.or(q.format), req.rev),
+            (None, Some(path)) => {
+                let text = tokio::fs::read(&path)
+                    .await
+                    .map_err(|e| bad(format!("cannot read {path}: {e}")))?;
+                let base = FsPath::new(&path).parent().map(|p| p.to_path_buf());
+                (String::from_utf8_lossy(&text).into_owned(), base, req.format.or(q.format).or(Some(path)), req.rev)
+            }
+            _ => return Err(bad("exactly one of `content` or `path` is required".into())),
+        }
+    } else {
+        (body, None, q.format, None)
+    };
+
+    let entries = match detect_playlist_format(format_hint.as_deref(), &text) {
+        "pls" => parse_pls(&text),
+        _ => parse_m3u(&text),
+    };
+    if entries.is_empty() {
+        return Err(bad("playlist has no entries".into()));
+    }
+
+    let (items, unresolved) = resolve_entries(entries, base_dir.as_deref()).await;
+    if items.is_empty() {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({"ok": false, "error": "no playlist entry could be resolved", "unresolved": unresolved})),
+        ));
+    }
+
+    let mut p = state.playout.write().await;
+    if rev.is_some() || headers.contains_key(header::IF_MATCH) {
+        check_queue_rev(&p, &headers, rev)
+            .map_err(|code| (code, Json(json!({"ok": false, "error": "queue revision check failed"}))))?;
+    }
+    let added = items.len();
+    for (_, item) in items {
+        let state = if p.log.is_empty() { "playing" } else { "queued" };
+        p.log.push(item.into_log_item(state));
+    }
+    normalize_log_state(&mut p);
+    bump_queue_rev(&mut p);
+    persist_queue(p.log.clone()).await;
+
+    Ok(Json(json!({"ok": true, "rev": p.queue_rev, "added": added, "unresolved": unresolved})))
+}
+
+pub(crate) async fn api_csv_mapping_get() -> Result<Json<CsvMapping>, StatusCode> {
+    crate::db::call(move |conn| db_load_csv_mapping(conn))
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map(Json)
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+pub(crate) async fn api_csv_mapping_set(Json(mapping): Json<CsvMapping>) -> Result<Json<serde_json::Value>, StatusCode> {
+    crate::db::call(move |conn| db_save_csv_mapping(conn, &mapping))
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(json!({"ok": true})))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct CsvImportQuery {
+    /// Refuse the whole import if any line is unresolvable.
+    #[serde(default)]
+    strict: bool,
+}
+
+/// JSON form of the CSV import.
+#[derive(Deserialize)]
+struct CsvImportReq {
+    content: Option<String>,
+    path: Option<String>,
+    /// One-off mapping; the stored mapping is used when omitted.
+    mapping: Option<CsvMapping>,
+    #[serde(default)]
+    strict: bool,
+}
+
+/// Build the day log for `date` from a scheduler CSV export.
+///
+/// Every line must reference a cart/file that exists; lines that don't are
+/// reported with their line number. Without `strict` the resolvable lines are
+/// still saved, so a single bad cart doesn't block the whole day.
+pub(crate) async fn api_daylog_import_csv(
+    Path(date): Path<String>,
+    Query(q): Query<CsvImportQuery>,
+    headers: HeaderMap,
+    body: String,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    let bad = |msg: String| (StatusCode::BAD_REQUEST, Json(json!({"ok": false, "error": msg})));
+    let internal = || (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"ok": false})));
+
+    if !daylog::valid_date(&date) {
+        return Err(bad("date must be YYYY-MM-DD".into()));
+    }
+
+    let (text, mapping, strict) = if is_json_request(&headers) {
+        let req: CsvImportReq = serde_json::from_str(&body).map_err(|e| bad(format!("invalid JSON: {e}")))?;
+        let text = match (req.content, req.path) {
+            (Some(c), None) => c,
+            (None, Some(path)) => {
+                let raw = tokio::fs::read(&path).await.map_err(|e| bad(format!("cannot read {path}: {e}")))?;
+                String::from_utf8_lossy(&raw).into_owned()
+            }
+            _ => return Err(bad("exactly one of `content` or `path` is required".into())),
+        };
+        (text, req.mapping, req.strict || q.strict)
+    } else {
+        (body, None, q.strict)
+    };
+
+    let mapping = match mapping {
+        Some(m) => m,
+        None => {
+            crate::db::call(move |conn| db_load_csv_mapping(conn))
+            .await
+            .map_err(|_| internal())?
+            .map_err(|_| internal())?
+        }
+    };
+
+    let (entries, times, mut unresolved) = parse_csv_log(&text, &mapping).map_err(bad)?;
+    let (items, lookup_failures) = resolve_entries(entries, None).await;
+    unresolved.extend(lookup_failures);
+    unresolved.sort_by_key(|u| u.line);
+
+    if items.is_empty() || (strict && !unresolved.is_empty()) {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({"ok": false, "date": date, "imported": 0, "unresolved": unresolved})),
+        ));
+    }
+
+    let imported = items.len();
+    let day: Vec<daylog::DayLogItem> = items
+        .into_iter()
+        .map(|(line, item)| daylog::DayLogItem::from_insert(times.get(&line).cloned().unwrap_or_default(), item))
+        .collect();
+    daylog::save_day(date.clone(), day).await.map_err(|_| internal())?;
+
+    Ok(Json(json!({"ok": true, "date": date, "imported": imported, "unresolved": unresolved})))
+}
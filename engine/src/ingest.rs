@@ -0,0 +1,502 @@
+// --- Watch-folder ingest ------------------------------------------------------
+//
+// Operators (or an FTP/SMB share, or a music service's download folder) drop
+// audio files into a "drop folder". The ingest task notices them via inotify,
+// checks them, measures loudness, moves them into the managed library layout
+// (`<dest>/<Artist>/<Album>/<file>`) and indexes them in the media library.
+//
+// Design notes:
+// - inotify only tells us *something happened*. A file that is still being
+//   copied shows up long before it is complete, so a path is processed only
+//   once its size has been stable for `SETTLE`.
+// - Files that fail (not audio, undecodable) are left in the drop folder and
+//   the reason is recorded, so the operator can fix or delete them.
+// - Every attempt is recorded in `ingest_results` and exposed through
+//   `GET /api/v1/ingest/status`.
+
+use std::collections::HashMap;
+use std::path::{Path as FsPath, PathBuf};
+use std::time::{Duration, Instant};
+
+use axum::{extract::State, http::StatusCode, Json};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{analysis, library, paths, probe_media, unix_ms_now, waveform, AppState};
+
+/// How long a file's size must stay unchanged before we consider the copy done.
+const SETTLE: Duration = Duration::from_secs(3);
+
+/// Only this many results are kept in SQLite.
+const MAX_RESULTS: i64 = 500;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct IngestConfig {
+    pub(crate) enabled: bool,
+    /// Folder watched for new files.
+    pub(crate) drop_dir: String,
+    /// Root of the managed storage layout. Should be inside one of the library
+    /// directories, otherwise the next library scan drops the imported rows.
+    pub(crate) dest_dir: String,
+}
+
+pub(crate) fn default_ingest_config() -> IngestConfig {
+    // Off by default: ingest *moves* files, which nobody should be surprised by.
+    IngestConfig {
+        enabled: false,
+        drop_dir: "/opt/studiocommand/shared/ingest".into(),
+        dest_dir: "/opt/studiocommand/shared/data/library".into(),
+    }
+}
+
+#[derive(Clone, Serialize)]
+struct IngestResult {
+    file: String,
+    /// "imported" or "failed".
+    status: String,
+    dest: Option<String>,
+    library_id: Option<i64>,
+    title: Option<String>,
+    artist: Option<String>,
+    duration_s: Option<u32>,
+    loudness_lufs: Option<f64>,
+    error: Option<String>,
+    at_ms: u64,
+}
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS ingest_config (
+            id        INTEGER PRIMARY KEY CHECK (id = 1),
+            enabled   INTEGER NOT NULL,
+            drop_dir  TEXT NOT NULL,
+            dest_dir  TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS ingest_results (
+            id             INTEGER PRIMARY KEY AUTOINCREMENT,
+            file           TEXT NOT NULL,
+            status         TEXT NOT NULL,
+            dest           TEXT,
+            library_id     INTEGER,
+            title          TEXT,
+            artist         TEXT,
+            duration_s     INTEGER,
+            loudness_lufs  REAL,
+            error          TEXT,
+            at_ms          INTEGER NOT NULL
+        );
+        "#,
+    )
+}
+
+fn db_load_config(conn: &Connection) -> anyhow::Result<IngestConfig> {
+    crate::db_init(conn)?;
+    let row = conn.query_row(
+        "SELECT enabled, drop_dir, dest_dir FROM ingest_config WHERE id = 1",
+        [],
+        |row| {
+            Ok(IngestConfig {
+                enabled: row.get::<_, i64>(0)? != 0,
+                drop_dir: row.get(1)?,
+                dest_dir: row.get(2)?,
+            })
+        },
+    );
+    match row {
+        Ok(cfg) => Ok(cfg),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(default_ingest_config()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn db_save_config(conn: &Connection, cfg: &IngestConfig) -> anyhow::Result<()> {
+    crate::db_init(conn)?;
+    conn.execute(
+        "INSERT INTO ingest_config (id, enabled, drop_dir, dest_dir)
+         VALUES (1, ?1, ?2, ?3)
+         ON CONFLICT(id) DO UPDATE SET
+           enabled=excluded.enabled,
+           drop_dir=excluded.drop_dir,
+           dest_dir=excluded.dest_dir",
+        params![if cfg.enabled { 1 } else { 0 }, cfg.drop_dir, cfg.dest_dir],
+    )?;
+    Ok(())
+}
+
+fn db_record_result(conn: &Connection, r: &IngestResult) -> anyhow::Result<()> {
+    crate::db_init(conn)?;
+    conn.execute(
+        "INSERT INTO ingest_results
+           (file, status, dest, library_id, title, artist, duration_s, loudness_lufs, error, at_ms)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        params![
+            r.file,
+            r.status,
+            r.dest,
+            r.library_id,
+            r.title,
+            r.artist,
+            r.duration_s.map(|d| d as i64),
+            r.loudness_lufs,
+            r.error,
+            r.at_ms as i64
+        ],
+    )?;
+    conn.execute(
+        "DELETE FROM ingest_results WHERE id <= (SELECT MAX(id) FROM ingest_results) - ?1",
+        params![MAX_RESULTS],
+    )?;
+    Ok(())
+}
+
+fn db_recent_results(conn: &Connection, limit: u32) -> anyhow::Result<Vec<IngestResult>> {
+    crate::db_init(conn)?;
+    let mut stmt = conn.prepare(
+        "SELECT file, status, dest, library_id, title, artist, duration_s, loudness_lufs, error, at_ms
+         FROM ingest_results ORDER BY id DESC LIMIT ?1",
+    )?;
+    let rows = stmt.query_map(params![limit as i64], |row| {
+        Ok(IngestResult {
+            file: row.get(0)?,
+            status: row.get(1)?,
+            dest: row.get(2)?,
+            library_id: row.get(3)?,
+            title: row.get(4)?,
+            artist: row.get(5)?,
+            duration_s: row.get::<_, Option<i64>>(6)?.map(|d| d as u32),
+            loudness_lufs: row.get(7)?,
+            error: row.get(8)?,
+            at_ms: row.get::<_, i64>(9)? as u64,
+        })
+    })?;
+    Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+}
+
+pub(crate) async fn load_ingest_config_from_db_or_default() -> IngestConfig {
+    let res = crate::db::call(move |conn| db_load_config(conn)).await;
+    match res {
+        Ok(Ok(cfg)) => cfg,
+        Ok(Err(e)) => {
+            tracing::warn!("ingest: failed to load config, using defaults: {e}");
+            default_ingest_config()
+        }
+        Err(e) => {
+            tracing::warn!("ingest: config load task failed, using defaults: {e}");
+            default_ingest_config()
+        }
+    }
+}
+
+/// Runtime view of the ingest task, for the status endpoint.
+#[derive(Clone, Serialize, Default)]
+pub(crate) struct IngestRuntime {
+    /// The drop folder currently being watched (None when disabled/broken).
+    watching: Option<String>,
+    /// Files seen but not yet settled/processed.
+    pending: Vec<String>,
+    last_error: Option<String>,
+}
+
+fn is_audio_file(p: &FsPath) -> bool {
+    let allowed = ["mp3", "flac", "wav", "m4a", "aac", "ogg", "opus"];
+    p.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| allowed.contains(&e.to_ascii_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+/// Make a tag value safe to use as a single path component.
+fn path_component(s: &str, fallback: &str) -> String {
+    let cleaned: String = s
+        .trim()
+        .chars()
+        .map(|c| if c == '/' || c == '\0' || c.is_control() { '_' } else { c })
+        .collect();
+    let cleaned = cleaned.trim_matches('.').trim().to_string();
+    if cleaned.is_empty() { fallback.to_string() } else { cleaned }
+}
+
+/// Move `src` to `dest`, falling back to copy+delete when the drop folder and
+/// the library live on different filesystems (rename fails with EXDEV).
+fn move_file(src: &FsPath, dest: &FsPath) -> std::io::Result<()> {
+    match std::fs::rename(src, dest) {
+        Ok(()) => Ok(()),
+        Err(e) if e.raw_os_error() == Some(libc::EXDEV) => {
+            std::fs::copy(src, dest)?;
+            std::fs::remove_file(src)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Pick `<dest>/<Artist>/<Album>/<file name>`, adding " (2)", " (3)", ... if
+/// something already lives there. Never overwrites library content.
+fn managed_path(dest_root: &str, artist: &str, album: &str, src: &FsPath) -> PathBuf {
+    let dir = PathBuf::from(dest_root)
+        .join(path_component(artist, "Unknown Artist"))
+        .join(path_component(album, "Singles"));
+    // Built from the name's bytes, which need not be UTF-8.
+    let stem = src.file_stem().unwrap_or("track".as_ref());
+    let ext = src.extension().unwrap_or_default();
+    let mut candidate = dir.join(src.file_name().unwrap_or_default());
+    let mut n = 2;
+    while candidate.exists() {
+        let mut name = stem.to_os_string();
+        name.push(format!(" ({n})"));
+        if !ext.is_empty() {
+            name.push(".");
+            name.push(ext);
+        }
+        candidate = dir.join(name);
+        n += 1;
+    }
+    candidate
+}
+
+async fn ingest_one(src: &FsPath, cfg: &IngestConfig) -> IngestResult {
+    let file = paths::to_cart(src);
+    let mut res = IngestResult {
+        file: file.clone(),
+        status: "failed".into(),
+        dest: None,
+        library_id: None,
+        title: None,
+        artist: None,
+        duration_s: None,
+        loudness_lufs: None,
+        error: None,
+        at_ms: unix_ms_now(),
+    };
+
+    let probe = match probe_media(&file).await {
+        Ok(p) if p.has_audio && p.duration_s > 0 => p,
+        Ok(_) => {
+            res.error = Some("no audio stream or unknown duration".into());
+            return res;
+        }
+        Err(e) => {
+            res.error = Some(e);
+            return res;
+        }
+    };
+    res.title = probe.title.clone();
+    res.artist = probe.artist.clone();
+    res.duration_s = Some(probe.duration_s);
+
+    // Loudness is informative, not a gate: a file we cannot measure still plays.
+    let loudness = match analysis::analyze_loudness(&file).await {
+        Ok(l) => Some(l),
+        Err(e) => {
+            tracing::warn!("ingest: {e}");
+            None
+        }
+    };
+    res.loudness_lufs = loudness.map(|l| l.integrated_lufs);
+
+    let dest = managed_path(
+        &cfg.dest_dir,
+        probe.artist.as_deref().unwrap_or(""),
+        probe.album.as_deref().unwrap_or(""),
+        src,
+    );
+    let (src_owned, dest_owned) = (src.to_path_buf(), dest.clone());
+    let moved = tokio::task::spawn_blocking(move || -> std::io::Result<()> {
+        if let Some(parent) = dest_owned.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        move_file(&src_owned, &dest_owned)
+    })
+    .await;
+    match moved {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => {
+            res.error = Some(format!("move to {} failed: {e}", dest.display()));
+            return res;
+        }
+        Err(e) => {
+            res.error = Some(format!("move task failed: {e}"));
+            return res;
+        }
+    }
+    let dest = paths::to_cart(&dest);
+    res.dest = Some(dest.clone());
+    analysis::store(&dest, &probe, loudness).await;
+    {
+        // Waveform peaks are only for the UI; build them off the ingest path.
+        let dest = dest.clone();
+        tokio::spawn(async move {
+            if let Err(e) = waveform::peaks_for(&dest).await {
+                tracing::warn!("ingest: {e}");
+            }
+        });
+    }
+
+    match library::import_file(&dest, &probe, res.loudness_lufs).await {
+        Ok(id) => {
+            res.library_id = Some(id);
+            res.status = "imported".into();
+        }
+        Err(e) => res.error = Some(format!("moved, but library import failed: {e}")),
+    }
+    res
+}
+
+async fn record_result(res: IngestResult) {
+    match res.status.as_str() {
+        "imported" => tracing::info!("ingest: imported {} -> {}", res.file, res.dest.as_deref().unwrap_or("")),
+        _ => tracing::warn!("ingest: {} failed: {}", res.file, res.error.as_deref().unwrap_or("")),
+    }
+    let saved = crate::db::call(move |conn| db_record_result(conn, &res)).await;
+    if let Ok(Err(e)) = saved {
+        tracing::warn!("ingest: failed to record result: {e}");
+    }
+}
+
+/// Start watching `dir` and forward the paths inotify reports into `tx`.
+fn start_watcher(
+    dir: &str,
+    tx: tokio::sync::mpsc::UnboundedSender<PathBuf>,
+) -> notify::Result<notify::RecommendedWatcher> {
+    use notify::{Event, RecursiveMode, Watcher};
+
+    let mut watcher = notify::recommended_watcher(move |ev: notify::Result<Event>| {
+        if let Ok(ev) = ev {
+            for p in ev.paths {
+                let _ = tx.send(p);
+            }
+        }
+    })?;
+    watcher.watch(FsPath::new(dir), RecursiveMode::Recursive)?;
+    Ok(watcher)
+}
+
+/// Background ingest loop. Follows config changes made through the API: the
+/// watcher is rebuilt whenever `enabled` or `drop_dir` changes.
+pub(crate) async fn ingest_task(
+    config: std::sync::Arc<tokio::sync::Mutex<IngestConfig>>,
+    runtime: std::sync::Arc<tokio::sync::Mutex<IngestRuntime>>,
+) {
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<PathBuf>();
+    let mut watcher: Option<(String, notify::RecommendedWatcher)> = None;
+    // path -> (last size, when the size last changed)
+    let mut pending: HashMap<PathBuf, (u64, Instant)> = HashMap::new();
+    let mut tick = tokio::time::interval(Duration::from_secs(1));
+
+    loop {
+        tokio::select! {
+            Some(p) = rx.recv() => {
+                if is_audio_file(&p) {
+                    pending.entry(p).or_insert((u64::MAX, Instant::now()));
+                }
+                continue;
+            }
+            _ = tick.tick() => {}
+        }
+
+        let cfg = config.lock().await.clone();
+        let want = cfg.enabled.then(|| cfg.drop_dir.clone());
+        if watcher.as_ref().map(|(d, _)| d) != want.as_ref() {
+            watcher = None;
+            pending.clear();
+            let mut rt = runtime.lock().await;
+            rt.watching = None;
+            rt.last_error = None;
+            if let Some(dir) = want {
+                if let Err(e) = std::fs::create_dir_all(&dir) {
+                    rt.last_error = Some(format!("cannot create drop folder {dir}: {e}"));
+                } else {
+                    match start_watcher(&dir, tx.clone()) {
+                        Ok(w) => {
+                            // Pick up whatever was dropped while we were not watching.
+                            if let Ok(files) = crate::scan_audio_files_recursive(&dir) {
+                                for f in files {
+                                    pending.insert(paths::from_cart(&f), (u64::MAX, Instant::now()));
+                                }
+                            }
+                            tracing::info!("ingest: watching {dir}");
+                            rt.watching = Some(dir.clone());
+                            watcher = Some((dir, w));
+                        }
+                        Err(e) => rt.last_error = Some(format!("cannot watch {dir}: {e}")),
+                    }
+                }
+            }
+            // Back off before retrying a drop folder we could not watch
+            // (e.g. a share that is not mounted yet).
+            if watcher.is_none() && rt.last_error.is_some() {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            continue;
+        }
+        if watcher.is_none() {
+            continue;
+        }
+
+        // Work out which pending files have settled.
+        let mut ready = Vec::new();
+        pending.retain(|p, (size, since)| {
+            let Ok(md) = std::fs::metadata(p) else {
+                return false; // gone (moved away / deleted)
+            };
+            if !md.is_file() {
+                return false;
+            }
+            if md.len() != *size {
+                *size = md.len();
+                *since = Instant::now();
+                return true;
+            }
+            if since.elapsed() >= SETTLE {
+                ready.push(p.clone());
+                return false;
+            }
+            true
+        });
+        runtime.lock().await.pending = pending.keys().map(|p| paths::to_cart(p)).collect();
+
+        for p in ready {
+            let res = ingest_one(&p, &cfg).await;
+            record_result(res).await;
+        }
+    }
+}
+
+// --- HTTP API -------------------------------------------------------------------
+
+pub(crate) async fn api_ingest_status(State(state): State<AppState>) -> Result<Json<serde_json::Value>, StatusCode> {
+    let cfg = state.ingest.lock().await.clone();
+    let rt = state.ingest_runtime.lock().await.clone();
+    let results = crate::db::call(move |conn| db_recent_results(conn, 100))
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(json!({"config": cfg, "runtime": rt, "results": results})))
+}
+
+pub(crate) async fn api_ingest_set_config(
+    State(state): State<AppState>,
+    Json(mut cfg): Json<IngestConfig>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    cfg.drop_dir = cfg.drop_dir.trim().to_string();
+    cfg.dest_dir = cfg.dest_dir.trim().to_string();
+    if !cfg.drop_dir.starts_with('/') || !cfg.dest_dir.starts_with('/') {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    // Watching the destination would re-ingest everything we just moved.
+    if FsPath::new(&cfg.dest_dir).starts_with(&cfg.drop_dir) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let cfg_clone = cfg.clone();
+    crate::db::call(move |conn| db_save_config(conn, &cfg_clone))
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    *state.ingest.lock().await = cfg;
+    Ok(Json(json!({"ok": true})))
+}
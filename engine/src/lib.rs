@@ -0,0 +1,162 @@
+// StudioCommand engine
+//
+// The engine is a library with a thin binary (`main.rs`) on top, so it can be
+// embedded headless: `Engine::builder()` (engine.rs) loads the state and
+// starts playout, and the HTTP API (api.rs, queue.rs and the handlers next to
+// each subsystem) is one way of driving it. The subsystems:
+//   - playout.rs: the queue, the audio writer and decoding
+//   - output.rs: the Icecast encoder; aes67.rs: AoIP; stl.rs: SRT to the transmitter;
+//     listen.rs: WebRTC "Listen Live"
+//   - topup.rs: queue top-up; persistence.rs: queue and config in SQLite
+//
+// The process-wide pieces (meters, logging, shutdown) are statics, so there is
+// one engine per process.
+
+use std::sync::Arc;
+
+use sysinfo::System;
+
+mod aes67;
+mod alerts;
+mod analysis;
+mod announce;
+mod api;
+mod art;
+mod asrun;
+mod azuracast;
+mod backup;
+mod bots;
+mod breaks;
+mod callers;
+mod cartwall;
+mod carts;
+mod changes;
+mod clocks;
+mod cron;
+mod daylog;
+mod db;
+mod diagnostics;
+mod emergency;
+mod engine;
+mod eventlog;
+mod events;
+mod export;
+mod failures;
+mod fallback;
+mod ffmpeg;
+mod gpio;
+mod history;
+mod import;
+mod ingest;
+mod library;
+mod listen;
+mod logbuf;
+mod macros;
+mod maintenance;
+mod metapush;
+mod meters;
+mod migrations;
+mod mpd;
+mod mqtt;
+mod output;
+mod overlay;
+mod paths;
+mod persistence;
+mod playout;
+mod preview;
+mod producers;
+mod public;
+mod queue;
+mod rds;
+mod recorder;
+mod reload;
+mod remote;
+mod requests;
+mod resume;
+mod rivendell;
+mod rotation;
+mod schedule;
+mod secrets;
+mod selfcheck;
+mod serve;
+mod settings;
+mod shufflebag;
+mod simulate;
+mod shutdown;
+mod snapshot;
+mod standby;
+mod stl;
+mod storage;
+mod supervisor;
+mod surfaces;
+mod tags;
+mod testtone;
+mod timesync;
+mod topup;
+mod topuplog;
+mod transitions;
+mod update;
+mod voicetrack;
+mod waveform;
+
+// Items of the split-out subsystems are used across the crate by their short
+// names (`crate::LogItem`, `crate::advance_to_next`).
+use api::*;
+use listen::*;
+use output::*;
+use persistence::*;
+use playout::*;
+use queue::*;
+use testtone::*;
+use topup::*;
+
+pub use engine::{Engine, EngineBuilder};
+pub use playout::{LogItem, NowPlaying, VuLevels};
+pub use reload::init_logging;
+pub use transitions::{Segue, Transition};
+
+#[derive(Clone)]
+struct AppState {
+    version: String,
+    sys: Arc<tokio::sync::Mutex<System>>,
+    playout: Arc<tokio::sync::RwLock<PlayoutState>>,
+    topup: Arc<tokio::sync::Mutex<TopUpConfig>>,
+    topup_stats: Arc<tokio::sync::Mutex<TopUpStats>>,
+    output: Arc<tokio::sync::Mutex<OutputRuntime>>,
+
+    // Broadcast of real-time PCM chunks (s16le stereo @ 48 kHz).
+    //
+    // This is the *single source of truth* for:
+    //   - Icecast encoding (ffmpeg stdin)
+    //   - UI meters/progress (derived from PCM)
+    //   - WebRTC \"Listen Live\" monitor (Opus)
+    //
+    // We keep it as a broadcast channel so multiple WebRTC listeners can
+    // subscribe without changing the core audio pipeline.
+    pcm_tx: tokio::sync::broadcast::Sender<Vec<u8>>,
+
+    // Active WebRTC "Listen Live" session (if any).
+    //
+    // We intentionally keep *at most one* active session for now because this
+    // feature is primarily a low-latency *operator monitor* rather than a
+    // public listener endpoint. This also keeps the signaling simple: the UI
+    // can POST ICE candidates to `/api/v1/webrtc/candidate` without needing a
+    // session id.
+    //
+    // If/when you want multiple concurrent listeners, we can evolve this into
+    // a map keyed by a session UUID returned from the `/offer` response.
+    webrtc: Arc<tokio::sync::Mutex<Option<WebRtcRuntime>>>,
+
+    // Progress of the current/last media library scan (see `library.rs`).
+    library_scan: Arc<tokio::sync::Mutex<library::LibraryScanStatus>>,
+
+    // Watch-folder ingest (see `ingest.rs`): persisted config + live state.
+    ingest: Arc<tokio::sync::Mutex<ingest::IngestConfig>>,
+    ingest_runtime: Arc<tokio::sync::Mutex<ingest::IngestRuntime>>,
+
+    // What `/api/v1/status` serves, refreshed from the
+    // playout state by `snapshot::refresh_task` (see `snapshot.rs`).
+    snapshot: tokio::sync::watch::Receiver<Arc<snapshot::Snapshot>>,
+}
+
+
@@ -0,0 +1,749 @@
+// --- Media library ------------------------------------------------------------
+//
+// The library is an index of the audio files under a set of configured
+// directories, so operators (and later the scheduler) can browse and insert
+// real content instead of hand-typing cart strings or absolute paths.
+//
+// Design notes:
+// - The files on disk stay the source of truth. The `library_tracks` table is
+//   a cache of what we learned about them (tags, duration, ...), keyed by path.
+// - Scans are incremental: a file whose mtime and size are unchanged is not
+//   probed again, so rescanning a large NAS share is cheap.
+// - Scans run in the background and report progress through
+//   `GET /api/v1/library/scan`; they never hold the playout lock.
+// - Columns such as `loudness_lufs` are part of the schema from day one even
+//   though they are filled in by later analysis passes.
+// - The intro cue (`intro_ms`, where the vocal starts) is set by an operator
+//   through `PATCH /api/v1/library/{id}`; scans never touch it. While the
+//   track plays, now-playing counts down to it (`intro_remaining_sec`).
+
+use std::collections::{HashMap, HashSet};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{analysis, paths, scan_audio_files_recursive, tags, title_from_path, unix_ms_now, AppState};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct LibraryConfig {
+    /// Directories scanned (recursively) into the library.
+    pub(crate) dirs: Vec<String>,
+}
+
+fn default_library_config() -> LibraryConfig {
+    // Same installer-managed folder top-up uses, so a fresh install has a
+    // non-empty library without any configuration.
+    LibraryConfig { dirs: vec!["/opt/studiocommand/shared/data".into()] }
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct LibraryTrack {
+    pub(crate) id: i64,
+    pub(crate) path: String,
+    pub(crate) title: String,
+    pub(crate) artist: String,
+    pub(crate) album: String,
+    pub(crate) duration_s: u32,
+    /// Integrated loudness (LUFS); `None` until analyzed.
+    pub(crate) loudness_lufs: Option<f64>,
+    pub(crate) last_played_ms: Option<u64>,
+    pub(crate) added_ms: u64,
+    /// Queue tag used when the track is inserted (MUS, ID, SPOT, ...).
+    pub(crate) tag: String,
+    /// End of the intro (ms from the start of the file), when cued.
+    pub(crate) intro_ms: Option<u32>,
+}
+
+/// Progress/result of the most recent library scan.
+#[derive(Clone, Serialize, Default)]
+pub(crate) struct LibraryScanStatus {
+    running: bool,
+    started_ms: Option<u64>,
+    finished_ms: Option<u64>,
+    files_seen: u32,
+    added: u32,
+    updated: u32,
+    removed: u32,
+    /// Files that exist but could not be probed (corrupt, unsupported, ...).
+    failed: u32,
+    last_error: Option<String>,
+}
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS library_tracks (
+            id             INTEGER PRIMARY KEY AUTOINCREMENT,
+            path           TEXT NOT NULL UNIQUE,
+            title          TEXT NOT NULL,
+            artist         TEXT NOT NULL,
+            album          TEXT NOT NULL,
+            duration_s     INTEGER NOT NULL,
+            loudness_lufs  REAL,
+            last_played_ms INTEGER,
+            added_ms       INTEGER NOT NULL,
+            mtime          INTEGER NOT NULL,
+            size           INTEGER NOT NULL
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_library_tracks_artist ON library_tracks(artist);
+        CREATE INDEX IF NOT EXISTS idx_library_tracks_title ON library_tracks(title);
+
+        CREATE TABLE IF NOT EXISTS library_config (
+            id    INTEGER PRIMARY KEY CHECK (id = 1),
+            dirs  TEXT NOT NULL
+        );
+        "#,
+    )?;
+    crate::db_add_column_if_missing(conn, "library_tracks", "tag", "TEXT NOT NULL DEFAULT 'MUS'")?;
+
+    // Full-text index over the human-facing columns. It is an external-content
+    // table, so the triggers below keep it in step with `library_tracks`.
+    let fts_exists: bool = conn
+        .query_row(
+            "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'library_fts'",
+            [],
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some();
+    conn.execute_batch(
+        r#"
+        CREATE VIRTUAL TABLE IF NOT EXISTS library_fts USING fts5(
+            title, artist, album,
+            content='library_tracks', content_rowid='id',
+            tokenize='unicode61 remove_diacritics 2'
+        );
+
+        CREATE TRIGGER IF NOT EXISTS library_fts_ai AFTER INSERT ON library_tracks BEGIN
+            INSERT INTO library_fts(rowid, title, artist, album)
+            VALUES (new.id, new.title, new.artist, new.album);
+        END;
+        CREATE TRIGGER IF NOT EXISTS library_fts_ad AFTER DELETE ON library_tracks BEGIN
+            INSERT INTO library_fts(library_fts, rowid, title, artist, album)
+            VALUES ('delete', old.id, old.title, old.artist, old.album);
+        END;
+        "#,
+    )?;
+    conn.execute_batch(FTS_UPDATE_TRIGGER)?;
+    if !fts_exists {
+        // Index rows scanned before the FTS table existed.
+        conn.execute("INSERT INTO library_fts(library_fts) VALUES ('rebuild')", [])?;
+    }
+    Ok(())
+}
+
+/// Re-indexes a track when an indexed column changes; play-count and
+/// last-played writes leave the index alone.
+const FTS_UPDATE_TRIGGER: &str = r#"
+    CREATE TRIGGER IF NOT EXISTS library_fts_au AFTER UPDATE OF title, artist, album ON library_tracks BEGIN
+        INSERT INTO library_fts(library_fts, rowid, title, artist, album)
+        VALUES ('delete', old.id, old.title, old.artist, old.album);
+        INSERT INTO library_fts(rowid, title, artist, album)
+        VALUES (new.id, new.title, new.artist, new.album);
+    END;
+"#;
+
+const TRACK_COLUMNS: &str =
+    "id, path, title, artist, album, duration_s, loudness_lufs, last_played_ms, added_ms, tag, intro_ms";
+
+fn track_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<LibraryTrack> {
+    Ok(LibraryTrack {
+        id: row.get(0)?,
+        path: row.get(1)?,
+        title: row.get(2)?,
+        artist: row.get(3)?,
+        album: row.get(4)?,
+        duration_s: row.get::<_, i64>(5)? as u32,
+        loudness_lufs: row.get(6)?,
+        last_played_ms: row.get::<_, Option<i64>>(7)?.map(|v| v as u64),
+        added_ms: row.get::<_, i64>(8)? as u64,
+        tag: row.get(9)?,
+        intro_ms: row.get::<_, Option<i64>>(10)?.map(|v| v as u32),
+    })
+}
+
+fn db_load_config(conn: &Connection) -> anyhow::Result<LibraryConfig> {
+    crate::db_init(conn)?;
+    let dirs: Option<String> = conn
+        .query_row("SELECT dirs FROM library_config WHERE id = 1", [], |row| row.get(0))
+        .optional()?;
+    match dirs {
+        Some(json) => Ok(LibraryConfig { dirs: serde_json::from_str(&json)? }),
+        None => Ok(default_library_config()),
+    }
+}
+
+fn db_save_config(conn: &Connection, cfg: &LibraryConfig) -> anyhow::Result<()> {
+    crate::db_init(conn)?;
+    conn.execute(
+        "INSERT INTO library_config (id, dirs) VALUES (1, ?1)
+         ON CONFLICT(id) DO UPDATE SET dirs=excluded.dirs",
+        params![serde_json::to_string(&cfg.dirs)?],
+    )?;
+    Ok(())
+}
+
+fn db_list(conn: &Connection, q: Option<&str>, limit: u32, offset: u32) -> anyhow::Result<(u64, Vec<LibraryTrack>)> {
+    crate::db_init(conn)?;
+
+    // Plain substring match (paths included); ranked word search lives in
+    // `db_search`.
+    let pattern = format!("%{}%", q.unwrap_or("").trim());
+    let filter = "title LIKE ?1 OR artist LIKE ?1 OR album LIKE ?1 OR path LIKE ?1";
+
+    let total: i64 = conn.query_row(
+        &format!("SELECT COUNT(*) FROM library_tracks WHERE {filter}"),
+        params![pattern],
+        |row| row.get(0),
+    )?;
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {TRACK_COLUMNS} FROM library_tracks WHERE {filter}
+         ORDER BY artist COLLATE NOCASE, title COLLATE NOCASE LIMIT ?2 OFFSET ?3"
+    ))?;
+    let items = stmt
+        .query_map(params![pattern, limit as i64, offset as i64], track_from_row)?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    Ok((total as u64, items))
+}
+
+/// Filters for `GET /api/v1/library/search`.
+#[derive(Deserialize)]
+pub(crate) struct LibrarySearchQuery {
+    q: Option<String>,
+    artist: Option<String>,
+    tag: Option<String>,
+    min_dur: Option<u32>,
+    max_dur: Option<u32>,
+    page: Option<u32>,
+    per_page: Option<u32>,
+}
+
+/// Turn free text into an FTS5 query: every word must match as a prefix, so
+/// typing "pointer neut" during a live show already finds "Neutron Dance".
+fn fts_query(q: &str) -> Option<String> {
+    let terms: Vec<String> = q
+        .split_whitespace()
+        .map(|w| format!("\"{}\"*", w.replace('"', "\"\"")))
+        .collect();
+    (!terms.is_empty()).then(|| terms.join(" "))
+}
+
+fn db_search(conn: &Connection, f: &LibrarySearchQuery, per_page: u32, page: u32) -> anyhow::Result<(u64, Vec<LibraryTrack>)> {
+    use rusqlite::types::Value;
+
+    crate::db_init(conn)?;
+
+    let mut from = String::from("library_tracks t");
+    let mut clauses: Vec<&str> = Vec::new();
+    let mut args: Vec<Value> = Vec::new();
+
+    let fts = f.q.as_deref().and_then(fts_query);
+    if let Some(m) = &fts {
+        from.push_str(" JOIN library_fts ON library_fts.rowid = t.id");
+        clauses.push("library_fts MATCH ?");
+        args.push(Value::Text(m.clone()));
+    }
+    if let Some(artist) = f.artist.as_deref().map(str::trim).filter(|a| !a.is_empty()) {
+        clauses.push("t.artist = ? COLLATE NOCASE");
+        args.push(Value::Text(artist.to_string()));
+    }
+    if let Some(tag) = f.tag.as_deref().map(str::trim).filter(|t| !t.is_empty()) {
+        clauses.push("t.tag = ?");
+        args.push(Value::Text(tag.to_ascii_uppercase()));
+    }
+    if let Some(min) = f.min_dur {
+        clauses.push("t.duration_s >= ?");
+        args.push(Value::Integer(min as i64));
+    }
+    if let Some(max) = f.max_dur {
+        clauses.push("t.duration_s <= ?");
+        args.push(Value::Integer(max as i64));
+    }
+    let filter = if clauses.is_empty() { String::new() } else { format!("WHERE {}", clauses.join(" AND ")) };
+
+    let total: i64 = conn.query_row(
+        &format!("SELECT COUNT(*) FROM {from} {filter}"),
+        rusqlite::params_from_iter(args.iter()),
+        |row| row.get(0),
+    )?;
+
+    let order = if fts.is_some() { "library_fts.rank" } else { "t.artist COLLATE NOCASE, t.title COLLATE NOCASE" };
+    let columns = TRACK_COLUMNS.split(", ").map(|c| format!("t.{c}")).collect::<Vec<_>>().join(", ");
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {columns} FROM {from} {filter} ORDER BY {order} LIMIT ? OFFSET ?"
+    ))?;
+    args.push(Value::Integer(per_page as i64));
+    args.push(Value::Integer(page.saturating_sub(1) as i64 * per_page as i64));
+    let items = stmt
+        .query_map(rusqlite::params_from_iter(args.iter()), track_from_row)?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    Ok((total as u64, items))
+}
+
+/// Plain text search (first `limit` matches), for callers outside the library API.
+pub(crate) fn db_search_text(conn: &Connection, q: &str, limit: u32) -> anyhow::Result<Vec<LibraryTrack>> {
+    let f = LibrarySearchQuery {
+        q: Some(q.to_string()),
+        artist: None,
+        tag: None,
+        min_dur: None,
+        max_dur: None,
+        page: None,
+        per_page: None,
+    };
+    Ok(db_search(conn, &f, limit, 1)?.1)
+}
+
+pub(crate) fn db_get(conn: &Connection, id: i64) -> anyhow::Result<Option<LibraryTrack>> {
+    crate::db_init(conn)?;
+    Ok(conn
+        .query_row(
+            &format!("SELECT {TRACK_COLUMNS} FROM library_tracks WHERE id = ?1"),
+            params![id],
+            track_from_row,
+        )
+        .optional()?)
+}
+
+fn db_set_tag(conn: &Connection, id: i64, tag: &str) -> anyhow::Result<bool> {
+    crate::db_init(conn)?;
+    Ok(conn.execute("UPDATE library_tracks SET tag = ?1 WHERE id = ?2", params![tag, id])? > 0)
+}
+
+fn db_set_intro(conn: &Connection, id: i64, intro_ms: Option<u32>) -> anyhow::Result<bool> {
+    crate::db_init(conn)?;
+    Ok(conn.execute("UPDATE library_tracks SET intro_ms = ?1 WHERE id = ?2", params![intro_ms, id])? > 0)
+}
+
+/// Migration 7: the intro cue point.
+pub(crate) fn db_add_intro_ms(conn: &Connection) -> rusqlite::Result<()> {
+    crate::db_add_column_if_missing(conn, "library_tracks", "intro_ms", "INTEGER")
+}
+
+/// Migration 28: the full-text update trigger only for the indexed columns
+/// (it used to fire on every update of a track).
+pub(crate) fn db_narrow_fts_trigger(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch("DROP TRIGGER IF EXISTS library_fts_au")?;
+    conn.execute_batch(FTS_UPDATE_TRIGGER)
+}
+
+fn db_mark_played(conn: &Connection, path: &str, at_ms: u64) -> anyhow::Result<()> {
+    crate::db_init(conn)?;
+    conn.prepare_cached("UPDATE library_tracks SET last_played_ms = ?1 WHERE path = ?2")?
+        .execute(params![at_ms as i64, path])?;
+    Ok(())
+}
+
+/// path -> (mtime, size) for every indexed file; used to skip unchanged files.
+fn db_file_index(conn: &Connection) -> anyhow::Result<HashMap<String, (i64, i64)>> {
+    crate::db_init(conn)?;
+    let mut stmt = conn.prepare("SELECT path, mtime, size FROM library_tracks")?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, (row.get(1)?, row.get(2)?))))?;
+    Ok(rows.collect::<rusqlite::Result<HashMap<_, _>>>()?)
+}
+
+/// A probed file ready to be written to `library_tracks`.
+struct ScannedFile {
+    path: String,
+    title: String,
+    artist: String,
+    album: String,
+    duration_s: u32,
+    mtime: i64,
+    size: i64,
+}
+
+fn db_apply_scan(conn: &mut Connection, upserts: &[ScannedFile], removed: &[String]) -> anyhow::Result<()> {
+    crate::db_init(conn)?;
+    let now = unix_ms_now() as i64;
+
+    let tx = conn.transaction()?;
+    for f in upserts {
+        // Keep id/added_ms/last_played_ms/loudness for files that changed on
+        // disk, so queue references and history stay attached.
+        tx.execute(
+            "INSERT INTO library_tracks (path, title, artist, album, duration_s, added_ms, mtime, size)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+             ON CONFLICT(path) DO UPDATE SET
+               title=excluded.title,
+               artist=excluded.artist,
+               album=excluded.album,
+               duration_s=excluded.duration_s,
+               mtime=excluded.mtime,
+               size=excluded.size",
+            params![f.path, f.title, f.artist, f.album, f.duration_s as i64, now, f.mtime, f.size],
+        )?;
+    }
+    for path in removed {
+        tx.execute("DELETE FROM library_tracks WHERE path = ?1", params![path])?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+fn db_import_file(conn: &Connection, f: &ScannedFile, loudness_lufs: Option<f64>) -> anyhow::Result<i64> {
+    crate::db_init(conn)?;
+    let id = conn.query_row(
+        "INSERT INTO library_tracks (path, title, artist, album, duration_s, loudness_lufs, added_ms, mtime, size)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+         ON CONFLICT(path) DO UPDATE SET
+           title=excluded.title,
+           artist=excluded.artist,
+           album=excluded.album,
+           duration_s=excluded.duration_s,
+           loudness_lufs=excluded.loudness_lufs,
+           mtime=excluded.mtime,
+           size=excluded.size
+         RETURNING id",
+        params![
+            f.path,
+            f.title,
+            f.artist,
+            f.album,
+            f.duration_s as i64,
+            loudness_lufs,
+            unix_ms_now() as i64,
+            f.mtime,
+            f.size
+        ],
+        |row| row.get(0),
+    )?;
+    Ok(id)
+}
+
+/// Add (or refresh) a single file in the index, e.g. after ingest moved it
+/// into managed storage. Returns the library id.
+pub(crate) async fn import_file(
+    path: &str,
+    probe: &crate::MediaProbe,
+    loudness_lufs: Option<f64>,
+) -> anyhow::Result<i64> {
+    let md = std::fs::metadata(paths::from_cart(path))?;
+    let file = ScannedFile {
+        path: path.to_string(),
+        title: probe.title.clone().unwrap_or_else(|| title_from_path(path)),
+        artist: probe.artist.clone().unwrap_or_default(),
+        album: probe.album.clone().unwrap_or_default(),
+        duration_s: probe.duration_s,
+        mtime: file_mtime_secs(&md),
+        size: md.len() as i64,
+    };
+    crate::db::call(move |conn| db_import_file(conn, &file, loudness_lufs)).await?
+}
+
+pub(crate) fn file_mtime_secs(md: &std::fs::Metadata) -> i64 {
+    md.modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Find a library track for a playlist/log entry: an exact path match first,
+/// then (for entries written on another machine, e.g. `C:\Music\x.mp3`) any
+/// indexed file with the same file name.
+pub(crate) async fn find_track(entry: &str) -> anyhow::Result<Option<LibraryTrack>> {
+    let entry = entry.replace('\\', "/");
+    crate::db::call(move |conn| {
+        crate::db_init(conn)?;
+        let exact = conn
+            .query_row(
+                &format!("SELECT {TRACK_COLUMNS} FROM library_tracks WHERE path = ?1"),
+                params![entry],
+                track_from_row,
+            )
+            .optional()?;
+        if exact.is_some() {
+            return Ok(exact);
+        }
+        let Some(name) = entry.rsplit('/').next().filter(|n| !n.is_empty()) else {
+            return Ok(None);
+        };
+        Ok(conn
+            .query_row(
+                &format!(
+                    "SELECT {TRACK_COLUMNS} FROM library_tracks
+                     WHERE path LIKE '%/' || ?1 ESCAPE '\\' ORDER BY id LIMIT 1"
+                ),
+                params![name.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")],
+                track_from_row,
+            )
+            .optional()?)
+    })
+    .await?
+}
+
+/// Library id and intro cue (ms) of the track stored at `file`, if it is indexed.
+pub(crate) async fn lookup_id(file: &str) -> anyhow::Result<Option<(i64, Option<u32>)>> {
+    let file = file.to_string();
+    crate::db::call(move |conn| {
+        crate::db_init(conn)?;
+        Ok(conn
+            .query_row("SELECT id, intro_ms FROM library_tracks WHERE path = ?1", params![file], |row| {
+                Ok((row.get(0)?, row.get::<_, Option<i64>>(1)?.map(|v| v as u32)))
+            })
+            .optional()?)
+    })
+    .await?
+}
+
+/// Look up a library track's file path (for queue inserts by library id).
+pub(crate) async fn lookup_path(id: i64) -> anyhow::Result<Option<String>> {
+    crate::db::call(move |conn| Ok(db_get(conn, id)?.map(|t| t.path))).await?
+}
+
+/// Best-effort: stamp `last_played_ms` when playout starts a file.
+pub(crate) async fn mark_played(file: String) {
+    let res = crate::db::call(move |conn| db_mark_played(conn, &file, unix_ms_now())).await;
+    if let Ok(Err(e)) = res {
+        tracing::warn!("library: failed to record last_played: {e}");
+    }
+}
+
+/// Walk the configured directories and bring `library_tracks` up to date.
+///
+/// Only new or changed files (by mtime/size) are probed. Files that vanished
+/// from disk are dropped from the index.
+pub(crate) async fn run_scan(status: std::sync::Arc<tokio::sync::Mutex<LibraryScanStatus>>) {
+    {
+        let mut s = status.lock().await;
+        if s.running {
+            return;
+        }
+        *s = LibraryScanStatus { running: true, started_ms: Some(unix_ms_now()), ..Default::default() };
+    }
+
+    let res = scan_inner(&status).await;
+
+    let mut s = status.lock().await;
+    s.running = false;
+    s.finished_ms = Some(unix_ms_now());
+    if let Err(e) = res {
+        tracing::warn!("library scan failed: {e}");
+        s.last_error = Some(e.to_string());
+    }
+}
+
+async fn scan_inner(status: &tokio::sync::Mutex<LibraryScanStatus>) -> anyhow::Result<()> {
+    let (cfg, known) = crate::db::call(move |conn| -> anyhow::Result<_> { Ok((db_load_config(conn)?, db_file_index(conn)?)) })
+    .await??;
+
+    // Discover files + their mtime/size off the async runtime.
+    let dirs = cfg.dirs.clone();
+    let (found, dir_errors) = tokio::task::spawn_blocking(move || {
+        let mut found = Vec::new();
+        let mut errors = Vec::new();
+        for dir in dirs {
+            match scan_audio_files_recursive(&dir) {
+                Ok(files) => {
+                    for f in files {
+                        if let Ok(md) = std::fs::metadata(paths::from_cart(&f)) {
+                            found.push((f, file_mtime_secs(&md), md.len() as i64));
+                        }
+                    }
+                }
+                Err(e) => errors.push(e.to_string()),
+            }
+        }
+        (found, errors)
+    })
+    .await?;
+
+    if !dir_errors.is_empty() {
+        status.lock().await.last_error = Some(dir_errors.join("; "));
+    }
+
+    let seen: HashSet<&str> = found.iter().map(|(p, _, _)| p.as_str()).collect();
+    // If a directory could not be read at all (e.g. a NAS mount is down) we
+    // must not treat its files as deleted, so removals only happen on a clean walk.
+    let removed: Vec<String> = if dir_errors.is_empty() {
+        known.keys().filter(|p| !seen.contains(p.as_str())).cloned().collect()
+    } else {
+        Vec::new()
+    };
+
+    let mut upserts = Vec::new();
+    let (mut added, mut updated, mut failed) = (0u32, 0u32, 0u32);
+    for (path, mtime, size) in &found {
+        let prev = known.get(path);
+        if prev == Some(&(*mtime, *size)) {
+            continue;
+        }
+        match analysis::probe_cached(path).await {
+            Ok(probe) if probe.has_audio => {
+                if prev.is_some() { updated += 1 } else { added += 1 }
+                upserts.push(ScannedFile {
+                    path: path.clone(),
+                    title: probe.title.unwrap_or_else(|| title_from_path(path)),
+                    artist: probe.artist.unwrap_or_default(),
+                    album: probe.album.unwrap_or_default(),
+                    duration_s: probe.duration_s,
+                    mtime: *mtime,
+                    size: *size,
+                });
+            }
+            Ok(_) => failed += 1,
+            Err(e) => {
+                tracing::debug!("library: skipping {path}: {e}");
+                failed += 1;
+            }
+        }
+        let mut s = status.lock().await;
+        s.files_seen = found.len() as u32;
+        s.added = added;
+        s.updated = updated;
+        s.failed = failed;
+    }
+
+    let removed_count = removed.len() as u32;
+    crate::db::call(move |conn| db_apply_scan(conn, &upserts, &removed)).await??;
+
+    let mut s = status.lock().await;
+    s.files_seen = found.len() as u32;
+    s.removed = removed_count;
+    tracing::info!(
+        "library scan: {} files, {added} added, {updated} updated, {removed_count} removed, {failed} failed",
+        found.len()
+    );
+    Ok(())
+}
+
+// --- HTTP API -------------------------------------------------------------------
+
+#[derive(Deserialize)]
+pub(crate) struct LibraryListQuery {
+    q: Option<String>,
+    limit: Option<u32>,
+    offset: Option<u32>,
+}
+
+pub(crate) async fn api_library_list(
+    Query(query): Query<LibraryListQuery>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let limit = query.limit.unwrap_or(50).clamp(1, 500);
+    let offset = query.offset.unwrap_or(0);
+    let (total, items) = crate::db::call(move |conn| db_list(conn, query.q.as_deref(), limit, offset))
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|e| {
+        tracing::warn!("library list failed: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(json!({"total": total, "limit": limit, "offset": offset, "items": items})))
+}
+
+pub(crate) async fn api_library_search(
+    Query(query): Query<LibrarySearchQuery>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let per_page = query.per_page.unwrap_or(25).clamp(1, 200);
+    let page = query.page.unwrap_or(1).max(1);
+    let (total, items) = crate::db::call(move |conn| db_search(conn, &query, per_page, page))
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|e| {
+        tracing::warn!("library search failed: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(json!({"total": total, "page": page, "per_page": per_page, "items": items})))
+}
+
+pub(crate) async fn api_library_get(Path(id): Path<i64>) -> Result<Json<LibraryTrack>, StatusCode> {
+    let track = crate::db::call(move |conn| db_get(conn, id))
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    track.map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+#[derive(Deserialize)]
+pub(crate) struct LibraryTrackPatch {
+    tag: Option<String>,
+    /// End of the intro in ms; 0 removes the cue.
+    intro_ms: Option<u32>,
+}
+
+/// Only the tag and the intro cue are editable: everything else comes from
+/// the file itself and would be overwritten by the next scan.
+pub(crate) async fn api_library_patch(
+    Path(id): Path<i64>,
+    Json(req): Json<LibraryTrackPatch>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let tag = req.tag.map(|t| tags::check(&t)).transpose().map_err(|_| StatusCode::BAD_REQUEST)?;
+    if tag.is_none() && req.intro_ms.is_none() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let intro_ms = req.intro_ms;
+    crate::db::call(move |conn| -> anyhow::Result<Result<(), StatusCode>> {
+        let Some(track) = db_get(conn, id)? else { return Ok(Err(StatusCode::NOT_FOUND)) };
+        if let Some(ms) = intro_ms {
+            // The cue has to fall inside the track (unknown lengths are taken on trust).
+            if track.duration_s > 0 && ms >= track.duration_s * 1000 {
+                return Ok(Err(StatusCode::BAD_REQUEST));
+            }
+            db_set_intro(conn, id, Some(ms).filter(|ms| *ms > 0))?;
+        }
+        if let Some(tag) = &tag {
+            db_set_tag(conn, id, tag)?;
+        }
+        Ok(Ok(()))
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)??;
+
+    Ok(Json(json!({"ok": true})))
+}
+
+pub(crate) async fn api_library_config_get() -> Result<Json<LibraryConfig>, StatusCode> {
+    crate::db::call(move |conn| db_load_config(conn))
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map(Json)
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+pub(crate) async fn api_library_config_set(
+    Json(mut cfg): Json<LibraryConfig>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    cfg.dirs = cfg
+        .dirs
+        .into_iter()
+        .map(|d| d.trim().to_string())
+        .filter(|d| !d.is_empty())
+        .collect();
+    if cfg.dirs.iter().any(|d| !d.starts_with('/')) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    crate::db::call(move |conn| db_save_config(conn, &cfg))
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(json!({"ok": true})))
+}
+
+pub(crate) async fn api_library_scan_start(State(state): State<AppState>) -> Result<Json<serde_json::Value>, StatusCode> {
+    if state.library_scan.lock().await.running {
+        return Err(StatusCode::CONFLICT);
+    }
+    tokio::spawn(run_scan(state.library_scan.clone()));
+    Ok(Json(json!({"ok": true})))
+}
+
+pub(crate) async fn api_library_scan_status(State(state): State<AppState>) -> Json<LibraryScanStatus> {
+    Json(state.library_scan.lock().await.clone())
+}
@@ -0,0 +1,582 @@
+// --- WebRTC "Listen Live" ---------------------------------------------------
+//
+// The UI uses a minimal HTTP signaling flow:
+//   1) POST /api/v1/webrtc/offer      (send SDP offer, receive SDP answer)
+//   2) POST /api/v1/webrtc/candidate  (send browser ICE candidates)
+//
+// Why we need the /candidate endpoint:
+//   WebRTC ICE negotiation is bi-directional. Even if the server includes its
+//   own host/srflx candidates in the SDP answer, the server still needs the
+//   browser's candidates (from `RTCPeerConnection.onicecandidate`) to
+//   establish a working ICE pair. Without those, ICE tends to get stuck at
+//   `checking` and the browser eventually tears the connection down.
+//
+// For now, StudioCommand supports a single active listen-live session at a
+// time (operator monitor). This keeps signaling dead-simple and avoids
+// accumulating idle peer connections on a small box.
+//
+// Future: multi-listener can be implemented by storing sessions in a HashMap
+// keyed by a UUID returned from `/offer`.
+
+use axum::http::StatusCode;
+use axum::{extract::State, Json};
+use serde::{Serialize, Deserialize};
+use serde_json::json;
+
+use crate::{meters, producers, AppState};
+
+pub(crate) struct WebRtcRuntime {
+    /// The active WebRTC PeerConnection for the operator "Listen Live" monitor.
+    ///
+    /// The `webrtc` crate exposes this type at `webrtc::peer_connection::RTCPeerConnection`.
+    /// (Earlier iterations accidentally referenced a non-existent nested module
+    /// path: `peer_connection::peer_connection::RTCPeerConnection`.)
+    pub(crate) pc: std::sync::Arc<webrtc::peer_connection::RTCPeerConnection>,
+    pub(crate) stopped: std::sync::Arc<std::sync::atomic::AtomicBool>,
+}
+
+#[derive(Clone, Deserialize)]
+pub(crate) struct WebRtcCandidate {
+    // The browser sends an `RTCIceCandidate` which is compatible with
+    // `RTCIceCandidateInit` (candidate string + mid/mline_index).
+    candidate: webrtc::ice_transport::ice_candidate::RTCIceCandidateInit,
+}
+
+// --- WebRTC "Listen Live" monitor ---------------------------------------
+//
+// This implements a simple single-endpoint signaling flow:
+//   Browser:  POST /api/v1/webrtc/offer  { sdp, type:"offer" }
+//   Engine :  200 OK                    { sdp, type:"answer" }
+//
+// The media source is the same PCM pipeline used for Icecast + meters.
+// We encode Opus frames in-process and publish them via a single WebRTC
+// peer connection per listener.
+//
+// Design notes:
+// - We *do not* create a new audio source per listener. Instead, we tap the
+//   existing PCM broadcast channel (`AppState.pcm_tx`) and encode Opus for
+//   each listener independently. (If CPU becomes a concern, we can evolve to a
+//   single shared Opus encoder + RTP fan-out later.)
+// - We standardize internal PCM to 48 kHz stereo so we can feed Opus/WebRTC
+//   without resampling.
+//
+// Browser support: all modern browsers support Opus in WebRTC.
+// Docs: https://docs.rs/webrtc (crate webrtc, WebRTC.rs stack).
+//
+// Security: this endpoint is intended for same-origin use behind your existing
+// TLS terminator (Caddy/Nginx). If you expose it publicly, treat it like any
+// other authenticated monitor endpoint.
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct WebRtcOffer {
+    sdp: String,
+    #[serde(rename = "type")]
+    r#type: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct WebRtcAnswer {
+    sdp: String,
+    #[serde(rename = "type")]
+    r#type: String, // always "answer"
+}
+
+pub(crate) async fn api_webrtc_offer(
+    State(state): State<AppState>,
+    Json(offer): Json<WebRtcOffer>,
+) -> Result<Json<WebRtcAnswer>, StatusCode> {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use bytes::Bytes;
+    use opus::{Application as OpusApplication, Channels as OpusChannels, Encoder as OpusEncoder};
+    use webrtc::api::APIBuilder;
+    use webrtc::api::media_engine::MediaEngine;
+    use webrtc::api::interceptor_registry::register_default_interceptors;
+    use webrtc::ice_transport::ice_server::RTCIceServer;
+    use webrtc::peer_connection::configuration::RTCConfiguration;
+    use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+    use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+    use webrtc::track::track_local::track_local_static_sample::TrackLocalStaticSample;
+    use webrtc::rtp_transceiver::rtp_codec::RTCRtpCodecCapability;
+    use webrtc::media::Sample;
+    use webrtc::data_channel::data_channel_init::RTCDataChannelInit;
+
+    // Basic validation: browsers send {type:"offer"}.
+    if offer.r#type.to_lowercase() != "offer" {
+        tracing::warn!("webrtc offer rejected: type was {}", offer.r#type);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    // --- Build WebRTC API stack (codecs + interceptors) -------------------
+    //
+    // MediaEngine: codec registry (Opus etc).
+    // Interceptors: RTCP, NACK, TWCC, etc. Default set is fine for audio-only.
+    let mut m = MediaEngine::default();
+    m.register_default_codecs()
+        .map_err(|e| {
+            tracing::warn!("webrtc: register_default_codecs failed: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let mut registry = webrtc::interceptor::registry::Registry::new();
+
+    // NOTE: In webrtc-rs, `register_default_interceptors(...)` is *synchronous* and returns
+    // `Result<Registry, webrtc::Error>`.
+    //
+    // Earlier drafts of this feature assumed an async API and incorrectly used `.await`.
+    // That fails to compile with:
+    //   "Result<...> is not a future"
+    //
+    // Keeping this explicit (and documented) helps future upgrades if the upstream API changes.
+    registry = register_default_interceptors(registry, &mut m).map_err(|e| {
+        tracing::warn!("webrtc: register_default_interceptors failed: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let api = APIBuilder::new()
+        .with_media_engine(m)
+        .with_interceptor_registry(registry)
+        .build();
+
+    // ICE servers: default to Google's public STUN unless overridden.
+    // This matters if you ever want to listen from outside the LAN.
+    let stun = std::env::var("STUDIOCOMMAND_WEBRTC_STUN")
+        .unwrap_or_else(|_| "stun:stun.l.google.com:19302".to_string());
+
+    let config = RTCConfiguration {
+        ice_servers: vec![RTCIceServer {
+            urls: vec![stun],
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    let pc = std::sync::Arc::new(api.new_peer_connection(config).await.map_err(|e| {
+        tracing::warn!("webrtc: new_peer_connection failed: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?);
+    // A shared stop flag used by background tasks (silence keepalive, PCM pump).
+    let stopped = std::sync::Arc::new(AtomicBool::new(false));
+
+    // Replace any existing session (if the operator clicks Start repeatedly).
+    //
+    // We proactively stop the previous PeerConnection to avoid leaving idle
+    // DTLS/SRTP tasks running on small machines.
+    {
+        let mut guard = state.webrtc.lock().await;
+        if let Some(prev) = guard.take() {
+            prev.stopped.store(true, Ordering::SeqCst);
+            // Close is best-effort; we don't fail the new session if it errors.
+            if let Err(e) = prev.pc.close().await {
+                tracing::warn!("webrtc: closing previous PeerConnection failed: {e}");
+            }
+        }
+
+        *guard = Some(WebRtcRuntime {
+            pc: pc.clone(),
+            stopped: stopped.clone(),
+        });
+    }
+
+
+
+    // Track: Opus audio.
+    let track = std::sync::Arc::new(TrackLocalStaticSample::new(
+        RTCRtpCodecCapability {
+            mime_type: "audio/opus".to_string(),
+            clock_rate: 48_000,
+            channels: 2,
+            sdp_fmtp_line: "minptime=10;useinbandfec=1".to_string(),
+            rtcp_feedback: vec![],
+        },
+        "audio".to_string(),
+        "studiocommand".to_string(),
+    ));
+
+    pc.add_track(track.clone()).await.map_err(|e| {
+        tracing::warn!("webrtc: add_track failed: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    // ---------------------------------------------------------------------
+    // WebRTC data channel: meter alignment with what you *hear*
+    //
+    // Problem:
+    //   Once we added WebRTC audio monitoring, operators may notice that the
+    //   on-screen VU meters lag slightly behind what they hear.
+    //
+    // Why:
+    //   - Audio playout in the browser runs through a jitter buffer and audio
+    //     output scheduling.
+    //   - The existing meters are delivered over HTTP polling (/api/v1/meters)
+    //     and intentionally apply smoothing/ballistics.
+    //   - Those two clocks will never be perfectly phase-aligned.
+    //
+    // Fix:
+    //   When "Listen Live" is active, we also send meter snapshots over a
+    //   WebRTC *data channel* in the same PeerConnection.
+    //
+    //   This gives the UI a low-latency meter stream that shares the same
+    //   transport timing and RTT dynamics as the audio you are monitoring.
+    //
+    // Notes:
+    //   - This is purely an *operator experience* feature.
+    //   - If the data channel fails for any reason, the UI will fall back to
+    //     the existing HTTP polling path.
+    // ---------------------------------------------------------------------
+    let dc = pc
+        .create_data_channel(
+            "meters",
+            Some(RTCDataChannelInit {
+                // Ordered delivery is fine; these are tiny.
+                ordered: Some(true),
+                ..Default::default()
+            }),
+        )
+        .await
+        .map_err(|e| {
+            tracing::warn!("webrtc: create_data_channel(meters) failed: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    // Start a background meter sender when the channel opens.
+    // We intentionally send at ~50 Hz (20 ms) to match the Opus frame cadence.
+    {
+        let stopped = stopped.clone();
+        let dc_open = dc.clone();
+        dc.on_open(Box::new(move || {
+            let stopped = stopped.clone();
+            let dc = dc_open.clone();
+            Box::pin(async move {
+                tracing::info!("webrtc: meters data channel open");
+                tokio::spawn(async move {
+                    use std::time::{Duration, Instant};
+                    let t0 = Instant::now();
+                    loop {
+                        if stopped.load(Ordering::SeqCst) {
+                            break;
+                        }
+
+                        // Lock-free (see meters.rs).
+                        let vu = meters::get();
+
+                        // Include a monotonic timestamp so the UI can detect staleness.
+                        let payload = json!({
+                            "t_ms": t0.elapsed().as_millis() as u64,
+                            "rms_l": vu.rms_l,
+                            "rms_r": vu.rms_r,
+                            "peak_l": vu.peak_l,
+                            "peak_r": vu.peak_r,
+                        })
+                        .to_string();
+
+                        // Best-effort send.
+                        // If the peer disconnects, `stopped` will flip and we exit.
+                        let _ = dc.send_text(payload).await;
+
+                        tokio::time::sleep(Duration::from_millis(20)).await;
+                    }
+                });
+            })
+        }));
+    }
+
+    // Producer messages (producers.rs), pushed to the operator as they are
+    // sent: `{"type": "message", "message": {...}}`, from the moment the
+    // channel opens. The UI reads the history over HTTP.
+    let messages_dc = pc
+        .create_data_channel("messages", Some(RTCDataChannelInit { ordered: Some(true), ..Default::default() }))
+        .await
+        .map_err(|e| {
+            tracing::warn!("webrtc: create_data_channel(messages) failed: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    {
+        let stopped = stopped.clone();
+        let dc_open = messages_dc.clone();
+        messages_dc.on_open(Box::new(move || {
+            let stopped = stopped.clone();
+            let dc = dc_open.clone();
+            Box::pin(async move {
+                tokio::spawn(async move {
+                    use std::time::Duration;
+                    let mut feed = producers::MessageFeed::open(None, producers::last_message_id());
+                    while !stopped.load(Ordering::SeqCst) {
+                        // Wake now and then to notice the session ending.
+                        let Ok(next) = tokio::time::timeout(Duration::from_secs(1), feed.next()).await else {
+                            continue;
+                        };
+                        let Some(msg) = next else { break };
+                        let _ = dc.send_text(json!({"type": "message", "message": msg}).to_string()).await;
+                    }
+                });
+            })
+        }));
+    }
+
+// ---------------------------------------------------------------------
+// WebRTC "keepalive" audio packets (Opus silence)
+//
+// Symptom this fixes:
+//   The browser shows "Connecting..." for a while and then returns to "Stopped"
+//   without ever reaching "Connected".
+//
+// Cause:
+//   Some browsers will tear down a PeerConnection if no RTP media arrives soon
+//   after ICE/DTLS completes. This is especially easy to trigger in broadcast
+//   scenarios where the "real" audio pipeline might take a moment to start,
+//   or when the server has not yet received any PCM frames.
+//
+// Fix:
+//   Immediately begin sending tiny 20 ms Opus packets that decode to silence.
+//   As soon as the real PCM->Opus pump successfully writes its first packet,
+//   it flips `audio_started` to true and this silence task exits.
+//
+// Notes:
+//   - This is a common WebRTC broadcasting practice.
+//   - CPU cost is negligible.
+//   - It dramatically improves connection reliability and debuggability.
+// ---------------------------------------------------------------------
+let audio_started = std::sync::Arc::new(AtomicBool::new(false));
+{
+    let track_for_silence = track.clone();
+    let stopped = stopped.clone();
+    let audio_started = audio_started.clone();
+
+    tokio::spawn(async move {
+        use std::time::Duration;
+
+        // A dedicated Opus encoder for the silence stream.
+        // We encode 20 ms of all-zero PCM (stereo, 48 kHz).
+        let mut enc = match OpusEncoder::new(48_000, OpusChannels::Stereo, OpusApplication::Audio) {
+            Ok(e) => e,
+            Err(e) => {
+                tracing::warn!("webrtc: failed to create Opus encoder for silence keepalive: {e}");
+                return;
+            }
+        };
+
+        // 20 ms @ 48 kHz => 960 samples/channel, stereo => 1920 samples total.
+        const SILENCE_SAMPLES_TOTAL: usize = 960 * 2;
+        let pcm_silence: Vec<i16> = vec![0; SILENCE_SAMPLES_TOTAL];
+
+        // Opus packets are small; 4000 bytes is plenty for 20 ms.
+        let mut out = vec![0u8; 4000];
+
+        while !stopped.load(Ordering::SeqCst) && !audio_started.load(Ordering::SeqCst) {
+            let n = match enc.encode(&pcm_silence, &mut out) {
+                Ok(n) => n,
+                Err(e) => {
+                    tracing::warn!("webrtc: Opus silence encode failed: {e}");
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    continue;
+                }
+            };
+
+            let sample = webrtc::media::Sample {
+                data: Bytes::from(out[..n].to_vec()),
+                duration: Duration::from_millis(20),
+                ..Default::default()
+            };
+
+            // Ignore transient errors here; if the peer goes away, the state
+            // callbacks will flip `stopped` and all tasks will exit naturally.
+            let _ = track_for_silence.write_sample(&sample).await;
+
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    });
+}
+
+    {
+        let stopped = stopped.clone();
+        pc.on_peer_connection_state_change(Box::new(move |s: RTCPeerConnectionState| {
+            if matches!(
+                s,
+                RTCPeerConnectionState::Failed
+                    | RTCPeerConnectionState::Closed
+                    | RTCPeerConnectionState::Disconnected
+            ) {
+                stopped.store(true, Ordering::Relaxed);
+            }
+            Box::pin(async {})
+        }));
+    }
+
+    // --- SDP handshake ----------------------------------------------------
+    pc.set_remote_description(
+        RTCSessionDescription::offer(offer.sdp)
+            .map_err(|e| {
+                tracing::warn!("webrtc: invalid offer SDP: {e}");
+                StatusCode::BAD_REQUEST
+            })?
+    )
+    .await
+    .map_err(|e| {
+        tracing::warn!("webrtc: set_remote_description failed: {e}");
+        StatusCode::BAD_REQUEST
+    })?;
+
+    let answer = pc.create_answer(None).await.map_err(|e| {
+        tracing::warn!("webrtc: create_answer failed: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    // IMPORTANT: We return a *non-trickle* SDP answer (all ICE candidates included in the SDP).
+//
+// In early WebRTC iterations we returned the SDP immediately after `set_local_description()`.
+// That can produce an SDP answer with *zero* candidates in some environments, causing the browser to
+// remain stuck in ICE state `new` (no remote candidates) and eventually give up.
+//
+// Full trickle ICE would require a candidate exchange endpoint and client-side event wiring.
+// For StudioCommand’s "Listen Live" monitor, a simpler and robust approach is:
+//   1) set the local description
+//   2) wait *briefly* for ICE gathering to complete (bounded, so we never stall forever)
+//   3) read the final local description (now containing candidates) and return it as the SDP answer
+pc.set_local_description(answer).await.map_err(|e| {
+    tracing::warn!("webrtc: set_local_description failed: {e}");
+    StatusCode::INTERNAL_SERVER_ERROR
+})?;
+
+// Wait up to 2 seconds for ICE gathering to complete so the returned SDP includes candidates.
+// If it times out, we still proceed (and the UI will show `new`/`checking`).
+let mut gather_complete = pc.gathering_complete_promise().await;
+let _ = tokio::time::timeout(std::time::Duration::from_secs(2), gather_complete.recv()).await;
+
+    let local = pc.local_description().await.ok_or_else(|| {
+        tracing::warn!("webrtc: local_description missing after set_local_description");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    // --- Audio pump -------------------------------------------------------
+    //
+    // Subscribe to the PCM broadcast channel and encode 20 ms Opus packets.
+    // PCM format: s16le stereo @ 48 kHz.
+    // A 20 ms Opus frame = 960 samples per channel.
+    let mut rx = state.pcm_tx.subscribe();
+    let stopped_for_task = stopped.clone();
+    let track_for_task = track.clone();
+
+    tokio::spawn(async move {
+        let audio_started = audio_started.clone();
+        let mut wrote_first_packet = false;
+
+        const SR: u32 = 48_000;
+        const CHANNELS: usize = 2;
+        const FRAME_SAMPLES_PER_CH: usize = 960; // 20 ms @ 48k
+        const FRAME_SAMPLES_TOTAL: usize = FRAME_SAMPLES_PER_CH * CHANNELS;
+        const FRAME_BYTES: usize = FRAME_SAMPLES_TOTAL * 2; // i16
+
+        // Opus encoder: stereo, 48 kHz, general audio.
+        let mut enc = match OpusEncoder::new(SR as u32, OpusChannels::Stereo, OpusApplication::Audio) {
+            Ok(e) => e,
+            Err(e) => {
+                tracing::warn!("webrtc: opus encoder init failed: {e}");
+                return;
+            }
+        };
+
+        // Buffer in case the PCM producer ever sends partial frames.
+        let mut buf: Vec<u8> = Vec::with_capacity(FRAME_BYTES * 4);
+
+        while !stopped_for_task.load(Ordering::Relaxed) {
+            let chunk = match rx.recv().await {
+                Ok(c) => c,
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                    // Listener fell behind; drop audio to catch up.
+                    tracing::warn!("webrtc: pcm receiver lagged by {n} messages (dropping)");
+                    crate::diagnostics::note_pcm_lag(n);
+                    continue;
+                }
+                Err(_) => break,
+            };
+
+            buf.extend_from_slice(&chunk);
+
+            while buf.len() >= FRAME_BYTES {
+                let frame = buf.drain(0..FRAME_BYTES).collect::<Vec<u8>>();
+
+                // Convert bytes -> i16 samples.
+                let mut samples: Vec<i16> = Vec::with_capacity(FRAME_SAMPLES_TOTAL);
+                let mut i = 0usize;
+                while i + 1 < frame.len() {
+                    samples.push(i16::from_le_bytes([frame[i], frame[i + 1]]));
+                    i += 2;
+                }
+
+                // Encode Opus.
+                let mut out = vec![0u8; 4000];
+                let encode_started = std::time::Instant::now();
+                let encoded = enc.encode(&samples, &mut out);
+                crate::diagnostics::OPUS_ENCODE.record(encode_started.elapsed());
+                let n = match encoded {
+                    Ok(n) => n,
+                    Err(e) => {
+                        tracing::warn!("webrtc: opus encode failed: {e}");
+                        break;
+                    }
+                };
+                out.truncate(n);
+
+                // Ship as a media sample (WebRTC will packetize it as RTP).
+                let sample = Sample {
+                    data: Bytes::from(out),
+                    duration: std::time::Duration::from_millis(20),
+                    ..Default::default()
+                };
+
+                if let Err(e) = track_for_task.write_sample(&sample).await {
+                    tracing::warn!("webrtc: write_sample failed (peer likely gone): {e}");
+                    return;
+                }
+if !wrote_first_packet {
+    wrote_first_packet = true;
+    audio_started.store(true, Ordering::SeqCst);
+    tracing::info!("webrtc: first audio packet sent (silence keepalive will stop)");
+}
+            }
+        }
+    });
+
+    Ok(Json(WebRtcAnswer {
+        sdp: local.sdp,
+        r#type: "answer".to_string(),
+    }))
+}
+
+/// Receive browser ICE candidates for the current WebRTC session.
+///
+/// WebRTC ICE negotiation is *bi-directional*: the server needs the browser's
+/// candidates in order to find a valid candidate pair. Without this endpoint,
+/// ICE commonly gets stuck at `checking` and the browser eventually closes the
+/// connection (the UI reverts to "Stopped").
+///
+/// The UI calls this from `pc.onicecandidate` while a session is active.
+///
+/// For now there is only one active session at a time (operator monitor).
+pub(crate) async fn api_webrtc_candidate(
+    State(state): State<AppState>,
+    Json(body): Json<WebRtcCandidate>,
+) -> Result<StatusCode, StatusCode> {
+    // Grab a snapshot of the current PeerConnection (if any) without holding
+    // the mutex across an await on `add_ice_candidate`.
+    let pc_opt = {
+        let guard = state.webrtc.lock().await;
+        guard.as_ref().map(|rt| rt.pc.clone())
+    };
+
+    let pc = match pc_opt {
+        Some(pc) => pc,
+        None => {
+            // No active session. This can happen if the user hit Stop while
+            // candidates were still trickling from the browser.
+            return Err(StatusCode::CONFLICT);
+        }
+    };
+
+    pc.add_ice_candidate(body.candidate).await.map_err(|e| {
+        tracing::warn!("webrtc: add_ice_candidate failed: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
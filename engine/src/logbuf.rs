@@ -0,0 +1,230 @@
+// --- In-engine log buffer ----------------------------------------------------------------
+//
+// When top-up finds nothing or the encoder dies, the reason is in the log,
+// and the log is in journald on a box the operator has no shell on. Every
+// record that passes the log filter is now also kept in a bounded ring in
+// memory (`STUDIOCOMMAND_LOG_BUFFER` records, default 2000) and served by
+// `GET /api/v1/admin/logs?level=warn&limit=200`, newest first.
+//
+// The ring sees exactly what journald sees: it sits behind the same filter,
+// so raising `engine.log_level` for a module fills it with that module's
+// debug output too. It is lost on restart; journald stays the archive.
+//
+// Output to stderr is plain text by default. `STUDIOCOMMAND_LOG_FORMAT=json`
+// (or `engine.log_format` in the config file) switches it to one JSON object
+// per line, for shipping to a log collector. The format is chosen once at
+// startup.
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+use axum::{extract::Query, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::Context;
+
+use crate::unix_ms_now;
+
+const DEFAULT_CAPACITY: usize = 2000;
+/// Longest message kept, in bytes; ffmpeg lines can be long.
+const MAX_MESSAGE: usize = 2048;
+
+#[derive(Clone, Serialize)]
+pub(crate) struct LogRecord {
+    /// Increases by one per record, so a client can poll with `after`.
+    seq: u64,
+    ts_ms: u64,
+    level: &'static str,
+    target: String,
+    message: String,
+    #[serde(skip_serializing_if = "Map::is_empty")]
+    fields: Map<String, Value>,
+    #[serde(skip)]
+    rank: u8,
+}
+
+struct Ring {
+    capacity: usize,
+    records: VecDeque<LogRecord>,
+}
+
+static RING: OnceLock<Mutex<Ring>> = OnceLock::new();
+static SEQ: AtomicU64 = AtomicU64::new(0);
+
+fn capacity() -> usize {
+    std::env::var("STUDIOCOMMAND_LOG_BUFFER")
+        .ok()
+        .and_then(|v| v.trim().parse::<usize>().ok())
+        .unwrap_or(DEFAULT_CAPACITY)
+        .clamp(100, 100_000)
+}
+
+fn ring() -> &'static Mutex<Ring> {
+    RING.get_or_init(|| {
+        let capacity = capacity();
+        Mutex::new(Ring { capacity, records: VecDeque::with_capacity(capacity) })
+    })
+}
+
+/// 0 (error) to 4 (trace), so "at least `warn`" is `rank <= 1`.
+fn rank(level: &Level) -> u8 {
+    match *level {
+        Level::ERROR => 0,
+        Level::WARN => 1,
+        Level::INFO => 2,
+        Level::DEBUG => 3,
+        Level::TRACE => 4,
+    }
+}
+
+fn level_name(level: &Level) -> &'static str {
+    match *level {
+        Level::ERROR => "error",
+        Level::WARN => "warn",
+        Level::INFO => "info",
+        Level::DEBUG => "debug",
+        Level::TRACE => "trace",
+    }
+}
+
+fn parse_level(s: &str) -> Option<u8> {
+    match s.trim().to_ascii_lowercase().as_str() {
+        "error" => Some(0),
+        "warn" | "warning" => Some(1),
+        "info" => Some(2),
+        "debug" => Some(3),
+        "trace" => Some(4),
+        _ => None,
+    }
+}
+
+/// Whether stderr output should be JSON: `STUDIOCOMMAND_LOG_FORMAT`, else
+/// `engine.log_format` from the config file.
+pub(crate) fn json_format(file_format: Option<&str>) -> bool {
+    let format = std::env::var("STUDIOCOMMAND_LOG_FORMAT").ok();
+    format.as_deref().or(file_format).is_some_and(|f| f.trim().eq_ignore_ascii_case("json"))
+}
+
+// --- Capture -----------------------------------------------------------------------------
+
+#[derive(Default)]
+struct Fields {
+    message: String,
+    fields: Map<String, Value>,
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            self.fields.insert(field.name().into(), value.into());
+        }
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.fields.insert(field.name().into(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.fields.insert(field.name().into(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.fields.insert(field.name().into(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{value:?}");
+        } else {
+            self.fields.insert(field.name().into(), format!("{value:?}").into());
+        }
+    }
+}
+
+fn truncate(s: &mut String, max: usize) {
+    if s.len() > max {
+        let mut end = max;
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        s.truncate(end);
+        s.push('…');
+    }
+}
+
+/// Tracing layer that copies every event it sees into the ring.
+pub(crate) struct RingLayer;
+
+impl<S: Subscriber> tracing_subscriber::Layer<S> for RingLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut f = Fields::default();
+        event.record(&mut f);
+        truncate(&mut f.message, MAX_MESSAGE);
+        let meta = event.metadata();
+        let record = LogRecord {
+            seq: SEQ.fetch_add(1, Ordering::Relaxed) + 1,
+            ts_ms: unix_ms_now(),
+            level: level_name(meta.level()),
+            target: meta.target().to_string(),
+            message: f.message,
+            fields: f.fields,
+            rank: rank(meta.level()),
+        };
+        // Never panic inside logging; a poisoned ring just keeps going.
+        let mut ring = ring().lock().unwrap_or_else(|e| e.into_inner());
+        if ring.records.len() >= ring.capacity {
+            ring.records.pop_front();
+        }
+        ring.records.push_back(record);
+    }
+}
+
+// --- API ---------------------------------------------------------------------------------
+
+#[derive(Deserialize)]
+pub(crate) struct LogsQuery {
+    /// Minimum level (`error`, `warn`, `info`, `debug`, `trace`); default all.
+    level: Option<String>,
+    limit: Option<usize>,
+    /// Only records whose target starts with this (e.g. `studiocommand_engine::metapush`).
+    target: Option<String>,
+    /// Only records newer than this `seq`.
+    after: Option<u64>,
+}
+
+/// `GET /api/v1/admin/logs?level=&limit=&target=&after=`, newest first.
+pub(crate) async fn api_admin_logs(Query(q): Query<LogsQuery>) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let min = match q.level.as_deref() {
+        Some(l) => parse_level(l).ok_or_else(|| {
+            (StatusCode::BAD_REQUEST, Json(json!({"ok": false, "error": format!("unknown level {l:?}")})))
+        })?,
+        None => 4,
+    };
+    let limit = q.limit.unwrap_or(200).clamp(1, 5000);
+    let after = q.after.unwrap_or(0);
+
+    let ring = ring().lock().unwrap_or_else(|e| e.into_inner());
+    let items: Vec<LogRecord> = ring
+        .records
+        .iter()
+        .rev()
+        .take_while(|r| r.seq > after)
+        .filter(|r| r.rank <= min)
+        .filter(|r| q.target.as_deref().is_none_or(|t| r.target.starts_with(t)))
+        .take(limit)
+        .cloned()
+        .collect();
+    Ok(Json(json!({
+        "ok": true,
+        "capacity": ring.capacity,
+        "buffered": ring.records.len(),
+        "latest_seq": SEQ.load(Ordering::Relaxed),
+        "items": items,
+    })))
+}
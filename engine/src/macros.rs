@@ -0,0 +1,429 @@
+// --- Macros ------------------------------------------------------------------------------
+//
+// Operators keep finding sequences they want on one button: "play the
+// news jingle, then the news, then top-up off", "tell the website we are
+// live". A macro is a named Rhai script (https://rhai.rs, a small
+// JavaScript-like language embedded in the engine) that calls engine
+// primitives:
+//
+//   now_playing(), queue(), queue_insert(ref[, after_id]), queue_remove(id),
+//   queue_move(id, to), skip(), output_start(), output_stop(), on_air(),
+//   action(#{type: ...}), http_get(url), http_post(url, body), sleep(ms)
+//
+// `action` runs any action-scheduler action (schedule.rs) except another
+// macro. `print` goes to the run's output. The script's last value is the
+// run's result.
+//
+// A macro runs from `POST /api/v1/macros/{name}/run`, from the action
+// scheduler, GPIO and control surfaces (`{"type": "macro", "name": ...}`),
+// or from a webhook: a macro with a hook token also runs on
+// `POST /api/v1/public/hooks/{token}`. A request body (JSON if it parses,
+// else text) is the script's `input`.
+//
+// Scripts run on a blocking thread, one run at a time per macro, and stop
+// after `RUN_LIMIT`. They are checked when saved, so a typo is a 400 and
+// not a silent failure at 3 a.m. There is no live input to switch yet.
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use rhai::{Dynamic, Engine, EvalAltResult, Scope};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::runtime::Handle;
+use tokio::time::Duration;
+use uuid::Uuid;
+
+use crate::schedule::{self, Action};
+use crate::{
+    advance_to_next, apply_queue_batch_op, bump_queue_rev, ffmpeg, normalize_log_state, output_start_internal,
+    output_stop_internal, persist_queue, resolve_insert_ref, unix_ms_now, AppState, QueueBatchOp,
+};
+
+/// Longest a run may take, sleeps and HTTP requests included.
+const RUN_LIMIT: Duration = Duration::from_secs(60);
+/// Timeout for one `http_get` / `http_post`.
+const HTTP_TIMEOUT_S: u64 = 10;
+/// Lines of `print` output kept from a run.
+const MAX_OUTPUT: usize = 200;
+
+#[derive(Clone, Serialize)]
+pub(crate) struct Macro {
+    name: String,
+    description: String,
+    script: String,
+    /// Secret for `POST /api/v1/public/hooks/{token}`; none means no webhook.
+    hook_token: Option<String>,
+    updated_ms: u64,
+    last_run_ms: Option<u64>,
+    /// "ok" or the error from the last run.
+    last_result: Option<String>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct RunOutcome {
+    ok: bool,
+    /// The script's last value.
+    result: serde_json::Value,
+    output: Vec<String>,
+    error: Option<String>,
+    ms: u64,
+}
+
+/// Macros running now, so a bouncing button does not start a second run.
+static RUNNING: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS macros (
+            name         TEXT PRIMARY KEY,
+            description  TEXT NOT NULL DEFAULT '',
+            script       TEXT NOT NULL,
+            hook_token   TEXT UNIQUE,
+            updated_ms   INTEGER NOT NULL,
+            last_run_ms  INTEGER,
+            last_result  TEXT
+        );
+        "#,
+    )
+}
+
+async fn with_db<T: Send + 'static>(
+    f: impl FnOnce(&Connection) -> anyhow::Result<T> + Send + 'static,
+) -> anyhow::Result<T> {
+    crate::db::call(move |conn| {
+        crate::db_init(conn)?;
+        f(conn)
+    })
+    .await?
+}
+
+const COLUMNS: &str = "name, description, script, hook_token, updated_ms, last_run_ms, last_result";
+
+fn row_to_macro(row: &rusqlite::Row<'_>) -> rusqlite::Result<Macro> {
+    Ok(Macro {
+        name: row.get(0)?,
+        description: row.get(1)?,
+        script: row.get(2)?,
+        hook_token: row.get(3)?,
+        updated_ms: row.get::<_, i64>(4)? as u64,
+        last_run_ms: row.get::<_, Option<i64>>(5)?.map(|v| v as u64),
+        last_result: row.get(6)?,
+    })
+}
+
+fn db_get(conn: &Connection, name: &str) -> anyhow::Result<Option<Macro>> {
+    let sql = format!("SELECT {COLUMNS} FROM macros WHERE name = ?1");
+    Ok(conn.query_row(&sql, params![name], row_to_macro).optional()?)
+}
+
+fn db_by_token(conn: &Connection, token: &str) -> anyhow::Result<Option<Macro>> {
+    let sql = format!("SELECT {COLUMNS} FROM macros WHERE hook_token = ?1");
+    Ok(conn.query_row(&sql, params![token], row_to_macro).optional()?)
+}
+
+fn valid_name(name: &str) -> bool {
+    (1..=64).contains(&name.len()) && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+// --- Script engine -----------------------------------------------------------------------------
+
+fn fail(e: impl std::fmt::Display) -> Box<EvalAltResult> {
+    e.to_string().into()
+}
+
+fn curl(args: &[&str]) -> Result<String, Box<EvalAltResult>> {
+    let curl = ffmpeg::curl_bin();
+    let timeout = HTTP_TIMEOUT_S.to_string();
+    let out = std::process::Command::new(&curl)
+        .args(["-sS", "--fail-with-body", "--max-time", &timeout])
+        .args(args)
+        .output()
+        .map_err(|e| fail(format!("{curl}: {e}")))?;
+    let body = String::from_utf8_lossy(&out.stdout).into_owned();
+    if !out.status.success() {
+        let err = String::from_utf8_lossy(&out.stderr);
+        return Err(fail(format!("{} {body}", err.trim())));
+    }
+    Ok(body)
+}
+
+/// An engine with the primitives bound to `state`. It runs on a blocking
+/// thread, so the async ones go through `handle.block_on`.
+fn build_engine(state: AppState, handle: Handle, output: Arc<Mutex<Vec<String>>>, deadline: Instant) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_expr_depths(64, 32);
+    engine.on_progress(move |_| (Instant::now() > deadline).then(|| Dynamic::from("time limit exceeded")));
+    engine.on_print(move |s| {
+        let mut out = output.lock().unwrap_or_else(|e| e.into_inner());
+        if out.len() < MAX_OUTPUT {
+            out.push(s.to_string());
+        }
+    });
+
+    let (st, h) = (state.clone(), handle.clone());
+    engine.register_fn("now_playing", move || -> Result<Dynamic, Box<EvalAltResult>> {
+        let now = h.block_on(async { st.playout.read().await.now.clone() });
+        rhai::serde::to_dynamic(now)
+    });
+    let (st, h) = (state.clone(), handle.clone());
+    engine.register_fn("queue", move || -> Result<Dynamic, Box<EvalAltResult>> {
+        let log = h.block_on(async { st.playout.read().await.log.clone() });
+        rhai::serde::to_dynamic(log)
+    });
+
+    let queue_op = {
+        let (st, h) = (state.clone(), handle.clone());
+        move |op: QueueBatchOp| -> Result<(), Box<EvalAltResult>> {
+            h.block_on(async {
+                let mut p = st.playout.write().await;
+                apply_queue_batch_op(&mut p.log, op).map_err(fail)?;
+                normalize_log_state(&mut p);
+                bump_queue_rev(&mut p);
+                persist_queue(p.log.clone()).await;
+                Ok(())
+            })
+        }
+    };
+    let insert = {
+        let (h, op) = (handle.clone(), queue_op.clone());
+        move |reference: &str, after_id: Option<&str>| -> Result<(), Box<EvalAltResult>> {
+            let after_id = after_id.map(Uuid::parse_str).transpose().map_err(fail)?;
+            let item = h.block_on(resolve_insert_ref(reference, "MUS".into()));
+            let item = item.map_err(|errors| fail(errors.join("; ")))?;
+            op(QueueBatchOp::Insert { after_id, item })
+        }
+    };
+    let ins = insert.clone();
+    engine.register_fn("queue_insert", move |reference: &str| ins(reference, None));
+    engine.register_fn("queue_insert", move |reference: &str, after_id: &str| insert(reference, Some(after_id)));
+    let op = queue_op.clone();
+    engine.register_fn("queue_remove", move |id: &str| {
+        op(QueueBatchOp::Remove { id: Uuid::parse_str(id).map_err(fail)? })
+    });
+    engine.register_fn("queue_move", move |id: &str, to: i64| {
+        let id = Uuid::parse_str(id).map_err(fail)?;
+        queue_op(QueueBatchOp::Move { id, to: usize::try_from(to).map_err(fail)? })
+    });
+
+    let (st, h) = (state.clone(), handle.clone());
+    engine.register_fn("skip", move || {
+        h.block_on(async { advance_to_next(&mut *st.playout.write().await, Some("skipped")) });
+    });
+    let (st, h) = (state.clone(), handle.clone());
+    engine.register_fn("output_start", move || -> Result<(), Box<EvalAltResult>> {
+        h.block_on(output_start_internal(
+            st.output.clone(),
+            st.playout.clone(),
+            st.topup.clone(),
+            st.topup_stats.clone(),
+            st.pcm_tx.clone(),
+        ))
+        .or_else(|code| if code == StatusCode::CONFLICT { Ok(()) } else { Err(code) })
+        .map_err(|code| fail(format!("output start failed ({code})")))
+    });
+    let (st, h) = (state.clone(), handle.clone());
+    engine.register_fn("output_stop", move || h.block_on(output_stop_internal(st.output.clone())));
+    let (st, h) = (state.clone(), handle.clone());
+    engine.register_fn("on_air", move || h.block_on(async { st.output.lock().await.status.state == "connected" }));
+    engine.register_fn("action", move |action: Dynamic| -> Result<(), Box<EvalAltResult>> {
+        let action: Action = rhai::serde::from_dynamic(&action)?;
+        if matches!(action, Action::Macro { .. }) {
+            return Err(fail("a macro cannot run another macro"));
+        }
+        handle.block_on(schedule::run_action(&state, &action)).map_err(fail)
+    });
+
+    engine.register_fn("http_get", |url: &str| curl(&[url]));
+    engine.register_fn("http_post", |url: &str, body: &str| {
+        curl(&["-H", "Content-Type: application/json", "--data-binary", body, url])
+    });
+    engine.register_fn("sleep", move |ms: i64| {
+        let left = deadline.saturating_duration_since(Instant::now());
+        std::thread::sleep(Duration::from_millis(ms.max(0) as u64).min(left));
+    });
+    engine
+}
+
+/// Compile without running, for the save-time check.
+fn check(script: &str) -> Result<(), String> {
+    Engine::new().compile(script).map(|_| ()).map_err(|e| e.to_string())
+}
+
+/// Run the macro `name` with `input` (a request body, or null).
+pub(crate) async fn run(state: &AppState, name: &str, input: serde_json::Value) -> Result<RunOutcome, String> {
+    let n = name.to_string();
+    let m = with_db(move |conn| db_get(conn, &n)).await.map_err(|e| e.to_string())?;
+    let m = m.ok_or_else(|| format!("no macro named {name}"))?;
+    if !RUNNING.lock().unwrap_or_else(|e| e.into_inner()).get_or_insert_with(HashSet::new).insert(m.name.clone()) {
+        return Err(format!("macro {name} is already running"));
+    }
+    tracing::info!(target: "audit", "macros: running {name}");
+
+    let started = Instant::now();
+    let output = Arc::new(Mutex::new(Vec::new()));
+    let (state, handle, out) = (state.clone(), Handle::current(), output.clone());
+    let script = m.script.clone();
+    let ran = tokio::task::spawn_blocking(move || -> Result<serde_json::Value, String> {
+        let engine = build_engine(state, handle, out, started + RUN_LIMIT);
+        let mut scope = Scope::new();
+        scope.push_dynamic("input", rhai::serde::to_dynamic(input).map_err(|e| e.to_string())?);
+        let result = engine.eval_with_scope::<Dynamic>(&mut scope, &script).map_err(|e| e.to_string())?;
+        rhai::serde::from_dynamic(&result).map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap_or_else(|e| Err(format!("macro panicked: {e}")));
+    RUNNING.lock().unwrap_or_else(|e| e.into_inner()).get_or_insert_with(HashSet::new).remove(&m.name);
+
+    let last_result = match &ran {
+        Ok(_) => "ok".to_string(),
+        Err(e) => {
+            tracing::warn!("macros: {name}: {e}");
+            e.clone()
+        }
+    };
+    let (n, now) = (m.name.clone(), unix_ms_now());
+    if let Err(e) = with_db(move |conn| {
+        conn.execute(
+            "UPDATE macros SET last_run_ms = ?2, last_result = ?3 WHERE name = ?1",
+            params![n, now as i64, last_result],
+        )?;
+        Ok(())
+    })
+    .await
+    {
+        tracing::warn!("macros: {e}");
+    }
+    let output = std::mem::take(&mut *output.lock().unwrap_or_else(|e| e.into_inner()));
+    let ms = started.elapsed().as_millis() as u64;
+    Ok(match ran {
+        Ok(result) => RunOutcome { ok: true, result, output, error: None, ms },
+        Err(e) => RunOutcome { ok: false, result: serde_json::Value::Null, output, error: Some(e), ms },
+    })
+}
+
+/// Run the macro `name` as an action (scheduler, GPIO, control surfaces).
+pub(crate) async fn fire(state: &AppState, name: &str) -> Result<(), String> {
+    let outcome = run(state, name, serde_json::Value::Null).await?;
+    outcome.error.map_or(Ok(()), Err)
+}
+
+/// A request body as the script's `input`: JSON if it parses, else the text.
+fn body_input(body: String) -> serde_json::Value {
+    if body.trim().is_empty() {
+        return serde_json::Value::Null;
+    }
+    serde_json::from_str(&body).unwrap_or(serde_json::Value::String(body))
+}
+
+// --- HTTP API --------------------------------------------------------------------------
+
+type ApiError = (StatusCode, Json<serde_json::Value>);
+
+fn api_error(status: StatusCode, msg: impl Into<String>) -> ApiError {
+    (status, Json(json!({"ok": false, "error": msg.into()})))
+}
+
+fn db_error(e: anyhow::Error) -> ApiError {
+    api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+/// `GET /api/v1/macros`
+pub(crate) async fn api_macros_list() -> Result<Json<Vec<Macro>>, StatusCode> {
+    with_db(|conn| {
+        let mut stmt = conn.prepare(&format!("SELECT {COLUMNS} FROM macros ORDER BY name"))?;
+        let rows = stmt.query_map([], row_to_macro)?.collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    })
+    .await
+    .map(Json)
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// `GET /api/v1/macros/{name}`
+pub(crate) async fn api_macro_get(Path(name): Path<String>) -> Result<Json<Macro>, StatusCode> {
+    match with_db(move |conn| db_get(conn, &name)).await {
+        Ok(Some(m)) => Ok(Json(m)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct MacroPut {
+    script: String,
+    #[serde(default)]
+    description: String,
+    /// Give the macro a hook token (kept if it has one), or take it away.
+    #[serde(default)]
+    webhook: bool,
+}
+
+/// `PUT /api/v1/macros/{name}`: create or replace a macro.
+pub(crate) async fn api_macro_put(
+    Path(name): Path<String>,
+    Json(req): Json<MacroPut>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    if !valid_name(&name) {
+        return Err(api_error(StatusCode::BAD_REQUEST, "name must be 1-64 letters, digits, '-' or '_'"));
+    }
+    check(&req.script).map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
+    let m = with_db(move |conn| {
+        let token = match (req.webhook, db_get(conn, &name)?.and_then(|m| m.hook_token)) {
+            (false, _) => None,
+            (true, Some(token)) => Some(token),
+            (true, None) => Some(Uuid::new_v4().simple().to_string()),
+        };
+        conn.execute(
+            "INSERT INTO macros (name, description, script, hook_token, updated_ms) VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT(name) DO UPDATE SET description=excluded.description, script=excluded.script,
+                 hook_token=excluded.hook_token, updated_ms=excluded.updated_ms",
+            params![name, req.description.trim(), req.script, token, unix_ms_now() as i64],
+        )?;
+        db_get(conn, &name)
+    })
+    .await
+    .map_err(db_error)?;
+    Ok(Json(json!({"ok": true, "macro": m})))
+}
+
+/// `DELETE /api/v1/macros/{name}`
+pub(crate) async fn api_macro_delete(Path(name): Path<String>) -> Result<Json<serde_json::Value>, ApiError> {
+    let n = with_db(move |conn| Ok(conn.execute("DELETE FROM macros WHERE name = ?1", params![name])?))
+        .await
+        .map_err(db_error)?;
+    if n == 0 {
+        return Err(api_error(StatusCode::NOT_FOUND, "no such macro"));
+    }
+    Ok(Json(json!({"ok": true})))
+}
+
+/// `POST /api/v1/macros/{name}/run`: run now and return the result and output.
+pub(crate) async fn api_macro_run(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    body: String,
+) -> Result<Json<RunOutcome>, ApiError> {
+    run(&state, &name, body_input(body)).await.map(Json).map_err(|e| api_error(StatusCode::CONFLICT, e))
+}
+
+/// `POST /api/v1/public/hooks/{token}`: run the macro with that hook token.
+/// Only success or failure is returned, not the script's output.
+pub(crate) async fn api_macro_hook(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+    body: String,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let m = with_db(move |conn| db_by_token(conn, &token)).await.map_err(db_error)?;
+    let m = m.ok_or_else(|| api_error(StatusCode::NOT_FOUND, "unknown hook"))?;
+    let outcome = run(&state, &m.name, body_input(body)).await.map_err(|e| api_error(StatusCode::CONFLICT, e))?;
+    Ok(Json(json!({"ok": outcome.ok, "error": outcome.error})))
+}
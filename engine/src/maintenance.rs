@@ -0,0 +1,361 @@
+// --- Database maintenance ----------------------------------------------------------------
+//
+// The database lives for years on an SD card or a small eMMC. Left alone,
+// SQLite never returns freed pages to the file system, the WAL file keeps
+// the size of its largest burst, and corruption (a bad card, a power cut at
+// the wrong moment) goes unnoticed until a query fails on air. A background
+// job now looks after it:
+// - `checkpoint`: `PRAGMA wal_checkpoint(TRUNCATE)` every 15 minutes, so the
+//   WAL is folded into the main file and truncated to zero.
+// - `integrity`: `PRAGMA integrity_check` once a day.
+// - `vacuum`: `VACUUM` once a week, only when at least 10% of the file is free
+//   pages (otherwise there is nothing worth rewriting), and only when the
+//   disk has room for the rewritten copy.
+//
+// Both `integrity` and `vacuum` hold the database worker for as long as they
+// run, so scheduled runs wait for the maintenance window
+// (`STUDIOCOMMAND_DB_MAINT_WINDOW`, local time, default `03:00-05:00`).
+// Playout does not wait on the database, but the API and history writes do.
+//
+// The outcome of each task's last run is kept in `db_maintenance`, so the
+// weekly and daily cadence survives restarts, and is reported (with file
+// sizes and page counts) by `GET /api/v1/admin/db/status`.
+// `POST /api/v1/admin/db/maintenance {"task": "..."}` runs one right away,
+// regardless of the window.
+
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use axum::{http::StatusCode, Json};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{db, db_path, local_weekday_minute, migrations, parse_hhmm, unix_ms_now};
+
+const CHECKPOINT_EVERY_MS: u64 = 15 * 60_000;
+const INTEGRITY_EVERY_MS: u64 = 24 * 3_600_000;
+const VACUUM_EVERY_MS: u64 = 7 * 24 * 3_600_000;
+/// VACUUM only pays off above this share of free pages (percent).
+const VACUUM_MIN_FREE_PCT: u64 = 10;
+/// Problems listed by one integrity check, at most.
+const INTEGRITY_MAX_ERRORS: u32 = 20;
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS db_maintenance (
+            task         TEXT PRIMARY KEY,
+            ran_ms       INTEGER NOT NULL,
+            ok           INTEGER NOT NULL,
+            result       TEXT NOT NULL,
+            duration_ms  INTEGER NOT NULL
+        );
+        "#,
+    )
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Task {
+    Checkpoint,
+    Integrity,
+    Vacuum,
+}
+
+impl Task {
+    const ALL: [Task; 3] = [Task::Checkpoint, Task::Integrity, Task::Vacuum];
+
+    fn name(self) -> &'static str {
+        match self {
+            Task::Checkpoint => "checkpoint",
+            Task::Integrity => "integrity",
+            Task::Vacuum => "vacuum",
+        }
+    }
+
+    fn every_ms(self) -> u64 {
+        match self {
+            Task::Checkpoint => CHECKPOINT_EVERY_MS,
+            Task::Integrity => INTEGRITY_EVERY_MS,
+            Task::Vacuum => VACUUM_EVERY_MS,
+        }
+    }
+
+    /// Whether scheduled runs wait for the maintenance window.
+    fn windowed(self) -> bool {
+        !matches!(self, Task::Checkpoint)
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+#[derive(Clone, Serialize)]
+struct Run {
+    ran_ms: u64,
+    ok: bool,
+    result: String,
+    duration_ms: u64,
+}
+
+/// Last run of each task, indexed by `Task::index`.
+static LAST: Mutex<[Option<Run>; 3]> = Mutex::new([None, None, None]);
+/// One task at a time, whether scheduled or requested.
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// `(start, end)` minutes after local midnight; `end < start` spans midnight.
+fn window() -> (u32, u32) {
+    let parsed = std::env::var("STUDIOCOMMAND_DB_MAINT_WINDOW").ok().and_then(|v| {
+        let (a, b) = v.split_once('-')?;
+        Some((parse_hhmm(a)?, parse_hhmm(b)?))
+    });
+    parsed.unwrap_or((3 * 60, 5 * 60))
+}
+
+fn in_window(now_ms: u64) -> bool {
+    let Some((_, minute)) = local_weekday_minute(now_ms) else { return false };
+    let (start, end) = window();
+    if start <= end {
+        (start..end).contains(&minute)
+    } else {
+        minute >= start || minute < end
+    }
+}
+
+fn fmt_hhmm(m: u32) -> String {
+    format!("{:02}:{:02}", m / 60, m % 60)
+}
+
+/// Bytes available to unprivileged users on the file system holding `path`.
+fn disk_free(path: &Path) -> Option<u64> {
+    let c = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut st: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c.as_ptr(), &mut st) } != 0 {
+        return None;
+    }
+    Some(st.f_bavail as u64 * st.f_frsize as u64)
+}
+
+fn file_len(path: &str) -> u64 {
+    std::fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
+// --- Tasks -------------------------------------------------------------------------------
+
+fn pragma_i64(conn: &Connection, name: &str) -> rusqlite::Result<i64> {
+    conn.query_row(&format!("PRAGMA {name}"), [], |row| row.get(0))
+}
+
+/// (ok, result) of one task, run on the database worker.
+fn run_on(conn: &Connection, task: Task) -> anyhow::Result<(bool, String)> {
+    match task {
+        Task::Checkpoint => {
+            let (busy, log, done): (i64, i64, i64) = conn
+                .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+            if busy != 0 {
+                return Ok((false, format!("busy: checkpointed {done} of {log} frames")));
+            }
+            Ok((true, format!("checkpointed {done} frames, WAL truncated")))
+        }
+        Task::Integrity => {
+            let mut stmt = conn.prepare(&format!("PRAGMA integrity_check({INTEGRITY_MAX_ERRORS})"))?;
+            let rows = stmt.query_map([], |row| row.get::<_, String>(0))?.collect::<Result<Vec<_>, _>>()?;
+            let ok = rows.len() == 1 && rows[0] == "ok";
+            Ok((ok, rows.join("; ")))
+        }
+        Task::Vacuum => {
+            let page_size = pragma_i64(conn, "page_size")?.max(0) as u64;
+            let pages = pragma_i64(conn, "page_count")?.max(0) as u64;
+            let free = pragma_i64(conn, "freelist_count")?.max(0) as u64;
+            let pct = (free * 100).checked_div(pages).unwrap_or(0);
+            if pct < VACUUM_MIN_FREE_PCT {
+                return Ok((true, format!("skipped: {pct}% free pages")));
+            }
+            // VACUUM writes a full copy of the database before replacing it.
+            let need = pages * page_size;
+            let path = db_path();
+            let dir = Path::new(&path).parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+            if let Some(avail) = disk_free(dir) {
+                if avail < need {
+                    return Ok((false, format!("skipped: needs {need} bytes free, disk has {avail}")));
+                }
+            }
+            conn.execute_batch("VACUUM")?;
+            let after = pragma_i64(conn, "page_count")?.max(0) as u64;
+            // The rewrite went through the WAL; fold it back so the file shrinks now.
+            conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+            Ok((true, format!("reclaimed {} bytes ({pct}% free pages)", pages.saturating_sub(after) * page_size)))
+        }
+    }
+}
+
+/// Run `task` now and record the outcome. `None` if another task is running.
+async fn run(task: Task) -> Option<Run> {
+    if RUNNING.swap(true, Ordering::AcqRel) {
+        return None;
+    }
+    let started = unix_ms_now();
+    let res = db::call(move |conn| -> anyhow::Result<(bool, String)> {
+        crate::db_init(conn)?;
+        run_on(conn, task)
+    })
+    .await;
+    let (ok, result) = match res {
+        Ok(Ok(r)) => r,
+        Ok(Err(e)) => (false, format!("error: {e}")),
+        Err(e) => (false, format!("error: {e}")),
+    };
+    let run = Run { ran_ms: started, ok, result, duration_ms: unix_ms_now().saturating_sub(started) };
+    RUNNING.store(false, Ordering::Release);
+
+    if run.ok {
+        tracing::info!("db maintenance: {}: {} ({} ms)", task.name(), run.result, run.duration_ms);
+    } else if task == Task::Integrity {
+        tracing::error!("db maintenance: integrity check FAILED: {}", run.result);
+    } else {
+        tracing::warn!("db maintenance: {}: {}", task.name(), run.result);
+    }
+
+    LAST.lock().unwrap_or_else(|e| e.into_inner())[task.index()] = Some(run.clone());
+    let r = run.clone();
+    let saved = db::call(move |conn| -> anyhow::Result<()> {
+        crate::db_init(conn)?;
+        conn.execute(
+            "INSERT INTO db_maintenance (task, ran_ms, ok, result, duration_ms) VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT(task) DO UPDATE SET ran_ms=excluded.ran_ms, ok=excluded.ok, result=excluded.result, duration_ms=excluded.duration_ms",
+            params![task.name(), r.ran_ms as i64, r.ok as i64, r.result, r.duration_ms as i64],
+        )?;
+        Ok(())
+    })
+    .await;
+    if let Ok(Err(e)) = saved {
+        tracing::warn!("db maintenance: cannot record {}: {e}", task.name());
+    }
+    Some(run)
+}
+
+/// Read the recorded runs into memory.
+async fn load() {
+    let res = db::call(|conn| -> anyhow::Result<Vec<(String, Run)>> {
+        crate::db_init(conn)?;
+        let mut stmt = conn.prepare("SELECT task, ran_ms, ok, result, duration_ms FROM db_maintenance")?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    Run {
+                        ran_ms: row.get::<_, i64>(1)?.max(0) as u64,
+                        ok: row.get::<_, i64>(2)? != 0,
+                        result: row.get(3)?,
+                        duration_ms: row.get::<_, i64>(4)?.max(0) as u64,
+                    },
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    })
+    .await;
+    match res {
+        Ok(Ok(rows)) => {
+            let mut last = LAST.lock().unwrap_or_else(|e| e.into_inner());
+            for (name, run) in rows {
+                if let Some(t) = Task::ALL.iter().find(|t| t.name() == name) {
+                    last[t.index()] = Some(run);
+                }
+            }
+        }
+        Ok(Err(e)) => tracing::warn!("db maintenance: cannot read previous runs: {e}"),
+        Err(e) => tracing::warn!("db maintenance: {e}"),
+    }
+}
+
+fn due_ms(task: Task) -> u64 {
+    LAST.lock().unwrap_or_else(|e| e.into_inner())[task.index()]
+        .as_ref()
+        .map(|r| r.ran_ms + task.every_ms())
+        .unwrap_or(0)
+}
+
+pub(crate) async fn maintenance_task() {
+    load().await;
+    let mut tick = tokio::time::interval(std::time::Duration::from_secs(60));
+    loop {
+        tick.tick().await;
+        let now = unix_ms_now();
+        for task in Task::ALL {
+            if due_ms(task) <= now && (!task.windowed() || in_window(now)) {
+                run(task).await;
+            }
+        }
+    }
+}
+
+// --- API ---------------------------------------------------------------------------------
+
+pub(crate) async fn api_db_status() -> Result<Json<serde_json::Value>, StatusCode> {
+    let stats = db::call(|conn| -> anyhow::Result<(i64, i64, i64, u32)> {
+        crate::db_init(conn)?;
+        Ok((
+            pragma_i64(conn, "page_size")?,
+            pragma_i64(conn, "page_count")?,
+            pragma_i64(conn, "freelist_count")?,
+            migrations::current(conn)?,
+        ))
+    })
+    .await
+    .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let (page_size, page_count, freelist_count, schema_version) = stats;
+
+    let path = db_path();
+    let dir = Path::new(&path).parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new(".")).to_path_buf();
+    let (start, end) = window();
+    let last = LAST.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    let mut tasks = serde_json::Map::new();
+    for task in Task::ALL {
+        tasks.insert(
+            task.name().into(),
+            json!({
+                "every_ms": task.every_ms(),
+                "windowed": task.windowed(),
+                "due_ms": due_ms(task),
+                "last": last[task.index()],
+            }),
+        );
+    }
+
+    Ok(Json(json!({
+        "ok": true,
+        "path": path,
+        "size_bytes": file_len(&path),
+        "wal_bytes": file_len(&format!("{path}-wal")),
+        "disk_free_bytes": disk_free(&dir),
+        "page_size": page_size,
+        "page_count": page_count,
+        "freelist_count": freelist_count,
+        "schema_version": schema_version,
+        "schema_latest": migrations::latest(),
+        "window": format!("{}-{}", fmt_hhmm(start), fmt_hhmm(end)),
+        "running": RUNNING.load(Ordering::Acquire),
+        "tasks": tasks,
+    })))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct RunRequest {
+    task: Task,
+}
+
+pub(crate) async fn api_db_maintenance(
+    Json(req): Json<RunRequest>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    match run(req.task).await {
+        Some(r) => Ok(Json(json!({"ok": r.ok, "task": req.task.name(), "run": r}))),
+        None => Err((StatusCode::CONFLICT, Json(json!({"ok": false, "error": "another maintenance task is running"})))),
+    }
+}
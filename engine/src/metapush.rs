@@ -0,0 +1,447 @@
+// --- Outbound metadata push --------------------------------------------------------
+//
+// Directories and station websites want to know what is playing without
+// polling us: TuneIn AIR takes a "now playing" call per track, and most other
+// services (Live365-style APIs, website webhooks, RDS encoders with an HTTP
+// input) take a plain GET or POST. Each configured target is pushed to on
+// every track change.
+//
+// Targets:
+// - `tunein`: TuneIn AIR (`partner_id`, `partner_key`, `station_id`). Items
+//   whose tag is not in `tags` are reported as commercials, as TuneIn asks.
+// - `http`: `method` + `url` (+ `body` for POST) templates with `{title}`,
+//   `{artist}`, `{tag}`, `{cart}` and `{dur}` (seconds) placeholders. Values
+//   are URL-encoded in the URL and in form bodies, JSON-escaped in JSON
+//   bodies. Only items whose tag is in `tags` are sent (empty: all).
+//
+// Items whose tag is flagged `suppress_metadata` (tags.rs) are not pushed to
+// any target.
+//
+// Rate limiting: a target receives at most one push per `min_interval_s`.
+// Changes in between are coalesced; the latest is sent when the interval is
+// up (a run of short jingles does not hammer the service, and what it shows
+// still ends up correct).
+//
+// Requests go through `curl` (`STUDIOCOMMAND_CURL`), the same way audio work
+// goes through ffmpeg, so the engine needs no TLS stack of its own. Delivery
+// status is kept in memory and returned with the target list.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::watch;
+use tokio::time::{Duration, Instant};
+
+use crate::{ffmpeg, tags, unix_ms_now, AppState};
+
+/// Longest a single delivery may take.
+const TIMEOUT_S: u32 = 10;
+
+/// What is pushed on a track change.
+#[derive(Clone, Default)]
+pub(crate) struct Track {
+    pub(crate) title: String,
+    pub(crate) artist: String,
+    pub(crate) tag: String,
+    pub(crate) cart: String,
+    pub(crate) dur_s: u32,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct MetaTarget {
+    #[serde(default)]
+    id: i64,
+    name: String,
+    /// "tunein" or "http".
+    kind: String,
+    #[serde(default = "default_true")]
+    enabled: bool,
+    /// Tags that count as music (see module comment); empty means all.
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default = "default_interval")]
+    min_interval_s: u32,
+    // tunein
+    #[serde(default)]
+    partner_id: String,
+    #[serde(default)]
+    partner_key: String,
+    #[serde(default)]
+    station_id: String,
+    // http
+    #[serde(default)]
+    method: String,
+    #[serde(default)]
+    url: String,
+    #[serde(default)]
+    body: String,
+    #[serde(default)]
+    content_type: String,
+    /// Extra request headers, `Name: value`.
+    #[serde(default)]
+    headers: Vec<String>,
+    /// Delivery status, filled in on read.
+    #[serde(default, skip_deserializing)]
+    status: Option<DeliveryStatus>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_interval() -> u32 {
+    10
+}
+
+#[derive(Clone, Serialize, Default)]
+pub(crate) struct DeliveryStatus {
+    sent: u64,
+    failed: u64,
+    /// Changes replaced by a later one while waiting out `min_interval_s`.
+    coalesced: u64,
+    last_attempt_ms: Option<u64>,
+    last_ok_ms: Option<u64>,
+    last_http_status: Option<u16>,
+    last_error: Option<String>,
+    last_title: Option<String>,
+}
+
+impl MetaTarget {
+    fn is_music(&self, tag: &str) -> bool {
+        self.tags.is_empty() || self.tags.iter().any(|t| t.eq_ignore_ascii_case(tag))
+    }
+
+    /// Whether this target gets a push for `track` at all. Tags flagged
+    /// `suppress_metadata` (tags.rs) go to no target.
+    fn wants(&self, track: &Track) -> bool {
+        !tags::suppresses_metadata(&track.tag) && (self.kind == "tunein" || self.is_music(&track.tag))
+    }
+}
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS metadata_targets (
+            id       INTEGER PRIMARY KEY AUTOINCREMENT,
+            config   TEXT NOT NULL
+        );
+        "#,
+    )
+}
+
+fn db_list(conn: &Connection) -> anyhow::Result<Vec<MetaTarget>> {
+    crate::db_init(conn)?;
+    let mut stmt = conn.prepare("SELECT id, config FROM metadata_targets ORDER BY id")?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?;
+    let mut out = Vec::new();
+    for r in rows {
+        let (id, config) = r?;
+        match serde_json::from_str::<MetaTarget>(&config) {
+            Ok(mut t) => {
+                t.id = id;
+                out.push(t);
+            }
+            Err(e) => tracing::warn!("metadata push: target {id} unreadable: {e}"),
+        }
+    }
+    Ok(out)
+}
+
+async fn with_db<T: Send + 'static>(
+    f: impl FnOnce(&Connection) -> anyhow::Result<T> + Send + 'static,
+) -> anyhow::Result<T> {
+    crate::db::call(move |conn| f(conn)).await?
+}
+
+fn status() -> &'static Mutex<HashMap<i64, DeliveryStatus>> {
+    static STATUS: OnceLock<Mutex<HashMap<i64, DeliveryStatus>>> = OnceLock::new();
+    STATUS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn changes() -> &'static watch::Sender<Option<Track>> {
+    static CHANGES: OnceLock<watch::Sender<Option<Track>>> = OnceLock::new();
+    CHANGES.get_or_init(|| watch::channel(None).0)
+}
+
+/// Called by playout when an item starts.
+pub(crate) fn track_changed(track: Track) {
+    changes().send_replace(Some(track));
+}
+
+/// Track changes for other outputs (e.g. RDS).
+pub(crate) fn subscribe() -> watch::Receiver<Option<Track>> {
+    changes().subscribe()
+}
+
+// --- Delivery ------------------------------------------------------------------------
+
+fn url_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => out.push(b as char),
+            _ => out.push_str(&format!("%{b:02X}")),
+        }
+    }
+    out
+}
+
+fn json_escape(s: &str) -> String {
+    let quoted = serde_json::to_string(s).unwrap_or_default();
+    quoted.trim_matches('"').to_string()
+}
+
+pub(crate) fn render(template: &str, track: &Track, escape: fn(&str) -> String) -> String {
+    template
+        .replace("{title}", &escape(&track.title))
+        .replace("{artist}", &escape(&track.artist))
+        .replace("{tag}", &escape(&track.tag))
+        .replace("{cart}", &escape(&track.cart))
+        .replace("{dur}", &track.dur_s.to_string())
+}
+
+/// (method, url, content type, body) for one push.
+fn request_for(t: &MetaTarget, track: &Track) -> (String, String, String, String) {
+    if t.kind == "tunein" {
+        let mut url = format!(
+            "http://air.radiotime.com/Playing.ashx?partnerId={}&partnerKey={}&id={}&title={}&artist={}",
+            url_encode(&t.partner_id),
+            url_encode(&t.partner_key),
+            url_encode(&t.station_id),
+            url_encode(&track.title),
+            url_encode(&track.artist),
+        );
+        if !t.is_music(&track.tag) {
+            url.push_str("&commercial=true");
+        }
+        return ("GET".into(), url, String::new(), String::new());
+    }
+    let method = if t.method.is_empty() { "GET".to_string() } else { t.method.to_uppercase() };
+    let content_type =
+        if t.content_type.is_empty() { "application/x-www-form-urlencoded".to_string() } else { t.content_type.clone() };
+    let body = if content_type.contains("json") {
+        render(&t.body, track, json_escape)
+    } else {
+        render(&t.body, track, url_encode)
+    };
+    (method, render(&t.url, track, url_encode), content_type, body)
+}
+
+/// Send one push via curl. Returns the HTTP status on a 2xx response.
+async fn send(t: &MetaTarget, track: &Track) -> Result<u16, String> {
+    let (method, url, content_type, body) = request_for(t, track);
+    let curl = ffmpeg::curl_bin();
+    let mut cmd = tokio::process::Command::new(&curl);
+    cmd.args(["-sS", "-o", "/dev/null", "-w", "%{http_code}", "--max-time", &TIMEOUT_S.to_string(), "-X", &method]);
+    for h in &t.headers {
+        cmd.args(["-H", h]);
+    }
+    if !body.is_empty() && method != "GET" {
+        cmd.args(["-H", &format!("Content-Type: {content_type}"), "--data-binary", &body]);
+    }
+    cmd.arg(&url).kill_on_drop(true);
+    let out = match tokio::time::timeout(Duration::from_secs(TIMEOUT_S as u64 + 5), cmd.output()).await {
+        Ok(Ok(out)) => out,
+        Ok(Err(e)) => return Err(format!("{curl}: {e}")),
+        Err(_) => return Err("timed out".into()),
+    };
+    let code: u16 = String::from_utf8_lossy(&out.stdout).trim().parse().unwrap_or(0);
+    if (200..300).contains(&code) {
+        return Ok(code);
+    }
+    let stderr = String::from_utf8_lossy(&out.stderr).trim().to_string();
+    Err(if code != 0 { format!("HTTP {code}") } else if stderr.is_empty() { "request failed".into() } else { stderr })
+}
+
+/// Send and record the outcome in the delivery status.
+async fn deliver(t: MetaTarget, track: Track) -> Result<u16, String> {
+    let res = send(&t, &track).await;
+    let now_ms = unix_ms_now();
+    let mut all = status().lock().unwrap_or_else(|e| e.into_inner());
+    let s = all.entry(t.id).or_default();
+    s.last_attempt_ms = Some(now_ms);
+    s.last_title = Some(track.title.clone());
+    match &res {
+        Ok(code) => {
+            s.sent += 1;
+            s.last_ok_ms = Some(now_ms);
+            s.last_http_status = Some(*code);
+            s.last_error = None;
+        }
+        Err(e) => {
+            s.failed += 1;
+            s.last_http_status = e.strip_prefix("HTTP ").and_then(|c| c.parse().ok());
+            s.last_error = Some(e.clone());
+            tracing::warn!("metadata push to {}: {e}", t.name);
+        }
+    }
+    res
+}
+
+/// Push every track change to the enabled targets, honouring `min_interval_s`.
+pub(crate) async fn push_task() {
+    let mut rx = changes().subscribe();
+    let mut last_sent: HashMap<i64, Instant> = HashMap::new();
+    // Held back by the rate limit: due time, target, latest track.
+    let mut pending: HashMap<i64, (Instant, MetaTarget, Track)> = HashMap::new();
+
+    loop {
+        let next_due = pending.values().map(|(due, _, _)| *due).min();
+        tokio::select! {
+            changed = rx.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+                let Some(track) = rx.borrow_and_update().clone() else { continue };
+                let targets = match with_db(db_list).await {
+                    Ok(t) => t,
+                    Err(e) => {
+                        tracing::warn!("metadata push: {e}");
+                        continue;
+                    }
+                };
+                let now = Instant::now();
+                for t in targets.into_iter().filter(|t| t.enabled && t.wants(&track)) {
+                    let interval = Duration::from_secs(t.min_interval_s as u64);
+                    let due = last_sent.get(&t.id).map(|l| *l + interval).unwrap_or(now);
+                    if due <= now {
+                        last_sent.insert(t.id, now);
+                        tokio::spawn(deliver(t, track.clone()));
+                    } else if pending.insert(t.id, (due, t.clone(), track.clone())).is_some() {
+                        status().lock().unwrap_or_else(|e| e.into_inner()).entry(t.id).or_default().coalesced += 1;
+                    }
+                }
+            }
+            _ = tokio::time::sleep_until(next_due.unwrap_or_else(|| Instant::now() + Duration::from_secs(3600))), if next_due.is_some() => {
+                let now = Instant::now();
+                let due: Vec<i64> = pending.iter().filter(|(_, (d, _, _))| *d <= now).map(|(id, _)| *id).collect();
+                for id in due {
+                    if let Some((_, t, track)) = pending.remove(&id) {
+                        last_sent.insert(id, now);
+                        tokio::spawn(deliver(t, track));
+                    }
+                }
+            }
+        }
+    }
+}
+
+// --- HTTP API -------------------------------------------------------------------------
+
+pub(crate) async fn api_targets_list() -> Result<Json<Vec<MetaTarget>>, StatusCode> {
+    let mut targets = with_db(db_list).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let all = status().lock().unwrap_or_else(|e| e.into_inner());
+    for t in targets.iter_mut() {
+        t.status = Some(all.get(&t.id).cloned().unwrap_or_default());
+    }
+    Ok(Json(targets))
+}
+
+fn validate(t: &mut MetaTarget) -> Result<(), (StatusCode, Json<serde_json::Value>)> {
+    let bad = |msg: &str| Err((StatusCode::BAD_REQUEST, Json(json!({"ok": false, "error": msg}))));
+    t.name = t.name.trim().to_string();
+    t.tags = t.tags.iter().map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect();
+    if t.name.is_empty() {
+        return bad("name is required");
+    }
+    match t.kind.as_str() {
+        "tunein" => {
+            if t.partner_id.trim().is_empty() || t.partner_key.trim().is_empty() || t.station_id.trim().is_empty() {
+                return bad("tunein needs partner_id, partner_key and station_id");
+            }
+        }
+        "http" => {
+            if !(t.url.starts_with("http://") || t.url.starts_with("https://")) {
+                return bad("url must start with http:// or https://");
+            }
+            if !matches!(t.method.to_uppercase().as_str(), "" | "GET" | "POST" | "PUT") {
+                return bad("method must be GET, POST or PUT");
+            }
+            if t.headers.iter().any(|h| !h.contains(':')) {
+                return bad("headers must be \"Name: value\"");
+            }
+        }
+        _ => return bad("kind must be tunein or http"),
+    }
+    t.status = None;
+    Ok(())
+}
+
+pub(crate) async fn api_target_create(
+    Json(mut t): Json<MetaTarget>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    validate(&mut t)?;
+    let id = with_db(move |conn| {
+        crate::db_init(conn)?;
+        conn.execute("INSERT INTO metadata_targets (config) VALUES (?1)", params![serde_json::to_string(&t)?])?;
+        Ok(conn.last_insert_rowid())
+    })
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"ok": false}))))?;
+    Ok(Json(json!({"ok": true, "id": id})))
+}
+
+pub(crate) async fn api_target_put(
+    Path(id): Path<i64>,
+    Json(mut t): Json<MetaTarget>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    validate(&mut t)?;
+    t.id = id;
+    let n = with_db(move |conn| {
+        crate::db_init(conn)?;
+        Ok(conn.execute("UPDATE metadata_targets SET config = ?2 WHERE id = ?1", params![id, serde_json::to_string(&t)?])?)
+    })
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"ok": false}))))?;
+    if n == 0 {
+        return Err((StatusCode::NOT_FOUND, Json(json!({"ok": false}))));
+    }
+    Ok(Json(json!({"ok": true})))
+}
+
+pub(crate) async fn api_target_delete(Path(id): Path<i64>) -> Result<Json<serde_json::Value>, StatusCode> {
+    let n = with_db(move |conn| {
+        crate::db_init(conn)?;
+        Ok(conn.execute("DELETE FROM metadata_targets WHERE id = ?1", params![id])?)
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if n == 0 {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    status().lock().unwrap_or_else(|e| e.into_inner()).remove(&id);
+    Ok(Json(json!({"ok": true})))
+}
+
+/// `POST /api/v1/metadata/targets/{id}/test`: push what is playing now,
+/// ignoring the rate limit and the tag filter.
+pub(crate) async fn api_target_test(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let targets = with_db(db_list).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let t = targets.into_iter().find(|t| t.id == id).ok_or(StatusCode::NOT_FOUND)?;
+    let track = {
+        let p = state.playout.read().await;
+        let first = p.log.first();
+        Track {
+            title: p.now.title.clone(),
+            artist: p.now.artist.clone(),
+            tag: first.map(|it| it.tag.clone()).unwrap_or_default(),
+            cart: first.map(|it| it.cart.clone()).unwrap_or_default(),
+            dur_s: p.now.dur,
+        }
+    };
+    Ok(Json(match deliver(t, track).await {
+        Ok(code) => json!({"ok": true, "http_status": code}),
+        Err(e) => json!({"ok": false, "error": e}),
+    }))
+}
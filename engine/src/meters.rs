@@ -0,0 +1,60 @@
+// --- Program meters -------------------------------------------------------------------------
+//
+// The VU levels used to live in `PlayoutState`, so the writer took the
+// playout write lock at ~30 Hz just to store four numbers, and every meter
+// reader (HTTP polls, the WebRTC meters channel, alerts) took the read lock
+// to copy them, contending with queue edits and with the writer's own
+// decoder control. They are now four `AtomicU32`s holding `f32` bits: the
+// writer stores, everyone else loads, and nobody waits. A reader may see the
+// left channel of one update next to the right channel of the next, which is
+// invisible on a meter.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::{smooth_level, VuLevels};
+
+struct Levels {
+    rms_l: AtomicU32,
+    rms_r: AtomicU32,
+    peak_l: AtomicU32,
+    peak_r: AtomicU32,
+}
+
+// 0 is the bit pattern of 0.0f32.
+static LEVELS: Levels =
+    Levels { rms_l: AtomicU32::new(0), rms_r: AtomicU32::new(0), peak_l: AtomicU32::new(0), peak_r: AtomicU32::new(0) };
+
+fn load(a: &AtomicU32) -> f32 {
+    f32::from_bits(a.load(Ordering::Relaxed))
+}
+
+fn store(a: &AtomicU32, v: f32) {
+    a.store(v.to_bits(), Ordering::Relaxed);
+}
+
+/// The current levels.
+pub(crate) fn get() -> VuLevels {
+    VuLevels {
+        rms_l: load(&LEVELS.rms_l),
+        rms_r: load(&LEVELS.rms_r),
+        peak_l: load(&LEVELS.peak_l),
+        peak_r: load(&LEVELS.peak_r),
+    }
+}
+
+/// Back to silence, e.g. when the playing item changes.
+pub(crate) fn reset() {
+    for a in [&LEVELS.rms_l, &LEVELS.rms_r, &LEVELS.peak_l, &LEVELS.peak_r] {
+        store(a, 0.0);
+    }
+}
+
+/// Move the meters toward the levels of the chunk just played. Only the
+/// playout writer calls this, so the read-modify-write needs no lock.
+pub(crate) fn update(inst: &VuLevels) {
+    // Faster ballistics: snappy attack, moderate decay.
+    store(&LEVELS.rms_l, smooth_level(load(&LEVELS.rms_l), inst.rms_l, 0.95, 0.55));
+    store(&LEVELS.rms_r, smooth_level(load(&LEVELS.rms_r), inst.rms_r, 0.95, 0.55));
+    store(&LEVELS.peak_l, smooth_level(load(&LEVELS.peak_l), inst.peak_l, 1.00, 0.65));
+    store(&LEVELS.peak_r, smooth_level(load(&LEVELS.peak_r), inst.peak_r, 1.00, 0.65));
+}
@@ -0,0 +1,155 @@
+// --- Schema migrations ---------------------------------------------------------------
+//
+// `CREATE TABLE IF NOT EXISTS` creates a table once and never touches it
+// again, so every column added later needed a hand-written "add if missing"
+// check that ran on every connection, and nothing recorded which shape a
+// database was in. Schema changes are now ordered, numbered migrations:
+// - `schema_migrations` records each applied version (and when),
+// - on the first connection of the process, pending migrations run in order,
+//   each in its own `BEGIN IMMEDIATE` transaction (SQLite DDL is
+//   transactional, so a failed migration leaves the database as it was),
+// - a database newer than this binary (after a downgrade) is refused rather
+//   than written to with assumptions that no longer hold.
+//
+// Migration 1 is the schema as it stood when versioning was introduced; it is
+// idempotent, so existing installs pass through it unchanged. To change the
+// schema, append a migration with the next version. Never edit or reorder
+// one that has shipped.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::unix_ms_now;
+
+struct Migration {
+    version: u32,
+    name: &'static str,
+    up: fn(&Connection) -> rusqlite::Result<()>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration { version: 1, name: "baseline", up: crate::db_schema_baseline },
+    Migration { version: 2, name: "playout_resume", up: crate::resume::db_init },
+    Migration { version: 3, name: "db_maintenance", up: crate::maintenance::db_init },
+    Migration { version: 4, name: "config_file_applied", up: crate::reload::db_init },
+    Migration { version: 5, name: "alerts", up: crate::alerts::db_init },
+    Migration { version: 6, name: "public_feed_upcoming_max", up: crate::public::db_add_upcoming_max },
+    Migration { version: 7, name: "library_intro_ms", up: crate::library::db_add_intro_ms },
+    Migration { version: 8, name: "tags", up: crate::tags::db_init },
+    Migration { version: 9, name: "item_transitions", up: crate::transitions::db_init },
+    Migration { version: 10, name: "engine_events", up: crate::eventlog::db_init },
+    Migration { version: 11, name: "dur_ms", up: crate::db_add_dur_ms },
+    Migration { version: 12, name: "remote_config", up: crate::remote::db_init },
+    Migration { version: 13, name: "playback_failures", up: crate::failures::db_init },
+    Migration { version: 14, name: "gpio_config", up: crate::gpio::db_init },
+    Migration { version: 15, name: "surface_config", up: crate::surfaces::db_init },
+    Migration { version: 16, name: "macros", up: crate::macros::db_init },
+    Migration { version: 17, name: "cartwall", up: crate::cartwall::db_init },
+    Migration { version: 18, name: "voice_tracks", up: crate::voicetrack::db_init },
+    Migration { version: 19, name: "emergency_log", up: crate::emergency::db_init },
+    Migration { version: 20, name: "producers", up: crate::producers::db_init },
+    Migration { version: 21, name: "callers", up: crate::callers::db_init },
+    Migration { version: 22, name: "recordings", up: crate::recorder::db_init },
+    Migration { version: 23, name: "aes67_config", up: crate::aes67::db_init },
+    Migration { version: 24, name: "stl_config", up: crate::stl::db_init },
+    Migration { version: 25, name: "mqtt_config", up: crate::mqtt::db_init },
+    Migration { version: 26, name: "bot_config", up: crate::bots::db_init },
+    Migration { version: 27, name: "fallback_config", up: crate::fallback::db_init },
+    Migration { version: 28, name: "library_fts_update_columns", up: crate::library::db_narrow_fts_trigger },
+];
+
+/// Schema version this binary expects.
+pub(crate) fn latest() -> u32 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+/// Version recorded in the database (0 for a database that predates migrations).
+pub(crate) fn current(conn: &Connection) -> rusqlite::Result<u32> {
+    let table: Option<i64> = conn
+        .query_row("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'schema_migrations'", [], |row| {
+            row.get(0)
+        })
+        .optional()?;
+    if table.is_none() {
+        return Ok(0);
+    }
+    let v: Option<i64> = conn.query_row("SELECT MAX(version) FROM schema_migrations", [], |row| row.get(0))?;
+    Ok(v.unwrap_or(0) as u32)
+}
+
+fn failure(msg: String) -> rusqlite::Error {
+    rusqlite::Error::SqliteFailure(rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_ERROR), Some(msg))
+}
+
+/// Apply pending migrations once per process. Later calls return immediately.
+pub(crate) fn ensure(conn: &Connection) -> rusqlite::Result<()> {
+    static DONE: AtomicBool = AtomicBool::new(false);
+    static LOCK: Mutex<()> = Mutex::new(());
+    if DONE.load(Ordering::Acquire) {
+        return Ok(());
+    }
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    if DONE.load(Ordering::Acquire) {
+        return Ok(());
+    }
+    migrate(conn)?;
+    DONE.store(true, Ordering::Release);
+    Ok(())
+}
+
+/// Bring `conn` up to `latest()` now. `ensure` is the normal entry point; this
+/// is for a database whose contents were just replaced (restore).
+pub(crate) fn migrate(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS schema_migrations (
+            version     INTEGER PRIMARY KEY,
+            name        TEXT NOT NULL,
+            applied_ms  INTEGER NOT NULL
+        );
+        "#,
+    )?;
+    let from = current(conn)?;
+    if from > latest() {
+        return Err(failure(format!(
+            "database schema is version {from}, this build only knows up to {}; refusing to use a newer database",
+            latest()
+        )));
+    }
+
+    for m in MIGRATIONS.iter().filter(|m| m.version > from) {
+        // IMMEDIATE takes the write lock up front; another process (e.g. a
+        // CLI run next to the service) may have applied it meanwhile.
+        conn.execute_batch("BEGIN IMMEDIATE")?;
+        let res = (|| {
+            let done: Option<i64> = conn
+                .query_row("SELECT 1 FROM schema_migrations WHERE version = ?1", params![m.version], |row| row.get(0))
+                .optional()?;
+            if done.is_some() {
+                return Ok(false);
+            }
+            (m.up)(conn)?;
+            conn.execute(
+                "INSERT INTO schema_migrations (version, name, applied_ms) VALUES (?1, ?2, ?3)",
+                params![m.version, m.name, unix_ms_now() as i64],
+            )?;
+            Ok(true)
+        })();
+        match res {
+            Ok(applied) => {
+                conn.execute_batch("COMMIT")?;
+                if applied {
+                    tracing::info!("database migration {} ({}) applied", m.version, m.name);
+                }
+            }
+            Err(e) => {
+                let _ = conn.execute_batch("ROLLBACK");
+                tracing::error!("database migration {} ({}) failed: {e}", m.version, m.name);
+                return Err(e);
+            }
+        }
+    }
+    Ok(())
+}
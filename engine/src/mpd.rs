@@ -0,0 +1,462 @@
+// --- MPD protocol ---------------------------------------------------------------------------
+//
+// There are hundreds of Music Player Daemon clients: phone apps, desktop
+// players, `mpc` in shell scripts, and hardware controllers with knobs and
+// displays. With `STUDIOCOMMAND_MPD_BIND` set (e.g. `0.0.0.0:6600`), the
+// engine speaks enough of the MPD protocol for them to show and drive the
+// playout queue:
+// - `status`, `currentsong`, `playlistinfo`, `playlistid`, `plchanges` and
+//   `idle` (`player`, `playlist`) show the queue as MPD's playlist: the
+//   playing item is song 0, the rest follow in queue order. The station is
+//   "playing" while the stream is connected. Played items leave the queue,
+//   so `consume` is always on.
+// - `play`/`playid` start the stream if it is stopped; with another song
+//   than the playing one, that song is moved up and started now (unless a
+//   locked item is in the way). `next` skips the playing item.
+// - `add`/`addid` append a cart, absolute path or `lib:<id>` to the queue,
+//   checked like `POST /api/v1/queue/insert`.
+// - `pause`, `stop`, `previous`, `seek` and volume are refused: this is a
+//   live station, and a client's pause button must not take it off the air.
+//
+// MPD song ids are numbers; queue items get one the first time a client sees
+// them, for as long as the engine runs. Command lists are supported. There is
+// no password, so bind it to a trusted network.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use uuid::Uuid;
+
+use crate::{
+    advance_to_next, bump_queue_rev, move_crosses_lock, normalize_log_state, output_start_internal,
+    parse_dur_to_sec, persist_queue, resolve_insert_ref, standby, AppState, LogItem,
+};
+
+/// The protocol version we claim; clients check it for command support.
+const VERSION: &str = "0.23.5";
+/// How often `idle` checks whether the output went on or off air.
+const OUTPUT_POLL: Duration = Duration::from_secs(1);
+
+// ACK error codes (MPD's `ack.h`).
+const ACK_ARG: u32 = 2;
+const ACK_UNKNOWN: u32 = 5;
+const ACK_NO_EXIST: u32 = 50;
+const ACK_SYSTEM: u32 = 52;
+
+const COMMANDS: &[&str] = &[
+    "add", "addid", "close", "commands", "currentsong", "idle", "next", "noidle", "notcommands", "outputs", "ping",
+    "play", "playid", "playlistid", "playlistinfo", "plchanges", "status", "tagtypes",
+];
+const REFUSED: &[&str] = &["pause", "stop", "previous", "seek", "seekid", "seekcur", "setvol", "volume"];
+
+struct Ack {
+    code: u32,
+    msg: String,
+}
+
+fn ack(code: u32, msg: impl Into<String>) -> Ack {
+    Ack { code, msg: msg.into() }
+}
+
+/// Uuid <-> MPD song id, assigned on first sight.
+#[derive(Default)]
+struct SongIds {
+    by_uuid: HashMap<Uuid, u32>,
+    next: u32,
+}
+
+fn song_ids() -> &'static Mutex<SongIds> {
+    static IDS: OnceLock<Mutex<SongIds>> = OnceLock::new();
+    IDS.get_or_init(Default::default)
+}
+
+fn song_id(id: Uuid) -> u32 {
+    let mut ids = song_ids().lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(n) = ids.by_uuid.get(&id) {
+        return *n;
+    }
+    ids.next += 1;
+    let n = ids.next;
+    ids.by_uuid.insert(id, n);
+    n
+}
+
+/// Forget ids of items that are no longer queued.
+fn prune_ids(log: &[LogItem]) {
+    let mut ids = song_ids().lock().unwrap_or_else(|e| e.into_inner());
+    if ids.by_uuid.len() > 4 * log.len() + 256 {
+        ids.by_uuid.retain(|id, _| log.iter().any(|it| it.id == *id));
+    }
+}
+
+/// Split a command line into words; MPD quotes arguments with `"` and
+/// escapes `"` and `\` inside them with a backslash.
+fn split_args(line: &str) -> Result<Vec<String>, Ack> {
+    let mut out = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_ascii_whitespace()).is_some() {}
+        let Some(&c) = chars.peek() else { break };
+        let mut word = String::new();
+        if c == '"' {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => word.push(chars.next().ok_or_else(|| ack(ACK_ARG, "unterminated string"))?),
+                    Some(c) => word.push(c),
+                    None => return Err(ack(ACK_ARG, "unterminated string")),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_ascii_whitespace()) {
+                word.push(c);
+            }
+        }
+        out.push(word);
+    }
+    Ok(out)
+}
+
+fn number(arg: Option<&String>) -> Result<Option<usize>, Ack> {
+    arg.map(|a| a.parse().map_err(|_| ack(ACK_ARG, format!("need an integer, not \"{a}\"")))).transpose()
+}
+
+/// The queue as of the last status snapshot (see snapshot.rs).
+fn queue(state: &AppState) -> (u64, Vec<LogItem>, f64) {
+    let snap = state.snapshot.borrow().clone();
+    let log: Vec<LogItem> = serde_json::from_str(snap.log.get()).unwrap_or_default();
+    prune_ids(&log);
+    (snap.queue_rev, log, snap.now.pos_f)
+}
+
+fn song(out: &mut String, pos: usize, it: &LogItem) {
+    let dur = parse_dur_to_sec(&it.dur);
+    out.push_str(&format!("file: {}\n", it.cart));
+    if !it.artist.is_empty() {
+        out.push_str(&format!("Artist: {}\n", it.artist));
+    }
+    out.push_str(&format!("Title: {}\nGenre: {}\n", it.title, it.tag));
+    out.push_str(&format!("Time: {dur}\nduration: {dur}.000\nPos: {pos}\nId: {}\n", song_id(it.id)));
+}
+
+async fn on_air(state: &AppState) -> bool {
+    state.output.lock().await.status.state == "connected"
+}
+
+async fn start_output(state: &AppState) -> Result<(), Ack> {
+    if state.output.lock().await.encoder.is_some() {
+        return Ok(());
+    }
+    if standby::is_standby() {
+        return Err(ack(ACK_SYSTEM, "this engine is a standby"));
+    }
+    output_start_internal(
+        state.output.clone(),
+        state.playout.clone(),
+        state.topup.clone(),
+        state.topup_stats.clone(),
+        state.pcm_tx.clone(),
+    )
+    .await
+    .map_err(|code| ack(ACK_SYSTEM, format!("output start failed ({code})")))
+}
+
+/// Play the item `id` now: move it up to next and skip to it.
+async fn play_now(state: &AppState, id: Uuid) -> Result<(), Ack> {
+    let log = {
+        let mut p = state.playout.write().await;
+        let pos = p.log.iter().position(|it| it.id == id).ok_or_else(|| ack(ACK_NO_EXIST, "No such song"))?;
+        if pos == 0 {
+            return Ok(());
+        }
+        if move_crosses_lock(&p.log, pos, 1) {
+            return Err(ack(ACK_ARG, "a locked item comes first"));
+        }
+        let item = p.log.remove(pos);
+        p.log.insert(1, item);
+        advance_to_next(&mut p, Some("skipped"));
+        p.log.clone()
+    };
+    persist_queue(log).await;
+    Ok(())
+}
+
+async fn add(state: &AppState, uri: &str) -> Result<u32, Ack> {
+    let item = resolve_insert_ref(uri, "MUS".into()).await.map_err(|errors| ack(ACK_NO_EXIST, errors.join("; ")))?;
+    let (id, log) = {
+        let mut p = state.playout.write().await;
+        let it = item.into_log_item(if p.log.is_empty() { "playing" } else { "queued" });
+        let id = it.id;
+        p.log.push(it);
+        normalize_log_state(&mut p);
+        bump_queue_rev(&mut p);
+        (id, p.log.clone())
+    };
+    persist_queue(log).await;
+    Ok(song_id(id))
+}
+
+fn find(log: &[LogItem], songid: usize) -> Result<Uuid, Ack> {
+    log.iter()
+        .map(|it| it.id)
+        .find(|id| song_id(*id) as usize == songid)
+        .ok_or_else(|| ack(ACK_NO_EXIST, "No such song"))
+}
+
+/// Run one command; its response lines, without the final `OK`.
+async fn run(state: &AppState, cmd: &str, args: &[String]) -> Result<String, Ack> {
+    let mut out = String::new();
+    match cmd {
+        "ping" => {}
+        "commands" => COMMANDS.iter().for_each(|c| out.push_str(&format!("command: {c}\n"))),
+        "notcommands" => REFUSED.iter().for_each(|c| out.push_str(&format!("command: {c}\n"))),
+        "tagtypes" => out.push_str("tagtype: Artist\ntagtype: Title\ntagtype: Genre\n"),
+        "outputs" => {
+            let on = on_air(state).await as u8;
+            out.push_str(&format!("outputid: 0\noutputname: Stream\nplugin: icecast\noutputenabled: {on}\n"));
+        }
+        "status" => {
+            let (rev, log, pos_f) = queue(state);
+            let playing = on_air(state).await && !log.is_empty();
+            out.push_str("volume: -1\nrepeat: 0\nrandom: 0\nsingle: 0\nconsume: 1\n");
+            out.push_str(&format!("playlist: {rev}\nplaylistlength: {}\n", log.len()));
+            out.push_str(&format!("state: {}\n", if playing { "play" } else { "stop" }));
+            if let Some(first) = log.first() {
+                out.push_str(&format!("song: 0\nsongid: {}\n", song_id(first.id)));
+            }
+            if let Some(next) = log.get(1) {
+                out.push_str(&format!("nextsong: 1\nnextsongid: {}\n", song_id(next.id)));
+            }
+            if let (true, Some(first)) = (playing, log.first()) {
+                let dur = parse_dur_to_sec(&first.dur);
+                out.push_str(&format!("time: {}:{dur}\nelapsed: {pos_f:.3}\nduration: {dur}.000\n", pos_f as u32));
+                out.push_str("audio: 48000:16:2\n");
+            }
+        }
+        "currentsong" => {
+            if let Some(first) = queue(state).1.first() {
+                song(&mut out, 0, first);
+            }
+        }
+        "playlistinfo" | "plchanges" => {
+            let (rev, log, _) = queue(state);
+            let range = match (cmd, args.first()) {
+                ("plchanges", Some(v)) if v.parse::<u64>().ok() == Some(rev) => 0..0,
+                ("playlistinfo", Some(r)) => match r.split_once(':') {
+                    Some((a, b)) => {
+                        let a = number(Some(&a.to_string()))?.unwrap_or(0);
+                        let b = if b.is_empty() { log.len() } else { number(Some(&b.to_string()))?.unwrap_or(0) };
+                        a.min(log.len())..b.min(log.len()).max(a.min(log.len()))
+                    }
+                    None => {
+                        let i = number(Some(r))?.unwrap_or(0);
+                        if i >= log.len() {
+                            return Err(ack(ACK_ARG, "Bad song index"));
+                        }
+                        i..i + 1
+                    }
+                },
+                _ => 0..log.len(),
+            };
+            for i in range {
+                song(&mut out, i, &log[i]);
+            }
+        }
+        "playlistid" => {
+            let (_, log, _) = queue(state);
+            let want = number(args.first())?;
+            for (i, it) in log.iter().enumerate() {
+                if want.is_none_or(|w| w == song_id(it.id) as usize) {
+                    song(&mut out, i, it);
+                }
+            }
+            if want.is_some() && out.is_empty() {
+                return Err(ack(ACK_NO_EXIST, "No such song"));
+            }
+        }
+        "play" | "playid" => {
+            let (_, log, _) = queue(state);
+            let target = match (cmd, number(args.first())?) {
+                (_, None) => None,
+                ("play", Some(pos)) => {
+                    Some(log.get(pos).map(|it| it.id).ok_or_else(|| ack(ACK_ARG, "Bad song index"))?)
+                }
+                (_, Some(songid)) => Some(find(&log, songid)?),
+            };
+            if let Some(id) = target {
+                play_now(state, id).await?;
+            }
+            start_output(state).await?;
+        }
+        "next" => {
+            let mut p = state.playout.write().await;
+            if p.log.is_empty() {
+                return Err(ack(ACK_NO_EXIST, "queue is empty"));
+            }
+            advance_to_next(&mut p, Some("skipped"));
+        }
+        "add" | "addid" => {
+            let uri = args.first().ok_or_else(|| ack(ACK_ARG, "missing argument"))?;
+            if cmd == "addid" && args.len() > 1 {
+                return Err(ack(ACK_ARG, "a position is not supported; items are appended"));
+            }
+            let id = add(state, uri).await?;
+            if cmd == "addid" {
+                out.push_str(&format!("Id: {id}\n"));
+            }
+        }
+        c if REFUSED.contains(&c) => return Err(ack(ACK_SYSTEM, "not available on a live station")),
+        _ => return Err(ack(ACK_UNKNOWN, format!("unknown command \"{cmd}\""))),
+    }
+    Ok(out)
+}
+
+/// What `idle` reports a change of.
+async fn idle_key(state: &AppState) -> (u64, Option<Uuid>, bool) {
+    let (rev, log, _) = queue(state);
+    (rev, log.first().map(|it| it.id), on_air(state).await)
+}
+
+/// Wait for a change (or `noidle`); `false` when the client went away.
+async fn idle<R: AsyncBufReadExt + Unpin, W: AsyncWriteExt + Unpin>(
+    state: &AppState,
+    lines: &mut tokio::io::Lines<R>,
+    w: &mut W,
+) -> std::io::Result<bool> {
+    let mut snapshot = state.snapshot.clone();
+    // The snapshot only wakes us for queue changes; the output is polled.
+    let mut output = tokio::time::interval(OUTPUT_POLL);
+    let before = idle_key(state).await;
+    loop {
+        tokio::select! {
+            line = lines.next_line() => {
+                // Only `noidle` may come while idle.
+                return match line? {
+                    Some(l) if l.trim() == "noidle" => w.write_all(b"OK\n").await.map(|_| true),
+                    _ => Ok(false),
+                };
+            }
+            changed = snapshot.changed() => {
+                if changed.is_err() {
+                    return Ok(false);
+                }
+            }
+            _ = output.tick() => {}
+        }
+        let now = idle_key(state).await;
+        let mut out = String::new();
+        if now.0 != before.0 {
+            out.push_str("changed: playlist\n");
+        }
+        if now.1 != before.1 || now.2 != before.2 {
+            out.push_str("changed: player\n");
+        }
+        if !out.is_empty() {
+            out.push_str("OK\n");
+            return w.write_all(out.as_bytes()).await.map(|_| true);
+        }
+    }
+}
+
+async fn session(state: AppState, stream: TcpStream) -> std::io::Result<()> {
+    let (r, mut w) = stream.into_split();
+    let mut lines = BufReader::new(r).lines();
+    w.write_all(format!("OK MPD {VERSION}\n").as_bytes()).await?;
+    // An open command list: whether to answer `list_OK` after each, and the lines so far.
+    let mut list: Option<(bool, Vec<String>)> = None;
+    while let Some(line) = lines.next_line().await? {
+        let line = line.trim().to_string();
+        if let Some((ok_each, cmds)) = &mut list {
+            if line != "command_list_end" {
+                cmds.push(line);
+                continue;
+            }
+            let (ok_each, cmds) = (*ok_each, std::mem::take(cmds));
+            list = None;
+            let mut out = String::new();
+            let mut failed = false;
+            for (i, l) in cmds.iter().enumerate() {
+                match execute(&state, l).await {
+                    Ok(res) => {
+                        out.push_str(&res);
+                        if ok_each {
+                            out.push_str("list_OK\n");
+                        }
+                    }
+                    Err((cmd, a)) => {
+                        out.push_str(&format!("ACK [{}@{i}] {{{cmd}}} {}\n", a.code, a.msg));
+                        failed = true;
+                        break;
+                    }
+                }
+            }
+            if !failed {
+                out.push_str("OK\n");
+            }
+            w.write_all(out.as_bytes()).await?;
+            continue;
+        }
+        match line.split_whitespace().next().unwrap_or("") {
+            "command_list_begin" => list = Some((false, Vec::new())),
+            "command_list_ok_begin" => list = Some((true, Vec::new())),
+            "close" => return Ok(()),
+            "idle" => {
+                if !idle(&state, &mut lines, &mut w).await? {
+                    return Ok(());
+                }
+            }
+            _ => {
+                let out = match execute(&state, &line).await {
+                    Ok(res) => res + "OK\n",
+                    Err((cmd, a)) => format!("ACK [{}@0] {{{cmd}}} {}\n", a.code, a.msg),
+                };
+                w.write_all(out.as_bytes()).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Parse and run one line; on failure, the command name for the `ACK`.
+async fn execute(state: &AppState, line: &str) -> Result<String, (String, Ack)> {
+    let mut args = split_args(line).map_err(|a| (String::new(), a))?;
+    if args.is_empty() {
+        return Err((String::new(), ack(ACK_UNKNOWN, "No command given")));
+    }
+    let cmd = args.remove(0);
+    run(state, &cmd, &args).await.map_err(|a| (cmd, a))
+}
+
+/// Serve MPD clients on `STUDIOCOMMAND_MPD_BIND`, if set.
+pub(crate) async fn mpd_task(state: AppState) {
+    let Ok(bind) = std::env::var("STUDIOCOMMAND_MPD_BIND") else { return };
+    let listener = match tokio::net::TcpListener::bind(bind.trim()).await {
+        Ok(l) => l,
+        Err(e) => {
+            tracing::warn!("mpd: cannot listen on {bind}: {e}");
+            return;
+        }
+    };
+    tracing::info!("mpd: listening on {bind}");
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    tracing::debug!("mpd: client {peer}");
+                    if let Err(e) = session(state, stream).await {
+                        tracing::debug!("mpd: client {peer}: {e}");
+                    }
+                });
+            }
+            Err(e) => {
+                tracing::warn!("mpd: accept failed: {e}");
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
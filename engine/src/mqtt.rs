@@ -0,0 +1,700 @@
+// --- MQTT / Home Assistant ---------------------------------------------------------------
+//
+// Stations that run Home Assistant for the building (lights, the ON AIR
+// sign, the rack's UPS) want the studio in it as well. With
+// `/api/v1/mqtt/config` enabled, the engine connects to an MQTT broker and
+// publishes Home Assistant discovery payloads (`<discovery_prefix>/<component>/
+// <node>/<object>/config`, retained), so these entities appear on their own,
+// grouped as one device named after the stream:
+// - `binary_sensor` "On air": the stream is connected to Icecast,
+// - `sensor` "Now playing": `Artist - Title` of the playing item, with
+//   artist, title, tag, duration and art as attributes,
+// - `sensor` "Listeners": from Icecast's `status-json.xsl`, as the
+//   AzuraCast-compatible API reads it (azuracast.rs),
+// - `binary_sensor` "Dead air": the dead-air alert (alerts.rs) is active,
+// - `switch` "Stream": output started; turning it on or off starts or stops
+//   the stream, as `POST /api/v1/output/start|stop`,
+// - `button` "Skip": skips the playing item, as `POST /api/v1/transport/skip`.
+// States go to `<base_topic>/<object>` (retained) when they change;
+// commands come in on `<base_topic>/<object>/set`. With `commands: false`
+// the switch and button are removed and command topics are ignored.
+// `<base_topic>/status` is `online`/`offline` (the broker's last will
+// covers a crash), and discovery is sent again when Home Assistant comes
+// online (`<discovery_prefix>/status`).
+//
+// Like the alert channels go through curl, the broker is reached through
+// the Mosquitto clients (`mosquitto_sub` holds the subscription and the
+// will, `mosquitto_pub` publishes; `STUDIOCOMMAND_MOSQUITTO_SUB`,
+// `STUDIOCOMMAND_MOSQUITTO_PUB`). The password is stored sealed
+// (secrets.rs) and never returned. A standby engine leaves the broker to
+// the primary until it takes over. If the subscription ends it is started
+// again after 2 s, doubling up to 60 s.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use axum::{http::StatusCode, Json};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::Command;
+use tokio::sync::mpsc;
+use tokio::time::Duration;
+
+use crate::{
+    advance_to_next, alerts, azuracast, output_start_internal, output_stop_internal, secrets, standby, supervisor,
+    unix_ms_now, AppState,
+};
+
+/// How often states are compared with what was last published.
+const PUBLISH_EVERY: Duration = Duration::from_secs(2);
+/// After a failed publish, everything is sent again at most this often.
+const RESYNC_EVERY: Duration = Duration::from_secs(30);
+/// Longest a single publish may take.
+const TIMEOUT_S: u64 = 10;
+const BACKOFF_MIN: Duration = Duration::from_secs(2);
+const BACKOFF_MAX: Duration = Duration::from_secs(60);
+/// Home Assistant's limit for a state.
+const STATE_MAX_CHARS: usize = 255;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct MqttConfig {
+    #[serde(default)]
+    enabled: bool,
+    /// The broker.
+    #[serde(default)]
+    host: String,
+    #[serde(default = "default_port")]
+    port: u16,
+    #[serde(default)]
+    username: String,
+    /// Never returned (see `config_view`); empty on save keeps the current one.
+    #[serde(default)]
+    password: String,
+    /// Connect with TLS, checking the broker against the system CAs.
+    #[serde(default)]
+    tls: bool,
+    /// Where Home Assistant looks for discovery payloads.
+    #[serde(default = "default_discovery_prefix")]
+    discovery_prefix: String,
+    /// Prefix of the state and command topics.
+    #[serde(default = "default_base_topic")]
+    base_topic: String,
+    /// Offer the stream switch and skip button, and act on their commands.
+    #[serde(default = "default_true")]
+    commands: bool,
+}
+
+fn default_port() -> u16 {
+    1883
+}
+
+fn default_discovery_prefix() -> String {
+    "homeassistant".into()
+}
+
+fn default_base_topic() -> String {
+    "studiocommand".into()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_config() -> MqttConfig {
+    serde_json::from_str("{}").expect("defaults")
+}
+
+impl MqttConfig {
+    /// Broker options shared by `mosquitto_sub` and `mosquitto_pub`.
+    fn connect_args(&self) -> Vec<String> {
+        let mut args = vec!["-h".to_string(), self.host.clone(), "-p".to_string(), self.port.to_string()];
+        if !self.username.is_empty() {
+            args.extend(["-u".to_string(), self.username.clone()]);
+            if !self.password.is_empty() {
+                args.extend(["-P".to_string(), self.password.clone()]);
+            }
+        }
+        if self.tls {
+            args.extend(["--capath".to_string(), "/etc/ssl/certs".to_string()]);
+        }
+        args
+    }
+
+    /// The device's node id in discovery topics and unique ids.
+    fn node_id(&self) -> String {
+        self.base_topic.replace(|c: char| !c.is_ascii_alphanumeric() && c != '-', "_")
+    }
+
+    fn topic(&self, object: &str) -> String {
+        format!("{}/{object}", self.base_topic)
+    }
+
+    fn broker(&self) -> String {
+        format!("{}://{}:{}", if self.tls { "mqtts" } else { "mqtt" }, self.host, self.port)
+    }
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct MqttStatus {
+    /// "stopped", "standby", "connected" or "error".
+    state: &'static str,
+    broker: Option<String>,
+    since_ms: Option<u64>,
+    /// Messages published since the engine started.
+    published: u64,
+    /// Commands received since the engine started.
+    commands: u64,
+    last_command: Option<String>,
+    last_command_ms: Option<u64>,
+    last_error: Option<String>,
+}
+
+struct Runtime {
+    running: bool,
+    broker: Option<String>,
+    since_ms: Option<u64>,
+    published: u64,
+    commands: u64,
+    last_command: Option<String>,
+    last_command_ms: Option<u64>,
+    last_error: Option<String>,
+    /// A publish failed: send everything again.
+    resync: bool,
+}
+
+static RUNTIME: Mutex<Runtime> = Mutex::new(Runtime {
+    running: false,
+    broker: None,
+    since_ms: None,
+    published: 0,
+    commands: 0,
+    last_command: None,
+    last_command_ms: None,
+    last_error: None,
+    resync: false,
+});
+/// Bumped when the configuration is saved, so the connection restarts with it.
+static CONFIG_GEN: AtomicU64 = AtomicU64::new(0);
+
+fn runtime() -> std::sync::MutexGuard<'static, Runtime> {
+    RUNTIME.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub(crate) fn status() -> MqttStatus {
+    let rt = runtime();
+    // The subscriber does not say when it is connected: the first publish that
+    // goes through clears the error.
+    let state = match (rt.running, &rt.last_error) {
+        (true, None) => "connected",
+        (_, Some(_)) => "error",
+        (false, None) if standby::is_standby() => "standby",
+        (false, None) => "stopped",
+    };
+    MqttStatus {
+        state,
+        broker: rt.broker.clone(),
+        since_ms: rt.since_ms,
+        published: rt.published,
+        commands: rt.commands,
+        last_command: rt.last_command.clone(),
+        last_command_ms: rt.last_command_ms,
+        last_error: rt.last_error.clone(),
+    }
+}
+
+fn mosquitto_sub_bin() -> String {
+    std::env::var("STUDIOCOMMAND_MOSQUITTO_SUB").unwrap_or_else(|_| "mosquitto_sub".to_string())
+}
+
+fn mosquitto_pub_bin() -> String {
+    std::env::var("STUDIOCOMMAND_MOSQUITTO_PUB").unwrap_or_else(|_| "mosquitto_pub".to_string())
+}
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS mqtt_config (
+            id      INTEGER PRIMARY KEY CHECK (id = 1),
+            config  TEXT NOT NULL
+        );
+        "#,
+    )
+}
+
+async fn with_db<T: Send + 'static>(
+    f: impl FnOnce(&Connection) -> anyhow::Result<T> + Send + 'static,
+) -> anyhow::Result<T> {
+    crate::db::call(move |conn| {
+        crate::db_init(conn)?;
+        f(conn)
+    })
+    .await?
+}
+
+/// The stored configuration, password opened.
+async fn load_config() -> anyhow::Result<MqttConfig> {
+    with_db(|conn| {
+        let raw: Option<String> =
+            conn.query_row("SELECT config FROM mqtt_config WHERE id = 1", [], |row| row.get(0)).optional()?;
+        let mut cfg: MqttConfig = match raw {
+            Some(r) => serde_json::from_str(&r)?,
+            None => default_config(),
+        };
+        cfg.password = secrets::open(&cfg.password)?;
+        Ok(cfg)
+    })
+    .await
+}
+
+/// The configuration as the API shows it: `has_password` instead of the password.
+fn config_view(cfg: &MqttConfig) -> Value {
+    let mut v = serde_json::to_value(cfg).unwrap_or_default();
+    if let Some(o) = v.as_object_mut() {
+        o.remove("password");
+        o.insert("has_password".into(), (!cfg.password.is_empty()).into());
+    }
+    v
+}
+
+// --- Home Assistant --------------------------------------------------------------------
+
+/// Discovery topics and payloads; an empty payload removes the entity.
+fn discovery(cfg: &MqttConfig, station: &str, version: &str) -> Vec<(String, String)> {
+    let node = cfg.node_id();
+    let device = json!({
+        "identifiers": [node],
+        "name": station,
+        "manufacturer": "StudioCommand",
+        "model": "StudioCommand engine",
+        "sw_version": version,
+    });
+    let entities = [
+        ("binary_sensor", "on_air", json!({"name": "On air", "icon": "mdi:radio-tower"})),
+        (
+            "sensor",
+            "now_playing",
+            json!({
+                "name": "Now playing",
+                "icon": "mdi:music",
+                "json_attributes_topic": cfg.topic("now_playing/attributes"),
+            }),
+        ),
+        (
+            "sensor",
+            "listeners",
+            json!({
+                "name": "Listeners",
+                "icon": "mdi:account-multiple",
+                "unit_of_measurement": "listeners",
+                "state_class": "measurement",
+            }),
+        ),
+        ("binary_sensor", "dead_air", json!({"name": "Dead air", "device_class": "problem"})),
+        (
+            "switch",
+            "stream",
+            json!({"name": "Stream", "icon": "mdi:broadcast", "command_topic": cfg.topic("stream/set")}),
+        ),
+        (
+            "button",
+            "skip",
+            json!({
+                "name": "Skip",
+                "icon": "mdi:skip-next",
+                "command_topic": cfg.topic("skip/set"),
+                "payload_press": "PRESS",
+            }),
+        ),
+    ];
+    entities
+        .into_iter()
+        .map(|(component, object, mut entity)| {
+            let topic = format!("{}/{component}/{node}/{object}/config", cfg.discovery_prefix);
+            if !cfg.commands && matches!(component, "switch" | "button") {
+                return (topic, String::new());
+            }
+            if let Some(o) = entity.as_object_mut() {
+                o.insert("unique_id".into(), format!("{node}_{object}").into());
+                o.insert("availability_topic".into(), cfg.topic("status").into());
+                o.insert("device".into(), device.clone());
+                if component != "button" {
+                    o.insert("state_topic".into(), cfg.topic(object).into());
+                }
+            }
+            (topic, entity.to_string())
+        })
+        .collect()
+}
+
+/// The current state topics (under `base_topic`) and their payloads.
+async fn states(state: &AppState) -> Vec<(&'static str, String)> {
+    let on_off = |on: bool| if on { "ON" } else { "OFF" }.to_string();
+    let (on_air, running, output) = {
+        let o = state.output.lock().await;
+        (o.status.state == "connected", o.encoder.is_some(), o.config.clone())
+    };
+    let (text, attributes) = {
+        let p = state.playout.read().await;
+        match p.log.first().filter(|it| it.state == "playing") {
+            Some(it) => {
+                let text = match p.now.artist.as_str() {
+                    "" => p.now.title.clone(),
+                    artist => format!("{artist} - {}", p.now.title),
+                };
+                let attributes = json!({
+                    "artist": p.now.artist,
+                    "title": p.now.title,
+                    "tag": it.tag,
+                    "duration_s": p.now.dur,
+                    "art": p.now.art,
+                });
+                (text.chars().take(STATE_MAX_CHARS).collect(), attributes)
+            }
+            None => (String::new(), json!({})),
+        }
+    };
+    let listeners = azuracast::listeners(&output.host, output.port, &output.mount).await;
+    vec![
+        ("on_air", on_off(on_air)),
+        ("stream", on_off(running)),
+        ("now_playing", text),
+        ("now_playing/attributes", attributes.to_string()),
+        ("listeners", listeners.to_string()),
+        ("dead_air", on_off(alerts::is_active("dead_air"))),
+    ]
+}
+
+/// Act on a message from a command topic.
+async fn command(state: &AppState, object: &str, payload: &str) -> Result<(), String> {
+    match (object, payload) {
+        ("skip", "PRESS") => {
+            advance_to_next(&mut *state.playout.write().await, Some("skipped"));
+            Ok(())
+        }
+        ("stream", "ON") => {
+            if state.output.lock().await.encoder.is_some() {
+                return Ok(());
+            }
+            supervisor::reset_restarts();
+            state.output.lock().await.status.restarts = 0;
+            let started = output_start_internal(
+                state.output.clone(),
+                state.playout.clone(),
+                state.topup.clone(),
+                state.topup_stats.clone(),
+                state.pcm_tx.clone(),
+            )
+            .await;
+            if started.is_ok() {
+                return Ok(());
+            }
+            let err = state.output.lock().await.status.last_error.clone();
+            Err(err.unwrap_or_else(|| "output did not start".into()))
+        }
+        ("stream", "OFF") => {
+            supervisor::reset_restarts();
+            output_stop_internal(state.output.clone()).await;
+            state.output.lock().await.status.restarts = 0;
+            Ok(())
+        }
+        _ => Err("unknown command".into()),
+    }
+}
+
+// --- Broker ----------------------------------------------------------------------------
+
+/// Publish one retained message.
+async fn publish(cfg: &MqttConfig, topic: &str, payload: &str) -> Result<(), String> {
+    let bin = mosquitto_pub_bin();
+    let mut cmd = Command::new(&bin);
+    cmd.args(cfg.connect_args()).args(["-q", "1", "-r", "-t", topic]);
+    // An empty retained message clears the topic (and removes a discovered entity).
+    if payload.is_empty() {
+        cmd.arg("-n");
+    } else {
+        cmd.args(["-m", payload]);
+    }
+    cmd.stdout(std::process::Stdio::null()).stderr(std::process::Stdio::piped()).kill_on_drop(true);
+    let out = match tokio::time::timeout(Duration::from_secs(TIMEOUT_S), cmd.output()).await {
+        Ok(r) => r.map_err(|e| format!("{bin}: {e} (install mosquitto-clients, or set STUDIOCOMMAND_MOSQUITTO_PUB)"))?,
+        Err(_) => return Err(format!("publishing {topic} timed out")),
+    };
+    if out.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&out.stderr).trim().to_string();
+    Err(if stderr.is_empty() { format!("{bin} exited ({})", out.status) } else { stderr })
+}
+
+/// Publish what the connection queues, in order.
+async fn publisher(cfg: MqttConfig, mut rx: mpsc::UnboundedReceiver<(String, String)>) {
+    while let Some((topic, payload)) = rx.recv().await {
+        let res = publish(&cfg, &topic, &payload).await;
+        let mut rt = runtime();
+        match res {
+            Ok(()) => {
+                rt.published += 1;
+                rt.last_error = None;
+            }
+            Err(e) => {
+                if rt.last_error.as_ref() != Some(&e) {
+                    tracing::warn!("mqtt: {e}");
+                }
+                rt.last_error = Some(e);
+                rt.resync = true;
+            }
+        }
+    }
+}
+
+/// Keep the connection up while it is enabled.
+pub(crate) async fn mqtt_task(state: AppState) {
+    let mut backoff = BACKOFF_MIN;
+    loop {
+        let generation = CONFIG_GEN.load(Ordering::Relaxed);
+        let res = match load_config().await {
+            Ok(cfg) if cfg.enabled && !standby::is_standby() => run(&state, &cfg, generation).await,
+            Ok(_) => {
+                let mut rt = runtime();
+                (rt.broker, rt.since_ms) = (None, None);
+                Ok(())
+            }
+            Err(e) => Err(e.to_string()),
+        };
+        let wait = {
+            let mut rt = runtime();
+            rt.running = false;
+            match res {
+                Ok(()) => {
+                    backoff = BACKOFF_MIN;
+                    Duration::from_secs(5)
+                }
+                Err(e) => {
+                    tracing::warn!("mqtt: {e}");
+                    rt.last_error = Some(e);
+                    let wait = backoff;
+                    backoff = (backoff * 2).min(BACKOFF_MAX);
+                    wait
+                }
+            }
+        };
+        // Retry after the backoff, or as soon as the configuration changes.
+        let until = Instant::now() + wait;
+        while Instant::now() < until && CONFIG_GEN.load(Ordering::Relaxed) == generation {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        if CONFIG_GEN.load(Ordering::Relaxed) != generation {
+            backoff = BACKOFF_MIN;
+            runtime().last_error = None;
+        }
+    }
+}
+
+/// Hold the subscription, publish states and run commands until the
+/// subscriber exits (`Err`), or the configuration or role changes (`Ok`).
+async fn run(state: &AppState, cfg: &MqttConfig, generation: u64) -> Result<(), String> {
+    let bin = mosquitto_sub_bin();
+    let ha_status = format!("{}/status", cfg.discovery_prefix);
+    let mut sub = Command::new(&bin)
+        .args(cfg.connect_args())
+        .args(["-v", "-q", "1", "-i", &format!("{}-{}", cfg.node_id(), std::process::id())])
+        .args(["-t", &cfg.topic("+/set"), "-t", &cfg.topic("status"), "-t", &ha_status])
+        .args(["--will-topic", &cfg.topic("status"), "--will-payload", "offline", "--will-retain", "--will-qos", "1"])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("{bin}: {e} (install mosquitto-clients, or set STUDIOCOMMAND_MOSQUITTO_SUB)"))?;
+    let mut lines = BufReader::new(sub.stdout.take().ok_or("subscriber stdout unavailable")?).lines();
+    let last_err = Arc::new(Mutex::new(String::new()));
+    let reader = sub.stderr.take().map(|stderr| tokio::spawn(keep_last_line(stderr, last_err.clone())));
+
+    let (tx, rx) = mpsc::unbounded_channel();
+    let publisher = tokio::spawn(publisher(cfg.clone(), rx));
+    {
+        let mut rt = runtime();
+        rt.running = true;
+        rt.broker = Some(cfg.broker());
+        rt.since_ms = Some(unix_ms_now());
+        rt.resync = false;
+    }
+    tracing::info!("mqtt: connected to {} as {}", cfg.broker(), cfg.base_topic);
+
+    let station = {
+        let name = state.output.lock().await.config.name.clone();
+        name.filter(|n| !n.trim().is_empty()).unwrap_or_else(|| "StudioCommand".into())
+    };
+    let announce = |tx: &mpsc::UnboundedSender<(String, String)>| {
+        for message in discovery(cfg, &station, &state.version) {
+            let _ = tx.send(message);
+        }
+        let _ = tx.send((cfg.topic("status"), "online".into()));
+    };
+    announce(&tx);
+
+    // What each state topic was last published as.
+    let mut sent: HashMap<&'static str, String> = HashMap::new();
+    let mut last_resync = Instant::now();
+    let mut tick = tokio::time::interval(PUBLISH_EVERY);
+    let res = loop {
+        tokio::select! {
+            line = lines.next_line() => {
+                let Ok(Some(line)) = line else { break Err(String::new()) };
+                let (topic, payload) = line.split_once(' ').unwrap_or((&line, ""));
+                let payload = payload.trim();
+                if topic == ha_status || topic == cfg.topic("status") {
+                    // Home Assistant restarted, or our will fired after a
+                    // reconnect: announce again.
+                    if (topic == ha_status && payload == "online") || (topic != ha_status && payload == "offline") {
+                        announce(&tx);
+                        sent.clear();
+                    }
+                    continue;
+                }
+                let Some(object) = topic.strip_prefix(&format!("{}/", cfg.base_topic)).and_then(|t| t.strip_suffix("/set"))
+                else {
+                    continue;
+                };
+                if !cfg.commands {
+                    tracing::debug!("mqtt: ignoring {topic} (commands are off)");
+                    continue;
+                }
+                tracing::info!("mqtt: {object} {payload}");
+                let res = command(state, object, payload).await;
+                let mut rt = runtime();
+                rt.commands += 1;
+                rt.last_command = Some(match &res {
+                    Ok(()) => format!("{object} {payload}"),
+                    Err(e) => format!("{object} {payload}: {e}"),
+                });
+                rt.last_command_ms = Some(unix_ms_now());
+                if let Err(e) = res {
+                    tracing::warn!("mqtt: {object} {payload}: {e}");
+                }
+                // Report the outcome on the next tick, even if nothing changed.
+                sent.remove(object);
+            }
+            _ = tick.tick() => {
+                if CONFIG_GEN.load(Ordering::Relaxed) != generation || standby::is_standby() {
+                    break Ok(());
+                }
+                if runtime().resync && last_resync.elapsed() >= RESYNC_EVERY {
+                    runtime().resync = false;
+                    last_resync = Instant::now();
+                    announce(&tx);
+                    sent.clear();
+                }
+                for (object, payload) in states(state).await {
+                    if sent.get(object) != Some(&payload) {
+                        let _ = tx.send((cfg.topic(object), payload.clone()));
+                        sent.insert(object, payload);
+                    }
+                }
+            }
+        }
+    };
+
+    drop(tx);
+    let _ = tokio::time::timeout(Duration::from_secs(TIMEOUT_S), publisher).await;
+    let sub_exit = sub.try_wait().ok().flatten();
+    let _ = sub.start_kill();
+    let _ = sub.wait().await;
+    if let Some(reader) = reader {
+        let _ = tokio::time::timeout(Duration::from_secs(1), reader).await;
+    }
+    // A clean stop says so itself; the will only covers a lost connection.
+    if res.is_ok() {
+        let _ = publish(cfg, &cfg.topic("status"), "offline").await;
+    }
+    tracing::info!("mqtt: disconnected from {}", cfg.broker());
+    res.map_err(|_| {
+        let e = match sub_exit {
+            Some(status) => format!("{bin} exited ({status})"),
+            None => format!("{bin} stopped"),
+        };
+        let last = last_err.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        if last.is_empty() { e } else { format!("{e}: {last}") }
+    })
+}
+
+async fn keep_last_line(stderr: impl AsyncRead + Unpin, last: Arc<Mutex<String>>) {
+    let mut lines = BufReader::new(stderr).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if !line.trim().is_empty() {
+            *last.lock().unwrap_or_else(|e| e.into_inner()) = line.trim().to_string();
+        }
+    }
+}
+
+// --- HTTP API --------------------------------------------------------------------------
+
+type ApiError = (StatusCode, Json<Value>);
+
+fn api_error(status: StatusCode, msg: impl Into<String>) -> ApiError {
+    (status, Json(json!({"ok": false, "error": msg.into()})))
+}
+
+pub(crate) async fn api_mqtt_config_get() -> Result<Json<Value>, StatusCode> {
+    let cfg = load_config().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(json!({"ok": true, "config": config_view(&cfg), "status": status()})))
+}
+
+/// The configuration with its password, for the settings export (which redacts it).
+pub(crate) async fn export_config() -> Result<Value, String> {
+    let cfg = load_config().await.map_err(|e| e.to_string())?;
+    serde_json::to_value(cfg).map_err(|e| e.to_string())
+}
+
+/// A topic prefix: levels separated by `/`, no wildcards.
+fn valid_prefix(prefix: &str) -> bool {
+    !prefix.is_empty() && !prefix.contains(['+', '#', ' ']) && prefix.split('/').all(|level| !level.is_empty())
+}
+
+pub(crate) async fn api_mqtt_config_set(Json(mut cfg): Json<MqttConfig>) -> Result<Json<Value>, ApiError> {
+    let bad = |msg: &str| Err(api_error(StatusCode::BAD_REQUEST, msg));
+    cfg.host = cfg.host.trim().to_string();
+    cfg.discovery_prefix = cfg.discovery_prefix.trim().to_string();
+    cfg.base_topic = cfg.base_topic.trim().to_string();
+    if cfg.enabled && cfg.host.is_empty() {
+        return bad("host (the broker) is required");
+    }
+    if cfg.host.contains(['/', ' ']) {
+        return bad("host must be a host name or address");
+    }
+    if cfg.port == 0 {
+        return bad("port must be 1-65535");
+    }
+    if !valid_prefix(&cfg.discovery_prefix) || !valid_prefix(&cfg.base_topic) {
+        return bad("discovery_prefix and base_topic must be topic levels separated by /, without + or #");
+    }
+    if cfg.base_topic == cfg.discovery_prefix {
+        return bad("base_topic must differ from discovery_prefix");
+    }
+    // GET never returns the password, so a form saved without touching it
+    // sends none: keep the one we have.
+    if cfg.password.is_empty() {
+        cfg.password = load_config().await.map(|c| c.password).unwrap_or_default();
+    }
+
+    let mut stored = cfg.clone();
+    stored.password =
+        secrets::seal(&cfg.password).map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let raw =
+        serde_json::to_string(&stored).map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    with_db(move |conn| {
+        conn.execute(
+            "INSERT INTO mqtt_config (id, config) VALUES (1, ?1)
+             ON CONFLICT(id) DO UPDATE SET config=excluded.config",
+            params![raw],
+        )?;
+        Ok(())
+    })
+    .await
+    .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    CONFIG_GEN.fetch_add(1, Ordering::Relaxed);
+    Ok(Json(json!({"ok": true, "config": config_view(&cfg)})))
+}
@@ -0,0 +1,423 @@
+// --- Streaming output (Icecast) -----------------------------------------
+//
+// The stream to Icecast: its stored config and runtime state, the ffmpeg
+// encoder fed from the playout writer's PCM (started, stopped and restarted
+// through supervisor.rs), and the `/api/v1/output` handlers.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use axum::http::StatusCode;
+use axum::{extract::State, Json};
+use serde::{Serialize, Deserialize};
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+
+use crate::{
+    db, db_save_output_config, emergency, fallback, ffmpeg, overlay, shutdown, stl, supervisor, writer_playout,
+    AppState, PlayoutState, TestTone, TopUpConfig, TopUpStats,
+};
+
+#[derive(Clone, Serialize, Deserialize, Default)]
+pub(crate) struct StreamOutputConfig {
+    pub(crate) r#type: String,      // "icecast" (future: "shoutcast")
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    pub(crate) mount: String,
+    pub(crate) username: String,
+    /// Never returned by `GET /api/v1/output` (see `output_config_view`);
+    /// empty on save keeps the current one.
+    #[serde(default)]
+    pub(crate) password: String,
+    pub(crate) codec: String,       // "mp3" | "aac"
+    pub(crate) bitrate_kbps: u16,   // 64..320
+    pub(crate) enabled: bool,
+    pub(crate) name: Option<String>,
+    pub(crate) genre: Option<String>,
+    pub(crate) description: Option<String>,
+    pub(crate) public: Option<bool>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct StreamOutputStatus {
+    pub(crate) state: String, // stopped | starting | connected | error
+    pub(crate) uptime_sec: u64,
+    pub(crate) last_error: Option<String>,
+    codec: Option<String>,
+    bitrate_kbps: Option<u16>,
+    /// Automatic restarts since the operator last started or stopped the stream.
+    #[serde(default)]
+    pub(crate) restarts: u32,
+    /// How the last encoder ended, e.g. `exited with code 1`.
+    #[serde(default)]
+    pub(crate) last_exit: Option<String>,
+    /// The test signal being streamed instead of the playout (see testtone.rs).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) test: Option<TestTone>,
+}
+
+pub(crate) struct OutputRuntime {
+    pub(crate) config: StreamOutputConfig,
+    pub(crate) status: StreamOutputStatus,
+    /// The running encoder (see supervisor.rs).
+    pub(crate) encoder: Option<supervisor::Supervised>,
+    /// Bumped on every start, so a supervisor can tell its encoder was replaced.
+    pub(crate) generation: u64,
+    pub(crate) writer_task: Option<tokio::task::JoinHandle<()>>,
+    pub(crate) stderr_task: Option<tokio::task::JoinHandle<()>>,
+    pub(crate) stderr_tail: VecDeque<String>,
+    pub(crate) started_at: Option<std::time::Instant>,
+}
+
+impl OutputRuntime {
+    pub(crate) fn new(config: StreamOutputConfig) -> Self {
+        Self {
+            status: StreamOutputStatus {
+                state: "stopped".into(),
+                uptime_sec: 0,
+                last_error: None,
+                codec: None,
+                bitrate_kbps: None,
+                restarts: 0,
+                last_exit: None,
+                test: None,
+            },
+            config,
+            encoder: None,
+            generation: 0,
+            writer_task: None,
+            stderr_task: None,
+            stderr_tail: VecDeque::with_capacity(80),
+            started_at: None,
+        }
+    }
+}
+
+// --- Output API (Icecast) -------------------------------------------------
+
+pub(crate) fn sanitize_ffmpeg_line(line: &str, password: &str) -> String {
+    // Best-effort redaction. We never want to leak credentials into UI/logs.
+    // ffmpeg typically doesn't echo full URLs at loglevel=error, but it can.
+    let mut s = line.to_string();
+    if !password.is_empty() {
+        s = s.replace(password, "****");
+    }
+    // Also redact any Basic auth header content if it appears.
+    if s.to_ascii_lowercase().contains("authorization:") {
+        return "Authorization: ****".to_string();
+    }
+    s
+}
+
+pub(crate) fn push_stderr_tail(o: &mut OutputRuntime, line: String) {
+    const MAX: usize = 80;
+    if o.stderr_tail.len() >= MAX {
+        o.stderr_tail.pop_front();
+    }
+    o.stderr_tail.push_back(line.clone());
+
+    // If ffmpeg emits a clear HTTP/auth/config error, surface it immediately.
+    let lc = line.to_ascii_lowercase();
+    if lc.contains("unauthorized") || lc.contains("forbidden") || lc.contains("not found") || lc.contains("server returned") {
+        o.status.state = "error".into();
+        o.status.last_error = Some(line);
+    }
+}
+
+pub(crate) fn last_stderr_summary(tail: &VecDeque<String>) -> Option<String> {
+    // Prefer the last non-empty, non-noisy line.
+    for line in tail.iter().rev() {
+        let t = line.trim();
+        if t.is_empty() {
+            continue;
+        }
+        // Skip repetitive/low-signal lines.
+        let lc = t.to_ascii_lowercase();
+        if lc.contains("broken pipe") {
+            continue;
+        }
+        if lc.contains("conversion failed") {
+            continue;
+        }
+        return Some(t.to_string());
+    }
+    // Fall back to the last line if that's all we have.
+    tail.back().map(|s| s.trim().to_string()).filter(|s| !s.is_empty())
+}
+
+#[derive(Serialize)]
+pub(crate) struct OutputGetResponse {
+    config: serde_json::Value,
+    status: StreamOutputStatus,
+    /// The studio-to-transmitter link (stl.rs), which runs beside the stream.
+    stl: stl::StlStatus,
+}
+
+/// The output config as the API shows it: `has_password` instead of the
+/// password itself.
+fn output_config_view(cfg: &StreamOutputConfig) -> serde_json::Value {
+    let mut v = serde_json::to_value(cfg).unwrap_or_default();
+    if let Some(o) = v.as_object_mut() {
+        o.remove("password");
+        o.insert("has_password".into(), (!cfg.password.is_empty()).into());
+    }
+    v
+}
+
+pub(crate) async fn api_output_get(State(state): State<AppState>) -> Json<OutputGetResponse> {
+    // Encoder exits are picked up by its supervisor (supervisor.rs), not here.
+    let mut o = state.output.lock().await;
+    // Refresh uptime
+    if let Some(started) = o.started_at {
+        o.status.uptime_sec = started.elapsed().as_secs();
+    } else {
+        o.status.uptime_sec = 0;
+    }
+    Json(OutputGetResponse {
+        config: output_config_view(&o.config),
+        status: o.status.clone(),
+        stl: stl::status(),
+    })
+}
+
+pub(crate) async fn api_output_set_config(
+    State(state): State<AppState>,
+    Json(mut cfg): Json<StreamOutputConfig>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    // Normalize a few inputs for operator convenience.
+    if !cfg.mount.starts_with('/') {
+        cfg.mount = format!("/{}", cfg.mount);
+    }
+    if cfg.codec != "mp3" && cfg.codec != "aac" {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if cfg.bitrate_kbps < 32 || cfg.bitrate_kbps > 320 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    // GET never returns the password, so a form saved without touching it
+    // sends none: keep the one we have.
+    if cfg.password.is_empty() {
+        cfg.password = state.output.lock().await.config.password.clone();
+    }
+
+    // Persist to SQLite.
+    let cfg_clone = cfg.clone();
+    db::call(move |conn| -> anyhow::Result<()> {
+        db_save_output_config(conn, &cfg_clone)?;
+        Ok(())
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    // Update in-memory config.
+    let mut o = state.output.lock().await;
+    o.config = cfg;
+
+    Ok(Json(json!({"ok": true})))
+}
+
+pub(crate) async fn api_output_start(State(state): State<AppState>) -> Result<Json<serde_json::Value>, StatusCode> {
+    supervisor::reset_restarts();
+    state.output.lock().await.status.restarts = 0;
+    output_start_internal(
+        state.output.clone(),
+        state.playout.clone(),
+        state.topup.clone(),
+        state.topup_stats.clone(),
+        state.pcm_tx.clone(),
+    ).await?;
+    Ok(Json(json!({"ok": true})))
+}
+
+pub(crate) async fn api_output_stop(State(state): State<AppState>) -> Result<Json<serde_json::Value>, StatusCode> {
+    supervisor::reset_restarts();
+    output_stop_internal(state.output.clone()).await;
+    state.output.lock().await.status.restarts = 0;
+    Ok(Json(json!({"ok": true})))
+}
+
+pub(crate) async fn output_start_internal(
+    output: Arc<tokio::sync::Mutex<OutputRuntime>>,
+    playout: Arc<tokio::sync::RwLock<PlayoutState>>,
+    topup: Arc<tokio::sync::Mutex<TopUpConfig>>,
+    topup_stats: Arc<tokio::sync::Mutex<TopUpStats>>,
+    pcm_tx: tokio::sync::broadcast::Sender<Vec<u8>>,
+) -> Result<(), StatusCode> {
+    let mut o = output.lock().await;
+    if o.encoder.is_some() {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    // Basic validation
+    if o.config.password.trim().is_empty() {
+        o.status.state = "error".into();
+        o.status.last_error = Some("Icecast password is empty".into());
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    // A missing encoder would otherwise only show up in the stderr tail.
+    if let Err(e) = ffmpeg::check_output_codec(&o.config.codec).await {
+        o.status.state = "error".into();
+        o.status.last_error = Some(e);
+        return Err(StatusCode::FAILED_DEPENDENCY);
+    }
+
+    // Spawn ffmpeg and a simple audio generator to prove end-to-end streaming.
+    let (child, stdin, stderr) = spawn_encoder(&o.config, EncoderSink::Icecast).await.map_err(|e| {
+        o.status.state = "error".into();
+        o.status.last_error = Some(e.to_string());
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    o.status.state = "starting".into();
+    o.status.last_error = None;
+    o.status.codec = Some(o.config.codec.clone());
+    o.status.bitrate_kbps = Some(o.config.bitrate_kbps);
+    o.started_at = Some(std::time::Instant::now());
+    // The supervisor reports the last line of *this* run when the encoder dies.
+    o.stderr_tail.clear();
+
+    let output_for_writer = output.clone();
+    let writer_task = tokio::spawn(async move {
+        if let Err(e) = writer_playout(stdin, playout, topup, topup_stats, pcm_tx).await {
+            let mut o = output_for_writer.lock().await;
+            o.status.state = "error".into();
+            o.status.last_error = Some(format!("audio writer: {e}"));
+        }
+    });
+
+    // Capture ffmpeg stderr so the UI can show actionable errors (e.g. 401 Unauthorized)
+    // without exposing secrets.
+    let output_for_stderr = output.clone();
+    let password = o.config.password.clone();
+    let stderr_task = tokio::spawn(async move {
+        let mut lines = BufReader::new(stderr).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let sanitized = sanitize_ffmpeg_line(&line, &password);
+            if sanitized.trim().is_empty() {
+                continue;
+            }
+            let mut o = output_for_stderr.lock().await;
+            push_stderr_tail(&mut o, sanitized);
+        }
+    });
+
+    // Put child + task into runtime; the supervisor reaps the encoder as soon
+    // as it exits and brings the stream back if nobody asked for that.
+    let label = format!("{}:{}{}", o.config.host, o.config.port, o.config.mount);
+    let (encoder, exit) = supervisor::supervise("encoder", label, child);
+    o.generation += 1;
+    tokio::spawn(supervisor::watch_encoder(output.clone(), o.generation, exit));
+    o.encoder = Some(encoder);
+    o.writer_task = Some(writer_task);
+    o.stderr_task = Some(stderr_task);
+
+    // Optimistically mark connected after a short grace period if ffmpeg is still alive.
+    drop(o);
+    tokio::time::sleep(std::time::Duration::from_millis(800)).await;
+    let mut o = output.lock().await;
+    if o.encoder.is_some() && o.status.state == "starting" {
+        o.status.state = "connected".into();
+        tokio::spawn(fallback::restore(o.config.clone()));
+    }
+
+    Ok(())
+}
+
+pub(crate) async fn output_stop_internal(output: Arc<tokio::sync::Mutex<OutputRuntime>>) {
+    // Listeners go to the fallback mount while the live one still exists.
+    // On shutdown, `shutdown::run` has done this before the fade.
+    let live = {
+        let o = output.lock().await;
+        (o.encoder.is_some() && o.status.state == "connected").then(|| o.config.clone())
+    };
+    if let Some(cfg) = live.filter(|_| !shutdown::requested()) {
+        fallback::engage(&cfg).await;
+    }
+
+    let mut o = output.lock().await;
+
+    if let Some(encoder) = o.encoder.take() {
+        encoder.stop();
+    }
+
+    if let Some(task) = o.writer_task.take() {
+        task.abort();
+    }
+    overlay::stop(None);
+    emergency::stop("dropped (stream stopped)");
+
+    if let Some(task) = o.stderr_task.take() {
+        task.abort();
+    }
+
+    o.started_at = None;
+    o.status.uptime_sec = 0;
+    o.status.state = "stopped".into();
+    o.status.test = None;
+}
+
+/// Where the encoder's stream goes.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum EncoderSink {
+    /// The configured Icecast mount.
+    Icecast,
+    /// Encoded and discarded (test tone without a server).
+    Null,
+}
+
+pub(crate) async fn spawn_encoder(cfg: &StreamOutputConfig, sink: EncoderSink) -> anyhow::Result<(tokio::process::Child, tokio::process::ChildStdin, tokio::process::ChildStderr)> {
+    let ffmpeg = ffmpeg::ffmpeg_bin();
+
+    // Important: never log the password.
+    // Note: Icecast source passwords are usually ASCII and safe to embed.
+    // If you need full URL-encoding later, we can add it, but we avoid pulling
+    // in extra deps for the MVP.
+    let url = format!(
+        "icecast://{}:{}@{}:{}{}",
+        cfg.username,
+        cfg.password,
+        cfg.host,
+        cfg.port,
+        cfg.mount
+    );
+
+    let mut cmd = Command::new(ffmpeg);
+    cmd.arg("-hide_banner");
+    cmd.arg("-loglevel").arg("error");
+    cmd.arg("-re");
+    cmd.arg("-f").arg("s16le");
+    cmd.arg("-ar").arg("48000");
+    cmd.arg("-ac").arg("2");
+    cmd.arg("-i").arg("pipe:0");
+
+    match cfg.codec.as_str() {
+        "mp3" => {
+            cmd.arg("-c:a").arg("libmp3lame");
+            cmd.arg("-b:a").arg(format!("{}k", cfg.bitrate_kbps));
+            cmd.arg("-content_type").arg("audio/mpeg");
+            cmd.arg("-f").arg("mp3");
+        }
+        "aac" => {
+            cmd.arg("-c:a").arg("aac");
+            cmd.arg("-b:a").arg(format!("{}k", cfg.bitrate_kbps));
+            cmd.arg("-content_type").arg("audio/aac");
+            cmd.arg("-f").arg("adts");
+        }
+        _ => anyhow::bail!("unsupported codec: {}", cfg.codec),
+    }
+
+    match sink {
+        EncoderSink::Icecast => cmd.arg(url),
+        EncoderSink::Null => cmd.arg("pipe:1").stdout(std::process::Stdio::null()),
+    };
+    cmd.stdin(std::process::Stdio::piped());
+    cmd.stderr(std::process::Stdio::piped());
+
+    let mut child = cmd.spawn()?;
+    let stdin = child.stdin.take().ok_or_else(|| anyhow::anyhow!("ffmpeg stdin unavailable"))?;
+    let stderr = child.stderr.take().ok_or_else(|| anyhow::anyhow!("ffmpeg stderr unavailable"))?;
+    Ok((child, stdin, stderr))
+}
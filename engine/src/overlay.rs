@@ -0,0 +1,196 @@
+// --- Overlay bus -------------------------------------------------------------------------
+//
+// Audio that plays on top of the queue instead of in it: a jingle fired from
+// the cart wall, a sound effect under a link. The queue has one item on air
+// at a time; the overlay bus has any number of voices, each a file decoded by
+// its own ffmpeg, summed into the program in `writer_playout` just before the
+// meters, the WebRTC monitor and the encoder see it. The queue, its timing
+// and now-playing are untouched.
+//
+// A voice is keyed (a cart wall slot, say). Playing a key that is already
+// playing starts it again from the top, as a jingle box does. Each decoder
+// runs up to `AHEAD_BYTES` ahead of the mix, and a voice joins the mix once
+// `PREROLL_BYTES` are buffered, so a slow start is late rather than choppy.
+// Stopping the stream stops every voice.
+//
+// A voice-track handover (voicetrack.rs) or an overlap between queue items
+// (transitions.rs) also runs through the bus: the next item starts here, and
+// `take` hands its position to the playout decoder.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use serde::Serialize;
+use tokio::io::AsyncReadExt;
+use tokio::time::Duration;
+
+use crate::{spawn_ffmpeg_decoder, unix_ms_now};
+
+/// s16le stereo at 48 kHz, as `writer_playout`.
+const BYTES_PER_MS: usize = 48 * 4;
+/// How far a decoder may run ahead of the mix.
+const AHEAD_BYTES: usize = 1000 * BYTES_PER_MS;
+/// Buffered audio before a voice is first mixed.
+const PREROLL_BYTES: usize = 100 * BYTES_PER_MS;
+
+struct Voice {
+    id: u64,
+    key: String,
+    label: String,
+    gain: f32,
+    started_ms: u64,
+    buf: VecDeque<u8>,
+    /// Bytes mixed so far.
+    mixed: usize,
+    /// Fade in from silence over this many bytes (0: start at full level).
+    fade_in: usize,
+    mixing: bool,
+    /// The decoder is done; the voice ends when `buf` runs dry.
+    eof: bool,
+}
+
+struct Bus {
+    next_id: u64,
+    voices: Vec<Voice>,
+}
+
+static BUS: Mutex<Bus> = Mutex::new(Bus { next_id: 1, voices: Vec::new() });
+
+fn bus() -> std::sync::MutexGuard<'static, Bus> {
+    BUS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct Playing {
+    pub(crate) key: String,
+    label: String,
+    started_ms: u64,
+    pos_ms: u64,
+}
+
+/// Play `path` on the bus as `key` (replacing a voice with the same key).
+pub(crate) fn play(key: String, label: String, path: String, gain_db: f32) {
+    let id = {
+        let mut bus = bus();
+        let id = bus.next_id;
+        bus.next_id += 1;
+        bus.voices.retain(|v| v.key != key);
+        bus.voices.push(Voice {
+            id,
+            key: key.clone(),
+            label,
+            gain: 10f32.powf(gain_db / 20.0),
+            started_ms: unix_ms_now(),
+            buf: VecDeque::new(),
+            mixed: 0,
+            fade_in: 0,
+            mixing: false,
+            eof: false,
+        });
+        id
+    };
+    tokio::spawn(async move {
+        if let Err(e) = decode(id, &path).await {
+            tracing::warn!("overlay: {key}: {path}: {e}");
+        }
+        if let Some(v) = bus().voices.iter_mut().find(|v| v.id == id) {
+            v.eof = true;
+        }
+    });
+}
+
+/// Feed voice `id` from its decoder until the file ends or the voice goes.
+async fn decode(id: u64, path: &str) -> anyhow::Result<()> {
+    let (mut child, mut stdout) = spawn_ffmpeg_decoder(path, 0).await?;
+    let mut chunk = vec![0u8; 20 * BYTES_PER_MS];
+    loop {
+        let ahead = match bus().voices.iter().find(|v| v.id == id) {
+            Some(v) => v.buf.len(),
+            None => break,
+        };
+        if ahead >= AHEAD_BYTES {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            continue;
+        }
+        let n = stdout.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        match bus().voices.iter_mut().find(|v| v.id == id) {
+            Some(v) => v.buf.extend(&chunk[..n]),
+            None => break,
+        }
+    }
+    let _ = child.kill().await;
+    Ok(())
+}
+
+/// Fade the voice `key` in over `ms` from its start, e.g. the next item of a crossfade.
+pub(crate) fn fade_in(key: &str, ms: u64) {
+    if let Some(v) = bus().voices.iter_mut().find(|v| v.key == key) {
+        v.fade_in = ms as usize * BYTES_PER_MS;
+    }
+}
+
+/// Stop the voice `key`, or every voice. Returns how many stopped.
+pub(crate) fn stop(key: Option<&str>) -> usize {
+    let mut bus = bus();
+    let before = bus.voices.len();
+    bus.voices.retain(|v| key.is_some_and(|k| v.key != k));
+    before - bus.voices.len()
+}
+
+/// Stop the voice `key` and return how far it got, in milliseconds.
+pub(crate) fn take(key: &str) -> Option<u64> {
+    let mut bus = bus();
+    let at = bus.voices.iter().position(|v| v.key == key)?;
+    Some((bus.voices.remove(at).mixed / BYTES_PER_MS) as u64)
+}
+
+pub(crate) fn playing() -> Vec<Playing> {
+    bus()
+        .voices
+        .iter()
+        .map(|v| Playing {
+            key: v.key.clone(),
+            label: v.label.clone(),
+            started_ms: v.started_ms,
+            pos_ms: (v.mixed / BYTES_PER_MS) as u64,
+        })
+        .collect()
+}
+
+/// Sum the voices into `buf` (interleaved s16le stereo). Returns whether
+/// anything was mixed in.
+pub(crate) fn mix(buf: &mut [u8]) -> bool {
+    let mut bus = bus();
+    if bus.voices.is_empty() {
+        return false;
+    }
+    let mut mixed = false;
+    for v in bus.voices.iter_mut() {
+        if !v.mixing && (v.buf.len() >= PREROLL_BYTES || v.eof) {
+            v.mixing = true;
+        }
+        if !v.mixing {
+            continue;
+        }
+        let n = buf.len().min(v.buf.len()) & !1;
+        for (i, s) in buf[..n].chunks_exact_mut(2).enumerate() {
+            let (Some(lo), Some(hi)) = (v.buf.pop_front(), v.buf.pop_front()) else { break };
+            let main = i16::from_le_bytes([s[0], s[1]]) as f32;
+            // Per frame (4 bytes), so both channels of a frame get the same gain.
+            let ramp = match v.fade_in {
+                0 => 1.0,
+                len => (((v.mixed + i * 2) & !3) as f32 / len as f32).min(1.0),
+            };
+            let add = i16::from_le_bytes([lo, hi]) as f32 * v.gain * ramp;
+            let sum = (main + add).clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+            s.copy_from_slice(&sum.to_le_bytes());
+        }
+        v.mixed += n;
+        mixed |= n > 0;
+    }
+    bus.voices.retain(|v| !(v.eof && v.buf.is_empty()));
+    mixed
+}
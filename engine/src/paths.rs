@@ -0,0 +1,114 @@
+// --- File paths as strings -------------------------------------------------------------
+//
+// A file travels through the engine as a string: a queue item's `cart`, a
+// library row, a JSON field. Linux paths are bytes, though; usually UTF-8, but
+// not always (old Samba shares, rips named in Latin-1), and
+// `to_string_lossy()` turns such a name into one that does not exist, so the
+// file was scanned fine and then failed to play. This is the one place a path
+// becomes a string and back:
+// - `to_cart`: a UTF-8 path as it is; anything else as `os:` followed by the
+//   name with `%XX` for each byte that is not UTF-8 (and for `%` itself).
+//   `from_cart` turns that back into exactly the same bytes.
+// - `ffmpeg_input`: ffmpeg reads `name:rest` as protocol `name`, so a local
+//   file is always passed as `file:<path>` and a name like `Live: 1999.mp3`
+//   is read as a file.
+//
+// Spaces and quotes need nothing: paths go to ffmpeg as their own argument,
+// never through a shell.
+
+use std::ffi::OsString;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+
+/// Marks a path string that is not the path itself but its escaped bytes.
+const ESCAPED: &str = "os:";
+
+/// `path` as a string that `from_cart` turns back into the same path.
+pub(crate) fn to_cart(path: &Path) -> String {
+    match path.to_str() {
+        Some(s) if !s.starts_with(ESCAPED) => s.to_string(),
+        _ => {
+            let mut out = String::from(ESCAPED);
+            for chunk in path.as_os_str().as_bytes().utf8_chunks() {
+                out.push_str(&chunk.valid().replace('%', "%25"));
+                for b in chunk.invalid() {
+                    out.push_str(&format!("%{b:02X}"));
+                }
+            }
+            out
+        }
+    }
+}
+
+/// The path a cart string (from `to_cart`, or typed by hand) names.
+pub(crate) fn from_cart(cart: &str) -> PathBuf {
+    let Some(escaped) = cart.strip_prefix(ESCAPED) else {
+        return PathBuf::from(cart);
+    };
+    let mut bytes = Vec::with_capacity(escaped.len());
+    let mut rest = escaped.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        let hex = match tail {
+            [h, l, ..] if b == b'%' && h.is_ascii_hexdigit() && l.is_ascii_hexdigit() => {
+                std::str::from_utf8(&tail[..2]).ok().and_then(|h| u8::from_str_radix(h, 16).ok())
+            }
+            _ => None,
+        };
+        match hex {
+            Some(v) => {
+                bytes.push(v);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(b);
+                rest = tail;
+            }
+        }
+    }
+    PathBuf::from(OsString::from_vec(bytes))
+}
+
+/// `path` as an ffmpeg/ffprobe input argument.
+pub(crate) fn ffmpeg_input(path: &Path) -> OsString {
+    let mut arg = OsString::from("file:");
+    arg.push(path.as_os_str());
+    arg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utf8_paths_stay_as_they_are() {
+        for p in ["/music/It's \"Live\" Tonight.mp3", "/music/a b/c.wav", "/music/100% Hits.mp3"] {
+            assert_eq!(to_cart(Path::new(p)), p);
+            assert_eq!(from_cart(p), PathBuf::from(p));
+        }
+    }
+
+    #[test]
+    fn non_utf8_round_trips() {
+        let raw = OsString::from_vec(b"/music/Caf\xe9 100%.mp3".to_vec());
+        let cart = to_cart(Path::new(&raw));
+        assert_eq!(cart, "os:/music/Caf%E9 100%25.mp3");
+        assert_eq!(from_cart(&cart).into_os_string(), raw);
+    }
+
+    #[test]
+    fn utf8_path_starting_with_marker_round_trips() {
+        let p = Path::new("os:%41");
+        let cart = to_cart(p);
+        assert_ne!(cart, "os:%41");
+        assert_eq!(from_cart(&cart), p);
+    }
+
+    #[test]
+    fn ffmpeg_input_is_always_a_file() {
+        for p in ["/music/a b.mp3", "/music/it's \"quoted\".mp3", "-y.mp3", "Live: 1999.mp3", "http:x.mp3"] {
+            assert_eq!(ffmpeg_input(Path::new(p)), OsString::from(format!("file:{p}")));
+        }
+        let raw = OsString::from_vec(b"/music/\xff.mp3".to_vec());
+        assert_eq!(ffmpeg_input(Path::new(&raw)).into_vec(), b"file:/music/\xff.mp3".to_vec());
+    }
+}
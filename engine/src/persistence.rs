@@ -0,0 +1,668 @@
+// --- Persistence (SQLite) -------------------------------------------------
+//
+// Why SQLite?
+// - Crash-safe: updates happen inside transactions.
+// - Concurrent-safe: UI reorder, future ingest, and engine ops can all share one DB.
+// - Operationally simple: a single file, but with the safety properties of a database.
+//
+// We keep the DB schema intentionally small and stable. The HTTP API remains the main
+// integration surface; future third-party file ingest can translate inputs into API/commands.
+//
+// DB location:
+// - Can be overridden with STUDIOCOMMAND_DB_PATH (or `EngineBuilder::database`)
+// - Defaults to /opt/studiocommand/shared/studiocommand.db (installer-managed persistent dir)
+//
+// Note: rusqlite is synchronous. All access goes through the database worker
+// (`db.rs`), one thread owning one connection, so tokio threads never block on it.
+
+use rusqlite::{Connection, params};
+use uuid::Uuid;
+
+use crate::{
+    analysis, announce, breaks, carts, clocks, daylog, db, events, history, import, ingest, library, metapush,
+    migrations, normalize_log_markers, parse_dur_to_sec, public, rds, requests, rotation, schedule, secrets, shufflebag,
+    topuplog, waveform, AUX_QUEUES, LogItem, StreamOutputConfig, TopUpConfig, TopUpFilters, Transition,
+};
+
+static DB_PATH: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+
+/// Use `path` instead of the environment's (`EngineBuilder::database`). Only
+/// before the database is first opened.
+pub(crate) fn set_db_path(path: String) {
+    let _ = DB_PATH.set(path);
+}
+
+pub(crate) fn db_path() -> String {
+    if let Some(path) = DB_PATH.get() {
+        return path.clone();
+    }
+    std::env::var("STUDIOCOMMAND_DB_PATH")
+        .unwrap_or_else(|_| "/opt/studiocommand/shared/studiocommand.db".to_string())
+}
+
+/// Prepare a freshly opened connection: per-connection settings, then (once
+/// per process) bring the schema up to date. See `migrations.rs`.
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    // The database worker's connection was set up when it was opened.
+    if db::initialized_here() {
+        return Ok(());
+    }
+    conn.execute_batch(
+        r#"
+        PRAGMA journal_mode = WAL;
+        PRAGMA synchronous = NORMAL;
+        PRAGMA foreign_keys = ON;
+        "#,
+    )?;
+    migrations::ensure(conn)
+}
+
+/// Migration 1: every table as of the introduction of versioned migrations.
+///
+/// Existing installs ran this code unversioned on every start, so it stays
+/// idempotent (`IF NOT EXISTS`, `db_add_column_if_missing`). Do not change it:
+/// schema changes go in a new migration.
+pub(crate) fn db_schema_baseline(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS queue_items (
+            id       TEXT PRIMARY KEY,
+            position INTEGER NOT NULL,
+            tag      TEXT NOT NULL,
+            time     TEXT NOT NULL,
+            title    TEXT NOT NULL,
+            artist   TEXT NOT NULL,
+            state    TEXT NOT NULL,
+            dur      TEXT NOT NULL,
+            cart     TEXT NOT NULL,
+            locked   INTEGER NOT NULL DEFAULT 0
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_queue_items_position ON queue_items(position);
+
+         CREATE TABLE IF NOT EXISTS stream_output_config (
+            id            INTEGER PRIMARY KEY CHECK (id = 1),
+            type          TEXT NOT NULL,
+            host          TEXT NOT NULL,
+            port          INTEGER NOT NULL,
+            mount         TEXT NOT NULL,
+            username      TEXT NOT NULL,
+            password      TEXT NOT NULL,
+            codec         TEXT NOT NULL,
+            bitrate_kbps  INTEGER NOT NULL,
+            enabled       INTEGER NOT NULL,
+            name          TEXT,
+            genre         TEXT,
+            description   TEXT,
+            public        INTEGER
+        );
+
+        CREATE TABLE IF NOT EXISTS aux_queue_items (
+            queue    TEXT NOT NULL,
+            id       TEXT NOT NULL,
+            position INTEGER NOT NULL,
+            tag      TEXT NOT NULL,
+            title    TEXT NOT NULL,
+            artist   TEXT NOT NULL,
+            dur      TEXT NOT NULL,
+            cart     TEXT NOT NULL,
+            PRIMARY KEY (queue, id)
+        );
+
+        CREATE TABLE IF NOT EXISTS top_up_config (
+            id            INTEGER PRIMARY KEY CHECK (id = 1),
+            enabled       INTEGER NOT NULL,
+            dir           TEXT NOT NULL,
+            min_queue     INTEGER NOT NULL,
+            batch         INTEGER NOT NULL
+        );
+        "#,
+    )?;
+
+    // Columns added after the first release, before migrations existed.
+    db_add_column_if_missing(conn, "queue_items", "locked", "INTEGER NOT NULL DEFAULT 0")?;
+    db_add_column_if_missing(conn, "top_up_config", "dayparts", "TEXT NOT NULL DEFAULT '[]'")?;
+    db_add_column_if_missing(conn, "top_up_config", "sources", "TEXT NOT NULL DEFAULT '[]'")?;
+    db_add_column_if_missing(conn, "top_up_config", "filters", "TEXT NOT NULL DEFAULT '{}'")?;
+    library::db_init(conn)?;
+    analysis::db_init(conn)?;
+    waveform::db_init(conn)?;
+    carts::db_init(conn)?;
+    daylog::db_init(conn)?;
+    import::db_init(conn)?;
+    ingest::db_init(conn)?;
+    clocks::db_init(conn)?;
+    history::db_init(conn)?;
+    rotation::db_init(conn)?;
+    events::db_init(conn)?;
+    schedule::db_init(conn)?;
+    breaks::db_init(conn)?;
+    announce::db_init(conn)?;
+    topuplog::db_init(conn)?;
+    shufflebag::db_init(conn)?;
+    public::db_init(conn)?;
+    metapush::db_init(conn)?;
+    rds::db_init(conn)?;
+    requests::db_init(conn)?;
+    Ok(())
+}
+
+pub(crate) fn db_add_column_if_missing(conn: &Connection, table: &str, column: &str, decl: &str) -> rusqlite::Result<()> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({table})"))?;
+    let exists = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .filter_map(|r| r.ok())
+        .any(|name| name == column);
+    if !exists {
+        conn.execute_batch(&format!("ALTER TABLE {table} ADD COLUMN {column} {decl};"))?;
+    }
+    Ok(())
+}
+
+/// Migration 11: exact lengths. Queue items get `dur_ms` (filled in from
+/// `dur`), the analysis cache `duration_ms` (left NULL until the file is
+/// probed again).
+pub(crate) fn db_add_dur_ms(conn: &Connection) -> rusqlite::Result<()> {
+    for table in ["queue_items", "aux_queue_items"] {
+        db_add_column_if_missing(conn, table, "dur_ms", "INTEGER NOT NULL DEFAULT 0")?;
+        let mut stmt = conn.prepare(&format!("SELECT id, dur FROM {table}"))?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for (id, dur) in rows {
+            let dur_ms = parse_dur_to_sec(&dur) as i64 * 1000;
+            conn.execute(&format!("UPDATE {table} SET dur_ms = ?1 WHERE id = ?2"), params![dur_ms, id])?;
+        }
+    }
+    db_add_column_if_missing(conn, "media_analysis", "duration_ms", "INTEGER")
+}
+
+/// A queue item's `transition` column: JSON, NULL when the item has none.
+fn transition_to_db(t: &Option<Transition>) -> Option<String> {
+    t.as_ref().and_then(|t| serde_json::to_string(t).ok())
+}
+
+fn transition_from_db(raw: Option<String>) -> Option<Transition> {
+    raw.and_then(|r| serde_json::from_str(&r).ok())
+}
+
+fn db_load_queue(conn: &Connection) -> anyhow::Result<Option<Vec<LogItem>>> {
+    db_init(conn)?;
+
+    let count: i64 = conn.query_row("SELECT COUNT(*) FROM queue_items", [], |row| row.get(0))?;
+    if count == 0 {
+        return Ok(None);
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT id, tag, time, title, artist, state, dur, cart, locked, transition, dur_ms FROM queue_items
+         ORDER BY position ASC",
+    )?;
+    let mut rows = stmt.query([])?;
+
+    let mut out: Vec<LogItem> = Vec::new();
+    while let Some(row) = rows.next()? {
+        let id_str: String = row.get(0)?;
+        let id = Uuid::parse_str(&id_str)
+            .map_err(|e| anyhow::anyhow!("invalid UUID in DB (id={id_str}): {e}"))?;
+
+        let state: String = row.get(5)?;
+        // Older installs only had the "locked" *marker*; treat it as the flag.
+        let locked = row.get::<_, i64>(8)? != 0 || state == "locked";
+
+        out.push(LogItem {
+            id,
+            tag: row.get(1)?,
+            time: row.get(2)?,
+            title: row.get(3)?,
+            artist: row.get(4)?,
+            state,
+            dur: row.get(6)?,
+            dur_ms: row.get::<_, i64>(10)?.max(0) as u64,
+            cart: row.get(7)?,
+            locked,
+            start_ms: None,
+            transition: transition_from_db(row.get(9)?),
+            hard_start_ms: None,
+        });
+    }
+
+    // Normalize state markers so the UI is consistent even if the DB contains older data.
+    // Note: we only normalize the *log* markers here; NowPlaying is derived from the
+    // in-memory PlayoutState and is handled separately.
+    normalize_log_markers(&mut out);
+
+    Ok(Some(out))
+}
+
+fn db_save_queue(conn: &mut Connection, log: &[LogItem]) -> anyhow::Result<()> {
+    db_init(conn)?;
+
+    let tx = conn.transaction()?;
+
+    // Simple + safe approach: rewrite the table in one transaction.
+    // This keeps ordering consistent and avoids partial updates on crash.
+    tx.execute("DELETE FROM queue_items", [])?;
+
+    // Cached: the queue is rewritten on every change, dozens of rows each time.
+    {
+        let mut insert = tx.prepare_cached(
+            "INSERT INTO queue_items
+               (id, position, tag, time, title, artist, state, dur, cart, locked, transition, dur_ms)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+        )?;
+        for (position, item) in log.iter().enumerate() {
+            insert.execute(params![
+                item.id.to_string(),
+                position as i64,
+                item.tag,
+                item.time,
+                item.title,
+                item.artist,
+                item.state,
+                item.dur,
+                item.cart,
+                if item.locked { 1 } else { 0 },
+                transition_to_db(&item.transition),
+                item.dur_ms as i64
+            ])?;
+        }
+    }
+
+    tx.commit()?;
+    Ok(())
+}
+
+pub(crate) async fn load_queue_from_db_or_demo() -> Vec<LogItem> {
+    let res = db::call(move |conn| db_load_queue(conn)).await;
+
+    match res {
+        Ok(Ok(Some(mut log))) => {
+            // In earlier versions we padded the queue with "Queued Track N" demo
+            // items to keep the UI busy. Operators asked that we stop doing
+            // this: an empty queue should remain empty.
+            //
+            // One more safety net: some installs may still have those old demo
+            // rows persisted in SQLite. If they remain, they can block Top-Up
+            // from refilling the real queue (because they count toward
+            // `min_queue`). We strip them on load so the station always prefers
+            // real audio.
+            log.retain(|it| {
+                let is_demo_title = it.title.starts_with("Queued Track");
+                let is_demo_artist = it.artist == "Various";
+                let has_no_path = it.cart.trim().is_empty();
+                !(is_demo_title && is_demo_artist) && !has_no_path
+            });
+            normalize_log_markers(&mut log);
+            log
+        }
+        Ok(Ok(None)) => Vec::new(),
+        Ok(Err(e)) => {
+            tracing::warn!("failed to load queue from sqlite, starting with empty queue: {e}");
+            Vec::new()
+        }
+        Err(e) => {
+            tracing::warn!("failed to join sqlite load task, starting with empty queue: {e}");
+            Vec::new()
+        }
+    }
+}
+
+fn default_output_config() -> StreamOutputConfig {
+    StreamOutputConfig {
+        r#type: "icecast".into(),
+        host: "seahorse.juststreamwith.us".into(),
+        port: 8006,
+        mount: "/studiocommand".into(),
+        username: "source".into(),
+        password: "".into(),
+        codec: "mp3".into(),
+        bitrate_kbps: 128,
+        enabled: false,
+        name: Some("StudioCommand".into()),
+        genre: None,
+        description: None,
+        public: Some(false),
+    }
+}
+
+pub(crate) fn default_topup_config() -> TopUpConfig {
+    // Default behavior: keep the station playing without requiring manual
+    // DB configuration on first install. The installer creates
+    // /opt/studiocommand/shared/data for persistent audio content.
+    // If you prefer a fully manual queue, set top_up_config.enabled = false
+    // via the API (or by inserting the row in SQLite).
+    TopUpConfig {
+        enabled: true,
+        dir: "/opt/studiocommand/shared/data".into(),
+        min_queue: 5,
+        batch: 5,
+        dayparts: Vec::new(),
+        sources: Vec::new(),
+        filters: TopUpFilters::default(),
+    }
+}
+
+/// Returns true if the stored top-up config looks like an *uninitialized* legacy row.
+///
+/// Why this exists:
+/// - Older StudioCommand versions created a `top_up_config` row with placeholder values
+///   (e.g., `enabled = 0`, empty dir, or zeros for min_queue/batch).
+/// - Newer versions default to a sensible, "keep the station playing" setup by
+///   topping up from `/opt/studiocommand/shared/data`.
+///
+/// If we always trust the presence of the row, a legacy placeholder would "win" and
+/// the engine would idle on silence forever even though audio exists.
+fn topup_config_needs_migration(cfg: &TopUpConfig) -> bool {
+    cfg.dir.trim().is_empty() || cfg.min_queue == 0 || cfg.batch == 0
+}
+
+fn db_load_topup_config(conn: &Connection) -> anyhow::Result<TopUpConfig> {
+    db_init(conn)?;
+
+    let row_opt = conn.query_row(
+        "SELECT enabled, dir, min_queue, batch, dayparts, sources, filters FROM top_up_config WHERE id = 1",
+        [],
+        |row| {
+            Ok(TopUpConfig {
+                enabled: row.get::<_, i64>(0)? != 0,
+                dir: row.get::<_, String>(1)?,
+                min_queue: row.get::<_, i64>(2)? as u16,
+                batch: row.get::<_, i64>(3)? as u16,
+                dayparts: serde_json::from_str(&row.get::<_, String>(4)?).unwrap_or_default(),
+                sources: serde_json::from_str(&row.get::<_, String>(5)?).unwrap_or_default(),
+                filters: serde_json::from_str(&row.get::<_, String>(6)?).unwrap_or_default(),
+            })
+        },
+    );
+
+    match row_opt {
+        Ok(cfg) => Ok(cfg),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(default_topup_config()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub(crate) fn db_save_topup_config(conn: &mut Connection, cfg: &TopUpConfig) -> anyhow::Result<()> {
+    db_init(conn)?;
+    conn.execute(
+        "INSERT INTO top_up_config (id, enabled, dir, min_queue, batch, dayparts, sources, filters)
+         VALUES (1, ?1, ?2, ?3, ?4, ?5, ?6, ?7)
+         ON CONFLICT(id) DO UPDATE SET
+           enabled=excluded.enabled,
+           dir=excluded.dir,
+           min_queue=excluded.min_queue,
+           batch=excluded.batch,
+           dayparts=excluded.dayparts,
+           sources=excluded.sources,
+           filters=excluded.filters",
+        params![
+            if cfg.enabled { 1 } else { 0 },
+            cfg.dir,
+            cfg.min_queue as i64,
+            cfg.batch as i64,
+            serde_json::to_string(&cfg.dayparts)?,
+            serde_json::to_string(&cfg.sources)?,
+            serde_json::to_string(&cfg.filters)?,
+        ],
+    )?;
+    Ok(())
+}
+
+pub(crate) async fn load_topup_config_from_db_or_default() -> TopUpConfig {
+    let res = db::call(move |conn| db_load_topup_config(conn)).await;
+
+    match res {
+        Ok(Ok(cfg)) => {
+            // If a legacy install already has a `top_up_config` row, it may contain
+            // placeholder values that effectively disable top-up forever.
+            //
+            // We treat that specific shape as "uninitialized" and migrate it to
+            // the new, safe defaults (shared data folder).
+            if topup_config_needs_migration(&cfg) {
+                let migrated = default_topup_config();
+
+                // Log before we move/clone any values so we never accidentally
+                // keep a legacy install silent.
+                tracing::warn!(
+                    "top-up config looked uninitialized; migrated to defaults (dir={})",
+                    migrated.dir
+                );
+
+                // We'll persist in the background, but we must not move `migrated`
+                // into the closure because we still return it below.
+                let migrated_for_save = migrated.clone();
+
+                // Best-effort persist; if this fails we still return the migrated
+                // config for this run so the station plays.
+                let _ = db::call(move |conn| -> anyhow::Result<()> {
+                    db_save_topup_config(conn, &migrated_for_save)?;
+                    Ok(())
+                })
+                .await;
+                migrated
+            } else {
+                cfg
+            }
+        }
+        Ok(Err(e)) => {
+            tracing::warn!("failed to load top-up config, using defaults: {e}");
+            default_topup_config()
+        }
+        Err(e) => {
+            tracing::warn!("failed to join top-up load task, using defaults: {e}");
+            default_topup_config()
+        }
+    }
+}
+
+fn db_load_output_config(conn: &Connection) -> anyhow::Result<StreamOutputConfig> {
+    db_init(conn)?;
+
+    let row_opt = conn.query_row(
+        "SELECT type, host, port, mount, username, password, codec, bitrate_kbps, enabled, name, genre, description, public FROM stream_output_config WHERE id = 1",
+        [],
+        |row| {
+            Ok(StreamOutputConfig {
+                r#type: row.get::<_, String>(0)?,
+                host: row.get::<_, String>(1)?,
+                port: row.get::<_, i64>(2)? as u16,
+                mount: row.get::<_, String>(3)?,
+                username: row.get::<_, String>(4)?,
+                password: row.get::<_, String>(5)?,
+                codec: row.get::<_, String>(6)?,
+                bitrate_kbps: row.get::<_, i64>(7)? as u16,
+                enabled: row.get::<_, i64>(8)? != 0,
+                name: row.get::<_, Option<String>>(9)?,
+                genre: row.get::<_, Option<String>>(10)?,
+                description: row.get::<_, Option<String>>(11)?,
+                public: match row.get::<_, Option<i64>>(12)? {
+                    Some(v) => Some(v != 0),
+                    None => None,
+                },
+            })
+        },
+    );
+
+    let mut cfg = match row_opt {
+        Ok(cfg) => cfg,
+        Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(default_output_config()),
+        Err(e) => return Err(e.into()),
+    };
+
+    // The column holds the sealed password (see `secrets.rs`). Rows from
+    // before encryption are plain text: seal them now.
+    let stored = std::mem::take(&mut cfg.password);
+    match secrets::open(&stored) {
+        Ok(plain) => cfg.password = plain,
+        Err(e) => tracing::warn!("stream output: stored password unreadable, treating it as empty: {e}"),
+    }
+    if !stored.is_empty() && !secrets::is_sealed(&stored) {
+        match secrets::seal(&cfg.password) {
+            Ok(sealed) => {
+                conn.execute("UPDATE stream_output_config SET password = ?1 WHERE id = 1", params![sealed])?;
+                tracing::info!("stream output: encrypted the stored password");
+            }
+            Err(e) => tracing::warn!("stream output: password left unencrypted: {e}"),
+        }
+    }
+    Ok(cfg)
+}
+
+pub(crate) fn db_save_output_config(conn: &mut Connection, cfg: &StreamOutputConfig) -> anyhow::Result<()> {
+    db_init(conn)?;
+    conn.execute(
+        "INSERT INTO stream_output_config (id, type, host, port, mount, username, password, codec, bitrate_kbps, enabled, name, genre, description, public)
+         VALUES (1, ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)
+         ON CONFLICT(id) DO UPDATE SET
+           type=excluded.type,
+           host=excluded.host,
+           port=excluded.port,
+           mount=excluded.mount,
+           username=excluded.username,
+           password=excluded.password,
+           codec=excluded.codec,
+           bitrate_kbps=excluded.bitrate_kbps,
+           enabled=excluded.enabled,
+           name=excluded.name,
+           genre=excluded.genre,
+           description=excluded.description,
+           public=excluded.public",
+        params![
+            cfg.r#type,
+            cfg.host,
+            cfg.port as i64,
+            cfg.mount,
+            cfg.username,
+            secrets::seal(&cfg.password)?,
+            cfg.codec,
+            cfg.bitrate_kbps as i64,
+            if cfg.enabled { 1 } else { 0 },
+            cfg.name,
+            cfg.genre,
+            cfg.description,
+            cfg.public.map(|v| if v { 1 } else { 0 }),
+        ],
+    )?;
+    Ok(())
+}
+
+pub(crate) async fn load_output_config_from_db_or_default() -> StreamOutputConfig {
+    let res = db::call(move |conn| db_load_output_config(conn)).await;
+
+    match res {
+        Ok(Ok(cfg)) => cfg,
+        Ok(Err(e)) => {
+            tracing::warn!("failed to load stream output config, using defaults: {e}");
+            default_output_config()
+        }
+        Err(e) => {
+            tracing::warn!("failed to join stream output load task, using defaults: {e}");
+            default_output_config()
+        }
+    }
+}
+
+fn db_load_aux_queues(conn: &Connection) -> anyhow::Result<std::collections::BTreeMap<String, Vec<LogItem>>> {
+    db_init(conn)?;
+
+    let mut out: std::collections::BTreeMap<String, Vec<LogItem>> =
+        AUX_QUEUES.iter().map(|q| (q.to_string(), Vec::new())).collect();
+
+    let mut stmt = conn.prepare(
+        "SELECT queue, id, tag, title, artist, dur, cart, transition, dur_ms FROM aux_queue_items
+         ORDER BY queue, position ASC",
+    )?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let queue: String = row.get(0)?;
+        let id_str: String = row.get(1)?;
+        let id = Uuid::parse_str(&id_str)
+            .map_err(|e| anyhow::anyhow!("invalid UUID in DB (aux id={id_str}): {e}"))?;
+        let Some(items) = out.get_mut(&queue) else {
+            // A queue name this build doesn't know about; leave the rows alone.
+            continue;
+        };
+        items.push(LogItem {
+            id,
+            tag: row.get(2)?,
+            time: "".into(),
+            title: row.get(3)?,
+            artist: row.get(4)?,
+            state: "queued".into(),
+            dur: row.get(5)?,
+            dur_ms: row.get::<_, i64>(8)?.max(0) as u64,
+            cart: row.get(6)?,
+            locked: false,
+            start_ms: None,
+            transition: transition_from_db(row.get(7)?),
+            hard_start_ms: None,
+        });
+    }
+    Ok(out)
+}
+
+fn db_save_aux_queue(conn: &mut Connection, queue: &str, items: &[LogItem]) -> anyhow::Result<()> {
+    db_init(conn)?;
+
+    // Same approach as `db_save_queue`: rewrite the queue in one transaction.
+    let tx = conn.transaction()?;
+    tx.execute("DELETE FROM aux_queue_items WHERE queue = ?1", params![queue])?;
+    for (position, item) in items.iter().enumerate() {
+        tx.execute(
+            "INSERT INTO aux_queue_items (queue, id, position, tag, title, artist, dur, cart, transition, dur_ms)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                queue,
+                item.id.to_string(),
+                position as i64,
+                item.tag,
+                item.title,
+                item.artist,
+                item.dur,
+                item.cart,
+                transition_to_db(&item.transition),
+                item.dur_ms as i64
+            ],
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+pub(crate) async fn load_aux_queues_from_db() -> std::collections::BTreeMap<String, Vec<LogItem>> {
+    let res = db::call(move |conn| db_load_aux_queues(conn))
+    .await
+    .map_err(|e| anyhow::anyhow!(e))
+    .and_then(|x| x);
+
+    match res {
+        Ok(q) => q,
+        Err(e) => {
+            tracing::warn!("failed to load secondary queues from sqlite, starting empty: {e}");
+            AUX_QUEUES.iter().map(|q| (q.to_string(), Vec::new())).collect()
+        }
+    }
+}
+
+pub(crate) async fn persist_aux_queue(queue: String, items: Vec<LogItem>) {
+    let _ = db::call(move |conn| -> anyhow::Result<()> {
+        db_save_aux_queue(conn, &queue, &items)?;
+        Ok(())
+    })
+    .await
+    .map_err(|e| anyhow::anyhow!(e))
+    .and_then(|x| x)
+    .map_err(|e| tracing::warn!("failed to persist secondary queue to sqlite: {e}"));
+}
+
+pub(crate) async fn persist_queue(log: Vec<LogItem>) {
+    let _ = db::call(move |conn| -> anyhow::Result<()> {
+        db_save_queue(conn, &log)?;
+        Ok(())
+    })
+    .await
+    .map_err(|e| anyhow::anyhow!(e))
+    .and_then(|x| x)
+    .map_err(|e| tracing::warn!("failed to persist queue to sqlite: {e}"));
+}
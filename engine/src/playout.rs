@@ -0,0 +1,1702 @@
+// --- Playout --------------------------------------------------------------------------------
+//
+// The queue and what is on the air: `PlayoutState`, the rules every queue
+// edit goes through (locked items, state markers, air-time estimates,
+// advancing to the next item), and `writer_playout`, which decodes the queue
+// item by item into the PCM stream the encoder, the meters and Listen Live
+// share.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use serde::{Serialize, Deserialize};
+use serde_json::json;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command;
+use uuid::Uuid;
+
+use crate::{
+    analysis, breaks, callers, carts, db, db_save_topup_config, default_topup_config, diagnostics, emergency, eventlog,
+    events, failures, ffmpeg, history, library, metapush, meters, overlay, paths, persist_aux_queue, persist_queue,
+    remote, resume, shutdown, supervisor, topup_is_reference, topup_try, topuplog, transitions, voicetrack, TopUpConfig,
+    TopUpStats, Transition,
+};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LogItem {
+    pub id: Uuid,
+    pub tag: String,
+    pub time: String,
+    pub title: String,
+    pub artist: String,
+    pub state: String, // "playing" | "next" | "queued" | "locked"
+    pub dur: String,   // "3:45"
+    /// Exact length in milliseconds; timing uses this. `dur` is the same,
+    /// rounded to the second, for display and older clients. 0 when only
+    /// `dur` is known (see `length_ms`).
+    #[serde(default)]
+    pub dur_ms: u64,
+    pub cart: String,
+    /// Locked items (legal IDs, sponsor spots) hold their position: reorders,
+    /// moves and top-up must never carry another item past them. The `state`
+    /// marker for an upcoming locked item is "locked" (see `normalize_log_markers`).
+    #[serde(default)]
+    pub locked: bool,
+    /// Estimated air time (Unix millis), recomputed from the playing position
+    /// and the durations ahead of this item. `time` carries the same value
+    /// formatted as local `HH:MM:SS`. Not persisted.
+    #[serde(default)]
+    pub start_ms: Option<u64>,
+    /// Overrides of the station's transition defaults (transitions.rs).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transition: Option<Transition>,
+    /// When a hard-timed event's item is due on air (Unix millis; events.rs).
+    /// Not persisted: after a restart the event no longer starts hard.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hard_start_ms: Option<u64>,
+}
+
+impl LogItem {
+    /// Length in milliseconds: `dur_ms`, or `dur` for items that only have that.
+    pub(crate) fn length_ms(&self) -> u64 {
+        if self.dur_ms > 0 {
+            self.dur_ms
+        } else {
+            parse_dur_to_sec(&self.dur) as u64 * 1000
+        }
+    }
+
+    /// Set the length, keeping `dur` in step.
+    pub(crate) fn set_length_ms(&mut self, ms: u64) {
+        self.dur_ms = ms;
+        self.dur = fmt_dur_ms(ms);
+    }
+}
+
+#[derive(Clone, Serialize)]
+pub struct NowPlaying {
+    pub title: String,
+    pub artist: String,
+    pub dur: u32,   // seconds
+    pub pos: u32,   // whole seconds (legacy/compat)
+    pub pos_f: f64, // seconds with fractions (for smooth UI)
+    /// Cover art URL (`/api/v1/library/{id}/art`) when the playing file is in
+    /// the library; filled in shortly after the track starts.
+    pub art: Option<String>,
+    /// End of the intro (seconds), from the library's cue; filled in with `art`.
+    pub intro: Option<f64>,
+    /// Seconds left until the intro ends, for a talk-up countdown; `None`
+    /// without a cue and once the vocal has started.
+    pub intro_remaining_sec: Option<f64>,
+}
+
+#[derive(Clone, Serialize, Default)]
+pub struct VuLevels {
+    pub rms_l: f32,
+    pub rms_r: f32,
+    pub peak_l: f32,
+    pub peak_r: f32,
+}
+
+#[derive(Clone)]
+pub(crate) struct PlayoutState {
+    pub(crate) now: NowPlaying,
+    pub(crate) log: Vec<LogItem>,
+
+    // Internal timing derived from the real PCM stream (meters: meters.rs).
+    pub(crate) track_started_at: Option<std::time::Instant>,
+
+    /// Monotonic revision of `log`, bumped on every queue mutation.
+    ///
+    /// Two operators editing the queue at the same time could previously
+    /// clobber each other silently (the second reorder wins, built from a
+    /// stale view). Clients now echo the revision they rendered from and the
+    /// engine rejects the mutation with 409 if the queue changed underneath.
+    pub(crate) queue_rev: u64,
+
+    /// Items that recently left the top of the queue (newest last), with their
+    /// final `state` ("played" | "skipped" | "dumped" | "faded"). Bounded; this is what
+    /// `POST /api/v1/queue/requeue/{id}` can put back.
+    pub(crate) recent: VecDeque<LogItem>,
+
+    /// Secondary named queues (see `AUX_QUEUES`), keyed by name.
+    pub(crate) aux: std::collections::BTreeMap<String, Vec<LogItem>>,
+
+    /// Set to the playing item's id to fade it out over `events::HARD_FADE_MS`
+    /// and advance (hard-timed events). The writer clears it when done.
+    pub(crate) fade_out: Option<Uuid>,
+}
+
+fn demo_log() -> Vec<LogItem> {
+    vec![
+        LogItem{ id: Uuid::new_v4(), tag:"MUS".into(), time:"Now".into(), title:"Neutron Dance".into(), artist:"Pointer Sisters".into(), state:"playing".into(), dur:"4:02".into(), dur_ms:0, cart:"080-0861".into(), locked:false, start_ms:None, transition:None, hard_start_ms:None },
+        LogItem{ id: Uuid::new_v4(), tag:"MUS".into(), time:"+0:00".into(), title:"Super Freak (Part 1)".into(), artist:"Rick James".into(), state:"next".into(), dur:"3:14".into(), dur_ms:0, cart:"080-1588".into(), locked:false, start_ms:None, transition:None, hard_start_ms:None },
+        LogItem{ id: Uuid::new_v4(), tag:"MUS".into(), time:"+3:14".into(), title:"Bette Davis Eyes".into(), artist:"Kim Carnes".into(), state:"queued".into(), dur:"3:30".into(), dur_ms:0, cart:"080-6250".into(), locked:false, start_ms:None, transition:None, hard_start_ms:None },
+        LogItem{ id: Uuid::new_v4(), tag:"MUS".into(), time:"+6:44".into(), title:"Jessie's Girl".into(), artist:"Rick Springfield".into(), state:"queued".into(), dur:"3:07".into(), dur_ms:0, cart:"080-1591".into(), locked:false, start_ms:None, transition:None, hard_start_ms:None },
+    ]
+}
+
+async fn playout_tick(playout: Arc<tokio::sync::RwLock<PlayoutState>>) {
+    use tokio::time::{sleep, Duration};
+
+    loop {
+        sleep(Duration::from_secs(1)).await;
+
+        let mut p = playout.write().await;
+        p.now.pos = p.now.pos.saturating_add(1);
+        p.now.pos_f = p.now.pos as f64;
+
+        // When the current item finishes, drop it from the log and promote the next item.
+        //
+        // NOTE: This stub engine mutates the queue over time (removing the playing
+        // item and padding demo items). To keep SQLite persistence intuitive during
+        // development/testing, we also persist the updated queue whenever the
+        // "track ends" event occurs.
+        // Update playing position from monotonic clock.
+        if let Some(started) = p.track_started_at {
+            let mut pos_f = started.elapsed().as_secs_f64();
+            if p.now.dur > 0 {
+                pos_f = pos_f.min(p.now.dur as f64);
+            }
+            p.now.pos_f = pos_f;
+            p.now.pos = pos_f.floor() as u32;
+        }
+
+        if p.now.pos >= p.now.dur {
+            p.now.pos = 0;
+    p.now.pos_f = 0.0;
+    p.track_started_at = Some(std::time::Instant::now());
+    meters::reset();
+
+            if !p.log.is_empty() {
+                // Remove the playing item (top of log).
+                p.log.remove(0);
+                bump_queue_rev(&mut p);
+            }
+
+            // Promote new playing item from top of log.
+            // Anchor timing for UI/progress and any dur-based logic.
+            p.track_started_at = Some(std::time::Instant::now());
+            meters::reset();
+            if let Some(first) = p.log.get_mut(0) {
+                // Mark the first log item as playing. We must avoid holding a mutable
+                // borrow of `first` while also mutating `p.now` (Rust borrow rules).
+                first.state = "playing".into();
+
+                // Clone the fields we need *while* we have access to `first`...
+                let title = first.title.clone();
+                let artist = first.artist.clone();
+                let dur = first.dur.clone();
+
+                // ...then explicitly end the `first` borrow before touching `p.now`.
+                drop(first);
+
+                p.now.title = title;
+                p.now.art = None;
+                p.now.intro = None;
+                p.now.intro_remaining_sec = None;
+                p.now.artist = artist;
+
+                if let Some(d) = parse_dur_seconds(&dur) {
+                    p.now.dur = d;
+                }
+            }
+
+            // Ensure there's a "next" item (locked items keep their marker)
+            normalize_log_markers(&mut p.log);
+
+            // Earlier versions padded the queue with demo tracks ("Queued Track N").
+            // That behavior was convenient for UI screenshots, but surprising in
+            // production. We now leave the queue exactly as the operator/scheduler
+            // set it.
+
+            // Persist the updated queue, but do it *after* releasing the write lock.
+            // We intentionally clone the log to keep the lock hold-time short.
+            let snapshot = p.log.clone();
+            drop(p);
+            persist_queue(snapshot).await;
+        }
+    }
+}
+
+/// Items kept in `PlayoutState::recent`.
+pub(crate) const MAX_RECENT: usize = 50;
+
+fn remember_recent(p: &mut PlayoutState, item: LogItem) {
+    tokio::spawn(history::record_end(item.id, item.state.clone()));
+    if p.recent.len() >= MAX_RECENT {
+        p.recent.pop_front();
+    }
+    p.recent.push_back(item);
+}
+
+/// Record that the queue changed so stale clients get a 409 on their next edit.
+pub(crate) fn bump_queue_rev(p: &mut PlayoutState) {
+    p.queue_rev = p.queue_rev.wrapping_add(1);
+}
+
+// --- Secondary queues (break stack, cart wall) ----------------------------
+//
+// Besides the main log, the engine keeps a couple of named side queues:
+//   - "breaks":   the spot/break stack. When playout reaches a break marker
+//                 (an item tagged "BRK" in the main log), the marker is
+//                 replaced by the stack's contents and the stack is emptied.
+//   - "cartwall": instant-play items. Enqueuing one copies it into the main
+//                 log as the next item; the cart wall itself is unchanged.
+//
+// Each has the same CRUD surface under /api/v1/queues/{name} and is persisted
+// in `aux_queue_items`, keyed by queue name.
+
+pub(crate) const AUX_QUEUES: [&str; 2] = ["breaks", "cartwall"];
+
+/// Tag that marks a break in the main log.
+pub(crate) const BREAK_MARKER_TAG: &str = "BRK";
+
+/// If the item at the top of the main log is a break marker, replace it with
+/// the contents of the break stack (emptying the stack). A marker with a
+/// length is fitted to it (see `breaks.rs`); spots that don't fit stay on the
+/// stack for the next break.
+///
+/// Returns true if anything changed so the caller can persist both queues.
+fn expand_break_marker(p: &mut PlayoutState, filler: Option<&breaks::Filler>) -> bool {
+    if p.log.first().map(|it| it.tag != BREAK_MARKER_TAG).unwrap_or(true) {
+        return false;
+    }
+
+    let marker = p.log.remove(0);
+    let stack = p.aux.get_mut("breaks").map(std::mem::take).unwrap_or_default();
+    let (spots, held) = match filler {
+        Some(f) => breaks::fit_break(stack, marker.length_ms(), f),
+        None => (stack, Vec::new()),
+    };
+    if let Some(stack) = p.aux.get_mut("breaks") {
+        *stack = held;
+    }
+    tracing::info!("break marker reached: injecting {} item(s)", spots.len());
+    for (i, mut spot) in spots.into_iter().enumerate() {
+        spot.id = Uuid::new_v4();
+        p.log.insert(i, spot);
+    }
+    normalize_queue_states(&mut p.log);
+    bump_queue_rev(p);
+    true
+}
+
+pub(crate) fn normalize_log_markers(log: &mut [LogItem]) {
+    // Keep queue marker semantics deterministic:
+    //   - index 0 is always "playing"
+    //   - locked upcoming items are always "locked" (the flag survives; the
+    //     marker is derived from it)
+    //   - index 1 (if present and not locked) is "next"
+    //   - everything after that is "queued"
+    //
+    // We centralize this logic so it can be applied both to the in-memory queue
+    // and to DB-loaded queues (which may contain legacy/incorrect markers).
+    for (i, item) in log.iter_mut().enumerate() {
+        item.state = match i {
+            0 => "playing",
+            _ if item.locked => "locked",
+            1 => "next",
+            _ => "queued",
+        }
+        .into();
+    }
+}
+
+/// True if moving the item at `from` to `to` would carry it past a locked
+/// item (or if the item itself is locked).
+pub(crate) fn move_crosses_lock(log: &[LogItem], from: usize, to: usize) -> bool {
+    if log[from].locked {
+        return true;
+    }
+    let between = if from < to { &log[from + 1..=to] } else { &log[to..from] };
+    between.iter().any(|it| it.locked)
+}
+
+/// Checks a proposed reorder against locked items: every locked item must keep
+/// its index, and no other item may end up on the other side of one.
+pub(crate) fn reorder_respects_locks(before: &[LogItem], after: &[LogItem]) -> bool {
+    use std::collections::HashMap;
+
+    // Segment index = number of locked items ahead of this position.
+    let segments = |log: &[LogItem]| {
+        let mut seg = 0usize;
+        let mut out = HashMap::with_capacity(log.len());
+        for it in log {
+            if it.locked {
+                seg += 1;
+            }
+            out.insert(it.id, seg);
+        }
+        out
+    };
+
+    let locks_in_place = before
+        .iter()
+        .zip(after)
+        .all(|(b, a)| b.locked == a.locked && (!b.locked || b.id == a.id));
+    locks_in_place && segments(before) == segments(after)
+}
+
+pub(crate) fn normalize_log_state(p: &mut PlayoutState){
+    // Ensure we always have deterministic "playing/next/queued" markers,
+    // and keep Now Playing in sync with the first item in the log.
+    normalize_log_markers(&mut p.log);
+
+    if let Some(first) = p.log.get(0) {
+        p.now.title = first.title.clone();
+        p.now.artist = first.artist.clone();
+        p.now.dur = ms_to_sec(first.length_ms());
+        // Keep current position, but clamp only when duration is known.
+        // If dur is 0 (unknown), do NOT reset pos; that makes the UI progress bar
+        // creep forward and snap back to 0 every tick.
+        if p.now.dur > 0 && p.now.pos > p.now.dur {
+            p.now.pos = p.now.dur;
+            p.now.pos_f = p.now.dur as f64;
+        }
+    }
+
+    let now = p.now.clone();
+    estimate_start_times(&mut p.log, &now, unix_ms_now());
+}
+
+/// Fill in `time`/`start_ms` for every item from the playing position forward.
+///
+/// The playing item started `pos_f` seconds ago; every following item starts
+/// when the one before it ends, less its transition's overlap (transitions.rs).
+/// A hard-timed event starts at its time, not before: the item in front of it
+/// is faded out then, and a gap before it stays a gap. Items with an unknown
+/// duration ("0:00") contribute nothing, so times after them are optimistic.
+pub(crate) fn estimate_start_times(log: &mut [LogItem], now: &NowPlaying, now_ms: u64) {
+    let mut t_ms = now_ms.saturating_sub((now.pos_f.max(0.0) * 1000.0) as u64);
+    for i in 0..log.len() {
+        // The playing item has started already, however it was timed.
+        if let Some(at_ms) = log[i].hard_start_ms.filter(|_| i > 0) {
+            t_ms = t_ms.max(at_ms);
+        }
+        let item = &mut log[i];
+        item.start_ms = Some(t_ms);
+        item.time = fmt_local_hhmmss(t_ms);
+        let overlap_ms = transitions::resolve(item.transition.as_ref()).overlap_into_next() as u64;
+        let mut end_ms = t_ms + item.length_ms().saturating_sub(overlap_ms);
+        if let Some(at_ms) = log.get(i + 1).and_then(|next| next.hard_start_ms) {
+            end_ms = end_ms.min(at_ms.max(t_ms));
+        }
+        t_ms = end_ms;
+    }
+}
+
+pub(crate) fn unix_ms_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Local day of week (0 = Sunday) and minute of the day for Unix millis.
+pub(crate) fn local_weekday_minute(unix_ms: u64) -> Option<(usize, u32)> {
+    let t = (unix_ms / 1000) as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&t, &mut tm) }.is_null() {
+        return None;
+    }
+    Some((tm.tm_wday as usize, (tm.tm_hour * 60 + tm.tm_min) as u32))
+}
+
+/// "HH:MM" -> minutes after midnight.
+pub(crate) fn parse_hhmm(s: &str) -> Option<u32> {
+    let (h, m) = s.trim().split_once(':')?;
+    let (h, m) = (h.parse::<u32>().ok()?, m.parse::<u32>().ok()?);
+    (h < 24 && m < 60).then_some(h * 60 + m)
+}
+
+/// Format Unix millis as local wall-clock `HH:MM:SS`.
+///
+/// We go through `localtime_r` because the `time` crate refuses to read the
+/// local offset in a multi-threaded process, and operators expect the queue
+/// column in station time rather than UTC.
+pub(crate) fn fmt_local_hhmmss(unix_ms: u64) -> String {
+    let t = (unix_ms / 1000) as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&t, &mut tm) }.is_null() {
+        return "--:--".into();
+    }
+    format!("{:02}:{:02}:{:02}", tm.tm_hour, tm.tm_min, tm.tm_sec)
+}
+
+pub(crate) fn reset_demo_playout(p: &mut PlayoutState) {
+    // Keep this deterministic so the UI is predictable while we build real scheduling.
+    p.now.title = "Lean On Me".into();
+    p.now.art = None;
+    p.now.intro = None;
+    p.now.intro_remaining_sec = None;
+    p.now.artist = "Club Nouveau".into();
+    p.now.dur = 3*60 + 48;
+    p.now.pos = 0;
+    p.now.pos_f = 0.0;
+    p.track_started_at = Some(std::time::Instant::now());
+    meters::reset();
+
+    p.log = vec![
+        LogItem{ id: Uuid::new_v4(), tag:"MUS".into(), time:"15:33".into(), title:"Lean On Me".into(), artist:"Club Nouveau".into(), state:"playing".into(), dur:"3:48".into(), dur_ms:0, cart:"080-0599".into(), locked:false, start_ms:None, transition:None, hard_start_ms:None },
+        LogItem{ id: Uuid::new_v4(), tag:"MUS".into(), time:"15:37".into(), title:"Bette Davis Eyes".into(), artist:"Kim Carnes".into(), state:"queued".into(), dur:"3:30".into(), dur_ms:0, cart:"080-6250".into(), locked:false, start_ms:None, transition:None, hard_start_ms:None },
+        LogItem{ id: Uuid::new_v4(), tag:"MUS".into(), time:"15:41".into(), title:"Talk Dirty To Me".into(), artist:"Poison".into(), state:"queued".into(), dur:"3:42".into(), dur_ms:0, cart:"080-4577".into(), locked:false, start_ms:None, transition:None, hard_start_ms:None },
+        LogItem{ id: Uuid::new_v4(), tag:"EVT".into(), time:"15:45".into(), title:"TOH Legal ID".into(), artist:"".into(), state:"queued".into(), dur:"0:10".into(), dur_ms:0, cart:"ID-TOH".into(), locked:true, start_ms:None, transition:None, hard_start_ms:None },
+        LogItem{ id: Uuid::new_v4(), tag:"MUS".into(), time:"15:46".into(), title:"Jessie's Girl".into(), artist:"Rick Springfield".into(), state:"queued".into(), dur:"3:07".into(), dur_ms:0, cart:"080-1591".into(), locked:false, start_ms:None, transition:None, hard_start_ms:None },
+    ];
+
+    // Ensure "next"/"locked" are marked consistently.
+    normalize_log_markers(&mut p.log);
+
+    bump_queue_rev(p);
+}
+
+/// `dur` in seconds, 0 when it cannot be parsed.
+pub(crate) fn parse_dur_to_sec(d: &str) -> u32 {
+    parse_dur_seconds(d).unwrap_or(0)
+}
+
+pub(crate) fn advance_to_next(p: &mut PlayoutState, reason: Option<&str>) {
+    // Mark and remove the current playing item, then promote the next queued item.
+    if !p.log.is_empty() {
+        // remove the first item (assumed playing)
+        let mut removed = p.log.remove(0);
+        if let Some(r) = reason {
+            removed.state = r.into();
+        } else {
+            removed.state = "played".into();
+        }
+        remember_recent(p, removed);
+    }
+
+    // Promote new first item
+    if let Some(first) = p.log.get_mut(0) {
+        first.state = "playing".into();
+        p.now.title = first.title.clone();
+        p.now.art = None;
+        p.now.intro = None;
+        p.now.intro_remaining_sec = None;
+        p.now.artist = first.artist.clone();
+        p.now.dur = ms_to_sec(first.length_ms());
+        p.now.pos = 0;
+    p.now.pos_f = 0.0;
+    p.track_started_at = Some(std::time::Instant::now());
+    meters::reset();
+    } else {
+        // Empty log: clear now
+        p.now.title = "".into();
+        p.now.art = None;
+        p.now.intro = None;
+        p.now.intro_remaining_sec = None;
+        p.now.artist = "".into();
+        p.now.dur = 0;
+        p.now.pos = 0;
+    p.now.pos_f = 0.0;
+    p.track_started_at = Some(std::time::Instant::now());
+    meters::reset();
+    }
+
+    // Maintain "next"/"locked" markers
+    normalize_log_markers(&mut p.log);
+
+    bump_queue_rev(p);
+}
+
+// --- Real playout writer --------------------------------------------------
+
+/// The queue head failed to start (see failures.rs): count it against the
+/// item and the file, and skip it once it has failed
+/// `failures::skip_after()` times in a row. Returns whether it is gone.
+async fn start_failed(
+    playout: &Arc<tokio::sync::RwLock<PlayoutState>>,
+    failing: &mut Option<(Uuid, u32)>,
+    s: history::Started,
+    error: String,
+) -> bool {
+    let count = match failing {
+        Some((id, n)) if *id == s.item_id => {
+            *n += 1;
+            *n
+        }
+        _ => {
+            *failing = Some((s.item_id, 1));
+            1
+        }
+    };
+    tracing::warn!("playout failed ({count}): {} - {} ({}): {error}", s.artist, s.title, s.path);
+    tokio::spawn(failures::record(s.path.clone(), s.title.clone(), error));
+
+    let limit = failures::skip_after();
+    if limit == 0 || count < limit {
+        return false;
+    }
+    *failing = None;
+    let log = {
+        let mut p = playout.write().await;
+        if p.log.first().map(|it| it.id) != Some(s.item_id) {
+            return true;
+        }
+        tracing::warn!("playout skip after {count} failures: {} - {}", s.artist, s.title);
+        eventlog::record(
+            "track_skipped",
+            format!("{} - {} skipped after {count} failures", s.artist, s.title),
+            json!({"item_id": s.item_id, "path": s.path}),
+        );
+        advance_to_next(&mut p, Some("errored"));
+        p.log.clone()
+    };
+    tokio::spawn(history::record_errored(s));
+    persist_queue(log).await;
+    true
+}
+
+/// Half a second of silence between attempts at a failing item.
+async fn retry_pause(
+    stdin: &mut tokio::process::ChildStdin,
+    interval: &mut tokio::time::Interval,
+    silence: &[u8],
+) -> std::io::Result<()> {
+    for _ in 0..25 {
+        let mut chunk = silence.to_vec();
+        overlay::mix(&mut chunk);
+        callers::mix(&mut chunk);
+        diagnostics::TICK_LATE.record(interval.tick().await.elapsed());
+        stdin.write_all(&chunk).await?;
+    }
+    Ok(())
+}
+
+pub(crate) fn resolve_cart_to_path(cart: &str) -> Option<String> {
+    use std::path::Path;
+
+    let cart = cart.trim();
+    if cart.is_empty() {
+        return None;
+    }
+
+    // A URL plays from its local copy (remote.rs).
+    if remote::is_url(cart) {
+        return remote::local_copy(cart);
+    }
+
+    // Absolute path (possibly escaped, see paths.rs)
+    let path = paths::from_cart(cart);
+    if path.is_absolute() && path.exists() {
+        return Some(cart.to_string());
+    }
+
+    // Registered carts (the `carts` table) win.
+    if let Some(p) = carts::lookup(cart) {
+        if paths::from_cart(&p).exists() {
+            return Some(p);
+        }
+    }
+
+    // Legacy: unregistered carts are looked up by file name in the shared
+    // carts folder: /opt/studiocommand/shared/carts/<cart>.<ext>
+    let base = "/opt/studiocommand/shared/carts";
+    let exts = ["flac", "wav", "mp3", "m4a", "aac", "ogg", "opus"]; // decode via ffmpeg
+    for ext in exts {
+        let p = format!("{base}/{cart}.{ext}");
+        if Path::new(&p).exists() {
+            return Some(p);
+        }
+    }
+
+    None
+}
+
+/// `start_ms` > 0 seeks before decoding (input seeking: fast, and exact for
+/// the formats we play).
+pub(crate) async fn spawn_ffmpeg_decoder(
+    input: &str,
+    start_ms: u64,
+) -> anyhow::Result<(tokio::process::Child, tokio::process::ChildStdout)> {
+    let ffmpeg = ffmpeg::ffmpeg_bin();
+
+    let mut cmd = Command::new(ffmpeg);
+    cmd.arg("-hide_banner")
+        .arg("-loglevel").arg("error");
+    if start_ms > 0 {
+        cmd.arg("-ss").arg(format!("{}.{:03}", start_ms / 1000, start_ms % 1000));
+    }
+    cmd.args(remote::input_args(input))
+        .arg("-f").arg("s16le")
+        .arg("-ar").arg("48000")
+        .arg("-ac").arg("2")
+        .arg("pipe:1")
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null());
+
+    let mut child = cmd.spawn()?;
+    let stdout = child.stdout.take().ok_or_else(|| anyhow::anyhow!("decoder stdout unavailable"))?;
+    Ok((child, stdout))
+}
+
+fn make_silence_chunk(frames: usize) -> Vec<u8> {
+    // s16le stereo = 2 bytes * 2 channels
+    vec![0u8; frames * 2 * 2]
+}
+
+fn clamp01_f32(x: f32) -> f32 { x.max(0.0).min(1.0) }
+
+fn db_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+/// Apply a linear fade-out to interleaved s16le PCM. `done` is how many frames
+/// of the fade have already been written; `total` is the fade length in frames.
+fn fade_pcm_s16le_stereo(buf: &mut [u8], done: u64, total: u64) {
+    ramp_pcm_s16le_stereo(buf, 1.0, 0.0, done, total);
+}
+
+/// Ramp the gain of interleaved s16le PCM linearly from `from` to `to` over
+/// `total` frames (`done` already written), then hold it at `to`.
+fn ramp_pcm_s16le_stereo(buf: &mut [u8], from: f32, to: f32, done: u64, total: u64) {
+    for (i, frame) in buf.chunks_exact_mut(4).enumerate() {
+        let gain = from + (to - from) * ((done + i as u64) as f32 / total.max(1) as f32).min(1.0);
+        for s in frame.chunks_exact_mut(2) {
+            let v = i16::from_le_bytes([s[0], s[1]]) as f32 * gain;
+            s.copy_from_slice(&(v as i16).to_le_bytes());
+        }
+    }
+}
+
+pub(crate) fn analyze_pcm_s16le_stereo(buf: &[u8]) -> VuLevels {
+    // Interleaved stereo, little-endian i16.
+    // Returns per-channel RMS and peak, normalized to [0,1].
+    let mut sumsq_l: f64 = 0.0;
+    let mut sumsq_r: f64 = 0.0;
+    let mut peak_l: i32 = 0;
+    let mut peak_r: i32 = 0;
+    let mut nframes: u64 = 0;
+
+    let mut i = 0usize;
+    while i + 3 < buf.len() {
+        let l = i16::from_le_bytes([buf[i], buf[i + 1]]) as i32;
+        let r = i16::from_le_bytes([buf[i + 2], buf[i + 3]]) as i32;
+        let al = l.abs();
+        let ar = r.abs();
+        if al > peak_l { peak_l = al; }
+        if ar > peak_r { peak_r = ar; }
+        sumsq_l += (l as f64) * (l as f64);
+        sumsq_r += (r as f64) * (r as f64);
+        nframes += 1;
+        i += 4;
+    }
+
+    if nframes == 0 {
+        return VuLevels::default();
+    }
+
+    let mean_l = sumsq_l / (nframes as f64);
+    let mean_r = sumsq_r / (nframes as f64);
+
+    let rms_l = (mean_l.sqrt() / 32768.0) as f32;
+    let rms_r = (mean_r.sqrt() / 32768.0) as f32;
+    let pk_l = (peak_l as f32) / 32768.0;
+    let pk_r = (peak_r as f32) / 32768.0;
+
+    VuLevels {
+        rms_l: clamp01_f32(rms_l),
+        rms_r: clamp01_f32(rms_r),
+        peak_l: clamp01_f32(pk_l),
+        peak_r: clamp01_f32(pk_r),
+    }
+}
+
+pub(crate) fn smooth_level(current: f32, target: f32, attack: f32, release: f32) -> f32 {
+    // attack/release are smoothing factors in (0,1]; higher = faster.
+    if target >= current {
+        current + (target - current) * attack
+    } else {
+        current + (target - current) * release
+    }
+}
+
+/// Parse a `dur`: "M:SS" (minutes may run past 59, as older clients send
+/// "75:00") or "H:MM:SS" for long-form content. Seconds past 59 are refused.
+pub(crate) fn parse_dur_seconds(dur: &str) -> Option<u32> {
+    let parts: Vec<u32> = dur.trim().split(':').map(|p| p.parse().ok()).collect::<Option<_>>()?;
+    match parts[..] {
+        [m, s] if s < 60 => m.checked_mul(60)?.checked_add(s),
+        [h, m, s] if m < 60 && s < 60 => h.checked_mul(3600)?.checked_add(m * 60 + s),
+        _ => None,
+    }
+}
+
+/// Format seconds as `dur`: "M:SS", or "H:MM:SS" from an hour up.
+pub(crate) fn fmt_dur_mmss(total_s: u32) -> String {
+    let h = total_s / 3600;
+    let m = total_s / 60 % 60;
+    let s = total_s % 60;
+    if h > 0 {
+        format!("{}:{:02}:{:02}", h, m, s)
+    } else {
+        format!("{}:{:02}", total_s / 60, s)
+    }
+}
+
+/// Milliseconds to whole seconds, rounded.
+pub(crate) fn ms_to_sec(ms: u64) -> u32 {
+    ((ms + 500) / 1000) as u32
+}
+
+/// `ms` as `dur`, rounded to the nearest second.
+pub(crate) fn fmt_dur_ms(ms: u64) -> String {
+    fmt_dur_mmss(ms_to_sec(ms))
+}
+
+/// What a quick ffprobe tells us about a file.
+#[derive(Clone)]
+pub(crate) struct MediaProbe {
+    pub(crate) duration_s: u32,
+    /// The same, exact (0 when unknown).
+    pub(crate) duration_ms: u64,
+    pub(crate) has_audio: bool,
+    pub(crate) title: Option<String>,
+    pub(crate) artist: Option<String>,
+    pub(crate) album: Option<String>,
+}
+
+impl MediaProbe {
+    /// Length in milliseconds, exact when known.
+    pub(crate) fn length_ms(&self) -> u64 {
+        if self.duration_ms > 0 {
+            self.duration_ms
+        } else {
+            self.duration_s as u64 * 1000
+        }
+    }
+}
+
+/// Quick, time-boxed ffprobe used to validate and tag files before they are
+/// queued.
+///
+/// Returns *why* a file is unusable, so API callers get an actionable error.
+pub(crate) async fn probe_media(path: &str) -> Result<MediaProbe, String> {
+    use tokio::time::{timeout, Duration};
+
+    let ffprobe = ffmpeg::ffprobe_bin();
+
+    let mut cmd = Command::new(ffprobe);
+    cmd.arg("-v").arg("error")
+        .arg("-show_entries").arg("format=duration:format_tags=title,artist,album:stream=codec_type")
+        .arg("-of").arg("json")
+        .args(remote::input_args(path))
+        .kill_on_drop(true);
+
+    let out = match timeout(Duration::from_secs(5), cmd.output()).await {
+        Ok(Ok(out)) => out,
+        Ok(Err(e)) => return Err(format!("ffprobe could not be run: {e}")),
+        Err(_) => return Err(format!("{path}: ffprobe timed out")),
+    };
+    if !out.status.success() {
+        let err = String::from_utf8_lossy(&out.stderr);
+        let err = err.lines().last().unwrap_or("").trim();
+        return Err(format!("{path}: not decodable ({err})"));
+    }
+
+    let v: serde_json::Value = serde_json::from_slice(&out.stdout)
+        .map_err(|e| format!("{path}: unreadable ffprobe output: {e}"))?;
+
+    let duration_ms = v["format"]["duration"]
+        .as_str()
+        .and_then(|d| d.parse::<f64>().ok())
+        .filter(|d| d.is_finite() && *d > 0.0)
+        .map(|d| (d * 1000.0).round() as u64)
+        .unwrap_or(0);
+    let duration_s = ms_to_sec(duration_ms);
+    let has_audio = v["streams"]
+        .as_array()
+        .map(|s| s.iter().any(|st| st["codec_type"] == "audio"))
+        .unwrap_or(false);
+    // Vorbis comments come back upper-case ("TITLE"), ID3 lower-case.
+    let tag = |k: &str| {
+        v["format"]["tags"]
+            .as_object()?
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(k))
+            .and_then(|(_, val)| val.as_str())
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+    };
+
+    Ok(MediaProbe {
+        duration_s,
+        duration_ms,
+        has_audio,
+        title: tag("title"),
+        artist: tag("artist"),
+        album: tag("album"),
+    })
+}
+
+pub(crate) fn normalize_queue_states(log: &mut Vec<LogItem>) {
+    normalize_log_markers(log);
+}
+
+pub(crate) fn title_from_path(p: &str) -> String {
+    paths::from_cart(p)
+        .file_stem()
+        .map(|s| s.to_string_lossy().replace('_', " "))
+        .unwrap_or_else(|| "Unknown".into())
+}
+
+/// Split a `Artist - Title.mp3` style file name into (artist, title).
+///
+/// Only used when a file carries no tags; names without the separator are
+/// returned as a bare title.
+pub(crate) fn artist_title_from_path(p: &str) -> (Option<String>, String) {
+    let stem = title_from_path(p);
+    match stem.split_once(" - ") {
+        Some((artist, title)) if !artist.trim().is_empty() && !title.trim().is_empty() => {
+            (Some(artist.trim().to_string()), title.trim().to_string())
+        }
+        _ => (None, stem),
+    }
+}
+
+pub(crate) async fn writer_playout(
+    mut stdin: tokio::process::ChildStdin,
+    playout: Arc<tokio::sync::RwLock<PlayoutState>>,
+    topup: Arc<tokio::sync::Mutex<TopUpConfig>>,
+    topup_stats: Arc<tokio::sync::Mutex<TopUpStats>>,
+    pcm_tx: tokio::sync::broadcast::Sender<Vec<u8>>,
+) -> anyhow::Result<()> {
+    const SR: u32 = 48_000;
+    // 20 ms @ 48 kHz = 960 frames. Keeping the chunk size aligned to 20 ms makes
+    // WebRTC/Opus framing straightforward and keeps pacing accurate.
+    const FRAMES: usize = 960;
+    const BYTES_PER_FRAME: usize = 2 * 2; // s16le * stereo
+    const CHUNK_BYTES: usize = FRAMES * BYTES_PER_FRAME;
+
+    let silence = make_silence_chunk(FRAMES);
+    let mut interval = tokio::time::interval(std::time::Duration::from_millis(20));
+    // Avoid hammering the filesystem when we're idling on silence.
+    let mut last_topup_check = std::time::Instant::now() - std::time::Duration::from_secs(10);
+    // The item that keeps failing to start, and how many times in a row.
+    let mut failing: Option<(Uuid, u32)> = None;
+    // The next item, already started on the overlay bus by a voice-track
+    // handover (voicetrack.rs) or a transition (transitions.rs), its gain
+    // there in dB and the fade-in (ms) it started with.
+    let mut handoff: Option<(Uuid, f32, u64)> = None;
+
+    loop {
+        // Shutting down (see shutdown.rs): returning closes the encoder's
+        // stdin, so it flushes and leaves Icecast cleanly.
+        if shutdown::requested() {
+            return Ok(());
+        }
+
+        // If output is running but the queue is empty/low, top-up must still run.
+        // (In v0.1.42 it only ran after an end-of-track advance, so an empty queue
+        // would idle on silence forever.)
+        if last_topup_check.elapsed() >= std::time::Duration::from_secs(2) {
+            last_topup_check = std::time::Instant::now();
+
+            // Top-up config is persisted in SQLite and may point at external
+            // storage (e.g., a NAS mount). If that mount disappears, the engine
+            // would otherwise sit on silence forever.
+            //
+            // We treat a missing configured directory as a *runtime health* issue
+            // and automatically fall back to the built-in shared data path
+            // created by the installer.
+            //
+            // This keeps "it plays" behavior reliable while still allowing
+            // operators to intentionally point top-up elsewhere.
+            let mut cfg_guard = topup.lock().await;
+            let cfg_default = default_topup_config();
+            if cfg_guard.enabled && cfg_guard.sources.is_empty() && !topup_is_reference(&cfg_guard.dir) {
+                let configured = cfg_guard.dir.clone();
+                let configured_exists = std::path::Path::new(&configured).exists();
+                if !configured_exists {
+                    let fallback = cfg_default.dir.clone();
+                    if configured != fallback && std::path::Path::new(&fallback).exists() {
+                        tracing::warn!(
+                            "top-up dir missing ({}); falling back to {}",
+                            configured,
+                            fallback
+                        );
+
+                        // Adopt the fallback for this run (and persist best-effort).
+                        cfg_guard.dir = fallback;
+
+                        // If a legacy row had min/batch=0, fix that too.
+                        if cfg_guard.min_queue == 0 {
+                            cfg_guard.min_queue = cfg_default.min_queue;
+                        }
+                        if cfg_guard.batch == 0 {
+                            cfg_guard.batch = cfg_default.batch;
+                        }
+
+                        let cfg_to_save = cfg_guard.clone();
+                        let _ = db::call(move |conn| -> anyhow::Result<()> {
+                            db_save_topup_config(conn, &cfg_to_save)?;
+                            Ok(())
+                        })
+                        .await;
+                    }
+                }
+            }
+
+            // A matching daypart swaps in its own directory for this tick.
+            let (cfg, active_rule) = cfg_guard.for_time(unix_ms_now());
+            let mut used_dir = cfg.dir.clone();
+            drop(cfg_guard);
+
+            // Attempt a normal scan.
+            let mut snapshot_to_persist: Option<Vec<LogItem>> = None;
+            let mut attempt = {
+                let mut p = playout.write().await;
+                let attempt = topup_try(&mut p.log, &cfg).await;
+                if attempt.appended > 0 {
+                    bump_queue_rev(&mut p);
+                    snapshot_to_persist = Some(p.log.clone());
+                }
+                attempt
+            };
+
+            // If the configured directory exists but is empty (or scan/probe
+            // fails), automatically try the installer-managed shared data path.
+            //
+            // This is the common "it plays" expectation on fresh installs.
+            if cfg.enabled && attempt.appended == 0 {
+                let fallback = default_topup_config().dir;
+                let should_try_fallback = (attempt.files_found == 0) || attempt.error.is_some();
+                if should_try_fallback && cfg.dir != fallback && std::path::Path::new(&fallback).exists() {
+                    let mut cfg2 = cfg.clone();
+                    cfg2.dir = fallback.clone();
+                    cfg2.sources.clear();
+
+                    let attempt2 = {
+                        let mut p = playout.write().await;
+                        let attempt2 = topup_try(&mut p.log, &cfg2).await;
+                        if attempt2.appended > 0 {
+                            bump_queue_rev(&mut p);
+                            snapshot_to_persist = Some(p.log.clone());
+                        }
+                        attempt2
+                    };
+
+                    // A daypart's folder, weighted folders or a playlist/category
+                    // failing is not a reason to rewrite the base directory; just
+                    // use the fallback for this tick.
+                    if attempt2.appended > 0
+                        && (active_rule.is_some() || !cfg.sources.is_empty() || topup_is_reference(&cfg.dir))
+                    {
+                        attempt = attempt2;
+                        used_dir = fallback;
+                    } else if attempt2.appended > 0 {
+                        tracing::warn!(
+                            "top-up from configured dir produced no items; falling back to {}",
+                            fallback
+                        );
+
+                        // Adopt the fallback for subsequent runs and persist best-effort.
+                        let mut cfg_guard = topup.lock().await;
+                        cfg_guard.dir = fallback.clone();
+                        let cfg_to_save = cfg_guard.clone();
+                        drop(cfg_guard);
+                        let _ = db::call(move |conn| -> anyhow::Result<()> {
+                            db_save_topup_config(conn, &cfg_to_save)?;
+                            Ok(())
+                        }).await;
+
+                        attempt = attempt2;
+                        used_dir = fallback;
+                    }
+                }
+            }
+
+            // Publish top-up telemetry.
+            {
+                let mut s = topup_stats.lock().await;
+                s.active_rule = active_rule;
+                // Only overwrite scan results if we actually scanned.
+                // Otherwise a healthy system (queue full) would constantly
+                // clobber the last meaningful stats with zeros.
+                if attempt.scanned {
+                    s.last_scan_ms = Some(
+                        std::time::SystemTime::now()
+                            .duration_since(std::time::UNIX_EPOCH)
+                            .unwrap_or_default()
+                            .as_millis() as u64,
+                    );
+                    s.last_dir = Some(used_dir.clone());
+                    s.last_files_found = Some(attempt.files_found);
+                    s.last_appended = Some(attempt.appended);
+                    s.last_error = attempt.error.clone();
+                    s.last_skip_reason = None;
+                    topuplog::record("tick", &used_dir, s.active_rule.clone(), &attempt);
+                    s.record_sources(attempt.sources);
+                } else {
+                    // Only the first skip after a scan goes into the history.
+                    if s.last_skip_reason.is_none() && attempt.skip_reason.is_some() {
+                        topuplog::record("tick", &used_dir, s.active_rule.clone(), &attempt);
+                    }
+                    s.last_skip_reason = attempt.skip_reason.clone();
+                }
+            }
+
+            if let Some(log) = snapshot_to_persist {
+                persist_queue(log).await;
+            }
+        }
+
+        // Filling a break to length needs filler candidates from SQLite; fetch
+        // them before taking the lock, and only when a break is up next.
+        let filler = if playout.read().await.log.first().map(|it| it.tag == BREAK_MARKER_TAG).unwrap_or(false) {
+            Some(breaks::filler_candidates().await)
+        } else {
+            None
+        };
+
+        // Determine current track (log[0]) and resolve its path.
+        let (id, tag, title, artist, mut dur_ms, cart, path_opt) = {
+            let mut p = playout.write().await;
+
+            // Break markers aren't playable themselves; swap in the spot stack.
+            // (Persisted in the background so we don't hold the lock on SQLite.)
+            if expand_break_marker(&mut p, filler.as_ref()) {
+                let log = p.log.clone();
+                let breaks = p.aux.get("breaks").cloned().unwrap_or_default();
+                tokio::spawn(async move {
+                    persist_queue(log).await;
+                    persist_aux_queue("breaks".into(), breaks).await;
+                });
+            }
+
+            if p.log.is_empty() {
+                // Nothing to play.
+
+                (Uuid::nil(), "".into(), "".into(), "".into(), 0u64, String::new(), None)
+            } else {
+                normalize_queue_states(&mut p.log);
+
+                let (first_id, tag, title, artist, dur_ms, cart) = {
+                    let first = &p.log[0];
+                    (
+                        first.id,
+                        first.tag.clone(),
+                        first.title.clone(),
+                        first.artist.clone(),
+                        first.length_ms(),
+                        first.cart.clone(),
+                    )
+
+                };
+
+                let path_opt = resolve_cart_to_path(&cart)
+
+                    .or_else(|| if cart.starts_with('/') { Some(cart.clone()) } else { None });
+
+                // Update now-playing (anchor timing + reset meters/progress).
+p.now.title = title.clone();
+p.now.art = None;
+p.now.intro = None;
+p.now.intro_remaining_sec = None;
+p.now.artist = artist.clone();
+p.now.dur = ms_to_sec(dur_ms);
+p.now.pos = 0;
+p.now.pos_f = 0.0;
+p.track_started_at = Some(std::time::Instant::now());
+meters::reset();
+
+(first_id, tag, title, artist, dur_ms, cart, path_opt)
+            }
+        };
+
+        let started = history::Started {
+            item_id: id,
+            path: path_opt.clone().unwrap_or_else(|| cart.clone()),
+            cart: cart.clone(),
+            tag: tag.clone(),
+            title: title.clone(),
+            artist: artist.clone(),
+            dur: fmt_dur_ms(dur_ms),
+        };
+
+        // A handover started this item on the overlay bus; anything else there
+        // is stale (the queue changed under it).
+        let handed = handoff.take().filter(|(h, _, _)| *h == id && path_opt.is_some());
+        let handed_ms = match handed {
+            Some(_) => overlay::take(voicetrack::HANDOVER),
+            None => {
+                overlay::stop(Some(voicetrack::HANDOVER));
+                None
+            }
+        };
+
+        // If we don't have a playable path, write silence and retry.
+        let Some(path) = path_opt else {
+            if id.is_nil() && emergency::pending() {
+                emergency::play_pending(&mut stdin, &pcm_tx, &mut interval, None).await?;
+            } else if id.is_nil() {
+                // Nothing queued: the overlay bus and callers still play over silence.
+                let mut chunk = silence.clone();
+                if overlay::mix(&mut chunk) | callers::mix(&mut chunk) {
+                    let _ = pcm_tx.send(chunk.clone());
+                }
+                diagnostics::TICK_LATE.record(interval.tick().await.elapsed());
+                stdin.write_all(&chunk).await?;
+            } else if !start_failed(&playout, &mut failing, started, "cart not found".into()).await {
+                retry_pause(&mut stdin, &mut interval, &silence).await?;
+            }
+            continue;
+        };
+        // Retries of a failing item are not new starts.
+        let retry = failing.is_some_and(|(f, _)| f == id);
+
+        tracing::info!("playout start: {} - {} ({})", artist, title, path);
+
+        // Items inserted without a usable duration ("0:00") would leave the
+        // progress bar and air-time estimates dead for the whole track; the
+        // analysis cache usually knows the real length.
+        if dur_ms == 0 {
+            if let Ok(probe) = analysis::probe_cached(&path).await {
+                let mut p = playout.write().await;
+                if p.log.first().map(|it| it.id) == Some(id) && probe.length_ms() > 0 {
+                    dur_ms = probe.length_ms();
+                    p.now.dur = ms_to_sec(dur_ms);
+                    p.log[0].set_length_ms(dur_ms);
+                    bump_queue_rev(&mut p);
+                }
+            }
+        }
+        tokio::spawn(history::record_start(started.clone()));
+        if !retry {
+            tokio::spawn(library::mark_played(path.clone()));
+            eventlog::record(
+                "track_started",
+                format!("{artist} - {title}"),
+                json!({"item_id": id, "tag": tag, "path": path}),
+            );
+            metapush::track_changed(metapush::Track {
+                title: title.clone(),
+                artist: artist.clone(),
+                tag,
+                cart,
+                dur_s: ms_to_sec(dur_ms),
+            });
+            // Point now-playing at the library's cover art and intro cue, if this is a library file.
+            let playout = playout.clone();
+            let path = path.clone();
+            tokio::spawn(async move {
+                if let Ok(Some((track_id, intro_ms))) = library::lookup_id(&path).await {
+                    let mut p = playout.write().await;
+                    if p.log.first().map(|it| it.id) == Some(id) {
+                        p.now.art = Some(format!("/api/v1/library/{track_id}/art"));
+                        p.now.intro = intro_ms.map(|ms| ms as f64 / 1000.0);
+                    }
+                }
+            });
+        }
+
+        // First start after a restart: pick up where the last run left off.
+        // After a handover, carry on from where the overlay voice got to.
+        let start_ms = resume::take_offset(id, dur_ms).or(handed_ms).unwrap_or(0);
+        if start_ms > 0 {
+            tracing::info!("playout resume: {} - {} at {}", artist, title, fmt_dur_mmss((start_ms / 1000) as u32));
+        }
+
+        // Start decoder and stream PCM to encoder stdin.
+        // IMPORTANT: we keep the supervisor handle so we can kill the decoder
+        // early on operator actions like "skip" or "dump".
+        let (child, mut dec_stdout) = match spawn_ffmpeg_decoder(&path, start_ms).await {
+            Ok(v) => v,
+            Err(e) => {
+                tracing::warn!("decoder spawn failed for {path}: {e}");
+                if !start_failed(&playout, &mut failing, started, format!("decoder: {e}")).await {
+                    retry_pause(&mut stdin, &mut interval, &silence).await?;
+                }
+                continue;
+            }
+        };
+        let (mut decoder, mut decoder_exit) = supervisor::supervise("decoder", path.clone(), child);
+        let mut retries_left = supervisor::decoder_retries();
+
+let mut buf = vec![0u8; CHUNK_BYTES];
+
+// Progress derived from actual PCM that we successfully feed to the encoder.
+// For s16le stereo, each frame is 4 bytes (2 bytes per channel).
+// A resumed item counts from where the decoder started.
+let mut frames_written: u64 = start_ms * SR as u64 / 1000;
+let start_frames = frames_written;
+// Set when the decoder failed and could not be restarted.
+let mut decode_error: Option<String> = None;
+resume::note(id, start_ms);
+
+// Meter + position updates (keep lock cadence modest).
+let mut last_update = std::time::Instant::now() - std::time::Duration::from_secs(1);
+
+// If an operator advances the queue while we're mid-track (Skip/Dump), we must
+// stop emitting this track immediately. Otherwise the UI will jump to the next
+// item while the previous track continues to play until EOF.
+let mut interrupted = false;
+
+// Hard-timed events fade the playing item out instead of cutting it; this is
+// the frame count at which the fade started.
+let fade_frames = events::HARD_FADE_MS * SR as u64 / 1000;
+let mut fade_start: Option<u64> = None;
+// Shutdown fades the item out too, but leaves it at the head of the queue.
+let shutdown_fade_frames = shutdown::fade_ms() * SR as u64 / 1000;
+let mut shutdown_start: Option<u64> = None;
+
+// Handover to the item after this one (a voice track or a transition): which
+// item it is, when and how it starts, and the frame (gain, ramp length) at
+// which this one was ducked. Planned again whenever the queue changes.
+let ramp_frames = voicetrack::RAMP_MS * SR as u64 / 1000;
+let mut plan_rev: Option<u64> = None;
+let mut next_up: Option<Uuid> = None;
+let mut handover: Option<(voicetrack::Handover, String)> = None;
+let mut duck_start: Option<(u64, f32, u64)> = None;
+// Handed over under a voice track: come up from the duck.
+let unduck = handed.map(|(_, db, _)| db).filter(|db| *db < 0.0);
+
+// This item's own fades (transitions.rs). One handed over keeps the fade-in
+// it started with on the overlay bus.
+let own = {
+    let p = playout.read().await;
+    transitions::resolve(p.log.first().filter(|it| it.id == id).and_then(|it| it.transition.as_ref()))
+};
+let fade_in_frames = handed.map_or(own.fade_in_ms as u64, |(_, _, ms)| ms) * SR as u64 / 1000;
+let mut fade_out_frames = own.fade_out_ms as u64 * SR as u64 / 1000;
+let dur_frames = dur_ms * SR as u64 / 1000;
+
+// Emergency announcement (emergency.rs): where the fade out for it began,
+// and where the item came back afterwards.
+let eas_frames = emergency::FADE_MS * SR as u64 / 1000;
+let mut eas_fade: Option<u64> = None;
+let mut eas_resumed: Option<u64> = None;
+
+loop {
+    // Check for operator-driven queue advance.
+    // We do this on every chunk (20ms) which is cheap and keeps stop latency low.
+    let mut replan = None;
+    {
+        let p = playout.read().await;
+        if p.log.is_empty() || p.log[0].id != id {
+            interrupted = true;
+        } else if fade_start.is_none() && p.fade_out == Some(id) {
+            fade_start = Some(frames_written);
+        }
+        if !interrupted && plan_rev != Some(p.queue_rev) {
+            plan_rev = Some(p.queue_rev);
+            let next = p.log.get(1).map(|it| (it.id, it.cart.clone(), transitions::resolve(it.transition.as_ref())));
+            replan = Some((transitions::resolve(p.log[0].transition.as_ref()), next));
+        }
+    }
+    // The queue changed: plan the handover again. A different next item calls
+    // off one in progress; otherwise one in progress carries on as started.
+    if let Some((own, next)) = replan {
+        fade_out_frames = own.fade_out_ms as u64 * SR as u64 / 1000;
+        let next_id = next.as_ref().map(|(id, _, _)| *id);
+        if next_id != next_up {
+            next_up = next_id;
+            if handoff.take().is_some() {
+                overlay::stop(Some(voicetrack::HANDOVER));
+                duck_start = None;
+            }
+        }
+        if handoff.is_none() {
+            handover = next.and_then(|(_, c, next_trans)| {
+                let next = resolve_cart_to_path(&c).or_else(|| c.starts_with('/').then(|| c.clone()))?;
+                let h = voicetrack::handover(&path, dur_ms, &next)
+                    .or_else(|| transitions::handover(&own, dur_ms, &next_trans))?;
+                Some((h, next))
+            });
+        }
+    }
+    if let (Some((h, next)), None, Some(next_id)) = (&handover, handoff, next_up) {
+        if frames_written * 1000 / SR as u64 >= h.at_ms {
+            tracing::info!("playout handover: {} - {} -> {}", artist, title, next);
+            overlay::play(voicetrack::HANDOVER.into(), String::new(), next.clone(), h.in_db);
+            overlay::fade_in(voicetrack::HANDOVER, h.fade_in_ms);
+            handoff = Some((next_id, h.in_db, h.fade_in_ms));
+            let duck_frames = h.ramp_ms * SR as u64 / 1000;
+            duck_start = Some((frames_written, h.out_db, duck_frames)).filter(|(_, db, _)| *db < 0.0);
+        }
+    }
+    if fade_start.is_some_and(|f0| frames_written >= f0 + fade_frames) {
+        let mut p = playout.write().await;
+        p.fade_out = None;
+        if p.log.first().map(|it| it.id) == Some(id) {
+            advance_to_next(&mut p, Some("faded"));
+        }
+        interrupted = true;
+    }
+    if interrupted {
+        tracing::info!("playout interrupted (skip/dump): {} - {}", artist, title);
+        break;
+    }
+    if eas_fade.is_none() && emergency::pending() {
+        eas_fade = Some(frames_written);
+    }
+    if eas_fade.is_some_and(|f0| frames_written >= f0 + eas_frames) {
+        eas_fade = None;
+        let at_ms = frames_written * 1000 / SR as u64;
+        let interrupted_item = format!("{artist} - {title}");
+        emergency::play_pending(&mut stdin, &pcm_tx, &mut interval, Some((interrupted_item, at_ms))).await?;
+        // Resume with a fresh decoder where the fade ended; the old one has
+        // been stalled on a full pipe all along.
+        match spawn_ffmpeg_decoder(&path, at_ms).await {
+            Ok((child, stdout)) => {
+                let (d, exit) = supervisor::supervise("decoder", path.clone(), child);
+                std::mem::replace(&mut decoder, d).stop();
+                let _ = std::mem::replace(&mut decoder_exit, exit).await;
+                dec_stdout = stdout;
+                eas_resumed = Some(frames_written);
+                tracing::info!("playout resume: {} - {} at {}", artist, title, fmt_dur_mmss((at_ms / 1000) as u32));
+            }
+            Err(e) => {
+                decode_error = Some(format!("decoder: {e}"));
+                break;
+            }
+        }
+        continue;
+    }
+    if shutdown::requested() {
+        let f0 = *shutdown_start.get_or_insert(frames_written);
+        if frames_written >= f0 + shutdown_fade_frames {
+            resume::save(id, frames_written * 1000 / SR as u64).await;
+            decoder.stop();
+            let _ = decoder_exit.await;
+            tracing::info!("playout shutdown: {} - {} at {}", artist, title, fmt_dur_mmss((frames_written / SR as u64) as u32));
+            return Ok(());
+        }
+    }
+
+    let read_started = std::time::Instant::now();
+    let n = dec_stdout.read(&mut buf).await?;
+    let read_done = std::time::Instant::now();
+    diagnostics::DECODER_READ.record(read_done - read_started);
+    if n == 0 {
+        // End of output: normally the end of the file. A decoder that failed
+        // well before the end is started again where it stopped.
+        let exit = match tokio::time::timeout(std::time::Duration::from_secs(2), &mut decoder_exit).await {
+            Ok(Ok(exit)) => Some(exit),
+            _ => None,
+        };
+        let at_ms = frames_written * 1000 / SR as u64;
+        let near_end = dur_ms > 0 && at_ms + 2000 >= dur_ms;
+        if exit.as_ref().is_some_and(|e| !e.success()) && !near_end && retries_left > 0 {
+            retries_left -= 1;
+            if let Ok((child, stdout)) = spawn_ffmpeg_decoder(&path, at_ms).await {
+                supervisor::event("decoder", &path, None, "restarting", format!("at {}", fmt_dur_mmss((at_ms / 1000) as u32)));
+                (decoder, decoder_exit) = supervisor::supervise("decoder", path.clone(), child);
+                dec_stdout = stdout;
+                continue;
+            }
+        }
+        if let Some(e) = exit.filter(|e| !e.success() && !near_end) {
+            decode_error = Some(format!("decoder {}", e.describe()));
+        }
+        break;
+    }
+
+    if let Some(f0) = fade_start {
+        fade_pcm_s16le_stereo(&mut buf[..n], frames_written - f0, fade_frames);
+    }
+    if let Some(f0) = shutdown_start {
+        fade_pcm_s16le_stereo(&mut buf[..n], frames_written - f0, shutdown_fade_frames);
+    }
+    if let Some(f0) = eas_fade {
+        fade_pcm_s16le_stereo(&mut buf[..n], frames_written - f0, eas_frames);
+    }
+    if let Some(f0) = eas_resumed.filter(|f0| frames_written - f0 < eas_frames) {
+        ramp_pcm_s16le_stereo(&mut buf[..n], 0.0, 1.0, frames_written - f0, eas_frames);
+    }
+    if let Some((f0, db, frames)) = duck_start {
+        ramp_pcm_s16le_stereo(&mut buf[..n], 1.0, db_gain(db), frames_written - f0, frames);
+    }
+    if frames_written < fade_in_frames {
+        ramp_pcm_s16le_stereo(&mut buf[..n], 0.0, 1.0, frames_written, fade_in_frames);
+    }
+    let fade_out_from = dur_frames.checked_sub(fade_out_frames).filter(|_| fade_out_frames > 0);
+    if let Some(f0) = fade_out_from.filter(|f0| frames_written >= *f0) {
+        fade_pcm_s16le_stereo(&mut buf[..n], frames_written - f0, fade_out_frames);
+    }
+    if let Some(db) = unduck.filter(|_| frames_written - start_frames < ramp_frames) {
+        ramp_pcm_s16le_stereo(&mut buf[..n], db_gain(db), 1.0, frames_written - start_frames, ramp_frames);
+    }
+
+    // Cart wall jingles and other overlay voices play on top (overlay.rs), and a caller on air.
+    overlay::mix(&mut buf[..n]);
+    callers::mix(&mut buf[..n]);
+
+    // Analyze *before* writing so we can update meters even if the encoder blocks briefly.
+    let inst = analyze_pcm_s16le_stereo(&buf[..n]);
+
+    // Fan out the raw PCM to any WebRTC listeners.
+    // If there are no receivers, broadcast::Sender::send returns an error; that's fine.
+    let _ = pcm_tx.send(buf[..n].to_vec());
+
+
+    // Pace writes to match real-time.
+    diagnostics::TICK_LATE.record(interval.tick().await.elapsed());
+    let write_started = std::time::Instant::now();
+    stdin.write_all(&buf[..n]).await?;
+    diagnostics::ENCODER_WRITE.record(write_started.elapsed());
+    diagnostics::PIPELINE.record(read_done.elapsed());
+
+    // Count frames actually delivered to the encoder.
+    frames_written += (n / BYTES_PER_FRAME) as u64;
+
+    // Update meters + position at ~30 Hz.
+    if last_update.elapsed() >= std::time::Duration::from_millis(33) {
+        last_update = std::time::Instant::now();
+
+        let pos_f = frames_written as f64 / SR as f64;
+
+        let mut p = playout.write().await;
+
+        // Position (seconds). Clamp only when we have a known duration.
+        p.now.pos_f = if p.now.dur > 0 {
+            pos_f.min(p.now.dur as f64)
+        } else {
+            pos_f
+        };
+        p.now.pos = p.now.pos_f.floor() as u32;
+        p.now.intro_remaining_sec = p.now.intro.map(|end| end - p.now.pos_f).filter(|left| *left > 0.0);
+        resume::note(id, (pos_f * 1000.0) as u64);
+        drop(p);
+
+        meters::update(&inst);
+    }
+}
+
+        // A file that would not decode at all is a failed start: retried, then
+        // skipped. One that failed part way still advances, but as `errored`.
+        let errored = !interrupted && decode_error.is_some();
+        if let Some(error) = decode_error.filter(|_| !interrupted) {
+            if frames_written == start_frames {
+                if !start_failed(&playout, &mut failing, started, error).await {
+                    retry_pause(&mut stdin, &mut interval, &silence).await?;
+                }
+                continue;
+            }
+            tracing::warn!("playout failed part way: {} - {} ({}): {}", artist, title, path, error);
+            tokio::spawn(failures::record(path.clone(), title.clone(), error));
+        } else if !interrupted {
+            tokio::spawn(failures::clear_run(path.clone()));
+        }
+        failing = None;
+
+        // If we broke out because the operator advanced the queue, kill ffmpeg
+        // so the audio actually stops. Otherwise the child would keep decoding
+        // in the background until it reaches EOF.
+        if interrupted {
+            decoder.stop();
+            let _ = decoder_exit.await;
+            tracing::info!("playout stop: {} - {}", artist, title);
+        } else {
+            tracing::info!("playout end: {} - {}", artist, title);
+        }
+        let how = match (interrupted, errored) {
+            (true, _) if fade_start.is_some() => "faded",
+            (true, _) => "stopped",
+            (false, true) => "errored",
+            (false, false) => "ended",
+        };
+        eventlog::record(
+            "track_ended",
+            format!("{artist} - {title} ({how})"),
+            json!({"item_id": id, "how": how, "played_s": frames_written / SR as u64}),
+        );
+
+        // Advance the queue if the currently playing id still matches log[0].
+        let mut snapshot_to_persist: Option<Vec<LogItem>> = None;
+        {
+            let mut p = playout.write().await;
+            if !p.log.is_empty() && p.log[0].id == id {
+                let mut finished = p.log.remove(0);
+                finished.state = if errored { "errored" } else { "played" }.into();
+                remember_recent(&mut p, finished);
+                normalize_queue_states(&mut p.log);
+                bump_queue_rev(&mut p);
+
+                if let Some(first) = p.log.get(0) {
+                    let (t, a, d) = (
+                        first.title.clone(),
+                        first.artist.clone(),
+                        ms_to_sec(first.length_ms()),
+                    );
+                    p.now.title = t;
+                    p.now.art = None;
+                    p.now.intro = None;
+                    p.now.intro_remaining_sec = None;
+                    p.now.artist = a;
+                    p.now.dur = d;
+                    p.now.pos = 0;
+                    p.now.pos_f = 0.0;
+                    p.track_started_at = Some(std::time::Instant::now());
+                    meters::reset();
+                } else {
+                    p.now.title.clear();
+                    p.now.art = None;
+                    p.now.intro = None;
+                    p.now.intro_remaining_sec = None;
+                    p.now.artist.clear();
+                    p.now.dur = 0;
+                    p.now.pos = 0;
+                    p.now.pos_f = 0.0;
+                    p.track_started_at = None;
+                    meters::reset();
+                }
+
+                // Top-up if configured and queue is getting low.
+                let (cfg, active_rule) = topup.lock().await.for_time(unix_ms_now());
+                let attempt = topup_try(&mut p.log, &cfg).await;
+                {
+                    let mut s = topup_stats.lock().await;
+                    s.active_rule = active_rule;
+                    s.last_scan_ms = Some(std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_millis() as u64);
+                    s.last_dir = Some(cfg.dir.clone());
+                    s.last_files_found = Some(attempt.files_found);
+                    s.last_appended = Some(attempt.appended);
+                    s.last_error = attempt.error.clone();
+                    if attempt.scanned {
+                        topuplog::record("track_end", &cfg.dir, s.active_rule.clone(), &attempt);
+                        s.record_sources(attempt.sources);
+                    }
+                }
+
+                snapshot_to_persist = Some(p.log.clone());
+            }
+        }
+        if let Some(log) = snapshot_to_persist {
+            persist_queue(log).await;
+        }
+
+        // If the queue is empty after advancing, continue producing silence.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_minutes_and_seconds() {
+        assert_eq!(parse_dur_seconds("0:00"), Some(0));
+        assert_eq!(parse_dur_seconds("3:25"), Some(205));
+        assert_eq!(parse_dur_seconds(" 3:05 "), Some(185));
+        // Older clients send long items as minutes.
+        assert_eq!(parse_dur_seconds("75:00"), Some(4500));
+    }
+
+    #[test]
+    fn parses_hours() {
+        assert_eq!(parse_dur_seconds("1:00:00"), Some(3600));
+        assert_eq!(parse_dur_seconds("1:02:30"), Some(3750));
+        assert_eq!(parse_dur_seconds("10:00:00"), Some(36_000));
+        assert_eq!(parse_dur_seconds("123:04:05"), Some(123 * 3600 + 4 * 60 + 5));
+    }
+
+    #[test]
+    fn refuses_malformed() {
+        for bad in ["", " ", "1:75", "1:60", "1:60:00", "1:00:60", "5", ":30", "1:", "a:bc", "1:2:3:4", "-1:00"] {
+            assert_eq!(parse_dur_seconds(bad), None, "{bad:?}");
+        }
+        assert_eq!(parse_dur_seconds("4294967295:00"), None);
+        assert_eq!(parse_dur_seconds("1193047:00:00"), None);
+        assert_eq!(parse_dur_to_sec("1:75"), 0);
+    }
+
+    #[test]
+    fn formats_minutes_then_hours() {
+        assert_eq!(fmt_dur_mmss(0), "0:00");
+        assert_eq!(fmt_dur_mmss(205), "3:25");
+        assert_eq!(fmt_dur_mmss(3599), "59:59");
+        assert_eq!(fmt_dur_mmss(3600), "1:00:00");
+        assert_eq!(fmt_dur_mmss(3750), "1:02:30");
+        assert_eq!(fmt_dur_mmss(36_000), "10:00:00");
+        assert_eq!(fmt_dur_ms(1_499), "0:01");
+        assert_eq!(fmt_dur_ms(1_500), "0:02");
+    }
+
+    #[test]
+    fn format_round_trips() {
+        for s in [0, 1, 59, 60, 205, 3599, 3600, 3601, 3750, 35_999, 36_000, 86_399, 360_000, 1_000_000] {
+            assert_eq!(parse_dur_seconds(&fmt_dur_mmss(s)), Some(s), "{s}");
+        }
+    }
+
+    const T0: u64 = 1_800_000_000_000;
+
+    /// A cut (no overlap) item `secs` long.
+    fn item(secs: u64, hard_start_ms: Option<u64>) -> LogItem {
+        let mut it = LogItem {
+            id: Uuid::new_v4(),
+            tag: "MUS".into(),
+            time: String::new(),
+            title: String::new(),
+            artist: String::new(),
+            state: "queued".into(),
+            dur: String::new(),
+            dur_ms: 0,
+            cart: String::new(),
+            locked: hard_start_ms.is_some(),
+            start_ms: None,
+            transition: Some(Transition { segue: Some(crate::transitions::Segue::Cut), ..Default::default() }),
+            hard_start_ms,
+        };
+        it.set_length_ms(secs * 1000);
+        it
+    }
+
+    fn playing_for(secs: f64) -> NowPlaying {
+        NowPlaying {
+            title: String::new(),
+            artist: String::new(),
+            dur: 0,
+            pos: secs as u32,
+            pos_f: secs,
+            art: None,
+            intro: None,
+            intro_remaining_sec: None,
+        }
+    }
+
+    fn starts(log: &[LogItem]) -> Vec<u64> {
+        log.iter().map(|it| (it.start_ms.unwrap() - T0) / 1000).collect()
+    }
+
+    #[test]
+    fn estimates_follow_the_playing_position() {
+        let mut log = vec![item(180, None), item(200, None), item(0, None), item(60, None)];
+        estimate_start_times(&mut log, &playing_for(30.0), T0 + 30_000);
+        assert_eq!(starts(&log), [0, 180, 380, 380]);
+    }
+
+    #[test]
+    fn hard_event_waits_for_its_time() {
+        // Queue runs out at 6:20; the event is due at 10:00.
+        let at = T0 + 600_000;
+        let mut log = vec![item(180, None), item(200, None), item(30, Some(at)), item(240, None)];
+        estimate_start_times(&mut log, &playing_for(0.0), T0);
+        assert_eq!(starts(&log), [0, 180, 600, 630]);
+    }
+
+    #[test]
+    fn hard_event_cuts_the_item_before_it() {
+        // The second item would run to 7:00; the event fades it at 5:00.
+        let at = T0 + 300_000;
+        let mut log = vec![item(180, None), item(240, None), item(30, Some(at)), item(240, None), item(60, None)];
+        estimate_start_times(&mut log, &playing_for(0.0), T0);
+        assert_eq!(starts(&log), [0, 180, 300, 330, 570]);
+    }
+
+    #[test]
+    fn playing_hard_event_keeps_its_position() {
+        // Started two seconds early (the fade), it is not pushed back to its time.
+        let at = T0 + 2_000;
+        let mut log = vec![item(30, Some(at)), item(60, None)];
+        estimate_start_times(&mut log, &playing_for(10.0), T0 + 10_000);
+        assert_eq!(starts(&log), [0, 30]);
+    }
+}
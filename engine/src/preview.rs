@@ -0,0 +1,297 @@
+// --- Schedule preview -------------------------------------------------------------
+//
+// `GET /api/v1/schedule/preview?minutes=60` answers "what will air tonight?"
+// before the operator walks out: the queue as it stands, scheduled events
+// slotted in the way `events.rs` will insert them, scheduled actions, and
+// top-up for whatever time the queue does not cover.
+//
+// Top-up picks at random when it runs, so its share is shown as blocks
+// ("top-up from /data/chill, 23:12-06:00") rather than invented tracks. The
+// blocks follow top-up dayparts and `topup_enable`/`topup_disable` actions,
+// and a stretch with nothing to play is reported as a gap plus a warning.
+//
+// This is a simulation from the same inputs the engine uses; queue edits,
+// unknown durations ("0:00") and the clock scheduler (which only generates an
+// hour shortly before it starts) can all make the real schedule differ.
+
+use std::collections::{HashMap, VecDeque};
+
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::import::{self, ImportEntry};
+use crate::schedule::Action;
+use crate::{
+    estimate_start_times, events, fmt_local_hhmmss, parse_dur_to_sec, schedule, unix_ms_now, AppState, TopUpFilters,
+};
+
+/// Longest window a preview covers (a full day).
+const MAX_MINUTES: u64 = 24 * 60;
+
+#[derive(Deserialize)]
+pub(crate) struct PreviewQuery {
+    minutes: Option<u64>,
+}
+
+#[derive(Serialize, Default)]
+pub(crate) struct PreviewItem {
+    pub(crate) start_ms: u64,
+    pub(crate) end_ms: u64,
+    pub(crate) time: String,
+    /// "queue", "event", "topup" or "gap" (and "clock" in a dry run).
+    pub(crate) source: &'static str,
+    pub(crate) tag: String,
+    pub(crate) title: String,
+    pub(crate) artist: String,
+    pub(crate) dur: String,
+    pub(crate) cart: String,
+    /// Cut short by a hard event.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) faded: bool,
+    /// Top-up blocks: the directory and daypart that will be used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) dir: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) daypart: Option<String>,
+    /// Dry run: picked by top-up or a clock although it breaks the rotation rules.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) breaks_rules: bool,
+}
+
+#[derive(Serialize)]
+pub(crate) struct PreviewAction {
+    pub(crate) at_ms: u64,
+    pub(crate) time: String,
+    pub(crate) name: String,
+    pub(crate) action: Action,
+}
+
+/// An event occurrence with its cart resolved.
+pub(crate) struct EventSlot {
+    pub(crate) at_ms: u64,
+    pub(crate) hard: bool,
+    pub(crate) item: PreviewItem,
+}
+
+/// Event occurrences in `[now_ms, until_ms)`, in time order. Events whose
+/// cart cannot be resolved are left out with a warning.
+pub(crate) async fn upcoming_events(now_ms: u64, until_ms: u64, warnings: &mut Vec<String>) -> VecDeque<EventSlot> {
+    let mut events: VecDeque<EventSlot> = VecDeque::new();
+    match events::upcoming(now_ms, until_ms).await {
+        Ok(list) => {
+            for ev in list {
+                let entry = ImportEntry { reference: ev.cart.clone(), ..Default::default() };
+                match import::resolve_entry(&entry, None).await {
+                    Ok(it) => events.push_back(EventSlot {
+                        at_ms: ev.at_ms,
+                        hard: ev.hard,
+                        item: PreviewItem {
+                            source: "event",
+                            tag: "EVT".into(),
+                            title: if it.title.is_empty() { ev.name.clone() } else { it.title },
+                            artist: it.artist,
+                            dur: it.dur,
+                            cart: it.cart,
+                            ..Default::default()
+                        },
+                    }),
+                    Err(e) => warnings.push(format!(
+                        "event {} at {}: cart {}: {e}",
+                        ev.name,
+                        fmt_local_hhmmss(ev.at_ms),
+                        ev.cart
+                    )),
+                }
+            }
+        }
+        Err(e) => warnings.push(format!("scheduled events unavailable: {e}")),
+    }
+    events
+}
+
+/// Scheduled action occurrences in `[now_ms, until_ms)`, in time order.
+pub(crate) async fn upcoming_actions(
+    now_ms: u64,
+    until_ms: u64,
+    warnings: &mut Vec<String>,
+) -> VecDeque<(u64, String, Action)> {
+    match schedule::upcoming(now_ms, until_ms).await {
+        Ok(v) => v.into(),
+        Err(e) => {
+            warnings.push(format!("scheduled actions unavailable: {e}"));
+            VecDeque::new()
+        }
+    }
+}
+
+/// Count playable files (after the path filters) per top-up directory, once per preview.
+async fn topup_files(
+    cache: &mut HashMap<String, Result<usize, String>>,
+    dir: &str,
+    filters: &TopUpFilters,
+) -> Result<usize, String> {
+    if let Some(r) = cache.get(dir) {
+        return r.clone();
+    }
+    let d = dir.to_string();
+    let r = match tokio::task::spawn_blocking(move || crate::topup_scan(&d)).await {
+        Ok(Ok(files)) => match files.iter().filter(|f| filters.keeps_path(dir, f)).count() {
+            0 => Err("no eligible audio files found".to_string()),
+            n => Ok(n),
+        },
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    cache.insert(dir.to_string(), r.clone());
+    r
+}
+
+pub(crate) async fn api_schedule_preview(
+    State(state): State<AppState>,
+    Query(q): Query<PreviewQuery>,
+) -> Json<serde_json::Value> {
+    let minutes = q.minutes.unwrap_or(60).clamp(1, MAX_MINUTES);
+    let now_ms = unix_ms_now();
+    let until_ms = now_ms + minutes * 60_000;
+    let mut warnings: Vec<String> = Vec::new();
+
+    let (mut log, now) = {
+        let p = state.playout.read().await;
+        (p.log.clone(), p.now.clone())
+    };
+    estimate_start_times(&mut log, &now, now_ms);
+    let topup = state.topup.lock().await.clone();
+
+    let mut events = upcoming_events(now_ms, until_ms, &mut warnings).await;
+    let mut actions = upcoming_actions(now_ms, until_ms, &mut warnings).await;
+
+    let mut queue: VecDeque<_> = log.into_iter().filter(|it| it.state != "played").collect();
+    let mut t = queue.front().and_then(|it| it.start_ms).unwrap_or(now_ms);
+    let mut topup_on = topup.enabled;
+    let mut dirs: HashMap<String, Result<usize, String>> = HashMap::new();
+    let mut items: Vec<PreviewItem> = Vec::new();
+    let mut ran: Vec<PreviewAction> = Vec::new();
+
+    while t < until_ms {
+        // Actions due by now change what follows (top-up on/off).
+        while let Some((at_ms, name, action)) = actions.front().filter(|(at, _, _)| *at <= t).cloned() {
+            actions.pop_front();
+            match action {
+                Action::TopupEnable => topup_on = true,
+                Action::TopupDisable => topup_on = false,
+                _ => {}
+            }
+            ran.push(PreviewAction { at_ms, time: fmt_local_hhmmss(at_ms), name, action });
+        }
+
+        // An event whose time has come plays next (a hard one already cut
+        // the item before it short, below).
+        if let Some(ev) = events.front_mut().filter(|e| e.at_ms <= t) {
+            let mut item = std::mem::take(&mut ev.item);
+            item.start_ms = if ev.hard { ev.at_ms.max(t) } else { t };
+            item.end_ms = item.start_ms + parse_dur_to_sec(&item.dur) as u64 * 1000;
+            item.time = fmt_local_hhmmss(item.start_ms);
+            events.pop_front();
+            t = item.end_ms;
+            items.push(item);
+            continue;
+        }
+        let next_event = events.front().map(|e| e.at_ms);
+        let next_hard = events.front().filter(|e| e.hard).map(|e| e.at_ms);
+        let next_action = actions.front().map(|(at, _, _)| *at);
+
+        if let Some(it) = queue.pop_front() {
+            let mut end = t + it.length_ms();
+            let faded = next_hard.is_some_and(|at| at > t && at < end);
+            if faded {
+                end = next_hard.unwrap_or(end);
+            }
+            items.push(PreviewItem {
+                start_ms: t,
+                end_ms: end,
+                time: fmt_local_hhmmss(t),
+                source: "queue",
+                tag: it.tag,
+                title: it.title,
+                artist: it.artist,
+                dur: it.dur,
+                cart: it.cart,
+                faded,
+                ..Default::default()
+            });
+            t = end;
+            continue;
+        }
+
+        // Nothing queued: top-up (or silence) until the next thing that changes it.
+        let mut end = [Some(until_ms), next_event, next_action].into_iter().flatten().min().unwrap_or(until_ms);
+        if topup_on {
+            let (cfg, daypart) = topup.for_time(t);
+            // Dayparts switch on minute boundaries; end the block at the next switch.
+            let mut m = (t / 60_000 + 1) * 60_000;
+            while m < end {
+                if topup.for_time(m).1 != daypart {
+                    end = m;
+                    break;
+                }
+                m += 60_000;
+            }
+            // Weighted folders: the block plays from all of them; one failing
+            // folder is a warning, all failing is a gap.
+            let folders: Vec<String> = if cfg.sources.is_empty() {
+                vec![cfg.dir.clone()]
+            } else {
+                cfg.sources.iter().map(|s| s.dir.clone()).collect()
+            };
+            let mut files = 0;
+            let mut error = None;
+            for dir in &folders {
+                match topup_files(&mut dirs, dir, &cfg.filters).await {
+                    Ok(n) => files += n,
+                    Err(e) => {
+                        warnings.push(format!("top-up from {dir} at {}: {e}", fmt_local_hhmmss(t)));
+                        error.get_or_insert(e);
+                    }
+                }
+            }
+            let (source, title) = match error {
+                Some(e) if files == 0 => ("gap", format!("Top-up failing: {e}")),
+                _ => ("topup", format!("Top-up ({files} files)")),
+            };
+            items.push(PreviewItem {
+                start_ms: t,
+                end_ms: end,
+                time: fmt_local_hhmmss(t),
+                source,
+                title,
+                dir: Some(folders.join(", ")),
+                daypart,
+                ..Default::default()
+            });
+        } else {
+            warnings.push(format!("nothing to play from {} (queue empty, top-up off)", fmt_local_hhmmss(t)));
+            items.push(PreviewItem {
+                start_ms: t,
+                end_ms: end,
+                time: fmt_local_hhmmss(t),
+                source: "gap",
+                title: "Dead air".into(),
+                ..Default::default()
+            });
+        }
+        t = end;
+    }
+
+    Json(json!({
+        "ok": true,
+        "from_ms": now_ms,
+        "until_ms": until_ms,
+        "items": items,
+        "actions": ran,
+        "warnings": warnings,
+    }))
+}
@@ -0,0 +1,739 @@
+// --- Producers -----------------------------------------------------------------------------
+//
+// Remote contributors working from the `/remote` page. The operator adds a
+// producer (name, role) and gets an invite token; the invite link
+// `/remote?token=…` is all the producer needs, and the token is their only
+// credential, so treat it as a password. A new token revokes the old link.
+//
+// Presence comes from the producer's page: while open it heartbeats to
+// `POST /api/v1/public/producers/{token}/heartbeat` with what its WebRTC
+// statistics say about the link (jitter, packet loss), its microphone level
+// and whether the camera is on. A producer is `connected` while heartbeats
+// keep arriving (`STALE_MS`), and figures nobody reported show as "—".
+// The public routes sit under `/api/v1/public/`, which the nginx rule for
+// listener requests already exposes, so producers outside the studio reach them.
+//
+// Each producer also has a channel on the studio's producer mixer: on air,
+// a fader, cough (mute) and cue. Cue is solo-to-cue: putting one producer in
+// cue takes the others out, so the operator hears one contribution at a time.
+// `POST /api/v1/producers/{id}/mix` changes any of these, the status snapshot
+// shows them, and the heartbeat reply tells the producer's page whether it is
+// on air and coughed (the page mutes its own microphone, so a cough holds even
+// before the studio hears the producer). There is no producer audio ingest
+// yet: the mixer is state only, ready for the bus to follow it.
+//
+// Operator and producers can also message each other ("you're on in 30",
+// "mic is low"): a message goes to one producer or to all of them, and a
+// producer's messages go to the operator. The last `KEEP_MESSAGES` are kept in
+// `producer_messages`. Messages are pushed the moment they are sent:
+// - to producers over `GET /api/v1/public/producers/{token}/ws` (their own
+//   conversation and messages to everyone; the socket closes when the token
+//   is revoked),
+// - to the operator over `GET /api/v1/producers/ws` and over the `messages`
+//   data channel of the Listen Live session (listen.rs).
+// Each socket takes `?since=` and first replays what came after it, so a
+// page that reconnects misses nothing. Behind a proxy that drops WebSockets
+// the heartbeat reply still carries the newest message id, and the page
+// fetches `…/messages?since=` when that moves.
+//
+// The registry (rows plus presence) is kept in memory; the status snapshot
+// reads it without touching the database or the playout lock. Rows persist,
+// presence does not: after a restart everyone is offline until they beat.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+use axum::{
+    extract::ws::{Message as WsMessage, WebSocket, WebSocketUpgrade},
+    extract::{Path, Query},
+    response::Response,
+    http::StatusCode,
+    Json,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::broadcast::{self, error::RecvError};
+use uuid::Uuid;
+
+use crate::unix_ms_now;
+
+/// A producer with no heartbeat for this long is shown as disconnected.
+const STALE_MS: u64 = 10_000;
+/// Fader range, dB.
+const FADER_MIN_DB: f32 = -60.0;
+const FADER_MAX_DB: f32 = 10.0;
+/// Messages kept in the history.
+const KEEP_MESSAGES: i64 = 1_000;
+const MAX_MESSAGE_CHARS: usize = 500;
+
+#[derive(Clone, Serialize)]
+pub(crate) struct Producer {
+    id: i64,
+    name: String,
+    role: String,
+    token: String,
+    on_air: bool,
+    fader_db: f32,
+    /// Cough: muted on the mixer (and at the producer's page).
+    muted: bool,
+    /// Soloed to the cue bus.
+    cue: bool,
+    created_ms: u64,
+}
+
+/// What the producer's page last reported.
+#[derive(Clone, Copy, Default)]
+struct Presence {
+    last_seen_ms: u64,
+    jitter_ms: Option<f32>,
+    loss_pct: Option<f32>,
+    level: Option<f32>,
+    cam_on: bool,
+}
+
+/// A producer as the status panel shows them (field names as the web UI reads them).
+#[derive(Clone, Serialize)]
+pub(crate) struct ProducerStatus {
+    id: i64,
+    name: String,
+    role: String,
+    connected: bool,
+    #[serde(rename = "onAir")]
+    on_air: bool,
+    #[serde(rename = "camOn")]
+    cam_on: bool,
+    jitter: String,
+    loss: String,
+    level: f32,
+    fader_db: f32,
+    muted: bool,
+    cue: bool,
+    last_seen_ms: Option<u64>,
+}
+
+/// Id of the newest message.
+static LAST_MESSAGE: AtomicI64 = AtomicI64::new(0);
+
+/// Every message as it is sent, for the push channels.
+fn feed() -> &'static broadcast::Sender<Message> {
+    static FEED: OnceLock<broadcast::Sender<Message>> = OnceLock::new();
+    FEED.get_or_init(|| broadcast::channel(64).0)
+}
+
+static REGISTRY: Mutex<BTreeMap<i64, (Producer, Option<Presence>)>> = Mutex::new(BTreeMap::new());
+
+fn registry() -> std::sync::MutexGuard<'static, BTreeMap<i64, (Producer, Option<Presence>)>> {
+    REGISTRY.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn registry_set(p: Producer) {
+    let mut reg = registry();
+    let presence = reg.remove(&p.id).and_then(|(old, presence)| presence.filter(|_| old.token == p.token));
+    reg.insert(p.id, (p, presence));
+}
+
+/// The panel, ordered as the producers were added.
+pub(crate) fn statuses() -> Vec<ProducerStatus> {
+    let now = unix_ms_now();
+    registry()
+        .values()
+        .map(|(p, presence)| {
+            let pr = presence.unwrap_or_default();
+            ProducerStatus {
+                id: p.id,
+                name: p.name.clone(),
+                role: p.role.clone(),
+                connected: presence.is_some_and(|pr| now.saturating_sub(pr.last_seen_ms) < STALE_MS),
+                on_air: p.on_air,
+                cam_on: pr.cam_on,
+                jitter: pr.jitter_ms.map_or("—".into(), |j| format!("{j:.0}ms")),
+                loss: pr.loss_pct.map_or("—".into(), |l| format!("{l:.1}%")),
+                level: pr.level.unwrap_or(0.0),
+                fader_db: p.fader_db,
+                muted: p.muted,
+                cue: p.cue,
+                last_seen_ms: presence.map(|pr| pr.last_seen_ms),
+            }
+        })
+        .collect()
+}
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS producers (
+            id          INTEGER PRIMARY KEY AUTOINCREMENT,
+            name        TEXT NOT NULL,
+            role        TEXT NOT NULL,
+            token       TEXT NOT NULL UNIQUE,
+            on_air      INTEGER NOT NULL DEFAULT 0,
+            created_ms  INTEGER NOT NULL
+        );
+        "#,
+    )?;
+    crate::db_add_column_if_missing(conn, "producers", "fader_db", "REAL NOT NULL DEFAULT 0")?;
+    crate::db_add_column_if_missing(conn, "producers", "muted", "INTEGER NOT NULL DEFAULT 0")?;
+    crate::db_add_column_if_missing(conn, "producers", "cue", "INTEGER NOT NULL DEFAULT 0")?;
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS producer_messages (
+            id             INTEGER PRIMARY KEY AUTOINCREMENT,
+            producer_id    INTEGER,
+            from_producer  INTEGER NOT NULL,
+            text           TEXT NOT NULL,
+            sent_ms        INTEGER NOT NULL
+        );
+        "#,
+    )
+}
+
+async fn with_db<T: Send + 'static>(
+    f: impl FnOnce(&Connection) -> anyhow::Result<T> + Send + 'static,
+) -> anyhow::Result<T> {
+    crate::db::call(move |conn| {
+        crate::db_init(conn)?;
+        f(conn)
+    })
+    .await?
+}
+
+const COLUMNS: &str = "id, name, role, token, on_air, fader_db, muted, cue, created_ms";
+
+fn producer_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Producer> {
+    Ok(Producer {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        role: row.get(2)?,
+        token: row.get(3)?,
+        on_air: row.get(4)?,
+        fader_db: row.get(5)?,
+        muted: row.get(6)?,
+        cue: row.get(7)?,
+        created_ms: row.get(8)?,
+    })
+}
+
+fn db_get(conn: &Connection, id: i64) -> anyhow::Result<Option<Producer>> {
+    Ok(conn
+        .query_row(&format!("SELECT {COLUMNS} FROM producers WHERE id = ?1"), [id], producer_from_row)
+        .optional()?)
+}
+
+/// Load the registry at startup.
+pub(crate) async fn load() {
+    match with_db(|conn| Ok(conn.query_row("SELECT COALESCE(MAX(id), 0) FROM producer_messages", [], |r| r.get(0))?))
+        .await
+    {
+        Ok(id) => LAST_MESSAGE.store(id, Ordering::Relaxed),
+        Err(e) => tracing::warn!("producers: failed to load messages: {e}"),
+    }
+    let res = with_db(|conn| {
+        let mut stmt = conn.prepare(&format!("SELECT {COLUMNS} FROM producers ORDER BY id"))?;
+        let rows = stmt.query_map([], producer_from_row)?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    })
+    .await;
+    match res {
+        Ok(list) => *registry() = list.into_iter().map(|p| (p.id, (p, None))).collect(),
+        Err(e) => tracing::warn!("producers: failed to load: {e}"),
+    }
+}
+
+type ApiError = (StatusCode, Json<serde_json::Value>);
+
+fn api_error(status: StatusCode, msg: impl Into<String>) -> ApiError {
+    (status, Json(json!({"ok": false, "error": msg.into()})))
+}
+
+fn db_error(e: anyhow::Error) -> ApiError {
+    api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+fn clean_name(name: &str) -> Result<String, ApiError> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > 64 {
+        return Err(api_error(StatusCode::BAD_REQUEST, "name must be 1..64 characters"));
+    }
+    Ok(name.to_string())
+}
+
+fn new_token() -> String {
+    Uuid::new_v4().simple().to_string()
+}
+
+fn by_token(token: &str) -> Result<Producer, ApiError> {
+    registry()
+        .values()
+        .find(|(p, _)| p.token == token)
+        .map(|(p, _)| p.clone())
+        .ok_or_else(|| api_error(StatusCode::NOT_FOUND, "unknown producer token"))
+}
+
+// --- Operator API ----------------------------------------------------------------------
+
+/// `GET /api/v1/producers`: the registry with tokens, and presence.
+pub(crate) async fn api_producers_list() -> Json<serde_json::Value> {
+    let producers: Vec<Producer> = registry().values().map(|(p, _)| p.clone()).collect();
+    Json(json!({"ok": true, "producers": producers, "status": statuses()}))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ProducerCreate {
+    name: String,
+    #[serde(default)]
+    role: Option<String>,
+}
+
+/// `POST /api/v1/producers`: add a producer and issue their invite token.
+pub(crate) async fn api_producer_create(Json(req): Json<ProducerCreate>) -> Result<Json<serde_json::Value>, ApiError> {
+    let name = clean_name(&req.name)?;
+    let role = req.role.map(|r| r.trim().to_string()).filter(|r| !r.is_empty()).unwrap_or_else(|| "Producer".into());
+    let producer = with_db(move |conn| {
+        conn.execute(
+            "INSERT INTO producers (name, role, token, created_ms) VALUES (?1, ?2, ?3, ?4)",
+            params![name, role, new_token(), unix_ms_now()],
+        )?;
+        db_get(conn, conn.last_insert_rowid())
+    })
+    .await
+    .map_err(db_error)?
+    .ok_or_else(|| api_error(StatusCode::INTERNAL_SERVER_ERROR, "producer vanished"))?;
+    tracing::info!(target: "audit", "producers: {} added ({})", producer.id, producer.name);
+    let invite = format!("/remote?token={}", producer.token);
+    registry_set(producer.clone());
+    Ok(Json(json!({"ok": true, "producer": producer, "invite": invite})))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ProducerPatch {
+    name: Option<String>,
+    role: Option<String>,
+    on_air: Option<bool>,
+}
+
+/// `PATCH /api/v1/producers/{id}`
+pub(crate) async fn api_producer_patch(
+    Path(id): Path<i64>,
+    Json(req): Json<ProducerPatch>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let name = req.name.as_deref().map(clean_name).transpose()?;
+    let role = req.role.map(|r| r.trim().to_string()).filter(|r| !r.is_empty());
+    let producer = with_db(move |conn| {
+        let Some(mut p) = db_get(conn, id)? else { return Ok(None) };
+        p.name = name.unwrap_or(p.name);
+        p.role = role.unwrap_or(p.role);
+        p.on_air = req.on_air.unwrap_or(p.on_air);
+        conn.execute(
+            "UPDATE producers SET name = ?2, role = ?3, on_air = ?4 WHERE id = ?1",
+            params![id, p.name, p.role, p.on_air],
+        )?;
+        Ok(Some(p))
+    })
+    .await
+    .map_err(db_error)?
+    .ok_or_else(|| api_error(StatusCode::NOT_FOUND, format!("no producer {id}")))?;
+    registry_set(producer.clone());
+    Ok(Json(json!({"ok": true, "producer": producer})))
+}
+
+/// `DELETE /api/v1/producers/{id}`
+pub(crate) async fn api_producer_delete(Path(id): Path<i64>) -> Result<Json<serde_json::Value>, ApiError> {
+    let n = with_db(move |conn| Ok(conn.execute("DELETE FROM producers WHERE id = ?1", [id])?))
+        .await
+        .map_err(db_error)?;
+    if n == 0 {
+        return Err(api_error(StatusCode::NOT_FOUND, format!("no producer {id}")));
+    }
+    registry().remove(&id);
+    tracing::info!(target: "audit", "producers: {id} removed");
+    Ok(Json(json!({"ok": true})))
+}
+
+/// `POST /api/v1/producers/{id}/token`: issue a new invite token; the old
+/// link stops working and the producer drops offline.
+pub(crate) async fn api_producer_token(Path(id): Path<i64>) -> Result<Json<serde_json::Value>, ApiError> {
+    let producer = with_db(move |conn| {
+        conn.execute("UPDATE producers SET token = ?2 WHERE id = ?1", params![id, new_token()])?;
+        db_get(conn, id)
+    })
+    .await
+    .map_err(db_error)?
+    .ok_or_else(|| api_error(StatusCode::NOT_FOUND, format!("no producer {id}")))?;
+    tracing::info!(target: "audit", "producers: {id} token renewed");
+    let invite = format!("/remote?token={}", producer.token);
+    registry_set(producer.clone());
+    Ok(Json(json!({"ok": true, "producer": producer, "invite": invite})))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ProducerMix {
+    on_air: Option<bool>,
+    fader_db: Option<f32>,
+    muted: Option<bool>,
+    cue: Option<bool>,
+}
+
+/// `POST /api/v1/producers/{id}/mix`: on air, fader, cough and cue. Fields
+/// left out are unchanged; cueing a producer takes everyone else out of cue.
+pub(crate) async fn api_producer_mix(
+    Path(id): Path<i64>,
+    Json(req): Json<ProducerMix>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    if let Some(db) = req.fader_db {
+        if !(FADER_MIN_DB..=FADER_MAX_DB).contains(&db) {
+            return Err(api_error(
+                StatusCode::BAD_REQUEST,
+                format!("fader_db must be {FADER_MIN_DB}..{FADER_MAX_DB}"),
+            ));
+        }
+    }
+    let cue = req.cue;
+    let producer = with_db(move |conn| {
+        let Some(mut p) = db_get(conn, id)? else { return Ok(None) };
+        p.on_air = req.on_air.unwrap_or(p.on_air);
+        p.fader_db = req.fader_db.unwrap_or(p.fader_db);
+        p.muted = req.muted.unwrap_or(p.muted);
+        p.cue = req.cue.unwrap_or(p.cue);
+        let tx = conn.unchecked_transaction()?;
+        if p.cue {
+            tx.execute("UPDATE producers SET cue = 0 WHERE id != ?1", [id])?;
+        }
+        tx.execute(
+            "UPDATE producers SET on_air = ?2, fader_db = ?3, muted = ?4, cue = ?5 WHERE id = ?1",
+            params![id, p.on_air, p.fader_db, p.muted, p.cue],
+        )?;
+        tx.commit()?;
+        Ok(Some(p))
+    })
+    .await
+    .map_err(db_error)?
+    .ok_or_else(|| api_error(StatusCode::NOT_FOUND, format!("no producer {id}")))?;
+    tracing::info!(
+        target: "audit",
+        "producers: {id} mix: on_air={} fader={:.1}dB muted={} cue={}",
+        producer.on_air,
+        producer.fader_db,
+        producer.muted,
+        producer.cue
+    );
+    if cue == Some(true) {
+        for (p, _) in registry().values_mut() {
+            p.cue = false;
+        }
+    }
+    registry_set(producer.clone());
+    Ok(Json(json!({"ok": true, "producer": producer})))
+}
+
+// --- Producer API (public, by token) ---------------------------------------------------
+
+/// `GET /api/v1/public/producers/{token}`: who the token belongs to.
+pub(crate) async fn api_producer_me(Path(token): Path<String>) -> Result<Json<serde_json::Value>, ApiError> {
+    let p = by_token(&token)?;
+    Ok(Json(json!({"ok": true, "id": p.id, "name": p.name, "role": p.role, "on_air": p.on_air, "muted": p.muted})))
+}
+
+#[derive(Deserialize, Default)]
+pub(crate) struct Heartbeat {
+    #[serde(default)]
+    jitter_ms: Option<f32>,
+    #[serde(default)]
+    loss_pct: Option<f32>,
+    /// Microphone level, 0..1.
+    #[serde(default)]
+    level: Option<f32>,
+    #[serde(default)]
+    cam_on: bool,
+}
+
+/// `POST /api/v1/public/producers/{token}/heartbeat`: the producer is here,
+/// with their link figures. The reply says whether they are on air or coughed,
+/// and the newest message id.
+pub(crate) async fn api_producer_heartbeat(
+    Path(token): Path<String>,
+    body: Option<Json<Heartbeat>>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let hb = body.map(|Json(hb)| hb).unwrap_or_default();
+    let fin = |v: Option<f32>, max: f32| v.filter(|v| v.is_finite()).map(|v| v.clamp(0.0, max));
+    let mut reg = registry();
+    let (p, presence) = reg
+        .values_mut()
+        .find(|(p, _)| p.token == token)
+        .ok_or_else(|| api_error(StatusCode::NOT_FOUND, "unknown producer token"))?;
+    if presence.is_none_or(|pr| unix_ms_now().saturating_sub(pr.last_seen_ms) >= STALE_MS) {
+        tracing::info!("producers: {} ({}) connected", p.id, p.name);
+    }
+    *presence = Some(Presence {
+        last_seen_ms: unix_ms_now(),
+        jitter_ms: fin(hb.jitter_ms, 10_000.0),
+        loss_pct: fin(hb.loss_pct, 100.0),
+        level: fin(hb.level, 1.0),
+        cam_on: hb.cam_on,
+    });
+    Ok(Json(json!({
+        "ok": true,
+        "on_air": p.on_air,
+        "muted": p.muted,
+        "interval_ms": STALE_MS / 4,
+        "last_message_id": LAST_MESSAGE.load(Ordering::Relaxed),
+    })))
+}
+
+/// `POST /api/v1/public/producers/{token}/leave`: the page is closing.
+pub(crate) async fn api_producer_leave(Path(token): Path<String>) -> Result<Json<serde_json::Value>, ApiError> {
+    let mut reg = registry();
+    let (p, presence) = reg
+        .values_mut()
+        .find(|(p, _)| p.token == token)
+        .ok_or_else(|| api_error(StatusCode::NOT_FOUND, "unknown producer token"))?;
+    if presence.take().is_some() {
+        tracing::info!("producers: {} ({}) left", p.id, p.name);
+    }
+    Ok(Json(json!({"ok": true})))
+}
+
+// --- Messages --------------------------------------------------------------------------
+
+#[derive(Clone, Serialize)]
+pub(crate) struct Message {
+    id: i64,
+    /// The producer written to or by; none for a message to everyone.
+    producer_id: Option<i64>,
+    from_producer: bool,
+    text: String,
+    sent_ms: u64,
+}
+
+fn message_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Message> {
+    Ok(Message {
+        id: row.get(0)?,
+        producer_id: row.get(1)?,
+        from_producer: row.get(2)?,
+        text: row.get(3)?,
+        sent_ms: row.get(4)?,
+    })
+}
+
+fn clean_text(text: &str) -> Result<String, ApiError> {
+    let text = text.trim();
+    if text.is_empty() || text.chars().count() > MAX_MESSAGE_CHARS {
+        return Err(api_error(StatusCode::BAD_REQUEST, format!("text must be 1..{MAX_MESSAGE_CHARS} characters")));
+    }
+    Ok(text.to_string())
+}
+
+async fn send_message(producer_id: Option<i64>, from_producer: bool, text: String) -> Result<Message, ApiError> {
+    let msg = with_db(move |conn| {
+        conn.execute(
+            "INSERT INTO producer_messages (producer_id, from_producer, text, sent_ms) VALUES (?1, ?2, ?3, ?4)",
+            params![producer_id, from_producer, text, unix_ms_now()],
+        )?;
+        let id = conn.last_insert_rowid();
+        conn.execute("DELETE FROM producer_messages WHERE id <= ?1", [id - KEEP_MESSAGES])?;
+        Ok(conn.query_row(
+            "SELECT id, producer_id, from_producer, text, sent_ms FROM producer_messages WHERE id = ?1",
+            [id],
+            message_from_row,
+        )?)
+    })
+    .await
+    .map_err(db_error)?;
+    LAST_MESSAGE.fetch_max(msg.id, Ordering::Relaxed);
+    // No receivers is fine: nobody is connected.
+    let _ = feed().send(msg.clone());
+    Ok(msg)
+}
+
+/// Messages after `since`, oldest first; for one producer, theirs and those to everyone.
+async fn messages(producer_id: Option<i64>, since: i64, limit: u32) -> Result<Vec<Message>, ApiError> {
+    with_db(move |conn| {
+        let mut stmt = conn.prepare(
+            "SELECT id, producer_id, from_producer, text, sent_ms FROM (
+                 SELECT * FROM producer_messages
+                 WHERE id > ?1 AND (?2 IS NULL OR producer_id = ?2 OR producer_id IS NULL)
+                 ORDER BY id DESC LIMIT ?3
+             ) ORDER BY id",
+        )?;
+        let rows = stmt.query_map(params![since, producer_id, limit], message_from_row)?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    })
+    .await
+    .map_err(db_error)
+}
+
+#[derive(Deserialize)]
+pub(crate) struct MessagesQuery {
+    #[serde(default)]
+    since: i64,
+    #[serde(default)]
+    limit: Option<u32>,
+}
+
+/// `GET /api/v1/producers/messages?since=&limit=100`: every conversation.
+pub(crate) async fn api_messages_list(Query(q): Query<MessagesQuery>) -> Result<Json<serde_json::Value>, ApiError> {
+    let list = messages(None, q.since, q.limit.unwrap_or(100).clamp(1, 1000)).await?;
+    Ok(Json(json!({"ok": true, "messages": list, "last_message_id": LAST_MESSAGE.load(Ordering::Relaxed)})))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct MessageSend {
+    /// None: to every producer.
+    #[serde(default)]
+    producer_id: Option<i64>,
+    text: String,
+}
+
+/// `POST /api/v1/producers/messages`: from the operator to one producer or all.
+pub(crate) async fn api_message_send(Json(req): Json<MessageSend>) -> Result<Json<serde_json::Value>, ApiError> {
+    let text = clean_text(&req.text)?;
+    if let Some(id) = req.producer_id.filter(|id| !registry().contains_key(id)) {
+        return Err(api_error(StatusCode::NOT_FOUND, format!("no producer {id}")));
+    }
+    let msg = send_message(req.producer_id, false, text).await?;
+    Ok(Json(json!({"ok": true, "message": msg})))
+}
+
+/// `GET /api/v1/public/producers/{token}/messages?since=`: the producer's conversation.
+pub(crate) async fn api_producer_messages(
+    Path(token): Path<String>,
+    Query(q): Query<MessagesQuery>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let p = by_token(&token)?;
+    let list = messages(Some(p.id), q.since, q.limit.unwrap_or(100).clamp(1, 1000)).await?;
+    Ok(Json(json!({"ok": true, "messages": list})))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ProducerMessage {
+    text: String,
+}
+
+/// `POST /api/v1/public/producers/{token}/messages`: from the producer to the operator.
+pub(crate) async fn api_producer_message_send(
+    Path(token): Path<String>,
+    Json(req): Json<ProducerMessage>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let p = by_token(&token)?;
+    let msg = send_message(Some(p.id), true, clean_text(&req.text)?).await?;
+    Ok(Json(json!({"ok": true, "message": msg})))
+}
+
+// --- Push ------------------------------------------------------------------------------
+
+/// Messages as one client sees them: after `since`, then each new one. A
+/// receiver that falls behind gets the missed ones from the database.
+pub(crate) struct MessageFeed {
+    rx: broadcast::Receiver<Message>,
+    /// Only this producer's conversation (and messages to everyone).
+    producer_id: Option<i64>,
+    /// Newest id handed out.
+    last: i64,
+    /// The database has messages the channel did not deliver.
+    behind: bool,
+    /// Read from the database, not yet handed out.
+    backlog: VecDeque<Message>,
+}
+
+impl MessageFeed {
+    /// Subscribed before the history is read, so nothing sent in between is lost.
+    pub(crate) fn open(producer_id: Option<i64>, since: i64) -> MessageFeed {
+        MessageFeed { rx: feed().subscribe(), producer_id, last: since, behind: true, backlog: VecDeque::new() }
+    }
+
+    fn wanted(&self, m: &Message) -> bool {
+        m.id > self.last && self.producer_id.is_none_or(|id| m.producer_id.is_none_or(|p| p == id))
+    }
+
+    /// The next message; `None` once the feed is gone. Cancel-safe.
+    pub(crate) async fn next(&mut self) -> Option<Message> {
+        loop {
+            if self.behind {
+                match messages(self.producer_id, self.last, 1000).await {
+                    Ok(list) => self.backlog.extend(list),
+                    Err(_) => tracing::warn!("producers: failed to read messages for a push channel"),
+                }
+                self.behind = false;
+            }
+            if let Some(m) = self.backlog.pop_front() {
+                if self.wanted(&m) {
+                    self.last = m.id;
+                    return Some(m);
+                }
+                continue;
+            }
+            match self.rx.recv().await {
+                Ok(m) if self.wanted(&m) => {
+                    self.last = m.id;
+                    return Some(m);
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(_)) => self.behind = true,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+/// Id of the newest message, for a push channel that starts from now.
+pub(crate) fn last_message_id() -> i64 {
+    LAST_MESSAGE.load(Ordering::Relaxed)
+}
+
+#[derive(Deserialize)]
+pub(crate) struct PushQuery {
+    #[serde(default)]
+    since: i64,
+}
+
+/// `GET /api/v1/producers/ws?since=`: every message, pushed.
+pub(crate) async fn api_messages_ws(Query(q): Query<PushQuery>, ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(move |socket| push_session(socket, None, q.since))
+}
+
+/// `GET /api/v1/public/producers/{token}/ws?since=`: the producer's conversation, pushed.
+pub(crate) async fn api_producer_ws(
+    Path(token): Path<String>,
+    Query(q): Query<PushQuery>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, ApiError> {
+    by_token(&token)?;
+    Ok(ws.on_upgrade(move |socket| push_session(socket, Some(token), q.since)))
+}
+
+async fn push_session(mut socket: WebSocket, token: Option<String>, since: i64) {
+    let producer_id = match &token {
+        Some(t) => match by_token(t) {
+            Ok(p) => Some(p.id),
+            Err(_) => return,
+        },
+        None => None,
+    };
+    let mut feed = MessageFeed::open(producer_id, since);
+    // A revoked token ends the session even when nothing is being said.
+    let mut check = tokio::time::interval(std::time::Duration::from_millis(STALE_MS));
+    loop {
+        let msg = tokio::select! {
+            m = feed.next() => match m {
+                Some(m) => m,
+                None => break,
+            },
+            incoming = socket.recv() => match incoming {
+                Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+            _ = check.tick() => {
+                if token.as_deref().is_some_and(|t| by_token(t).is_err()) {
+                    break;
+                }
+                continue;
+            }
+        };
+        let frame = json!({"type": "message", "message": msg}).to_string();
+        if socket.send(WsMessage::Text(frame)).await.is_err() {
+            break;
+        }
+    }
+    let _ = socket.close().await;
+}
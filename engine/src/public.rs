@@ -0,0 +1,372 @@
+// --- Public now-playing / recently-played feeds --------------------------------
+//
+// Station websites want "now playing" and "recently played" widgets without
+// being handed the operator API. `/api/v1/public/*` is a read-only, trimmed
+// view meant to be exposed to the internet on its own (e.g. an nginx
+// `location ^~ /api/v1/public/` without the auth the rest of `/api/` gets):
+// - only music is shown: items whose tag is not flagged `music` (tags.rs;
+//   `music_tags` in the config is that list) are hidden, i.e. spots, IDs,
+//   events and announcements; while one airs `now` is null,
+// - at most `upcoming_max` upcoming music items are shown (`next`,
+//   `/api/v1/public/upcoming`, the AzuraCast API's `playing_next`); 0 shows
+//   none, so a station can keep its running order to itself. Like the tag
+//   filter, this is enforced here rather than left to the web page,
+// - carts, file paths, queue ids and outcomes never appear,
+// - responses carry `Cache-Control` so a CDN or the browser can absorb
+//   traffic spikes, and `Access-Control-Allow-Origin: *` so a page on another
+//   domain can fetch them directly.
+//
+// The settings live under `/api/v1/public-feed/config`, outside the public
+// prefix, so exposing the feeds never exposes their configuration.
+//
+// `/api/v1/status` applies the same rules to unauthenticated callers. The
+// engine has no logins of its own, so the reverse proxy says which requests
+// those are: it sets `X-StudioCommand-Public` on the route it exposes without
+// auth (a proxy-set header replaces whatever the client sent). Such callers
+// get `now` and the queue only, cut down to the music on air and `upcoming`,
+// and each item to what the feeds show (`PublicQueueItem`).
+
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use serde_json::value::RawValue;
+
+use crate::{
+    db_add_column_if_missing, estimate_start_times, parse_dur_to_sec, tags, unix_ms_now, AppState, LogItem,
+};
+
+/// Longest recently-played list a caller can ask for.
+const MAX_HISTORY: u32 = 50;
+/// Highest `upcoming_max`.
+const MAX_UPCOMING: u32 = 20;
+/// Set by the reverse proxy on requests from callers that did not log in.
+const PUBLIC_HEADER: &str = "x-studiocommand-public";
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct PublicFeedConfig {
+    /// Tags shown in the feeds; everything else is treated as non-music.
+    /// The tags flagged `music`: saving the config sets the flags.
+    music_tags: Vec<String>,
+    /// Upcoming music items shown; 0 hides what comes next.
+    #[serde(default = "default_upcoming_max")]
+    upcoming_max: u32,
+}
+
+fn default_upcoming_max() -> u32 {
+    1
+}
+
+fn default_config() -> PublicFeedConfig {
+    PublicFeedConfig { music_tags: vec!["MUS".into()], upcoming_max: default_upcoming_max() }
+}
+
+impl PublicFeedConfig {
+    pub(crate) fn is_music(&self, tag: &str) -> bool {
+        self.music_tags.iter().any(|t| t.eq_ignore_ascii_case(tag))
+    }
+
+    /// The upcoming items the public may see: music after the playing item, at most `upcoming_max`.
+    pub(crate) fn upcoming<'a>(&'a self, log: &'a [LogItem]) -> impl Iterator<Item = &'a LogItem> + 'a {
+        log.iter().skip(1).filter(|it| self.is_music(&it.tag)).take(self.upcoming_max as usize)
+    }
+
+    /// The status API's serialized queue as an unauthenticated caller may see
+    /// it (the music on air, then `upcoming`, trimmed to `PublicQueueItem`),
+    /// and whether music is on air.
+    pub(crate) fn public_log(&self, log: &RawValue) -> Option<(Box<RawValue>, bool)> {
+        let items: Vec<LogItem> = serde_json::from_str(log.get()).ok()?;
+        let on_air = items.first().filter(|it| it.state == "playing" && self.is_music(&it.tag));
+        let visible: Vec<PublicQueueItem> =
+            on_air.into_iter().chain(self.upcoming(&items)).map(PublicQueueItem::from).collect();
+        Some((serde_json::value::to_raw_value(&visible).ok()?, on_air.is_some()))
+    }
+}
+
+/// A queue item on the public side: no cart, id, state or transition.
+#[derive(Serialize)]
+pub(crate) struct PublicQueueItem {
+    title: String,
+    artist: String,
+    /// Seconds.
+    dur: u32,
+    start_ms: Option<u64>,
+}
+
+impl From<&LogItem> for PublicQueueItem {
+    fn from(it: &LogItem) -> Self {
+        PublicQueueItem {
+            title: it.title.clone(),
+            artist: it.artist.clone(),
+            dur: parse_dur_to_sec(&it.dur),
+            start_ms: it.start_ms,
+        }
+    }
+}
+
+/// Whether the proxy marked this request as coming from the public side.
+pub(crate) fn is_public_request(headers: &HeaderMap) -> bool {
+    headers.contains_key(PUBLIC_HEADER)
+}
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS public_feed_config (
+            id          INTEGER PRIMARY KEY CHECK (id = 1),
+            music_tags  TEXT NOT NULL
+        );
+        "#,
+    )
+}
+
+/// Migration 6: how many upcoming items the feeds show.
+pub(crate) fn db_add_upcoming_max(conn: &Connection) -> rusqlite::Result<()> {
+    db_add_column_if_missing(conn, "public_feed_config", "upcoming_max", "INTEGER NOT NULL DEFAULT 1")
+}
+
+fn db_load_config(conn: &Connection) -> anyhow::Result<PublicFeedConfig> {
+    crate::db_init(conn)?;
+    let upcoming_max: Option<i64> = conn
+        .query_row("SELECT upcoming_max FROM public_feed_config WHERE id = 1", [], |row| row.get(0))
+        .optional()?;
+    let mut stmt = conn.prepare("SELECT code FROM tags WHERE music = 1 ORDER BY code")?;
+    let music_tags = stmt.query_map([], |row| row.get(0))?.collect::<rusqlite::Result<Vec<String>>>()?;
+    Ok(PublicFeedConfig {
+        music_tags,
+        upcoming_max: upcoming_max.map(|n| n as u32).unwrap_or_else(default_upcoming_max),
+    })
+}
+
+/// Tags the public side treats as music (also what listeners may request).
+pub(crate) fn db_music_tags(conn: &Connection) -> anyhow::Result<Vec<String>> {
+    Ok(db_load_config(conn)?.music_tags)
+}
+
+pub(crate) async fn load_config() -> PublicFeedConfig {
+    let res = crate::db::call(move |conn| db_load_config(conn)).await;
+    match res {
+        Ok(Ok(cfg)) => cfg,
+        Ok(Err(e)) => {
+            tracing::warn!("public feed: {e}");
+            default_config()
+        }
+        Err(_) => default_config(),
+    }
+}
+
+/// JSON with the cache and CORS headers every public response carries.
+pub(crate) fn public_json(max_age_s: u32, body: serde_json::Value) -> Response {
+    (
+        [
+            (header::CACHE_CONTROL, format!("public, max-age={max_age_s}")),
+            (header::ACCESS_CONTROL_ALLOW_ORIGIN, "*".to_string()),
+        ],
+        Json(body),
+    )
+        .into_response()
+}
+
+#[derive(Serialize)]
+pub(crate) struct PublicTrack {
+    pub(crate) title: String,
+    pub(crate) artist: String,
+    /// Seconds.
+    pub(crate) dur: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) started_ms: Option<u64>,
+}
+
+/// The last `limit` music items played, newest first, with their `play_history` ids.
+pub(crate) fn db_music_history(conn: &Connection, limit: u32) -> anyhow::Result<Vec<(i64, PublicTrack)>> {
+    let cfg = db_load_config(conn)?;
+    // Filtered in SQL so `limit` counts music only; tags are matched
+    // case-insensitively, like `is_music`.
+    let tags = serde_json::to_string(&cfg.music_tags.iter().map(|t| t.to_uppercase()).collect::<Vec<_>>())?;
+    let mut stmt = conn.prepare(
+        "SELECT id, title, artist, dur, started_ms FROM play_history
+         WHERE outcome IS NOT NULL AND UPPER(tag) IN (SELECT value FROM json_each(?1))
+         ORDER BY started_ms DESC, id DESC LIMIT ?2",
+    )?;
+    let rows = stmt.query_map(params![tags, limit], |row| {
+        Ok((
+            row.get(0)?,
+            PublicTrack {
+                title: row.get(1)?,
+                artist: row.get(2)?,
+                dur: parse_dur_to_sec(&row.get::<_, String>(3)?),
+                started_ms: Some(row.get::<_, i64>(4)? as u64),
+            },
+        ))
+    })?;
+    Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+}
+
+// --- HTTP API -------------------------------------------------------------------
+
+/// `GET /api/v1/public/nowplaying`
+pub(crate) async fn api_public_nowplaying(State(state): State<AppState>) -> Response {
+    let cfg = load_config().await;
+    let now_ms = unix_ms_now();
+    let p = state.playout.read().await;
+
+    let on_air = p.log.first().filter(|it| it.state == "playing" && cfg.is_music(&it.tag));
+    let now = on_air.map(|_| {
+        json!({
+            "title": p.now.title,
+            "artist": p.now.artist,
+            "dur": p.now.dur,
+            "pos": p.now.pos,
+            "started_ms": now_ms.saturating_sub((p.now.pos_f * 1000.0) as u64),
+            "art": p.now.art,
+        })
+    });
+    let next = cfg.upcoming(&p.log).next().map(|it| PublicTrack {
+        title: it.title.clone(),
+        artist: it.artist.clone(),
+        dur: parse_dur_to_sec(&it.dur),
+        started_ms: None,
+    });
+
+    public_json(5, json!({"ok": true, "now": now, "next": next, "updated_ms": now_ms}))
+}
+
+/// `GET /api/v1/public/upcoming`: the next music items with their estimated start.
+pub(crate) async fn api_public_upcoming(State(state): State<AppState>) -> Response {
+    let cfg = load_config().await;
+    let now_ms = unix_ms_now();
+    let log = {
+        let p = state.playout.read().await;
+        let mut log = p.log.clone();
+        estimate_start_times(&mut log, &p.now, now_ms);
+        log
+    };
+    let items: Vec<serde_json::Value> = cfg
+        .upcoming(&log)
+        .map(|it| {
+            json!({
+                "title": it.title,
+                "artist": it.artist,
+                "dur": parse_dur_to_sec(&it.dur),
+                "starts_ms": it.start_ms,
+            })
+        })
+        .collect();
+
+    public_json(5, json!({"ok": true, "items": items, "updated_ms": now_ms}))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct PublicHistoryQuery {
+    limit: Option<u32>,
+}
+
+/// `GET /api/v1/public/history?limit=10`, newest first. The item on air is
+/// not included (see `nowplaying`).
+pub(crate) async fn api_public_history(Query(q): Query<PublicHistoryQuery>) -> Result<Response, StatusCode> {
+    let limit = q.limit.unwrap_or(10).clamp(1, MAX_HISTORY);
+    let items = crate::db::call(move |conn| -> anyhow::Result<Vec<PublicTrack>> {
+        Ok(db_music_history(conn, limit)?.into_iter().map(|(_, t)| t).collect())
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|e| {
+        tracing::warn!("public history failed: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(public_json(30, json!({"ok": true, "items": items})))
+}
+
+pub(crate) async fn api_public_feed_config_get() -> Json<PublicFeedConfig> {
+    Json(load_config().await)
+}
+
+pub(crate) async fn api_public_feed_config_set(
+    Json(mut cfg): Json<PublicFeedConfig>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    cfg.music_tags = cfg
+        .music_tags
+        .iter()
+        .filter(|t| !t.trim().is_empty())
+        .map(|t| tags::check(t))
+        .collect::<Result<_, _>>()
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    if cfg.music_tags.is_empty() || cfg.upcoming_max > MAX_UPCOMING {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let music = serde_json::to_string(&cfg.music_tags).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let upcoming_max = cfg.upcoming_max;
+    crate::db::call(move |conn| -> anyhow::Result<()> {
+        crate::db_init(conn)?;
+        // `music_tags` is still written for older builds reading this row.
+        conn.execute(
+            "INSERT INTO public_feed_config (id, music_tags, upcoming_max) VALUES (1, ?1, ?2)
+             ON CONFLICT(id) DO UPDATE SET music_tags=excluded.music_tags, upcoming_max=excluded.upcoming_max",
+            params![music, upcoming_max],
+        )?;
+        conn.execute("UPDATE tags SET music = (code IN (SELECT value FROM json_each(?1)))", params![music])?;
+        Ok(())
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    tags::load().await;
+    Ok(Json(json!({"ok": true, "config": cfg})))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(tag: &str, state: &str, title: &str) -> serde_json::Value {
+        json!({
+            "id": uuid::Uuid::new_v4(),
+            "tag": tag,
+            "time": "12:00:00",
+            "title": title,
+            "artist": "Artist",
+            "state": state,
+            "dur": "3:30",
+            "cart": "/srv/music/secret path.mp3",
+            "locked": true,
+            "start_ms": 1_700_000_000_000u64,
+            "transition": {"overlap_ms": 500},
+        })
+    }
+
+    fn public(cfg: &PublicFeedConfig, log: serde_json::Value) -> (serde_json::Value, bool) {
+        let raw = serde_json::value::to_raw_value(&log).unwrap();
+        let (out, on_air) = cfg.public_log(&raw).expect("queue parses");
+        (serde_json::from_str(out.get()).unwrap(), on_air)
+    }
+
+    #[test]
+    fn public_status_hides_carts_and_ids() {
+        let log = json!([item("MUS", "playing", "On air"), item("MUS", "next", "Next")]);
+        let (items, on_air) = public(&default_config(), log);
+        assert!(on_air);
+        let items = items.as_array().unwrap();
+        assert_eq!(items.len(), 2);
+        for it in items {
+            let keys: Vec<&String> = it.as_object().unwrap().keys().collect();
+            assert_eq!(keys, ["artist", "dur", "start_ms", "title"]);
+        }
+        assert_eq!(items[0]["dur"], 210);
+        let body = serde_json::to_string(&items).unwrap();
+        assert!(!body.contains("cart") && !body.contains("secret path") && !body.contains("\"id\""));
+    }
+
+    #[test]
+    fn public_status_shows_music_only() {
+        let log = json!([item("SPT", "playing", "Spot"), item("ID", "next", "Legal ID"), item("MUS", "queued", "Song")]);
+        let (items, on_air) = public(&default_config(), log);
+        assert!(!on_air);
+        assert_eq!(items, json!([{"title": "Song", "artist": "Artist", "dur": 210, "start_ms": 1_700_000_000_000u64}]));
+    }
+}
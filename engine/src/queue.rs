@@ -0,0 +1,798 @@
+// --- Queue API ------------------------------------------------------------------------------
+//
+// Edits to the main queue and the secondary queues over HTTP: insert, move,
+// reorder, batch, replace, patch, clear, requeue. Edits made against a view
+// of the queue check the revision the client rendered from (409 when the
+// queue moved underneath it); every edit is persisted. The queue rules
+// themselves (locks, markers, advancing) are in playout.rs.
+
+use axum::extract::{Path, State};
+use axum::Json;
+use axum::http::{HeaderMap, StatusCode};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    analysis, bump_queue_rev, fmt_dur_mmss, library, move_crosses_lock, normalize_log_state, parse_dur_seconds,
+    parse_dur_to_sec, paths, persist_aux_queue, persist_queue, remote, reorder_respects_locks, resolve_cart_to_path,
+    tags, title_from_path, AppState, BREAK_MARKER_TAG, LogItem, PlayoutState, Transition,
+};
+
+// Queue mutations that depend on the caller's view of the queue (indices or a
+// full ordering) carry the revision the caller rendered from, either as a
+// `rev` field in the body or as an `If-Match` header. See `check_queue_rev`.
+
+#[derive(serde::Deserialize)]
+pub(crate) struct QueueRemoveReq { index: usize, #[serde(default)] rev: Option<u64> }
+
+#[derive(serde::Deserialize)]
+pub(crate) struct QueueMoveReq { from: usize, to: usize, #[serde(default)] rev: Option<u64> }
+
+#[derive(serde::Deserialize)]
+pub(crate) struct QueueReorderReq { order: Vec<Uuid>, #[serde(default)] rev: Option<u64> }
+
+
+#[derive(serde::Deserialize)]
+pub(crate) struct QueueInsertReq {
+    after: usize,
+    /// Free-form item (legacy mode): inserted as-is, cart is not checked.
+    #[serde(default)]
+    item: Option<QueueInsertItem>,
+    /// Reference mode: a cart number (or absolute path) that must resolve to
+    /// an existing, decodable file. Metadata is filled in from the file.
+    #[serde(default, rename = "ref")]
+    reference: Option<String>,
+    /// Tag for reference-mode inserts (defaults to "MUS").
+    #[serde(default)]
+    tag: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct QueueInsertItem {
+    pub(crate) tag: String,
+    pub(crate) title: String,
+    pub(crate) artist: String,
+    /// "M:SS"; `dur_ms`, when given, wins.
+    #[serde(default)]
+    pub(crate) dur: String,
+    #[serde(default)]
+    pub(crate) dur_ms: Option<u64>,
+    pub(crate) cart: String,
+    #[serde(default)]
+    pub(crate) locked: bool,
+}
+
+impl QueueInsertItem {
+    /// Normalize the tag, refusing one that is not in the tags table (tags.rs).
+    pub(crate) fn check_tag(&mut self) -> Result<(), String> {
+        self.tag = tags::check(&self.tag)?;
+        Ok(())
+    }
+
+    /// Length in milliseconds: `dur_ms`, or `dur`.
+    pub(crate) fn length_ms(&self) -> u64 {
+        self.dur_ms.unwrap_or_else(|| parse_dur_to_sec(&self.dur) as u64 * 1000)
+    }
+
+    pub(crate) fn into_log_item(self, state: &str) -> LogItem {
+        let dur_ms = self.length_ms();
+        let mut item = LogItem {
+            id: Uuid::new_v4(),
+            tag: self.tag,
+            time: "--:--".into(),
+            title: self.title,
+            artist: self.artist,
+            state: state.into(),
+            dur: self.dur,
+            dur_ms: 0,
+            cart: self.cart,
+            locked: self.locked,
+            start_ms: None,
+            transition: None,
+            hard_start_ms: None,
+        };
+        if dur_ms > 0 {
+            item.set_length_ms(dur_ms);
+        }
+        item
+    }
+}
+
+/// Body for `POST /api/v1/queue/batch`.
+///
+/// Multi-item drag/drop used to issue one request per item, which meant a
+/// storm of revisions and, worse, partial-failure states when one request in
+/// the middle was rejected. A batch is applied to a scratch copy of the queue
+/// under a single write lock and only committed (and persisted in one SQLite
+/// transaction) if every operation succeeds.
+#[derive(serde::Deserialize)]
+pub(crate) struct QueueBatchReq {
+    ops: Vec<QueueBatchOp>,
+    #[serde(default)]
+    rev: Option<u64>,
+}
+
+/// Body for `PUT /api/v1/queue`: the complete upcoming list, in order.
+///
+/// The playing item (index 0) is always preserved; `items` replaces
+/// everything after it. Intended for log import tools and "load tomorrow's
+/// log" workflows, where a whole list is built elsewhere and swapped in.
+#[derive(serde::Deserialize)]
+pub(crate) struct QueueReplaceReq {
+    items: Vec<QueueInsertItem>,
+    #[serde(default)]
+    rev: Option<u64>,
+}
+
+/// Body for `PATCH /api/v1/queue/items/{id}`. Omitted fields are left as-is.
+#[derive(serde::Deserialize)]
+pub(crate) struct QueueItemPatch {
+    tag: Option<String>,
+    title: Option<String>,
+    artist: Option<String>,
+    dur: Option<String>,
+    /// Exact length; wins over `dur`.
+    dur_ms: Option<u64>,
+    locked: Option<bool>,
+    /// Replaces the item's transition overrides; `{}` goes back to the defaults.
+    transition: Option<Transition>,
+    #[serde(default)]
+    rev: Option<u64>,
+}
+
+impl QueueItemPatch {
+    /// Validate and apply the patch. Fields are normalized before anything is
+    /// written so a bad field rejects the whole patch.
+    fn apply(self, item: &mut LogItem) -> Result<(), StatusCode> {
+        let tag = match self.tag {
+            Some(t) => Some(tags::check(&t).map_err(|_| StatusCode::BAD_REQUEST)?),
+            None => None,
+        };
+        let dur_ms = match (self.dur_ms, self.dur) {
+            (Some(ms), _) => Some(ms),
+            (None, Some(d)) => Some(parse_dur_seconds(&d).ok_or(StatusCode::BAD_REQUEST)? as u64 * 1000),
+            (None, None) => None,
+        };
+        if let Some(t) = &self.transition {
+            t.validate().map_err(|_| StatusCode::BAD_REQUEST)?;
+        }
+
+        if let Some(t) = tag {
+            item.tag = t;
+        }
+        if let Some(t) = self.title {
+            item.title = t.trim().to_string();
+        }
+        if let Some(a) = self.artist {
+            item.artist = a.trim().to_string();
+        }
+        if let Some(ms) = dur_ms {
+            item.set_length_ms(ms);
+        }
+        if let Some(l) = self.locked {
+            item.locked = l;
+        }
+        if let Some(t) = self.transition {
+            item.transition = Some(t).filter(|t| !t.is_empty());
+        }
+        Ok(())
+    }
+}
+
+/// A single batch operation. Items are addressed by UUID (not index) because
+/// indices shift as earlier operations in the same batch are applied.
+#[derive(serde::Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub(crate) enum QueueBatchOp {
+    /// Insert after `after_id`, or append to the end when omitted.
+    Insert { #[serde(default)] after_id: Option<Uuid>, item: QueueInsertItem },
+    Remove { id: Uuid },
+    /// Move an upcoming item to absolute index `to` (>= 1).
+    Move { id: Uuid, to: usize },
+}
+
+pub(crate) async fn api_queue_remove(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<QueueRemoveReq>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    // Remove an upcoming item from the queue. Index 0 is "playing" and cannot be removed.
+    let mut p = state.playout.write().await;
+    check_queue_rev(&p, &headers, req.rev)?;
+    if req.index == 0 || req.index >= p.log.len() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    p.log.remove(req.index);
+    normalize_log_state(&mut p);
+    bump_queue_rev(&mut p);
+
+    // Persist the updated queue so restarts keep the same order.
+    persist_queue(p.log.clone()).await;
+    Ok(Json(json!({"ok": true, "rev": p.queue_rev})))
+}
+
+pub(crate) async fn api_queue_move(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<QueueMoveReq>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    // Move an upcoming item within the queue. Index 0 is "playing" and stays put.
+    let mut p = state.playout.write().await;
+    check_queue_rev(&p, &headers, req.rev)?;
+    if req.from == 0 || req.to == 0 || req.from >= p.log.len() || req.to >= p.log.len() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if req.from == req.to {
+        return Ok(Json(json!({"ok": true, "rev": p.queue_rev})));
+    }
+    if move_crosses_lock(&p.log, req.from, req.to) {
+        return Err(StatusCode::LOCKED);
+    }
+    let item = p.log.remove(req.from);
+    p.log.insert(req.to, item);
+    normalize_log_state(&mut p);
+    bump_queue_rev(&mut p);
+
+    // Persist the updated queue so restarts keep the same order.
+    persist_queue(p.log.clone()).await;
+    Ok(Json(json!({"ok": true, "rev": p.queue_rev})))
+}
+
+
+pub(crate) async fn api_queue_reorder(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<QueueReorderReq>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    // Reorder upcoming items in the queue using stable item IDs.
+    // Index 0 is "playing" and is pinned.
+    let mut p = state.playout.write().await;
+    check_queue_rev(&p, &headers, req.rev)?;
+
+    if p.log.len() <= 1 {
+        return Ok(Json(json!({"ok": true, "rev": p.queue_rev})));
+    }
+
+    // We reorder only the upcoming items (everything after the playing item).
+    // Require a full list for determinism.
+    let upcoming_len = p.log.len() - 1;
+    if req.order.len() != upcoming_len {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    // Build a lookup for upcoming items.
+    use std::collections::{HashMap, HashSet};
+    // Work from a copy of the upcoming items so any rejection below leaves the
+    // live queue untouched.
+    let before: Vec<LogItem> = p.log[1..].to_vec();
+    let mut by_id: HashMap<Uuid, LogItem> = HashMap::with_capacity(upcoming_len);
+    for item in &before {
+        by_id.insert(item.id, item.clone());
+    }
+
+    // Validate: no duplicates and all IDs exist.
+    let mut seen: HashSet<Uuid> = HashSet::with_capacity(req.order.len());
+    let mut reordered: Vec<LogItem> = Vec::with_capacity(upcoming_len);
+
+    for id in &req.order {
+        if !seen.insert(*id) {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        let item = by_id.remove(id).ok_or(StatusCode::BAD_REQUEST)?;
+        reordered.push(item);
+    }
+
+    // Defensive: append any stragglers (should be none due to strict length check).
+    reordered.extend(by_id.into_values());
+
+    // Locked items are anchors: compare against the current upcoming order
+    // (still in `before`) and refuse anything that shifts or crosses one.
+    if !reorder_respects_locks(&before, &reordered) {
+        return Err(StatusCode::LOCKED);
+    }
+
+    // Keep the playing item at the front and normalize state markers.
+    p.log.truncate(1);
+    p.log.extend(reordered);
+    normalize_log_state(&mut p);
+    bump_queue_rev(&mut p);
+
+    // Persist the updated queue so restarts keep the same order.
+    persist_queue(p.log.clone()).await;
+
+    Ok(Json(json!({"ok": true, "rev": p.queue_rev})))
+}
+
+pub(crate) async fn api_queue_insert(
+    State(state): State<AppState>,
+    Json(req): Json<QueueInsertReq>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    // Insert a cart after a given index (e.g., after "next" => after=1).
+    let mut item = match (req.item, req.reference) {
+        (Some(item), None) => item,
+        (None, Some(reference)) => {
+            let tag = req.tag.unwrap_or_else(|| "MUS".into());
+            resolve_insert_ref(&reference, tag).await.map_err(|errors| {
+                (
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    Json(json!({"ok": false, "ref": reference, "errors": errors})),
+                )
+            })?
+        }
+        _ => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(json!({"ok": false, "errors": ["exactly one of `item` or `ref` is required"]})),
+            ))
+        }
+    };
+    item.check_tag().map_err(|e| (StatusCode::BAD_REQUEST, Json(json!({"ok": false, "errors": [e]}))))?;
+
+    let mut p = state.playout.write().await;
+    // Handle truly-empty queues: inserting at index 1 would panic.
+    // In that case, the first inserted item becomes "playing".
+    if p.log.is_empty() {
+        p.log.push(item.into_log_item("playing"));
+    } else {
+        let after = req.after.min(p.log.len().saturating_sub(1));
+        p.log.insert(after + 1, item.into_log_item("queued"));
+    }
+    normalize_log_state(&mut p);
+    bump_queue_rev(&mut p);
+
+    // Persist the updated queue so restarts keep the same order.
+    persist_queue(p.log.clone()).await;
+    Ok(Json(json!({"ok": true, "rev": p.queue_rev})))
+}
+
+/// Resolve a reference-mode insert into a concrete item.
+///
+/// Every reason for rejecting the reference is collected (rather than failing
+/// on the first) so the operator sees the whole story in one response instead
+/// of finding out later through a stretch of silence.
+pub(crate) async fn resolve_insert_ref(reference: &str, tag: String) -> Result<QueueInsertItem, Vec<String>> {
+    let reference = reference.trim();
+    if reference.is_empty() {
+        return Err(vec!["reference is empty".into()]);
+    }
+
+    // `lib:<id>` points at a media library track.
+    let path = if let Some(id) = reference.strip_prefix("lib:") {
+        let id: i64 = id.trim().parse().map_err(|_| vec![format!("invalid library id: {id}")])?;
+        let path = library::lookup_path(id)
+            .await
+            .map_err(|e| vec![format!("library lookup failed: {e}")])?
+            .ok_or_else(|| vec![format!("library track {id} not found")])?;
+        Some(path).filter(|p| paths::from_cart(p).is_file())
+    } else if remote::is_url(reference) {
+        // Played from a copy fetched shortly before it is due (remote.rs).
+        remote::check(reference).map_err(|e| vec![e])?;
+        Some(reference.to_string())
+    } else {
+        let r = reference.to_string();
+        tokio::task::spawn_blocking(move || resolve_cart_to_path(&r))
+            .await
+            .map_err(|e| vec![format!("resolve task failed: {e}")])?
+    };
+    let Some(path) = path else {
+        return Err(vec![if reference.starts_with('/') {
+            format!("file does not exist: {reference}")
+        } else {
+            format!("cart {reference} not found (no matching file in the carts folder)")
+        }]);
+    };
+
+    let probe = analysis::probe_cached(&path).await.map_err(|e| vec![e])?;
+    let mut errors = Vec::new();
+    if !probe.has_audio {
+        errors.push(format!("{path}: no audio stream"));
+    }
+    if probe.duration_s == 0 {
+        errors.push(format!("{path}: duration unknown or zero"));
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let dur_ms = probe.length_ms();
+    Ok(QueueInsertItem {
+        tag,
+        title: probe.title.unwrap_or_else(|| title_from_path(&path)),
+        artist: probe.artist.unwrap_or_default(),
+        dur: fmt_dur_mmss(probe.duration_s),
+        dur_ms: Some(dur_ms),
+        cart: path,
+        locked: false,
+    })
+}
+
+pub(crate) async fn api_queue_batch(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<QueueBatchReq>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    let mut p = state.playout.write().await;
+    check_queue_rev(&p, &headers, req.rev)
+        .map_err(|code| (code, Json(json!({"ok": false, "error": "queue revision check failed"}))))?;
+
+    // Work on a scratch copy so a failure halfway through leaves the live
+    // queue untouched.
+    let mut log = p.log.clone();
+    for (i, op) in req.ops.into_iter().enumerate() {
+        apply_queue_batch_op(&mut log, op).map_err(|e| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"ok": false, "failed_op": i, "error": e})),
+            )
+        })?;
+    }
+
+    p.log = log;
+    normalize_log_state(&mut p);
+    bump_queue_rev(&mut p);
+
+    // One persist call == one SQLite transaction for the whole batch.
+    persist_queue(p.log.clone()).await;
+    Ok(Json(json!({"ok": true, "rev": p.queue_rev})))
+}
+
+pub(crate) async fn api_queue_replace(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<QueueReplaceReq>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    // Validate every cart up front (outside the lock: this touches the
+    // filesystem) so a typo in line 300 of an imported log doesn't leave us
+    // with a half-replaced queue or a stretch of silence later.
+    // Break markers carry no audio of their own and are exempt.
+    let mut items = req.items;
+    let mut bad_tags: Vec<_> = items
+        .iter_mut()
+        .enumerate()
+        .filter_map(|(i, it)| it.check_tag().err().map(|e| json!({"index": i, "tag": it.tag, "error": e})))
+        .collect();
+    let carts: Vec<Option<String>> =
+        items.iter().map(|it| (it.tag != BREAK_MARKER_TAG).then(|| it.cart.clone())).collect();
+    let mut invalid = tokio::task::spawn_blocking(move || {
+        carts
+            .iter()
+            .enumerate()
+            .filter_map(|(i, cart)| cart.as_ref().map(|c| (i, c)))
+            .filter(|(_, cart)| resolve_cart_to_path(cart).is_none())
+            .map(|(i, cart)| json!({"index": i, "cart": cart, "error": "cart not found"}))
+            .collect::<Vec<_>>()
+    })
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"ok": false}))))?;
+    invalid.append(&mut bad_tags);
+    if !invalid.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, Json(json!({"ok": false, "errors": invalid}))));
+    }
+
+    let mut p = state.playout.write().await;
+
+    // A wholesale replace doesn't depend on the caller's view of the current
+    // order, so the revision is optional here; if one is sent it must match.
+    if req.rev.is_some() || headers.contains_key(axum::http::header::IF_MATCH) {
+        check_queue_rev(&p, &headers, req.rev)
+            .map_err(|code| (code, Json(json!({"ok": false, "error": "queue revision check failed"}))))?;
+    }
+
+    p.log.truncate(1);
+    for item in items {
+        let marker = if p.log.is_empty() { "playing" } else { "queued" };
+        p.log.push(item.into_log_item(marker));
+    }
+    normalize_log_state(&mut p);
+    bump_queue_rev(&mut p);
+
+    persist_queue(p.log.clone()).await;
+    Ok(Json(json!({"ok": true, "rev": p.queue_rev, "count": p.log.len()})))
+}
+
+pub(crate) async fn api_queue_item_patch(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+    Json(req): Json<QueueItemPatch>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    // Edit metadata on an existing item (typo'd title, wrong tag, bad duration).
+    // Items are addressed by UUID so the revision is optional; if sent, it is checked.
+    let mut p = state.playout.write().await;
+    if req.rev.is_some() || headers.contains_key(axum::http::header::IF_MATCH) {
+        check_queue_rev(&p, &headers, req.rev)?;
+    }
+
+    let item = p.log.iter_mut().find(|it| it.id == id).ok_or(StatusCode::NOT_FOUND)?;
+    req.apply(item)?;
+    let updated = item.clone();
+
+    // Keeps NowPlaying in sync if the playing item was edited.
+    normalize_log_state(&mut p);
+    bump_queue_rev(&mut p);
+
+    persist_queue(p.log.clone()).await;
+    Ok(Json(json!({"ok": true, "rev": p.queue_rev, "item": updated})))
+}
+
+pub(crate) fn apply_queue_batch_op(log: &mut Vec<LogItem>, op: QueueBatchOp) -> Result<(), String> {
+    let index_of = |log: &[LogItem], id: &Uuid| log.iter().position(|it| it.id == *id);
+
+    match op {
+        QueueBatchOp::Insert { after_id, mut item } => {
+            item.check_tag()?;
+            if log.is_empty() {
+                if after_id.is_some() {
+                    return Err("after_id given but queue is empty".into());
+                }
+                log.push(item.into_log_item("playing"));
+                return Ok(());
+            }
+            let at = match after_id {
+                Some(id) => index_of(log, &id).ok_or_else(|| format!("unknown after_id {id}"))? + 1,
+                None => log.len(),
+            };
+            log.insert(at, item.into_log_item("queued"));
+        }
+        QueueBatchOp::Remove { id } => {
+            let idx = index_of(log, &id).ok_or_else(|| format!("unknown id {id}"))?;
+            if idx == 0 {
+                return Err("the playing item cannot be removed".into());
+            }
+            log.remove(idx);
+        }
+        QueueBatchOp::Move { id, to } => {
+            let from = index_of(log, &id).ok_or_else(|| format!("unknown id {id}"))?;
+            if from == 0 {
+                return Err("the playing item cannot be moved".into());
+            }
+            if to == 0 || to >= log.len() {
+                return Err(format!("move target {to} out of range"));
+            }
+            if move_crosses_lock(log, from, to) {
+                return Err("move would cross a locked item".into());
+            }
+            let item = log.remove(from);
+            log.insert(to, item);
+        }
+    }
+    Ok(())
+}
+
+/// Body for `POST /api/v1/queue/clear`.
+#[derive(serde::Deserialize, Default)]
+pub(crate) struct QueueClearReq {
+    /// Token returned by a first, unconfirmed call. Tied to the queue revision,
+    /// so it goes stale if the queue changes before the operator confirms.
+    #[serde(default)]
+    confirm: Option<String>,
+    /// Keep locked items (legal IDs, sponsor spots) in place.
+    #[serde(default)]
+    keep_locked: bool,
+}
+
+/// Remove every upcoming item (the playing item is untouched).
+///
+/// This is a two-step action so a stray click or script can't wipe a
+/// hand-built log: the first call returns 428 with a `confirm` token and the
+/// number of items that would go; repeating the call with that token clears.
+pub(crate) async fn api_queue_clear(
+    State(state): State<AppState>,
+    body: Option<Json<QueueClearReq>>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    let req = body.map(|Json(b)| b).unwrap_or_default();
+    let mut p = state.playout.write().await;
+
+    let expected = format!("clear-{}", p.queue_rev);
+    let upcoming = p.log.len().saturating_sub(1);
+    if req.confirm.as_deref() != Some(expected.as_str()) {
+        return Err((
+            StatusCode::PRECONDITION_REQUIRED,
+            Json(json!({"ok": false, "confirm": expected, "upcoming": upcoming})),
+        ));
+    }
+
+    if !p.log.is_empty() {
+        let keep_locked = req.keep_locked;
+        let mut i = 0usize;
+        p.log.retain(|it| {
+            let keep = i == 0 || (keep_locked && it.locked);
+            i += 1;
+            keep
+        });
+    }
+    let removed = upcoming - p.log.len().saturating_sub(1);
+    normalize_log_state(&mut p);
+    bump_queue_rev(&mut p);
+
+    tracing::info!(target: "audit", "queue cleared: removed {removed} upcoming item(s) (keep_locked={})", req.keep_locked);
+
+    persist_queue(p.log.clone()).await;
+    Ok(Json(json!({"ok": true, "rev": p.queue_rev, "removed": removed})))
+}
+
+/// Put a recently played/skipped item back on the queue as the next item.
+///
+/// The requeued copy gets a fresh UUID: the old one refers to the airing that
+/// already happened.
+pub(crate) async fn api_queue_requeue(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let mut p = state.playout.write().await;
+    let prev = p.recent.iter().rev().find(|it| it.id == id).cloned().ok_or(StatusCode::NOT_FOUND)?;
+
+    let mut item = prev;
+    item.id = Uuid::new_v4();
+    item.locked = false;
+    let new_id = item.id;
+    if p.log.is_empty() {
+        p.log.push(item);
+    } else {
+        p.log.insert(1, item);
+    }
+    normalize_log_state(&mut p);
+    bump_queue_rev(&mut p);
+
+    tracing::info!(target: "audit", "queue requeue: {id} -> {new_id}");
+
+    persist_queue(p.log.clone()).await;
+    Ok(Json(json!({"ok": true, "rev": p.queue_rev, "id": new_id})))
+}
+
+/// Optimistic concurrency check for queue mutations.
+///
+/// The caller's revision comes from the JSON body (`rev`) or, for clients that
+/// prefer HTTP semantics, an `If-Match` header (`"12"`, `W/"12"` or `12`).
+/// A missing revision is rejected with 428 so that an outdated UI can't keep
+/// silently overwriting someone else's edits; a stale one yields 409.
+pub(crate) fn check_queue_rev(p: &PlayoutState, headers: &HeaderMap, body_rev: Option<u64>) -> Result<(), StatusCode> {
+    let header_rev = match headers.get(axum::http::header::IF_MATCH) {
+        Some(v) => {
+            let raw = v.to_str().map_err(|_| StatusCode::BAD_REQUEST)?.trim();
+            let raw = raw.strip_prefix("W/").unwrap_or(raw).trim_matches('"');
+            Some(raw.parse::<u64>().map_err(|_| StatusCode::BAD_REQUEST)?)
+        }
+        None => None,
+    };
+
+    match body_rev.or(header_rev) {
+        None => Err(StatusCode::PRECONDITION_REQUIRED),
+        Some(rev) if rev != p.queue_rev => Err(StatusCode::CONFLICT),
+        Some(_) => Ok(()),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct AuxQueueReplaceReq {
+    items: Vec<QueueInsertItem>,
+}
+
+fn aux_queue_mut<'a>(p: &'a mut PlayoutState, name: &str) -> Result<&'a mut Vec<LogItem>, StatusCode> {
+    p.aux.get_mut(name).ok_or(StatusCode::NOT_FOUND)
+}
+
+pub(crate) async fn api_aux_queues_list(State(state): State<AppState>) -> Json<serde_json::Value> {
+    let p = state.playout.read().await;
+    let queues: Vec<_> = p
+        .aux
+        .iter()
+        .map(|(name, items)| json!({"name": name, "count": items.len()}))
+        .collect();
+    Json(json!({"queues": queues}))
+}
+
+pub(crate) async fn api_aux_queue_get(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let p = state.playout.read().await;
+    let items = p.aux.get(&name).ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(json!({"name": name, "items": items})))
+}
+
+pub(crate) async fn api_aux_queue_add(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(mut item): Json<QueueInsertItem>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    item.check_tag().map_err(|_| StatusCode::BAD_REQUEST)?;
+    let mut p = state.playout.write().await;
+    let items = aux_queue_mut(&mut p, &name)?;
+    let mut item = item.into_log_item("queued");
+    item.locked = false;
+    let id = item.id;
+    items.push(item);
+    let snapshot = items.clone();
+    drop(p);
+
+    persist_aux_queue(name, snapshot).await;
+    Ok(Json(json!({"ok": true, "id": id})))
+}
+
+pub(crate) async fn api_aux_queue_replace(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(mut req): Json<AuxQueueReplaceReq>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    for it in &mut req.items {
+        it.check_tag().map_err(|_| StatusCode::BAD_REQUEST)?;
+    }
+    let mut p = state.playout.write().await;
+    let items = aux_queue_mut(&mut p, &name)?;
+    *items = req
+        .items
+        .into_iter()
+        .map(|it| {
+            let mut it = it.into_log_item("queued");
+            it.locked = false;
+            it
+        })
+        .collect();
+    let snapshot = items.clone();
+    drop(p);
+
+    let count = snapshot.len();
+    persist_aux_queue(name, snapshot).await;
+    Ok(Json(json!({"ok": true, "count": count})))
+}
+
+pub(crate) async fn api_aux_queue_item_patch(
+    State(state): State<AppState>,
+    Path((name, id)): Path<(String, Uuid)>,
+    Json(req): Json<QueueItemPatch>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let mut p = state.playout.write().await;
+    let items = aux_queue_mut(&mut p, &name)?;
+    let item = items.iter_mut().find(|it| it.id == id).ok_or(StatusCode::NOT_FOUND)?;
+    req.apply(item)?;
+    // Lock semantics only exist in the main log.
+    item.locked = false;
+    let updated = item.clone();
+    let snapshot = items.clone();
+    drop(p);
+
+    persist_aux_queue(name, snapshot).await;
+    Ok(Json(json!({"ok": true, "item": updated})))
+}
+
+pub(crate) async fn api_aux_queue_item_delete(
+    State(state): State<AppState>,
+    Path((name, id)): Path<(String, Uuid)>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let mut p = state.playout.write().await;
+    let items = aux_queue_mut(&mut p, &name)?;
+    let before = items.len();
+    items.retain(|it| it.id != id);
+    if items.len() == before {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let snapshot = items.clone();
+    drop(p);
+
+    persist_aux_queue(name, snapshot).await;
+    Ok(Json(json!({"ok": true})))
+}
+
+/// Copy a side-queue item into the main log as the next item.
+pub(crate) async fn api_aux_queue_item_enqueue(
+    State(state): State<AppState>,
+    Path((name, id)): Path<(String, Uuid)>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let mut p = state.playout.write().await;
+    let items = aux_queue_mut(&mut p, &name)?;
+    let mut item = items.iter().find(|it| it.id == id).cloned().ok_or(StatusCode::NOT_FOUND)?;
+    item.id = Uuid::new_v4();
+    let new_id = item.id;
+
+    if p.log.is_empty() {
+        p.log.push(item);
+    } else {
+        p.log.insert(1, item);
+    }
+    normalize_log_state(&mut p);
+    bump_queue_rev(&mut p);
+
+    persist_queue(p.log.clone()).await;
+    Ok(Json(json!({"ok": true, "rev": p.queue_rev, "id": new_id})))
+}
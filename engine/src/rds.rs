@@ -0,0 +1,333 @@
+// --- RDS / RBDS encoder feed ------------------------------------------------------------
+//
+// FM stations want RadioText (and optionally a scrolling-free dynamic PS) to
+// follow now-playing. On each track change the engine renders the PS/RT
+// templates (same placeholders as metadata push: `{title}`, `{artist}`,
+// `{tag}`, `{cart}`, `{dur}`) and sends them to the encoder:
+// - `protocol: "ascii"`: the line commands most encoders accept (`PS=…`,
+//   `RT=…` + CRLF); the command prefixes are configurable because vendors
+//   differ (`DPS=`, `TEXT=`, ...),
+// - `protocol: "uecp"`: EBU UECP (SPB 490) frames, MEC 0x02 (PS) and 0x0A (RT),
+//   for encoders that only speak UECP.
+//
+// `address` is `host:port` for TCP, or a device path (`/dev/ttyUSB0`) for a
+// serial encoder; the port's baud rate is set outside the engine (`stty`).
+// Each update opens the connection, writes and closes it, so an encoder
+// restart needs no reconnect logic.
+//
+// Items whose tag is not in `tags` (spots, IDs, ...) or is flagged
+// `suppress_metadata` (tags.rs) get `idle_ps`/`idle_rt` instead, e.g. the
+// station slogan; an empty idle text leaves the encoder alone. Text is folded
+// to the RDS character range (ASCII) and cut to 8/64.
+
+use std::sync::{Mutex, OnceLock};
+
+use axum::{http::StatusCode, Json};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::io::AsyncWriteExt;
+use tokio::time::{timeout, Duration};
+
+use crate::metapush::{self, Track};
+use crate::{tags, unix_ms_now};
+
+const PS_LEN: usize = 8;
+const RT_LEN: usize = 64;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct RdsConfig {
+    #[serde(default)]
+    enabled: bool,
+    /// "ascii" or "uecp".
+    #[serde(default = "default_protocol")]
+    protocol: String,
+    /// `host:port` or a serial device path.
+    #[serde(default)]
+    address: String,
+    /// Empty: PS is not touched (most stations keep a static PS).
+    #[serde(default)]
+    ps_template: String,
+    #[serde(default = "default_rt_template")]
+    rt_template: String,
+    #[serde(default)]
+    idle_ps: String,
+    #[serde(default)]
+    idle_rt: String,
+    /// Tags that get the templates; empty means all.
+    #[serde(default = "default_tags")]
+    tags: Vec<String>,
+    /// ASCII command prefixes.
+    #[serde(default = "default_ps_command")]
+    ps_command: String,
+    #[serde(default = "default_rt_command")]
+    rt_command: String,
+    /// UECP encoder address (0 = all encoders) and data set number (0 = current).
+    #[serde(default)]
+    uecp_address: u16,
+    #[serde(default)]
+    uecp_dsn: u8,
+}
+
+fn default_protocol() -> String {
+    "ascii".into()
+}
+
+fn default_rt_template() -> String {
+    "{artist} - {title}".into()
+}
+
+fn default_tags() -> Vec<String> {
+    vec!["MUS".into()]
+}
+
+fn default_ps_command() -> String {
+    "PS=".into()
+}
+
+fn default_rt_command() -> String {
+    "RT=".into()
+}
+
+fn default_config() -> RdsConfig {
+    serde_json::from_str("{}").expect("defaults")
+}
+
+#[derive(Clone, Serialize, Default)]
+struct RdsStatus {
+    last_attempt_ms: Option<u64>,
+    last_ok_ms: Option<u64>,
+    last_ps: Option<String>,
+    last_rt: Option<String>,
+    last_error: Option<String>,
+}
+
+fn status() -> &'static Mutex<RdsStatus> {
+    static STATUS: OnceLock<Mutex<RdsStatus>> = OnceLock::new();
+    STATUS.get_or_init(|| Mutex::new(RdsStatus::default()))
+}
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS rds_config (
+            id      INTEGER PRIMARY KEY CHECK (id = 1),
+            config  TEXT NOT NULL
+        );
+        "#,
+    )
+}
+
+fn db_load_config(conn: &Connection) -> anyhow::Result<RdsConfig> {
+    crate::db_init(conn)?;
+    let raw: Option<String> =
+        conn.query_row("SELECT config FROM rds_config WHERE id = 1", [], |row| row.get(0)).optional()?;
+    Ok(match raw {
+        Some(r) => serde_json::from_str(&r)?,
+        None => default_config(),
+    })
+}
+
+async fn load_config() -> anyhow::Result<RdsConfig> {
+    crate::db::call(move |conn| db_load_config(conn)).await?
+}
+
+// --- Text and framing ------------------------------------------------------------------
+
+/// Fold to printable ASCII (accents dropped, the rest `?`) and cut to `max`.
+fn rds_text(s: &str, max: usize) -> String {
+    let folded: String = s
+        .chars()
+        .map(|c| match c {
+            ' '..='~' => c,
+            'À'..='Å' | 'à'..='å' => if c.is_uppercase() { 'A' } else { 'a' },
+            'È'..='Ë' | 'è'..='ë' => if c.is_uppercase() { 'E' } else { 'e' },
+            'Ì'..='Ï' | 'ì'..='ï' => if c.is_uppercase() { 'I' } else { 'i' },
+            'Ò'..='Ö' | 'ò'..='ö' | 'Ø' | 'ø' => if c.is_uppercase() { 'O' } else { 'o' },
+            'Ù'..='Ü' | 'ù'..='ü' => if c.is_uppercase() { 'U' } else { 'u' },
+            'Ç' => 'C',
+            'ç' => 'c',
+            'Ñ' => 'N',
+            'ñ' => 'n',
+            'ß' => 's',
+            '‘' | '’' => '\'',
+            '“' | '”' => '"',
+            '–' | '—' => '-',
+            '\t' | '\n' | '\r' => ' ',
+            _ => '?',
+        })
+        .collect();
+    folded.trim().chars().take(max).collect()
+}
+
+/// CRC-CCITT (x^16 + x^12 + x^5 + 1), init 0xFFFF, inverted, as UECP specifies.
+fn uecp_crc(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &b in data {
+        crc ^= (b as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    !crc
+}
+
+/// One UECP frame around `msg` (MEC + data), with byte stuffing.
+fn uecp_frame(address: u16, msg: &[u8]) -> Vec<u8> {
+    let mut body = vec![(address >> 8) as u8, address as u8, 0x00, msg.len() as u8];
+    body.extend_from_slice(msg);
+    let crc = uecp_crc(&body);
+    body.extend_from_slice(&crc.to_be_bytes());
+
+    let mut out = vec![0xFE];
+    for b in body {
+        match b {
+            0xFD..=0xFF => out.extend_from_slice(&[0xFD, b - 0xFD]),
+            _ => out.push(b),
+        }
+    }
+    out.push(0xFF);
+    out
+}
+
+fn uecp_ps(cfg: &RdsConfig, ps: &str) -> Vec<u8> {
+    let mut msg = vec![0x02, cfg.uecp_dsn, 0x00];
+    msg.extend(format!("{ps:<8}").bytes().take(PS_LEN));
+    uecp_frame(cfg.uecp_address, &msg)
+}
+
+fn uecp_rt(cfg: &RdsConfig, rt: &str) -> Vec<u8> {
+    // MEL covers the flags byte and the text. Flags: transmit indefinitely
+    // (0 repetitions field) and toggle A/B so receivers clear the old text.
+    let toggle = {
+        static AB: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+        AB.fetch_xor(true, std::sync::atomic::Ordering::Relaxed) as u8
+    };
+    let mut msg = vec![0x0A, cfg.uecp_dsn, 0x00, (rt.len() + 1) as u8, toggle];
+    msg.extend(rt.bytes());
+    uecp_frame(cfg.uecp_address, &msg)
+}
+
+fn payload(cfg: &RdsConfig, ps: Option<&str>, rt: Option<&str>) -> Vec<u8> {
+    let mut out = Vec::new();
+    if cfg.protocol == "uecp" {
+        if let Some(ps) = ps {
+            out.extend(uecp_ps(cfg, ps));
+        }
+        if let Some(rt) = rt {
+            out.extend(uecp_rt(cfg, rt));
+        }
+    } else {
+        if let Some(ps) = ps {
+            out.extend(format!("{}{ps}\r\n", cfg.ps_command).bytes());
+        }
+        if let Some(rt) = rt {
+            out.extend(format!("{}{rt}\r\n", cfg.rt_command).bytes());
+        }
+    }
+    out
+}
+
+async fn write_to(address: &str, bytes: &[u8]) -> Result<(), String> {
+    let io = async {
+        if address.starts_with('/') {
+            let mut f = tokio::fs::OpenOptions::new().write(true).open(address).await?;
+            f.write_all(bytes).await?;
+            f.flush().await
+        } else {
+            let mut s = tokio::net::TcpStream::connect(address).await?;
+            s.write_all(bytes).await?;
+            s.shutdown().await
+        }
+    };
+    match timeout(Duration::from_secs(5), io).await {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(e)) => Err(format!("{address}: {e}")),
+        Err(_) => Err(format!("{address}: timed out")),
+    }
+}
+
+/// Render and send the texts for `track`. `Ok(false)`: nothing to send.
+async fn update(cfg: &RdsConfig, track: &Track) -> Result<bool, String> {
+    let music = !tags::suppresses_metadata(&track.tag)
+        && (cfg.tags.is_empty() || cfg.tags.iter().any(|t| t.eq_ignore_ascii_case(&track.tag)));
+    let (ps_t, rt_t) = if music { (&cfg.ps_template, &cfg.rt_template) } else { (&cfg.idle_ps, &cfg.idle_rt) };
+    let keep = |s: &str| s.to_string();
+    let ps = Some(rds_text(&metapush::render(ps_t, track, keep), PS_LEN)).filter(|s| !s.is_empty());
+    let rt = Some(rds_text(&metapush::render(rt_t, track, keep), RT_LEN)).filter(|s| !s.is_empty());
+    if ps.is_none() && rt.is_none() {
+        return Ok(false);
+    }
+    let res = write_to(&cfg.address, &payload(cfg, ps.as_deref(), rt.as_deref())).await;
+
+    let now_ms = unix_ms_now();
+    let mut s = status().lock().unwrap_or_else(|e| e.into_inner());
+    s.last_attempt_ms = Some(now_ms);
+    match &res {
+        Ok(()) => {
+            s.last_ok_ms = Some(now_ms);
+            s.last_ps = ps.or(s.last_ps.take());
+            s.last_rt = rt.or(s.last_rt.take());
+            s.last_error = None;
+        }
+        Err(e) => {
+            tracing::warn!("rds: {e}");
+            s.last_error = Some(e.clone());
+        }
+    }
+    res.map(|_| true)
+}
+
+/// Follow track changes and update the encoder.
+pub(crate) async fn rds_task() {
+    let mut rx = metapush::subscribe();
+    while rx.changed().await.is_ok() {
+        let Some(track) = rx.borrow_and_update().clone() else { continue };
+        let cfg = match load_config().await {
+            Ok(c) => c,
+            Err(e) => {
+                tracing::warn!("rds: {e}");
+                continue;
+            }
+        };
+        if cfg.enabled {
+            let _ = update(&cfg, &track).await;
+        }
+    }
+}
+
+// --- HTTP API --------------------------------------------------------------------------
+
+pub(crate) async fn api_rds_config_get() -> Result<Json<serde_json::Value>, StatusCode> {
+    let cfg = load_config().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let st = status().lock().unwrap_or_else(|e| e.into_inner()).clone();
+    Ok(Json(json!({"ok": true, "config": cfg, "status": st})))
+}
+
+pub(crate) async fn api_rds_config_set(
+    Json(mut cfg): Json<RdsConfig>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    let bad = |msg: &str| Err((StatusCode::BAD_REQUEST, Json(json!({"ok": false, "error": msg}))));
+    cfg.address = cfg.address.trim().to_string();
+    cfg.tags = cfg.tags.iter().map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect();
+    if !matches!(cfg.protocol.as_str(), "ascii" | "uecp") {
+        return bad("protocol must be ascii or uecp");
+    }
+    if cfg.enabled && !cfg.address.starts_with('/') && !cfg.address.contains(':') {
+        return bad("address must be host:port or a device path");
+    }
+    let raw = serde_json::to_string(&cfg).map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"ok": false}))))?;
+    crate::db::call(move |conn| -> anyhow::Result<()> {
+        crate::db_init(conn)?;
+        conn.execute(
+            "INSERT INTO rds_config (id, config) VALUES (1, ?1)
+             ON CONFLICT(id) DO UPDATE SET config=excluded.config",
+            params![raw],
+        )?;
+        Ok(())
+    })
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"ok": false}))))?
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"ok": false}))))?;
+    Ok(Json(json!({"ok": true, "config": cfg})))
+}
@@ -0,0 +1,363 @@
+// --- Show recording --------------------------------------------------------------------
+//
+// Records a show for editing or a podcast, one file per bus:
+// - `program`: what went to air, as the encoder gets it (cart wall, a caller
+//   on air and all),
+// - `callers`: the phone line before the fader (callers.rs), whether or not
+//   the caller is on air, so the conversation can be re-cut afterwards.
+// There are no other sources in the engine yet (producer audio and studio
+// microphones are not brought in), so those are the buses.
+//
+// A recording is started by the `record` action (`{"type": "record", "show":
+// …, "minutes": …, "buses": […]}`), from the action scheduler for a weekly
+// show, or a macro, or `POST /api/v1/recorder/start`. It stops after
+// `minutes`, or on `POST /api/v1/recorder/stop`. Each bus is encoded to FLAC
+// by its own ffmpeg under `<archive>/recordings/<show>/<time>-<bus>.flac`.
+//
+// The files of one recording start together and stay the length of the wall
+// clock: while a bus has nothing to give (the stream is stopped, no call is
+// connected) the writer pads silence, so the buses line up in an editor.
+// Each file is a row of `recordings`; rows left open by a restart are marked
+// `interrupted`.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::Duration;
+
+use crate::{backup, callers, ffmpeg, storage, unix_ms_now, AppState};
+
+/// Recordable buses.
+pub(crate) const BUSES: &[&str] = &["program", "callers"];
+/// Longest recording, minutes.
+const MAX_MINUTES: u32 = 12 * 60;
+/// s16le stereo at 48 kHz, as `writer_playout`.
+const BYTES_PER_MS: usize = 48 * 4;
+/// How far a bus may fall behind the clock before silence is padded in.
+const SLACK_MS: usize = 500;
+
+/// A file being written.
+struct Active {
+    id: i64,
+    show: String,
+    bus: String,
+    file: String,
+    started_ms: u64,
+    until_ms: u64,
+    stop: Arc<AtomicBool>,
+}
+
+static ACTIVE: Mutex<Vec<Active>> = Mutex::new(Vec::new());
+
+fn active() -> std::sync::MutexGuard<'static, Vec<Active>> {
+    ACTIVE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS recordings (
+            id          INTEGER PRIMARY KEY AUTOINCREMENT,
+            show        TEXT NOT NULL,
+            bus         TEXT NOT NULL,
+            file        TEXT NOT NULL,
+            started_ms  INTEGER NOT NULL,
+            ended_ms    INTEGER,
+            result      TEXT NOT NULL
+        );
+        "#,
+    )
+}
+
+async fn with_db<T: Send + 'static>(
+    f: impl FnOnce(&Connection) -> anyhow::Result<T> + Send + 'static,
+) -> anyhow::Result<T> {
+    crate::db::call(move |conn| {
+        crate::db_init(conn)?;
+        f(conn)
+    })
+    .await?
+}
+
+/// At startup: recordings still open were cut off by the last shutdown.
+pub(crate) async fn close_interrupted() {
+    match with_db(|conn| Ok(conn.execute("UPDATE recordings SET result = 'interrupted' WHERE ended_ms IS NULL", [])?))
+        .await
+    {
+        Ok(n) if n > 0 => tracing::info!("recorder: {n} recording(s) interrupted by the last shutdown"),
+        Ok(_) => {}
+        Err(e) => tracing::warn!("recorder: {e}"),
+    }
+}
+
+fn recordings_dir() -> PathBuf {
+    storage::archive_dir().join("recordings")
+}
+
+/// `Morning Show` -> `Morning-Show`: a folder name.
+fn slug(show: &str) -> String {
+    let s: String = show
+        .trim()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '-' })
+        .collect();
+    s.trim_matches('-').to_string()
+}
+
+/// Check a `record` action.
+pub(crate) fn validate(show: &str, minutes: u32, buses: &[String]) -> Result<(), String> {
+    if slug(show).is_empty() {
+        return Err("show is required".into());
+    }
+    if !(1..=MAX_MINUTES).contains(&minutes) {
+        return Err(format!("minutes must be 1..{MAX_MINUTES}"));
+    }
+    if buses.is_empty() {
+        return Err("buses: at least one".into());
+    }
+    if let Some(b) = buses.iter().find(|b| !BUSES.contains(&b.as_str())) {
+        return Err(format!("unknown bus {b} (one of {})", BUSES.join(", ")));
+    }
+    Ok(())
+}
+
+/// Start recording `show` on `buses` for `minutes`. Returns the recording ids.
+pub(crate) async fn start(state: &AppState, show: &str, minutes: u32, buses: &[String]) -> Result<Vec<i64>, String> {
+    validate(show, minutes, buses)?;
+    let show = show.trim().to_string();
+    if active().iter().any(|a| a.show == show) {
+        return Err(format!("{show} is already recording"));
+    }
+    let dir = recordings_dir().join(slug(&show));
+    tokio::fs::create_dir_all(&dir).await.map_err(|e| format!("{}: {e}", dir.display()))?;
+    let (stamp, started_ms) = (backup::stamp(), unix_ms_now());
+    let until_ms = started_ms + minutes as u64 * 60_000;
+    let mut ids = Vec::new();
+    for bus in BUSES.iter().filter(|b| buses.iter().any(|x| x == *b)).map(|b| b.to_string()) {
+        let path = dir.join(format!("{stamp}-{bus}.flac"));
+        let file = path.to_string_lossy().into_owned();
+        let row = (show.clone(), bus.clone(), file.clone());
+        let id = with_db(move |conn| {
+            conn.execute(
+                "INSERT INTO recordings (show, bus, file, started_ms, result) VALUES (?1, ?2, ?3, ?4, 'recording')",
+                params![row.0, row.1, row.2, started_ms],
+            )?;
+            Ok(conn.last_insert_rowid())
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+        let rx = match bus.as_str() {
+            "callers" => callers::subscribe(),
+            _ => state.pcm_tx.subscribe(),
+        };
+        let stop = Arc::new(AtomicBool::new(false));
+        active().push(Active {
+            id,
+            show: show.clone(),
+            bus: bus.clone(),
+            file: file.clone(),
+            started_ms,
+            until_ms,
+            stop: stop.clone(),
+        });
+        tracing::info!(target: "audit", "recorder: {show}: recording {bus} for {minutes} min to {file}");
+        tokio::spawn(async move {
+            let result = match write(rx, path, until_ms, &stop).await {
+                Ok(()) if stop.load(Ordering::Relaxed) => "stopped".to_string(),
+                Ok(()) => "ok".to_string(),
+                Err(e) => {
+                    tracing::warn!("recorder: {file}: {e}");
+                    format!("failed: {e}")
+                }
+            };
+            active().retain(|a| a.id != id);
+            let res = with_db(move |conn| {
+                conn.execute(
+                    "UPDATE recordings SET ended_ms = ?2, result = ?3 WHERE id = ?1",
+                    params![id, unix_ms_now(), result],
+                )?;
+                Ok(())
+            })
+            .await;
+            if let Err(e) = res {
+                tracing::warn!("recorder: {e}");
+            }
+        });
+        ids.push(id);
+    }
+    Ok(ids)
+}
+
+/// Encode a bus to `path` until `until_ms` or `stop`.
+async fn write(
+    mut rx: broadcast::Receiver<Vec<u8>>,
+    path: PathBuf,
+    until_ms: u64,
+    stop: &AtomicBool,
+) -> anyhow::Result<()> {
+    let mut child = Command::new(ffmpeg::ffmpeg_bin())
+        .arg("-hide_banner")
+        .arg("-loglevel").arg("error")
+        .arg("-f").arg("s16le").arg("-ar").arg("48000").arg("-ac").arg("2")
+        .arg("-i").arg("pipe:0")
+        .arg("-c:a").arg("flac")
+        .arg("-y").arg(&path)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()?;
+    let mut stdin = child.stdin.take().ok_or_else(|| anyhow::anyhow!("encoder stdin unavailable"))?;
+    let started = Instant::now();
+    let mut written = 0usize;
+    let mut tick = tokio::time::interval(Duration::from_millis(100));
+    loop {
+        tokio::select! {
+            msg = rx.recv() => match msg {
+                Ok(chunk) => {
+                    stdin.write_all(&chunk).await?;
+                    written += chunk.len();
+                }
+                // Missed chunks are made up with silence below.
+                Err(RecvError::Lagged(n)) => tracing::warn!("recorder: {} fell behind ({n} chunks)", path.display()),
+                Err(RecvError::Closed) => break,
+            },
+            _ = tick.tick() => {
+                if stop.load(Ordering::Relaxed) || unix_ms_now() >= until_ms {
+                    break;
+                }
+                // Nothing coming (stream stopped, no call): keep the file in step with the clock.
+                let due = started.elapsed().as_millis() as usize * BYTES_PER_MS;
+                if due > written + SLACK_MS * BYTES_PER_MS {
+                    let pad = (due - written) & !3;
+                    stdin.write_all(&vec![0u8; pad]).await?;
+                    written += pad;
+                }
+            }
+        }
+    }
+    drop(stdin);
+    let status = child.wait().await?;
+    if !status.success() {
+        anyhow::bail!("encoder exited ({status})");
+    }
+    Ok(())
+}
+
+/// Stop the recordings of `show`, or all. Returns how many were stopped.
+pub(crate) fn stop(show: Option<&str>) -> usize {
+    let active = active();
+    let mut n = 0;
+    for a in active.iter().filter(|a| show.is_none_or(|s| a.show == s.trim())) {
+        a.stop.store(true, Ordering::Relaxed);
+        n += 1;
+    }
+    n
+}
+
+// --- HTTP API --------------------------------------------------------------------------
+
+type ApiError = (StatusCode, Json<serde_json::Value>);
+
+fn api_error(status: StatusCode, msg: impl Into<String>) -> ApiError {
+    (status, Json(json!({"ok": false, "error": msg.into()})))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ListQuery {
+    #[serde(default)]
+    limit: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct Recording {
+    id: i64,
+    show: String,
+    bus: String,
+    file: String,
+    started_ms: u64,
+    ended_ms: Option<u64>,
+    result: String,
+}
+
+/// `GET /api/v1/recorder?limit=50`: what is recording, and recent recordings.
+pub(crate) async fn api_recorder_get(Query(q): Query<ListQuery>) -> Result<Json<serde_json::Value>, StatusCode> {
+    let limit = q.limit.unwrap_or(50).clamp(1, 1000);
+    let recordings = with_db(move |conn| {
+        let mut stmt = conn.prepare(
+            "SELECT id, show, bus, file, started_ms, ended_ms, result FROM recordings ORDER BY id DESC LIMIT ?1",
+        )?;
+        let rows = stmt.query_map([limit], |row| {
+            Ok(Recording {
+                id: row.get(0)?,
+                show: row.get(1)?,
+                bus: row.get(2)?,
+                file: row.get(3)?,
+                started_ms: row.get(4)?,
+                ended_ms: row.get(5)?,
+                result: row.get(6)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let active: Vec<_> = active()
+        .iter()
+        .map(|a| {
+            json!({"id": a.id, "show": a.show, "bus": a.bus, "file": a.file,
+                   "started_ms": a.started_ms, "until_ms": a.until_ms})
+        })
+        .collect();
+    Ok(Json(json!({"ok": true, "buses": BUSES, "active": active, "recordings": recordings})))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct StartRequest {
+    show: String,
+    minutes: u32,
+    #[serde(default = "default_buses")]
+    buses: Vec<String>,
+}
+
+pub(crate) fn default_buses() -> Vec<String> {
+    vec!["program".into()]
+}
+
+/// `POST /api/v1/recorder/start`: record now, as the `record` action.
+pub(crate) async fn api_recorder_start(
+    State(state): State<AppState>,
+    Json(req): Json<StartRequest>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let ids = start(&state, &req.show, req.minutes, &req.buses).await.map_err(|e| {
+        let status = if e.contains("already recording") { StatusCode::CONFLICT } else { StatusCode::BAD_REQUEST };
+        api_error(status, e)
+    })?;
+    Ok(Json(json!({"ok": true, "ids": ids})))
+}
+
+#[derive(Deserialize, Default)]
+pub(crate) struct StopRequest {
+    #[serde(default)]
+    show: Option<String>,
+}
+
+/// `POST /api/v1/recorder/stop`: stop `show`'s recording, or all of them.
+pub(crate) async fn api_recorder_stop(body: Option<Json<StopRequest>>) -> Json<serde_json::Value> {
+    let req = body.map(|Json(r)| r).unwrap_or_default();
+    let stopped = stop(req.show.as_deref());
+    tracing::info!(target: "audit", "recorder: stop {}: {stopped} file(s)", req.show.as_deref().unwrap_or("all"));
+    Json(json!({"ok": true, "stopped": stopped}))
+}
@@ -0,0 +1,355 @@
+// --- Configuration file and hot reload ----------------------------------------------------
+//
+// Settings live in the database and are edited from the UI, which is right
+// for day-to-day operation but awkward for deployments managed as files
+// (configuration management, a second studio set up the same way). The
+// engine now also reads an optional JSON file, `STUDIOCOMMAND_CONFIG`
+// (default `/opt/studiocommand/shared/studiocommand.json`):
+//
+//   {
+//     "engine": { "log_level": "info,studiocommand_engine::metapush=debug", "bind": "127.0.0.1:3000" },
+//     "topup": { ... },
+//     "metadata_targets": [ ... ]
+//   }
+//
+// Besides `engine`, it takes any section of the configuration export
+// (`settings.rs`), applied through the same handlers, so a file can start
+// as a `GET /api/v1/admin/config` document.
+//
+// The file is read at startup and again on `SIGHUP` (`systemctl reload`) or
+// `POST /api/v1/admin/reload`. A section is applied when its contents differ
+// from what was last applied from the file (recorded in the database), so a
+// reload that changed one section leaves the others, and the UI edits made
+// to them since, alone. Applied live: the log level and every section. Not
+// live, and reported as `restart_required` instead:
+// - `engine.log_format`: chosen when logging is set up (`logbuf.rs`);
+// - `engine.bind`: the listeners are bound once at startup
+//   (`STUDIOCOMMAND_BIND`, when set, wins over the file; see serve.rs);
+// - `output` while streaming: the encoder picks the new settings up when the
+//   stream is restarted.
+
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+
+use axum::{extract::State, http::StatusCode, Json};
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use tracing_subscriber::{prelude::*, reload, EnvFilter, Registry};
+
+use crate::{db, logbuf, settings, unix_ms_now, AppState};
+
+const DEFAULT_BIND: &str = "127.0.0.1:3000";
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS config_file_applied (
+            section     TEXT PRIMARY KEY,
+            value       TEXT NOT NULL,
+            applied_ms  INTEGER NOT NULL
+        );
+        "#,
+    )
+}
+
+fn config_path() -> PathBuf {
+    std::env::var("STUDIOCOMMAND_CONFIG")
+        .unwrap_or_else(|_| "/opt/studiocommand/shared/studiocommand.json".to_string())
+        .into()
+}
+
+/// The file as a JSON object; `None` if there is no file.
+fn read_file() -> Result<Option<Map<String, Value>>, String> {
+    let path = config_path();
+    let text = match std::fs::read_to_string(&path) {
+        Ok(t) => t,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("{}: {e}", path.display())),
+    };
+    match serde_json::from_str(&text) {
+        Ok(Value::Object(doc)) => Ok(Some(doc)),
+        Ok(_) => Err(format!("{}: expected a JSON object", path.display())),
+        Err(e) => Err(format!("{}: {e}", path.display())),
+    }
+}
+
+fn engine_str(doc: &Map<String, Value>, key: &str) -> Option<String> {
+    doc.get("engine")?.get(key)?.as_str().map(str::to_string)
+}
+
+/// `engine.bind`: one address, a comma-separated list, or an array of them.
+fn engine_bind(doc: &Map<String, Value>) -> Option<String> {
+    match doc.get("engine")?.get("bind")? {
+        Value::Array(list) => Some(list.iter().filter_map(Value::as_str).collect::<Vec<_>>().join(", ")),
+        v => v.as_str().map(str::to_string),
+    }
+}
+
+// --- Logging -----------------------------------------------------------------------------
+
+static LOG_HANDLE: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+/// `engine.log_level` in effect (`None`: `RUST_LOG`, else `info`).
+static LOG_LEVEL: Mutex<Option<String>> = Mutex::new(None);
+/// Whether stderr output is JSON; fixed at startup.
+static LOG_JSON: OnceLock<bool> = OnceLock::new();
+/// Addresses the listeners were bound to.
+static BOUND: OnceLock<String> = OnceLock::new();
+/// `engine.bind` read at startup, for `bind_addr`.
+static STARTUP_BIND: Mutex<Option<String>> = Mutex::new(None);
+
+fn log_filter(level: Option<&str>) -> Result<EnvFilter, String> {
+    match level {
+        Some(l) => EnvFilter::try_new(l).map_err(|e| format!("invalid log_level {l:?}: {e}")),
+        None => Ok(EnvFilter::from_default_env().add_directive("info".parse().map_err(|e| format!("{e}"))?)),
+    }
+}
+
+/// Set up logging, with the level from the config file if it has one. Called
+/// first thing in `main`; problems with the file are logged once logging works.
+pub fn init_logging() -> anyhow::Result<()> {
+    let file = read_file();
+    let doc = file.as_ref().ok().and_then(|d| d.as_ref());
+    let level = doc.and_then(|d| engine_str(d, "log_level"));
+    *STARTUP_BIND.lock().unwrap_or_else(|e| e.into_inner()) = doc.and_then(engine_bind);
+
+    let (filter, bad_level) = match log_filter(level.as_deref()) {
+        Ok(f) => (f, None),
+        Err(e) => (log_filter(None).map_err(anyhow::Error::msg)?, Some(e)),
+    };
+    let json = logbuf::json_format(doc.and_then(|d| engine_str(d, "log_format")).as_deref());
+    let (layer, handle) = reload::Layer::new(filter);
+    tracing_subscriber::registry()
+        .with(layer)
+        .with(json.then(|| tracing_subscriber::fmt::layer().json()))
+        .with((!json).then(tracing_subscriber::fmt::layer))
+        .with(logbuf::RingLayer)
+        .init();
+    let _ = LOG_JSON.set(json);
+    let _ = LOG_HANDLE.set(handle);
+
+    if let Err(e) = &file {
+        tracing::warn!("config file: {e}");
+    }
+    match bad_level {
+        Some(e) => tracing::warn!("config file: {e}"),
+        None => *LOG_LEVEL.lock().unwrap_or_else(|e| e.into_inner()) = level,
+    }
+    Ok(())
+}
+
+fn set_log_level(level: Option<&str>) -> Result<(), String> {
+    let filter = log_filter(level)?;
+    let handle = LOG_HANDLE.get().ok_or("logging is not set up")?;
+    handle.reload(filter).map_err(|e| e.to_string())?;
+    *LOG_LEVEL.lock().unwrap_or_else(|e| e.into_inner()) = level.map(str::to_string);
+    Ok(())
+}
+
+/// Listen addresses: `STUDIOCOMMAND_BIND`, else `engine.bind`, else loopback
+/// (see `serve::parse_binds`).
+fn effective_bind(file_bind: Option<String>) -> String {
+    std::env::var("STUDIOCOMMAND_BIND").ok().or(file_bind).unwrap_or_else(|| DEFAULT_BIND.to_string())
+}
+
+/// The addresses to bind at startup (remembered to tell a later change apart).
+pub(crate) fn bind_addr() -> String {
+    let addr = effective_bind(STARTUP_BIND.lock().unwrap_or_else(|e| e.into_inner()).clone());
+    let _ = BOUND.set(addr.clone());
+    addr
+}
+
+// --- Apply -------------------------------------------------------------------------------
+
+#[derive(Serialize, Default)]
+struct Outcome {
+    applied: Vec<String>,
+    unchanged: Vec<String>,
+    errors: Vec<Value>,
+    ignored: Vec<String>,
+    restart_required: Vec<Value>,
+}
+
+async fn recorded() -> Result<Map<String, Value>, String> {
+    db::call(|conn| -> anyhow::Result<Map<String, Value>> {
+        crate::db_init(conn)?;
+        let mut stmt = conn.prepare("SELECT section, value FROM config_file_applied")?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows.into_iter().map(|(s, v)| (s, Value::String(v))).collect())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())
+}
+
+async fn record(section: &str, text: String) -> Result<(), String> {
+    let section = section.to_string();
+    db::call(move |conn| -> anyhow::Result<()> {
+        crate::db_init(conn)?;
+        conn.execute(
+            "INSERT INTO config_file_applied (section, value, applied_ms) VALUES (?1, ?2, ?3)
+             ON CONFLICT(section) DO UPDATE SET value=excluded.value, applied_ms=excluded.applied_ms",
+            params![section, text, unix_ms_now() as i64],
+        )?;
+        Ok(())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())
+}
+
+/// Apply the parts of `doc` that changed. `startup`: logging and the listener
+/// were just set up from this same file.
+async fn apply(state: &AppState, mut doc: Map<String, Value>, startup: bool) -> Outcome {
+    let mut out = Outcome::default();
+    let err = |section: &str, error: String| json!({"section": section, "error": error});
+
+    if let Some(f) = doc.get("format").and_then(|f| f.as_u64()) {
+        if f as u32 > settings::FORMAT {
+            out.errors.push(err("format", format!("document format {f} is newer than this build")));
+            return out;
+        }
+    }
+
+    let level = engine_str(&doc, "log_level");
+    if let Some(engine) = doc.remove("engine") {
+        match engine.as_object() {
+            Some(e) => out.ignored.extend(
+                e.keys().filter(|k| !matches!(k.as_str(), "log_level" | "log_format" | "bind")).map(|k| format!("engine.{k}")),
+            ),
+            None => out.errors.push(err("engine", "expected an object".into())),
+        }
+    }
+    if !startup && *LOG_LEVEL.lock().unwrap_or_else(|e| e.into_inner()) != level {
+        match set_log_level(level.as_deref()) {
+            Ok(()) => out.applied.push("engine.log_level".into()),
+            Err(e) => out.errors.push(err("engine.log_level", e)),
+        }
+    }
+    let json = logbuf::json_format(engine_str(&doc, "log_format").as_deref());
+    if let Some(running) = LOG_JSON.get().filter(|j| **j != json) {
+        out.restart_required.push(json!({
+            "setting": "engine.log_format",
+            "running": if *running { "json" } else { "text" },
+            "configured": if json { "json" } else { "text" },
+            "reason": "the log format is chosen at startup",
+        }));
+    }
+    let bind = effective_bind(engine_bind(&doc));
+    if let Some(running) = BOUND.get().filter(|b| **b != bind) {
+        out.restart_required.push(json!({
+            "setting": "engine.bind",
+            "running": running,
+            "configured": bind,
+            "reason": "the HTTP listener is bound at startup",
+        }));
+    }
+
+    let done = match recorded().await {
+        Ok(r) => r,
+        Err(e) => {
+            out.errors.push(err("config_file_applied", e));
+            return out;
+        }
+    };
+    for section in settings::SECTIONS {
+        let Some(v) = doc.remove(*section) else { continue };
+        let text = v.to_string();
+        if done.get(*section).and_then(|d| d.as_str()) == Some(text.as_str()) {
+            out.unchanged.push(section.to_string());
+            continue;
+        }
+        match settings::apply_section(state, section, v).await {
+            Ok(failed) => {
+                for (item, error) in failed {
+                    out.errors.push(json!({"section": section, "item": item, "error": error}));
+                }
+                if let Err(e) = record(section, text).await {
+                    tracing::warn!("config file: cannot record {section}: {e}");
+                }
+                out.applied.push(section.to_string());
+                if *section == "output" && state.output.lock().await.encoder.is_some() {
+                    out.restart_required.push(json!({
+                        "setting": "output",
+                        "reason": "the stream uses the new settings after it is restarted",
+                    }));
+                }
+            }
+            Err(e) => out.errors.push(err(section, e)),
+        }
+    }
+    out.ignored.extend(
+        doc.keys().filter(|k| !matches!(k.as_str(), "format" | "version" | "exported_ms" | "redacted")).cloned(),
+    );
+    out
+}
+
+/// Serializes reloads (a `SIGHUP` during an API reload, or two of either).
+static RELOADING: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Read the file and apply it. `Ok(None)`: there is no file.
+async fn load(state: &AppState, startup: bool) -> Result<Option<Outcome>, String> {
+    let _guard = RELOADING.lock().await;
+    let Some(doc) = read_file()? else { return Ok(None) };
+    let out = apply(state, doc, startup).await;
+    tracing::info!(
+        "config file: {} applied, {} unchanged, {} error(s){}",
+        if out.applied.is_empty() { "nothing".to_string() } else { out.applied.join(", ") },
+        out.unchanged.len(),
+        out.errors.len(),
+        if out.restart_required.is_empty() { "" } else { "; restart required for some settings" },
+    );
+    for e in &out.errors {
+        tracing::warn!("config file: {e}");
+    }
+    Ok(Some(out))
+}
+
+/// Apply the file once at startup, before output and schedulers start.
+pub(crate) async fn apply_at_startup(state: &AppState) {
+    // A read error was already logged by `init_logging`.
+    let _ = load(state, true).await;
+}
+
+/// Reload on `SIGHUP` (`systemctl reload studiocommand`).
+pub(crate) async fn sighup_task(state: AppState) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut hup = match signal(SignalKind::hangup()) {
+            Ok(s) => s,
+            Err(e) => {
+                tracing::warn!("config file: no SIGHUP handler: {e}");
+                return;
+            }
+        };
+        while hup.recv().await.is_some() {
+            tracing::info!("SIGHUP: reloading {}", config_path().display());
+            match load(&state, false).await {
+                Ok(Some(_)) => {}
+                Ok(None) => tracing::warn!("config file: {} not found, nothing to reload", config_path().display()),
+                Err(e) => tracing::warn!("config file: {e}"),
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = state;
+}
+
+/// `POST /api/v1/admin/reload`
+pub(crate) async fn api_reload(State(state): State<AppState>) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let path = config_path().display().to_string();
+    match load(&state, false).await {
+        Ok(Some(out)) => {
+            let mut v = json!(out);
+            v["ok"] = out.errors.is_empty().into();
+            v["file"] = path.into();
+            v["log_level"] = LOG_LEVEL.lock().unwrap_or_else(|e| e.into_inner()).clone().into();
+            Ok(Json(v))
+        }
+        Ok(None) => Err((StatusCode::NOT_FOUND, Json(json!({"ok": false, "error": format!("no config file at {path}")})))),
+        Err(e) => Err((StatusCode::UNPROCESSABLE_ENTITY, Json(json!({"ok": false, "error": e})))),
+    }
+}
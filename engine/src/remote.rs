@@ -0,0 +1,444 @@
+// --- Remote items -----------------------------------------------------------------------
+//
+// A queue item's cart may be an `http://` or `https://` URL, e.g. a newscast
+// MP3 that the network publishes at the same address every hour. Playout
+// never reads from the network itself: a slow or dead server would stall the
+// writer and leave the encoder without audio. Instead the file is fetched to
+// `<data dir>/remote/` shortly before the item is due (`LEAD_MS`), once per
+// queue item, and the local copy is what plays.
+//
+// - Only URLs whose scheme and host are on the allowlist
+//   (`/api/v1/remote/config`) are inserted or fetched. The host list is empty
+//   by default, so remote items stay off until a station names its sources; a
+//   host on the list allows its subdomains too. Redirects are not followed.
+// - A fetch gives up after `timeout_s` and is tried again `RETRY_MS` later
+//   while the item is still due. A copy older than `COPY_MAX_AGE_MS` is never
+//   played, so last hour's newscast cannot go out again.
+// - An item without a usable copy plays `fallback_cart` in its place when one
+//   is set; otherwise it fails like a missing file and is skipped (failures.rs).
+
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+
+use axum::{http::StatusCode, Json};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    estimate_start_times, eventlog, ffmpeg, paths, probe_media, resolve_cart_to_path, storage, unix_ms_now,
+    PlayoutState,
+};
+
+/// Fetch a remote item this long before its estimated start.
+const LEAD_MS: u64 = 3 * 60 * 1000;
+
+/// Queue items looked at for remote carts.
+const AHEAD: usize = 5;
+
+/// Wait before trying a failed fetch again.
+const RETRY_MS: u64 = 15_000;
+
+/// A copy older than this is stale.
+const COPY_MAX_AGE_MS: u64 = 15 * 60 * 1000;
+
+const CONNECT_TIMEOUT_S: u32 = 5;
+
+/// Larger downloads are refused (a newscast is a few MB).
+const MAX_BYTES: u64 = 200 * 1024 * 1024;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct RemoteConfig {
+    /// Allowed schemes: `https`, `http`.
+    #[serde(default = "default_schemes")]
+    pub(crate) schemes: Vec<String>,
+    /// Allowed hosts (and their subdomains). Empty: no remote items.
+    #[serde(default)]
+    pub(crate) domains: Vec<String>,
+    /// Give up on a fetch after this long.
+    #[serde(default = "default_timeout_s")]
+    pub(crate) timeout_s: u32,
+    /// Played in place of a remote item that could not be fetched; empty
+    /// skips the item.
+    #[serde(default)]
+    pub(crate) fallback_cart: String,
+}
+
+fn default_schemes() -> Vec<String> {
+    vec!["https".into()]
+}
+
+fn default_timeout_s() -> u32 {
+    30
+}
+
+impl Default for RemoteConfig {
+    fn default() -> Self {
+        RemoteConfig {
+            schemes: default_schemes(),
+            domains: Vec::new(),
+            timeout_s: default_timeout_s(),
+            fallback_cart: String::new(),
+        }
+    }
+}
+
+fn current() -> &'static RwLock<RemoteConfig> {
+    static CURRENT: OnceLock<RwLock<RemoteConfig>> = OnceLock::new();
+    CURRENT.get_or_init(|| RwLock::new(RemoteConfig::default()))
+}
+
+fn config() -> RemoteConfig {
+    current().read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Whether `cart` is an `http(s)://` URL (allowed or not).
+pub(crate) fn is_url(cart: &str) -> bool {
+    let c = cart.trim().as_bytes();
+    c.get(..7).is_some_and(|p| p.eq_ignore_ascii_case(b"http://"))
+        || c.get(..8).is_some_and(|p| p.eq_ignore_ascii_case(b"https://"))
+}
+
+/// `(scheme, host)` of a URL, lower-cased. URLs with credentials, a port
+/// that is not a number or a host with anything but letters, digits, `-` and
+/// `.` (or an IPv6 literal) are refused, so no parser reads another host.
+fn scheme_host(url: &str) -> Option<(String, String)> {
+    let (scheme, rest) = url.trim().split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    if authority.contains('@') {
+        return None;
+    }
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(v6) => {
+            let (host, rest) = v6.split_once(']')?;
+            let port = if rest.is_empty() { "" } else { rest.strip_prefix(':')? };
+            (host.chars().all(|c| c.is_ascii_hexdigit() || c == ':')).then_some((host, port))?
+        }
+        None => {
+            let (host, port) = authority.split_once(':').unwrap_or((authority, ""));
+            (host.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')).then_some((host, port))?
+        }
+    };
+    (!host.is_empty() && port.chars().all(|c| c.is_ascii_digit()))
+        .then(|| (scheme.to_ascii_lowercase(), host.to_ascii_lowercase()))
+}
+
+/// Whether `url` may be played, or why not.
+pub(crate) fn check(url: &str) -> Result<(), String> {
+    check_with(&config(), url)
+}
+
+fn check_with(cfg: &RemoteConfig, url: &str) -> Result<(), String> {
+    let (scheme, host) = scheme_host(url).ok_or_else(|| format!("{url}: not a usable URL"))?;
+    if !cfg.schemes.iter().any(|s| s.eq_ignore_ascii_case(&scheme)) {
+        return Err(format!("{url}: scheme {scheme} is not allowed"));
+    }
+    let allowed = cfg.domains.iter().any(|d| {
+        let d = d.trim().trim_start_matches('.').to_ascii_lowercase();
+        !d.is_empty() && (host == d || host.ends_with(&format!(".{d}")))
+    });
+    if !allowed {
+        return Err(format!("{url}: host {host} is not on the remote allowlist"));
+    }
+    Ok(())
+}
+
+/// Where the copy of `url` is kept.
+fn copy_path(url: &str) -> PathBuf {
+    use std::hash::{Hash, Hasher};
+
+    let mut h = std::collections::hash_map::DefaultHasher::new();
+    url.trim().hash(&mut h);
+    // Keep a plain extension (".mp3") for anyone looking at the folder.
+    let name = url.split(['?', '#']).next().unwrap_or("").rsplit('/').next().unwrap_or("");
+    let ext = name
+        .rsplit_once('.')
+        .map(|(_, e)| e)
+        .filter(|e| (1..=5).contains(&e.len()) && e.chars().all(|c| c.is_ascii_alphanumeric()))
+        .map(|e| format!(".{}", e.to_ascii_lowercase()))
+        .unwrap_or_default();
+    storage::data_dir().join("remote").join(format!("{:016x}{ext}", h.finish()))
+}
+
+/// The file to play for remote `url`: its fresh local copy, else the
+/// fallback cart, else none.
+pub(crate) fn local_copy(url: &str) -> Option<String> {
+    let copy = copy_path(url);
+    let fresh = check(url).is_ok()
+        && std::fs::metadata(&copy)
+            .and_then(|m| m.modified())
+            .is_ok_and(|t| t.elapsed().is_ok_and(|age| age.as_millis() < COPY_MAX_AGE_MS as u128));
+    if fresh {
+        return Some(paths::to_cart(&copy));
+    }
+    let fallback = config().fallback_cart;
+    let fallback = fallback.trim();
+    (!fallback.is_empty() && !is_url(fallback)).then(|| resolve_cart_to_path(fallback)).flatten()
+}
+
+/// ffmpeg/ffprobe arguments to read `input` (a cart path or a URL).
+pub(crate) fn input_args(input: &str) -> Vec<OsString> {
+    if is_url(input) {
+        let timeout_us = config().timeout_s as u64 * 1_000_000;
+        vec![
+            "-rw_timeout".into(),
+            timeout_us.to_string().into(),
+            "-protocol_whitelist".into(),
+            "http,https,tcp,tls".into(),
+            "-i".into(),
+            input.trim().into(),
+        ]
+    } else {
+        vec!["-i".into(), paths::ffmpeg_input(&paths::from_cart(input))]
+    }
+}
+
+/// Download `url` to its copy, replacing the old one only once the new one
+/// is complete and has audio.
+async fn fetch(url: &str) -> Result<(), String> {
+    check(url)?;
+    let cfg = config();
+    let dest = copy_path(url);
+    let part = dest.with_extension("part");
+    if let Some(dir) = dest.parent() {
+        tokio::fs::create_dir_all(dir).await.map_err(|e| format!("{}: {e}", dir.display()))?;
+    }
+    let protos: Vec<String> = cfg.schemes.iter().map(|s| s.trim().to_ascii_lowercase()).collect();
+    let fut = tokio::process::Command::new(ffmpeg::curl_bin())
+        .args(["-fsS", "--proto", &format!("={}", protos.join(","))])
+        .args(["--connect-timeout", &CONNECT_TIMEOUT_S.to_string()])
+        .args(["--max-time", &cfg.timeout_s.to_string()])
+        .args(["--max-filesize", &MAX_BYTES.to_string(), "-o"])
+        .arg(&part)
+        .arg(url.trim())
+        .kill_on_drop(true)
+        .output();
+    let limit = Duration::from_secs(cfg.timeout_s as u64 + CONNECT_TIMEOUT_S as u64);
+    let out = match tokio::time::timeout(limit, fut).await {
+        Ok(Ok(out)) => out,
+        Ok(Err(e)) => return Err(format!("{}: {e}", ffmpeg::curl_bin())),
+        Err(_) => return Err(format!("{url}: timed out")),
+    };
+    if !out.status.success() {
+        let _ = tokio::fs::remove_file(&part).await;
+        return Err(format!("{url}: {}", String::from_utf8_lossy(&out.stderr).trim()));
+    }
+    match probe_media(&paths::to_cart(&part)).await {
+        Ok(p) if p.has_audio => {}
+        Ok(_) => {
+            let _ = tokio::fs::remove_file(&part).await;
+            return Err(format!("{url}: no audio stream"));
+        }
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&part).await;
+            return Err(e);
+        }
+    }
+    tokio::fs::rename(&part, &dest).await.map_err(|e| format!("{}: {e}", dest.display()))
+}
+
+/// Remove copies nobody has needed for a day.
+fn prune() {
+    let Ok(rd) = std::fs::read_dir(storage::data_dir().join("remote")) else { return };
+    for e in rd.flatten() {
+        let old = e
+            .metadata()
+            .and_then(|m| m.modified())
+            .is_ok_and(|t| t.elapsed().is_ok_and(|age| age > Duration::from_secs(24 * 3600)));
+        if old {
+            let _ = std::fs::remove_file(e.path());
+        }
+    }
+}
+
+/// Fetch remote items as they come due.
+pub(crate) async fn prefetch_task(playout: Arc<tokio::sync::RwLock<PlayoutState>>) {
+    let mut tick = tokio::time::interval(Duration::from_secs(1));
+    // Per queue item: fetched, or when the last attempt failed.
+    let mut fetched: HashMap<Uuid, Option<Instant>> = HashMap::new();
+    let mut pruned = Instant::now();
+    loop {
+        tick.tick().await;
+        let now_ms = unix_ms_now();
+        let due: Vec<(Uuid, String)> = {
+            let p = playout.read().await;
+            if !p.log.iter().take(AHEAD).any(|it| is_url(&it.cart)) {
+                fetched.clear();
+                continue;
+            }
+            let mut log: Vec<_> = p.log.iter().take(AHEAD).cloned().collect();
+            estimate_start_times(&mut log, &p.now, now_ms);
+            log.into_iter()
+                .filter(|it| is_url(&it.cart) && it.start_ms.is_none_or(|s| s <= now_ms + LEAD_MS))
+                .map(|it| (it.id, it.cart))
+                .collect()
+        };
+        fetched.retain(|id, _| due.iter().any(|(d, _)| d == id));
+        for (id, url) in due {
+            match fetched.get(&id) {
+                Some(None) => continue,
+                Some(Some(failed)) if failed.elapsed() < Duration::from_millis(RETRY_MS) => continue,
+                _ => {}
+            }
+            match fetch(&url).await {
+                Ok(()) => {
+                    tracing::info!("remote: fetched {url}");
+                    fetched.insert(id, None);
+                }
+                Err(e) => {
+                    tracing::warn!("remote: {e}");
+                    eventlog::record("remote", format!("fetch failed: {e}"), json!({"item_id": id, "url": url}));
+                    fetched.insert(id, Some(Instant::now()));
+                }
+            }
+        }
+        if pruned.elapsed() > Duration::from_secs(3600) {
+            pruned = Instant::now();
+            let _ = tokio::task::spawn_blocking(prune).await;
+        }
+    }
+}
+
+/// Migration 12: the settings table.
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS remote_config (
+            id      INTEGER PRIMARY KEY CHECK (id = 1),
+            config  TEXT NOT NULL
+        );
+        "#,
+    )
+}
+
+fn db_load_config(conn: &Connection) -> anyhow::Result<RemoteConfig> {
+    crate::db_init(conn)?;
+    let raw: Option<String> =
+        conn.query_row("SELECT config FROM remote_config WHERE id = 1", [], |row| row.get(0)).optional()?;
+    Ok(match raw {
+        Some(r) => serde_json::from_str(&r)?,
+        None => RemoteConfig::default(),
+    })
+}
+
+/// Load the settings at startup.
+pub(crate) async fn load() {
+    match crate::db::call(|conn| db_load_config(conn)).await {
+        Ok(Ok(cfg)) => *current().write().unwrap_or_else(|e| e.into_inner()) = cfg,
+        Ok(Err(e)) => tracing::warn!("remote: failed to load settings: {e}"),
+        Err(e) => tracing::warn!("remote: load task failed: {e}"),
+    }
+}
+
+// --- HTTP API --------------------------------------------------------------------------
+
+pub(crate) async fn api_remote_config_get() -> Result<Json<RemoteConfig>, StatusCode> {
+    Ok(Json(config()))
+}
+
+pub(crate) async fn api_remote_config_set(
+    Json(mut cfg): Json<RemoteConfig>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    cfg.schemes = cfg.schemes.iter().map(|s| s.trim().to_ascii_lowercase()).collect();
+    cfg.domains = cfg.domains.iter().map(|d| d.trim().to_ascii_lowercase()).filter(|d| !d.is_empty()).collect();
+    cfg.fallback_cart = cfg.fallback_cart.trim().to_string();
+    if cfg.schemes.iter().any(|s| s != "http" && s != "https")
+        || !(1..=600).contains(&cfg.timeout_s)
+        || is_url(&cfg.fallback_cart)
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let raw = serde_json::to_string(&cfg).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    crate::db::call(move |conn| -> anyhow::Result<()> {
+        crate::db_init(conn)?;
+        conn.execute(
+            "INSERT INTO remote_config (id, config) VALUES (1, ?1)
+             ON CONFLICT(id) DO UPDATE SET config=excluded.config",
+            params![raw],
+        )?;
+        Ok(())
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    *current().write().unwrap_or_else(|e| e.into_inner()) = cfg.clone();
+    Ok(Json(json!({"ok": true, "config": cfg})))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cfg(schemes: &[&str], domains: &[&str]) -> RemoteConfig {
+        RemoteConfig {
+            schemes: schemes.iter().map(|s| s.to_string()).collect(),
+            domains: domains.iter().map(|d| d.to_string()).collect(),
+            ..RemoteConfig::default()
+        }
+    }
+
+    #[test]
+    fn empty_allowlist_refuses_everything() {
+        assert!(check_with(&RemoteConfig::default(), "https://news.example.com/top.mp3").is_err());
+    }
+
+    #[test]
+    fn scheme_must_be_allowed() {
+        let c = cfg(&["https"], &["example.com"]);
+        assert!(check_with(&c, "https://example.com/a.mp3").is_ok());
+        assert!(check_with(&c, "HTTPS://example.com/a.mp3").is_ok());
+        assert!(check_with(&c, "http://example.com/a.mp3").is_err());
+        assert!(check_with(&c, "ftp://example.com/a.mp3").is_err());
+        assert!(check_with(&cfg(&["http", "https"], &["example.com"]), "http://example.com/a.mp3").is_ok());
+    }
+
+    #[test]
+    fn exact_host_and_subdomains_only() {
+        let c = cfg(&["https"], &["example.com"]);
+        assert!(check_with(&c, "https://example.com/a.mp3").is_ok());
+        assert!(check_with(&c, "https://News.Example.COM/a.mp3").is_ok());
+        assert!(check_with(&c, "https://badexample.com/a.mp3").is_err());
+        assert!(check_with(&c, "https://example.com.evil.net/a.mp3").is_err());
+        assert!(check_with(&c, "https://example.com./a.mp3").is_err());
+        assert!(check_with(&c, "https://com/a.mp3").is_err());
+        // A listed subdomain does not open up its parent.
+        let sub = cfg(&["https"], &["news.example.com"]);
+        assert!(check_with(&sub, "https://example.com/a.mp3").is_err());
+        assert!(check_with(&sub, "https://a.news.example.com/a.mp3").is_ok());
+    }
+
+    #[test]
+    fn ports() {
+        let c = cfg(&["https"], &["example.com"]);
+        assert!(check_with(&c, "https://example.com:8443/a.mp3").is_ok());
+        assert!(check_with(&c, "https://example.com:/a.mp3").is_ok());
+        assert!(check_with(&c, "https://example.com:443.evil.net/a.mp3").is_err());
+        assert!(check_with(&c, "https://evil.net:443/example.com").is_err());
+    }
+
+    #[test]
+    fn userinfo_and_odd_hosts_are_refused() {
+        let c = cfg(&["https", "http"], &["allowed.com"]);
+        assert!(check_with(&c, "http://allowed.com@evil.com/a.mp3").is_err());
+        assert!(check_with(&c, "http://evil.com@allowed.com/a.mp3").is_err());
+        assert!(check_with(&c, "http://user:pw@allowed.com/a.mp3").is_err());
+        assert!(check_with(&c, "http://evil.com\\@allowed.com/a.mp3").is_err());
+        assert!(check_with(&c, "http://evil.com\\.allowed.com/a.mp3").is_err());
+        assert!(check_with(&c, "http://evil.com%2eallowed.com/a.mp3").is_err());
+        assert!(check_with(&c, "http://evil.com?.allowed.com/a.mp3").is_err());
+        assert!(check_with(&c, "http://evil.com#.allowed.com/a.mp3").is_err());
+        assert!(check_with(&c, "http:///allowed.com/a.mp3").is_err());
+    }
+
+    #[test]
+    fn ipv6_literals() {
+        let c = cfg(&["https"], &["::1"]);
+        assert!(check_with(&c, "https://[::1]:8443/a.mp3").is_ok());
+        assert!(check_with(&c, "https://[::1]x/a.mp3").is_err());
+        assert!(check_with(&c, "https://[::1/a.mp3").is_err());
+    }
+}
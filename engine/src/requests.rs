@@ -0,0 +1,328 @@
+// --- Listener requests -----------------------------------------------------------------
+//
+// Listeners (usually through the station website) pick a song from the
+// library and ask for it; an operator decides what airs:
+// - `GET /api/v1/public/library?q=` finds requestable tracks (music tags of
+//   the public feeds only, no paths) and `POST /api/v1/public/requests`
+//   submits one, with an optional name and message,
+// - `GET /api/v1/requests?status=pending` is the moderation queue;
+//   `approve`/`reject` record the decision, and `queue` inserts the track
+//   into the playout queue tagged `REQ` in one call (approving it if needed).
+//
+// The submit side is public like the feeds, so it is defensive: a track that
+// already has an open request cannot be requested again, and the number of
+// open requests is capped so a script cannot flood the moderation queue.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::public::{db_music_tags, public_json};
+use crate::{
+    bump_queue_rev, library, normalize_log_state, persist_queue, resolve_insert_ref, unix_ms_now, AppState,
+};
+
+/// Open (pending or approved) requests accepted before new ones are refused.
+const MAX_OPEN: i64 = 200;
+const MAX_NAME: usize = 60;
+const MAX_MESSAGE: usize = 280;
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS listener_requests (
+            id            INTEGER PRIMARY KEY AUTOINCREMENT,
+            created_ms    INTEGER NOT NULL,
+            track_id      INTEGER NOT NULL,
+            title         TEXT NOT NULL,
+            artist        TEXT NOT NULL,
+            name          TEXT NOT NULL,
+            message       TEXT NOT NULL,
+            status        TEXT NOT NULL,
+            decided_ms    INTEGER,
+            queue_item_id TEXT
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_listener_requests_status ON listener_requests(status);
+        "#,
+    )
+}
+
+#[derive(Serialize)]
+pub(crate) struct ListenerRequest {
+    id: i64,
+    created_ms: u64,
+    track_id: i64,
+    title: String,
+    artist: String,
+    name: String,
+    message: String,
+    /// "pending", "approved", "rejected" or "queued".
+    status: String,
+    decided_ms: Option<u64>,
+    queue_item_id: Option<String>,
+}
+
+fn request_from_row(row: &rusqlite::Row) -> rusqlite::Result<ListenerRequest> {
+    Ok(ListenerRequest {
+        id: row.get(0)?,
+        created_ms: row.get::<_, i64>(1)? as u64,
+        track_id: row.get(2)?,
+        title: row.get(3)?,
+        artist: row.get(4)?,
+        name: row.get(5)?,
+        message: row.get(6)?,
+        status: row.get(7)?,
+        decided_ms: row.get::<_, Option<i64>>(8)?.map(|v| v as u64),
+        queue_item_id: row.get(9)?,
+    })
+}
+
+const COLUMNS: &str = "id, created_ms, track_id, title, artist, name, message, status, decided_ms, queue_item_id";
+
+fn db_get(conn: &Connection, id: i64) -> anyhow::Result<Option<ListenerRequest>> {
+    crate::db_init(conn)?;
+    Ok(conn
+        .query_row(&format!("SELECT {COLUMNS} FROM listener_requests WHERE id = ?1"), params![id], request_from_row)
+        .optional()?)
+}
+
+fn db_set_status(conn: &Connection, id: i64, status: &str, queue_item_id: Option<String>) -> anyhow::Result<()> {
+    conn.execute(
+        "UPDATE listener_requests SET status = ?2, decided_ms = ?3, queue_item_id = COALESCE(?4, queue_item_id)
+         WHERE id = ?1",
+        params![id, status, unix_ms_now() as i64, queue_item_id],
+    )?;
+    Ok(())
+}
+
+async fn with_db<T: Send + 'static>(
+    f: impl FnOnce(&Connection) -> anyhow::Result<T> + Send + 'static,
+) -> anyhow::Result<T> {
+    crate::db::call(move |conn| f(conn)).await?
+}
+
+fn err(code: StatusCode, msg: &str) -> (StatusCode, Json<serde_json::Value>) {
+    (code, Json(json!({"ok": false, "error": msg})))
+}
+
+fn internal(e: anyhow::Error) -> (StatusCode, Json<serde_json::Value>) {
+    tracing::warn!("listener requests: {e}");
+    err(StatusCode::INTERNAL_SERVER_ERROR, "internal error")
+}
+
+// --- Public side -----------------------------------------------------------------------
+
+#[derive(Deserialize)]
+pub(crate) struct PublicLibraryQuery {
+    q: Option<String>,
+    limit: Option<u32>,
+}
+
+/// `GET /api/v1/public/library?q=`: requestable tracks, id/title/artist only.
+pub(crate) async fn api_public_library(Query(q): Query<PublicLibraryQuery>) -> Result<Response, StatusCode> {
+    let limit = q.limit.unwrap_or(20).clamp(1, 50) as usize;
+    let text = q.q.unwrap_or_default();
+    if text.trim().len() < 2 {
+        return Ok(public_json(60, json!({"ok": true, "items": []})));
+    }
+    let items = with_db(move |conn| {
+        let tags = db_music_tags(conn)?;
+        // Over-fetch: non-music matches are dropped afterwards.
+        let found = library::db_search_text(conn, &text, 200)?;
+        Ok(found
+            .into_iter()
+            .filter(|t| tags.iter().any(|m| m.eq_ignore_ascii_case(&t.tag)))
+            .take(limit)
+            .map(|t| json!({"id": t.id, "title": t.title, "artist": t.artist, "dur": t.duration_s}))
+            .collect::<Vec<_>>())
+    })
+    .await
+    .map_err(|e| {
+        tracing::warn!("public library search failed: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(public_json(60, json!({"ok": true, "items": items})))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct SubmitRequest {
+    track_id: i64,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    message: String,
+}
+
+/// `POST /api/v1/public/requests`
+pub(crate) async fn api_public_request_submit(Json(req): Json<SubmitRequest>) -> Response {
+    let name: String = req.name.trim().chars().take(MAX_NAME).collect();
+    let message: String = req.message.trim().chars().take(MAX_MESSAGE).collect();
+    let res = with_db(move |conn| -> anyhow::Result<Result<i64, (StatusCode, &'static str)>> {
+        crate::db_init(conn)?;
+        let Some(track) = library::db_get(conn, req.track_id)? else {
+            return Ok(Err((StatusCode::NOT_FOUND, "track not found")));
+        };
+        if !db_music_tags(conn)?.iter().any(|m| m.eq_ignore_ascii_case(&track.tag)) {
+            return Ok(Err((StatusCode::NOT_FOUND, "track not found")));
+        }
+        let open: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM listener_requests WHERE status IN ('pending', 'approved')",
+            [],
+            |row| row.get(0),
+        )?;
+        if open >= MAX_OPEN {
+            return Ok(Err((StatusCode::SERVICE_UNAVAILABLE, "too many open requests, try again later")));
+        }
+        let dup: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM listener_requests WHERE track_id = ?1 AND status IN ('pending', 'approved')",
+            params![track.id],
+            |row| row.get(0),
+        )?;
+        if dup > 0 {
+            return Ok(Err((StatusCode::CONFLICT, "this song has already been requested")));
+        }
+        conn.execute(
+            "INSERT INTO listener_requests (created_ms, track_id, title, artist, name, message, status)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, 'pending')",
+            params![unix_ms_now() as i64, track.id, track.title, track.artist, name, message],
+        )?;
+        Ok(Ok(conn.last_insert_rowid()))
+    })
+    .await;
+    let cors = [(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")];
+    match res {
+        Ok(Ok(id)) => {
+            tracing::info!(target: "audit", "listener request {id} submitted");
+            (cors, Json(json!({"ok": true, "id": id}))).into_response()
+        }
+        Ok(Err((code, msg))) => (code, cors, Json(json!({"ok": false, "error": msg}))).into_response(),
+        Err(e) => {
+            tracing::warn!("listener request submit failed: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, cors, Json(json!({"ok": false}))).into_response()
+        }
+    }
+}
+
+/// CORS preflight for `POST /api/v1/public/requests` (a JSON POST from
+/// another origin is preflighted by browsers).
+pub(crate) async fn api_public_request_preflight() -> Response {
+    (
+        StatusCode::NO_CONTENT,
+        [
+            (header::ACCESS_CONTROL_ALLOW_ORIGIN, "*"),
+            (header::ACCESS_CONTROL_ALLOW_METHODS, "POST, OPTIONS"),
+            (header::ACCESS_CONTROL_ALLOW_HEADERS, "content-type"),
+            (header::ACCESS_CONTROL_MAX_AGE, "86400"),
+        ],
+    )
+        .into_response()
+}
+
+// --- Operator side ---------------------------------------------------------------------
+
+#[derive(Deserialize)]
+pub(crate) struct RequestListQuery {
+    status: Option<String>,
+    limit: Option<u32>,
+}
+
+/// `GET /api/v1/requests?status=pending`: oldest first, so the moderation
+/// queue reads top to bottom.
+pub(crate) async fn api_requests_list(Query(q): Query<RequestListQuery>) -> Result<Json<serde_json::Value>, StatusCode> {
+    let limit = q.limit.unwrap_or(100).clamp(1, 500);
+    let items = with_db(move |conn| {
+        crate::db_init(conn)?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {COLUMNS} FROM listener_requests WHERE (?1 IS NULL OR status = ?1)
+             ORDER BY id LIMIT ?2"
+        ))?;
+        let rows = stmt.query_map(params![q.status, limit], request_from_row)?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(json!({"ok": true, "items": items})))
+}
+
+/// Approve or reject a request that is still open.
+async fn decide(id: i64, status: &'static str) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    let found = with_db(move |conn| {
+        let Some(r) = db_get(conn, id)? else { return Ok(None) };
+        if r.status == "pending" || r.status == "approved" {
+            db_set_status(conn, id, status, None)?;
+        }
+        Ok(Some(r.status))
+    })
+    .await
+    .map_err(internal)?;
+    match found.as_deref() {
+        None => Err(err(StatusCode::NOT_FOUND, "request not found")),
+        Some("pending" | "approved") => {
+            tracing::info!(target: "audit", "listener request {id} {status}");
+            Ok(Json(json!({"ok": true, "status": status})))
+        }
+        Some(other) => Err(err(StatusCode::CONFLICT, &format!("request is already {other}"))),
+    }
+}
+
+pub(crate) async fn api_request_approve(
+    Path(id): Path<i64>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    decide(id, "approved").await
+}
+
+pub(crate) async fn api_request_reject(
+    Path(id): Path<i64>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    decide(id, "rejected").await
+}
+
+#[derive(Deserialize, Default)]
+pub(crate) struct QueueRequestBody {
+    /// Play right after the current item instead of at the end of the queue.
+    #[serde(default)]
+    next: bool,
+}
+
+/// `POST /api/v1/requests/{id}/queue`: insert the track tagged `REQ`.
+pub(crate) async fn api_request_queue(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    body: Option<Json<QueueRequestBody>>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    let next = body.map(|Json(b)| b.next).unwrap_or(false);
+    let r = with_db(move |conn| db_get(conn, id))
+        .await
+        .map_err(internal)?
+        .ok_or_else(|| err(StatusCode::NOT_FOUND, "request not found"))?;
+    if r.status != "pending" && r.status != "approved" {
+        return Err(err(StatusCode::CONFLICT, &format!("request is already {}", r.status)));
+    }
+    let item = resolve_insert_ref(&format!("lib:{}", r.track_id), "REQ".into())
+        .await
+        .map_err(|errors| (StatusCode::UNPROCESSABLE_ENTITY, Json(json!({"ok": false, "errors": errors}))))?;
+
+    let (item_id, rev) = {
+        let mut p = state.playout.write().await;
+        let at = if next { 1.min(p.log.len()) } else { p.log.len() };
+        let state_name = if p.log.is_empty() { "playing" } else { "queued" };
+        let log_item = item.into_log_item(state_name);
+        let item_id = log_item.id;
+        p.log.insert(at, log_item);
+        normalize_log_state(&mut p);
+        bump_queue_rev(&mut p);
+        persist_queue(p.log.clone()).await;
+        (item_id, p.queue_rev)
+    };
+
+    with_db(move |conn| db_set_status(conn, id, "queued", Some(item_id.to_string()))).await.map_err(internal)?;
+    tracing::info!(target: "audit", "listener request {id} queued as {item_id}");
+    Ok(Json(json!({"ok": true, "id": item_id, "rev": rev})))
+}
@@ -0,0 +1,133 @@
+// --- Resume after restart --------------------------------------------------------------
+//
+// The queue survives a restart (it is persisted), but the position inside the
+// playing item did not: after an update, a crash or a power blip the engine
+// started the item again from the top, so listeners heard the first minutes
+// of a song or a long-form show twice.
+//
+// While an item plays, the writer records (item id, position) every few
+// seconds. At startup the saved row is read once; when the playout writer
+// next starts that same item, the decoder seeks to the saved position, a
+// couple of seconds early so the resume does not land mid-word. The saved
+// position is not used when it is stale (the engine was down a long time, so
+// the item is no longer current in any meaningful sense) or when the item was
+// nearly over anyway.
+
+use std::sync::Mutex;
+
+use rusqlite::{params, Connection, OptionalExtension};
+use uuid::Uuid;
+
+use crate::{db, unix_ms_now};
+
+/// How often the position is written while an item plays.
+const SAVE_EVERY_MS: u64 = 5_000;
+/// A saved position older than this is ignored.
+const MAX_AGE_MS: u64 = 30 * 60_000;
+/// Resume this much before the saved position.
+const REWIND_MS: u64 = 2_000;
+/// Items with less than this left start over instead.
+const MIN_REMAINING_MS: u64 = 5_000;
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS playout_resume (
+            id        INTEGER PRIMARY KEY CHECK (id = 1),
+            item_id   TEXT NOT NULL,
+            pos_ms    INTEGER NOT NULL,
+            saved_ms  INTEGER NOT NULL
+        );
+        "#,
+    )
+}
+
+#[derive(Clone, Copy)]
+struct Saved {
+    item_id: Uuid,
+    pos_ms: u64,
+    saved_ms: u64,
+}
+
+/// The position read at startup, until the writer takes it.
+static PENDING: Mutex<Option<Saved>> = Mutex::new(None);
+/// Last position written: (item, unix ms of the write).
+static LAST_SAVE: Mutex<Option<(Uuid, u64)>> = Mutex::new(None);
+
+/// Read the saved position. Called once at startup, before output starts.
+pub(crate) async fn load() {
+    let res = db::call(|conn| -> anyhow::Result<Option<Saved>> {
+        crate::db_init(conn)?;
+        let row = conn
+            .query_row("SELECT item_id, pos_ms, saved_ms FROM playout_resume WHERE id = 1", [], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?, row.get::<_, i64>(2)?))
+            })
+            .optional()?;
+        Ok(row.and_then(|(id, pos, at)| {
+            Some(Saved { item_id: Uuid::parse_str(&id).ok()?, pos_ms: pos.max(0) as u64, saved_ms: at.max(0) as u64 })
+        }))
+    })
+    .await;
+    match res {
+        Ok(Ok(saved)) => *PENDING.lock().unwrap_or_else(|e| e.into_inner()) = saved,
+        Ok(Err(e)) => tracing::warn!("resume: cannot read saved position: {e}"),
+        Err(e) => tracing::warn!("resume: {e}"),
+    }
+}
+
+/// Where to start `item_id` (ms into the file), if it was interrupted by the
+/// last shutdown. Only ever answers once.
+pub(crate) fn take_offset(item_id: Uuid, dur_ms: u64) -> Option<u64> {
+    let saved = PENDING.lock().unwrap_or_else(|e| e.into_inner()).take()?;
+    if saved.item_id != item_id || unix_ms_now().saturating_sub(saved.saved_ms) > MAX_AGE_MS {
+        return None;
+    }
+    if dur_ms > 0 && saved.pos_ms + MIN_REMAINING_MS >= dur_ms {
+        return None;
+    }
+    let offset = saved.pos_ms.saturating_sub(REWIND_MS);
+    (offset > 0).then_some(offset)
+}
+
+/// Start `item_id` at `pos_ms` when the writer next reaches it, as after a
+/// restart: a standby taking over where the primary stopped (standby.rs).
+pub(crate) fn hand_over(item_id: Uuid, pos_ms: u64) {
+    *PENDING.lock().unwrap_or_else(|e| e.into_inner()) = Some(Saved { item_id, pos_ms, saved_ms: unix_ms_now() });
+}
+
+/// Note the position of the playing item; written at most every
+/// `SAVE_EVERY_MS`, and right away when the item changes.
+pub(crate) fn note(item_id: Uuid, pos_ms: u64) {
+    let now = unix_ms_now();
+    {
+        let mut last = LAST_SAVE.lock().unwrap_or_else(|e| e.into_inner());
+        if matches!(*last, Some((id, at)) if id == item_id && now.saturating_sub(at) < SAVE_EVERY_MS) {
+            return;
+        }
+        *last = Some((item_id, now));
+    }
+    tokio::spawn(write(item_id, pos_ms, now));
+}
+
+/// Save the position right away, e.g. on shutdown.
+pub(crate) async fn save(item_id: Uuid, pos_ms: u64) {
+    let now = unix_ms_now();
+    *LAST_SAVE.lock().unwrap_or_else(|e| e.into_inner()) = Some((item_id, now));
+    write(item_id, pos_ms, now).await;
+}
+
+async fn write(item_id: Uuid, pos_ms: u64, now: u64) {
+    let res = db::call(move |conn| -> anyhow::Result<()> {
+        crate::db_init(conn)?;
+        conn.prepare_cached(
+            "INSERT INTO playout_resume (id, item_id, pos_ms, saved_ms) VALUES (1, ?1, ?2, ?3)
+             ON CONFLICT(id) DO UPDATE SET item_id=excluded.item_id, pos_ms=excluded.pos_ms, saved_ms=excluded.saved_ms",
+        )?
+        .execute(params![item_id.to_string(), pos_ms as i64, now as i64])?;
+        Ok(())
+    })
+    .await;
+    if let Ok(Err(e)) = res {
+        tracing::warn!("resume: cannot save position: {e}");
+    }
+}
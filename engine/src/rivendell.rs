@@ -0,0 +1,191 @@
+// --- Rivendell compatibility ------------------------------------------------------
+//
+// Stations migrating from Rivendell already have their scheduler set up to
+// generate Rivendell logs. Two bridges let them keep that workflow:
+//
+// - Log import: a dump of Rivendell's `LOG_LINES` table for one log, e.g.
+//     mysql -B -e "SELECT * FROM LOG_LINES WHERE LOG_NAME='2024_05_01'" Rivendell
+//   (tab-separated with a header row; CSV works too). Cart lines are resolved
+//   to files; markers, macros and voice tracks are reported, not imported.
+// - A small RML subset over HTTP (`POST /api/v1/rml`) so existing automation
+//   scripts that drive RDAirPlay can drive StudioCommand instead.
+//
+// Rivendell stores audio as `NNNNNN_CCC.wav` (cart/cut) under /var/snd, so a
+// cart number that is not in our carts table is also looked up by that file
+// name in the library.
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::import::{self, ImportEntry, Unresolved};
+use crate::{bump_queue_rev, daylog, normalize_log_state, persist_queue, AppState};
+
+/// Rivendell `LOG_LINES.TYPE` values we care about.
+const RD_TYPE_CART: i64 = 0;
+const RD_TYPE_MARKER: i64 = 1;
+const RD_TYPE_MACRO: i64 = 2;
+const RD_TYPE_TRACK: i64 = 6;
+
+/// A log line we deliberately did not import.
+#[derive(Serialize)]
+struct Skipped {
+    line: usize,
+    kind: &'static str,
+    note: String,
+}
+
+/// `START_TIME` is milliseconds after midnight.
+fn rd_start_time(ms: &str) -> Option<String> {
+    let ms: u64 = ms.trim().parse().ok()?;
+    let s = ms / 1000;
+    (s < 86_400).then(|| format!("{:02}:{:02}:{:02}", s / 3600, s / 60 % 60, s % 60))
+}
+
+/// Try our own cart resolution first, then Rivendell's on-disk naming.
+async fn resolve_rd_cart(line: usize, cart: &str) -> Result<crate::QueueInsertItem, String> {
+    let entry = ImportEntry { line, reference: cart.to_string(), ..Default::default() };
+    match import::resolve_entry(&entry, None).await {
+        Ok(item) => Ok(item),
+        Err(first_err) => {
+            let Ok(n) = cart.trim().parse::<u32>() else { return Err(first_err) };
+            let rd_name = ImportEntry { line, reference: format!("{n:06}_001.wav"), ..Default::default() };
+            import::resolve_entry(&rd_name, None)
+                .await
+                .map_err(|_| format!("cart {cart} not found (also tried {n:06}_001.wav)"))
+        }
+    }
+}
+
+/// Import a Rivendell log dump as the day log for `date`.
+pub(crate) async fn api_daylog_import_rivendell(
+    Path(date): Path<String>,
+    body: String,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    let bad = |msg: String| (StatusCode::BAD_REQUEST, Json(json!({"ok": false, "error": msg})));
+    if !daylog::valid_date(&date) {
+        return Err(bad("date must be YYYY-MM-DD".into()));
+    }
+
+    let delim = if body.lines().next().unwrap_or("").contains('\t') { '\t' } else { ',' };
+    let mut records = import::parse_csv(&body, delim).into_iter();
+    let header: Vec<String> = records.next().map(|(_, h)| h).unwrap_or_default();
+    let col = |name: &str| header.iter().position(|h| h.trim().eq_ignore_ascii_case(name));
+    let Some(cart_col) = col("CART_NUMBER") else {
+        return Err(bad("header must include CART_NUMBER (dump LOG_LINES with column names)".into()));
+    };
+    let (type_col, time_col, comment_col) = (col("TYPE"), col("START_TIME"), col("COMMENT"));
+
+    let mut day = Vec::new();
+    let mut unresolved: Vec<Unresolved> = Vec::new();
+    let mut skipped = Vec::new();
+    for (line, rec) in records {
+        let get = |c: Option<usize>| c.and_then(|i| rec.get(i)).map(|v| v.trim()).unwrap_or("");
+        let kind = get(type_col).parse::<i64>().unwrap_or(RD_TYPE_CART);
+        let note = get(comment_col).to_string();
+        match kind {
+            RD_TYPE_CART => {}
+            RD_TYPE_MARKER => {
+                skipped.push(Skipped { line, kind: "marker", note });
+                continue;
+            }
+            RD_TYPE_MACRO => {
+                skipped.push(Skipped { line, kind: "macro", note });
+                continue;
+            }
+            RD_TYPE_TRACK => {
+                skipped.push(Skipped { line, kind: "voice_track", note });
+                continue;
+            }
+            _ => {
+                skipped.push(Skipped { line, kind: "other", note: format!("TYPE={kind}") });
+                continue;
+            }
+        }
+
+        let cart = get(Some(cart_col));
+        if cart.is_empty() || cart == "0" {
+            unresolved.push(Unresolved { line, entry: cart.to_string(), error: "no cart".into() });
+            continue;
+        }
+        match resolve_rd_cart(line, cart).await {
+            Ok(item) => {
+                let time = rd_start_time(get(time_col)).filter(|t| t != "00:00:00").unwrap_or_default();
+                day.push(daylog::DayLogItem::from_insert(time, item));
+            }
+            Err(error) => unresolved.push(Unresolved { line, entry: cart.to_string(), error }),
+        }
+    }
+
+    if day.is_empty() {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({"ok": false, "date": date, "imported": 0, "unresolved": unresolved, "skipped": skipped})),
+        ));
+    }
+    let imported = day.len();
+    daylog::save_day(date.clone(), day)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"ok": false}))))?;
+
+    Ok(Json(json!({
+        "ok": true, "date": date, "imported": imported, "unresolved": unresolved, "skipped": skipped
+    })))
+}
+
+// --- RML -----------------------------------------------------------------------------
+
+#[derive(Deserialize)]
+pub(crate) struct RmlReq {
+    /// e.g. "PX 1 010001!"
+    command: String,
+}
+
+/// Execute one RML command. Supported: `PN` (start next), `PX` (add cart as
+/// next), `LL` (load log `YYYY_MM_DD` into the queue). The machine number is
+/// accepted and ignored: StudioCommand has a single main log.
+pub(crate) async fn api_rml(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<RmlReq>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    let err = |code: StatusCode, msg: String| (code, Json(json!({"ok": false, "command": req.command, "error": msg})));
+
+    let cmd = req.command.trim().trim_end_matches('!');
+    let args: Vec<&str> = cmd.split_whitespace().collect();
+    match args.as_slice() {
+        ["PN", _mach] => {
+            let mut p = state.playout.write().await;
+            crate::advance_to_next(&mut p, Some("skipped"));
+            Ok(Json(json!({"ok": true, "command": req.command})))
+        }
+        ["PX", _mach, cart] => {
+            let item = resolve_rd_cart(0, cart).await.map_err(|e| err(StatusCode::UNPROCESSABLE_ENTITY, e))?;
+            let mut p = state.playout.write().await;
+            if p.log.is_empty() {
+                p.log.push(item.into_log_item("playing"));
+            } else {
+                p.log.insert(1, item.into_log_item("queued"));
+            }
+            normalize_log_state(&mut p);
+            bump_queue_rev(&mut p);
+            persist_queue(p.log.clone()).await;
+            Ok(Json(json!({"ok": true, "command": req.command, "rev": p.queue_rev})))
+        }
+        ["LL", _mach, log_name, ..] => {
+            // Rivendell's default log naming is YYYY_MM_DD.
+            let date = log_name.replace('_', "-");
+            if !daylog::valid_date(&date) {
+                return Err(err(StatusCode::BAD_REQUEST, format!("log name {log_name} is not YYYY_MM_DD")));
+            }
+            daylog::api_daylog_load(State(state), Path(date), headers, None)
+                .await
+                .map_err(|code| err(code, format!("log {log_name} could not be loaded")))
+        }
+        _ => Err(err(StatusCode::BAD_REQUEST, "unsupported RML command (supported: PN, PX, LL)".into())),
+    }
+}
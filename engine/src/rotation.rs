@@ -0,0 +1,227 @@
+// --- Rotation / separation rules -----------------------------------------------------
+//
+// Rules that top-up and the clock scheduler apply when they choose tracks:
+// - song separation: minimum minutes before the same file may air again,
+// - artist separation: minimum minutes between two songs by the same artist,
+// - category quotas: at most N items of a category in any hour.
+//
+// "Aired" means the persistent play history *plus* everything already waiting
+// in the queue (it will air soon, so it counts as if it aired now) plus what
+// the current selection has picked so far.
+//
+// Rules are preferences, not guarantees: a small folder cannot satisfy a three
+// hour song separation, and dead air is worse than a repeat. Callers try rule-
+// abiding candidates first and fall back to anything not already picked.
+
+use std::collections::{HashMap, HashSet};
+
+use axum::{http::StatusCode, Json};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::history;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct CategoryQuota {
+    category: String,
+    max_per_hour: u32,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct RotationRules {
+    /// Minutes before the same song may repeat (0 = no rule).
+    #[serde(default)]
+    song_separation_min: u32,
+    /// Minutes between songs by the same artist (0 = no rule).
+    #[serde(default)]
+    artist_separation_min: u32,
+    #[serde(default)]
+    quotas: Vec<CategoryQuota>,
+}
+
+fn default_rules() -> RotationRules {
+    RotationRules { song_separation_min: 180, artist_separation_min: 30, quotas: Vec::new() }
+}
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS rotation_rules (
+            id                     INTEGER PRIMARY KEY CHECK (id = 1),
+            song_separation_min    INTEGER NOT NULL,
+            artist_separation_min  INTEGER NOT NULL,
+            quotas                 TEXT NOT NULL
+        );
+        "#,
+    )
+}
+
+fn db_load_rules(conn: &Connection) -> anyhow::Result<RotationRules> {
+    crate::db_init(conn)?;
+    let row = conn
+        .query_row(
+            "SELECT song_separation_min, artist_separation_min, quotas FROM rotation_rules WHERE id = 1",
+            [],
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?, row.get::<_, String>(2)?)),
+        )
+        .optional()?;
+    Ok(match row {
+        Some((song, artist, quotas)) => RotationRules {
+            song_separation_min: song.max(0) as u32,
+            artist_separation_min: artist.max(0) as u32,
+            quotas: serde_json::from_str(&quotas).unwrap_or_default(),
+        },
+        None => default_rules(),
+    })
+}
+
+fn db_save_rules(conn: &Connection, r: &RotationRules) -> anyhow::Result<()> {
+    crate::db_init(conn)?;
+    conn.execute(
+        "INSERT INTO rotation_rules (id, song_separation_min, artist_separation_min, quotas)
+         VALUES (1, ?1, ?2, ?3)
+         ON CONFLICT(id) DO UPDATE SET
+           song_separation_min=excluded.song_separation_min,
+           artist_separation_min=excluded.artist_separation_min,
+           quotas=excluded.quotas",
+        params![r.song_separation_min as i64, r.artist_separation_min as i64, serde_json::to_string(&r.quotas)?],
+    )?;
+    Ok(())
+}
+
+// --- Applying the rules ------------------------------------------------------------
+
+/// Snapshot of what aired recently, checked against the rules.
+#[derive(Clone)]
+pub(crate) struct Separation {
+    rules: RotationRules,
+    now_ms: u64,
+    /// path -> last aired (or queued/picked) millis
+    songs: HashMap<String, u64>,
+    /// lowercased artist -> last aired millis
+    artists: HashMap<String, u64>,
+    /// (category, aired millis)
+    categories: Vec<(String, u64)>,
+    /// Picked by the current selection; never picked twice regardless of rules.
+    picked: HashSet<String>,
+}
+
+impl Separation {
+    pub(crate) fn load(conn: &Connection, now_ms: u64) -> anyhow::Result<Self> {
+        let rules = db_load_rules(conn)?;
+        let window_min = rules.song_separation_min.max(rules.artist_separation_min).max(60) as u64;
+        let mut sep = Separation {
+            rules,
+            now_ms,
+            songs: HashMap::new(),
+            artists: HashMap::new(),
+            categories: Vec::new(),
+            picked: HashSet::new(),
+        };
+        for a in history::db_aired_since(conn, now_ms.saturating_sub(window_min * 60_000))? {
+            sep.note(&a.path, &a.artist, &a.tag, a.started_ms);
+        }
+        Ok(sep)
+    }
+
+    /// Load on the database worker (for async callers).
+    pub(crate) async fn load_now() -> anyhow::Result<Self> {
+        let now_ms = crate::unix_ms_now();
+        crate::db::call(move |conn| Separation::load(conn, now_ms)).await?
+    }
+
+    fn note(&mut self, path: &str, artist: &str, category: &str, at_ms: u64) {
+        let last = self.songs.entry(path.to_string()).or_insert(at_ms);
+        *last = (*last).max(at_ms);
+        if !artist.trim().is_empty() {
+            let last = self.artists.entry(artist.trim().to_lowercase()).or_insert(at_ms);
+            *last = (*last).max(at_ms);
+        }
+        self.categories.push((category.to_string(), at_ms));
+    }
+
+    /// An item that aired at `at_ms` (in a dry run, one that would have).
+    pub(crate) fn note_aired(&mut self, path: &str, artist: &str, category: &str, at_ms: u64) {
+        self.note(path, artist, category, at_ms);
+    }
+
+    /// An item already in the queue: it will air soon, so treat it as airing now.
+    pub(crate) fn note_queued(&mut self, path: &str, artist: &str, category: &str) {
+        self.note(path, artist, category, self.now_ms);
+    }
+
+    /// An item the current selection chose.
+    pub(crate) fn note_picked(&mut self, path: &str, artist: &str, category: &str) {
+        self.note(path, artist, category, self.now_ms);
+        self.picked.insert(path.to_string());
+    }
+
+    /// Evaluate the rules as of `now_ms` (e.g. a future slot's air time).
+    pub(crate) fn set_now(&mut self, now_ms: u64) {
+        self.now_ms = now_ms;
+    }
+
+    pub(crate) fn was_picked(&self, path: &str) -> bool {
+        self.picked.contains(path)
+    }
+
+    /// When `path` last aired (or was queued/picked), if within the loaded window.
+    pub(crate) fn last_aired(&self, path: &str) -> Option<u64> {
+        self.songs.get(path).copied()
+    }
+
+    fn within(&self, last_ms: Option<&u64>, minutes: u32) -> bool {
+        minutes > 0 && last_ms.map(|t| *t + minutes as u64 * 60_000 > self.now_ms).unwrap_or(false)
+    }
+
+    /// Song separation only; cheap, so callers can filter before probing files.
+    pub(crate) fn song_allowed(&self, path: &str) -> bool {
+        !self.picked.contains(path) && !self.within(self.songs.get(path), self.rules.song_separation_min)
+    }
+
+    /// All rules.
+    pub(crate) fn allows(&self, path: &str, artist: &str, category: &str) -> bool {
+        if !self.song_allowed(path) {
+            return false;
+        }
+        if !artist.trim().is_empty()
+            && self.within(self.artists.get(&artist.trim().to_lowercase()), self.rules.artist_separation_min)
+        {
+            return false;
+        }
+        self.rules.quotas.iter().filter(|q| q.category.eq_ignore_ascii_case(category)).all(|q| {
+            let hour_ago = self.now_ms.saturating_sub(3_600_000);
+            let aired = self
+                .categories
+                .iter()
+                .filter(|(c, t)| c.eq_ignore_ascii_case(category) && *t > hour_ago && *t <= self.now_ms)
+                .count();
+            (aired as u32) < q.max_per_hour
+        })
+    }
+}
+
+// --- HTTP API ---------------------------------------------------------------------------
+
+pub(crate) async fn api_rotation_rules_get() -> Result<Json<RotationRules>, StatusCode> {
+    crate::db::call(move |conn| db_load_rules(conn))
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map(Json)
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+pub(crate) async fn api_rotation_rules_set(
+    Json(rules): Json<RotationRules>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    if rules.quotas.iter().any(|q| q.category.trim().is_empty()) {
+        return Err((StatusCode::BAD_REQUEST, Json(json!({"ok": false, "error": "quota needs a category"}))));
+    }
+    let to_save = rules.clone();
+    crate::db::call(move |conn| db_save_rules(conn, &to_save))
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"ok": false}))))?
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"ok": false}))))?;
+    Ok(Json(json!({"ok": true, "rules": rules})))
+}
@@ -0,0 +1,388 @@
+// --- Action scheduler ---------------------------------------------------------------
+//
+// Scheduled events (`events.rs`) put audio on air at set times; this schedules
+// everything else: start/stop the stream output, switch top-up on or off, skip,
+// load the day's log, drop a cart in as next, or record a show. Each entry is a cron spec
+// (see `cron.rs`) plus one action, persisted in `schedule_actions`.
+//
+// Occurrences missed while the engine was down are skipped rather than run
+// late: "stop the stream at 02:00" must not fire at 09:00 after a reboot.
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::clocks::LocalHour;
+use crate::cron::CronSpec;
+use crate::import::{self, ImportEntry};
+use crate::{
+    advance_to_next, bump_queue_rev, cartwall, daylog, db_save_topup_config, macros, normalize_log_state,
+    output_start_internal, output_stop_internal, persist_queue, recorder, standby, unix_ms_now, AppState,
+};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum Action {
+    OutputStart,
+    OutputStop,
+    /// Stop the stream if it runs, else start it (one button, see gpio.rs).
+    OutputToggle,
+    TopupEnable,
+    TopupDisable,
+    Skip,
+    /// Append a day log to the queue; `date` defaults to today.
+    LoadLog {
+        #[serde(default)]
+        date: Option<String>,
+    },
+    /// Insert a cart (or file) as the next item.
+    InsertCart { cart: String },
+    /// Play a cart wall slot over the program (see cartwall.rs).
+    CartwallFire { slot: String },
+    /// Run a macro (see macros.rs).
+    Macro { name: String },
+    /// Record a show (see recorder.rs).
+    Record {
+        show: String,
+        minutes: u32,
+        #[serde(default = "recorder::default_buses")]
+        buses: Vec<String>,
+    },
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct ScheduledAction {
+    #[serde(default)]
+    id: i64,
+    name: String,
+    /// Cron spec, local time.
+    spec: String,
+    action: Action,
+    #[serde(default = "default_true")]
+    enabled: bool,
+    #[serde(default)]
+    last_run_ms: Option<u64>,
+    /// "ok" or the error from the last run.
+    #[serde(default)]
+    last_result: Option<String>,
+    /// Next occurrence, computed on read.
+    #[serde(default)]
+    next_ms: Option<u64>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS schedule_actions (
+            id           INTEGER PRIMARY KEY AUTOINCREMENT,
+            name         TEXT NOT NULL,
+            spec         TEXT NOT NULL,
+            action       TEXT NOT NULL,
+            enabled      INTEGER NOT NULL DEFAULT 1,
+            last_run_ms  INTEGER,
+            last_result  TEXT
+        );
+        "#,
+    )
+}
+
+fn db_list(conn: &Connection) -> anyhow::Result<Vec<ScheduledAction>> {
+    crate::db_init(conn)?;
+    let mut stmt = conn.prepare(
+        "SELECT id, name, spec, action, enabled, last_run_ms, last_result FROM schedule_actions ORDER BY id",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, i64>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, String>(2)?,
+            row.get::<_, String>(3)?,
+            row.get::<_, i64>(4)? != 0,
+            row.get::<_, Option<i64>>(5)?,
+            row.get::<_, Option<String>>(6)?,
+        ))
+    })?;
+    let mut out = Vec::new();
+    for r in rows {
+        let (id, name, spec, action, enabled, last_run_ms, last_result) = r?;
+        // A row whose action no longer parses (e.g. written by a newer build)
+        // is skipped rather than failing the whole list.
+        let Ok(action) = serde_json::from_str(&action) else {
+            tracing::warn!("schedule: ignoring action {id} with unknown type: {action}");
+            continue;
+        };
+        out.push(ScheduledAction {
+            id,
+            name,
+            spec,
+            action,
+            enabled,
+            last_run_ms: last_run_ms.map(|v| v as u64),
+            last_result,
+            next_ms: None,
+        });
+    }
+    Ok(out)
+}
+
+fn db_record_run(conn: &Connection, id: i64, at_ms: u64, result: &str) -> anyhow::Result<()> {
+    crate::db_init(conn)?;
+    conn.execute(
+        "UPDATE schedule_actions SET last_run_ms = ?2, last_result = ?3 WHERE id = ?1",
+        params![id, at_ms as i64, result],
+    )?;
+    Ok(())
+}
+
+async fn with_db<T: Send + 'static>(
+    f: impl FnOnce(&Connection) -> anyhow::Result<T> + Send + 'static,
+) -> anyhow::Result<T> {
+    crate::db::call(move |conn| f(conn)).await?
+}
+
+fn next_occurrence(a: &ScheduledAction, now_ms: u64) -> Option<u64> {
+    let spec = CronSpec::parse(&a.spec).ok()?;
+    spec.next_after(a.last_run_ms.unwrap_or(0).max(now_ms.saturating_sub(60_000)))
+}
+
+// --- Running actions ---------------------------------------------------------------
+
+async fn set_topup_enabled(state: &AppState, enabled: bool) -> Result<(), String> {
+    let cfg = {
+        let mut cfg = state.topup.lock().await;
+        cfg.enabled = enabled;
+        cfg.clone()
+    };
+    crate::db::call(move |conn| db_save_topup_config(conn, &cfg))
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())
+}
+
+pub(crate) async fn run_action(state: &AppState, action: &Action) -> Result<(), String> {
+    let running = matches!(action, Action::OutputToggle) && state.output.lock().await.encoder.is_some();
+    match action {
+        Action::OutputToggle if running => {
+            output_stop_internal(state.output.clone()).await;
+            Ok(())
+        }
+        Action::OutputStart | Action::OutputToggle => output_start_internal(
+            state.output.clone(),
+            state.playout.clone(),
+            state.topup.clone(),
+            state.topup_stats.clone(),
+            state.pcm_tx.clone(),
+        )
+        .await
+        .or_else(|code| if code == StatusCode::CONFLICT { Ok(()) } else { Err(code) })
+        .map_err(|code| format!("output start failed ({code})")),
+        Action::OutputStop => {
+            output_stop_internal(state.output.clone()).await;
+            Ok(())
+        }
+        Action::TopupEnable => set_topup_enabled(state, true).await,
+        Action::TopupDisable => set_topup_enabled(state, false).await,
+        Action::Skip => {
+            let mut p = state.playout.write().await;
+            advance_to_next(&mut p, Some("skipped"));
+            Ok(())
+        }
+        Action::LoadLog { date } => {
+            let date = match date {
+                Some(d) => d.clone(),
+                None => LocalHour::at(unix_ms_now()).map(|h| h.date()).ok_or("local time unavailable")?,
+            };
+            daylog::api_daylog_load(State(state.clone()), Path(date.clone()), HeaderMap::new(), None)
+                .await
+                .map(|_| ())
+                .map_err(|code| format!("loading log {date} failed ({code})"))
+        }
+        Action::InsertCart { cart } => {
+            let entry = ImportEntry { reference: cart.clone(), ..Default::default() };
+            let item = import::resolve_entry(&entry, None).await?;
+            let mut p = state.playout.write().await;
+            if p.log.is_empty() {
+                p.log.push(item.into_log_item("playing"));
+            } else {
+                p.log.insert(1, item.into_log_item("queued"));
+            }
+            normalize_log_state(&mut p);
+            bump_queue_rev(&mut p);
+            persist_queue(p.log.clone()).await;
+            Ok(())
+        }
+        Action::CartwallFire { slot } => cartwall::fire(state, slot).await,
+        Action::Macro { name } => macros::fire(state, name).await,
+        Action::Record { show, minutes, buses } => recorder::start(state, show, *minutes, buses).await.map(|_| ()),
+    }
+}
+
+pub(crate) async fn schedule_task(state: AppState) {
+    let mut tick = tokio::time::interval(std::time::Duration::from_secs(1));
+    let mut actions: Vec<ScheduledAction> = Vec::new();
+    let mut loaded_at: Option<std::time::Instant> = None;
+
+    loop {
+        tick.tick().await;
+        if loaded_at.map(|t| t.elapsed().as_secs() >= 15).unwrap_or(true) {
+            match with_db(db_list).await {
+                Ok(v) => actions = v,
+                Err(e) => tracing::warn!("schedule: {e}"),
+            }
+            loaded_at = Some(std::time::Instant::now());
+        }
+
+        let now_ms = unix_ms_now();
+        for a in actions.iter_mut().filter(|a| a.enabled) {
+            let Some(at_ms) = next_occurrence(a, now_ms) else { continue };
+            if at_ms > now_ms {
+                continue;
+            }
+            // A standby leaves the actions to the primary (see standby.rs).
+            let result = if standby::is_standby() {
+                "skipped (standby)".to_string()
+            } else {
+                match run_action(&state, &a.action).await {
+                    Ok(()) => "ok".to_string(),
+                    Err(e) => {
+                        tracing::warn!("schedule: {} failed: {e}", a.name);
+                        e
+                    }
+                }
+            };
+            tracing::info!("schedule: ran {} ({result})", a.name);
+            a.last_run_ms = Some(at_ms);
+            let id = a.id;
+            if let Err(e) = with_db(move |conn| db_record_run(conn, id, at_ms, &result)).await {
+                tracing::warn!("schedule: failed to record run: {e}");
+            }
+        }
+    }
+}
+
+/// Enabled action occurrences from `now_ms` until `until_ms`, as
+/// (time, name, action), for the preview.
+pub(crate) async fn upcoming(now_ms: u64, until_ms: u64) -> anyhow::Result<Vec<(u64, String, Action)>> {
+    let actions = with_db(db_list).await?;
+    let mut out = Vec::new();
+    for a in actions.iter().filter(|a| a.enabled) {
+        let Ok(spec) = CronSpec::parse(&a.spec) else { continue };
+        let mut next = next_occurrence(a, now_ms);
+        while let Some(at_ms) = next.filter(|t| *t < until_ms) {
+            out.push((at_ms, a.name.clone(), a.action.clone()));
+            next = spec.next_after(at_ms);
+        }
+    }
+    out.sort_by_key(|(at_ms, _, _)| *at_ms);
+    Ok(out)
+}
+
+// --- HTTP API -----------------------------------------------------------------------
+
+pub(crate) async fn api_schedule_list() -> Result<Json<Vec<ScheduledAction>>, StatusCode> {
+    let mut actions = with_db(db_list).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let now_ms = unix_ms_now();
+    for a in actions.iter_mut() {
+        a.next_ms = if a.enabled { next_occurrence(a, now_ms) } else { None };
+    }
+    Ok(Json(actions))
+}
+
+fn validate(a: &ScheduledAction) -> Result<(), (StatusCode, Json<serde_json::Value>)> {
+    let bad = |msg: String| Err((StatusCode::BAD_REQUEST, Json(json!({"ok": false, "error": msg}))));
+    if a.name.trim().is_empty() {
+        return bad("name is required".into());
+    }
+    if let Err(e) = CronSpec::parse(&a.spec) {
+        return bad(format!("spec: {e}"));
+    }
+    match &a.action {
+        Action::LoadLog { date: Some(d) } if !daylog::valid_date(d) => bad("date must be YYYY-MM-DD".into()),
+        Action::InsertCart { cart } if cart.trim().is_empty() => bad("cart is required".into()),
+        Action::Record { show, minutes, buses } => recorder::validate(show, *minutes, buses).or_else(bad),
+        _ => Ok(()),
+    }
+}
+
+pub(crate) async fn api_schedule_create(
+    Json(a): Json<ScheduledAction>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    validate(&a)?;
+    let id = with_db(move |conn| {
+        crate::db_init(conn)?;
+        conn.execute(
+            "INSERT INTO schedule_actions (name, spec, action, enabled) VALUES (?1, ?2, ?3, ?4)",
+            params![a.name.trim(), a.spec.trim(), serde_json::to_string(&a.action)?, a.enabled as i64],
+        )?;
+        Ok(conn.last_insert_rowid())
+    })
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"ok": false}))))?;
+    Ok(Json(json!({"ok": true, "id": id})))
+}
+
+pub(crate) async fn api_schedule_put(
+    Path(id): Path<i64>,
+    Json(a): Json<ScheduledAction>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    validate(&a)?;
+    let updated = with_db(move |conn| {
+        crate::db_init(conn)?;
+        let old_spec: Option<String> = conn
+            .query_row("SELECT spec FROM schedule_actions WHERE id = ?1", params![id], |row| row.get(0))
+            .optional()?;
+        let Some(old_spec) = old_spec else { return Ok(false) };
+        let reset = old_spec != a.spec.trim();
+        conn.execute(
+            "UPDATE schedule_actions
+             SET name = ?2, spec = ?3, action = ?4, enabled = ?5,
+                 last_run_ms = CASE WHEN ?6 THEN NULL ELSE last_run_ms END
+             WHERE id = ?1",
+            params![id, a.name.trim(), a.spec.trim(), serde_json::to_string(&a.action)?, a.enabled as i64, reset],
+        )?;
+        Ok(true)
+    })
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"ok": false}))))?;
+    if !updated {
+        return Err((StatusCode::NOT_FOUND, Json(json!({"ok": false}))));
+    }
+    Ok(Json(json!({"ok": true})))
+}
+
+pub(crate) async fn api_schedule_delete(Path(id): Path<i64>) -> Result<Json<serde_json::Value>, StatusCode> {
+    let n = with_db(move |conn| {
+        crate::db_init(conn)?;
+        Ok(conn.execute("DELETE FROM schedule_actions WHERE id = ?1", params![id])?)
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if n == 0 {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(Json(json!({"ok": true})))
+}
+
+/// Run an action now, outside its schedule (for testing a setup).
+pub(crate) async fn api_schedule_run(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    let actions = with_db(db_list).await.map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"ok": false}))))?;
+    let Some(a) = actions.into_iter().find(|a| a.id == id) else {
+        return Err((StatusCode::NOT_FOUND, Json(json!({"ok": false}))));
+    };
+    run_action(&state, &a.action)
+        .await
+        .map_err(|e| (StatusCode::BAD_GATEWAY, Json(json!({"ok": false, "error": e}))))?;
+    Ok(Json(json!({"ok": true})))
+}
@@ -0,0 +1,114 @@
+// --- Secrets at rest -------------------------------------------------------------------
+//
+// Credentials (the Icecast source password) used to sit in SQLite as plain
+// text, so anyone with a copy of the database or a backup had them. They are
+// now sealed with ChaCha20-Poly1305 before they are written and opened when
+// read. The key comes from:
+// - `STUDIOCOMMAND_SECRET_KEY` (base64, 32 bytes), or
+// - the key file `STUDIOCOMMAND_SECRET_KEY_FILE`, by default `secret.key`
+//   next to the database. If it does not exist it is created (mode 0600)
+//   with a random key, so encryption needs no setup.
+//
+// Sealed values are stored as `enc:v1:<base64 nonce+ciphertext>`. A value
+// without that prefix is a plain-text value from an older release: it is
+// still read, and callers re-save it sealed.
+//
+// The key file is not part of database backups on purpose; keep a copy of it
+// with them (or re-enter the password after restoring on another host).
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use base64::{engine::general_purpose::STANDARD as B64, Engine};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+use crate::db_path;
+
+const PREFIX: &str = "enc:v1:";
+const NONCE_LEN: usize = 12;
+
+fn key_file() -> PathBuf {
+    match std::env::var("STUDIOCOMMAND_SECRET_KEY_FILE") {
+        Ok(p) => p.into(),
+        Err(_) => PathBuf::from(db_path()).with_file_name("secret.key"),
+    }
+}
+
+fn decode_key(b64: &str) -> Result<Key, String> {
+    let bytes = B64.decode(b64.trim()).map_err(|e| format!("key is not base64: {e}"))?;
+    if bytes.len() != 32 {
+        return Err(format!("key must be 32 bytes, got {}", bytes.len()));
+    }
+    Ok(*Key::from_slice(&bytes))
+}
+
+fn load_key() -> Result<Key, String> {
+    if let Ok(k) = std::env::var("STUDIOCOMMAND_SECRET_KEY") {
+        return decode_key(&k).map_err(|e| format!("STUDIOCOMMAND_SECRET_KEY: {e}"));
+    }
+    let path = key_file();
+    match std::fs::read_to_string(&path) {
+        Ok(s) => return decode_key(&s).map_err(|e| format!("{}: {e}", path.display())),
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(format!("{}: {e}", path.display())),
+        Err(_) => {}
+    }
+
+    let key = ChaCha20Poly1305::generate_key(&mut OsRng);
+    let mut opts = std::fs::OpenOptions::new();
+    opts.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut opts, 0o600);
+    let mut f = opts.open(&path).map_err(|e| format!("cannot create {}: {e}", path.display()))?;
+    writeln!(f, "{}", B64.encode(key)).map_err(|e| format!("cannot write {}: {e}", path.display()))?;
+    tracing::info!("secrets: created key file {}", path.display());
+    Ok(key)
+}
+
+fn cipher() -> Result<ChaCha20Poly1305, String> {
+    static KEY: OnceLock<Result<Key, String>> = OnceLock::new();
+    let key = KEY.get_or_init(|| {
+        let k = load_key();
+        if let Err(e) = &k {
+            tracing::error!("secrets: no key, credentials cannot be stored or read: {e}");
+        }
+        k
+    });
+    key.as_ref().map(ChaCha20Poly1305::new).map_err(|e| e.clone())
+}
+
+/// Whether `stored` is already sealed (rather than legacy plain text).
+pub(crate) fn is_sealed(stored: &str) -> bool {
+    stored.starts_with(PREFIX)
+}
+
+/// Seal a secret for storage. Empty stays empty (nothing to hide).
+pub(crate) fn seal(plain: &str) -> anyhow::Result<String> {
+    if plain.is_empty() {
+        return Ok(String::new());
+    }
+    let cipher = cipher().map_err(anyhow::Error::msg)?;
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ct = cipher.encrypt(&nonce, plain.as_bytes()).map_err(|_| anyhow::anyhow!("encryption failed"))?;
+    let mut out = nonce.to_vec();
+    out.extend_from_slice(&ct);
+    Ok(format!("{PREFIX}{}", B64.encode(out)))
+}
+
+/// Open a stored secret. Legacy plain text is returned as is.
+pub(crate) fn open(stored: &str) -> anyhow::Result<String> {
+    let Some(b64) = stored.strip_prefix(PREFIX) else {
+        return Ok(stored.to_string());
+    };
+    let raw = B64.decode(b64)?;
+    if raw.len() <= NONCE_LEN {
+        anyhow::bail!("sealed value is truncated");
+    }
+    let (nonce, ct) = raw.split_at(NONCE_LEN);
+    let cipher = cipher().map_err(anyhow::Error::msg)?;
+    let plain = cipher
+        .decrypt(Nonce::from_slice(nonce), ct)
+        .map_err(|_| anyhow::anyhow!("cannot decrypt (was the key changed?)"))?;
+    Ok(String::from_utf8(plain)?)
+}
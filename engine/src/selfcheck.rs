@@ -0,0 +1,228 @@
+// --- Startup self-check and readiness -------------------------------------------------------
+//
+// A fresh install or an update can come up "healthy" (`/health` says OK) and
+// still be unable to put anything on air: ffmpeg missing from the image, a
+// read-only database after a disk error, a top-up folder on a mount that did
+// not come back. Those used to surface minutes later as a cryptic stream
+// error or as silence. The engine now validates its environment once at
+// startup, before output starts:
+// - `ffmpeg`: runs, with the encoder the stream codec needs (`ffmpeg.rs`),
+// - `ffprobe`: runs (needed to validate inserted files),
+// - `database`: opens and takes a write transaction,
+// - `topup`: each enabled top-up folder can be read,
+// - `carts`: the shared carts folder exists,
+// - `output`: the stream settings are complete and in range,
+// - `storage`: no location the engine writes to is nearly full (`storage.rs`).
+//
+// Each check is `ok`, `warn` (the station runs, with reduced function) or
+// `fail` (it cannot go on air). `GET /api/v1/selfcheck` returns the last
+// report, `?refresh=1` runs the checks again. `GET /ready` is 200 once a
+// report without failures exists and 503 otherwise, for load balancers and
+// monitoring; `/health` stays a plain liveness probe.
+
+use std::sync::Mutex;
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{db, ffmpeg, storage, topup_is_reference, unix_ms_now, AppState};
+
+const CARTS_DIR: &str = "/opt/studiocommand/shared/carts";
+
+#[derive(Clone, Serialize)]
+pub(crate) struct Check {
+    name: &'static str,
+    /// "ok", "warn" or "fail".
+    status: &'static str,
+    detail: String,
+}
+
+fn check(name: &'static str, status: &'static str, detail: impl Into<String>) -> Check {
+    Check { name, status, detail: detail.into() }
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct Report {
+    ran_ms: u64,
+    duration_ms: u64,
+    ready: bool,
+    checks: Vec<Check>,
+}
+
+static LAST: Mutex<Option<Report>> = Mutex::new(None);
+
+async fn check_tools(state: &AppState) -> Vec<Check> {
+    let tools = ffmpeg::detect().await;
+    let ffmpeg = match tools.ffmpeg_status() {
+        Ok(v) => {
+            // Only the configured codec matters for going on air; the others are reported.
+            let codec = state.output.lock().await.config.codec.clone();
+            match tools.check_codec(&codec) {
+                Ok(()) => check("ffmpeg", "ok", v),
+                Err(e) => check("ffmpeg", "warn", e),
+            }
+        }
+        Err(e) => check("ffmpeg", "fail", e),
+    };
+    let ffprobe = match tools.ffprobe_status() {
+        Ok(v) => check("ffprobe", "ok", v),
+        Err(e) => check("ffprobe", "warn", e),
+    };
+    vec![ffmpeg, ffprobe]
+}
+
+async fn check_database() -> Check {
+    let res = db::call(|conn| -> anyhow::Result<u32> {
+        crate::db_init(conn)?;
+        if conn.is_readonly(rusqlite::DatabaseName::Main)? {
+            anyhow::bail!("the database is open read-only");
+        }
+        // A header write inside a transaction that is rolled back: proves the
+        // file (and its WAL) can be written without changing anything.
+        let v: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        conn.execute_batch(&format!("BEGIN IMMEDIATE; PRAGMA user_version = {v}; ROLLBACK;"))?;
+        Ok(crate::migrations::current(conn)?)
+    })
+    .await;
+    match res {
+        Ok(Ok(version)) => check("database", "ok", format!("{} (schema {version})", crate::db_path())),
+        Ok(Err(e)) => check("database", "fail", format!("{}: {e}", crate::db_path())),
+        Err(e) => check("database", "fail", e.to_string()),
+    }
+}
+
+async fn check_topup(state: &AppState) -> Check {
+    let cfg = state.topup.lock().await.clone();
+    if !cfg.enabled {
+        return check("topup", "ok", "disabled");
+    }
+    let mut dirs = vec![cfg.dir.clone()];
+    dirs.extend(cfg.sources.iter().map(|s| s.dir.clone()));
+    dirs.extend(cfg.dayparts.iter().map(|d| d.dir.clone()));
+    dirs.retain(|d| !d.trim().is_empty() && !topup_is_reference(d));
+    dirs.sort();
+    dirs.dedup();
+
+    let problems: Vec<String> = dirs
+        .iter()
+        .filter_map(|d| match std::fs::read_dir(d) {
+            Ok(_) => None,
+            Err(e) => Some(format!("{d}: {e}")),
+        })
+        .collect();
+    if problems.is_empty() {
+        check("topup", "ok", format!("{} folder(s) readable", dirs.len()))
+    } else {
+        check("topup", "warn", problems.join("; "))
+    }
+}
+
+fn check_carts() -> Check {
+    if std::path::Path::new(CARTS_DIR).is_dir() {
+        check("carts", "ok", CARTS_DIR)
+    } else {
+        check("carts", "warn", format!("{CARTS_DIR} does not exist; unregistered carts cannot be found"))
+    }
+}
+
+async fn check_output(state: &AppState) -> Check {
+    let cfg = state.output.lock().await.config.clone();
+    let mut problems = Vec::new();
+    if cfg.host.trim().is_empty() {
+        problems.push("host is empty".to_string());
+    }
+    if cfg.port == 0 {
+        problems.push("port is 0".into());
+    }
+    if !cfg.mount.starts_with('/') {
+        problems.push(format!("mount {:?} must start with /", cfg.mount));
+    }
+    if cfg.password.trim().is_empty() {
+        problems.push("password is empty".into());
+    }
+    if !matches!(cfg.codec.as_str(), "mp3" | "aac") {
+        problems.push(format!("unsupported codec {:?}", cfg.codec));
+    }
+    if !(32..=320).contains(&cfg.bitrate_kbps) {
+        problems.push(format!("bitrate {} kbps is out of range (32-320)", cfg.bitrate_kbps));
+    }
+    match (problems.is_empty(), cfg.enabled) {
+        (true, true) => check("output", "ok", format!("{}:{}{} ({})", cfg.host, cfg.port, cfg.mount, cfg.codec)),
+        (true, false) => check("output", "ok", "disabled"),
+        // Output that is off cannot fail to start; the settings still need fixing before it is turned on.
+        (false, false) => check("output", "warn", format!("disabled; {}", problems.join("; "))),
+        (false, true) => check("output", "fail", problems.join("; ")),
+    }
+}
+
+async fn check_storage() -> Check {
+    let warnings = storage::warnings(&storage::check_all().await);
+    if warnings.is_empty() {
+        check("storage", "ok", "enough free space")
+    } else {
+        check("storage", "warn", warnings.join("; "))
+    }
+}
+
+/// Run every check and keep the report.
+pub(crate) async fn run(state: &AppState) -> Report {
+    let started = unix_ms_now();
+    let mut checks = check_tools(state).await;
+    checks.extend([
+        check_database().await,
+        check_topup(state).await,
+        check_carts(),
+        check_output(state).await,
+        check_storage().await,
+    ]);
+    for c in &checks {
+        match c.status {
+            "fail" => tracing::error!("self-check: {}: {}", c.name, c.detail),
+            "warn" => tracing::warn!("self-check: {}: {}", c.name, c.detail),
+            _ => tracing::info!("self-check: {}: {}", c.name, c.detail),
+        }
+    }
+    let report = Report {
+        ran_ms: started,
+        duration_ms: unix_ms_now().saturating_sub(started),
+        ready: checks.iter().all(|c| c.status != "fail"),
+        checks,
+    };
+    *LAST.lock().unwrap_or_else(|e| e.into_inner()) = Some(report.clone());
+    report
+}
+
+#[derive(Deserialize)]
+pub(crate) struct SelfCheckQuery {
+    refresh: Option<u8>,
+}
+
+/// `GET /api/v1/selfcheck[?refresh=1]`
+pub(crate) async fn api_selfcheck(
+    State(state): State<AppState>,
+    Query(q): Query<SelfCheckQuery>,
+) -> Json<serde_json::Value> {
+    let last = LAST.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    let report = match last {
+        Some(r) if q.refresh.unwrap_or(0) == 0 => r,
+        _ => run(&state).await,
+    };
+    Json(json!({"ok": report.ready, "report": report}))
+}
+
+/// `GET /ready`: 200 once the self-check passed, 503 before or after a failure.
+pub(crate) async fn ready() -> (StatusCode, Json<serde_json::Value>) {
+    match LAST.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        Some(r) if r.ready => (StatusCode::OK, Json(json!({"ready": true}))),
+        Some(r) => {
+            let failed: Vec<&Check> = r.checks.iter().filter(|c| c.status == "fail").collect();
+            (StatusCode::SERVICE_UNAVAILABLE, Json(json!({"ready": false, "failed": failed})))
+        }
+        None => (StatusCode::SERVICE_UNAVAILABLE, Json(json!({"ready": false, "reason": "self-check has not run yet"}))),
+    }
+}
@@ -0,0 +1,341 @@
+// --- Listener: TLS and the browser UI -------------------------------------------------------
+//
+// The packaged setup puts nginx in front: it terminates TLS and serves the UI
+// from `/opt/studiocommand/current/web`, and the engine only answers the API
+// on loopback. On a small install (one Pi, one operator) nginx and certbot
+// are most of the moving parts. The engine can now do both jobs itself; the
+// nginx setup keeps working unchanged, since every option here is off by
+// default.
+//
+// TLS, chosen at startup:
+// - `STUDIOCOMMAND_TLS_CERT` + `STUDIOCOMMAND_TLS_KEY`: PEM files (e.g.
+//   certbot's `fullchain.pem` and `privkey.pem`). They are read again on
+//   `SIGHUP`, so a renewal hook only needs `systemctl reload studiocommand`.
+// - `STUDIOCOMMAND_ACME_DOMAINS` (comma-separated): certificates from Let's
+//   Encrypt via TLS-ALPN-01, so the engine itself must be reachable on port
+//   443 for those names. `STUDIOCOMMAND_ACME_EMAIL` is the contact,
+//   `STUDIOCOMMAND_ACME_CACHE` keeps account and certificates (default
+//   `/opt/studiocommand/shared/acme`), `STUDIOCOMMAND_ACME_STAGING=1` uses
+//   the staging directory while trying things out.
+//
+// Listeners: `STUDIOCOMMAND_BIND` (or `engine.bind`) takes several addresses,
+// separated by commas, e.g. `127.0.0.1:3000, 10.20.0.5:3000` for loopback
+// plus a management VLAN, and `unix:/run/studiocommand/engine.sock` for an
+// nginx upstream without TCP. A socket is created with mode
+// `STUDIOCOMMAND_SOCKET_MODE` (octal, default 660) and removed on shutdown;
+// it always speaks plain HTTP. TLS applies to every TCP address.
+//
+// UI: with `STUDIOCOMMAND_WEB_DIR` set (e.g. `/opt/studiocommand/current/web`)
+// every path the API does not answer is served from that folder, with the
+// same clean URLs (`/remote`, `/admin`) and `no-store` caching as the nginx
+// config, and unknown paths fall back to `index.html`.
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use axum::http::{header, HeaderValue};
+use axum::Router;
+use tower_http::services::{ServeDir, ServeFile};
+use tokio::sync::watch;
+use tower_http::set_header::SetResponseHeaderLayer;
+
+/// How long open connections get to finish once shutdown starts.
+const DRAIN: Duration = Duration::from_secs(5);
+
+fn env_nonempty(name: &str) -> Option<String> {
+    std::env::var(name).ok().map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+pub(crate) enum Tls {
+    Off,
+    Files { cert: PathBuf, key: PathBuf },
+    Acme { domains: Vec<String>, email: Option<String>, cache: PathBuf, staging: bool },
+}
+
+impl Tls {
+    pub(crate) fn from_env() -> anyhow::Result<Tls> {
+        let cert = env_nonempty("STUDIOCOMMAND_TLS_CERT");
+        let key = env_nonempty("STUDIOCOMMAND_TLS_KEY");
+        let domains: Vec<String> = env_nonempty("STUDIOCOMMAND_ACME_DOMAINS")
+            .map(|d| d.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect())
+            .unwrap_or_default();
+        match (cert, key, domains.is_empty()) {
+            (Some(_), Some(_), false) => {
+                anyhow::bail!("set either STUDIOCOMMAND_TLS_CERT/KEY or STUDIOCOMMAND_ACME_DOMAINS, not both")
+            }
+            (Some(cert), Some(key), true) => Ok(Tls::Files { cert: cert.into(), key: key.into() }),
+            (Some(_), None, _) | (None, Some(_), _) => {
+                anyhow::bail!("STUDIOCOMMAND_TLS_CERT and STUDIOCOMMAND_TLS_KEY must be set together")
+            }
+            (None, None, false) => Ok(Tls::Acme {
+                domains,
+                email: env_nonempty("STUDIOCOMMAND_ACME_EMAIL"),
+                cache: env_nonempty("STUDIOCOMMAND_ACME_CACHE")
+                    .unwrap_or_else(|| "/opt/studiocommand/shared/acme".to_string())
+                    .into(),
+                staging: env_nonempty("STUDIOCOMMAND_ACME_STAGING").is_some_and(|v| v != "0"),
+            }),
+            (None, None, true) => Ok(Tls::Off),
+        }
+    }
+
+    fn scheme(&self) -> &'static str {
+        match self {
+            Tls::Off => "http",
+            _ => "https",
+        }
+    }
+}
+
+/// The UI folder, if the engine serves the UI itself.
+pub(crate) fn web_dir() -> Option<PathBuf> {
+    env_nonempty("STUDIOCOMMAND_WEB_DIR").map(PathBuf::from)
+}
+
+/// `router` plus the browser UI from `dir` for every path it does not answer.
+pub(crate) fn with_ui(router: Router, dir: PathBuf) -> Router {
+    if !dir.join("index.html").is_file() {
+        tracing::warn!("STUDIOCOMMAND_WEB_DIR={}: no index.html there", dir.display());
+    }
+    let page = |name: &str| ServeFile::new(dir.join(name));
+    let ui = Router::new()
+        .route_service("/remote", page("remote.html"))
+        .route_service("/remote/", page("remote.html"))
+        .route_service("/admin", page("admin.html"))
+        .route_service("/admin/", page("admin.html"))
+        .fallback_service(ServeDir::new(&dir).fallback(page("index.html")))
+        // Same as the nginx config: a cached app.js next to a newer index.html
+        // leaves the UI half-updated.
+        .layer(SetResponseHeaderLayer::overriding(header::CACHE_CONTROL, HeaderValue::from_static("no-store")));
+    router.merge(ui)
+}
+
+/// One listen address: `host:port`, or `unix:/path/to.sock`.
+pub(crate) enum Bind {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+/// `STUDIOCOMMAND_BIND` / `engine.bind`: addresses separated by commas.
+pub(crate) fn parse_binds(spec: &str) -> anyhow::Result<Vec<Bind>> {
+    let mut out = Vec::new();
+    for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        match part.strip_prefix("unix:") {
+            Some(path) if path.starts_with('/') => out.push(Bind::Unix(path.into())),
+            Some(path) => anyhow::bail!("bind {part:?}: the socket path {path:?} must be absolute"),
+            None => out.push(Bind::Tcp(part.parse().map_err(|e| anyhow::anyhow!("bind {part:?}: {e}"))?)),
+        }
+    }
+    if out.is_empty() {
+        anyhow::bail!("no bind address configured");
+    }
+    Ok(out)
+}
+
+/// Permissions of a Unix socket: `STUDIOCOMMAND_SOCKET_MODE` (octal), default 660.
+fn socket_mode() -> u32 {
+    env_nonempty("STUDIOCOMMAND_SOCKET_MODE").and_then(|m| u32::from_str_radix(&m, 8).ok()).unwrap_or(0o660)
+}
+
+fn bind_unix(path: &Path) -> anyhow::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    // Left over from a previous run; anything else at that path is not ours to delete.
+    if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    let listener = tokio::net::UnixListener::bind(path).map_err(|e| anyhow::anyhow!("{}: {e}", path.display()))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(socket_mode()))?;
+    Ok(listener)
+}
+
+fn bind_tcp(addr: SocketAddr) -> anyhow::Result<std::net::TcpListener> {
+    let listener = std::net::TcpListener::bind(addr).map_err(|e| anyhow::anyhow!("{addr}: {e}"))?;
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+/// TLS as set up once for every TCP listener.
+#[derive(Clone)]
+enum Acceptor {
+    Plain,
+    Files(axum_server::tls_rustls::RustlsConfig),
+    Acme(rustls_acme::axum::AxumAcceptor),
+}
+
+async fn acceptor(tls: Tls) -> anyhow::Result<Acceptor> {
+    if !matches!(tls, Tls::Off) {
+        // One provider for every rustls user in the process (WebRTC's DTLS too).
+        let _ = rustls::crypto::ring::default_provider().install_default();
+    }
+    Ok(match tls {
+        Tls::Off => Acceptor::Plain,
+        Tls::Files { cert, key } => {
+            let config = axum_server::tls_rustls::RustlsConfig::from_pem_file(&cert, &key)
+                .await
+                .map_err(|e| anyhow::anyhow!("TLS certificate {} / key {}: {e}", cert.display(), key.display()))?;
+            tokio::spawn(reload_on_sighup(config.clone(), cert, key));
+            Acceptor::Files(config)
+        }
+        Tls::Acme { domains, email, cache, staging } => {
+            use futures_util::StreamExt;
+            let mut acme = rustls_acme::AcmeConfig::new(domains.clone())
+                .contact(email.iter().map(|e| format!("mailto:{e}")))
+                .cache(rustls_acme::caches::DirCache::new(cache))
+                .directory_lets_encrypt(!staging)
+                .state();
+            let acceptor = acme.axum_acceptor(acme.default_rustls_config());
+            tokio::spawn(async move {
+                while let Some(event) = acme.next().await {
+                    match event {
+                        Ok(ok) => tracing::info!("acme: {ok:?}"),
+                        Err(e) => tracing::warn!("acme: {e}"),
+                    }
+                }
+            });
+            tracing::info!("acme: certificates for {} ({})", domains.join(", "), if staging { "staging" } else { "production" });
+            Acceptor::Acme(acceptor)
+        }
+    })
+}
+
+/// Serve `app` on every address in `binds` until `shutdown` completes, then
+/// let open connections finish. Every address is bound before any is served,
+/// so a bad one stops startup.
+pub(crate) async fn serve(
+    binds: Vec<Bind>,
+    app: Router,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> anyhow::Result<()> {
+    let tls = Tls::from_env()?;
+    let scheme = tls.scheme();
+    let acceptor = acceptor(tls).await?;
+
+    let (stop_tx, stop_rx) = watch::channel(false);
+    tokio::spawn(async move {
+        shutdown.await;
+        let _ = stop_tx.send(true);
+    });
+
+    let mut servers = tokio::task::JoinSet::new();
+    for bind in binds {
+        let stopped = stopped(stop_rx.clone());
+        match bind {
+            Bind::Tcp(addr) => {
+                let listener = bind_tcp(addr)?;
+                tracing::info!("StudioCommand engine starting on {scheme}://{addr}");
+                servers.spawn(serve_tcp(listener, app.clone(), acceptor.clone(), stopped));
+            }
+            Bind::Unix(path) => {
+                let listener = bind_unix(&path)?;
+                tracing::info!("StudioCommand engine starting on unix:{}", path.display());
+                servers.spawn(serve_unix(listener, path, app.clone(), stopped));
+            }
+        }
+    }
+    while let Some(res) = servers.join_next().await {
+        res??;
+    }
+    Ok(())
+}
+
+async fn stopped(mut rx: watch::Receiver<bool>) {
+    let _ = rx.wait_for(|stop| *stop).await;
+}
+
+async fn serve_tcp(
+    listener: std::net::TcpListener,
+    app: Router,
+    acceptor: Acceptor,
+    stopped: impl Future<Output = ()> + Send + 'static,
+) -> anyhow::Result<()> {
+    match acceptor {
+        Acceptor::Plain => {
+            let listener = tokio::net::TcpListener::from_std(listener)?;
+            axum::serve(listener, app).with_graceful_shutdown(stopped).await?;
+        }
+        Acceptor::Files(config) => {
+            axum_server::from_tcp_rustls(listener, config)
+                .handle(drain_on(stopped))
+                .serve(app.into_make_service())
+                .await?;
+        }
+        Acceptor::Acme(acceptor) => {
+            axum_server::from_tcp(listener)
+                .acceptor(acceptor)
+                .handle(drain_on(stopped))
+                .serve(app.into_make_service())
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Plain HTTP (with upgrades, for the WebSockets) on a Unix socket; TLS is
+/// for TCP listeners only.
+async fn serve_unix(
+    listener: tokio::net::UnixListener,
+    path: PathBuf,
+    app: Router,
+    stopped: impl Future<Output = ()> + Send + 'static,
+) -> anyhow::Result<()> {
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use hyper_util::server::conn::auto::Builder;
+    use hyper_util::service::TowerToHyperService;
+
+    let builder = Builder::new(TokioExecutor::new());
+    let graceful = hyper_util::server::graceful::GracefulShutdown::new();
+    tokio::pin!(stopped);
+    loop {
+        let stream = tokio::select! {
+            res = listener.accept() => match res {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    tracing::warn!("unix:{}: accept failed: {e}", path.display());
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            },
+            _ = &mut stopped => break,
+        };
+        let conn = builder
+            .serve_connection_with_upgrades(TokioIo::new(stream), TowerToHyperService::new(app.clone()))
+            .into_owned();
+        let conn = graceful.watch(conn);
+        tokio::spawn(async move {
+            if let Err(e) = conn.await {
+                tracing::debug!("unix socket connection: {e}");
+            }
+        });
+    }
+    drop(listener);
+    let _ = std::fs::remove_file(&path);
+    let _ = tokio::time::timeout(DRAIN, graceful.shutdown()).await;
+    Ok(())
+}
+
+/// A server handle that starts a graceful shutdown once `shutdown` completes.
+fn drain_on(shutdown: impl Future<Output = ()> + Send + 'static) -> axum_server::Handle {
+    let handle = axum_server::Handle::new();
+    let h = handle.clone();
+    tokio::spawn(async move {
+        shutdown.await;
+        h.graceful_shutdown(Some(DRAIN));
+    });
+    handle
+}
+
+async fn reload_on_sighup(config: axum_server::tls_rustls::RustlsConfig, cert: PathBuf, key: PathBuf) {
+    use tokio::signal::unix::{signal, SignalKind};
+    let Ok(mut hup) = signal(SignalKind::hangup()) else { return };
+    while hup.recv().await.is_some() {
+        match config.reload_from_pem_file(&cert, &key).await {
+            Ok(()) => tracing::info!("TLS certificate reloaded from {}", cert.display()),
+            Err(e) => tracing::warn!("TLS certificate reload failed, keeping the old one: {e}"),
+        }
+    }
+}
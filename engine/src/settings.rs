@@ -0,0 +1,557 @@
+// --- Configuration export / import ------------------------------------------------------
+//
+// A station's setup is spread over a dozen settings pages: stream output,
+// top-up, timed actions and events, clocks, rotation, tags, carts, metadata push
+// targets, RDS, and more. `GET /api/v1/admin/config` collects all of it into
+// one JSON document, and `POST /api/v1/admin/config` applies such a document.
+// Use it to keep a deployment in git, to set up a second studio the same way,
+// or to rebuild a station after losing the host.
+//
+// Unlike a database backup (`backup.rs`), the document holds settings only,
+// not the queue, library index or history, and it is readable and editable.
+//
+// - Every section is read and written through the same handlers as its own
+//   settings endpoint, so an import gets the same validation and takes effect
+//   the same way (live, no restart).
+// - Runtime fields (ids, last run, next occurrence, delivery status) are left
+//   out of the export.
+// - Secrets (the Icecast source and admin passwords, the STL's SRT
+//   passphrase, the MQTT broker password, chat bot tokens, TuneIn partner
+//   keys, extra HTTP header values, alert channel webhooks, tokens and
+//   passwords) are replaced by `REDACTED` unless `?redact=false`. Importing
+//   a redacted value keeps the secret currently configured, so an exported
+//   document can be shared and re-imported on the same station without
+//   wiping credentials.
+// - Sections missing from the document are left alone. A list section
+//   (`schedule`, `events`, `clocks`, `carts`, `metadata_targets`,
+//   `alert_channels`) replaces the current list; `tags` and `cart_categories`
+//   only add and update.
+// - Before anything changes, the database is snapshotted (`-pre-import`) so
+//   an import can be rolled back with a restore. Sections are applied one by
+//   one; a failing section or item is reported and does not stop the rest.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::{
+    aes67, alerts, announce, backup, bots, breaks, callers, carts, clocks, daylog, events, fallback, gpio, import,
+    ingest, library, metapush, mqtt, public, rds, remote, rotation, schedule, stl, surfaces, tags, transitions,
+    unix_ms_now, AppState,
+};
+
+/// Format version of the exported document.
+pub(crate) const FORMAT: u32 = 1;
+
+/// Stands in for a secret in a redacted export.
+const REDACTED: &str = "REDACTED";
+
+/// Alert channel fields that hold credentials (a webhook URL is one).
+const ALERT_SECRETS: &[&str] = &["url", "bot_token", "password"];
+
+/// Read-only or runtime fields dropped from exported entries.
+const RUNTIME_FIELDS: &[&str] =
+    &["id", "last_run_ms", "last_result", "next_ms", "last_fired_ms", "status", "updated_ms", "last_loaded_date"];
+
+/// Sections in the order they are exported and applied (tags and categories
+/// before the carts that use them, carts before the events that fire them).
+pub(crate) const SECTIONS: &[&str] = &[
+    "output",
+    "output_fallback",
+    "topup",
+    "rotation",
+    "library",
+    "ingest",
+    "tags",
+    "cart_categories",
+    "carts",
+    "clocks",
+    "clock_schedule",
+    "daylog",
+    "csv_mapping",
+    "breaks",
+    "transitions",
+    "remote",
+    "announce",
+    "schedule",
+    "events",
+    "metadata_targets",
+    "rds",
+    "aes67",
+    "stl",
+    "mqtt",
+    "bots",
+    "callers",
+    "gpio",
+    "surfaces",
+    "public_feed",
+    "alerts",
+    "alert_channels",
+];
+
+/// Errors from the settings handlers, as one message.
+trait Failure {
+    fn describe(self) -> String;
+}
+
+impl Failure for StatusCode {
+    fn describe(self) -> String {
+        self.to_string()
+    }
+}
+
+impl Failure for (StatusCode, Json<Value>) {
+    fn describe(self) -> String {
+        match self.1 .0.get("error").and_then(|e| e.as_str()) {
+            Some(e) => format!("{}: {e}", self.0),
+            None => self.0.to_string(),
+        }
+    }
+}
+
+fn done<T, E: Failure>(r: Result<T, E>) -> Result<(), String> {
+    r.map(|_| ()).map_err(Failure::describe)
+}
+
+fn to_value<T: Serialize, E: Failure>(r: Result<Json<T>, E>) -> Result<Value, String> {
+    let Json(v) = r.map_err(Failure::describe)?;
+    serde_json::to_value(v).map_err(|e| e.to_string())
+}
+
+fn parse<T: DeserializeOwned>(v: Value) -> Result<T, String> {
+    serde_json::from_value(v).map_err(|e| format!("invalid: {e}"))
+}
+
+fn strip_runtime(v: &mut Value) {
+    match v {
+        Value::Array(items) => items.iter_mut().for_each(strip_runtime),
+        Value::Object(map) => RUNTIME_FIELDS.iter().for_each(|k| {
+            map.remove(*k);
+        }),
+        _ => {}
+    }
+}
+
+fn is_redacted(v: Option<&Value>) -> bool {
+    v.and_then(|v| v.as_str()) == Some(REDACTED)
+}
+
+/// `Name: value` header lines with the value hidden.
+fn redact_headers(headers: &mut Value) {
+    if let Some(list) = headers.as_array_mut() {
+        for h in list.iter_mut() {
+            if let Some((name, _)) = h.as_str().and_then(|s| s.split_once(':')) {
+                *h = Value::String(format!("{name}: {REDACTED}"));
+            }
+        }
+    }
+}
+
+// --- Export ------------------------------------------------------------------------
+
+async fn export_section(state: &AppState, section: &str) -> Result<Value, String> {
+    match section {
+        "output" => serde_json::to_value(state.output.lock().await.config.clone()).map_err(|e| e.to_string()),
+        "output_fallback" => fallback::export_config().await,
+        "topup" => serde_json::to_value(state.topup.lock().await.clone()).map_err(|e| e.to_string()),
+        "rotation" => to_value(rotation::api_rotation_rules_get().await),
+        "library" => to_value(library::api_library_config_get().await),
+        "ingest" => serde_json::to_value(state.ingest.lock().await.clone()).map_err(|e| e.to_string()),
+        "tags" => to_value(tags::api_tags_list().await),
+        "cart_categories" => to_value(carts::api_cart_categories_list().await),
+        "carts" => {
+            let all = Query(parse(json!({}))?);
+            to_value(carts::api_carts_list(all).await)
+        }
+        "clocks" => to_value(clocks::api_clocks_list().await),
+        "clock_schedule" => to_value(clocks::api_clock_schedule_get().await),
+        "daylog" => to_value(daylog::api_daylog_config_get().await),
+        "csv_mapping" => to_value(import::api_csv_mapping_get().await),
+        "breaks" => to_value(breaks::api_break_config_get().await),
+        "transitions" => to_value(transitions::api_transitions_config_get().await),
+        "remote" => to_value(remote::api_remote_config_get().await),
+        "announce" => to_value(announce::api_announce_config_get().await),
+        "schedule" => to_value(schedule::api_schedule_list().await),
+        "events" => to_value(events::api_events_list().await),
+        "metadata_targets" => to_value(metapush::api_targets_list().await),
+        "rds" => Ok(to_value(rds::api_rds_config_get().await)?.get("config").cloned().unwrap_or(Value::Null)),
+        "stl" => stl::export_config().await,
+        "mqtt" => mqtt::export_config().await,
+        "bots" => bots::export_config().await,
+        "aes67" => Ok(to_value(aes67::api_aes67_config_get().await)?.get("config").cloned().unwrap_or(Value::Null)),
+        "callers" => {
+            Ok(to_value(callers::api_callers_config_get().await)?.get("config").cloned().unwrap_or(Value::Null))
+        }
+        "gpio" => Ok(to_value(gpio::api_gpio_get().await)?.get("config").cloned().unwrap_or(Value::Null)),
+        "surfaces" => {
+            Ok(to_value(surfaces::api_surfaces_config_get().await)?.get("config").cloned().unwrap_or(Value::Null))
+        }
+        "public_feed" => serde_json::to_value(public::api_public_feed_config_get().await.0).map_err(|e| e.to_string()),
+        "alerts" => to_value(alerts::api_alert_config_get().await),
+        "alert_channels" => to_value(alerts::api_channels_list().await),
+        _ => Err("unknown section".into()),
+    }
+}
+
+fn redact(doc: &mut Map<String, Value>) {
+    if let Some(o) = doc.get_mut("output").and_then(|o| o.as_object_mut()) {
+        if o.get("password").and_then(|p| p.as_str()).is_some_and(|p| !p.is_empty()) {
+            o.insert("password".into(), REDACTED.into());
+        }
+    }
+    if let Some(o) = doc.get_mut("output_fallback").and_then(|o| o.as_object_mut()) {
+        if o.get("admin_password").and_then(|p| p.as_str()).is_some_and(|p| !p.is_empty()) {
+            o.insert("admin_password".into(), REDACTED.into());
+        }
+    }
+    if let Some(o) = doc.get_mut("stl").and_then(|o| o.as_object_mut()) {
+        if o.get("passphrase").and_then(|p| p.as_str()).is_some_and(|p| !p.is_empty()) {
+            o.insert("passphrase".into(), REDACTED.into());
+        }
+    }
+    if let Some(o) = doc.get_mut("mqtt").and_then(|o| o.as_object_mut()) {
+        if o.get("password").and_then(|p| p.as_str()).is_some_and(|p| !p.is_empty()) {
+            o.insert("password".into(), REDACTED.into());
+        }
+    }
+    for bot in ["telegram", "discord"] {
+        if let Some(o) = doc.get_mut("bots").and_then(|b| b.get_mut(bot)).and_then(|o| o.as_object_mut()) {
+            if o.get("bot_token").and_then(|t| t.as_str()).is_some_and(|t| !t.is_empty()) {
+                o.insert("bot_token".into(), REDACTED.into());
+            }
+        }
+    }
+    if let Some(targets) = doc.get_mut("metadata_targets").and_then(|t| t.as_array_mut()) {
+        for t in targets.iter_mut().filter_map(|t| t.as_object_mut()) {
+            if t.get("partner_key").and_then(|k| k.as_str()).is_some_and(|k| !k.is_empty()) {
+                t.insert("partner_key".into(), REDACTED.into());
+            }
+            if let Some(h) = t.get_mut("headers") {
+                redact_headers(h);
+            }
+        }
+    }
+    if let Some(channels) = doc.get_mut("alert_channels").and_then(|c| c.as_array_mut()) {
+        for c in channels.iter_mut().filter_map(|c| c.as_object_mut()) {
+            for key in ALERT_SECRETS {
+                if c.get(*key).and_then(|v| v.as_str()).is_some_and(|v| !v.is_empty()) {
+                    c.insert((*key).into(), REDACTED.into());
+                }
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ExportQuery {
+    redact: Option<bool>,
+}
+
+/// `GET /api/v1/admin/config[?redact=false]`
+pub(crate) async fn api_config_export(
+    State(state): State<AppState>,
+    Query(q): Query<ExportQuery>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let redacted = q.redact.unwrap_or(true);
+    let mut doc = Map::new();
+    doc.insert("format".into(), FORMAT.into());
+    doc.insert("version".into(), state.version.clone().into());
+    doc.insert("exported_ms".into(), unix_ms_now().into());
+    doc.insert("redacted".into(), redacted.into());
+    for section in SECTIONS {
+        let mut v = export_section(&state, section).await.map_err(|e| {
+            tracing::warn!("config export: {section}: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"ok": false, "section": section, "error": e})))
+        })?;
+        strip_runtime(&mut v);
+        doc.insert((*section).into(), v);
+    }
+    if redacted {
+        redact(&mut doc);
+    }
+    Ok(Json(Value::Object(doc)))
+}
+
+// --- Import ------------------------------------------------------------------------
+
+#[derive(Serialize)]
+struct ImportError {
+    section: String,
+    /// Name of the failing entry in a list section.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    item: Option<String>,
+    error: String,
+}
+
+/// Failures of a list section, per entry.
+pub(crate) type ItemErrors = Vec<(String, String)>;
+
+fn name_of(v: &Value, key: &str) -> String {
+    v.get(key).and_then(|n| n.as_str()).unwrap_or("?").to_string()
+}
+
+/// Apply one single-valued section.
+async fn import_single(state: &AppState, section: &str, mut v: Value) -> Result<(), String> {
+    match section {
+        "output" => {
+            if is_redacted(v.get("password")) {
+                v["password"] = state.output.lock().await.config.password.clone().into();
+            }
+            done(crate::api_output_set_config(State(state.clone()), Json(parse(v)?)).await)
+        }
+        "output_fallback" => {
+            if is_redacted(v.get("admin_password")) {
+                v["admin_password"] = "".into();
+            }
+            done(fallback::api_fallback_config_set(Json(parse(v)?)).await)
+        }
+        "topup" => done(crate::api_topup_set_config(State(state.clone()), Json(parse(v)?)).await),
+        "rotation" => done(rotation::api_rotation_rules_set(Json(parse(v)?)).await),
+        "library" => done(library::api_library_config_set(Json(parse(v)?)).await),
+        "ingest" => done(ingest::api_ingest_set_config(State(state.clone()), Json(parse(v)?)).await),
+        "clock_schedule" => done(clocks::api_clock_schedule_set(Json(parse(v)?)).await),
+        "daylog" => done(daylog::api_daylog_config_set(Json(parse(v)?)).await),
+        "csv_mapping" => done(import::api_csv_mapping_set(Json(parse(v)?)).await),
+        "breaks" => done(breaks::api_break_config_set(Json(parse(v)?)).await),
+        "transitions" => done(transitions::api_transitions_config_set(Json(parse(v)?)).await),
+        "remote" => done(remote::api_remote_config_set(Json(parse(v)?)).await),
+        "announce" => done(announce::api_announce_config_set(Json(parse(v)?)).await),
+        "rds" => done(rds::api_rds_config_set(Json(parse(v)?)).await),
+        "stl" => {
+            if is_redacted(v.get("passphrase")) {
+                v["passphrase"] = "".into();
+            }
+            done(stl::api_stl_config_set(Json(parse(v)?)).await)
+        }
+        "mqtt" => {
+            if is_redacted(v.get("password")) {
+                v["password"] = "".into();
+            }
+            done(mqtt::api_mqtt_config_set(Json(parse(v)?)).await)
+        }
+        "bots" => {
+            for bot in ["telegram", "discord"] {
+                if is_redacted(v.get(bot).and_then(|b| b.get("bot_token"))) {
+                    v[bot]["bot_token"] = "".into();
+                }
+            }
+            done(bots::api_bots_config_set(Json(parse(v)?)).await)
+        }
+        "aes67" => done(aes67::api_aes67_config_set(Json(parse(v)?)).await),
+        "callers" => done(callers::api_callers_config_set(Json(parse(v)?)).await),
+        "gpio" => done(gpio::api_gpio_config_set(Json(parse(v)?)).await),
+        "surfaces" => done(surfaces::api_surfaces_config_set(Json(parse(v)?)).await),
+        "public_feed" => done(public::api_public_feed_config_set(Json(parse(v)?)).await),
+        "alerts" => done(alerts::api_alert_config_set(Json(parse(v)?)).await),
+        _ => Err("unknown section".into()),
+    }
+}
+
+/// Ids of the current entries of a list section, for removal.
+fn ids(current: &Value, key: &str) -> Vec<Value> {
+    current.as_array().map(|a| a.iter().filter_map(|e| e.get(key).cloned()).collect()).unwrap_or_default()
+}
+
+/// Replace a list section. Returns per-entry failures.
+async fn import_list(state: &AppState, section: &str, items: Vec<Value>) -> Result<ItemErrors, String> {
+    let current = export_section(state, section).await?;
+    let mut failed = Vec::new();
+    match section {
+        "tags" => {
+            for t in items {
+                let code = name_of(&t, "code");
+                if let Err(e) = done(tags::api_tag_put(Path(code.clone()), Json(parse(t)?)).await) {
+                    failed.push((code, e));
+                }
+            }
+            // Like categories: a tag missing from the document may still be in use.
+        }
+        "cart_categories" => {
+            for c in items {
+                let code = name_of(&c, "code");
+                if let Err(e) = done(carts::api_cart_category_put(Path(code.clone()), Json(parse(c)?)).await) {
+                    failed.push((code, e));
+                }
+            }
+            // Added or renamed only: a category missing from the document may
+            // still be in use by carts the operator added by hand.
+        }
+        "carts" => {
+            for id in ids(&current, "cart") {
+                done(carts::api_cart_delete(Path(parse(id)?)).await)?;
+            }
+            for c in items {
+                let cart = name_of(&c, "cart");
+                if let Err(e) = done(carts::api_cart_create(Json(parse(c)?)).await) {
+                    failed.push((cart, e));
+                }
+            }
+        }
+        "clocks" => {
+            let keep: Vec<String> = items.iter().map(|c| name_of(c, "name")).collect();
+            for name in ids(&current, "name") {
+                let name: String = parse(name)?;
+                if !keep.contains(&name) {
+                    done(clocks::api_clock_delete(Path(name)).await)?;
+                }
+            }
+            for c in items {
+                let name = name_of(&c, "name");
+                let req = parse(json!({"slots": c.get("slots").cloned().unwrap_or(Value::Null)}))?;
+                if let Err(e) = done(clocks::api_clock_put(Path(name.clone()), Json(req)).await) {
+                    failed.push((name, e));
+                }
+            }
+        }
+        "schedule" => {
+            for id in ids(&current, "id") {
+                done(schedule::api_schedule_delete(Path(parse(id)?)).await)?;
+            }
+            for a in items {
+                let name = name_of(&a, "name");
+                if let Err(e) = done(schedule::api_schedule_create(Json(parse(a)?)).await) {
+                    failed.push((name, e));
+                }
+            }
+        }
+        "events" => {
+            for id in ids(&current, "id") {
+                done(events::api_event_delete(Path(parse(id)?)).await)?;
+            }
+            for ev in items {
+                let name = name_of(&ev, "name");
+                if let Err(e) = done(events::api_event_create(Json(parse(ev)?)).await) {
+                    failed.push((name, e));
+                }
+            }
+        }
+        "metadata_targets" => {
+            let existing = current.as_array().cloned().unwrap_or_default();
+            for id in ids(&current, "id") {
+                done(metapush::api_target_delete(Path(parse(id)?)).await)?;
+            }
+            for mut t in items {
+                let name = name_of(&t, "name");
+                keep_target_secrets(&mut t, existing.iter().find(|e| name_of(e, "name") == name));
+                if let Err(e) = done(metapush::api_target_create(Json(parse(t)?)).await) {
+                    failed.push((name, e));
+                }
+            }
+        }
+        "alert_channels" => {
+            let existing = current.as_array().cloned().unwrap_or_default();
+            for id in ids(&current, "id") {
+                done(alerts::api_channel_delete(Path(parse(id)?)).await)?;
+            }
+            for mut c in items {
+                let name = name_of(&c, "name");
+                let same = existing.iter().find(|e| name_of(e, "name") == name);
+                for key in ALERT_SECRETS {
+                    if is_redacted(c.get(*key)) {
+                        c[*key] = same.and_then(|e| e.get(*key)).cloned().unwrap_or_else(|| "".into());
+                    }
+                }
+                if let Err(e) = done(alerts::api_channel_create(Json(parse(c)?)).await) {
+                    failed.push((name, e));
+                }
+            }
+        }
+        _ => return Err("unknown section".into()),
+    }
+    Ok(failed)
+}
+
+/// Apply one section of a document (single-valued or list). Returns the
+/// per-entry failures of a list section.
+pub(crate) async fn apply_section(state: &AppState, section: &str, v: Value) -> Result<ItemErrors, String> {
+    match v {
+        Value::Array(items) => import_list(state, section, items).await,
+        Value::Object(_) => import_single(state, section, v).await.map(|_| Vec::new()),
+        _ => Err("expected an object or a list".into()),
+    }
+}
+
+/// Put back secrets a redacted export left out, from the current target of
+/// the same name (or blank if there is none).
+fn keep_target_secrets(t: &mut Value, current: Option<&Value>) {
+    if is_redacted(t.get("partner_key")) {
+        t["partner_key"] = current.and_then(|c| c.get("partner_key")).cloned().unwrap_or_else(|| "".into());
+    }
+    let known: Vec<String> = current
+        .and_then(|c| c.get("headers"))
+        .and_then(|h| h.as_array())
+        .map(|h| h.iter().filter_map(|l| l.as_str().map(str::to_string)).collect())
+        .unwrap_or_default();
+    if let Some(headers) = t.get_mut("headers").and_then(|h| h.as_array_mut()) {
+        headers.retain_mut(|h| {
+            let Some((name, value)) = h.as_str().and_then(|s| s.split_once(':')) else { return true };
+            if value.trim() != REDACTED {
+                return true;
+            }
+            let prefix = format!("{}:", name.trim().to_ascii_lowercase());
+            match known.iter().find(|k| k.to_ascii_lowercase().starts_with(&prefix)) {
+                Some(k) => {
+                    *h = Value::String(k.clone());
+                    true
+                }
+                None => false,
+            }
+        });
+    }
+}
+
+/// `POST /api/v1/admin/config` with a document from `GET`.
+pub(crate) async fn api_config_import(
+    State(state): State<AppState>,
+    Json(doc): Json<Value>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let err = |code: StatusCode, msg: String| (code, Json(json!({"ok": false, "error": msg})));
+    let Some(mut doc) = doc.as_object().cloned() else {
+        return Err(err(StatusCode::BAD_REQUEST, "expected a JSON object".into()));
+    };
+    match doc.get("format").and_then(|f| f.as_u64()) {
+        Some(f) if f as u32 <= FORMAT => {}
+        Some(f) => return Err(err(StatusCode::BAD_REQUEST, format!("document format {f} is newer than this build"))),
+        None => return Err(err(StatusCode::BAD_REQUEST, "not a configuration export (no `format`)".into())),
+    }
+    let unknown: Vec<String> = doc
+        .keys()
+        .filter(|k| !SECTIONS.contains(&k.as_str()) && !matches!(k.as_str(), "format" | "version" | "exported_ms" | "redacted"))
+        .cloned()
+        .collect();
+
+    let (backup, _) = backup::backup_now("-pre-import")
+        .await
+        .map_err(|e| err(StatusCode::INTERNAL_SERVER_ERROR, format!("could not snapshot the database first: {e}")))?;
+
+    let mut applied = Vec::new();
+    let mut errors = Vec::new();
+    for section in SECTIONS {
+        let Some(v) = doc.remove(*section) else { continue };
+        match apply_section(&state, section, v).await {
+            Ok(failed) => {
+                for (item, error) in failed {
+                    errors.push(ImportError { section: section.to_string(), item: Some(item), error });
+                }
+                applied.push(*section);
+            }
+            Err(error) => errors.push(ImportError { section: section.to_string(), item: None, error }),
+        }
+    }
+    tracing::info!(
+        "config import: applied {} section(s), {} error(s); previous state saved as {backup}",
+        applied.len(),
+        errors.len()
+    );
+    Ok(Json(json!({
+        "ok": errors.is_empty(),
+        "applied": applied,
+        "errors": errors,
+        "ignored": unknown,
+        "pre_import_backup": backup,
+    })))
+}
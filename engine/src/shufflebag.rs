@@ -0,0 +1,78 @@
+// --- Top-up shuffle bag --------------------------------------------------------------
+//
+// Picking files independently at random repeats songs long before a folder is
+// exhausted (the birthday paradox: in a 100-song folder a repeat within the
+// first dozen picks is more likely than not). Top-up instead draws each
+// source like a shuffled deck: every file is played once, in random order,
+// before any file comes round again.
+//
+// The files drawn in the current cycle are persisted per source (the folder
+// path or playlist/category reference), so a restart continues the cycle
+// rather than starting a fresh one. New files join the current cycle as
+// undrawn; deleted files drop out when the cycle restarts.
+
+use std::collections::HashSet;
+
+use rusqlite::{params, Connection};
+
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS topup_bag (
+            source  TEXT NOT NULL,
+            path    TEXT NOT NULL,
+            PRIMARY KEY (source, path)
+        );
+        "#,
+    )
+}
+
+/// Files of `source` already drawn this cycle. When every file in `files`
+/// has been drawn, the cycle restarts and the set comes back empty.
+pub(crate) async fn drawn(source: &str, files: &[String]) -> HashSet<String> {
+    let source = source.to_string();
+    let files: HashSet<String> = files.iter().cloned().collect();
+    let res = crate::db::call(move |conn| -> anyhow::Result<HashSet<String>> {
+        crate::db_init(conn)?;
+        let mut stmt = conn.prepare("SELECT path FROM topup_bag WHERE source = ?1")?;
+        let drawn: HashSet<String> = stmt
+            .query_map(params![source], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        if !files.is_empty() && files.iter().all(|f| drawn.contains(f)) {
+            conn.execute("DELETE FROM topup_bag WHERE source = ?1", params![source])?;
+            return Ok(HashSet::new());
+        }
+        Ok(drawn.into_iter().filter(|p| files.contains(p)).collect())
+    })
+    .await;
+    match res {
+        Ok(Ok(d)) => d,
+        Ok(Err(e)) => {
+            tracing::warn!("top-up bag: {e}");
+            HashSet::new()
+        }
+        Err(_) => HashSet::new(),
+    }
+}
+
+/// Mark `(source, path)` pairs as drawn. Only for picks that were actually
+/// appended (a preview must not use up the bag).
+pub(crate) async fn mark_drawn(picks: Vec<(String, String)>) {
+    if picks.is_empty() {
+        return;
+    }
+    let res = crate::db::call(move |conn| -> anyhow::Result<()> {
+        crate::db_init(conn)?;
+        let tx = conn.transaction()?;
+        for (source, path) in &picks {
+            tx.execute("INSERT OR IGNORE INTO topup_bag (source, path) VALUES (?1, ?2)", params![source, path])?;
+        }
+        tx.commit()?;
+        Ok(())
+    })
+    .await;
+    if let Ok(Err(e)) = res {
+        tracing::warn!("top-up bag: failed to record picks: {e}");
+    }
+}
@@ -0,0 +1,93 @@
+// --- Graceful shutdown ----------------------------------------------------------------------
+//
+// On SIGTERM (`systemctl stop`, an update restarting the service) the engine
+// used to exit on the spot: the audio cut mid-word, Icecast saw the source
+// drop without a goodbye and kept listeners waiting on a dead mount, and the
+// resume position (resume.rs) was up to five seconds old. `run` now takes
+// the station off the air in order:
+// 0. with a fallback mount configured (fallback.rs), listeners are moved to
+//    it while the live mount still exists;
+// 1. the playing item fades out over `STUDIOCOMMAND_SHUTDOWN_FADE_MS`
+//    (default 2000), and the writer saves its exact position for resume;
+// 2. the writer closes the encoder's input, so ffmpeg flushes its last frames
+//    and closes the Icecast connection itself (killed if it does not);
+// 3. the Listen Live WebRTC session is closed.
+// The whole sequence is bounded by `STUDIOCOMMAND_SHUTDOWN_TIMEOUT_S`
+// (default 10); whatever is still running then is killed. While it runs the
+// encoder supervisor (supervisor.rs) does not restart anything.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::AppState;
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+fn env_u64(name: &str, default: u64) -> u64 {
+    std::env::var(name).ok().and_then(|v| v.trim().parse().ok()).unwrap_or(default)
+}
+
+/// Whether the engine is shutting down.
+pub(crate) fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+/// How long the playing item fades out for.
+pub(crate) fn fade_ms() -> u64 {
+    env_u64("STUDIOCOMMAND_SHUTDOWN_FADE_MS", 2000).min(10_000)
+}
+
+fn timeout() -> Duration {
+    Duration::from_secs(env_u64("STUDIOCOMMAND_SHUTDOWN_TIMEOUT_S", 10).max(1))
+}
+
+/// Fade, flush and close; returns when done or when the timeout ran out.
+pub(crate) async fn run(state: &AppState) {
+    REQUESTED.store(true, Ordering::SeqCst);
+    let limit = timeout();
+    tracing::info!("shutdown: fading out (at most {} s)", limit.as_secs());
+    let started = std::time::Instant::now();
+    let live = {
+        let o = state.output.lock().await;
+        (o.encoder.is_some() && o.status.state == "connected").then(|| o.config.clone())
+    };
+    if let Some(cfg) = live {
+        crate::fallback::engage(&cfg).await;
+    }
+    if tokio::time::timeout(limit, sequence(state)).await.is_err() {
+        tracing::warn!("shutdown: not done after {} s; stopping the rest", limit.as_secs());
+        crate::output_stop_internal(state.output.clone()).await;
+    }
+    close_webrtc(state).await;
+    tracing::info!("shutdown: done in {} ms", started.elapsed().as_millis());
+}
+
+async fn sequence(state: &AppState) {
+    // The writer sees `requested`, fades, saves the position and returns,
+    // which closes the encoder's stdin.
+    let writer = state.output.lock().await.writer_task.take();
+    if let Some(writer) = writer {
+        let _ = writer.await;
+    }
+    // Then ffmpeg drains and disconnects; the supervisor clears `encoder`
+    // once it has exited.
+    loop {
+        if state.output.lock().await.encoder.is_none() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    tracing::info!("shutdown: encoder closed");
+}
+
+async fn close_webrtc(state: &AppState) {
+    let Some(rt) = state.webrtc.lock().await.take() else { return };
+    rt.stopped.store(true, Ordering::SeqCst);
+    let pc = Arc::clone(&rt.pc);
+    match tokio::time::timeout(Duration::from_secs(2), pc.close()).await {
+        Ok(Ok(())) => tracing::info!("shutdown: Listen Live session closed"),
+        Ok(Err(e)) => tracing::warn!("shutdown: closing Listen Live session failed: {e}"),
+        Err(_) => tracing::warn!("shutdown: closing Listen Live session timed out"),
+    }
+}
@@ -0,0 +1,332 @@
+// --- Dry run ----------------------------------------------------------------------------
+//
+// The schedule preview (preview.rs) shows top-up as blocks and leaves out
+// hours the clock scheduler has not generated yet, which are exactly what an
+// operator wants to check before an unattended weekend: will every clock slot
+// fill, and do the separation rules hold over two days of automation?
+//
+// `GET /api/v1/schedule/simulate?hours=48` runs the scheduling logic itself
+// on a virtual clock. Nothing is decoded or encoded and nothing waits: each
+// item "airs" for its duration and the clock jumps to its end. Along the way
+// - the clock scheduler generates each hour `lead_minutes` before it starts,
+//   with the same generator and separation rules as the automatic push;
+// - top-up picks real tracks whenever the queue drops below `min_queue`,
+//   from the daypart of the virtual time;
+// - events and `topup_enable`/`topup_disable` actions apply as in the preview.
+// Separation is judged against the play history plus everything the run has
+// "aired" so far, so hour 40 sees the picks of hours 1-39.
+//
+// Nothing is written: the queue, play history, generated-hour records and
+// the top-up shuffle bag are left alone, so a run uses up nothing. Files top-up
+// has never probed are probed once (and cached), which is the slow part of a
+// first run over a large folder.
+//
+// The result is one possible air log, not a promise: top-up and the
+// generator choose at random among equally good candidates, and the real
+// queue will see edits, skips and day-log loads the run does not.
+
+use std::collections::{HashSet, VecDeque};
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::clocks::{self, LocalHour};
+use crate::preview::{self, PreviewAction, PreviewItem};
+use crate::rotation::Separation;
+use crate::schedule::Action;
+use crate::{
+    estimate_start_times, fmt_local_hhmmss, parse_dur_to_sec, resolve_cart_to_path, topup_active_len,
+    topup_select_with, unix_ms_now, AppState, LogItem, TopUpAttempt,
+};
+
+/// Longest window a run covers (a long weekend).
+const MAX_HOURS: u64 = 96;
+/// Stop after this many items, whatever the window (guards against a folder
+/// of very short files).
+const MAX_ITEMS: usize = 20_000;
+/// Length assumed for items whose duration is unknown ("0:00").
+const UNKNOWN_DUR_S: u64 = 180;
+
+#[derive(Deserialize)]
+pub(crate) struct SimulateQuery {
+    hours: Option<u64>,
+}
+
+/// An hour the clock scheduler would generate.
+#[derive(Serialize)]
+struct SimHour {
+    hour: String,
+    clock: String,
+    generated_ms: u64,
+    items: usize,
+    empty_slots: Vec<String>,
+}
+
+#[derive(Serialize, Default)]
+struct Summary {
+    queue: usize,
+    clock: usize,
+    topup: usize,
+    event: usize,
+    gap_s: u64,
+    rule_breaks: usize,
+    empty_slots: usize,
+}
+
+/// The file an item plays, as the play history records it.
+fn path_of(it: &LogItem) -> String {
+    resolve_cart_to_path(&it.cart).unwrap_or_else(|| it.cart.clone())
+}
+
+fn bad(code: StatusCode, msg: String) -> (StatusCode, Json<serde_json::Value>) {
+    (code, Json(json!({"ok": false, "error": msg})))
+}
+
+/// The next hour after `hour_ms` (the start of a local hour).
+fn next_hour(hour_ms: u64) -> Option<u64> {
+    LocalHour::at(hour_ms + 3_600_000)?.unix_ms(0)
+}
+
+pub(crate) async fn api_schedule_simulate(
+    State(state): State<AppState>,
+    Query(q): Query<SimulateQuery>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    let hours = q.hours.unwrap_or(24).clamp(1, MAX_HOURS);
+    let now_ms = unix_ms_now();
+    let until_ms = now_ms + hours * 3_600_000;
+    let mut warnings: Vec<String> = Vec::new();
+
+    let (mut log, now) = {
+        let p = state.playout.read().await;
+        (p.log.clone(), p.now.clone())
+    };
+    estimate_start_times(&mut log, &now, now_ms);
+    let topup = state.topup.lock().await.clone();
+
+    // `judge` holds what has aired (really, then virtually) and is what picks
+    // are checked against; each top-up run and generated hour starts from a
+    // copy of it plus the simulated queue, like the real ones start from the
+    // history plus the real queue.
+    let (schedule, mut judge) = crate::db::call(move |conn| -> anyhow::Result<_> {
+        Ok((clocks::db_load_schedule(conn)?, Separation::load(conn, now_ms)?))
+    })
+    .await
+    .map_err(|e| bad(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .map_err(|e| bad(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let mut events = preview::upcoming_events(now_ms, until_ms, &mut warnings).await;
+    let mut actions = preview::upcoming_actions(now_ms, until_ms, &mut warnings).await;
+
+    let mut queue: VecDeque<(LogItem, &'static str)> =
+        log.into_iter().filter(|it| it.state != "played").map(|it| (it, "queue")).collect();
+    let mut t = queue.front().and_then(|(it, _)| it.start_ms).unwrap_or(now_ms);
+    let mut topup_on = topup.enabled;
+    let lead_ms = schedule.lead_minutes as u64 * 60_000;
+    // The automatic push only ever generates the *next* hour.
+    let mut hour_ms = LocalHour::at(now_ms).and_then(|h| h.unix_ms(0)).and_then(next_hour).filter(|_| schedule.enabled);
+    let mut hour_keys: HashSet<String> = HashSet::new();
+
+    let mut items: Vec<PreviewItem> = Vec::new();
+    let mut ran: Vec<PreviewAction> = Vec::new();
+    let mut generated: Vec<SimHour> = Vec::new();
+    let mut summary = Summary::default();
+
+    while t < until_ms {
+        if items.len() >= MAX_ITEMS {
+            warnings.push(format!("stopped after {MAX_ITEMS} items at {}", fmt_local_hhmmss(t)));
+            break;
+        }
+
+        while let Some((at_ms, name, action)) = actions.front().filter(|(at, _, _)| *at <= t).cloned() {
+            actions.pop_front();
+            match action {
+                Action::TopupEnable => topup_on = true,
+                Action::TopupDisable => topup_on = false,
+                _ => {}
+            }
+            ran.push(PreviewAction { at_ms, time: fmt_local_hhmmss(at_ms), name, action });
+        }
+
+        // Clock scheduler: every hour whose lead time has come.
+        while let Some(h) = hour_ms.filter(|h| h.saturating_sub(lead_ms) <= t && *h < until_ms) {
+            hour_ms = next_hour(h);
+            let Some(target) = LocalHour::at(h) else { continue };
+            let key = target.key();
+            // A DST change can map two hour starts to the same local hour.
+            if !hour_keys.insert(key.clone()) {
+                continue;
+            }
+            let mut sep = judge.clone();
+            sep.set_now(t);
+            for (it, _) in &queue {
+                sep.note_queued(&path_of(it), &it.artist, &it.tag);
+            }
+            let schedule = schedule.clone();
+            let res = crate::db::call(move |conn| -> anyhow::Result<_> {
+                // Already pushed: its items are in the real queue above.
+                if clocks::db_hour_generated(conn, &key)? {
+                    return Ok(None);
+                }
+                clocks::generate_hour(conn, &schedule, &target, &mut sep)
+            })
+            .await;
+            match res {
+                Ok(Ok(Some(hour))) => {
+                    for slot in &hour.empty_slots {
+                        warnings.push(format!("clock {} for {}: nothing for {slot}", hour.clock, target.key()));
+                    }
+                    summary.empty_slots += hour.empty_slots.len();
+                    generated.push(SimHour {
+                        hour: target.key(),
+                        clock: hour.clock,
+                        generated_ms: t,
+                        items: hour.items.len(),
+                        empty_slots: hour.empty_slots,
+                    });
+                    queue.extend(hour.items.into_iter().map(|(_, it)| (it.into_log_item("queued"), "clock")));
+                }
+                Ok(Ok(None)) => {}
+                Ok(Err(e)) => warnings.push(format!("clock for {}: {e}", target.key())),
+                Err(e) => warnings.push(format!("clock for {}: {e}", target.key())),
+            }
+        }
+
+        // An event whose time has come plays next, as in the preview.
+        if let Some(ev) = events.front_mut().filter(|e| e.at_ms <= t) {
+            let mut item = std::mem::take(&mut ev.item);
+            item.start_ms = if ev.hard { ev.at_ms.max(t) } else { t };
+            item.end_ms = item.start_ms + parse_dur_to_sec(&item.dur) as u64 * 1000;
+            item.time = fmt_local_hhmmss(item.start_ms);
+            events.pop_front();
+            let path = resolve_cart_to_path(&item.cart).unwrap_or_else(|| item.cart.clone());
+            judge.note_aired(&path, &item.artist, &item.tag, item.start_ms);
+            summary.event += 1;
+            t = item.end_ms;
+            items.push(item);
+            continue;
+        }
+        let next_event = events.front().map(|e| e.at_ms);
+        let next_hard = events.front().filter(|e| e.hard).map(|e| e.at_ms);
+        let next_action = actions.front().map(|(at, _, _)| *at);
+        let next_generate = hour_ms.filter(|h| *h < until_ms).map(|h| h.saturating_sub(lead_ms));
+
+        // Top-up, as the writer runs it when an item starts.
+        let mut topup_error = None;
+        let (cfg, daypart) = topup.for_time(t);
+        if topup_on {
+            let log: Vec<LogItem> = queue.iter().map(|(it, _)| it.clone()).collect();
+            if topup_active_len(&log) < cfg.min_queue {
+                let mut sep = judge.clone();
+                sep.set_now(t);
+                let mut attempt = TopUpAttempt::default();
+                let picked = topup_select_with(&log, &cfg, Some(sep), &mut attempt).await;
+                if picked.is_empty() {
+                    topup_error = Some(attempt.error.unwrap_or_else(|| "nothing to pick".into()));
+                }
+                queue.extend(picked.into_iter().map(|it| (it, "topup")));
+            }
+        }
+
+        if let Some((it, source)) = queue.pop_front() {
+            let mut dur_ms = it.length_ms();
+            if dur_ms == 0 {
+                warnings.push(format!(
+                    "{} {}: unknown duration, assumed {}:{:02}",
+                    fmt_local_hhmmss(t),
+                    it.title,
+                    UNKNOWN_DUR_S / 60,
+                    UNKNOWN_DUR_S % 60
+                ));
+                dur_ms = UNKNOWN_DUR_S * 1000;
+            }
+            let mut end = t + dur_ms;
+            let faded = next_hard.is_some_and(|at| at > t && at < end);
+            if faded {
+                end = next_hard.unwrap_or(end);
+            }
+            let path = path_of(&it);
+            // Operators' own queue items are theirs to judge; only check what
+            // the engine picked.
+            judge.set_now(t);
+            let breaks_rules = source != "queue" && !judge.allows(&path, &it.artist, &it.tag);
+            if breaks_rules {
+                warnings.push(format!(
+                    "{} {} - {} ({source}) breaks the rotation rules",
+                    fmt_local_hhmmss(t),
+                    it.artist,
+                    it.title
+                ));
+                summary.rule_breaks += 1;
+            }
+            judge.note_aired(&path, &it.artist, &it.tag, t);
+            match source {
+                "clock" => summary.clock += 1,
+                "topup" => summary.topup += 1,
+                _ => summary.queue += 1,
+            }
+            items.push(PreviewItem {
+                start_ms: t,
+                end_ms: end,
+                time: fmt_local_hhmmss(t),
+                source,
+                tag: it.tag,
+                title: it.title,
+                artist: it.artist,
+                dur: it.dur,
+                cart: it.cart,
+                faded,
+                daypart: if source == "topup" { daypart } else { None },
+                breaks_rules,
+                ..Default::default()
+            });
+            t = end;
+            continue;
+        }
+
+        // Nothing to play until the next thing that can change that.
+        let mut end =
+            [Some(until_ms), next_event, next_action, next_generate].into_iter().flatten().min().unwrap_or(until_ms);
+        if topup_on {
+            let mut m = (t / 60_000 + 1) * 60_000;
+            while m < end {
+                if topup.for_time(m).1 != daypart {
+                    end = m;
+                    break;
+                }
+                m += 60_000;
+            }
+        }
+        let title = match topup_error.filter(|_| topup_on) {
+            Some(e) => format!("Top-up failing: {e}"),
+            None => "Dead air".into(),
+        };
+        warnings.push(format!("{}-{}: {title}", fmt_local_hhmmss(t), fmt_local_hhmmss(end)));
+        summary.gap_s += (end - t) / 1000;
+        items.push(PreviewItem {
+            start_ms: t,
+            end_ms: end,
+            time: fmt_local_hhmmss(t),
+            source: "gap",
+            title,
+            daypart,
+            ..Default::default()
+        });
+        t = end;
+    }
+
+    Ok(Json(json!({
+        "ok": true,
+        "from_ms": now_ms,
+        "until_ms": until_ms,
+        "items": items,
+        "hours": generated,
+        "actions": ran,
+        "summary": summary,
+        "warnings": warnings,
+    })))
+}
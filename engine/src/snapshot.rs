@@ -0,0 +1,183 @@
+// --- Status snapshot ------------------------------------------------------------------------
+//
+// `GET /api/v1/status` took the playout read lock and cloned the whole queue
+// (twice: `queue` and `log`) on every poll, competing with the audio writer
+// for the write lock. With a
+// few browsers open that was a steady stream of lock traffic in the audio
+// path.
+//
+// `refresh_task` now is the only status reader of `PlayoutState`: every
+// 50 ms it takes the read lock once, copies what changed and lets go. The
+// queue (with estimated air times), recent items, producers (from the
+// registry in producers.rs) and phone calls (callers.rs) are cloned and serialized only when the queue
+// revision moves, or once a second so air times keep up with the playing
+// item. The result goes into a
+// `tokio::sync::watch`, and the status handler reads that without touching
+// the playout lock, however many clients poll. Meters are not part of it;
+// they are read straight from meters.rs. Receivers are only notified when the
+// queue revision or `revision` moves; the play position is refreshed in
+// place, so `changed()` does not fire every 50 ms.
+//
+// For `?since_revision=` (delta polling), the snapshot also counts how often
+// the lists change: `revision` moves whenever one of them does, and each
+// list remembers the revision it last changed at. The queue counts as
+// changed when its revision moves or the air times drift by `DRIFT_MS`; a
+// new estimate that only moves them by a few milliseconds keeps the previous
+// one, so the queue does not change every second. Revisions start at the
+// startup time in Unix millis, so they keep growing across restarts.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use serde_json::value::{to_raw_value, RawValue};
+use tokio::sync::{watch, RwLock};
+
+use crate::callers::{self, Call};
+use crate::producers::{self, ProducerStatus};
+use crate::{
+    changes, estimate_start_times, unix_ms_now, LogItem, NowPlaying, PlayoutState,
+};
+
+const REFRESH: Duration = Duration::from_millis(50);
+/// Air times are re-estimated at least this often.
+const QUEUE_EVERY: Duration = Duration::from_secs(1);
+/// Air times moving by less than this are not a change of the queue.
+const DRIFT_MS: u64 = 1000;
+
+pub(crate) struct Snapshot {
+    pub(crate) queue_rev: u64,
+    /// Moves whenever one of the lists below changes.
+    pub(crate) revision: u64,
+    /// The revision at which each list last changed.
+    pub(crate) changed: Changed,
+    /// Estimated start of the playing item the queue's air times are based on.
+    base_ms: u64,
+    pub(crate) now: NowPlaying,
+    /// The queue with estimated air times, serialized once for `queue` and `log`.
+    pub(crate) log: Box<RawValue>,
+    /// Recently aired items, newest first.
+    pub(crate) recent: Box<RawValue>,
+    pub(crate) producers: Box<RawValue>,
+    pub(crate) callers: Box<RawValue>,
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct Changed {
+    pub(crate) log: u64,
+    pub(crate) recent: u64,
+    pub(crate) producers: u64,
+    pub(crate) callers: u64,
+}
+
+fn empty_list() -> Box<RawValue> {
+    RawValue::from_string("[]".into()).expect("valid JSON")
+}
+
+fn raw<T: Serialize>(v: &T) -> Box<RawValue> {
+    to_raw_value(v).unwrap_or_else(|e| {
+        tracing::warn!("status snapshot: {e}");
+        empty_list()
+    })
+}
+
+/// Queue, recent items, producers and calls.
+type Lists = (Vec<LogItem>, Vec<LogItem>, Vec<ProducerStatus>, Vec<Call>);
+
+fn lists(p: &PlayoutState) -> Lists {
+    (p.log.clone(), p.recent.iter().rev().take(10).cloned().collect(), producers::statuses(), callers::calls())
+}
+
+/// The snapshot for the lists just read; `prev` is the one before it (none at
+/// startup).
+fn build(prev: Option<&Snapshot>, queue_rev: u64, now: NowPlaying, lists: Lists) -> Snapshot {
+    let (mut log, recent, producers, calls) = lists;
+    let now_ms = unix_ms_now();
+    estimate_start_times(&mut log, &now, now_ms);
+    if prev.is_none_or(|p| p.queue_rev != queue_rev) {
+        changes::record(queue_rev, &log);
+    }
+    let base_ms = log.first().and_then(|it| it.start_ms).unwrap_or(now_ms);
+    let (recent, producers, callers) = (raw(&recent), raw(&producers), raw(&calls));
+    let Some(prev) = prev else {
+        let changed = Changed { log: now_ms, recent: now_ms, producers: now_ms, callers: now_ms };
+        let log = raw(&log);
+        return Snapshot { queue_rev, revision: now_ms, changed, base_ms, log, recent, producers, callers, now };
+    };
+
+    let revision = prev.revision + 1;
+    let mut changed = prev.changed;
+    let log_moved = prev.queue_rev != queue_rev || prev.base_ms.abs_diff(base_ms) >= DRIFT_MS;
+    let (log, base_ms) = if log_moved {
+        changed.log = revision;
+        (raw(&log), base_ms)
+    } else {
+        (prev.log.clone(), prev.base_ms)
+    };
+    for (new, old, rev) in [
+        (&recent, &prev.recent, &mut changed.recent),
+        (&producers, &prev.producers, &mut changed.producers),
+        (&callers, &prev.callers, &mut changed.callers),
+    ] {
+        if new.get() != old.get() {
+            *rev = revision;
+        }
+    }
+    let changed_any = [changed.log, changed.recent, changed.producers, changed.callers].contains(&revision);
+    Snapshot {
+        queue_rev,
+        revision: if changed_any { revision } else { prev.revision },
+        changed,
+        base_ms,
+        log,
+        recent,
+        producers,
+        callers,
+        now,
+    }
+}
+
+/// The channel, starting from the state at startup.
+pub(crate) fn channel(p: &PlayoutState) -> (watch::Sender<Arc<Snapshot>>, watch::Receiver<Arc<Snapshot>>) {
+    watch::channel(Arc::new(build(None, p.queue_rev, p.now.clone(), lists(p))))
+}
+
+pub(crate) async fn refresh_task(playout: Arc<RwLock<PlayoutState>>, tx: watch::Sender<Arc<Snapshot>>) {
+    let mut interval = tokio::time::interval(REFRESH);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut last_queue = (tx.borrow().queue_rev, Instant::now());
+    loop {
+        interval.tick().await;
+        let prev = tx.borrow().clone();
+        let (queue_rev, now, changed) = {
+            let p = playout.read().await;
+            let stale = last_queue.0 != p.queue_rev || last_queue.1.elapsed() >= QUEUE_EVERY;
+            (p.queue_rev, p.now.clone(), stale.then(|| lists(&p)))
+        };
+
+        let snap = match changed {
+            Some(lists) => {
+                last_queue = (queue_rev, Instant::now());
+                build(Some(&prev), queue_rev, now, lists)
+            }
+            None => Snapshot {
+                queue_rev,
+                revision: prev.revision,
+                changed: prev.changed,
+                base_ms: prev.base_ms,
+                now,
+                log: prev.log.clone(),
+                recent: prev.recent.clone(),
+                producers: prev.producers.clone(),
+                callers: prev.callers.clone(),
+            },
+        };
+        // Everyone reads the fresh play position, but only a change of the
+        // lists wakes the waiters (the queue long-poll in changes.rs).
+        tx.send_if_modified(|cur| {
+            let moved = cur.queue_rev != snap.queue_rev || cur.revision != snap.revision;
+            *cur = Arc::new(snap);
+            moved
+        });
+    }
+}
@@ -0,0 +1,341 @@
+// --- Hot standby ----------------------------------------------------------------------------
+//
+// One box is one point of failure: a dead SD card or a tripped breaker takes
+// the station off the air until someone drives to the transmitter site. A
+// second engine can now run as a hot standby for the first.
+//
+// With `STUDIOCOMMAND_STANDBY_PRIMARY` set to the primary's API address
+// (e.g. `http://10.0.0.2:3000`), this engine starts as a standby: it does not
+// start its stream output, and scheduled actions (schedule.rs) are left to
+// the primary. Every couple of seconds it reads the primary's status and
+// output state over the API:
+// - while the primary is on air, its queue is mirrored into ours (same items
+//   and ids, persisted like any queue change), and every 30 s its settings
+//   (the configuration export, unredacted, so the Icecast password comes
+//   along) are applied here section by section, only where they changed;
+// - when the primary has been unreachable, off air or silent (peak below
+//   0.003, as the dead-air alert) for `STUDIOCOMMAND_STANDBY_FAILOVER_S`
+//   seconds (default 15), the standby takes over: it asks the primary to
+//   stop its output, in case it can still hear us but has lost its audio,
+//   and starts its own stream on the mirrored Icecast settings, in the
+//   item the primary was playing, about where it had got to (resume.rs).
+//
+// There is no automatic failback: a primary that comes back would fight the
+// standby for the mount. `POST /api/v1/standby/failback` stops our output,
+// asks the primary to start its own and goes back to mirroring;
+// `POST /api/v1/standby/takeover` takes over at once (maintenance on the
+// primary). `GET /api/v1/standby` shows the role, the state of the primary
+// and the last sync.
+//
+// Like metadata push, requests go through `curl`. The API has no
+// authentication of its own, so keep the two engines on a trusted network.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use axum::{extract::State, http::StatusCode, Json};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::{
+    bump_queue_rev, ffmpeg, normalize_log_state, output_start_internal, output_stop_internal, persist_queue, resume,
+    settings, supervisor, unix_ms_now, AppState, LogItem,
+};
+
+/// How often the primary is checked.
+const POLL_S: u64 = 2;
+/// How often its settings are copied.
+const CONFIG_EVERY_S: u64 = 30;
+/// Longest a single request to the primary may take.
+const TIMEOUT_S: u32 = 3;
+/// Peak level (0..1) below which the primary counts as silent.
+const SILENT_PEAK: f32 = 0.003;
+
+/// The primary's API address, if this engine is a standby.
+pub(crate) fn primary_url() -> Option<String> {
+    let url = std::env::var("STUDIOCOMMAND_STANDBY_PRIMARY").ok()?;
+    let url = url.trim().trim_end_matches('/');
+    (!url.is_empty()).then(|| url.to_string())
+}
+
+fn failover_s() -> u64 {
+    std::env::var("STUDIOCOMMAND_STANDBY_FAILOVER_S").ok().and_then(|v| v.trim().parse().ok()).unwrap_or(15)
+}
+
+#[derive(Clone, Default, Serialize)]
+pub(crate) struct StandbyStatus {
+    /// "standby" (mirroring, off air) or "active" (took over, on air).
+    role: String,
+    primary: String,
+    failover_s: u64,
+    /// The primary answered and is on air with audio.
+    primary_ok: bool,
+    /// Why it does not count as on air.
+    primary_problem: Option<String>,
+    last_seen_ms: Option<u64>,
+    /// Off air since; the standby takes over `failover_s` later.
+    down_since_ms: Option<u64>,
+    last_queue_sync_ms: Option<u64>,
+    last_config_sync_ms: Option<u64>,
+    /// Sections (or entries) of the primary's settings that did not apply here.
+    config_errors: Vec<String>,
+    took_over_ms: Option<u64>,
+    takeover_reason: Option<String>,
+    last_error: Option<String>,
+}
+
+/// `None` when this engine is not a standby.
+static STATUS: Mutex<Option<StandbyStatus>> = Mutex::new(None);
+
+fn with_status<T>(f: impl FnOnce(&mut StandbyStatus) -> T) -> Option<T> {
+    STATUS.lock().unwrap_or_else(|e| e.into_inner()).as_mut().map(f)
+}
+
+/// A standby that has not taken over: it must stay off the air.
+pub(crate) fn is_standby() -> bool {
+    with_status(|s| s.role == "standby").unwrap_or(false)
+}
+
+/// One request to the primary's API; the body as JSON.
+async fn call(method: &str, url: &str) -> Result<Value, String> {
+    let out = tokio::process::Command::new(ffmpeg::curl_bin())
+        .args(["-fsS", "--max-time", &TIMEOUT_S.to_string(), "-X", method, url])
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|e| format!("{}: {e}", ffmpeg::curl_bin()))?;
+    if !out.status.success() {
+        return Err(String::from_utf8_lossy(&out.stderr).trim().to_string());
+    }
+    serde_json::from_slice(&out.stdout).map_err(|e| format!("{url}: {e}"))
+}
+
+/// Where the primary was in its playing item, and when we saw it.
+struct Position {
+    item_id: Uuid,
+    pos_ms: u64,
+    seen: Instant,
+}
+
+/// The primary's status, and why it is not on air (`None`: it is).
+async fn probe(primary: &str) -> Result<(Value, Option<String>), String> {
+    let status = call("GET", &format!("{primary}/api/v1/status")).await?;
+    let output = call("GET", &format!("{primary}/api/v1/output")).await?;
+    let state = output.pointer("/status/state").and_then(|s| s.as_str()).unwrap_or("unknown");
+    if state != "connected" {
+        return Ok((status, Some(format!("stream {state}"))));
+    }
+    let peak = ["/vu/peak_l", "/vu/peak_r"]
+        .iter()
+        .filter_map(|p| status.pointer(p).and_then(|v| v.as_f64()))
+        .fold(0.0, f64::max);
+    let silent = peak < SILENT_PEAK as f64;
+    Ok((status, silent.then(|| "silent".to_string())))
+}
+
+/// Make our queue the primary's. Returns whether anything changed.
+async fn mirror_queue(state: &AppState, status: &Value) -> bool {
+    let Some(items) = status.get("log").cloned().and_then(|v| serde_json::from_value::<Vec<LogItem>>(v).ok()) else {
+        return false;
+    };
+    let pos_f = status.pointer("/now/pos_f").and_then(|v| v.as_f64()).unwrap_or(0.0);
+    let log = {
+        let mut p = state.playout.write().await;
+        p.now.pos_f = pos_f;
+        p.now.pos = pos_f.floor() as u32;
+        let same = p.log.len() == items.len()
+            && p.log.iter().zip(&items).all(|(a, b)| a.id == b.id && a.cart == b.cart && a.locked == b.locked);
+        if same {
+            return false;
+        }
+        p.log = items;
+        normalize_log_state(&mut p);
+        bump_queue_rev(&mut p);
+        p.log.clone()
+    };
+    persist_queue(log).await;
+    true
+}
+
+/// Apply the primary's settings sections that changed since the last sync.
+async fn mirror_config(state: &AppState, primary: &str, applied: &mut HashMap<String, String>) -> Result<(), String> {
+    let mut doc = call("GET", &format!("{primary}/api/v1/admin/config?redact=false")).await?;
+    let mut errors = Vec::new();
+    for section in settings::SECTIONS {
+        let Some(v) = doc.get_mut(*section).map(Value::take) else { continue };
+        let text = v.to_string();
+        if applied.get(*section) == Some(&text) {
+            continue;
+        }
+        match settings::apply_section(state, section, v).await {
+            Ok(failed) => {
+                errors.extend(failed.into_iter().map(|(item, e)| format!("{section}: {item}: {e}")));
+                applied.insert(section.to_string(), text);
+            }
+            Err(e) => errors.push(format!("{section}: {e}")),
+        }
+    }
+    with_status(|s| {
+        s.last_config_sync_ms = Some(unix_ms_now());
+        s.config_errors = errors;
+    });
+    Ok(())
+}
+
+async fn start_output(state: &AppState) -> Result<(), String> {
+    supervisor::reset_restarts();
+    output_start_internal(
+        state.output.clone(),
+        state.playout.clone(),
+        state.topup.clone(),
+        state.topup_stats.clone(),
+        state.pcm_tx.clone(),
+    )
+    .await
+    .map_err(|code| format!("output start failed ({code})"))
+}
+
+/// Go on air in place of the primary.
+async fn take_over(state: &AppState, primary: &str, reason: String, at: Option<&Position>) -> Result<(), String> {
+    tracing::warn!("standby: taking over from {primary}: {reason}");
+    // A primary that lost its audio but not its connection would keep the mount.
+    if let Err(e) = call("POST", &format!("{primary}/api/v1/output/stop")).await {
+        tracing::info!("standby: could not stop the primary's output: {e}");
+    }
+    if let Some(at) = at {
+        resume::hand_over(at.item_id, at.pos_ms + at.seen.elapsed().as_millis() as u64);
+    }
+    with_status(|s| {
+        s.role = "active".into();
+        s.took_over_ms = Some(unix_ms_now());
+        s.takeover_reason = Some(reason);
+    });
+    let res = start_output(state).await;
+    with_status(|s| s.last_error = res.as_ref().err().cloned());
+    res
+}
+
+/// Watch the primary, mirror it while it is on air, and take over when it is not.
+pub(crate) async fn standby_task(state: AppState) {
+    let Some(primary) = primary_url() else { return };
+    let failover = Duration::from_secs(failover_s());
+    *STATUS.lock().unwrap_or_else(|e| e.into_inner()) = Some(StandbyStatus {
+        role: "standby".into(),
+        primary: primary.clone(),
+        failover_s: failover.as_secs(),
+        ..Default::default()
+    });
+    tracing::info!("standby: mirroring {primary}, taking over after {} s off air", failover.as_secs());
+
+    let mut applied: HashMap<String, String> = HashMap::new();
+    let mut last_config: Option<Instant> = None;
+    let mut down_since: Option<Instant> = None;
+    let mut position: Option<Position> = None;
+    let mut tick = tokio::time::interval(Duration::from_secs(POLL_S));
+    loop {
+        tick.tick().await;
+        let problem = match probe(&primary).await {
+            Ok((status, problem)) => {
+                with_status(|s| s.last_seen_ms = Some(unix_ms_now()));
+                let item = status.pointer("/log/0/id").and_then(|v| v.as_str()).and_then(|s| Uuid::parse_str(s).ok());
+                let pos_f = status.pointer("/now/pos_f").and_then(|v| v.as_f64()).unwrap_or(0.0);
+                if let (Some(item_id), None) = (item, &problem) {
+                    position = Some(Position { item_id, pos_ms: (pos_f * 1000.0) as u64, seen: Instant::now() });
+                }
+                if problem.is_none() && is_standby() && mirror_queue(&state, &status).await {
+                    with_status(|s| s.last_queue_sync_ms = Some(unix_ms_now()));
+                }
+                problem
+            }
+            Err(e) => Some(format!("unreachable: {e}")),
+        };
+        with_status(|s| {
+            s.primary_ok = problem.is_none();
+            s.primary_problem = problem.clone();
+        });
+
+        if !is_standby() {
+            // Taken over: only failback (by the operator) changes that.
+            down_since = None;
+            continue;
+        }
+        let Some(problem) = problem else {
+            down_since = None;
+            with_status(|s| s.down_since_ms = None);
+            if last_config.is_none_or(|t| t.elapsed() >= Duration::from_secs(CONFIG_EVERY_S)) {
+                last_config = Some(Instant::now());
+                if let Err(e) = mirror_config(&state, &primary, &mut applied).await {
+                    tracing::warn!("standby: config sync: {e}");
+                }
+            }
+            continue;
+        };
+        let since = *down_since.get_or_insert_with(Instant::now);
+        with_status(|s| {
+            s.down_since_ms.get_or_insert(unix_ms_now());
+        });
+        if since.elapsed() >= failover {
+            let reason = format!("primary {problem} for {} s", since.elapsed().as_secs());
+            if let Err(e) = take_over(&state, &primary, reason, position.as_ref()).await {
+                tracing::warn!("standby: {e}");
+            }
+        }
+    }
+}
+
+// --- HTTP API -------------------------------------------------------------------------
+
+fn not_standby() -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::CONFLICT,
+        Json(json!({"ok": false, "error": "not a standby (STUDIOCOMMAND_STANDBY_PRIMARY is not set)"})),
+    )
+}
+
+/// `GET /api/v1/standby`
+pub(crate) async fn api_standby() -> Json<Value> {
+    match with_status(|s| s.clone()) {
+        Some(s) => Json(json!({"ok": true, "standby": s})),
+        None => Json(json!({"ok": true, "standby": null})),
+    }
+}
+
+/// `POST /api/v1/standby/takeover`: go on air now.
+pub(crate) async fn api_standby_takeover(
+    State(state): State<AppState>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let Some(primary) = with_status(|s| s.primary.clone()) else { return Err(not_standby()) };
+    if !is_standby() {
+        return Err((StatusCode::CONFLICT, Json(json!({"ok": false, "error": "already taken over"}))));
+    }
+    take_over(&state, &primary, "operator".into(), None)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"ok": false, "error": e}))))?;
+    Ok(Json(json!({"ok": true})))
+}
+
+/// `POST /api/v1/standby/failback`: hand the air back to the primary.
+pub(crate) async fn api_standby_failback(
+    State(state): State<AppState>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let Some(primary) = with_status(|s| s.primary.clone()) else { return Err(not_standby()) };
+    if is_standby() {
+        return Err((StatusCode::CONFLICT, Json(json!({"ok": false, "error": "not taken over"}))));
+    }
+    tracing::info!("standby: failing back to {primary}");
+    supervisor::reset_restarts();
+    output_stop_internal(state.output.clone()).await;
+    with_status(|s| {
+        s.role = "standby".into();
+        s.took_over_ms = None;
+        s.takeover_reason = None;
+        s.down_since_ms = None;
+    });
+    // Mirroring resumes once the primary is on air again.
+    let started = call("POST", &format!("{primary}/api/v1/output/start")).await;
+    let error = started.as_ref().err().cloned();
+    with_status(|s| s.last_error = error.clone());
+    Ok(Json(json!({"ok": true, "primary_started": started.is_ok(), "error": error})))
+}
@@ -0,0 +1,601 @@
+// --- Studio-to-transmitter link (SRT) ---------------------------------------------------
+//
+// The Icecast encoder is for listeners: MP3/AAC, seconds of buffering, and a
+// server in between. Feeding a transmitter site wants a contribution link
+// instead: low latency, high quality, and a transport that survives a lossy
+// internet path. With `/api/v1/stl/config` enabled the engine sends the
+// program bus (`AppState.pcm_tx`, what goes to air) over SRT, next to and
+// independent of the Icecast stream:
+// - `codec: "opus"`: Opus in low-delay mode (`bitrate_kbps`, `frame_ms`),
+//   or `"pcm"`: linear PCM (SMPTE 302M, about 2.3 Mbit/s), both in MPEG-TS,
+//   which hardware decoders and `ffmpeg -i srt://…` at the far end take.
+// - `mode: "caller"` connects to the transmitter site's `host:port`;
+//   `"listener"` waits on `port` for it to call in (through the studio
+//   firewall). `latency_ms` is SRT's receive buffer: the longer, the more
+//   retransmissions fit. `fec` adds SRT's packet filter FEC (`fec_cols` x
+//   `fec_rows`), which the receiver must be configured for as well.
+// - `passphrase` turns on SRT encryption (AES); it is stored sealed, like
+//   the Icecast password (secrets.rs), and never returned.
+//
+// ffmpeg encodes into a pipe to `srt-live-transmit` (srt-tools;
+// `STUDIOCOMMAND_SRT_LIVE_TRANSMIT`), which does the SRT side and reports
+// link statistics once a second: round-trip time, bandwidth, retransmitted,
+// lost and dropped packets. Those are the `stl` part of `GET /api/v1/output`.
+// While output is stopped the link carries silence, so the far end does not
+// fall back to its own backup. If either process exits the link is started
+// again after 2 s, doubling up to 60 s.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use axum::{http::StatusCode, Json};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader};
+use tokio::process::Command;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::Duration;
+
+use crate::{ffmpeg, secrets, unix_ms_now, AppState};
+
+/// s16le stereo at 48 kHz, as `writer_playout`.
+const BYTES_PER_MS: usize = 48 * 4;
+/// How far the program bus may fall behind the clock before silence is sent.
+const SLACK_MS: usize = 100;
+/// Opus frame sizes offered, ms.
+const FRAME_SIZES: &[u32] = &[5, 10, 20];
+/// Without a statistics report for this long, the link counts as down.
+const STATS_STALE: Duration = Duration::from_secs(3);
+const BACKOFF_MIN: Duration = Duration::from_secs(2);
+const BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct StlConfig {
+    #[serde(default)]
+    enabled: bool,
+    /// "caller" or "listener".
+    #[serde(default = "default_mode")]
+    mode: String,
+    /// The far end (caller), or the address to listen on (listener; empty: all).
+    #[serde(default)]
+    host: String,
+    #[serde(default = "default_port")]
+    port: u16,
+    /// "opus" or "pcm".
+    #[serde(default = "default_codec")]
+    codec: String,
+    /// Opus only.
+    #[serde(default = "default_bitrate")]
+    bitrate_kbps: u32,
+    /// Opus frame size, ms.
+    #[serde(default = "default_frame_ms")]
+    frame_ms: u32,
+    /// SRT latency (receive buffer), ms.
+    #[serde(default = "default_latency")]
+    latency_ms: u32,
+    #[serde(default)]
+    fec: bool,
+    #[serde(default = "default_fec_cols")]
+    fec_cols: u32,
+    #[serde(default = "default_fec_rows")]
+    fec_rows: u32,
+    /// Never returned (see `config_view`); empty on save keeps the current one.
+    #[serde(default)]
+    passphrase: String,
+}
+
+fn default_mode() -> String {
+    "caller".into()
+}
+
+fn default_port() -> u16 {
+    9000
+}
+
+fn default_codec() -> String {
+    "opus".into()
+}
+
+fn default_bitrate() -> u32 {
+    256
+}
+
+fn default_frame_ms() -> u32 {
+    10
+}
+
+fn default_latency() -> u32 {
+    200
+}
+
+fn default_fec_cols() -> u32 {
+    10
+}
+
+fn default_fec_rows() -> u32 {
+    5
+}
+
+fn default_config() -> StlConfig {
+    serde_json::from_str("{}").expect("defaults")
+}
+
+impl StlConfig {
+    /// The `srt://` URL for srt-live-transmit (with the passphrase).
+    fn url(&self) -> String {
+        let mut url = format!(
+            "srt://{}:{}?mode={}&latency={}&transtype=live",
+            self.host, self.port, self.mode, self.latency_ms
+        );
+        if self.fec {
+            url.push_str(&format!("&packetfilter=fec,cols:{},rows:{}", self.fec_cols, self.fec_rows));
+        }
+        if !self.passphrase.is_empty() {
+            url.push_str(&format!("&passphrase={}&pbkeylen=32", self.passphrase));
+        }
+        url
+    }
+
+    /// Where the link goes, for the status and the log.
+    fn target(&self) -> String {
+        match self.mode.as_str() {
+            "listener" => format!("listening on {}:{}", self.host, self.port),
+            _ => format!("srt://{}:{}", self.host, self.port),
+        }
+    }
+}
+
+/// Link statistics from srt-live-transmit; the counts are since the link started.
+#[derive(Clone, Serialize, Default)]
+pub(crate) struct LinkStats {
+    updated_ms: u64,
+    rtt_ms: f64,
+    /// Estimated link capacity.
+    bandwidth_mbps: f64,
+    /// What is being sent.
+    send_mbps: f64,
+    /// Sent but not yet acknowledged.
+    buffer_ms: f64,
+    packets: u64,
+    retransmitted: u64,
+    /// Reported lost by the receiver (and retransmitted, if in time).
+    lost: u64,
+    /// Dropped as too late: the far end heard a gap.
+    dropped: u64,
+    /// FEC packets sent.
+    fec_packets: u64,
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct StlStatus {
+    /// "stopped", "connecting", "connected" or "error".
+    state: &'static str,
+    target: Option<String>,
+    codec: Option<String>,
+    since_ms: Option<u64>,
+    /// Restarts since the link was last configured.
+    restarts: u32,
+    last_error: Option<String>,
+    link: Option<LinkStats>,
+}
+
+struct Runtime {
+    running: bool,
+    target: Option<String>,
+    codec: Option<String>,
+    since_ms: Option<u64>,
+    restarts: u32,
+    last_error: Option<String>,
+    link: Option<LinkStats>,
+    last_report: Option<Instant>,
+}
+
+static RUNTIME: Mutex<Runtime> = Mutex::new(Runtime {
+    running: false,
+    target: None,
+    codec: None,
+    since_ms: None,
+    restarts: 0,
+    last_error: None,
+    link: None,
+    last_report: None,
+});
+/// Bumped when the configuration is saved, so the link restarts with it.
+static CONFIG_GEN: AtomicU64 = AtomicU64::new(0);
+
+fn runtime() -> std::sync::MutexGuard<'static, Runtime> {
+    RUNTIME.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The link as `GET /api/v1/output` shows it.
+pub(crate) fn status() -> StlStatus {
+    let rt = runtime();
+    let fresh = rt.last_report.is_some_and(|t| t.elapsed() < STATS_STALE);
+    let state = match (rt.running, fresh, &rt.last_error) {
+        (true, true, _) => "connected",
+        (true, false, _) => "connecting",
+        (false, _, Some(_)) => "error",
+        (false, _, None) => "stopped",
+    };
+    StlStatus {
+        state,
+        target: rt.target.clone(),
+        codec: rt.codec.clone(),
+        since_ms: rt.since_ms,
+        restarts: rt.restarts,
+        last_error: rt.last_error.clone(),
+        link: rt.link.clone(),
+    }
+}
+
+fn srt_live_transmit_bin() -> String {
+    std::env::var("STUDIOCOMMAND_SRT_LIVE_TRANSMIT").unwrap_or_else(|_| "srt-live-transmit".to_string())
+}
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS stl_config (
+            id      INTEGER PRIMARY KEY CHECK (id = 1),
+            config  TEXT NOT NULL
+        );
+        "#,
+    )
+}
+
+async fn with_db<T: Send + 'static>(
+    f: impl FnOnce(&Connection) -> anyhow::Result<T> + Send + 'static,
+) -> anyhow::Result<T> {
+    crate::db::call(move |conn| {
+        crate::db_init(conn)?;
+        f(conn)
+    })
+    .await?
+}
+
+/// The stored configuration, passphrase opened.
+async fn load_config() -> anyhow::Result<StlConfig> {
+    with_db(|conn| {
+        let raw: Option<String> =
+            conn.query_row("SELECT config FROM stl_config WHERE id = 1", [], |row| row.get(0)).optional()?;
+        let mut cfg: StlConfig = match raw {
+            Some(r) => serde_json::from_str(&r)?,
+            None => default_config(),
+        };
+        cfg.passphrase = secrets::open(&cfg.passphrase)?;
+        Ok(cfg)
+    })
+    .await
+}
+
+/// The configuration as the API shows it: `has_passphrase` instead of the passphrase.
+fn config_view(cfg: &StlConfig) -> Value {
+    let mut v = serde_json::to_value(cfg).unwrap_or_default();
+    if let Some(o) = v.as_object_mut() {
+        o.remove("passphrase");
+        o.insert("has_passphrase".into(), (!cfg.passphrase.is_empty()).into());
+    }
+    v
+}
+
+// --- Link ------------------------------------------------------------------------------
+
+/// Keep the link up while it is enabled.
+pub(crate) async fn stl_task(state: AppState) {
+    let mut backoff = BACKOFF_MIN;
+    loop {
+        let generation = CONFIG_GEN.load(Ordering::Relaxed);
+        let res = match load_config().await {
+            Ok(cfg) if cfg.enabled => run(&state, &cfg, generation).await,
+            Ok(_) => {
+                let mut rt = runtime();
+                (rt.target, rt.codec, rt.since_ms, rt.link) = (None, None, None, None);
+                Ok(())
+            }
+            Err(e) => Err(e.to_string()),
+        };
+        let wait = {
+            let mut rt = runtime();
+            rt.running = false;
+            rt.last_report = None;
+            match res {
+                Ok(()) => {
+                    backoff = BACKOFF_MIN;
+                    Duration::from_secs(5)
+                }
+                Err(e) => {
+                    tracing::warn!("stl: {e}");
+                    rt.last_error = Some(e);
+                    rt.restarts += 1;
+                    let wait = backoff;
+                    backoff = (backoff * 2).min(BACKOFF_MAX);
+                    wait
+                }
+            }
+        };
+        // Retry after the backoff, or as soon as the configuration changes.
+        let until = Instant::now() + wait;
+        while Instant::now() < until && CONFIG_GEN.load(Ordering::Relaxed) == generation {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        if CONFIG_GEN.load(Ordering::Relaxed) != generation {
+            backoff = BACKOFF_MIN;
+            let mut rt = runtime();
+            rt.restarts = 0;
+            rt.last_error = None;
+        }
+    }
+}
+
+/// Run the encoder and the SRT sender until one exits (`Err`) or the
+/// configuration changes (`Ok`).
+async fn run(state: &AppState, cfg: &StlConfig, generation: u64) -> Result<(), String> {
+    ffmpeg::check_output_codec(&cfg.codec).await?;
+
+    let mut encoder = Command::new(ffmpeg::ffmpeg_bin());
+    encoder
+        .arg("-hide_banner")
+        .arg("-loglevel").arg("error")
+        .arg("-f").arg("s16le").arg("-ar").arg("48000").arg("-ac").arg("2")
+        .arg("-i").arg("pipe:0");
+    match cfg.codec.as_str() {
+        "pcm" => encoder.arg("-c:a").arg("s302m").arg("-strict").arg("-2"),
+        _ => encoder
+            .arg("-c:a").arg("libopus")
+            .arg("-application").arg("lowdelay")
+            .arg("-frame_duration").arg(cfg.frame_ms.to_string())
+            .arg("-b:a").arg(format!("{}k", cfg.bitrate_kbps)),
+    };
+    encoder
+        .arg("-f").arg("mpegts")
+        .arg("-flush_packets").arg("1")
+        .arg("-muxdelay").arg("0")
+        .arg("pipe:1")
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true);
+    let mut encoder = encoder.spawn().map_err(|e| format!("ffmpeg: {e}"))?;
+    let mut stdin = encoder.stdin.take().ok_or("encoder stdin unavailable")?;
+    let ts: std::process::Stdio =
+        encoder.stdout.take().ok_or("encoder stdout unavailable")?.try_into().map_err(|e| format!("ffmpeg: {e}"))?;
+
+    let bin = srt_live_transmit_bin();
+    let mut sender = Command::new(&bin)
+        .arg("-autoreconnect:yes")
+        .arg("-chunk:1316")
+        .arg("-stats-report-frequency:1000")
+        .arg("-statspf:json")
+        .arg("-loglevel:error")
+        .arg("file://con")
+        .arg(cfg.url())
+        .stdin(ts)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("{bin}: {e} (install srt-tools, or set STUDIOCOMMAND_SRT_LIVE_TRANSMIT)"))?;
+
+    // The last error line of each process, for the status when it dies.
+    let (encoder_err, sender_err) = (Arc::new(Mutex::new(String::new())), Arc::new(Mutex::new(String::new())));
+    let mut readers = Vec::new();
+    if let Some(stderr) = encoder.stderr.take() {
+        readers.push(tokio::spawn(keep_last_line(stderr, encoder_err.clone())));
+    }
+    if let Some(stderr) = sender.stderr.take() {
+        readers.push(tokio::spawn(keep_last_line(stderr, sender_err.clone())));
+    }
+    let stats = sender.stdout.take().map(|stdout| tokio::spawn(read_stats(stdout)));
+
+    {
+        let mut rt = runtime();
+        rt.running = true;
+        rt.target = Some(cfg.target());
+        rt.codec = Some(match cfg.codec.as_str() {
+            "pcm" => "pcm".to_string(),
+            _ => format!("opus {} kbit/s", cfg.bitrate_kbps),
+        });
+        rt.since_ms = Some(unix_ms_now());
+        rt.link = None;
+        rt.last_report = None;
+    }
+    tracing::info!("stl: sending the program bus ({}) to {}", cfg.codec, cfg.target());
+
+    let mut rx = state.pcm_tx.subscribe();
+    let started = Instant::now();
+    let mut written = 0usize;
+    let mut tick = tokio::time::interval(Duration::from_millis(20));
+    let res = loop {
+        tokio::select! {
+            msg = rx.recv() => match msg {
+                Ok(chunk) => {
+                    if let Err(e) = stdin.write_all(&chunk).await {
+                        break Err(format!("encoder: {e}"));
+                    }
+                    written += chunk.len();
+                }
+                // Missed chunks are made up with silence below.
+                Err(RecvError::Lagged(n)) => tracing::warn!("stl: fell behind the program bus ({n} chunks)"),
+                Err(RecvError::Closed) => break Ok(()),
+            },
+            _ = tick.tick() => {
+                if CONFIG_GEN.load(Ordering::Relaxed) != generation {
+                    break Ok(());
+                }
+                if let Ok(Some(status)) = encoder.try_wait() {
+                    break Err(format!("encoder exited ({status})"));
+                }
+                if matches!(sender.try_wait(), Ok(Some(_))) {
+                    break Err(String::new());
+                }
+                // Nothing coming (output stopped): keep the link fed with silence.
+                let due = started.elapsed().as_millis() as usize * BYTES_PER_MS;
+                if due > written + SLACK_MS * BYTES_PER_MS {
+                    let pad = (due - written) & !3;
+                    if let Err(e) = stdin.write_all(&vec![0u8; pad]).await {
+                        break Err(format!("encoder: {e}"));
+                    }
+                    written += pad;
+                }
+            }
+        }
+    };
+    // When the SRT side gave up, the encoder only saw a broken pipe: report the sender.
+    let sender_exit = sender.try_wait().ok().flatten();
+    drop(stdin);
+    let _ = encoder.start_kill();
+    let _ = sender.start_kill();
+    let _ = tokio::join!(encoder.wait(), sender.wait());
+    if let Some(stats) = stats {
+        stats.abort();
+    }
+    // The readers end with the processes; take their last lines.
+    for r in readers {
+        let _ = tokio::time::timeout(Duration::from_secs(1), r).await;
+    }
+    tracing::info!("stl: stopped sending to {}", cfg.target());
+    res.map_err(|e| {
+        let (e, last) = match sender_exit {
+            Some(status) => (format!("{bin} exited ({status})"), &sender_err),
+            None => (e, &encoder_err),
+        };
+        let last = last.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        if last.is_empty() { e } else { format!("{e}: {}", last.replace(&cfg.passphrase, "****")) }
+    })
+}
+
+async fn keep_last_line(stderr: impl AsyncRead + Unpin, last: Arc<Mutex<String>>) {
+    let mut lines = BufReader::new(stderr).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if !line.trim().is_empty() {
+            *last.lock().unwrap_or_else(|e| e.into_inner()) = line.trim().to_string();
+        }
+    }
+}
+
+/// Follow srt-live-transmit's JSON statistics (one object per report).
+async fn read_stats(stdout: tokio::process::ChildStdout) {
+    let mut lines = BufReader::new(stdout).lines();
+    let (mut pending, mut depth) = (String::new(), 0i32);
+    while let Ok(Some(line)) = lines.next_line().await {
+        // A report may span lines: collect until the braces balance.
+        for c in line.chars() {
+            match c {
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                _ => {}
+            }
+        }
+        if depth > 0 || !pending.is_empty() {
+            pending.push_str(&line);
+        }
+        if depth > 0 {
+            continue;
+        }
+        let text = if pending.is_empty() { line } else { std::mem::take(&mut pending) };
+        depth = 0;
+        if let Ok(report) = serde_json::from_str::<Value>(&text) {
+            record(&report);
+        }
+    }
+}
+
+/// Add one report (whose counts cover the last interval) to the link statistics.
+fn record(report: &Value) {
+    let f = |path: &str| report.pointer(path).and_then(Value::as_f64).unwrap_or(0.0);
+    let n = |path: &str| report.pointer(path).and_then(Value::as_u64).unwrap_or(0);
+    let mut rt = runtime();
+    rt.last_report = Some(Instant::now());
+    let link = rt.link.get_or_insert_with(LinkStats::default);
+    link.updated_ms = unix_ms_now();
+    link.rtt_ms = f("/link/rtt");
+    link.bandwidth_mbps = f("/link/bandwidth");
+    link.send_mbps = f("/send/mbitRate");
+    link.buffer_ms = f("/send/msBuf");
+    link.packets += n("/send/packets");
+    link.retransmitted += n("/send/packetsRetransmitted");
+    link.lost += n("/send/packetsLost");
+    link.dropped += n("/send/packetsDropped");
+    link.fec_packets += n("/send/packetsFilterExtra");
+}
+
+// --- HTTP API --------------------------------------------------------------------------
+
+type ApiError = (StatusCode, Json<Value>);
+
+fn api_error(status: StatusCode, msg: impl Into<String>) -> ApiError {
+    (status, Json(json!({"ok": false, "error": msg.into()})))
+}
+
+pub(crate) async fn api_stl_config_get() -> Result<Json<Value>, StatusCode> {
+    let cfg = load_config().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(json!({"ok": true, "config": config_view(&cfg), "status": status()})))
+}
+
+/// The configuration with its passphrase, for the settings export (which redacts it).
+pub(crate) async fn export_config() -> Result<Value, String> {
+    let cfg = load_config().await.map_err(|e| e.to_string())?;
+    serde_json::to_value(cfg).map_err(|e| e.to_string())
+}
+
+pub(crate) async fn api_stl_config_set(Json(mut cfg): Json<StlConfig>) -> Result<Json<Value>, ApiError> {
+    let bad = |msg: String| Err(api_error(StatusCode::BAD_REQUEST, msg));
+    cfg.host = cfg.host.trim().to_string();
+    if !matches!(cfg.mode.as_str(), "caller" | "listener") {
+        return bad("mode must be caller or listener".into());
+    }
+    if !matches!(cfg.codec.as_str(), "opus" | "pcm") {
+        return bad("codec must be opus or pcm".into());
+    }
+    if cfg.mode == "caller" && cfg.enabled && cfg.host.is_empty() {
+        return bad("host (the far end) is required".into());
+    }
+    if cfg.host.contains(['/', '?', '&', ' ']) {
+        return bad("host must be a host name or address".into());
+    }
+    if cfg.port == 0 {
+        return bad("port must be 1-65535".into());
+    }
+    if !(32..=510).contains(&cfg.bitrate_kbps) {
+        return bad("bitrate_kbps must be 32-510".into());
+    }
+    if !FRAME_SIZES.contains(&cfg.frame_ms) {
+        return bad(format!("frame_ms must be one of {FRAME_SIZES:?}"));
+    }
+    if !(20..=8000).contains(&cfg.latency_ms) {
+        return bad("latency_ms must be 20-8000".into());
+    }
+    if !(1..=60).contains(&cfg.fec_cols) || !(1..=60).contains(&cfg.fec_rows) {
+        return bad("fec_cols and fec_rows must be 1-60".into());
+    }
+    // GET never returns the passphrase, so a form saved without touching it
+    // sends none: keep the one we have.
+    if cfg.passphrase.is_empty() {
+        cfg.passphrase = load_config().await.map(|c| c.passphrase).unwrap_or_default();
+    }
+    if !cfg.passphrase.is_empty()
+        && (!(10..=79).contains(&cfg.passphrase.len()) || cfg.passphrase.contains(['&', '?', '#', ' ']))
+    {
+        return bad("passphrase must be 10-79 characters, without spaces, &, ? or #".into());
+    }
+
+    let mut stored = cfg.clone();
+    stored.passphrase =
+        secrets::seal(&cfg.passphrase).map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let raw =
+        serde_json::to_string(&stored).map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    with_db(move |conn| {
+        conn.execute(
+            "INSERT INTO stl_config (id, config) VALUES (1, ?1)
+             ON CONFLICT(id) DO UPDATE SET config=excluded.config",
+            params![raw],
+        )?;
+        Ok(())
+    })
+    .await
+    .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    CONFIG_GEN.fetch_add(1, Ordering::Relaxed);
+    Ok(Json(json!({"ok": true, "config": config_view(&cfg)})))
+}
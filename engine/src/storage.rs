@@ -0,0 +1,160 @@
+// --- Storage health -------------------------------------------------------------------------
+//
+// Running out of disk is the most common cause of overnight failures on these
+// boxes: the database stops taking writes, recordings stop, and an update
+// cannot unpack. The admin dashboard lists every mount, which does not say
+// which of them matter. `GET /api/v1/system/info` now reports the locations
+// the engine writes to, each with the mount it lives on and its free space:
+// - `database`: the directory holding the database (`STUDIOCOMMAND_DB_PATH`),
+// - `data`: the shared data folder (`STUDIOCOMMAND_DATA_DIR`, default
+//   `/opt/studiocommand/shared`: library, carts, backups, caches),
+// - `archive`: where recordings and exports are kept
+//   (`STUDIOCOMMAND_ARCHIVE_DIR`, default `/opt/studiocommand/shared/archive`).
+// Below `STUDIOCOMMAND_DISK_WARN_PCT` percent free (default 15) a location is
+// `warn`, below `STUDIOCOMMAND_DISK_CRIT_PCT` (default 5) `crit`, and each one
+// adds a line to `warnings`. Locations on the same mount share the numbers.
+//
+// A location that does not exist yet is measured on its nearest existing
+// parent. Each statvfs call is time-boxed, as in the admin dashboard, so a
+// dead network mount cannot hang the request.
+
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use tokio::time::{timeout, Duration};
+
+use crate::{db_path, read_mountinfo, statvfs_bytes};
+
+#[derive(Clone, Serialize)]
+pub(crate) struct StorageCheck {
+    pub(crate) name: &'static str,
+    pub(crate) path: String,
+    /// Mount point the location lives on.
+    mount: Option<String>,
+    total_bytes: Option<u64>,
+    free_bytes: Option<u64>,
+    pub(crate) free_pct: Option<f32>,
+    /// "ok", "warn", "crit" or "unknown".
+    pub(crate) status: &'static str,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    message: String,
+}
+
+fn env_pct(name: &str, default: f32) -> f32 {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.trim().parse::<f32>().ok())
+        .filter(|p| (0.0..100.0).contains(p))
+        .unwrap_or(default)
+}
+
+/// `(warn, crit)` free-space thresholds, in percent.
+fn thresholds() -> (f32, f32) {
+    (env_pct("STUDIOCOMMAND_DISK_WARN_PCT", 15.0), env_pct("STUDIOCOMMAND_DISK_CRIT_PCT", 5.0))
+}
+
+pub(crate) fn data_dir() -> PathBuf {
+    std::env::var("STUDIOCOMMAND_DATA_DIR").unwrap_or_else(|_| "/opt/studiocommand/shared".to_string()).into()
+}
+
+pub(crate) fn archive_dir() -> PathBuf {
+    std::env::var("STUDIOCOMMAND_ARCHIVE_DIR")
+        .unwrap_or_else(|_| "/opt/studiocommand/shared/archive".to_string())
+        .into()
+}
+
+fn locations() -> Vec<(&'static str, PathBuf)> {
+    let db = db_path();
+    let db_dir = Path::new(&db).parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new(".")).to_path_buf();
+    vec![("database", db_dir), ("data", data_dir()), ("archive", archive_dir())]
+}
+
+/// `path`, or its nearest ancestor that exists.
+fn existing(path: &Path) -> Option<PathBuf> {
+    path.ancestors().find(|p| p.exists()).map(Path::to_path_buf)
+}
+
+/// Longest mount point that contains `path`.
+fn mount_of(path: &Path, mounts: &[String]) -> Option<String> {
+    mounts.iter().filter(|m| path.starts_with(m.as_str())).max_by_key(|m| m.len()).cloned()
+}
+
+async fn check_one(name: &'static str, path: PathBuf, mounts: &[String], (warn, crit): (f32, f32)) -> StorageCheck {
+    let mut c = StorageCheck {
+        name,
+        path: path.display().to_string(),
+        mount: None,
+        total_bytes: None,
+        free_bytes: None,
+        free_pct: None,
+        status: "unknown",
+        message: String::new(),
+    };
+    let Some(target) = existing(&path) else {
+        c.message = "no existing parent directory".into();
+        return c;
+    };
+    if target != path {
+        c.message = format!("does not exist yet; measured on {}", target.display());
+    }
+    let target = target.canonicalize().unwrap_or(target);
+    c.mount = mount_of(&target, mounts);
+
+    let stat_path = target.to_string_lossy().into_owned();
+    let res = timeout(Duration::from_millis(200), tokio::task::spawn_blocking(move || statvfs_bytes(&stat_path))).await;
+    let (total, free) = match res {
+        Ok(Ok(Ok((total, _, free, _)))) => (total, free),
+        Ok(Ok(Err(e))) => {
+            c.message = format!("statvfs failed: {e}");
+            return c;
+        }
+        Ok(Err(e)) => {
+            c.message = format!("statvfs task failed: {e}");
+            return c;
+        }
+        Err(_) => {
+            c.message = "statvfs timed out".into();
+            return c;
+        }
+    };
+    let pct = if total > 0 { (free as f64 / total as f64 * 100.0) as f32 } else { 0.0 };
+    c.total_bytes = Some(total);
+    c.free_bytes = Some(free);
+    c.free_pct = Some(pct);
+    c.status = if pct < crit {
+        "crit"
+    } else if pct < warn {
+        "warn"
+    } else {
+        "ok"
+    };
+    c
+}
+
+/// Free space of every location the engine writes to.
+pub(crate) async fn check_all() -> Vec<StorageCheck> {
+    let mounts: Vec<String> = read_mountinfo().into_iter().map(|m| m.mount).collect();
+    let limits = thresholds();
+    let mut out = Vec::new();
+    for (name, path) in locations() {
+        out.push(check_one(name, path, &mounts, limits).await);
+    }
+    out
+}
+
+/// One line per location that is low on space.
+pub(crate) fn warnings(checks: &[StorageCheck]) -> Vec<String> {
+    checks
+        .iter()
+        .filter(|c| matches!(c.status, "warn" | "crit"))
+        .map(|c| {
+            format!(
+                "{}: only {:.1}% free ({} MB) at {}",
+                c.name,
+                c.free_pct.unwrap_or(0.0),
+                c.free_bytes.unwrap_or(0) / 1_000_000,
+                c.path
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,318 @@
+// --- ffmpeg child supervision ---------------------------------------------------------------
+//
+// The encoder and the per-item decoders are ffmpeg child processes. An
+// encoder exit used to be noticed only when `GET /api/v1/output` happened to
+// be polled (`try_wait`), so with no UI open a dead stream stayed "connected"
+// and the process stayed a zombie; a decoder that reached the end of its
+// file was never waited for at all.
+//
+// Every child is now handed to `supervise`, which spawns a task that waits
+// for it, so it is reaped the moment it exits, and reports how it ended:
+// - the encoder: the status changes to `error` straight away (with the last
+//   meaningful stderr line), and the stream is restarted after a backoff of
+//   2 s, doubling up to 60 s. After `STUDIOCOMMAND_ENCODER_MAX_RESTARTS`
+//   restarts (default 5) within ten minutes it is left stopped; 0 turns
+//   restarting off. A stop or start from the operator resets the count.
+// - a decoder that fails part way through an item is started again where it
+//   stopped, up to `STUDIOCOMMAND_DECODER_RETRIES` times per item (default 1),
+//   before the item ends as `errored` (see failures.rs).
+// Dropping the handle (the writer task was aborted) kills the child.
+//
+// Encoder starts, exits, restarts and give-ups, and decoder failures and
+// restarts, are logged and kept, newest first, at `GET /api/v1/output/events`.
+
+use std::collections::VecDeque;
+use std::os::unix::process::ExitStatusExt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::{extract::{Query, State}, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::{oneshot, Notify};
+
+use crate::{eventlog, unix_ms_now, AppState, OutputRuntime};
+
+const EVENTS_KEPT: usize = 200;
+const RESTART_WINDOW: Duration = Duration::from_secs(600);
+const BACKOFF_MIN: Duration = Duration::from_secs(2);
+const BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Serialize)]
+pub(crate) struct ChildEvent {
+    seq: u64,
+    ts_ms: u64,
+    /// "encoder" or "decoder".
+    child: &'static str,
+    /// Stream target or file being decoded.
+    label: String,
+    pid: Option<u32>,
+    /// "started", "exited", "failed", "stopped", "restarting" or "gave_up".
+    event: &'static str,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    detail: String,
+}
+
+struct Events {
+    seq: u64,
+    items: VecDeque<ChildEvent>,
+}
+
+static EVENTS: Mutex<Events> = Mutex::new(Events { seq: 0, items: VecDeque::new() });
+
+pub(crate) fn event(child: &'static str, label: &str, pid: Option<u32>, event: &'static str, detail: impl Into<String>) {
+    let detail = detail.into();
+    match (child, event) {
+        (_, "failed" | "gave_up") => tracing::warn!("{child} {event}: {label} (pid {pid:?}) {detail}"),
+        ("encoder", _) => tracing::info!("{child} {event}: {label} (pid {pid:?}) {detail}"),
+        _ => tracing::debug!("{child} {event}: {label} (pid {pid:?}) {detail}"),
+    }
+    if child == "encoder" {
+        let message = format!("encoder {event}: {label} {detail}");
+        eventlog::record("output", message.trim_end(), json!({"event": event, "pid": pid, "detail": detail}));
+    }
+    // A decoder starts and ends with every item; only its trouble is kept.
+    if child == "decoder" && matches!(event, "started" | "exited" | "stopped") {
+        return;
+    }
+    let mut ev = EVENTS.lock().unwrap_or_else(|e| e.into_inner());
+    ev.seq += 1;
+    let seq = ev.seq;
+    if ev.items.len() >= EVENTS_KEPT {
+        ev.items.pop_front();
+    }
+    ev.items.push_back(ChildEvent { seq, ts_ms: unix_ms_now(), child, label: label.to_string(), pid, event, detail });
+}
+
+/// How a supervised child ended.
+#[derive(Clone, Debug)]
+pub(crate) struct Exit {
+    code: Option<i32>,
+    signal: Option<i32>,
+    /// Killed because the handle asked for it (or was dropped).
+    pub(crate) requested: bool,
+    /// `wait` itself failed.
+    error: Option<String>,
+}
+
+impl Exit {
+    fn from_wait(res: std::io::Result<std::process::ExitStatus>, requested: bool) -> Self {
+        match res {
+            Ok(es) => Exit { code: es.code(), signal: es.signal(), requested, error: None },
+            Err(e) => Exit { code: None, signal: None, requested, error: Some(e.to_string()) },
+        }
+    }
+
+    pub(crate) fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+
+    pub(crate) fn describe(&self) -> String {
+        match (&self.error, self.code, self.signal) {
+            (Some(e), _, _) => format!("wait failed: {e}"),
+            (None, Some(code), _) => format!("exited with code {code}"),
+            (None, None, Some(sig)) => format!("killed by signal {sig}"),
+            (None, None, None) => "exited".into(),
+        }
+    }
+}
+
+/// A running child. Dropping it kills the child.
+pub(crate) struct Supervised {
+    pub(crate) pid: Option<u32>,
+    stop: oneshot::Sender<()>,
+}
+
+impl Supervised {
+    /// Kill the child; its exit still arrives on the receiver from `supervise`.
+    pub(crate) fn stop(self) {
+        let _ = self.stop.send(());
+    }
+}
+
+/// Wait for `child` in a task of its own; the receiver gets its exit.
+pub(crate) fn supervise(
+    kind: &'static str,
+    label: String,
+    mut child: tokio::process::Child,
+) -> (Supervised, oneshot::Receiver<Exit>) {
+    let pid = child.id();
+    let (stop_tx, stop_rx) = oneshot::channel::<()>();
+    let (exit_tx, exit_rx) = oneshot::channel();
+    event(kind, &label, pid, "started", "");
+    tokio::spawn(async move {
+        let exit = tokio::select! {
+            res = child.wait() => Exit::from_wait(res, false),
+            // A stop, or the handle was dropped.
+            _ = stop_rx => {
+                let _ = child.start_kill();
+                Exit::from_wait(child.wait().await, true)
+            }
+        };
+        let name = match (exit.requested, exit.success()) {
+            (true, _) => "stopped",
+            (false, true) => "exited",
+            (false, false) => "failed",
+        };
+        event(kind, &label, pid, name, exit.describe());
+        let _ = exit_tx.send(exit);
+    });
+    (Supervised { pid, stop: stop_tx }, exit_rx)
+}
+
+// --- Encoder -----------------------------------------------------------------------------
+
+fn max_restarts() -> usize {
+    std::env::var("STUDIOCOMMAND_ENCODER_MAX_RESTARTS").ok().and_then(|v| v.trim().parse().ok()).unwrap_or(5)
+}
+
+/// Restarts per item for a decoder that fails part way through.
+pub(crate) fn decoder_retries() -> u32 {
+    std::env::var("STUDIOCOMMAND_DECODER_RETRIES").ok().and_then(|v| v.trim().parse().ok()).unwrap_or(1)
+}
+
+static RESTART: Notify = Notify::const_new();
+/// When the recent automatic restarts happened.
+static RESTARTS: Mutex<VecDeque<Instant>> = Mutex::new(VecDeque::new());
+
+/// Forget earlier failures: the operator started or stopped the stream.
+pub(crate) fn reset_restarts() {
+    RESTARTS.lock().unwrap_or_else(|e| e.into_inner()).clear();
+}
+
+/// Wait for the encoder of stream `generation` to exit, and when nobody asked
+/// for that, mark the stream down and ask `restart_task` to bring it back.
+pub(crate) async fn watch_encoder(
+    output: Arc<tokio::sync::Mutex<OutputRuntime>>,
+    generation: u64,
+    exit: oneshot::Receiver<Exit>,
+) {
+    let Ok(exit) = exit.await else { return };
+    if exit.requested {
+        return;
+    }
+    let mut o = output.lock().await;
+    // Stopped, or already replaced, while we waited for the lock.
+    if o.generation != generation || o.encoder.is_none() {
+        return;
+    }
+    o.encoder = None;
+    o.started_at = None;
+    o.status.uptime_sec = 0;
+    if let Some(task) = o.writer_task.take() {
+        task.abort();
+    }
+    if let Some(task) = o.stderr_task.take() {
+        task.abort();
+    }
+    // On shutdown the writer closed the encoder's input on purpose.
+    if crate::shutdown::requested() {
+        o.status.state = "stopped".into();
+        o.status.last_exit = Some(exit.describe());
+        return;
+    }
+    let reason = crate::last_stderr_summary(&o.stderr_tail)
+        .or_else(|| o.status.last_error.clone().filter(|_| o.status.state == "error"))
+        .unwrap_or_else(|| format!("ffmpeg {}", exit.describe()));
+    o.status.state = "error".into();
+    o.status.last_error = Some(reason);
+    o.status.last_exit = Some(exit.describe());
+    drop(o);
+    RESTART.notify_one();
+}
+
+/// Brings the stream back after the encoder died, with backoff.
+pub(crate) async fn restart_task(state: AppState) {
+    loop {
+        RESTART.notified().await;
+        let label = {
+            let o = state.output.lock().await;
+            format!("{}:{}{}", o.config.host, o.config.port, o.config.mount)
+        };
+        let max = max_restarts();
+        let delay = {
+            let mut recent = RESTARTS.lock().unwrap_or_else(|e| e.into_inner());
+            while recent.front().is_some_and(|t| t.elapsed() > RESTART_WINDOW) {
+                recent.pop_front();
+            }
+            if recent.len() >= max {
+                None
+            } else {
+                recent.push_back(Instant::now());
+                Some((BACKOFF_MIN * 2u32.saturating_pow(recent.len() as u32 - 1)).min(BACKOFF_MAX))
+            }
+        };
+        let Some(delay) = delay else {
+            let detail = match max {
+                0 => "automatic restart is off".to_string(),
+                _ => format!("{max} restarts within {} min", RESTART_WINDOW.as_secs() / 60),
+            };
+            event("encoder", &label, None, "gave_up", detail);
+            continue;
+        };
+        event("encoder", &label, None, "restarting", format!("in {} s", delay.as_secs()));
+        tokio::time::sleep(delay).await;
+
+        {
+            let mut o = state.output.lock().await;
+            // The operator stopped it, or started it again, in the meantime.
+            if o.encoder.is_some() || o.status.state != "error" || crate::shutdown::requested() {
+                continue;
+            }
+            o.status.restarts += 1;
+        }
+        let res = crate::output_start_internal(
+            state.output.clone(),
+            state.playout.clone(),
+            state.topup.clone(),
+            state.topup_stats.clone(),
+            state.pcm_tx.clone(),
+        )
+        .await;
+        match res {
+            Ok(()) => {}
+            // Only a failure to spawn is worth another try; the rest are
+            // settings (or a missing encoder) that waiting will not fix.
+            Err(StatusCode::INTERNAL_SERVER_ERROR) => RESTART.notify_one(),
+            Err(code) => {
+                let err = state.output.lock().await.status.last_error.clone().unwrap_or_default();
+                event("encoder", &label, None, "gave_up", format!("start failed ({code}): {err}"));
+            }
+        }
+    }
+}
+
+// --- API ---------------------------------------------------------------------------------
+
+#[derive(Deserialize)]
+pub(crate) struct EventsQuery {
+    /// "encoder" or "decoder"; default both.
+    child: Option<String>,
+    limit: Option<usize>,
+}
+
+/// `GET /api/v1/output/events?child=&limit=`, newest first.
+pub(crate) async fn api_output_events(
+    State(state): State<AppState>,
+    Query(q): Query<EventsQuery>,
+) -> Json<serde_json::Value> {
+    let limit = q.limit.unwrap_or(50).clamp(1, EVENTS_KEPT);
+    let items: Vec<ChildEvent> = {
+        let ev = EVENTS.lock().unwrap_or_else(|e| e.into_inner());
+        ev.items
+            .iter()
+            .rev()
+            .filter(|e| q.child.as_deref().is_none_or(|c| e.child == c))
+            .take(limit)
+            .cloned()
+            .collect()
+    };
+    let o = state.output.lock().await;
+    Json(json!({
+        "ok": true,
+        "encoder_pid": o.encoder.as_ref().and_then(|e| e.pid),
+        "restarts": o.status.restarts,
+        "max_restarts": max_restarts(),
+        "items": items,
+    }))
+}
@@ -0,0 +1,417 @@
+// --- Control surfaces ---------------------------------------------------------------------
+//
+// Physical buttons beyond GPIO: a MIDI controller on the desk, or a Stream
+// Deck. Both end up as a named control being pressed, and `bindings` map a
+// control to an action-scheduler action (schedule.rs: `skip`,
+// `insert_cart`, `output_toggle`, ...), as GPIO inputs do.
+//
+// - MIDI comes from the ALSA sequencer through `aseqdump -p <port>`
+//   (alsa-utils; `STUDIOCOMMAND_ASEQDUMP` overrides the program), read line
+//   by line like ffmpeg's progress. A note-on is `midi:note:<ch>:<note>`. A
+//   control change is `midi:cc:<ch>:<controller>`, pressed when its value
+//   rises to 64 or more, so CC buttons (127 down, 0 up) fire once.
+// - Stream Deck plugins (or any script) connect to
+//   `GET /api/v1/surfaces/ws` and send `{"type": "press", "key": "<name>"}`,
+//   the control `deck:<name>`. The engine answers each press with
+//   `{"type": "result", ...}` and pushes `{"type": "state", ...}` (on air,
+//   title, artist, seconds left of the intro) when it changes, so a key can
+//   show the tally or the talk-up countdown.
+//
+// Learn mode: `POST /api/v1/surfaces/learn` with an action arms it, and the
+// next control pressed (MIDI or WebSocket) is bound to that action instead
+// of firing. It gives up after `timeout_s` (default 30).
+//
+// There are no audio buses in the engine, so a fader (a CC that sweeps
+// 0..127) has nothing to move yet; only presses are mapped.
+
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::sync::Mutex;
+
+use axum::{
+    extract::ws::{Message, WebSocket, WebSocketUpgrade},
+    extract::State,
+    http::StatusCode,
+    response::Response,
+    Json,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::time::Duration;
+
+use crate::schedule::{self, Action};
+use crate::{standby, unix_ms_now, AppState};
+
+/// How often the MIDI reader checks for a changed port.
+const RELOAD: Duration = Duration::from_secs(5);
+/// How often WebSocket clients are checked for a changed state.
+const STATE_POLL: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct SurfaceConfig {
+    /// ALSA sequencer port to read (`aseqdump -l` lists them), e.g. `20:0`
+    /// or a client name. Empty turns MIDI off.
+    #[serde(default)]
+    midi_port: String,
+    #[serde(default)]
+    bindings: Vec<Binding>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Binding {
+    control: String,
+    action: Action,
+}
+
+// `Action` has no PartialEq; compare bindings by their JSON.
+impl PartialEq for Binding {
+    fn eq(&self, other: &Self) -> bool {
+        serde_json::to_value(self).ok() == serde_json::to_value(other).ok()
+    }
+}
+
+#[derive(Clone, Serialize)]
+struct Learn {
+    action: Action,
+    until_ms: u64,
+}
+
+#[derive(Clone, Serialize)]
+struct Press {
+    control: String,
+    at_ms: u64,
+    /// "ok", an error, "learned", "unbound" or "skipped (standby)".
+    result: String,
+}
+
+#[derive(Clone, Serialize, Default)]
+struct SurfaceStatus {
+    /// `aseqdump` is running on `midi_port`.
+    midi_running: bool,
+    midi_error: Option<String>,
+    deck_clients: u32,
+    learning: Option<Learn>,
+    last_learned: Option<String>,
+    last_press: Option<Press>,
+}
+
+static STATUS: Mutex<Option<SurfaceStatus>> = Mutex::new(None);
+
+fn with_status<T>(f: impl FnOnce(&mut SurfaceStatus) -> T) -> T {
+    f(STATUS.lock().unwrap_or_else(|e| e.into_inner()).get_or_insert_with(SurfaceStatus::default))
+}
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS surface_config (
+            id      INTEGER PRIMARY KEY CHECK (id = 1),
+            config  TEXT NOT NULL
+        );
+        "#,
+    )
+}
+
+fn db_load_config(conn: &Connection) -> anyhow::Result<SurfaceConfig> {
+    crate::db_init(conn)?;
+    let raw: Option<String> =
+        conn.query_row("SELECT config FROM surface_config WHERE id = 1", [], |row| row.get(0)).optional()?;
+    Ok(match raw {
+        Some(r) => serde_json::from_str(&r)?,
+        None => SurfaceConfig::default(),
+    })
+}
+
+fn db_save_config(conn: &Connection, cfg: &SurfaceConfig) -> anyhow::Result<()> {
+    crate::db_init(conn)?;
+    conn.execute(
+        "INSERT INTO surface_config (id, config) VALUES (1, ?1)
+         ON CONFLICT(id) DO UPDATE SET config=excluded.config",
+        params![serde_json::to_string(cfg)?],
+    )?;
+    Ok(())
+}
+
+async fn load_config() -> anyhow::Result<SurfaceConfig> {
+    crate::db::call(move |conn| db_load_config(conn)).await?
+}
+
+// --- Presses ---------------------------------------------------------------------------------
+
+/// Handle one press of `control`: learn it, or run its action.
+async fn press(state: &AppState, control: String) -> String {
+    let now = unix_ms_now();
+    let learn = with_status(|s| s.learning.take()).filter(|l| l.until_ms > now);
+    let result = if let Some(learn) = learn {
+        let binding = Binding { control: control.clone(), action: learn.action };
+        let saved = crate::db::call(move |conn| -> anyhow::Result<()> {
+            let mut cfg = db_load_config(conn)?;
+            cfg.bindings.retain(|b| b.control != binding.control);
+            cfg.bindings.push(binding);
+            db_save_config(conn, &cfg)
+        })
+        .await;
+        match saved {
+            Ok(Ok(())) => {
+                tracing::info!(target: "audit", "surfaces: learned {control}");
+                with_status(|s| s.last_learned = Some(control.clone()));
+                "learned".to_string()
+            }
+            Ok(Err(e)) => e.to_string(),
+            Err(e) => e.to_string(),
+        }
+    } else {
+        let action = match load_config().await {
+            Ok(cfg) => cfg.bindings.into_iter().find(|b| b.control == control).map(|b| b.action),
+            Err(e) => {
+                tracing::warn!("surfaces: {e}");
+                None
+            }
+        };
+        match action {
+            None => "unbound".to_string(),
+            // A standby leaves the actions to the primary (see standby.rs).
+            Some(_) if standby::is_standby() => "skipped (standby)".to_string(),
+            Some(action) => {
+                tracing::info!(target: "audit", "surfaces: {control} pressed");
+                match schedule::run_action(state, &action).await {
+                    Ok(()) => "ok".to_string(),
+                    Err(e) => {
+                        tracing::warn!("surfaces: {control}: {e}");
+                        e
+                    }
+                }
+            }
+        }
+    };
+    with_status(|s| s.last_press = Some(Press { control, at_ms: now, result: result.clone() }));
+    result
+}
+
+// --- MIDI --------------------------------------------------------------------------------------
+
+/// A press from one `aseqdump` line, e.g.
+/// ` 20:0   Note on                 0, note 60, velocity 100` or
+/// ` 20:0   Control change          0, controller 7, value 127`.
+/// `ccs` holds the last value of each controller, to fire on the rising edge.
+fn parse_midi(line: &str, ccs: &mut HashMap<(u8, u8), u8>) -> Option<String> {
+    let (kind, rest) = if let Some((_, rest)) = line.split_once("Note on") {
+        ("note", rest)
+    } else if let Some((_, rest)) = line.split_once("Control change") {
+        ("cc", rest)
+    } else {
+        return None;
+    };
+    let mut fields = rest.split(',').map(str::trim);
+    let ch: u8 = fields.next()?.parse().ok()?;
+    let num: u8 = fields.next()?.split_whitespace().last()?.parse().ok()?;
+    let value: u8 = fields.next()?.split_whitespace().last()?.parse().ok()?;
+    match kind {
+        // Many controllers send note-on with velocity 0 for note-off.
+        "note" => (value > 0).then(|| format!("midi:note:{ch}:{num}")),
+        _ => {
+            let before = ccs.insert((ch, num), value).unwrap_or(0);
+            (before < 64 && value >= 64).then(|| format!("midi:cc:{ch}:{num}"))
+        }
+    }
+}
+
+/// Read the configured MIDI port while there is one.
+pub(crate) async fn midi_task(state: AppState) {
+    let program = std::env::var("STUDIOCOMMAND_ASEQDUMP").unwrap_or_else(|_| "aseqdump".to_string());
+    loop {
+        let port = match load_config().await {
+            Ok(cfg) => cfg.midi_port,
+            Err(e) => {
+                tracing::warn!("surfaces: {e}");
+                String::new()
+            }
+        };
+        if port.is_empty() {
+            with_status(|s| (s.midi_running, s.midi_error) = (false, None));
+            tokio::time::sleep(RELOAD).await;
+            continue;
+        }
+        let mut cmd = tokio::process::Command::new(&program);
+        cmd.args(["-p", &port]).stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped());
+        cmd.kill_on_drop(true);
+        let mut child = match cmd.spawn() {
+            Ok(c) => c,
+            Err(e) => {
+                with_status(|s| (s.midi_running, s.midi_error) = (false, Some(format!("{program}: {e}"))));
+                tokio::time::sleep(RELOAD).await;
+                continue;
+            }
+        };
+        tracing::info!("surfaces: reading MIDI from {port}");
+        with_status(|s| (s.midi_running, s.midi_error) = (true, None));
+        let mut lines = BufReader::new(child.stdout.take().expect("piped")).lines();
+        let mut ccs = HashMap::new();
+        let mut reload = tokio::time::interval(RELOAD);
+        reload.tick().await;
+        let error = loop {
+            tokio::select! {
+                line = lines.next_line() => match line {
+                    Ok(Some(line)) => {
+                        if let Some(control) = parse_midi(&line, &mut ccs) {
+                            let state = state.clone();
+                            tokio::spawn(async move { press(&state, control).await });
+                        }
+                    }
+                    _ => break Some(format!("{program} -p {port} exited")),
+                },
+                _ = reload.tick() => {
+                    if load_config().await.map(|c| c.midi_port != port).unwrap_or(false) {
+                        break None;
+                    }
+                }
+            }
+        };
+        // aseqdump explains a bad port on stderr.
+        let _ = child.start_kill();
+        let mut detail = String::new();
+        if let Some(stderr) = child.stderr.take() {
+            let mut stderr = BufReader::new(stderr).lines();
+            while let Ok(Ok(Some(line))) = tokio::time::timeout(Duration::from_secs(1), stderr.next_line()).await {
+                detail = line;
+            }
+        }
+        let _ = child.wait().await;
+        let error = error.map(|e| if detail.is_empty() { e } else { format!("{e}: {detail}") });
+        if let Some(e) = &error {
+            tracing::warn!("surfaces: {e}");
+        }
+        with_status(|s| (s.midi_running, s.midi_error) = (false, error.clone()));
+        if error.is_some() {
+            tokio::time::sleep(RELOAD).await;
+        }
+    }
+}
+
+// --- WebSocket ---------------------------------------------------------------------------------
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum DeckMessage {
+    Press { key: String },
+}
+
+async fn deck_state(state: &AppState) -> serde_json::Value {
+    let on_air = state.output.lock().await.status.state == "connected";
+    let snap = state.snapshot.borrow().clone();
+    // Whole seconds, so the countdown is pushed once a second rather than every poll.
+    let intro = snap.now.intro_remaining_sec.map(|s| s.ceil() as u32);
+    json!({"type": "state", "on_air": on_air, "title": snap.now.title, "artist": snap.now.artist, "intro": intro})
+}
+
+/// `GET /api/v1/surfaces/ws`: the Stream Deck protocol (see the top of this file).
+pub(crate) async fn api_surfaces_ws(State(state): State<AppState>, ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(move |socket| deck_session(state, socket))
+}
+
+async fn deck_session(state: AppState, mut socket: WebSocket) {
+    with_status(|s| s.deck_clients += 1);
+    let mut sent = serde_json::Value::Null;
+    let mut poll = tokio::time::interval(STATE_POLL);
+    loop {
+        let reply = tokio::select! {
+            msg = socket.recv() => match msg {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str::<DeckMessage>(&text) {
+                    Ok(DeckMessage::Press { key }) => {
+                        let result = press(&state, format!("deck:{key}")).await;
+                        json!({"type": "result", "key": key, "result": result})
+                    }
+                    Err(e) => json!({"type": "error", "error": e.to_string()}),
+                },
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+            _ = poll.tick() => {
+                let now = deck_state(&state).await;
+                if now == sent {
+                    continue;
+                }
+                sent = now.clone();
+                now
+            }
+        };
+        if socket.send(Message::Text(reply.to_string())).await.is_err() {
+            break;
+        }
+    }
+    with_status(|s| s.deck_clients -= 1);
+}
+
+// --- HTTP API --------------------------------------------------------------------------
+
+/// `GET /api/v1/surfaces/config`
+pub(crate) async fn api_surfaces_config_get() -> Result<Json<serde_json::Value>, StatusCode> {
+    let cfg = load_config().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let now = unix_ms_now();
+    let status = with_status(|s| {
+        s.learning = s.learning.take().filter(|l| l.until_ms > now);
+        s.clone()
+    });
+    Ok(Json(json!({"ok": true, "config": cfg, "status": status})))
+}
+
+/// `POST /api/v1/surfaces/config`
+pub(crate) async fn api_surfaces_config_set(
+    Json(mut cfg): Json<SurfaceConfig>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    let bad = |msg: String| Err((StatusCode::BAD_REQUEST, Json(json!({"ok": false, "error": msg}))));
+    cfg.midi_port = cfg.midi_port.trim().to_string();
+    for b in &mut cfg.bindings {
+        b.control = b.control.trim().to_string();
+    }
+    if let Some(b) = cfg.bindings.iter().find(|b| !valid_control(&b.control)) {
+        return bad(format!("control {:?}: expected midi:note:<ch>:<n>, midi:cc:<ch>:<n> or deck:<key>", b.control));
+    }
+    let mut controls: Vec<&str> = cfg.bindings.iter().map(|b| b.control.as_str()).collect();
+    controls.sort_unstable();
+    if let Some(w) = controls.windows(2).find(|w| w[0] == w[1]) {
+        return bad(format!("control {} is bound twice", w[0]));
+    }
+    let saved = cfg.clone();
+    crate::db::call(move |conn| db_save_config(conn, &saved))
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"ok": false}))))?
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"ok": false}))))?;
+    Ok(Json(json!({"ok": true, "config": cfg})))
+}
+
+fn valid_control(control: &str) -> bool {
+    if let Some(rest) = control.strip_prefix("midi:note:").or_else(|| control.strip_prefix("midi:cc:")) {
+        let Some((ch, n)) = rest.split_once(':') else { return false };
+        ch.parse::<u8>().is_ok_and(|c| c < 16) && n.parse::<u8>().is_ok_and(|n| n < 128)
+    } else {
+        control.strip_prefix("deck:").is_some_and(|key| !key.is_empty())
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct LearnReq {
+    action: Action,
+    timeout_s: Option<u64>,
+}
+
+/// `POST /api/v1/surfaces/learn`: bind the next control pressed to `action`.
+pub(crate) async fn api_surfaces_learn(
+    Json(req): Json<LearnReq>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    let timeout_s = req.timeout_s.unwrap_or(30);
+    if !(1..=600).contains(&timeout_s) {
+        return Err((StatusCode::BAD_REQUEST, Json(json!({"ok": false, "error": "timeout_s must be 1..600"}))));
+    }
+    let learn = Learn { action: req.action, until_ms: unix_ms_now() + timeout_s * 1000 };
+    with_status(|s| s.learning = Some(learn.clone()));
+    Ok(Json(json!({"ok": true, "learning": learn})))
+}
+
+/// `DELETE /api/v1/surfaces/learn`
+pub(crate) async fn api_surfaces_learn_cancel() -> Json<serde_json::Value> {
+    let was = with_status(|s| s.learning.take()).is_some();
+    Json(json!({"ok": true, "cancelled": was}))
+}
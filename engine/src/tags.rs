@@ -0,0 +1,254 @@
+// --- Tags ----------------------------------------------------------------------------
+//
+// Every queue item carries a tag (MUS, EVT, ID, ...). The tag used to be a
+// free-form string, with behavior keyed off magic values scattered over the
+// code and settings pages: the public feeds' `music_tags`, "MUS" as the
+// default everywhere. The `tags` table makes the taxonomy explicit:
+// - `code`, `label` and `color` for the UI (a CSS color, e.g. `#2e7d32`;
+//   empty leaves it to the UI),
+// - `music`: counts as music. The public feeds, the AzuraCast API and
+//   listener requests show only music (the public feed config's
+//   `music_tags` is this list),
+// - `suppress_metadata`: never sent to metadata targets (metapush.rs); RDS
+//   shows its idle texts. For items the audience should not see named.
+//
+// Tags set through the API (queue insert, batch, replace, item patch,
+// library tag) must exist here; an unknown one is refused instead of
+// silently matching no rule. The tags the engine itself assigns (MUS, EVT,
+// ID, REQ, VT and the break marker BRK) are built in and cannot be deleted.
+// A cart category is a tag too: creating one registers it.
+//
+// The table is mirrored in memory (checks run under the queue lock and on the
+// playout path), like the cart index.
+
+use std::collections::HashMap;
+use std::sync::{OnceLock, RwLock};
+
+use axum::{extract::Path, http::StatusCode, Json};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::BREAK_MARKER_TAG;
+
+/// Tags the engine assigns on its own.
+const BUILTIN: &[&str] = &["MUS", "EVT", "ID", "REQ", "VT", BREAK_MARKER_TAG];
+
+#[derive(Clone, Serialize)]
+pub(crate) struct Tag {
+    code: String,
+    label: String,
+    color: String,
+    music: bool,
+    suppress_metadata: bool,
+    builtin: bool,
+}
+
+/// Migration 8: the tags table, seeded with the built-in tags, the cart
+/// categories and every tag already in use. `music` starts out as the
+/// public feed's `music_tags`.
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(&format!(
+        r#"
+        CREATE TABLE IF NOT EXISTS tags (
+            code               TEXT PRIMARY KEY,
+            label              TEXT NOT NULL,
+            color              TEXT NOT NULL DEFAULT '',
+            music              INTEGER NOT NULL DEFAULT 0,
+            suppress_metadata  INTEGER NOT NULL DEFAULT 0
+        );
+
+        INSERT OR IGNORE INTO tags (code, label, color) VALUES
+            ('MUS', 'Music', '#2e7d32'),
+            ('EVT', 'Event', '#c62828'),
+            ('ID',  'Station ID', '#6a1b9a'),
+            ('REQ', 'Request', '#00838f'),
+            ('VT',  'Voice track', '#ef6c00'),
+            ('{BREAK_MARKER_TAG}', 'Break', '#546e7a');
+
+        INSERT OR IGNORE INTO tags (code, label) SELECT UPPER(code), name FROM cart_categories;
+        INSERT OR IGNORE INTO tags (code, label)
+            SELECT DISTINCT UPPER(tag), UPPER(tag) FROM (
+                SELECT tag FROM queue_items
+                UNION SELECT tag FROM aux_queue_items
+                UNION SELECT tag FROM library_tracks
+                UNION SELECT tag FROM day_log_items
+            ) WHERE TRIM(tag) <> '';
+
+        UPDATE tags SET music = 1 WHERE code IN (
+            SELECT UPPER(value) FROM public_feed_config, json_each(public_feed_config.music_tags)
+        );
+        UPDATE tags SET music = 1 WHERE code = 'MUS' AND NOT EXISTS (SELECT 1 FROM public_feed_config);
+        "#
+    ))
+}
+
+// --- In-memory copy ------------------------------------------------------------
+
+fn cache() -> &'static RwLock<HashMap<String, Tag>> {
+    static CACHE: OnceLock<RwLock<HashMap<String, Tag>>> = OnceLock::new();
+    CACHE.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+fn tag_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Tag> {
+    let code: String = row.get(0)?;
+    Ok(Tag {
+        builtin: BUILTIN.contains(&code.as_str()),
+        code,
+        label: row.get(1)?,
+        color: row.get(2)?,
+        music: row.get(3)?,
+        suppress_metadata: row.get(4)?,
+    })
+}
+
+fn db_list(conn: &Connection) -> anyhow::Result<Vec<Tag>> {
+    crate::db_init(conn)?;
+    let mut stmt = conn.prepare("SELECT code, label, color, music, suppress_metadata FROM tags ORDER BY code")?;
+    let rows = stmt.query_map([], tag_from_row)?;
+    Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+}
+
+/// Load (or reload) the in-memory copy. At startup and after every change.
+pub(crate) async fn load() {
+    match crate::db::call(|conn| db_list(conn)).await {
+        Ok(Ok(list)) => {
+            if let Ok(mut c) = cache().write() {
+                *c = list.into_iter().map(|t| (t.code.clone(), t)).collect();
+            }
+        }
+        Ok(Err(e)) => tracing::warn!("tags: failed to load: {e}"),
+        Err(e) => tracing::warn!("tags: load task failed: {e}"),
+    }
+}
+
+/// `tag` as stored (trimmed, upper-cased), or why it is refused. Everything is
+/// accepted while the table has not been loaded.
+pub(crate) fn check(tag: &str) -> Result<String, String> {
+    let code = tag.trim().to_ascii_uppercase();
+    if code.is_empty() {
+        return Err("tag is empty".into());
+    }
+    let known = cache().read().map(|c| c.is_empty() || c.contains_key(&code)).unwrap_or(true);
+    if known {
+        Ok(code)
+    } else {
+        Err(format!("unknown tag {code} (see /api/v1/tags)"))
+    }
+}
+
+/// Items with this tag are kept out of outbound metadata.
+pub(crate) fn suppresses_metadata(tag: &str) -> bool {
+    cache()
+        .read()
+        .ok()
+        .and_then(|c| c.get(&tag.to_ascii_uppercase()).map(|t| t.suppress_metadata))
+        .unwrap_or(false)
+}
+
+// --- HTTP API ----------------------------------------------------------------------
+
+/// Run a blocking DB closure, mapping every failure to a 500.
+async fn with_db<T: Send + 'static>(
+    f: impl FnOnce(&Connection) -> anyhow::Result<T> + Send + 'static,
+) -> Result<T, StatusCode> {
+    crate::db::call(move |conn| f(conn))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|e| {
+            tracing::warn!("tags: db error: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+/// Codes end up in logs, CSV imports and URLs: keep them short and plain.
+fn valid_code(code: &str) -> bool {
+    !code.is_empty() && code.len() <= 16 && code.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+}
+
+pub(crate) async fn api_tags_list() -> Result<Json<Vec<Tag>>, StatusCode> {
+    with_db(db_list).await.map(Json)
+}
+
+#[derive(Deserialize)]
+pub(crate) struct TagReq {
+    label: String,
+    #[serde(default)]
+    color: String,
+    #[serde(default)]
+    music: bool,
+    #[serde(default)]
+    suppress_metadata: bool,
+}
+
+/// Create or update a tag.
+pub(crate) async fn api_tag_put(
+    Path(code): Path<String>,
+    Json(req): Json<TagReq>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let code = code.trim().to_ascii_uppercase();
+    let label = req.label.trim().to_string();
+    let color = req.color.trim().to_string();
+    if !valid_code(&code) || label.is_empty() || color.len() > 32 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    with_db(move |conn| {
+        crate::db_init(conn)?;
+        conn.execute(
+            "INSERT INTO tags (code, label, color, music, suppress_metadata) VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT(code) DO UPDATE SET
+               label=excluded.label,
+               color=excluded.color,
+               music=excluded.music,
+               suppress_metadata=excluded.suppress_metadata",
+            params![code, label, color, req.music, req.suppress_metadata],
+        )?;
+        Ok(())
+    })
+    .await?;
+    load().await;
+    Ok(Json(json!({"ok": true})))
+}
+
+/// Register `code` with `label` unless it exists (a new cart category).
+pub(crate) async fn ensure(code: String, label: String) -> Result<(), StatusCode> {
+    with_db(move |conn| {
+        crate::db_init(conn)?;
+        conn.execute("INSERT OR IGNORE INTO tags (code, label) VALUES (?1, ?2)", params![code, label])?;
+        Ok(())
+    })
+    .await?;
+    load().await;
+    Ok(())
+}
+
+/// Delete a tag. Built-in tags, and tags still used by the queue, the library,
+/// carts or day logs, are refused with 409.
+pub(crate) async fn api_tag_delete(Path(code): Path<String>) -> Result<Json<serde_json::Value>, StatusCode> {
+    let code = code.trim().to_ascii_uppercase();
+    if BUILTIN.contains(&code.as_str()) {
+        return Err(StatusCode::CONFLICT);
+    }
+    with_db(move |conn| {
+        crate::db_init(conn)?;
+        let in_use: i64 = conn.query_row(
+            "SELECT (SELECT COUNT(*) FROM queue_items WHERE UPPER(tag) = ?1)
+                  + (SELECT COUNT(*) FROM aux_queue_items WHERE UPPER(tag) = ?1)
+                  + (SELECT COUNT(*) FROM library_tracks WHERE UPPER(tag) = ?1)
+                  + (SELECT COUNT(*) FROM carts WHERE UPPER(category) = ?1)
+                  + (SELECT COUNT(*) FROM day_log_items WHERE UPPER(tag) = ?1)",
+            params![code],
+            |r| r.get(0),
+        )?;
+        if in_use > 0 {
+            return Ok(Err(StatusCode::CONFLICT));
+        }
+        if conn.execute("DELETE FROM tags WHERE code = ?1", params![code])? == 0 {
+            return Ok(Err(StatusCode::NOT_FOUND));
+        }
+        Ok(Ok(()))
+    })
+    .await??;
+    load().await;
+    Ok(Json(json!({"ok": true})))
+}
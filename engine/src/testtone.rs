@@ -0,0 +1,268 @@
+// --- Test tone ------------------------------------------------------------------------------
+//
+// Installers need to prove the encoder and the Icecast mount work before
+// there is anything worth queueing, and "queue a song and listen" mixes up
+// library, decoder and stream problems. `POST /api/v1/output/test-tone`
+// feeds the encoder a generated signal instead of the playout:
+// - `signal`: "tone" (a sine, `freq_hz`, default 1000) or "pink" (pink noise),
+//   at `level_dbfs` (default -18; pink noise at the same RMS as the tone);
+// - `sink`: "icecast" (the configured mount, as the real stream) or "null"
+//   (encode and throw away, to check ffmpeg and the codec without a server);
+// - `duration_s`: default 60, at most 3600.
+//
+// The test uses the output's encoder slot, so it refuses to start while the
+// stream is up, a stream start refuses while a test runs, and
+// `POST /api/v1/output/stop` ends it early. The signal also goes to the
+// meters and to Listen Live. An encoder that dies during a test is reported
+// in the output status and events but is not restarted, and when the test
+// ends the output stays stopped.
+
+use std::sync::Arc;
+
+use axum::{extract::State, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+use crate::{
+    analyze_pcm_s16le_stereo, ffmpeg, last_stderr_summary, meters, push_stderr_tail, sanitize_ffmpeg_line, shutdown,
+    spawn_encoder, supervisor, unix_ms_now, AppState, EncoderSink, OutputRuntime,
+};
+
+const SAMPLE_RATE: f32 = 48_000.0;
+/// 20 ms of audio per write.
+const FRAMES: usize = 960;
+const MAX_DURATION_S: u64 = 3600;
+
+#[derive(Deserialize)]
+pub(crate) struct TestToneReq {
+    #[serde(default = "default_signal")]
+    signal: String,
+    #[serde(default = "default_freq")]
+    freq_hz: f32,
+    #[serde(default = "default_level")]
+    level_dbfs: f32,
+    #[serde(default = "default_sink")]
+    sink: String,
+    #[serde(default = "default_duration")]
+    duration_s: u64,
+}
+
+fn default_signal() -> String {
+    "tone".into()
+}
+
+fn default_freq() -> f32 {
+    1000.0
+}
+
+fn default_level() -> f32 {
+    -18.0
+}
+
+fn default_sink() -> String {
+    "icecast".into()
+}
+
+fn default_duration() -> u64 {
+    60
+}
+
+/// A running test, as `GET /api/v1/output` shows it.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct TestTone {
+    signal: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    freq_hz: Option<f32>,
+    level_dbfs: f32,
+    sink: String,
+    started_ms: u64,
+    until_ms: u64,
+}
+
+/// Sine or pink noise, interleaved stereo s16le.
+enum Generator {
+    Tone { phase: f32, step: f32 },
+    /// Paul Kellet's economy filter over white noise.
+    Pink { b: [f32; 3] },
+}
+
+impl Generator {
+    fn next(&mut self) -> f32 {
+        match self {
+            Generator::Tone { phase, step } => {
+                let v = phase.sin();
+                *phase = (*phase + *step) % std::f32::consts::TAU;
+                v
+            }
+            Generator::Pink { b } => {
+                let white = fastrand::f32() * 2.0 - 1.0;
+                b[0] = 0.99765 * b[0] + white * 0.0990;
+                b[1] = 0.96300 * b[1] + white * 0.2965;
+                b[2] = 0.57000 * b[2] + white * 1.0526;
+                // The filter's output has an RMS of about 1.73; scale it to a
+                // full-scale sine's (1/sqrt 2) so both read the same on a meter.
+                (b[0] + b[1] + b[2] + white * 0.1848) / 1.73 * std::f32::consts::FRAC_1_SQRT_2
+            }
+        }
+    }
+
+    fn chunk(&mut self, gain: f32) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(FRAMES * 4);
+        for _ in 0..FRAMES {
+            let v = (self.next() * gain).clamp(-1.0, 1.0);
+            let s = (v * i16::MAX as f32) as i16;
+            buf.extend_from_slice(&s.to_le_bytes());
+            buf.extend_from_slice(&s.to_le_bytes());
+        }
+        buf
+    }
+}
+
+fn bad(code: StatusCode, msg: impl Into<String>) -> (StatusCode, Json<serde_json::Value>) {
+    (code, Json(json!({"ok": false, "error": msg.into()})))
+}
+
+pub(crate) async fn api_output_test_tone(
+    State(state): State<AppState>,
+    Json(req): Json<TestToneReq>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    let generator = match req.signal.as_str() {
+        "tone" if (20.0..=20_000.0).contains(&req.freq_hz) => {
+            Generator::Tone { phase: 0.0, step: std::f32::consts::TAU * req.freq_hz / SAMPLE_RATE }
+        }
+        "tone" => return Err(bad(StatusCode::BAD_REQUEST, "freq_hz must be 20..20000")),
+        "pink" => Generator::Pink { b: [0.0; 3] },
+        _ => return Err(bad(StatusCode::BAD_REQUEST, "signal must be \"tone\" or \"pink\"")),
+    };
+    let sink = match req.sink.as_str() {
+        "icecast" => EncoderSink::Icecast,
+        "null" => EncoderSink::Null,
+        _ => return Err(bad(StatusCode::BAD_REQUEST, "sink must be \"icecast\" or \"null\"")),
+    };
+    if !(-60.0..=0.0).contains(&req.level_dbfs) {
+        return Err(bad(StatusCode::BAD_REQUEST, "level_dbfs must be -60..0"));
+    }
+    let duration_s = req.duration_s.clamp(1, MAX_DURATION_S);
+
+    let mut o = state.output.lock().await;
+    if o.encoder.is_some() {
+        return Err(bad(StatusCode::CONFLICT, "output is running; stop it first"));
+    }
+    if sink == EncoderSink::Icecast && o.config.password.trim().is_empty() {
+        return Err(bad(StatusCode::BAD_REQUEST, "Icecast password is empty"));
+    }
+    ffmpeg::check_output_codec(&o.config.codec).await.map_err(|e| bad(StatusCode::FAILED_DEPENDENCY, e))?;
+    let (child, stdin, stderr) =
+        spawn_encoder(&o.config, sink).await.map_err(|e| bad(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let started_ms = unix_ms_now();
+    let test = TestTone {
+        signal: req.signal.clone(),
+        freq_hz: (req.signal == "tone").then_some(req.freq_hz),
+        level_dbfs: req.level_dbfs,
+        sink: req.sink.clone(),
+        started_ms,
+        until_ms: started_ms + duration_s * 1000,
+    };
+    tracing::info!("output: test {} to {} for {duration_s} s", test.signal, test.sink);
+
+    supervisor::reset_restarts();
+    o.status.state = "testing".into();
+    o.status.last_error = None;
+    o.status.restarts = 0;
+    o.status.test = Some(test.clone());
+    o.started_at = Some(std::time::Instant::now());
+    o.stderr_tail.clear();
+
+    let gain = 10f32.powf(req.level_dbfs / 20.0);
+    let pcm_tx = state.pcm_tx.clone();
+    let writer_task = tokio::spawn(async move {
+        if let Err(e) = writer_test_signal(stdin, generator, gain, duration_s, pcm_tx).await {
+            tracing::warn!("output: test signal writer: {e}");
+        }
+    });
+
+    let output_for_stderr = state.output.clone();
+    let password = o.config.password.clone();
+    let stderr_task = tokio::spawn(async move {
+        let mut lines = BufReader::new(stderr).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let sanitized = sanitize_ffmpeg_line(&line, &password);
+            if !sanitized.trim().is_empty() {
+                push_stderr_tail(&mut *output_for_stderr.lock().await, sanitized);
+            }
+        }
+    });
+
+    let label = match sink {
+        EncoderSink::Icecast => format!("test {}:{}{}", o.config.host, o.config.port, o.config.mount),
+        EncoderSink::Null => "test null".to_string(),
+    };
+    let (encoder, exit) = supervisor::supervise("encoder", label, child);
+    o.generation += 1;
+    tokio::spawn(watch_test(state.output.clone(), o.generation, exit));
+    o.encoder = Some(encoder);
+    o.writer_task = Some(writer_task);
+    o.stderr_task = Some(stderr_task);
+
+    Ok(Json(json!({"ok": true, "test": test})))
+}
+
+/// Write the signal in real time for `duration_s`, then close the encoder's
+/// input so it flushes and exits.
+async fn writer_test_signal(
+    mut stdin: tokio::process::ChildStdin,
+    mut generator: Generator,
+    gain: f32,
+    duration_s: u64,
+    pcm_tx: tokio::sync::broadcast::Sender<Vec<u8>>,
+) -> anyhow::Result<()> {
+    let chunks = duration_s * SAMPLE_RATE as u64 / FRAMES as u64;
+    let mut interval = tokio::time::interval(std::time::Duration::from_millis(20));
+    for _ in 0..chunks {
+        if shutdown::requested() {
+            break;
+        }
+        interval.tick().await;
+        let buf = generator.chunk(gain);
+        meters::update(&analyze_pcm_s16le_stereo(&buf));
+        let _ = pcm_tx.send(buf.clone());
+        stdin.write_all(&buf).await?;
+    }
+    meters::reset();
+    stdin.shutdown().await?;
+    Ok(())
+}
+
+/// Like `supervisor::watch_encoder`, but a test is never restarted: when the
+/// encoder exits the output is stopped, with the error if it failed.
+async fn watch_test(
+    output: Arc<tokio::sync::Mutex<OutputRuntime>>,
+    generation: u64,
+    exit: tokio::sync::oneshot::Receiver<supervisor::Exit>,
+) {
+    let Ok(exit) = exit.await else { return };
+    let mut o = output.lock().await;
+    if exit.requested || o.generation != generation || o.encoder.is_none() {
+        return;
+    }
+    o.encoder = None;
+    o.started_at = None;
+    o.status.uptime_sec = 0;
+    o.status.test = None;
+    o.status.last_exit = Some(exit.describe());
+    if let Some(task) = o.writer_task.take() {
+        task.abort();
+    }
+    if let Some(task) = o.stderr_task.take() {
+        task.abort();
+    }
+    if exit.success() {
+        o.status.state = "stopped".into();
+        return;
+    }
+    o.status.state = "error".into();
+    o.status.last_error =
+        Some(last_stderr_summary(&o.stderr_tail).unwrap_or_else(|| format!("test: ffmpeg {}", exit.describe())));
+}
@@ -0,0 +1,179 @@
+// --- Clock synchronization ------------------------------------------------------------------
+//
+// Hard-timed events, clocks, announcements and the as-run log all trust the
+// system clock. A box whose NTP daemon never reached a server (no network at
+// boot, a firewalled port 123, a dead RTC battery) runs seconds or minutes
+// off, and nothing said so. `GET /api/v1/system/info` now reports `clock`:
+// whether the clock is synchronized, the estimated offset from the time
+// source, and where that came from:
+// - `chrony`: `chronyc -c tracking` (offset, stratum, reference),
+// - `timesyncd`: `timedatectl show` and `timedatectl timesync-status`,
+// - `kernel`: when neither answers, the kernel's own view (`adjtimex`):
+//   synchronized or not and the estimated error, but no offset.
+// The result is kept for 30 s, since alerts look at it every few seconds.
+// The `clock_unsynced` alert (alerts.rs) fires when the clock is not
+// synchronized, or is further off than `clock_offset_ms`.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+const CACHE_FOR: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Serialize)]
+pub(crate) struct TimeSync {
+    /// "chrony", "timesyncd", "kernel" or "unknown".
+    source: &'static str,
+    /// `None` when nothing could tell.
+    pub(crate) synchronized: Option<bool>,
+    /// System clock minus source time, in milliseconds (positive: fast).
+    pub(crate) offset_ms: Option<f64>,
+    /// The kernel's estimated error bound, in milliseconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    est_error_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stratum: Option<u32>,
+    /// Server the clock follows.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    server: Option<String>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    detail: String,
+}
+
+impl TimeSync {
+    fn unknown(detail: impl Into<String>) -> Self {
+        TimeSync {
+            source: "unknown",
+            synchronized: None,
+            offset_ms: None,
+            est_error_ms: None,
+            stratum: None,
+            server: None,
+            detail: detail.into(),
+        }
+    }
+
+    /// A line for `SystemInfo::warnings`, if the clock cannot be trusted.
+    pub(crate) fn warning(&self) -> Option<String> {
+        match self.synchronized {
+            Some(false) => Some(format!("clock is not synchronized ({})", self.source)),
+            _ => None,
+        }
+    }
+}
+
+static CACHE: Mutex<Option<(Instant, TimeSync)>> = Mutex::new(None);
+
+async fn run(program: &str, args: &[&str]) -> Option<String> {
+    let mut cmd = tokio::process::Command::new(program);
+    cmd.args(args).kill_on_drop(true);
+    let out = tokio::time::timeout(Duration::from_secs(2), cmd.output()).await.ok()?.ok()?;
+    out.status.success().then(|| String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+/// `chronyc -c tracking`: one CSV line, e.g.
+/// `A9FEA97B,169.254.169.123,4,1697500000.1,-0.000012,...,64.5,Normal`.
+fn parse_chrony(text: &str) -> Option<TimeSync> {
+    let f: Vec<&str> = text.trim().split(',').collect();
+    if f.len() < 14 {
+        return None;
+    }
+    let stratum: u32 = f[2].parse().ok()?;
+    // chrony reports how far the system clock is *behind* NTP time.
+    let behind_s: f64 = f[4].parse().ok()?;
+    let leap = f[13].trim();
+    let synchronized = stratum > 0 && !leap.eq_ignore_ascii_case("Not synchronised");
+    Some(TimeSync {
+        source: "chrony",
+        synchronized: Some(synchronized),
+        offset_ms: synchronized.then_some(-behind_s * 1000.0),
+        est_error_ms: None,
+        stratum: (stratum > 0).then_some(stratum),
+        server: Some(f[1].to_string()).filter(|s| !s.is_empty()),
+        detail: leap.to_string(),
+    })
+}
+
+/// `+1.234ms`, `-56us`, `2.5s` -> milliseconds.
+fn parse_duration_ms(s: &str) -> Option<f64> {
+    let s = s.trim();
+    let (num, scale) = if let Some(n) = s.strip_suffix("ms") {
+        (n, 1.0)
+    } else if let Some(n) = s.strip_suffix("us").or_else(|| s.strip_suffix("µs")) {
+        (n, 0.001)
+    } else if let Some(n) = s.strip_suffix("min") {
+        (n, 60_000.0)
+    } else if let Some(n) = s.strip_suffix('s') {
+        (n, 1000.0)
+    } else {
+        return None;
+    };
+    num.trim().trim_start_matches('+').parse::<f64>().ok().map(|v| v * scale)
+}
+
+fn field<'a>(text: &'a str, key: &str, sep: char) -> Option<&'a str> {
+    text.lines().find_map(|l| {
+        let (k, v) = l.split_once(sep)?;
+        (k.trim() == key).then(|| v.trim())
+    })
+}
+
+async fn timesyncd() -> Option<TimeSync> {
+    let show = run("timedatectl", &["show"]).await?;
+    let synchronized = field(&show, "NTPSynchronized", '=')? == "yes";
+    let ntp_on = field(&show, "NTP", '=') == Some("yes");
+    // `timesync-status` only works while systemd-timesyncd is the NTP service.
+    let status = run("timedatectl", &["timesync-status"]).await.unwrap_or_default();
+    let offset_ms = field(&status, "Offset", ':').and_then(parse_duration_ms);
+    Some(TimeSync {
+        source: "timesyncd",
+        synchronized: Some(synchronized),
+        offset_ms,
+        est_error_ms: None,
+        stratum: field(&status, "Stratum", ':').and_then(|s| s.parse().ok()),
+        server: field(&status, "Server", ':').map(str::to_string),
+        detail: if ntp_on { String::new() } else { "NTP is turned off (timedatectl set-ntp true)".into() },
+    })
+}
+
+fn kernel() -> TimeSync {
+    let mut tx: libc::timex = unsafe { std::mem::zeroed() };
+    // modes = 0: read only.
+    let state = unsafe { libc::adjtimex(&mut tx) };
+    if state < 0 {
+        return TimeSync::unknown(format!("adjtimex: {}", std::io::Error::last_os_error()));
+    }
+    let synchronized = state != libc::TIME_ERROR && tx.status & libc::STA_UNSYNC == 0;
+    TimeSync {
+        source: "kernel",
+        synchronized: Some(synchronized),
+        offset_ms: None,
+        est_error_ms: Some(tx.esterror as f64 / 1000.0),
+        stratum: None,
+        server: None,
+        detail: "neither chrony nor timesyncd answered".into(),
+    }
+}
+
+async fn detect() -> TimeSync {
+    if let Some(t) = run("chronyc", &["-c", "tracking"]).await.as_deref().and_then(parse_chrony) {
+        return t;
+    }
+    if let Some(t) = timesyncd().await {
+        return t;
+    }
+    kernel()
+}
+
+/// The current state, looked up at most every 30 s.
+pub(crate) async fn status() -> TimeSync {
+    if let Some((at, t)) = CACHE.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        if at.elapsed() < CACHE_FOR {
+            return t.clone();
+        }
+    }
+    let t = detect().await;
+    *CACHE.lock().unwrap_or_else(|e| e.into_inner()) = Some((Instant::now(), t.clone()));
+    t
+}
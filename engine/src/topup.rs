@@ -0,0 +1,794 @@
+// --- Top-up -------------------------------------------------------------------------------
+//
+// Keeps the queue from running dry: when fewer than `min_queue` items are
+// left, files from the configured folder (or weighted folders, or the active
+// daypart's folder) are appended, subject to the filters and rotation rules.
+
+use std::collections::HashSet;
+
+use axum::http::StatusCode;
+use axum::{extract::State, Json};
+use rusqlite::params;
+use serde::{Serialize, Deserialize};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    analysis, artist_title_from_path, bump_queue_rev, clocks, db, db_init, db_save_topup_config, estimate_start_times,
+    fmt_dur_ms, import, local_weekday_minute, normalize_queue_states, parse_dur_to_sec, parse_hhmm, paths,
+    persist_queue, resolve_cart_to_path, rotation, shufflebag, topuplog, unix_ms_now, AppState, LogItem,
+};
+
+#[derive(Clone, Serialize, Deserialize, Default)]
+pub(crate) struct TopUpConfig {
+    pub(crate) enabled: bool,
+    /// A folder, or `playlist:<file>` / `category:<name>` (see `topup_scan`).
+    pub(crate) dir: String,
+    pub(crate) min_queue: u16,
+    pub(crate) batch: u16,
+    /// Time-of-week overrides for `dir` (first match wins). Outside every
+    /// daypart, `dir` is used.
+    #[serde(default)]
+    pub(crate) dayparts: Vec<TopUpDaypart>,
+    /// Weighted folders used instead of `dir` when non-empty (a matching
+    /// daypart still wins).
+    #[serde(default)]
+    pub(crate) sources: Vec<TopUpSource>,
+    #[serde(default)]
+    pub(crate) filters: TopUpFilters,
+}
+
+/// Which files in a top-up folder may be queued at all. Keeps 2-second
+/// stingers, 3-hour airchecks and the `archive` subfolder off the air.
+#[derive(Clone, Serialize, Deserialize, Default)]
+pub(crate) struct TopUpFilters {
+    /// Shortest/longest file in seconds (0 = no limit). Checked against the
+    /// probed duration, so a file that cannot be probed fails a minimum.
+    #[serde(default)]
+    min_dur_s: u32,
+    #[serde(default)]
+    max_dur_s: u32,
+    /// Subfolders to skip: "archive" skips any folder of that name, a path
+    /// such as "/old/airchecks" is relative to the top-up folder.
+    #[serde(default)]
+    exclude_dirs: Vec<String>,
+    /// Allowed extensions ("mp3", "flac"); empty allows every supported one.
+    #[serde(default)]
+    extensions: Vec<String>,
+}
+
+impl TopUpFilters {
+    /// Path rules only (cheap, applied right after the scan).
+    pub(crate) fn keeps_path(&self, root: &str, path: &str) -> bool {
+        let p = paths::from_cart(path);
+        let p = p.as_path();
+        if !self.extensions.is_empty() {
+            let ext = p.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+            if !self.extensions.contains(&ext) {
+                return false;
+            }
+        }
+        let rel = p.strip_prefix(root).unwrap_or(p);
+        let rel_dir = rel.parent().map(|d| d.to_string_lossy().to_string()).unwrap_or_default();
+        !self.exclude_dirs.iter().any(|x| {
+            if x.contains('/') {
+                let x = x.trim_matches('/');
+                rel_dir == x || rel_dir.starts_with(&format!("{x}/"))
+            } else {
+                rel_dir.split('/').any(|c| c == x)
+            }
+        })
+    }
+
+    fn keeps_duration(&self, dur_s: u32) -> bool {
+        (self.min_dur_s == 0 || dur_s >= self.min_dur_s) && (self.max_dur_s == 0 || dur_s <= self.max_dur_s)
+    }
+}
+
+/// One of several weighted top-up folders: "70% music, 20% sweepers, 10% promos".
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct TopUpSource {
+    pub(crate) dir: String,
+    /// Relative share of appended items.
+    weight: u32,
+    /// Tag for items from this folder (e.g. "SWP"); empty means "MUS".
+    #[serde(default)]
+    tag: String,
+}
+
+/// "Overnight uses /data/chill, daytime uses /data/hits."
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct TopUpDaypart {
+    #[serde(default)]
+    name: String,
+    /// "mon", "tue", ... "sun"; empty means every day.
+    #[serde(default)]
+    days: Vec<String>,
+    /// Local "HH:MM". `end` is exclusive; an `end` at or before `start` wraps
+    /// past midnight (the days list names the day the daypart starts).
+    start: String,
+    end: String,
+    pub(crate) dir: String,
+}
+
+impl TopUpDaypart {
+    fn matches(&self, weekday: usize, minute: u32) -> bool {
+        in_daypart(&self.days, &self.start, &self.end, weekday, minute)
+    }
+}
+
+/// Whether local `weekday` (0 = Sunday) / `minute` of the day falls in a
+/// daypart: `days` names ("mon", ...; empty = every day) and a local "HH:MM"
+/// range, `end` exclusive. A range ending at or before its start wraps past
+/// midnight, and `days` then names the day it starts.
+pub(crate) fn in_daypart(days: &[String], start: &str, end: &str, weekday: usize, minute: u32) -> bool {
+    let (Some(start), Some(end)) = (parse_hhmm(start), parse_hhmm(end)) else {
+        return false;
+    };
+    let on = |wd: usize| days.is_empty() || days.iter().any(|d| d.eq_ignore_ascii_case(clocks::DAYS[wd]));
+    if start < end {
+        on(weekday) && start <= minute && minute < end
+    } else {
+        (on(weekday) && minute >= start) || (on((weekday + 6) % 7) && minute < end)
+    }
+}
+
+impl TopUpConfig {
+    /// The config to use right now: `dir` replaced by the active daypart's
+    /// directory, plus that daypart's name (None when the base `dir` applies).
+    pub(crate) fn for_time(&self, unix_ms: u64) -> (TopUpConfig, Option<String>) {
+        let mut cfg = self.clone();
+        let Some((weekday, minute)) = local_weekday_minute(unix_ms) else { return (cfg, None) };
+        match self.dayparts.iter().enumerate().find(|(_, d)| d.matches(weekday, minute)) {
+            Some((i, d)) => {
+                cfg.dir = d.dir.clone();
+                cfg.sources.clear();
+                let name = if d.name.is_empty() { format!("daypart {}", i + 1) } else { d.name.clone() };
+                (cfg, Some(name))
+            }
+            None => (cfg, None),
+        }
+    }
+}
+
+/// Runtime visibility for top-up.
+///
+/// Top-up is an automation feature and when it fails (missing directory,
+/// permission issues, unsupported formats, empty folder, etc.) it can leave the
+/// playout queue empty with no obvious UI indication.
+///
+/// We keep small, operator-friendly telemetry so we can surface it via API and
+/// (later) the UI.
+#[derive(Clone, Serialize, Default)]
+pub(crate) struct TopUpStats {
+    /// Unix millis of the last scan attempt.
+    pub(crate) last_scan_ms: Option<u64>,
+    /// The directory that was scanned (may be a fallback).
+    pub(crate) last_dir: Option<String>,
+    /// How many candidate audio files were discovered.
+    pub(crate) last_files_found: Option<u32>,
+    /// How many items were appended.
+    pub(crate) last_appended: Option<u32>,
+    /// Human-friendly last error string.
+    pub(crate) last_error: Option<String>,
+
+    /// If the last periodic tick *did not* scan because the queue was already
+    /// at/above `min_queue`, we record a short reason here.
+    ///
+    /// Why this exists:
+    /// We continuously publish top-up telemetry so operators can see whether
+    /// the automation is healthy. If we overwrite `last_files_found` with 0
+    /// every time we *skip* scanning (because the queue is already full), it
+    /// looks like top-up is broken even when it previously appended items.
+    pub(crate) last_skip_reason: Option<String>,
+
+    /// Name of the daypart currently selecting the top-up directory, or null
+    /// when the base `dir` applies.
+    pub(crate) active_rule: Option<String>,
+
+    /// Per-folder results of the last scan (one entry for a single `dir`).
+    sources: Vec<TopUpSourceStats>,
+}
+
+#[derive(Clone, Debug, Serialize, Default)]
+pub(crate) struct TopUpSourceStats {
+    dir: String,
+    weight: u32,
+    files_found: u32,
+    /// Skipped by the extension/subfolder filters (not in `files_found`).
+    excluded: u32,
+    /// Appended by the last scan.
+    last_appended: u32,
+    /// Appended since the engine started.
+    total_appended: u64,
+    last_error: Option<String>,
+}
+
+impl TopUpStats {
+    /// Record a scan's per-folder results, keeping running totals per folder.
+    pub(crate) fn record_sources(&mut self, scanned: Vec<TopUpSourceStats>) {
+        let mut merged = Vec::with_capacity(scanned.len());
+        for mut s in scanned {
+            if let Some(prev) = self.sources.iter().find(|p| p.dir == s.dir) {
+                s.total_appended += prev.total_appended;
+            }
+            merged.push(s);
+        }
+        self.sources = merged;
+    }
+}
+
+
+// --- Playout top-up (random folder filler) -------------------------------
+
+
+#[derive(Serialize)]
+pub(crate) struct TopUpGetResponse {
+    config: TopUpConfig,
+    stats: TopUpStats,
+}
+
+pub(crate) async fn api_topup_get(State(state): State<AppState>) -> Json<TopUpGetResponse> {
+    let cfg = state.topup.lock().await.clone();
+    let stats = state.topup_stats.lock().await.clone();
+    Json(TopUpGetResponse { config: cfg, stats })
+}
+
+pub(crate) async fn api_topup_set_config(
+    State(state): State<AppState>,
+    Json(mut cfg): Json<TopUpConfig>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    // Basic validation / normalization
+    cfg.dir = cfg.dir.trim().to_string();
+    if cfg.min_queue == 0 || cfg.min_queue > 100 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if cfg.batch == 0 || cfg.batch > 100 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    for d in cfg.dayparts.iter_mut() {
+        d.dir = d.dir.trim().to_string();
+        let known_days = d.days.iter().all(|day| clocks::DAYS.contains(&day.to_lowercase().as_str()));
+        if d.dir.is_empty() || !known_days || parse_hhmm(&d.start).is_none() || parse_hhmm(&d.end).is_none() {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    }
+    for s in cfg.sources.iter_mut() {
+        s.dir = s.dir.trim().to_string();
+        s.tag = s.tag.trim().to_string();
+        if s.dir.is_empty() || s.weight == 0 || s.weight > 1000 {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    }
+    let references = std::iter::once(&cfg.dir)
+        .chain(cfg.sources.iter().map(|s| &s.dir))
+        .chain(cfg.dayparts.iter().map(|d| &d.dir));
+    for r in references {
+        let name = r.strip_prefix("playlist:").or_else(|| r.strip_prefix("category:"));
+        if name.is_some_and(|n| n.trim().is_empty()) {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    }
+    let f = &mut cfg.filters;
+    if f.max_dur_s > 0 && f.min_dur_s > f.max_dur_s {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    f.extensions = f.extensions.iter().map(|e| e.trim().trim_start_matches('.').to_ascii_lowercase()).collect();
+    f.extensions.retain(|e| !e.is_empty());
+    f.exclude_dirs = f.exclude_dirs.iter().map(|d| d.trim().to_string()).filter(|d| !d.is_empty()).collect();
+
+    let cfg_clone = cfg.clone();
+    db::call(move |conn| -> anyhow::Result<()> {
+        db_save_topup_config(conn, &cfg_clone)?;
+        Ok(())
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let mut cur = state.topup.lock().await;
+    *cur = cfg;
+
+    Ok(Json(json!({"ok": true})))
+}
+
+/// `POST /api/v1/playout/topup/run`: top up now, whatever `min_queue` and
+/// `enabled` say ("Top Up Now" button). Selection runs on a snapshot of the
+/// queue; the picks are appended afterwards, so the playout lock is not held
+/// while folders are scanned.
+pub(crate) async fn api_topup_run(State(state): State<AppState>) -> Json<serde_json::Value> {
+    let (cfg, active_rule) = state.topup.lock().await.for_time(unix_ms_now());
+    let snapshot = state.playout.read().await.log.clone();
+    let mut attempt = TopUpAttempt::default();
+    let picked = topup_select(&snapshot, &cfg, &mut attempt).await;
+    attempt.appended = picked.len() as u32;
+
+    let rev = {
+        let mut p = state.playout.write().await;
+        if !picked.is_empty() {
+            p.log.extend(picked.iter().cloned());
+            normalize_queue_states(&mut p.log);
+            bump_queue_rev(&mut p);
+            persist_queue(p.log.clone()).await;
+        }
+        p.queue_rev
+    };
+    shufflebag::mark_drawn(std::mem::take(&mut attempt.drawn)).await;
+
+    {
+        let mut s = state.topup_stats.lock().await;
+        s.active_rule = active_rule;
+        s.last_scan_ms = Some(unix_ms_now());
+        s.last_dir = Some(cfg.dir.clone());
+        s.last_files_found = Some(attempt.files_found);
+        s.last_appended = Some(attempt.appended);
+        s.last_error = attempt.error.clone();
+        s.last_skip_reason = None;
+        topuplog::record("manual", &cfg.dir, s.active_rule.clone(), &attempt);
+        s.record_sources(attempt.sources);
+    }
+    Json(json!({
+        "ok": attempt.appended > 0,
+        "appended": attempt.appended,
+        "items": picked,
+        "error": attempt.error,
+        "rev": rev,
+    }))
+}
+
+/// `GET /api/v1/playout/topup/preview`: what a run would append right now,
+/// with estimated start times, without changing the queue. Selection is
+/// random, so this is a representative sample rather than the exact items a
+/// later run will pick.
+pub(crate) async fn api_topup_preview(State(state): State<AppState>) -> Json<serde_json::Value> {
+    let (cfg, active_rule) = state.topup.lock().await.for_time(unix_ms_now());
+    let (mut log, now) = {
+        let p = state.playout.read().await;
+        (p.log.clone(), p.now.clone())
+    };
+    let mut attempt = TopUpAttempt::default();
+    let picked = topup_select(&log, &cfg, &mut attempt).await;
+    let n = picked.len();
+    log.extend(picked);
+    estimate_start_times(&mut log, &now, unix_ms_now());
+    let items = log.split_off(log.len() - n);
+    Json(json!({
+        "ok": true,
+        "active_rule": active_rule,
+        "dir": cfg.dir,
+        "files_found": attempt.files_found,
+        "sources": attempt.sources,
+        "items": items,
+        "error": attempt.error,
+    }))
+}
+
+/// Top-up sources that are not folders: `playlist:/srv/lists/overnight.m3u`
+/// or `category:GOLD` (carts in that category plus library tracks with that
+/// tag), for stations that schedule from the library rather than flat folders.
+pub(crate) fn topup_is_reference(dir: &str) -> bool {
+    dir.starts_with("playlist:") || dir.starts_with("category:")
+}
+
+/// Candidate files for a top-up source (a folder or a reference). Blocking.
+pub(crate) fn topup_scan(source: &str) -> anyhow::Result<Vec<String>> {
+    if let Some(list) = source.strip_prefix("playlist:") {
+        return import::playlist_paths(list.trim());
+    }
+    if let Some(cat) = source.strip_prefix("category:") {
+        let cat = cat.trim();
+        let cat = cat.to_string();
+        let rows = db::call_blocking(move |conn| -> anyhow::Result<Vec<(String, bool)>> {
+            db_init(conn)?;
+            let mut stmt = conn.prepare(
+                "SELECT cart, 1 FROM carts WHERE category = ?1
+                 UNION ALL
+                 SELECT path, 0 FROM library_tracks WHERE tag = ?1",
+            )?;
+            let rows = stmt
+                .query_map(params![cat], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)? != 0)))?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            Ok(rows)
+        })??;
+        let mut out: Vec<String> = rows
+            .into_iter()
+            .filter_map(|(r, is_cart)| {
+                if is_cart {
+                    resolve_cart_to_path(&r)
+                } else {
+                    paths::from_cart(&r).is_file().then_some(r)
+                }
+            })
+            .collect();
+        out.sort();
+        out.dedup();
+        return Ok(out);
+    }
+    scan_audio_files_recursive(source)
+}
+
+pub(crate) fn scan_audio_files_recursive(dir: &str) -> anyhow::Result<Vec<String>> {
+    use std::path::Path;
+
+    // Decoder-supported file extensions.
+    // Keep this list conservative — ffmpeg can decode more, but this is enough
+    // for common station libraries.
+    let allowed = ["flac", "wav", "mp3", "m4a", "aac", "ogg", "opus"];
+
+    let root = Path::new(dir);
+    if !root.exists() {
+        anyhow::bail!("top-up dir does not exist: {dir}");
+    }
+
+    // IMPORTANT: do not silently ignore filesystem errors.
+    // Earlier versions treated a failing `read_dir()` as "empty", which made
+    // debugging impossible (e.g., permission denied / stale NAS mount).
+    let mut out = Vec::new();
+    let mut stack = vec![root.to_path_buf()];
+    while let Some(path) = stack.pop() {
+        let rd = std::fs::read_dir(&path)
+            .map_err(|e| anyhow::anyhow!("failed to read_dir({}): {e}", path.display()))?;
+        for ent in rd {
+            let ent = ent.map_err(|e| anyhow::anyhow!("failed to read_dir entry: {e}"))?;
+            let p = ent.path();
+            if p.is_dir() {
+                stack.push(p);
+                continue;
+            }
+            if !p.is_file() {
+                continue;
+            }
+
+            let Some(ext) = p.extension().and_then(|e| e.to_str()) else {
+                continue;
+            };
+            let ext_lc = ext.to_ascii_lowercase();
+            if !allowed.iter().any(|a| *a == ext_lc.as_str()) {
+                continue;
+            }
+
+            // Paths on Linux are bytes; they are *usually* UTF-8, but not always.
+            // `to_cart` keeps the others playable (see paths.rs).
+            out.push(paths::to_cart(&p));
+        }
+    }
+
+    Ok(out)
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TopUpAttempt {
+    /// True if we actually walked the filesystem to discover files.
+    ///
+    /// A periodic tick can also short-circuit early if the queue is already
+    /// at/above `min_queue`. In that case we do *not* want to overwrite the
+    /// last meaningful scan stats with zeros.
+    pub(crate) scanned: bool,
+    pub(crate) appended: u32,
+    pub(crate) files_found: u32,
+    pub(crate) error: Option<String>,
+
+    /// If we didn't scan, record why.
+    pub(crate) skip_reason: Option<String>,
+
+    /// Per-folder results (when scanned).
+    pub(crate) sources: Vec<TopUpSourceStats>,
+
+    /// (source, path) of every pick, for the shuffle bag once appended.
+    drawn: Vec<(String, String)>,
+}
+
+/// Try to top-up a queue using the provided config.
+///
+/// This function never panics; it reports scan/probe errors via `error` so the
+/// caller can decide whether to fallback to another directory.
+pub(crate) async fn topup_try(log: &mut Vec<LogItem>, cfg: &TopUpConfig) -> TopUpAttempt {
+    let mut out = TopUpAttempt::default();
+
+    if !cfg.enabled {
+        return out;
+    }
+    if cfg.dir.trim().is_empty() && cfg.sources.is_empty() {
+        out.error = Some("top-up dir is empty".into());
+        return out;
+    }
+    // Only count *actually playable* items toward `min_queue`.
+    //
+    // Why this matters:
+    // - Some UI modes keep played items visible, or older installs may still
+    //   have placeholder/demo rows in SQLite.
+    // - Those rows can make the queue look "full" even when there is nothing
+    //   we can actually play, which would prevent Top-Up from refilling.
+    let active_len = topup_active_len(log);
+    if active_len >= cfg.min_queue {
+        out.skip_reason = Some(format!(
+            "skipped: active queue {} >= min_queue {}",
+            active_len, cfg.min_queue
+        ));
+        return out;
+    }
+
+    // From here onward we intend to actually scan.
+    let picked = topup_select(log, cfg, &mut out).await;
+
+    // Top-up only ever appends to the tail of the queue, so it can never carry
+    // a filler track ahead of a locked item (legal ID, sponsor spot): anything
+    // appended lands after the last lock, never in front of one.
+    let appended = picked.len() as u32;
+    log.extend(picked);
+    // Called under the playout lock; record the draws without waiting.
+    tokio::spawn(shufflebag::mark_drawn(std::mem::take(&mut out.drawn)));
+
+    normalize_queue_states(log);
+    out.appended = appended;
+    out
+}
+
+/// Items that count toward `min_queue`. We treat an item as "active" only if:
+/// - it is not explicitly marked played, AND
+/// - it has a non-empty `cart` path, AND
+/// - that path exists on disk.
+pub(crate) fn topup_active_len(log: &[LogItem]) -> u16 {
+    log.iter()
+        .filter(|it| {
+            it.state != "played"
+                && !it.cart.trim().is_empty()
+                && paths::from_cart(&it.cart).exists()
+        })
+        .count() as u16
+}
+
+/// Scan the configured folder(s) and choose up to `batch` items to append to
+/// `log`, without touching it. Used by `topup_try` and by the manual run and
+/// preview endpoints (which ignore `min_queue`).
+async fn topup_select(log: &[LogItem], cfg: &TopUpConfig, out: &mut TopUpAttempt) -> Vec<LogItem> {
+    // Separation rules (song/artist/category) are judged against play history
+    // plus everything already queued. If the database is unavailable we still
+    // top up: rules are a preference, silence is a failure.
+    let sep = match rotation::Separation::load_now().await {
+        Ok(s) => Some(s),
+        Err(e) => {
+            tracing::warn!("top-up: rotation rules unavailable: {e}");
+            None
+        }
+    };
+    topup_select_with(log, cfg, sep, out).await
+}
+
+/// `topup_select` with the history to judge separation against supplied by
+/// the caller (the dry run in simulate.rs passes its virtual one). Queued
+/// items in `log` are added to it here.
+pub(crate) async fn topup_select_with(
+    log: &[LogItem],
+    cfg: &TopUpConfig,
+    mut sep: Option<rotation::Separation>,
+    out: &mut TopUpAttempt,
+) -> Vec<LogItem> {
+    out.scanned = true;
+
+    let sources: Vec<TopUpSource> = if cfg.sources.is_empty() {
+        vec![TopUpSource { dir: cfg.dir.clone(), weight: 1, tag: String::new() }]
+    } else {
+        cfg.sources.clone()
+    };
+    let mut pools: Vec<TopUpPool> = Vec::with_capacity(sources.len());
+    for src in sources {
+        let dir = src.dir.clone();
+        let files_res = tokio::task::spawn_blocking(move || topup_scan(&dir)).await;
+        let mut stats = TopUpSourceStats { dir: src.dir.clone(), weight: src.weight, ..Default::default() };
+        let files = match files_res {
+            Ok(Ok(v)) => v,
+            Ok(Err(e)) => {
+                stats.last_error = Some(format!("scan failed: {e}"));
+                Vec::new()
+            }
+            Err(e) => {
+                stats.last_error = Some(format!("scan join failed: {e}"));
+                Vec::new()
+            }
+        };
+        let scanned = files.len();
+        let files: Vec<String> = files.into_iter().filter(|f| cfg.filters.keeps_path(&src.dir, f)).collect();
+        stats.excluded = (scanned - files.len()) as u32;
+        if files.is_empty() && stats.last_error.is_none() {
+            stats.last_error = Some("no eligible audio files found".into());
+        }
+        stats.files_found = files.len() as u32;
+        // Shuffle bag: files not drawn yet this cycle come first, in random
+        // order; already drawn ones only if rules or filters exhaust the rest.
+        let drawn = shufflebag::drawn(&src.dir, &files).await;
+        let mut order: Vec<usize> = (0..files.len()).collect();
+        fastrand::shuffle(&mut order);
+        order.sort_by_key(|&i| drawn.contains(&files[i]));
+        let tag = match (src.tag.is_empty(), src.dir.strip_prefix("category:")) {
+            (false, _) => src.tag,
+            (true, Some(cat)) => cat.trim().to_string(),
+            (true, None) => "MUS".to_string(),
+        };
+        pools.push(TopUpPool {
+            source: src.dir.clone(),
+            weight: src.weight,
+            tag,
+            files,
+            drawn,
+            order,
+            cursor: 0,
+            examined: 0,
+            rejected: HashSet::new(),
+            done: false,
+            stats,
+        });
+    }
+
+    out.files_found = pools.iter().map(|p| p.files.len() as u32).sum();
+    if out.files_found == 0 {
+        // Treat this as an operational error so the caller can fall back to a
+        // known-good directory (e.g., /opt/studiocommand/shared/data) and so
+        // operators can see what happened via /api/v1/playout/topup.
+        out.error = pools.iter().find_map(|p| p.stats.last_error.clone());
+        out.sources = pools.into_iter().map(|p| p.stats).collect();
+        return Vec::new();
+    }
+    // One failing folder among several is worth surfacing even if the rest play.
+    out.error = pools.iter().find_map(|p| p.stats.last_error.as_ref().map(|e| format!("{}: {e}", p.stats.dir)));
+
+    if let Some(sep) = sep.as_mut() {
+        for it in log.iter().filter(|it| it.state != "played") {
+            let path = resolve_cart_to_path(&it.cart).unwrap_or_else(|| it.cart.clone());
+            sep.note_queued(&path, &it.artist, &it.tag);
+        }
+    }
+
+    // Each appended item first draws a folder by weight, then a file from it.
+    let batch = cfg.batch as usize;
+    let mut picked: Vec<LogItem> = Vec::new();
+    while picked.len() < batch {
+        let total: u32 = pools.iter().filter(|p| !p.done).map(|p| p.weight).sum();
+        if total == 0 {
+            break;
+        }
+        let mut draw = fastrand::u32(0..total);
+        let Some(pool) = pools.iter_mut().filter(|p| !p.done).find(|p| {
+            if draw < p.weight {
+                return true;
+            }
+            draw -= p.weight;
+            false
+        }) else {
+            break;
+        };
+        match topup_pick(pool, sep.as_mut(), &picked, batch, &cfg.filters, out).await {
+            Some(item) => {
+                pool.stats.last_appended += 1;
+                pool.stats.total_appended += 1;
+                out.drawn.push((pool.source.clone(), item.cart.clone()));
+                picked.push(item);
+            }
+            // Everything in this folder is already picked or filtered out.
+            None => pool.done = true,
+        }
+    }
+    out.sources = pools.into_iter().map(|p| p.stats).collect();
+    picked
+}
+
+/// One top-up folder during a single attempt.
+struct TopUpPool {
+    /// The folder or reference, also the shuffle-bag key.
+    source: String,
+    weight: u32,
+    tag: String,
+    files: Vec<String>,
+    /// Already drawn from the shuffle bag this cycle.
+    drawn: HashSet<String>,
+    /// Indices into `files`, undrawn first, each part shuffled; `cursor` is
+    /// the next one to try.
+    order: Vec<usize>,
+    cursor: usize,
+    /// Files probed so far against the rules.
+    examined: usize,
+    /// Files whose duration fails the filters (probed once per attempt).
+    rejected: HashSet<String>,
+    /// No file left to pick.
+    done: bool,
+    stats: TopUpSourceStats,
+}
+
+/// Pick the next file from `pool`: the next one in shuffle-bag order that
+/// satisfies the rotation rules, or, when the folder is too small or the rules
+/// too tight, an undrawn file that aired longest ago (never-aired first).
+///
+/// Probing is cached but can still mean an ffprobe per new file, so only a
+/// bounded number of candidates are examined before falling back.
+async fn topup_pick(
+    pool: &mut TopUpPool,
+    mut sep: Option<&mut rotation::Separation>,
+    picked: &[LogItem],
+    batch: usize,
+    filters: &TopUpFilters,
+    out: &mut TopUpAttempt,
+) -> Option<LogItem> {
+    let already = |path: &str| picked.iter().any(|it| it.cart == path);
+    while pool.cursor < pool.order.len() && pool.examined < batch * 20 {
+        let path = pool.files[pool.order[pool.cursor]].clone();
+        pool.cursor += 1;
+        if already(&path) || sep.as_ref().map(|s| !s.song_allowed(&path)).unwrap_or(false) {
+            continue;
+        }
+        pool.examined += 1;
+        let mut item = topup_item(&path, out).await;
+        if !filters.keeps_duration(parse_dur_to_sec(&item.dur)) {
+            pool.rejected.insert(path);
+            continue;
+        }
+        item.tag = pool.tag.clone();
+        if let Some(sep) = sep.as_mut() {
+            if !sep.allows(&path, &item.artist, &item.tag) {
+                continue;
+            }
+            sep.note_picked(&path, &item.artist, &item.tag);
+        }
+        return Some(item);
+    }
+
+    let mut fallback: Vec<&String> = pool
+        .order
+        .iter()
+        .map(|&i| &pool.files[i])
+        .filter(|p| !already(p) && !pool.rejected.contains(*p))
+        .collect();
+    fallback.sort_by_key(|p| (pool.drawn.contains(*p), sep.as_ref().and_then(|s| s.last_aired(p)).unwrap_or(0)));
+    let fallback: Vec<String> = fallback.into_iter().take(batch * 20).cloned().collect();
+    for path in fallback {
+        let mut item = topup_item(&path, out).await;
+        if !filters.keeps_duration(parse_dur_to_sec(&item.dur)) {
+            pool.rejected.insert(path);
+            continue;
+        }
+        item.tag = pool.tag.clone();
+        if let Some(sep) = sep.as_mut() {
+            sep.note_picked(&path, &item.artist, &item.tag);
+        }
+        return Some(item);
+    }
+    None
+}
+
+/// Build a queue item for a top-up file.
+async fn topup_item(path: &str, out: &mut TopUpAttempt) -> LogItem {
+    // One (cached) ffprobe gives us both the duration and the embedded
+    // tags (ID3, Vorbis comments, MP4 atoms), so the queue, now-playing
+    // and Icecast metadata show the real artist/title.
+    let (dur_ms, tag_title, tag_artist) = match analysis::probe_cached(path).await {
+        Ok(probe) => (probe.length_ms(), probe.title, probe.artist),
+        Err(e) => {
+            tracing::warn!("top-up: probe failed: {e}");
+            (0, None, None)
+        }
+    };
+    if dur_ms == 0 {
+        // Keep going, but record that probe was unhappy.
+        out.error.get_or_insert_with(|| "ffprobe duration failed for one or more files".into());
+    }
+
+    // Untagged files: fall back to the common "Artist - Title" file naming.
+    let (name_artist, name_title) = artist_title_from_path(path);
+    let title = tag_title.unwrap_or(name_title);
+    let artist = tag_artist.or(name_artist).unwrap_or_default();
+
+    LogItem {
+        id: Uuid::new_v4(),
+        tag: "MUS".into(),
+        time: "".into(),
+        title,
+        artist,
+        state: "queued".into(),
+        dur: fmt_dur_ms(dur_ms),
+        dur_ms,
+        cart: path.to_string(), // absolute path
+        locked: false,
+        start_ms: None,
+        transition: None,
+        hard_start_ms: None,
+    }
+}
@@ -0,0 +1,154 @@
+// --- Top-up history ----------------------------------------------------------------
+//
+// `TopUpStats` only shows the last attempt, which is useless at 9 am for "why
+// was there dead air at 03:12?". Every scan is recorded in `topup_history`,
+// plus the first skip after a scan ("queue already full"), so the log shows
+// when top-up ran, from where, and what went wrong, without a row every two
+// seconds while the queue is healthy.
+//
+// The table is rolling: only the newest `KEEP_ROWS` entries are kept.
+
+use axum::{extract::Query, http::StatusCode, Json};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{eventlog, unix_ms_now, TopUpAttempt};
+
+/// Roughly two weeks of scans at a few dozen per hour.
+const KEEP_ROWS: i64 = 10_000;
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS topup_history (
+            id           INTEGER PRIMARY KEY AUTOINCREMENT,
+            at_ms        INTEGER NOT NULL,
+            trigger      TEXT NOT NULL,
+            dir          TEXT NOT NULL,
+            active_rule  TEXT,
+            files_found  INTEGER NOT NULL,
+            appended     INTEGER NOT NULL,
+            error        TEXT,
+            skip_reason  TEXT
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_topup_history_at ON topup_history(at_ms);
+        "#,
+    )
+}
+
+#[derive(Serialize)]
+pub(crate) struct TopUpHistoryEntry {
+    pub(crate) at_ms: u64,
+    /// "tick" (periodic check), "track_end" (after an advance) or "manual".
+    pub(crate) trigger: String,
+    pub(crate) dir: String,
+    pub(crate) active_rule: Option<String>,
+    files_found: u32,
+    pub(crate) appended: u32,
+    pub(crate) error: Option<String>,
+    skip_reason: Option<String>,
+}
+
+/// Record an attempt in the background; top-up never waits on this.
+pub(crate) fn record(trigger: &str, dir: &str, active_rule: Option<String>, attempt: &TopUpAttempt) {
+    if attempt.appended > 0 {
+        eventlog::record(
+            "topup",
+            format!("top-up appended {} from {dir}", attempt.appended),
+            json!({"appended": attempt.appended, "dir": dir, "trigger": trigger, "active_rule": active_rule}),
+        );
+    }
+    let e = TopUpHistoryEntry {
+        at_ms: unix_ms_now(),
+        trigger: trigger.to_string(),
+        dir: dir.to_string(),
+        active_rule,
+        files_found: attempt.files_found,
+        appended: attempt.appended,
+        error: attempt.error.clone(),
+        skip_reason: attempt.skip_reason.clone(),
+    };
+    tokio::spawn(async move {
+        let res = crate::db::call(move |conn| -> anyhow::Result<()> {
+            crate::db_init(conn)?;
+            conn.execute(
+                "INSERT INTO topup_history (at_ms, trigger, dir, active_rule, files_found, appended, error, skip_reason)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    e.at_ms as i64,
+                    e.trigger,
+                    e.dir,
+                    e.active_rule,
+                    e.files_found as i64,
+                    e.appended as i64,
+                    e.error,
+                    e.skip_reason
+                ],
+            )?;
+            let id = conn.last_insert_rowid();
+            conn.execute("DELETE FROM topup_history WHERE id <= ?1", params![id - KEEP_ROWS])?;
+            Ok(())
+        })
+        .await;
+        if let Ok(Err(e)) = res {
+            tracing::warn!("top-up history: failed to record: {e}");
+        }
+    });
+}
+
+fn entry_from_row(row: &rusqlite::Row) -> rusqlite::Result<TopUpHistoryEntry> {
+    Ok(TopUpHistoryEntry {
+        at_ms: row.get::<_, i64>(0)? as u64,
+        trigger: row.get(1)?,
+        dir: row.get(2)?,
+        active_rule: row.get(3)?,
+        files_found: row.get::<_, i64>(4)? as u32,
+        appended: row.get::<_, i64>(5)? as u32,
+        error: row.get(6)?,
+        skip_reason: row.get(7)?,
+    })
+}
+
+/// Attempts in `[from_ms, to_ms)` that appended something or failed, oldest first.
+pub(crate) fn db_notable_between(conn: &Connection, from_ms: u64, to_ms: u64) -> anyhow::Result<Vec<TopUpHistoryEntry>> {
+    crate::db_init(conn)?;
+    let mut stmt = conn.prepare(
+        "SELECT at_ms, trigger, dir, active_rule, files_found, appended, error, skip_reason
+         FROM topup_history
+         WHERE at_ms >= ?1 AND at_ms < ?2 AND (appended > 0 OR error IS NOT NULL)
+         ORDER BY id",
+    )?;
+    let rows = stmt.query_map(params![from_ms as i64, to_ms as i64], entry_from_row)?;
+    Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+}
+
+#[derive(Deserialize)]
+pub(crate) struct HistoryQuery {
+    limit: Option<u32>,
+    since_ms: Option<u64>,
+    /// Only attempts that reported an error.
+    #[serde(default)]
+    errors: bool,
+}
+
+/// `GET /api/v1/playout/topup/history?limit=&since_ms=&errors=true`, newest first.
+pub(crate) async fn api_topup_history(Query(q): Query<HistoryQuery>) -> Result<Json<Vec<TopUpHistoryEntry>>, StatusCode> {
+    let limit = q.limit.unwrap_or(200).clamp(1, 5000);
+    crate::db::call(move |conn| -> anyhow::Result<Vec<TopUpHistoryEntry>> {
+        crate::db_init(conn)?;
+        let mut stmt = conn.prepare(
+            "SELECT at_ms, trigger, dir, active_rule, files_found, appended, error, skip_reason
+             FROM topup_history
+             WHERE at_ms >= ?1 AND (?2 = 0 OR error IS NOT NULL)
+             ORDER BY id DESC LIMIT ?3",
+        )?;
+        let rows = stmt.query_map(params![q.since_ms.unwrap_or(0) as i64, q.errors, limit], entry_from_row)?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map(Json)
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
@@ -0,0 +1,212 @@
+// --- Item transitions ------------------------------------------------------------------
+//
+// How one queue item hands over to the next. The station sets defaults
+// (`/api/v1/transitions/config`); a queue item may override any of them
+// (`transition` in `PATCH /api/v1/queue/items/{id}`), e.g. a cold ending
+// that must not be crossfaded, or a news intro that should overlap the song
+// before it by a few seconds.
+//
+// - `segue`: how this item goes into the next one. `cut` starts the next item
+//   when this one ends; `overlap` starts it `overlap_ms` before the end, with
+//   this one playing out untouched; `crossfade` does the same while fading
+//   this one out and the next one in over `overlap_ms`.
+// - `fade_in_ms` / `fade_out_ms`: this item's own fades at its start and end.
+//
+// The writer renders overlaps the way it renders voice-track links
+// (voicetrack.rs): the next item starts on the overlay bus and its decoder
+// takes over where the overlay got to. A voice track's own transition wins
+// over these. Overlaps need the item's length; an item without one cuts.
+//
+// The defaults are mirrored in memory, for the writer.
+
+use std::sync::RwLock;
+
+use axum::{http::StatusCode, Json};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{db_add_column_if_missing, voicetrack};
+
+/// Longest overlap or fade.
+const MAX_MS: u32 = 30_000;
+
+/// How an item goes into the next one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Segue {
+    Cut,
+    Crossfade,
+    Overlap,
+}
+
+/// A queue item's transition settings. Unset fields take the station default.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transition {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub segue: Option<Segue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub overlap_ms: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fade_in_ms: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fade_out_ms: Option<u32>,
+}
+
+impl Transition {
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == Transition::default()
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        let too_long = [self.overlap_ms, self.fade_in_ms, self.fade_out_ms].iter().flatten().any(|ms| *ms > MAX_MS);
+        if too_long {
+            return Err(format!("overlaps and fades are at most {MAX_MS} ms"));
+        }
+        Ok(())
+    }
+}
+
+/// The station defaults, and a transition with every field resolved.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct TransitionConfig {
+    #[serde(default = "default_segue")]
+    pub(crate) segue: Segue,
+    #[serde(default = "default_overlap_ms")]
+    pub(crate) overlap_ms: u32,
+    #[serde(default)]
+    pub(crate) fade_in_ms: u32,
+    #[serde(default)]
+    pub(crate) fade_out_ms: u32,
+}
+
+fn default_segue() -> Segue {
+    DEFAULTS.segue
+}
+
+fn default_overlap_ms() -> u32 {
+    DEFAULTS.overlap_ms
+}
+
+const DEFAULTS: TransitionConfig =
+    TransitionConfig { segue: Segue::Cut, overlap_ms: 3000, fade_in_ms: 0, fade_out_ms: 0 };
+
+static CURRENT: RwLock<TransitionConfig> = RwLock::new(DEFAULTS);
+
+fn defaults() -> TransitionConfig {
+    *CURRENT.read().unwrap_or_else(|e| e.into_inner())
+}
+
+/// `item`'s transition with the station defaults filled in. Cheap.
+pub(crate) fn resolve(item: Option<&Transition>) -> TransitionConfig {
+    let d = defaults();
+    let Some(t) = item else { return d };
+    TransitionConfig {
+        segue: t.segue.unwrap_or(d.segue),
+        overlap_ms: t.overlap_ms.unwrap_or(d.overlap_ms),
+        fade_in_ms: t.fade_in_ms.unwrap_or(d.fade_in_ms),
+        fade_out_ms: t.fade_out_ms.unwrap_or(d.fade_out_ms),
+    }
+}
+
+impl TransitionConfig {
+    /// How long the next item plays over the end of this one.
+    pub(crate) fn overlap_into_next(&self) -> u32 {
+        match self.segue {
+            Segue::Cut => 0,
+            Segue::Crossfade | Segue::Overlap => self.overlap_ms,
+        }
+    }
+}
+
+/// The handover from an item `dur_ms` long with transition `current` into one
+/// with transition `next`, unless it is a cut.
+pub(crate) fn handover(
+    current: &TransitionConfig,
+    dur_ms: u64,
+    next: &TransitionConfig,
+) -> Option<voicetrack::Handover> {
+    let overlap_ms = (current.overlap_into_next() as u64).min(dur_ms);
+    if overlap_ms == 0 {
+        return None;
+    }
+    let at_ms = dur_ms - overlap_ms;
+    Some(match current.segue {
+        Segue::Crossfade => voicetrack::Handover {
+            at_ms,
+            out_db: f32::NEG_INFINITY,
+            in_db: 0.0,
+            ramp_ms: overlap_ms,
+            fade_in_ms: overlap_ms,
+        },
+        Segue::Cut | Segue::Overlap => voicetrack::Handover {
+            at_ms,
+            out_db: 0.0,
+            in_db: 0.0,
+            ramp_ms: 0,
+            fade_in_ms: next.fade_in_ms as u64,
+        },
+    })
+}
+
+/// Migration 9: the defaults table, and the per-item transition on both queues.
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS transition_config (
+            id      INTEGER PRIMARY KEY CHECK (id = 1),
+            config  TEXT NOT NULL
+        );
+        "#,
+    )?;
+    db_add_column_if_missing(conn, "queue_items", "transition", "TEXT")?;
+    db_add_column_if_missing(conn, "aux_queue_items", "transition", "TEXT")
+}
+
+fn db_load_config(conn: &Connection) -> anyhow::Result<TransitionConfig> {
+    crate::db_init(conn)?;
+    let raw: Option<String> =
+        conn.query_row("SELECT config FROM transition_config WHERE id = 1", [], |row| row.get(0)).optional()?;
+    Ok(match raw {
+        Some(r) => serde_json::from_str(&r)?,
+        None => DEFAULTS,
+    })
+}
+
+/// Load the defaults at startup.
+pub(crate) async fn load() {
+    match crate::db::call(|conn| db_load_config(conn)).await {
+        Ok(Ok(cfg)) => *CURRENT.write().unwrap_or_else(|e| e.into_inner()) = cfg,
+        Ok(Err(e)) => tracing::warn!("transitions: failed to load defaults: {e}"),
+        Err(e) => tracing::warn!("transitions: load task failed: {e}"),
+    }
+}
+
+// --- HTTP API --------------------------------------------------------------------------
+
+pub(crate) async fn api_transitions_config_get() -> Result<Json<TransitionConfig>, StatusCode> {
+    Ok(Json(defaults()))
+}
+
+pub(crate) async fn api_transitions_config_set(
+    Json(cfg): Json<TransitionConfig>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    if cfg.overlap_ms > MAX_MS || cfg.fade_in_ms > MAX_MS || cfg.fade_out_ms > MAX_MS {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let raw = serde_json::to_string(&cfg).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    crate::db::call(move |conn| -> anyhow::Result<()> {
+        crate::db_init(conn)?;
+        conn.execute(
+            "INSERT INTO transition_config (id, config) VALUES (1, ?1)
+             ON CONFLICT(id) DO UPDATE SET config=excluded.config",
+            params![raw],
+        )?;
+        Ok(())
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    *CURRENT.write().unwrap_or_else(|e| e.into_inner()) = cfg;
+    Ok(Json(json!({"ok": true, "config": cfg})))
+}
@@ -0,0 +1,597 @@
+// --- Self-update ---------------------------------------------------------------------------
+//
+// Installs follow the layout of `packaging/install.sh`:
+//   /opt/studiocommand/releases/<version>/   one unpacked release tarball each
+//   /opt/studiocommand/current -> releases/<version>   what systemd starts
+//   /opt/studiocommand/shared/updates/       downloads and `state.json`
+// (the root is `STUDIOCOMMAND_ROOT`). An update is the same thing the
+// installer does, done by the engine itself:
+//
+// 1. `POST /admin/api/v1/update/check` reads the release feed
+//    (`STUDIOCOMMAND_UPDATE_FEED`, GitHub's "latest release" by default) and
+//    looks for `studiocommand-linux-<arch>.tar.gz` and `sha256sums.txt`.
+// 2. `POST /admin/api/v1/update/apply` downloads the tarball, checks its
+//    SHA-256 against `sha256sums.txt` and checks `sha256sums.txt.minisig`
+//    against the project's minisign key (`STUDIOCOMMAND_RELEASE_PUBKEY` at
+//    build time, which the release workflow takes from
+//    `packaging/minisign.pub`; `STUDIOCOMMAND_UPDATE_PUBKEY` at run time
+//    replaces it, e.g. for a fork). The checksum
+//    file comes from the same feed as the tarball, so without the signature
+//    a tampered feed could ship anything with matching checksums: an
+//    unsigned release is refused unless `STUDIOCOMMAND_UPDATE_ALLOW_UNSIGNED=1`.
+//    The tarball is unpacked next to the other releases ("staged"), the
+//    database is snapshotted (`-pre-update`), `current` is switched with an
+//    atomic rename, and the engine exits so systemd (`Restart=on-failure`)
+//    starts the new release.
+// 3. `POST /admin/api/v1/update/rollback` points `current` back at the
+//    release that was running before the last switch and restarts. If the
+//    newer release migrated the schema, the older one would refuse the
+//    database, so the `-pre-update` snapshot is restored as well (changes
+//    made since the update are lost; the database as it was is kept as a
+//    `-pre-rollback` snapshot).
+//
+// Progress and results are reported by `GET /admin/api/v1/update/status`.
+// `state.json` remembers the previous release and the switch in flight, so
+// after the restart the status says whether the new version came up.
+//
+// Nothing runs unattended: checks and updates happen only when asked.
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use axum::{extract::State, http::StatusCode, Json};
+use rusqlite::DatabaseName;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::{backup, db, ffmpeg, migrations, AppState, UpdateStatus};
+
+/// Time allowed for one download.
+const DOWNLOAD_TIMEOUT_S: u64 = 1800;
+/// Time allowed for a feed or checksum request.
+const FETCH_TIMEOUT_S: u64 = 30;
+/// Exit code used to have systemd restart the engine on the new release.
+const RESTART_EXIT_CODE: i32 = 75;
+/// The public half of the key releases are signed with (the `MINISIGN_KEY`
+/// secret of the build workflow), when the build was given one.
+const RELEASE_PUBKEY: Option<&str> = option_env!("STUDIOCOMMAND_RELEASE_PUBKEY");
+
+fn root() -> PathBuf {
+    std::env::var("STUDIOCOMMAND_ROOT").unwrap_or_else(|_| "/opt/studiocommand".to_string()).into()
+}
+
+fn feed_url() -> String {
+    std::env::var("STUDIOCOMMAND_UPDATE_FEED")
+        .unwrap_or_else(|_| "https://api.github.com/repos/ChZeman/StudioCommand/releases/latest".to_string())
+}
+
+fn updates_dir() -> PathBuf {
+    root().join("shared").join("updates")
+}
+
+fn tarball_name() -> String {
+    format!("studiocommand-linux-{}.tar.gz", std::env::consts::ARCH)
+}
+
+/// A release from the feed that has an asset for this machine.
+#[derive(Clone)]
+struct Release {
+    version: String,
+    tarball_url: String,
+    tarball_size: u64,
+    sums_url: Option<String>,
+    sig_url: Option<String>,
+}
+
+struct Inner {
+    state: &'static str,
+    available: Option<Release>,
+    staged: Option<String>,
+    last_result: Option<String>,
+    progress: Option<u8>,
+}
+
+static STATUS: Mutex<Inner> =
+    Mutex::new(Inner { state: "idle", available: None, staged: None, last_result: None, progress: None });
+
+fn set(state: &'static str, progress: Option<u8>) {
+    let mut s = STATUS.lock().unwrap_or_else(|e| e.into_inner());
+    s.state = state;
+    s.progress = progress;
+}
+
+fn fail(msg: String) {
+    tracing::warn!("update: {msg}");
+    let mut s = STATUS.lock().unwrap_or_else(|e| e.into_inner());
+    s.state = "error";
+    s.progress = None;
+    s.last_result = Some(msg);
+}
+
+/// States during which nothing else may start.
+fn busy() -> bool {
+    matches!(
+        STATUS.lock().unwrap_or_else(|e| e.into_inner()).state,
+        "checking" | "downloading" | "verifying" | "staging" | "applying" | "restarting"
+    )
+}
+
+pub(crate) fn status(current: &str) -> UpdateStatus {
+    let s = STATUS.lock().unwrap_or_else(|e| e.into_inner());
+    UpdateStatus {
+        state: s.state.to_string(),
+        current: current.to_string(),
+        available: s.available.as_ref().map(|r| r.version.clone()),
+        staged: s.staged.clone(),
+        last_result: s.last_result.clone(),
+        progress: s.progress,
+        arch: std::env::consts::ARCH.to_string(),
+    }
+}
+
+// --- state.json ------------------------------------------------------------------------
+
+#[derive(Default, Serialize, Deserialize)]
+struct Persisted {
+    /// Release directory that was `current` before the last switch.
+    previous: Option<PathBuf>,
+    /// Switch in flight across the restart: (from version, to version, "update" | "rollback").
+    pending: Option<(String, String, String)>,
+    /// Snapshot taken before the last update, and the schema version then.
+    pre_update_backup: Option<String>,
+    schema_before: Option<u32>,
+}
+
+fn state_path() -> PathBuf {
+    updates_dir().join("state.json")
+}
+
+fn load_state() -> Persisted {
+    std::fs::read(state_path()).ok().and_then(|b| serde_json::from_slice(&b).ok()).unwrap_or_default()
+}
+
+fn save_state(p: &Persisted) -> std::io::Result<()> {
+    let path = state_path();
+    std::fs::create_dir_all(updates_dir())?;
+    let tmp = path.with_extension("json.part");
+    std::fs::write(&tmp, serde_json::to_vec_pretty(p)?)?;
+    std::fs::rename(tmp, path)
+}
+
+/// Report how the switch before this start went. Called once at startup.
+pub(crate) fn startup(running: &str) {
+    let mut p = load_state();
+    let Some((from, to, kind)) = p.pending.take() else { return };
+    let msg = if to == running {
+        format!("{kind} from {from} to {to} completed")
+    } else {
+        format!("{kind} to {to} did not take effect: running {running}")
+    };
+    if to == running {
+        tracing::info!("update: {msg}");
+    } else {
+        tracing::warn!("update: {msg}");
+    }
+    STATUS.lock().unwrap_or_else(|e| e.into_inner()).last_result = Some(msg);
+    if let Err(e) = save_state(&p) {
+        tracing::warn!("update: cannot write {}: {e}", state_path().display());
+    }
+}
+
+// --- Feed ------------------------------------------------------------------------------
+
+/// `v0.1.99` / `0.1.99` -> [0, 1, 99]; anything after a `-` (pre-release) is ignored.
+fn version_key(v: &str) -> Vec<u64> {
+    let v = v.trim().trim_start_matches('v');
+    let core = v.split('-').next().unwrap_or(v);
+    core.split('.').map(|n| n.parse().unwrap_or(0)).collect()
+}
+
+async fn fetch(url: &str) -> Result<Vec<u8>, String> {
+    let out = tokio::process::Command::new(ffmpeg::curl_bin())
+        .args(["-fsSL", "--max-time", &FETCH_TIMEOUT_S.to_string(), "-H", "Accept: application/vnd.github+json", url])
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|e| format!("{}: {e}", ffmpeg::curl_bin()))?;
+    if !out.status.success() {
+        return Err(format!("{url}: {}", String::from_utf8_lossy(&out.stderr).trim()));
+    }
+    Ok(out.stdout)
+}
+
+fn parse_release(feed: &Value) -> Result<Release, String> {
+    let tag = feed.get("tag_name").and_then(|t| t.as_str()).ok_or("release feed has no tag_name")?;
+    let assets = feed.get("assets").and_then(|a| a.as_array()).cloned().unwrap_or_default();
+    let asset = |name: &str| assets.iter().find(|a| a.get("name").and_then(|n| n.as_str()) == Some(name));
+    let url = |a: &Value| a.get("browser_download_url").and_then(|u| u.as_str()).map(str::to_string);
+    let tarball = asset(&tarball_name()).ok_or_else(|| format!("release {tag} has no {}", tarball_name()))?;
+    Ok(Release {
+        version: tag.trim_start_matches('v').to_string(),
+        tarball_url: url(tarball).ok_or("tarball has no download URL")?,
+        tarball_size: tarball.get("size").and_then(|s| s.as_u64()).unwrap_or(0),
+        sums_url: asset("sha256sums.txt").and_then(url),
+        sig_url: asset("sha256sums.txt.minisig").and_then(url),
+    })
+}
+
+async fn check(current: &str) -> Result<Option<Release>, String> {
+    let body = fetch(&feed_url()).await?;
+    let feed: Value = serde_json::from_slice(&body).map_err(|e| format!("release feed: {e}"))?;
+    let release = parse_release(&feed)?;
+    Ok((version_key(&release.version) > version_key(current)).then_some(release))
+}
+
+// --- Download, verify, stage -----------------------------------------------------------
+
+/// Download to `dest`, reporting progress against the size from the feed.
+async fn download(r: &Release, dest: &Path) -> Result<(), String> {
+    let mut child = tokio::process::Command::new(ffmpeg::curl_bin())
+        .args(["-fsSL", "--max-time", &DOWNLOAD_TIMEOUT_S.to_string(), "-o"])
+        .arg(dest)
+        .arg(&r.tarball_url)
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("{}: {e}", ffmpeg::curl_bin()))?;
+    let mut tick = tokio::time::interval(Duration::from_millis(500));
+    let status = loop {
+        tokio::select! {
+            st = child.wait() => break st.map_err(|e| e.to_string())?,
+            _ = tick.tick() => {
+                let got = tokio::fs::metadata(dest).await.map(|m| m.len()).unwrap_or(0);
+                if let Some(pct) = (got * 100).checked_div(r.tarball_size) {
+                    set("downloading", Some(pct.min(99) as u8));
+                }
+            }
+        }
+    };
+    if !status.success() {
+        let mut err = String::new();
+        if let Some(mut e) = child.stderr.take() {
+            use tokio::io::AsyncReadExt;
+            let _ = e.read_to_string(&mut err).await;
+        }
+        return Err(format!("download failed: {}", err.trim()));
+    }
+    Ok(())
+}
+
+fn sha256_file(path: &Path) -> std::io::Result<String> {
+    let mut f = std::fs::File::open(path)?;
+    let mut h = Sha256::new();
+    std::io::copy(&mut f, &mut h)?;
+    Ok(h.finalize().iter().map(|b| format!("{b:02x}")).collect())
+}
+
+/// The key release signatures are checked with.
+fn signing_key() -> Result<minisign_verify::PublicKey, String> {
+    let (key, from) = match std::env::var("STUDIOCOMMAND_UPDATE_PUBKEY") {
+        Ok(key) => (key, "STUDIOCOMMAND_UPDATE_PUBKEY"),
+        Err(_) => match RELEASE_PUBKEY {
+            Some(key) => (key.to_string(), "built-in release key"),
+            None => return Err("this build has no release key; set STUDIOCOMMAND_UPDATE_PUBKEY".into()),
+        },
+    };
+    decode_key(&key).map_err(|e| format!("{from}: {e}"))
+}
+
+/// A minisign public key, either the base64 line or a whole `.pub` file.
+fn decode_key(key: &str) -> Result<minisign_verify::PublicKey, minisign_verify::Error> {
+    minisign_verify::PublicKey::from_base64(key.trim()).or_else(|_| minisign_verify::PublicKey::decode(key.trim()))
+}
+
+fn allow_unsigned() -> bool {
+    std::env::var("STUDIOCOMMAND_UPDATE_ALLOW_UNSIGNED").is_ok_and(|v| v == "1")
+}
+
+/// Check `sums` against its minisign signature.
+fn check_signature(pk: &minisign_verify::PublicKey, sums: &[u8], sig: &str) -> Result<(), String> {
+    let sig = minisign_verify::Signature::decode(sig).map_err(|e| format!("signature: {e}"))?;
+    pk.verify(sums, &sig, false).map_err(|e| format!("signature check failed: {e}"))
+}
+
+/// Check the tarball against the signed checksum file.
+async fn verify(r: &Release, tarball: &Path) -> Result<String, String> {
+    let sums_url = r.sums_url.as_ref().ok_or("release has no sha256sums.txt; refusing an unverifiable update")?;
+    let sums = fetch(sums_url).await?;
+
+    // A signature that is there is always checked; only a missing one can be let through.
+    let signed = match (&r.sig_url, allow_unsigned()) {
+        (Some(sig_url), _) => {
+            let pk = signing_key()?;
+            let sig = String::from_utf8(fetch(sig_url).await?).map_err(|_| "signature is not text")?;
+            check_signature(&pk, &sums, &sig)?;
+            true
+        }
+        (None, true) => false,
+        (None, false) => {
+            return Err("release is not signed (no sha256sums.txt.minisig); refusing an unverifiable update".into())
+        }
+    };
+
+    let name = tarball_name();
+    let expected = String::from_utf8_lossy(&sums)
+        .lines()
+        .filter_map(|l| l.split_once(char::is_whitespace))
+        .find(|(_, file)| file.trim().trim_start_matches('*') == name)
+        .map(|(hash, _)| hash.to_ascii_lowercase())
+        .ok_or_else(|| format!("sha256sums.txt has no entry for {name}"))?;
+    let path = tarball.to_path_buf();
+    let actual = tokio::task::spawn_blocking(move || sha256_file(&path))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())?;
+    if actual != expected {
+        return Err(format!("checksum mismatch for {name}: expected {expected}, got {actual}"));
+    }
+    Ok(if signed {
+        "checksum and signature verified".into()
+    } else {
+        "checksum verified; UNSIGNED release accepted (STUDIOCOMMAND_UPDATE_ALLOW_UNSIGNED)".into()
+    })
+}
+
+/// Unpack into `releases/<version>` (via a `.pending` name, as the installer
+/// does) and check that it holds an engine of the expected version.
+async fn stage(version: &str, tarball: &Path) -> Result<PathBuf, String> {
+    let releases = root().join("releases");
+    let rel = releases.join(version);
+    let pending = releases.join(format!("{version}.pending"));
+    let current = tokio::fs::canonicalize(root().join("current")).await.ok();
+    if current.as_deref() == Some(rel.as_path()) {
+        return Err(format!("{version} is the release already running"));
+    }
+    let _ = tokio::fs::remove_dir_all(&pending).await;
+    tokio::fs::create_dir_all(&pending).await.map_err(|e| format!("{}: {e}", pending.display()))?;
+    let out = tokio::process::Command::new("tar")
+        .arg("-xzf")
+        .arg(tarball)
+        .arg("-C")
+        .arg(&pending)
+        .output()
+        .await
+        .map_err(|e| format!("tar: {e}"))?;
+    if !out.status.success() {
+        return Err(format!("cannot unpack: {}", String::from_utf8_lossy(&out.stderr).trim()));
+    }
+
+    let unpacked = pending.join("studiocommand");
+    let bin = unpacked.join("studiocommand-engine");
+    let executable = {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::metadata(&bin).map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0).unwrap_or(false)
+    };
+    if !executable {
+        return Err("tarball has no studiocommand/studiocommand-engine".into());
+    }
+    let packaged = tokio::fs::read_to_string(unpacked.join("VERSION")).await.unwrap_or_default();
+    if version_key(&packaged) != version_key(version) {
+        return Err(format!("tarball is version {:?}, expected {version}", packaged.trim()));
+    }
+
+    let _ = tokio::fs::remove_dir_all(&rel).await;
+    tokio::fs::rename(&unpacked, &rel).await.map_err(|e| format!("{}: {e}", rel.display()))?;
+    let _ = tokio::fs::remove_dir_all(&pending).await;
+    Ok(rel)
+}
+
+// --- Switch ----------------------------------------------------------------------------
+
+/// Point `current` at `target` atomically and record the switch.
+fn switch_to(target: &Path, pending: (String, String, String), backup: Option<(String, u32)>) -> Result<(), String> {
+    let current = root().join("current");
+    let previous = std::fs::canonicalize(&current).ok();
+    let tmp = root().join("current.new");
+    let _ = std::fs::remove_file(&tmp);
+    std::os::unix::fs::symlink(target, &tmp).map_err(|e| format!("{}: {e}", tmp.display()))?;
+
+    let (pre_update_backup, schema_before) = backup.unzip();
+    let state = Persisted { previous, pending: Some(pending), pre_update_backup, schema_before };
+    save_state(&state).map_err(|e| format!("{}: {e}", state_path().display()))?;
+    std::fs::rename(&tmp, &current).map_err(|e| format!("{}: {e}", current.display()))?;
+    Ok(())
+}
+
+/// Exit shortly (after the HTTP response went out) so systemd restarts us.
+fn restart_soon() {
+    set("restarting", None);
+    exit_for_restart("update: exiting to restart on the new release");
+}
+
+/// Exit a second from now (after the HTTP response has gone out) with the
+/// code that has systemd start the engine again.
+pub(crate) fn exit_for_restart(why: &'static str) {
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        tracing::warn!("{why}");
+        std::process::exit(RESTART_EXIT_CODE);
+    });
+}
+
+async fn apply(current: String, r: Release) -> Result<(), String> {
+    let staged = STATUS.lock().unwrap_or_else(|e| e.into_inner()).staged.clone();
+    let rel = root().join("releases").join(&r.version);
+    if staged.as_deref() != Some(r.version.as_str()) || !rel.is_dir() {
+        let dir = updates_dir();
+        tokio::fs::create_dir_all(&dir).await.map_err(|e| format!("{}: {e}", dir.display()))?;
+        let tarball = dir.join(tarball_name());
+        set("downloading", Some(0));
+        download(&r, &tarball).await?;
+        set("verifying", None);
+        let verified = verify(&r, &tarball).await?;
+        set("staging", None);
+        stage(&r.version, &tarball).await?;
+        let _ = tokio::fs::remove_file(&tarball).await;
+        let mut s = STATUS.lock().unwrap_or_else(|e| e.into_inner());
+        s.staged = Some(r.version.clone());
+        s.last_result = Some(format!("{} staged: {verified}", r.version));
+    }
+
+    set("applying", None);
+    let schema = schema_version().await?;
+    let (snapshot, _) =
+        backup::backup_now("-pre-update").await.map_err(|e| format!("could not snapshot the database first: {e}"))?;
+    switch_to(&rel, (current, r.version.clone(), "update".into()), Some((snapshot, schema)))?;
+    tracing::info!("update: switched to {}", rel.display());
+    restart_soon();
+    Ok(())
+}
+
+async fn schema_version() -> Result<u32, String> {
+    db::call(|conn| migrations::current(conn)).await.map_err(|e| e.to_string())?.map_err(|e| e.to_string())
+}
+
+/// Put the database back as it was before the update, for the older release,
+/// and close it so nothing writes to it before the restart. Returns the
+/// snapshot of the database as it is now.
+async fn restore_pre_update(name: &str) -> Result<String, String> {
+    let src = backup::backup_dir().join(name);
+    if !src.is_file() {
+        return Err(format!("pre-update snapshot {name} is gone"));
+    }
+    let (safety, _) =
+        backup::backup_now("-pre-rollback").await.map_err(|e| format!("could not snapshot the database first: {e}"))?;
+    // No migration afterwards: the snapshot is already in the older release's schema.
+    db::call(move |conn| -> rusqlite::Result<()> {
+        conn.restore(DatabaseName::Main, &src, None::<fn(rusqlite::backup::Progress)>)?;
+        db::close();
+        Ok(())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| format!("restore failed: {e}"))?;
+    Ok(safety)
+}
+
+// --- API -------------------------------------------------------------------------------
+
+type ApiError = (StatusCode, Json<Value>);
+
+fn api_err(code: StatusCode, msg: impl Into<String>) -> ApiError {
+    (code, Json(json!({"ok": false, "error": msg.into()})))
+}
+
+/// `POST /admin/api/v1/update/check`
+pub(crate) async fn api_check(State(st): State<AppState>) -> Result<Json<UpdateStatus>, ApiError> {
+    if busy() {
+        return Err(api_err(StatusCode::CONFLICT, "an update is in progress"));
+    }
+    set("checking", None);
+    match check(&st.version).await {
+        Ok(found) => {
+            let mut s = STATUS.lock().unwrap_or_else(|e| e.into_inner());
+            s.state = if found.is_some() { "available" } else { "idle" };
+            s.last_result = Some(match &found {
+                Some(r) => format!("{} is available", r.version),
+                None => format!("{} is up to date", st.version),
+            });
+            s.available = found;
+        }
+        Err(e) => fail(format!("check failed: {e}")),
+    }
+    Ok(Json(status(&st.version)))
+}
+
+/// `POST /admin/api/v1/update/apply`: download, verify, stage and switch to
+/// the release found by the last check, then restart. Runs in the
+/// background; follow it with the status endpoint.
+pub(crate) async fn api_apply(State(st): State<AppState>) -> Result<Json<UpdateStatus>, ApiError> {
+    if busy() {
+        return Err(api_err(StatusCode::CONFLICT, "an update is in progress"));
+    }
+    let Some(release) = STATUS.lock().unwrap_or_else(|e| e.into_inner()).available.clone() else {
+        return Err(api_err(StatusCode::CONFLICT, "no update available (run a check first)"));
+    };
+    set("downloading", Some(0));
+    let current = st.version.clone();
+    tokio::spawn(async move {
+        if let Err(e) = apply(current, release).await {
+            fail(format!("update failed: {e}"));
+        }
+    });
+    Ok(Json(status(&st.version)))
+}
+
+/// `POST /admin/api/v1/update/rollback`: back to the release that ran before
+/// the last switch.
+pub(crate) async fn api_rollback(State(st): State<AppState>) -> Result<Json<UpdateStatus>, ApiError> {
+    if busy() {
+        return Err(api_err(StatusCode::CONFLICT, "an update is in progress"));
+    }
+    let state = load_state();
+    let Some(previous) = state.previous.filter(|p| p.is_dir()) else {
+        return Err(api_err(StatusCode::CONFLICT, "no previous release to roll back to"));
+    };
+    let to = std::fs::read_to_string(previous.join("VERSION"))
+        .map(|v| v.trim().to_string())
+        .unwrap_or_else(|_| previous.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default());
+    set("applying", None);
+    let mut restored = false;
+    let res = async {
+        let mut note = String::new();
+        if let (Some(name), Some(before)) = (state.pre_update_backup, state.schema_before) {
+            if schema_version().await? > before {
+                let safety = restore_pre_update(&name).await?;
+                restored = true;
+                note = format!("; database restored from {name} (previous state saved as {safety})");
+            }
+        }
+        switch_to(&previous, (st.version.clone(), to.clone(), "rollback".into()), None)?;
+        Ok::<_, String>(note)
+    }
+    .await;
+    let note = match res {
+        Ok(n) => n,
+        Err(e) => {
+            fail(format!("rollback failed: {e}"));
+            // The database is closed once restored: start again on this
+            // release, which migrates it back up.
+            if restored {
+                exit_for_restart("update: exiting to restart after a failed rollback");
+            }
+            return Err(api_err(StatusCode::INTERNAL_SERVER_ERROR, format!("rollback failed: {e}")));
+        }
+    };
+    tracing::info!("update: rolling back to {}{note}", previous.display());
+    STATUS.lock().unwrap_or_else(|e| e.into_inner()).last_result = Some(format!("rolling back to {to}{note}"));
+    restart_soon();
+    Ok(Json(status(&st.version)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Signed with a throwaway key; only its public half is kept.
+    const TEST_PUBKEY: &str = "RWSTKMsw67zvgoqKXb37QsrAiveGK01QHoDqYxChEbr5aXyhEHUvqHX6";
+    /// Another throwaway key, for a signature from the wrong key.
+    const OTHER_PUBKEY: &str = "RWQ4prEL/xornLf8Z4KkCRur7imo5OdECc0A87zUcj1lIwwU1N9vLGEg";
+    const TEST_SUMS: &str = "0123abcd  studiocommand-linux-x86_64.tar.gz\n";
+    const TEST_SIG: &str = "untrusted comment: signature from minisign secret key
+RUSTKMsw67zvgm0iATWBlw+yd4DpJztrK193V7sotWG4g7DVl9J9o3kTyTV7fKR4VoqMn6RBOAXtqUVsvG0gRiFJE3ikVKnk/wA=
+trusted comment: timestamp:1792000000\tfile:sha256sums.txt\thashed
+gEFNBEfN8+o3pcqsVjJisDPxU5sK4yfqVxUiLiZyILmVU0jjiySXIDg1BZhr+skjXWqtbZgpWEWj0//zEUXPBQ==
+";
+
+    #[test]
+    fn signature_checks() {
+        let pk = decode_key(TEST_PUBKEY).unwrap();
+        assert!(check_signature(&pk, TEST_SUMS.as_bytes(), TEST_SIG).is_ok());
+        let tampered = TEST_SUMS.replace("0123abcd", "0123abce");
+        assert!(check_signature(&pk, tampered.as_bytes(), TEST_SIG).is_err());
+        let other = decode_key(OTHER_PUBKEY).unwrap();
+        assert!(check_signature(&other, TEST_SUMS.as_bytes(), TEST_SIG).is_err());
+        assert!(check_signature(&pk, TEST_SUMS.as_bytes(), "not a signature").is_err());
+    }
+
+    #[test]
+    fn keys_decode_from_line_or_file() {
+        let file = format!("untrusted comment: minisign public key\n{TEST_PUBKEY}\n");
+        assert!(decode_key(&file).is_ok());
+        assert!(decode_key(&format!(" {TEST_PUBKEY}\n")).is_ok());
+        assert!(decode_key("RWnotakey").is_err());
+    }
+}
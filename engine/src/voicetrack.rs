@@ -0,0 +1,469 @@
+// --- Voice tracking ----------------------------------------------------------------------
+//
+// A voice track is a DJ's link, recorded ahead of time and dropped into the
+// running order between two songs, so a show can be pre-recorded and still
+// sound live. The browser records it (MediaRecorder) and POSTs the file; it is
+// transcoded to FLAC under `<data dir>/voicetracks/` (browser recordings are
+// often unseekable WebM) and measured to the millisecond by decoding it once.
+//
+// Attached to the queue or a day log, a voice track is an ordinary `VT` item
+// whose cart is its file. What makes it a voice track is its transition, one
+// set of parameters per side:
+//
+// - `prev_overlap_ms` / `prev_duck_db`: the voice starts this long before the
+//   song before it ends, and that song is ducked under it;
+// - `next_overlap_ms` / `next_duck_db`: the song after it starts this long
+//   before the voice ends, ducked under it, and comes up when the voice ends.
+//
+// `writer_playout` renders the overlap: at the handover point it starts the
+// next item on the overlay bus (overlay.rs) and ducks the item on air; when
+// that one ends, the next item's own decoder takes over from exactly where the
+// overlay voice got to. The transition belongs to the voice track, not to the
+// songs, so it follows the track when the queue around it is edited.
+//
+// The writer finds voice tracks by file path in an in-memory index, as carts.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{OnceLock, RwLock};
+
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::io::AsyncReadExt;
+use uuid::Uuid;
+
+use crate::daylog::{self, DayLogItem};
+use crate::{
+    apply_queue_batch_op, bump_queue_rev, ffmpeg, fmt_dur_mmss, normalize_log_state, persist_queue,
+    spawn_ffmpeg_decoder, storage, unix_ms_now, AppState, QueueBatchOp, QueueInsertItem,
+};
+
+/// Largest recording accepted by the upload endpoint.
+pub(crate) const MAX_UPLOAD_BYTES: usize = 200 * 1024 * 1024;
+/// Longest overlap on either side.
+const MAX_OVERLAP_MS: u32 = 30_000;
+/// Overlay bus key of the item being handed over.
+pub(crate) const HANDOVER: &str = "handover";
+/// How long ducking and coming back up take.
+pub(crate) const RAMP_MS: u64 = 300;
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct Transition {
+    #[serde(default)]
+    prev_overlap_ms: u32,
+    #[serde(default = "default_duck_db")]
+    prev_duck_db: f32,
+    #[serde(default)]
+    next_overlap_ms: u32,
+    #[serde(default = "default_duck_db")]
+    next_duck_db: f32,
+}
+
+fn default_duck_db() -> f32 {
+    -10.0
+}
+
+impl Transition {
+    fn validate(&self) -> Result<(), String> {
+        if self.prev_overlap_ms > MAX_OVERLAP_MS || self.next_overlap_ms > MAX_OVERLAP_MS {
+            return Err(format!("overlaps must be 0..{MAX_OVERLAP_MS} ms"));
+        }
+        if ![self.prev_duck_db, self.next_duck_db].iter().all(|d| (-40.0..=0.0).contains(d)) {
+            return Err("duck_db must be -40..0".into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct VoiceTrack {
+    id: i64,
+    title: String,
+    path: String,
+    dur_ms: u64,
+    created_ms: u64,
+    transition: Transition,
+}
+
+impl VoiceTrack {
+    fn insert_item(&self) -> QueueInsertItem {
+        QueueInsertItem {
+            tag: "VT".into(),
+            title: self.title.clone(),
+            artist: "Voice track".into(),
+            dur: fmt_dur_mmss(self.dur_ms.div_ceil(1000) as u32),
+            dur_ms: Some(self.dur_ms),
+            cart: self.path.clone(),
+            locked: false,
+        }
+    }
+}
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS voice_tracks (
+            id               INTEGER PRIMARY KEY AUTOINCREMENT,
+            title            TEXT NOT NULL,
+            path             TEXT NOT NULL UNIQUE,
+            dur_ms           INTEGER NOT NULL,
+            created_ms       INTEGER NOT NULL,
+            prev_overlap_ms  INTEGER NOT NULL DEFAULT 0,
+            prev_duck_db     REAL NOT NULL DEFAULT -10,
+            next_overlap_ms  INTEGER NOT NULL DEFAULT 0,
+            next_duck_db     REAL NOT NULL DEFAULT -10
+        );
+        "#,
+    )
+}
+
+// --- In-memory index ---------------------------------------------------------
+
+fn index() -> &'static RwLock<HashMap<String, (u64, Transition)>> {
+    static INDEX: OnceLock<RwLock<HashMap<String, (u64, Transition)>>> = OnceLock::new();
+    INDEX.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+fn index_set(path: &str, entry: Option<(u64, Transition)>) {
+    if let Ok(mut idx) = index().write() {
+        match entry {
+            Some(e) => idx.insert(path.to_string(), e),
+            None => idx.remove(path),
+        };
+    }
+}
+
+/// Load the voice-track index at startup.
+pub(crate) async fn load_index() {
+    let res = with_db(|conn| {
+        let tracks = db_list(conn)?;
+        Ok(tracks.into_iter().map(|t| (t.path, (t.dur_ms, t.transition))).collect::<HashMap<_, _>>())
+    })
+    .await;
+    match res {
+        Ok(map) => {
+            if let Ok(mut idx) = index().write() {
+                *idx = map;
+            }
+        }
+        Err(e) => tracing::warn!("voicetrack: failed to load index: {e}"),
+    }
+}
+
+/// Where the item on air hands over to the next one, if either is a voice
+/// track (or, see transitions.rs, the item on air overlaps the next).
+pub(crate) struct Handover {
+    /// Position in the item on air at which the next one starts.
+    pub(crate) at_ms: u64,
+    /// Gain on the item on air from then on.
+    pub(crate) out_db: f32,
+    /// How long the item on air takes to get to `out_db`.
+    pub(crate) ramp_ms: u64,
+    /// Gain on the next item until the item on air ends.
+    pub(crate) in_db: f32,
+    /// The next item fades in from silence over this long.
+    pub(crate) fade_in_ms: u64,
+}
+
+/// The handover from `current` (a file `dur_ms` long, if known) to `next`.
+/// Cheap; safe to call from playout.
+pub(crate) fn handover(current: &str, dur_ms: u64, next: &str) -> Option<Handover> {
+    let idx = index().read().ok()?;
+    if let Some((_, t)) = idx.get(next).filter(|(_, t)| t.prev_overlap_ms > 0) {
+        let dur_ms = idx.get(current).map_or(dur_ms, |(d, _)| *d);
+        return (dur_ms > 0).then(|| Handover {
+            at_ms: dur_ms.saturating_sub(t.prev_overlap_ms as u64),
+            out_db: t.prev_duck_db,
+            ramp_ms: RAMP_MS,
+            in_db: 0.0,
+            fade_in_ms: 0,
+        });
+    }
+    let (dur_ms, t) = idx.get(current).filter(|(_, t)| t.next_overlap_ms > 0)?;
+    Some(Handover {
+        at_ms: dur_ms.saturating_sub(t.next_overlap_ms as u64),
+        out_db: 0.0,
+        ramp_ms: RAMP_MS,
+        in_db: t.next_duck_db,
+        fade_in_ms: 0,
+    })
+}
+
+// --- SQLite ----------------------------------------------------------------------
+
+async fn with_db<T: Send + 'static>(
+    f: impl FnOnce(&mut Connection) -> anyhow::Result<T> + Send + 'static,
+) -> anyhow::Result<T> {
+    crate::db::call(move |conn| {
+        crate::db_init(conn)?;
+        f(conn)
+    })
+    .await?
+}
+
+const COLUMNS: &str =
+    "id, title, path, dur_ms, created_ms, prev_overlap_ms, prev_duck_db, next_overlap_ms, next_duck_db";
+
+fn track_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<VoiceTrack> {
+    Ok(VoiceTrack {
+        id: row.get(0)?,
+        title: row.get(1)?,
+        path: row.get(2)?,
+        dur_ms: row.get(3)?,
+        created_ms: row.get(4)?,
+        transition: Transition {
+            prev_overlap_ms: row.get(5)?,
+            prev_duck_db: row.get(6)?,
+            next_overlap_ms: row.get(7)?,
+            next_duck_db: row.get(8)?,
+        },
+    })
+}
+
+fn db_list(conn: &Connection) -> anyhow::Result<Vec<VoiceTrack>> {
+    let mut stmt = conn.prepare(&format!("SELECT {COLUMNS} FROM voice_tracks ORDER BY id DESC"))?;
+    let rows = stmt.query_map([], track_from_row)?;
+    Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+}
+
+fn db_get(conn: &Connection, id: i64) -> anyhow::Result<Option<VoiceTrack>> {
+    Ok(conn
+        .query_row(&format!("SELECT {COLUMNS} FROM voice_tracks WHERE id = ?1"), [id], track_from_row)
+        .optional()?)
+}
+
+async fn get_track(id: i64) -> Result<VoiceTrack, ApiError> {
+    with_db(move |conn| db_get(conn, id))
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| api_error(StatusCode::NOT_FOUND, format!("no voice track {id}")))
+}
+
+type ApiError = (StatusCode, Json<serde_json::Value>);
+
+fn api_error(status: StatusCode, msg: impl Into<String>) -> ApiError {
+    (status, Json(json!({"ok": false, "error": msg.into()})))
+}
+
+fn db_error(e: anyhow::Error) -> ApiError {
+    api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+// --- Recording ---------------------------------------------------------------------
+
+fn track_dir() -> PathBuf {
+    storage::data_dir().join("voicetracks")
+}
+
+/// Transcode an upload to `dest` (FLAC, 48 kHz stereo).
+async fn transcode(src: &std::path::Path, dest: &std::path::Path) -> Result<(), String> {
+    let out = tokio::process::Command::new(ffmpeg::ffmpeg_bin())
+        .args(["-hide_banner", "-loglevel", "error", "-y", "-i"])
+        .arg(src)
+        .args(["-vn", "-ac", "2", "-ar", "48000", "-c:a", "flac"])
+        .arg(dest)
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|e| format!("ffmpeg could not be run: {e}"))?;
+    if !out.status.success() {
+        let err = String::from_utf8_lossy(&out.stderr);
+        return Err(format!("not decodable ({})", err.lines().last().unwrap_or("").trim()));
+    }
+    Ok(())
+}
+
+/// Exact length of `path`, by decoding it.
+async fn measure_ms(path: &str) -> Result<u64, String> {
+    let (mut child, mut stdout) = spawn_ffmpeg_decoder(path, 0).await.map_err(|e| e.to_string())?;
+    let (mut chunk, mut bytes) = (vec![0u8; 64 * 1024], 0u64);
+    loop {
+        match stdout.read(&mut chunk).await {
+            Ok(0) => break,
+            Ok(n) => bytes += n as u64,
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+    let _ = child.wait().await;
+    // s16le stereo at 48 kHz: 192 bytes per millisecond.
+    Ok(bytes / 192)
+}
+
+#[derive(Deserialize)]
+pub(crate) struct UploadQuery {
+    #[serde(default)]
+    title: Option<String>,
+}
+
+/// `POST /api/v1/voicetracks?title=…` (body: the recording, any format ffmpeg reads).
+pub(crate) async fn api_voicetrack_upload(
+    Query(q): Query<UploadQuery>,
+    body: Bytes,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    if body.is_empty() {
+        return Err(api_error(StatusCode::BAD_REQUEST, "empty upload"));
+    }
+    let dir = track_dir();
+    let name = Uuid::new_v4().simple().to_string();
+    let (upload, dest) = (dir.join(format!("{name}.upload")), dir.join(format!("{name}.flac")));
+    let io = |e: std::io::Error| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    tokio::fs::create_dir_all(&dir).await.map_err(io)?;
+    tokio::fs::write(&upload, &body).await.map_err(io)?;
+    let transcoded = transcode(&upload, &dest).await;
+    let _ = tokio::fs::remove_file(&upload).await;
+    let path = dest.to_string_lossy().into_owned();
+    let dur_ms = match transcoded {
+        Ok(()) => measure_ms(&path).await,
+        Err(e) => Err(e),
+    };
+    let dur_ms = match dur_ms {
+        Ok(ms) if ms > 0 => ms,
+        res => {
+            let _ = tokio::fs::remove_file(&dest).await;
+            let e = res.err().unwrap_or_else(|| "no audio".into());
+            return Err(api_error(StatusCode::UNPROCESSABLE_ENTITY, format!("recording: {e}")));
+        }
+    };
+
+    let title = q.title.map(|t| t.trim().to_string()).filter(|t| !t.is_empty());
+    let title = title.unwrap_or_else(|| format!("Voice track {}", crate::fmt_local_hhmmss(unix_ms_now())));
+    let track = with_db(move |conn| {
+        conn.execute(
+            "INSERT INTO voice_tracks (title, path, dur_ms, created_ms) VALUES (?1, ?2, ?3, ?4)",
+            params![title, path, dur_ms, unix_ms_now()],
+        )?;
+        db_get(conn, conn.last_insert_rowid())
+    })
+    .await
+    .map_err(db_error)?
+    .ok_or_else(|| api_error(StatusCode::INTERNAL_SERVER_ERROR, "voice track vanished"))?;
+    index_set(&track.path, Some((track.dur_ms, track.transition)));
+    tracing::info!(target: "audit", "voicetrack: {} recorded ({} ms)", track.id, track.dur_ms);
+    Ok(Json(json!({"ok": true, "track": track})))
+}
+
+// --- HTTP API --------------------------------------------------------------------------
+
+/// `GET /api/v1/voicetracks`
+pub(crate) async fn api_voicetracks_list() -> Result<Json<serde_json::Value>, ApiError> {
+    let tracks = with_db(|conn| db_list(conn)).await.map_err(db_error)?;
+    Ok(Json(json!({"ok": true, "tracks": tracks})))
+}
+
+/// `GET /api/v1/voicetracks/{id}`
+pub(crate) async fn api_voicetrack_get(Path(id): Path<i64>) -> Result<Json<serde_json::Value>, ApiError> {
+    Ok(Json(json!({"ok": true, "track": get_track(id).await?})))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct VoiceTrackPatch {
+    title: Option<String>,
+    transition: Option<Transition>,
+}
+
+/// `PATCH /api/v1/voicetracks/{id}`: rename, or set the transition.
+pub(crate) async fn api_voicetrack_patch(
+    Path(id): Path<i64>,
+    Json(req): Json<VoiceTrackPatch>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let mut track = get_track(id).await?;
+    if let Some(title) = req.title.map(|t| t.trim().to_string()) {
+        if title.is_empty() {
+            return Err(api_error(StatusCode::BAD_REQUEST, "title is empty"));
+        }
+        track.title = title;
+    }
+    if let Some(t) = req.transition {
+        t.validate().map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
+        track.transition = t;
+    }
+    let saved = track.clone();
+    with_db(move |conn| {
+        let t = saved.transition;
+        conn.execute(
+            "UPDATE voice_tracks SET title = ?2, prev_overlap_ms = ?3, prev_duck_db = ?4, next_overlap_ms = ?5,
+                 next_duck_db = ?6 WHERE id = ?1",
+            params![id, saved.title, t.prev_overlap_ms, t.prev_duck_db, t.next_overlap_ms, t.next_duck_db],
+        )?;
+        Ok(())
+    })
+    .await
+    .map_err(db_error)?;
+    index_set(&track.path, Some((track.dur_ms, track.transition)));
+    Ok(Json(json!({"ok": true, "track": track})))
+}
+
+/// `DELETE /api/v1/voicetracks/{id}`: the row and the file. Items already in
+/// the queue or a day log are skipped as missing when they come up.
+pub(crate) async fn api_voicetrack_delete(Path(id): Path<i64>) -> Result<Json<serde_json::Value>, ApiError> {
+    let track = get_track(id).await?;
+    with_db(move |conn| {
+        conn.execute("DELETE FROM voice_tracks WHERE id = ?1", [id])?;
+        Ok(())
+    })
+    .await
+    .map_err(db_error)?;
+    index_set(&track.path, None);
+    let _ = tokio::fs::remove_file(&track.path).await;
+    tracing::info!(target: "audit", "voicetrack: {id} deleted");
+    Ok(Json(json!({"ok": true})))
+}
+
+/// Body for `POST /api/v1/voicetracks/{id}/attach`: either `after` (a queue
+/// item id) or `date` and `position` (a day log).
+#[derive(Deserialize)]
+pub(crate) struct AttachReq {
+    #[serde(default)]
+    after: Option<Uuid>,
+    #[serde(default)]
+    date: Option<String>,
+    #[serde(default)]
+    position: Option<usize>,
+}
+
+/// `POST /api/v1/voicetracks/{id}/attach`: put the track into the running order.
+/// Anchored on an item id, a queue attach needs no revision.
+pub(crate) async fn api_voicetrack_attach(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(req): Json<AttachReq>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let track = get_track(id).await?;
+    let item = track.insert_item();
+
+    if let Some(after) = req.after {
+        let mut p = state.playout.write().await;
+        apply_queue_batch_op(&mut p.log, QueueBatchOp::Insert { after_id: Some(after), item })
+            .map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
+        normalize_log_state(&mut p);
+        bump_queue_rev(&mut p);
+        persist_queue(p.log.clone()).await;
+        return Ok(Json(json!({"ok": true, "rev": p.queue_rev})));
+    }
+
+    let (Some(date), Some(position)) = (req.date, req.position) else {
+        return Err(api_error(StatusCode::BAD_REQUEST, "give `after` (queue item id) or `date` and `position`"));
+    };
+    if !daylog::valid_date(&date) {
+        return Err(api_error(StatusCode::BAD_REQUEST, "date must be YYYY-MM-DD"));
+    }
+    let rev = with_db(move |conn| {
+        let mut items = daylog::db_load_day(conn, &date)?;
+        if position > items.len() {
+            return Ok(Err(format!("position {position} is past the end of the log ({} items)", items.len())));
+        }
+        items.insert(position, DayLogItem::from_insert(String::new(), item));
+        Ok(Ok(daylog::db_save_day(conn, &date, &items)?))
+    })
+    .await
+    .map_err(db_error)?
+    .map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
+    Ok(Json(json!({"ok": true, "rev": rev})))
+}
@@ -0,0 +1,159 @@
+// --- Waveform peaks -------------------------------------------------------------
+//
+// The UI draws a track's waveform behind the progress bar and (later) uses it
+// for visual cue-point editing. It only needs a coarse outline: we decode the
+// file once to low-rate mono PCM, keep the absolute peak of each of
+// `RESOLUTION` equal slices scaled to 0..=255, and cache that in SQLite.
+//
+// Peaks are generated on ingest, or on the first request for a track.
+
+use axum::{
+    extract::{Path, Query},
+    http::StatusCode,
+    Json,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{library, paths};
+
+/// Number of peaks stored per file; requests for fewer are downsampled.
+const RESOLUTION: usize = 1000;
+
+/// Decode rate. Plenty for an outline and keeps decode output small.
+const DECODE_RATE: u32 = 8000;
+
+pub(crate) fn db_init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS waveforms (
+            path   TEXT PRIMARY KEY,
+            mtime  INTEGER NOT NULL,
+            size   INTEGER NOT NULL,
+            peaks  BLOB NOT NULL
+        );
+        "#,
+    )
+}
+
+fn db_lookup(conn: &Connection, path: &str, mtime: i64, size: i64) -> anyhow::Result<Option<Vec<u8>>> {
+    crate::db_init(conn)?;
+    Ok(conn
+        .query_row(
+            "SELECT peaks FROM waveforms WHERE path = ?1 AND mtime = ?2 AND size = ?3",
+            params![path, mtime, size],
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+fn db_store(conn: &Connection, path: &str, mtime: i64, size: i64, peaks: &[u8]) -> anyhow::Result<()> {
+    crate::db_init(conn)?;
+    conn.execute(
+        "INSERT INTO waveforms (path, mtime, size, peaks) VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT(path) DO UPDATE SET mtime=excluded.mtime, size=excluded.size, peaks=excluded.peaks",
+        params![path, mtime, size, peaks],
+    )?;
+    Ok(())
+}
+
+/// Decode `path` and reduce it to `RESOLUTION` peaks.
+async fn generate(path: &str) -> Result<Vec<u8>, String> {
+    use tokio::time::{timeout, Duration};
+
+    let ffmpeg = crate::ffmpeg::ffmpeg_bin();
+    let mut cmd = tokio::process::Command::new(ffmpeg);
+    cmd.arg("-v").arg("error")
+        .arg("-i").arg(paths::ffmpeg_input(&paths::from_cart(path)))
+        .arg("-ac").arg("1")
+        .arg("-ar").arg(DECODE_RATE.to_string())
+        .arg("-f").arg("s16le").arg("-")
+        .kill_on_drop(true);
+
+    let out = match timeout(Duration::from_secs(60), cmd.output()).await {
+        Ok(Ok(out)) => out,
+        Ok(Err(e)) => return Err(format!("ffmpeg could not be run: {e}")),
+        Err(_) => return Err(format!("{path}: waveform decode timed out")),
+    };
+    if !out.status.success() {
+        return Err(format!("{path}: waveform decode failed"));
+    }
+
+    let samples: Vec<u16> = out
+        .stdout
+        .chunks_exact(2)
+        .map(|b| i16::from_le_bytes([b[0], b[1]]).unsigned_abs())
+        .collect();
+    if samples.is_empty() {
+        return Err(format!("{path}: no audio decoded"));
+    }
+
+    let peaks = (0..RESOLUTION)
+        .map(|i| {
+            let start = i * samples.len() / RESOLUTION;
+            let end = ((i + 1) * samples.len() / RESOLUTION).max(start + 1).min(samples.len());
+            let peak = samples[start.min(samples.len() - 1)..end].iter().copied().max().unwrap_or(0);
+            (peak as u32 * 255 / 32768) as u8
+        })
+        .collect();
+    Ok(peaks)
+}
+
+/// Cached peaks for `path`, generating (and storing) them on a miss.
+pub(crate) async fn peaks_for(path: &str) -> Result<Vec<u8>, String> {
+    let md = std::fs::metadata(paths::from_cart(path)).map_err(|e| format!("{path}: {e}"))?;
+    let (mtime, size) = (library::file_mtime_secs(&md), md.len() as i64);
+
+    let p = path.to_string();
+    if let Ok(Ok(Some(peaks))) = crate::db::call(move |conn| db_lookup(conn, &p, mtime, size)).await
+    {
+        return Ok(peaks);
+    }
+
+    let peaks = generate(path).await?;
+    let (p, stored) = (path.to_string(), peaks.clone());
+    let saved = crate::db::call(move |conn| db_store(conn, &p, mtime, size, &stored)).await;
+    if let Ok(Err(e)) = saved {
+        tracing::warn!("waveform cache store failed: {e}");
+    }
+    Ok(peaks)
+}
+
+/// Reduce `peaks` to `n` points by taking the max of each group.
+fn downsample(peaks: &[u8], n: usize) -> Vec<u8> {
+    if n >= peaks.len() {
+        return peaks.to_vec();
+    }
+    (0..n)
+        .map(|i| {
+            let start = i * peaks.len() / n;
+            let end = ((i + 1) * peaks.len() / n).max(start + 1);
+            peaks[start..end].iter().copied().max().unwrap_or(0)
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+pub(crate) struct WaveformQuery {
+    points: Option<usize>,
+}
+
+pub(crate) async fn api_library_waveform(
+    Path(id): Path<i64>,
+    Query(q): Query<WaveformQuery>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let path = library::lookup_path(id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let peaks = peaks_for(&path).await.map_err(|e| {
+        tracing::warn!("waveform: {e}");
+        StatusCode::UNPROCESSABLE_ENTITY
+    })?;
+    let peaks = downsample(&peaks, q.points.unwrap_or(RESOLUTION).clamp(10, RESOLUTION));
+
+    // Each peak is 0..=255 (full scale) and covers an equal slice of the track.
+    Ok(Json(json!({"id": id, "points": peaks.len(), "peaks": peaks})))
+}
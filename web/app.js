@@ -23,6 +23,7 @@ const UI_VERSION = "0.1.99";
 const state = {
   role: "operator",
   log: [],
+  queueRev: null, // queue revision from /api/v1/status (optimistic concurrency)
   history: [], // not displayed here; would be in reports/admin
   selectedLogIndex: 0,
   selectedLogId: null,
@@ -110,20 +111,21 @@ const state = {
 function pad(n){ return String(n).padStart(2,'0');}
 function fmtTime(sec){
   sec = Math.max(0, Math.floor(sec));
-  const m = Math.floor(sec/60), s = sec%60;
-  return `${pad(m)}:${pad(s)}`;
+  const h = Math.floor(sec/3600), m = Math.floor(sec/60)%60, s = sec%60;
+  return h ? `${h}:${pad(m)}:${pad(s)}` : `${pad(m)}:${pad(s)}`;
+}
+// M:SS, or H:MM:SS from an hour up (like the engine's `dur`).
+function fmtDur(sec){
+  const h = Math.floor(sec/3600), m = Math.floor(sec/60)%60, s = sec%60;
+  return h ? `${h}:${pad(m)}:${pad(s)}` : `${m}:${pad(s)}`;
 }
 function fmtPosDur(pos, dur){
-  const m1 = Math.floor(pos/60), s1 = pos%60;
-  const m2 = Math.floor(dur/60), s2 = dur%60;
-  return [`${m1}:${pad(s1)}`, `${m2}:${pad(s2)}`];
+  return [fmtDur(pos), fmtDur(dur)];
 }
 function parseDurToSec(d){
   const parts = String(d).split(":");
-  if(parts.length !== 2) return 180;
-  const m = parseInt(parts[0], 10) || 0;
-  const s = parseInt(parts[1], 10) || 0;
-  return m*60 + s;
+  if(parts.length !== 2 && parts.length !== 3) return 180;
+  return parts.reduce((acc, p) => acc*60 + (parseInt(p, 10) || 0), 0);
 }
 function randFrom(arr){ return arr[Math.floor(Math.random()*arr.length)]; }
 
@@ -335,6 +337,10 @@ async function fetchStatus(){
     // Playout queue (log)
     state.log = Array.isArray(data.log) ? data.log : [];
 
+    // Queue revision: echoed back on reorder so the engine can reject edits
+    // made against a stale view (another operator changed the queue).
+    state.queueRev = (typeof data.queue_rev === "number") ? data.queue_rev : null;
+
 // If a reorder action just completed, compute which items *actually* moved.
 // We do this here (after we ingest the fresh log) so the highlight reflects
 // the authoritative backend order.
@@ -393,6 +399,7 @@ if(state.flashArmed && Array.isArray(state.flashPrevOrder) && state.flashPrevOrd
         jitter: p.jitter || "—",
         loss: p.loss || "—",
         camera: !!p.camOn,
+        level: Number(p.level) || 0,
       }));
     }
 
@@ -703,7 +710,18 @@ function renderAdminSystem(){
 async function postUpcomingReorder(upcomingIds){
   // The backend expects the full upcoming list, in the desired order.
   // (Strictness keeps the API simple and prevents accidental partial moves.)
-  return await postAction("/api/v1/queue/reorder", { order: upcomingIds });
+  // `rev` is the queue revision we rendered from; the engine answers 409 if
+  // someone else changed the queue in the meantime.
+  try{
+    return await postAction("/api/v1/queue/reorder", { order: upcomingIds, rev: state.queueRev });
+  }catch(err){
+    if(String(err && err.message || "").startsWith("HTTP 409")){
+      // Refresh so the operator sees the current order before trying again.
+      await fetchStatus();
+      throw new Error("The queue was changed by someone else. It has been refreshed; please try again.");
+    }
+    throw err;
+  }
 }
 
 
@@ -1177,7 +1195,8 @@ function renderProducers(){
     const meter = document.createElement("div");
     meter.className = "p-meter";
     const fill = document.createElement("div");
-    fill.style.width = (p.conn === "WARN" ? "38%" : "62%");
+    // Live producers carry their reported mic level; demo tiles do not.
+    fill.style.width = p.level != null ? `${Math.round(Math.min(1, p.level) * 100)}%` : (p.conn === "WARN" ? "38%" : "62%");
     meter.appendChild(fill);
 
     main.appendChild(name); main.appendChild(sub); main.appendChild(meter);
@@ -2030,7 +2049,9 @@ function renderStreaming(){
     const codecEl = qs("#outCodec"); if(codecEl) codecEl.value = cfg.codec || "mp3";
     setVal("#outBitrate", String(cfg.bitrate_kbps || 128));
     const en = qs("#outEnabled"); if(en) en.checked = !!cfg.enabled;
-    // Never auto-fill password.
+    // Never auto-fill password (the API does not return it, only whether one is set).
+    const passEl = qs("#outPass");
+    if(passEl) passEl.placeholder = cfg.has_password ? "•••••••• (unchanged)" : "not set";
   }
 
   const stEl = qs("#outStatusText");
@@ -2076,7 +2097,8 @@ function wireStreamingControls(){
       port: port || cfg0.port || 8006,
       mount: mount || cfg0.mount || "/studiocommand",
       username: username || cfg0.username || "source",
-      password: passIn.length ? passIn : (cfg0.password || ""),
+      // Empty keeps the stored password.
+      password: passIn,
       codec,
       bitrate_kbps,
       enabled,